@@ -0,0 +1,474 @@
+use crate::damf::{BedInstance, Configuration, Event, MetadataThinning};
+use std::fmt::Write;
+use truehd::structs::oamd::{ObjectAudioMetadataPayload, SpeakerLabels};
+
+/// Position of ISF objects, which carry no position of their own
+const DEFAULT_POSITION: [f64; 3] = [0.0, 1.0, 0.0];
+
+/// Audio description of an Atmos program as an ITU-R BS.2076 ADM document.
+///
+/// Bed channels are described as one DirectSpeakers audio object and every
+/// ISF or dynamic object as an Objects audio object, in the channel order of
+/// the decoded audio. Each metadata update that changes a channel adds an
+/// audioBlockFormat to it, so the blocks follow the same diffed events as the
+/// DAMF metadata file.
+pub struct AdmDocument {
+    sample_rate: u32,
+    bit_depth: u32,
+    channels: Vec<AdmChannel>,
+    prev_events: Vec<Event>,
+}
+
+struct AdmChannel {
+    /// DAMF ID of the bed channel or object
+    id: u32,
+    /// Speaker of a bed channel, `None` for objects
+    speaker: Option<SpeakerLabels>,
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Block {
+    start: u64,
+    position: [f64; 3],
+    gain: f64,
+    size: f64,
+    ramp_length: u32,
+}
+
+impl Block {
+    fn with_event(event: &Event, speaker: Option<SpeakerLabels>, start: u64) -> Self {
+        let position = match speaker {
+            Some(speaker) => speaker.pos().map(f64::from),
+            None => event.position().unwrap_or(DEFAULT_POSITION),
+        };
+        let gain = match event.gain_db() {
+            _ if !event.is_active() => 0.0,
+            Some(gain_db) => 10f64.powf(gain_db / 20.0),
+            None => 1.0,
+        };
+
+        Self {
+            start,
+            position,
+            gain,
+            size: event.size().unwrap_or(0.0),
+            ramp_length: event.ramp_length().unwrap_or(0),
+        }
+    }
+
+    /// Whether both blocks render the same, ignoring their start
+    fn same_state(&self, other: &Self) -> bool {
+        Self {
+            start: other.start,
+            ..self.clone()
+        } == *other
+    }
+}
+
+impl AdmChannel {
+    fn type_label(&self) -> &'static str {
+        match self.speaker {
+            Some(_) => "0001",
+            None => "0003",
+        }
+    }
+
+    fn type_definition(&self) -> &'static str {
+        match self.speaker {
+            Some(_) => "DirectSpeakers",
+            None => "Objects",
+        }
+    }
+}
+
+impl AdmDocument {
+    pub fn with_oamd_payload(
+        oamd: &ObjectAudioMetadataPayload,
+        sample_rate: u32,
+        bit_depth: u32,
+    ) -> Self {
+        let bed_assignment = &oamd.program_assignment.bed_assignment;
+
+        let mut channels = bed_assignment
+            .iter()
+            .zip(BedInstance::channel_ids(bed_assignment))
+            .flat_map(|(bed, ids)| {
+                bed.to_index_vec()
+                    .into_iter()
+                    .zip(ids)
+                    .map(|(i, id)| AdmChannel {
+                        id,
+                        speaker: SpeakerLabels::from_u8(i as u8),
+                        blocks: Vec::new(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // ISF and dynamic objects follow the bed channels with IDs from 10
+        let object_count = oamd.object_count.saturating_sub(channels.len());
+        channels.extend((0..object_count).map(|i| AdmChannel {
+            id: i as u32 + 10,
+            speaker: None,
+            blocks: Vec::new(),
+        }));
+
+        Self {
+            sample_rate,
+            bit_depth,
+            channels,
+            prev_events: Vec::new(),
+        }
+    }
+
+    /// Add a block to every channel changed by the events of `configuration`.
+    ///
+    /// The first block of each channel starts at the beginning of the file.
+    pub fn push_configuration(
+        &mut self,
+        configuration: Configuration,
+        thinning: Option<MetadataThinning>,
+    ) {
+        for diff in configuration.diff_events(&mut self.prev_events, thinning) {
+            let (Some(id), Some(start)) = (diff.id(), diff.sample_pos()) else {
+                continue;
+            };
+            let (Some(channel), Some(event)) = (
+                self.channels.iter_mut().find(|channel| channel.id == id),
+                self.prev_events.iter().find(|event| event.id() == Some(id)),
+            ) else {
+                continue;
+            };
+
+            let start = if channel.blocks.is_empty() { 0 } else { start };
+            let block = Block::with_event(event, channel.speaker, start);
+            match channel.blocks.last_mut() {
+                Some(last) if last.same_state(&block) => {}
+                Some(last) if last.start == block.start => *last = block,
+                _ => channel.blocks.push(block),
+            }
+        }
+    }
+
+    fn channel_suffix(&self, index: usize) -> String {
+        let channel = &self.channels[index];
+        let number = self.channels[..index]
+            .iter()
+            .filter(|other| other.speaker.is_some() == channel.speaker.is_some())
+            .count();
+        format!("{}{:04X}", channel.type_label(), 0x1001 + number)
+    }
+
+    fn pack_id(&self, index: usize) -> String {
+        match self.channels[index].speaker {
+            // All bed channels share one pack
+            Some(_) => "AP_00011001".to_string(),
+            None => format!("AP_{}", self.channel_suffix(index)),
+        }
+    }
+
+    /// `chna` chunk assigning a track UID, track format and pack format to each channel
+    pub fn chna_chunk(&self) -> Vec<u8> {
+        let track_count = self.channels.len() as u16;
+        let mut chunk = Vec::with_capacity(4 + 40 * self.channels.len());
+        chunk.extend_from_slice(&track_count.to_le_bytes());
+        chunk.extend_from_slice(&track_count.to_le_bytes());
+
+        for index in 0..self.channels.len() {
+            chunk.extend_from_slice(&(index as u16 + 1).to_le_bytes());
+            chunk.extend_from_slice(format!("ATU_{:08X}", index + 1).as_bytes());
+            chunk.extend_from_slice(format!("AT_{}_01", self.channel_suffix(index)).as_bytes());
+            chunk.extend_from_slice(self.pack_id(index).as_bytes());
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    /// `axml` chunk with the ADM document for a file of `duration` samples
+    pub fn axml_chunk(&self, duration: u64) -> Vec<u8> {
+        let time = |samples: u64| adm_time(samples, self.sample_rate);
+        let bed_channels = (0..self.channels.len())
+            .filter(|&index| self.channels[index].speaker.is_some())
+            .collect::<Vec<_>>();
+        let objects = (0..self.channels.len())
+            .filter(|&index| self.channels[index].speaker.is_none())
+            .collect::<Vec<_>>();
+
+        // Audio objects with their name, pack and channels
+        let mut audio_objects = Vec::new();
+        if !bed_channels.is_empty() {
+            audio_objects.push(("Bed".to_string(), bed_channels.clone()));
+        }
+        for (number, &index) in objects.iter().enumerate() {
+            audio_objects.push((format!("Object {}", number + 1), vec![index]));
+        }
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<ebuCoreMain xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns=\"urn:ebu:metadata-schema:ebuCore_2016\" schema=\"EBU_CORE_20161115.xsd\" version=\"1.7\" xml:lang=\"en\">\n");
+        xml.push_str(
+            "<coreMetadata>\n<format>\n<audioFormatExtended version=\"ITU-R_BS.2076-2\">\n",
+        );
+
+        let _ = writeln!(
+            xml,
+            "<audioProgramme audioProgrammeID=\"APR_1001\" audioProgrammeName=\"Atmos\" start=\"{}\" end=\"{}\">",
+            time(0),
+            time(duration)
+        );
+        xml.push_str("  <audioContentIDRef>ACO_1001</audioContentIDRef>\n</audioProgramme>\n");
+
+        xml.push_str("<audioContent audioContentID=\"ACO_1001\" audioContentName=\"Atmos\">\n");
+        for number in 0..audio_objects.len() {
+            let _ = writeln!(
+                xml,
+                "  <audioObjectIDRef>AO_{:04X}</audioObjectIDRef>",
+                0x1001 + number
+            );
+        }
+        xml.push_str("</audioContent>\n");
+
+        for (number, (name, indices)) in audio_objects.iter().enumerate() {
+            let _ = writeln!(
+                xml,
+                "<audioObject audioObjectID=\"AO_{:04X}\" audioObjectName=\"{name}\" start=\"{}\" duration=\"{}\">",
+                0x1001 + number,
+                time(0),
+                time(duration)
+            );
+            let _ = writeln!(
+                xml,
+                "  <audioPackFormatIDRef>{}</audioPackFormatIDRef>",
+                self.pack_id(indices[0])
+            );
+            for &index in indices {
+                let _ = writeln!(
+                    xml,
+                    "  <audioTrackUIDRef>ATU_{:08X}</audioTrackUIDRef>",
+                    index + 1
+                );
+            }
+            xml.push_str("</audioObject>\n");
+        }
+
+        for (name, indices) in &audio_objects {
+            let channel = &self.channels[indices[0]];
+            let _ = writeln!(
+                xml,
+                "<audioPackFormat audioPackFormatID=\"{}\" audioPackFormatName=\"{name}\" typeLabel=\"{}\" typeDefinition=\"{}\">",
+                self.pack_id(indices[0]),
+                channel.type_label(),
+                channel.type_definition()
+            );
+            for &index in indices {
+                let _ = writeln!(
+                    xml,
+                    "  <audioChannelFormatIDRef>AC_{}</audioChannelFormatIDRef>",
+                    self.channel_suffix(index)
+                );
+            }
+            xml.push_str("</audioPackFormat>\n");
+        }
+
+        for (index, channel) in self.channels.iter().enumerate() {
+            self.write_channel_format(&mut xml, index, channel, duration);
+        }
+
+        for index in 0..self.channels.len() {
+            let suffix = self.channel_suffix(index);
+            let _ = writeln!(
+                xml,
+                "<audioStreamFormat audioStreamFormatID=\"AS_{suffix}\" audioStreamFormatName=\"PCM_{suffix}\" formatLabel=\"0001\" formatDefinition=\"PCM\">"
+            );
+            let _ = writeln!(
+                xml,
+                "  <audioChannelFormatIDRef>AC_{suffix}</audioChannelFormatIDRef>"
+            );
+            let _ = writeln!(
+                xml,
+                "  <audioTrackFormatIDRef>AT_{suffix}_01</audioTrackFormatIDRef>"
+            );
+            xml.push_str("</audioStreamFormat>\n");
+
+            let _ = writeln!(
+                xml,
+                "<audioTrackFormat audioTrackFormatID=\"AT_{suffix}_01\" audioTrackFormatName=\"PCM_{suffix}\" formatLabel=\"0001\" formatDefinition=\"PCM\">"
+            );
+            let _ = writeln!(
+                xml,
+                "  <audioStreamFormatIDRef>AS_{suffix}</audioStreamFormatIDRef>"
+            );
+            xml.push_str("</audioTrackFormat>\n");
+        }
+
+        for index in 0..self.channels.len() {
+            let _ = writeln!(
+                xml,
+                "<audioTrackUID UID=\"ATU_{:08X}\" sampleRate=\"{}\" bitDepth=\"{}\">",
+                index + 1,
+                self.sample_rate,
+                self.bit_depth
+            );
+            let _ = writeln!(
+                xml,
+                "  <audioTrackFormatIDRef>AT_{}_01</audioTrackFormatIDRef>",
+                self.channel_suffix(index)
+            );
+            let _ = writeln!(
+                xml,
+                "  <audioPackFormatIDRef>{}</audioPackFormatIDRef>",
+                self.pack_id(index)
+            );
+            xml.push_str("</audioTrackUID>\n");
+        }
+
+        xml.push_str("</audioFormatExtended>\n</format>\n</coreMetadata>\n</ebuCoreMain>\n");
+        xml.into_bytes()
+    }
+
+    fn write_channel_format(
+        &self,
+        xml: &mut String,
+        index: usize,
+        channel: &AdmChannel,
+        duration: u64,
+    ) {
+        let suffix = self.channel_suffix(index);
+        let name = match channel.speaker {
+            Some(speaker) => format!("{speaker:?}"),
+            None => format!("Object {}", channel.id - 9),
+        };
+        let _ = writeln!(
+            xml,
+            "<audioChannelFormat audioChannelFormatID=\"AC_{suffix}\" audioChannelFormatName=\"{name}\" typeLabel=\"{}\" typeDefinition=\"{}\">",
+            channel.type_label(),
+            channel.type_definition()
+        );
+
+        for (number, block) in channel.blocks.iter().enumerate() {
+            let end = channel
+                .blocks
+                .get(number + 1)
+                .map_or(duration, |next| next.start);
+            let _ = writeln!(
+                xml,
+                "  <audioBlockFormat audioBlockFormatID=\"AB_{suffix}_{:08X}\" rtime=\"{}\" duration=\"{}\">",
+                number + 1,
+                adm_time(block.start, self.sample_rate),
+                adm_time(end.saturating_sub(block.start), self.sample_rate)
+            );
+            if let Some(speaker) = channel.speaker {
+                let _ = writeln!(
+                    xml,
+                    "    <speakerLabel>{}</speakerLabel>",
+                    speaker_label(speaker)
+                );
+            }
+            xml.push_str("    <cartesian>1</cartesian>\n");
+            for (coordinate, value) in ["X", "Y", "Z"].iter().zip(block.position) {
+                let _ = writeln!(
+                    xml,
+                    "    <position coordinate=\"{coordinate}\">{value}</position>"
+                );
+            }
+            if channel.speaker.is_none() {
+                for dimension in ["width", "depth", "height"] {
+                    let _ = writeln!(xml, "    <{dimension}>{}</{dimension}>", block.size);
+                }
+            }
+            let _ = writeln!(xml, "    <gain>{}</gain>", block.gain);
+            if channel.speaker.is_none() && number > 0 {
+                let _ = writeln!(
+                    xml,
+                    "    <jumpPosition interpolationLength=\"{}\">1</jumpPosition>",
+                    block.ramp_length as f64 / self.sample_rate as f64
+                );
+            }
+            xml.push_str("  </audioBlockFormat>\n");
+        }
+        xml.push_str("</audioChannelFormat>\n");
+    }
+}
+
+/// ITU-R BS.2051 label of a bed speaker
+fn speaker_label(speaker: SpeakerLabels) -> &'static str {
+    match speaker {
+        SpeakerLabels::L => "M+030",
+        SpeakerLabels::R => "M-030",
+        SpeakerLabels::C => "M+000",
+        SpeakerLabels::LFE => "LFE1",
+        SpeakerLabels::Lss => "M+090",
+        SpeakerLabels::Rss => "M-090",
+        SpeakerLabels::Lrs => "M+135",
+        SpeakerLabels::Rrs => "M-135",
+        SpeakerLabels::Lfh => "U+030",
+        SpeakerLabels::Rfh => "U-030",
+        SpeakerLabels::Lts => "U+090",
+        SpeakerLabels::Rts => "U-090",
+        SpeakerLabels::Lrh => "U+135",
+        SpeakerLabels::Rrh => "U-135",
+        SpeakerLabels::Lw => "M+060",
+        SpeakerLabels::Rw => "M-060",
+        SpeakerLabels::LFE2 => "LFE2",
+    }
+}
+
+/// ADM time `hh:mm:ss.zzzzz` of a sample position
+fn adm_time(samples: u64, sample_rate: u32) -> String {
+    let sample_rate = sample_rate as u64;
+    let seconds = samples / sample_rate;
+    let fraction = ((samples % sample_rate) * 100_000 + sample_rate / 2) / sample_rate;
+    format!(
+        "{:02}:{:02}:{:02}.{fraction:05}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[test]
+fn adm_document() {
+    use truehd::structs::oamd::TEST_DATA_TRIM;
+
+    let oamd = ObjectAudioMetadataPayload::read(TEST_DATA_TRIM).unwrap();
+    let mut document = AdmDocument::with_oamd_payload(&oamd, 48000, 24);
+    assert_eq!(document.channels.len(), 16);
+
+    // Unchanged payloads add no blocks
+    for sample_pos in [0, 1920, 3840] {
+        let configuration = Configuration::with_oamd_payload(&oamd, 48000, sample_pos);
+        document.push_configuration(configuration, None);
+    }
+    assert!(
+        document
+            .channels
+            .iter()
+            .all(|channel| channel.blocks.len() == 1)
+    );
+
+    let chna = document.chna_chunk();
+    assert_eq!(chna.len(), 4 + 40 * 16);
+    assert_eq!(&chna[..4], &[16, 0, 16, 0]);
+    assert_eq!(&chna[4..6], &[1, 0]);
+    assert_eq!(&chna[6..18], b"ATU_00000001");
+    assert_eq!(&chna[18..32], b"AT_00011001_01");
+    assert_eq!(&chna[32..43], b"AP_00011001");
+    assert_eq!(&chna[46..58], b"ATU_00000002");
+    assert_eq!(&chna[58..72], b"AT_00031001_01");
+    assert_eq!(&chna[72..83], b"AP_00031001");
+
+    let axml = String::from_utf8(document.axml_chunk(96000)).unwrap();
+    assert!(axml.contains("end=\"00:00:02.00000\""));
+    assert!(axml.contains("<speakerLabel>LFE1</speakerLabel>"));
+    assert_eq!(axml.matches("<audioObject ").count(), 16);
+    assert_eq!(axml.matches("<audioBlockFormat ").count(), 16);
+    assert_eq!(axml.matches("typeDefinition=\"Objects\"").count(), 30);
+}
+
+#[test]
+fn adm_time_format() {
+    assert_eq!(adm_time(0, 48000), "00:00:00.00000");
+    assert_eq!(adm_time(48000 * 3723 + 24000, 48000), "01:02:03.50000");
+    assert_eq!(adm_time(1, 48000), "00:00:00.00002");
+}
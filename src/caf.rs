@@ -2,6 +2,8 @@ use std::io::{self, Read, Seek, SeekFrom, Write};
 
 use crate::byteorder::{WriteBytesBe, WriteBytesLe};
 use crate::impl_u32_enum;
+use truehd::structs::channel::ChannelLabel as TrueHDChannelLabel;
+use truehd::structs::oamd::SpeakerLabels;
 use truehdd_macros::{ToBytes, caf_chunk_type};
 
 pub fn write_caf_file_header<W: Write>(writer: &mut W) -> io::Result<()> {
@@ -39,12 +41,21 @@ pub struct AudioFormat {
     pub bits_per_channel: u32,
 }
 
+impl AudioFormat {
+    /// Whether samples are floating point (kLinearPCMFormatFlagIsFloat)
+    pub fn is_float(&self) -> bool {
+        self.format_flags & 1 != 0
+    }
+}
+
 #[derive(Debug, ToBytes)]
 #[caf_chunk_type(b"chan")]
 pub struct ChannelLayout {
     pub channel_layout_tag: ChannelLayoutTag,
-    pub channel_bitmap: ChannelBitmap,
-    pub chennel_description: Vec<ChennelDescription>,
+    /// Combination of [`ChannelBitmap`] flags, only used with `UseChannelBitmap`
+    pub channel_bitmap: u32,
+    pub number_channel_descriptions: u32,
+    pub channel_descriptions: Vec<ChannelDescription>,
 }
 
 #[allow(non_camel_case_types)]
@@ -168,12 +179,71 @@ pub enum ChannelBitmap {
 }
 
 #[derive(Debug, Clone, ToBytes)]
-pub struct ChennelDescription {
+pub struct ChannelDescription {
     pub channel_label: ChannelLabel,
     pub channel_flags: u32,
     pub coordinates: [f32; 3],
 }
 
+impl ChannelDescription {
+    /// kAudioChannelFlags_RectangularCoordinates
+    pub const RECTANGULAR_COORDINATES: u32 = 1 << 0;
+
+    pub fn with_label(channel_label: ChannelLabel) -> Self {
+        Self {
+            channel_label,
+            channel_flags: 0,
+            coordinates: [0.0; 3],
+        }
+    }
+
+    /// Describe a channel by its rectangular (left/right, back/front, down/up) position
+    pub fn with_coordinates(coordinates: [f32; 3]) -> Self {
+        Self {
+            channel_label: ChannelLabel::UseCoordinates,
+            channel_flags: Self::RECTANGULAR_COORDINATES,
+            coordinates,
+        }
+    }
+
+    /// Map a TrueHD channel label to its CAF description.
+    ///
+    /// Top side channels have no CAF label and are described by coordinates
+    /// instead, objects and channels of reserved assignments have no fixed position
+    /// and are `Unknown`.
+    pub fn from_truehd_label(label: TrueHDChannelLabel) -> Self {
+        let channel_label = match label {
+            TrueHDChannelLabel::L => ChannelLabel::Left,
+            TrueHDChannelLabel::R => ChannelLabel::Right,
+            TrueHDChannelLabel::C => ChannelLabel::Center,
+            TrueHDChannelLabel::LFE => ChannelLabel::LFEScreen,
+            TrueHDChannelLabel::Ls => ChannelLabel::LeftSurround,
+            TrueHDChannelLabel::Rs => ChannelLabel::RightSurround,
+            TrueHDChannelLabel::Tfl => ChannelLabel::VerticalHeightLeft,
+            TrueHDChannelLabel::Tfr => ChannelLabel::VerticalHeightRight,
+            TrueHDChannelLabel::Tfc => ChannelLabel::VerticalHeightCenter,
+            TrueHDChannelLabel::Tbl => ChannelLabel::TopBackLeft,
+            TrueHDChannelLabel::Tbr => ChannelLabel::TopBackRight,
+            TrueHDChannelLabel::Tc => ChannelLabel::TopCenterSurround,
+            TrueHDChannelLabel::Lsc => ChannelLabel::LeftCenter,
+            TrueHDChannelLabel::Rsc => ChannelLabel::RightCenter,
+            TrueHDChannelLabel::Lb => ChannelLabel::RearSurroundLeft,
+            TrueHDChannelLabel::Rb => ChannelLabel::RearSurroundRight,
+            TrueHDChannelLabel::Cb => ChannelLabel::CenterSurround,
+            TrueHDChannelLabel::Lsd => ChannelLabel::LeftSurroundDirect,
+            TrueHDChannelLabel::Rsd => ChannelLabel::RightSurroundDirect,
+            TrueHDChannelLabel::Lw => ChannelLabel::LeftWide,
+            TrueHDChannelLabel::Rw => ChannelLabel::RightWide,
+            TrueHDChannelLabel::LFE2 => ChannelLabel::LFE2,
+            TrueHDChannelLabel::Tsl => return Self::with_coordinates(*SpeakerLabels::Lts.pos()),
+            TrueHDChannelLabel::Tsr => return Self::with_coordinates(*SpeakerLabels::Rts.pos()),
+            TrueHDChannelLabel::Object(_) | TrueHDChannelLabel::Generic(_) => ChannelLabel::Unknown,
+        };
+
+        Self::with_label(channel_label)
+    }
+}
+
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
@@ -340,9 +410,51 @@ pub struct CAFWriter<W: Write + Seek> {
     data_size_position: Option<u64>,
     data_written: u64,
     finished: bool,
+    /// The data chunk size is left unknown instead of being patched
+    streaming: bool,
     endianness: Endianness,
 }
 
+/// Destination of a CAF written without seeking, see [`CAFWriter::new_streaming`]
+///
+/// Counts the bytes written to report the current position; any other seek
+/// fails with [`io::ErrorKind::Unsupported`].
+pub struct StreamingWriter<W: Write> {
+    writer: W,
+    position: u64,
+}
+
+impl<W: Write> StreamingWriter<W> {
+    /// Get the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Write for StreamingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Seek for StreamingWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.position),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Streamed CAF output cannot seek",
+            )),
+        }
+    }
+}
+
 /// Information extracted from parsing an existing CAF file
 #[derive(Debug)]
 pub struct CAFFileInfo {
@@ -491,6 +603,7 @@ impl<W: Write + Seek> CAFWriter<W> {
             data_size_position: None,
             data_written: 0,
             finished: false,
+            streaming: false,
             endianness: Endianness::BigEndian, // Default CAF endianness
         }
     }
@@ -520,6 +633,7 @@ impl<W: Write + Seek> CAFWriter<W> {
             data_size_position: Some(file_info.data_size_position),
             data_written: 0, // Will be calculated dynamically in finish()
             finished: false,
+            streaming: false,
             endianness: file_info.endianness,
         })
     }
@@ -537,6 +651,7 @@ impl<W: Write + Seek> CAFWriter<W> {
             data_size_position: Some(file_info.data_size_position),
             data_written: 0, // Will be calculated dynamically in finish()
             finished: false,
+            streaming: false,
             endianness: file_info.endianness,
         })
     }
@@ -645,8 +760,66 @@ impl<W: Write + Seek> CAFWriter<W> {
 
         self.channel_layout = Some(ChannelLayout {
             channel_layout_tag: layout_tag,
-            channel_bitmap: ChannelBitmap::Left, // Not used when layout_tag is set
-            chennel_description: Vec::new(),
+            channel_bitmap: 0, // Not used when layout_tag is set
+            number_channel_descriptions: 0,
+            channel_descriptions: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Describe every channel individually from decoded TrueHD channel labels.
+    ///
+    /// Channels beyond the given labels (e.g. Atmos objects) are written as
+    /// `Unknown`. Must be called after the audio format is set and before
+    /// `write_header()`.
+    pub fn set_channel_layout_from_labels(
+        &mut self,
+        labels: &[TrueHDChannelLabel],
+    ) -> io::Result<()> {
+        self.set_channel_layout_from_descriptions(
+            labels
+                .iter()
+                .map(|&label| ChannelDescription::from_truehd_label(label))
+                .collect(),
+        )
+    }
+
+    /// Describe every channel individually from decoded TrueHD channel labels.
+    #[deprecated(note = "renamed to `set_channel_layout_from_labels`")]
+    pub fn set_channel_descriptions(&mut self, labels: &[TrueHDChannelLabel]) -> io::Result<()> {
+        self.set_channel_layout_from_labels(labels)
+    }
+
+    /// Describe every channel individually, e.g. Atmos objects by their position.
+    ///
+    /// Channels beyond the given descriptions are written as `Unknown`. Must be
+    /// called after the audio format is set and before `write_header()`.
+    pub fn set_channel_layout_from_descriptions(
+        &mut self,
+        mut channel_descriptions: Vec<ChannelDescription>,
+    ) -> io::Result<()> {
+        let channels = self.ensure_audio_format()?.channels_per_frame as usize;
+
+        if channel_descriptions.len() > channels {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} channel descriptions given for {channels} channels",
+                    channel_descriptions.len()
+                ),
+            ));
+        }
+
+        channel_descriptions.resize(
+            channels,
+            ChannelDescription::with_label(ChannelLabel::Unknown),
+        );
+
+        self.channel_layout = Some(ChannelLayout {
+            channel_layout_tag: ChannelLayoutTag::UseChannelDescriptions,
+            channel_bitmap: 0,
+            number_channel_descriptions: channels as u32,
+            channel_descriptions,
         });
         Ok(())
     }
@@ -681,6 +854,20 @@ impl<W: Write + Seek> CAFWriter<W> {
         Ok(())
     }
 
+    /// Length of the sample data in bytes, including the data of a resumed file
+    pub fn data_len(&mut self) -> io::Result<u64> {
+        self.ensure_header_written()?;
+        let data_start = self.data_chunk_start.unwrap_or_default();
+        Ok(self.writer.stream_position()? - data_start)
+    }
+
+    /// Size of a sample frame in bytes, once the audio format is set
+    pub fn bytes_per_frame(&self) -> Option<u32> {
+        self.audio_format
+            .as_ref()
+            .map(|format| format.bytes_per_packet)
+    }
+
     /// Write audio data (PCM samples)
     pub fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
         self.ensure_header_written()?;
@@ -691,12 +878,25 @@ impl<W: Write + Seek> CAFWriter<W> {
         Ok(())
     }
 
+    /// Flush the audio data written so far without finishing the file
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
     /// Finish writing and update the data chunk size
+    ///
+    /// A streamed file keeps its unknown data chunk size and is only flushed.
     pub fn finish(&mut self) -> io::Result<()> {
         if self.finished {
             return Ok(()); // Already finished
         }
 
+        if self.streaming {
+            self.writer.flush()?;
+            self.finished = true;
+            return Ok(());
+        }
+
         let data_size_pos = self.get_data_size_position()?;
         let data_start = self.data_chunk_start.ok_or_else(|| {
             io::Error::new(
@@ -748,6 +948,24 @@ impl<W: Write + Seek> CAFWriter<W> {
     }
 }
 
+impl<W: Write> CAFWriter<StreamingWriter<W>> {
+    /// Create a CAF writer for a destination that cannot seek, such as a pipe
+    ///
+    /// The data chunk size is written as -1, which CAF defines as extending
+    /// to the end of the file, and is never patched. The audio data must
+    /// then be the last chunk, and readers cannot tell a truncated file from
+    /// a complete one or know its duration before reading to the end.
+    /// [`finish`](Self::finish) only flushes.
+    pub fn new_streaming(writer: W) -> Self {
+        let mut caf_writer = Self::new(StreamingWriter {
+            writer,
+            position: 0,
+        });
+        caf_writer.streaming = true;
+        caf_writer
+    }
+}
+
 /// Statistics about the CAF writer
 #[derive(Debug, Clone)]
 pub struct CAFWriterStats {
@@ -773,6 +991,24 @@ impl<W: Write + Seek> CAFWriter<W> {
         Ok(())
     }
 
+    /// Configure the writer for 32-bit float output from TrueHD stream parameters
+    pub fn configure_float_audio_format(
+        &mut self,
+        sample_rate: u32,
+        channels: u32,
+    ) -> io::Result<()> {
+        self.set_audio_format_with_options(
+            sample_rate as f64,
+            channels,
+            32,
+            PCMDataType::Float,
+            Endianness::BigEndian,
+        )?;
+        self.set_basic_channel_layout(channels)?;
+
+        Ok(())
+    }
+
     /// Convenience method to write 24-bit PCM data from TrueHD decoder
     /// Expects interleaved samples in i32 format (with 24-bit of effective data)
     pub fn write_pcm_24bit_as_packed(&mut self, samples: &[i32]) -> io::Result<()> {
@@ -795,6 +1031,52 @@ impl<W: Write + Seek> CAFWriter<W> {
 
         self.write_data(&buffer)
     }
+
+    /// Write integer PCM samples packed to the configured bits per channel
+    pub fn write_pcm_packed(&mut self, samples: &[i32]) -> io::Result<()> {
+        let bytes_per_sample = self
+            .audio_format
+            .as_ref()
+            .map_or(3, |format| format.bits_per_channel as usize / 8);
+        let mut buffer = Vec::with_capacity(samples.len() * bytes_per_sample);
+
+        for &sample in samples {
+            match self.endianness {
+                Endianness::BigEndian => {
+                    buffer.extend_from_slice(&sample.to_be_bytes()[4 - bytes_per_sample..])
+                }
+                Endianness::LittleEndian => {
+                    buffer.extend_from_slice(&sample.to_le_bytes()[..bytes_per_sample])
+                }
+            }
+        }
+
+        self.write_data(&buffer)
+    }
+
+    /// Write integer PCM data from TrueHD decoder as 32-bit float
+    /// Samples of `bit_depth` effective bits are normalized to [-1.0, 1.0)
+    pub fn write_pcm_f32(&mut self, samples: &[i32], bit_depth: u32) -> io::Result<()> {
+        let scale = 1.0 / (1u32 << (bit_depth - 1)) as f32;
+        let mut buffer = Vec::with_capacity(samples.len() * 4);
+
+        for &sample in samples {
+            let value = sample as f32 * scale;
+            match self.endianness {
+                Endianness::BigEndian => buffer.extend_from_slice(&value.to_be_bytes()),
+                Endianness::LittleEndian => buffer.extend_from_slice(&value.to_le_bytes()),
+            }
+        }
+
+        self.write_data(&buffer)
+    }
+
+    /// Whether the configured format stores floating point samples
+    pub fn is_float(&self) -> bool {
+        self.audio_format
+            .as_ref()
+            .is_some_and(AudioFormat::is_float)
+    }
 }
 
 // Implement Drop to ensure finish() is called
@@ -839,6 +1121,51 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_caf_writer_channel_descriptions() -> io::Result<()> {
+        let cursor = Cursor::new(Vec::new());
+        let mut writer = CAFWriter::new(cursor);
+
+        writer.set_audio_format(48000.0, 4, 24)?;
+        writer.set_channel_layout_from_labels(&[
+            TrueHDChannelLabel::L,
+            TrueHDChannelLabel::R,
+            TrueHDChannelLabel::Tsl,
+        ])?;
+
+        let layout = writer.channel_layout.as_ref().unwrap();
+        let data = layout.chunk_data();
+
+        // tag + bitmap + count + 4 * (label + flags + 3 coordinates)
+        assert_eq!(data.len(), 12 + 4 * 20);
+        assert_eq!(&data[..4], &0u32.to_be_bytes());
+        assert_eq!(&data[8..12], &4u32.to_be_bytes());
+        assert_eq!(&data[12..16], &(ChannelLabel::Left as u32).to_be_bytes());
+        assert_eq!(&data[32..36], &(ChannelLabel::Right as u32).to_be_bytes());
+
+        let top_side = &data[52..72];
+        assert_eq!(
+            &top_side[..4],
+            &(ChannelLabel::UseCoordinates as u32).to_be_bytes()
+        );
+        assert_eq!(&top_side[4..8], &1u32.to_be_bytes());
+        assert_eq!(&top_side[8..12], &(-1.0f32).to_be_bytes());
+        assert_eq!(&top_side[16..20], &1.0f32.to_be_bytes());
+
+        assert_eq!(&data[72..76], &(ChannelLabel::Unknown as u32).to_be_bytes());
+
+        assert!(
+            writer
+                .set_channel_layout_from_labels(&[TrueHDChannelLabel::C; 5])
+                .is_err()
+        );
+
+        writer.write_header()?;
+        writer.finish()?;
+
+        Ok(())
+    }
+
     #[test]
     fn test_caf_writer_pcm_conversion() -> io::Result<()> {
         let buffer = Vec::new();
@@ -1020,6 +1347,67 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_caf_writer_float32() -> io::Result<()> {
+        let mut writer = CAFWriter::new(Cursor::new(Vec::new()));
+        writer.configure_float_audio_format(48000, 2)?;
+        writer.write_header()?;
+        writer.write_pcm_f32(&[0x400000, -0x800000], 24)?;
+        writer.finish()?;
+
+        let buffer = writer.into_inner()?.into_inner();
+        let file_info = parse_caf_file(Cursor::new(&buffer))?;
+        let format = file_info.audio_format.unwrap();
+
+        assert!(format.is_float());
+        assert_eq!(format.bits_per_channel, 32);
+        assert_eq!(format.bytes_per_packet, 8);
+        assert_eq!(file_info.endianness, Endianness::BigEndian);
+
+        let data = &buffer[file_info.data_chunk_start as usize..];
+        assert_eq!(data[..4], 0.5f32.to_be_bytes());
+        assert_eq!(data[4..], (-1.0f32).to_be_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_caf_writer_packed_bit_depths() -> io::Result<()> {
+        let samples = [0x123456, -0x7FFF00, 0x7F];
+        let write = |bits, packed: bool| -> io::Result<Vec<u8>> {
+            let mut writer = CAFWriter::new(Cursor::new(Vec::new()));
+            writer.configure_audio_format(48000, 1, bits)?;
+            writer.write_header()?;
+            if packed {
+                writer.write_pcm_packed(&samples)?;
+            } else {
+                writer.write_pcm_24bit_as_packed(&samples)?;
+            }
+            writer.finish()?;
+            Ok(writer.into_inner()?.into_inner())
+        };
+
+        // The 24-bit output is unchanged
+        assert_eq!(write(24, true)?, write(24, false)?);
+
+        let buffer = write(16, true)?;
+        let file_info = parse_caf_file(Cursor::new(&buffer))?;
+        let format = file_info.audio_format.unwrap();
+        assert_eq!(format.bits_per_channel, 16);
+        assert_eq!(format.bytes_per_packet, 2);
+        assert_eq!(
+            &buffer[file_info.data_chunk_start as usize..],
+            &[0x34, 0x56, 0x01, 0x00, 0x00, 0x7F]
+        );
+
+        let buffer = write(32, true)?;
+        let file_info = parse_caf_file(Cursor::new(&buffer))?;
+        assert_eq!(file_info.audio_format.unwrap().bits_per_channel, 32);
+        assert_eq!(buffer.len() as u64 - file_info.data_chunk_start, 12);
+
+        Ok(())
+    }
+
     #[test]
     fn test_caf_parsing_positions() -> io::Result<()> {
         let buffer = Vec::new();
@@ -1053,4 +1441,46 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_caf_writer_streaming() -> io::Result<()> {
+        let mut writer = CAFWriter::new_streaming(Vec::new());
+        writer.set_audio_format(48000.0, 2, 24)?;
+        writer.set_basic_channel_layout(2)?;
+        writer.write_header()?;
+
+        let audio_data = [0x12u8; 12];
+        writer.write_data(&audio_data)?;
+        writer.finish()?;
+        assert_eq!(writer.data_len()?, 12);
+
+        let buffer = writer.into_inner()?.into_inner();
+        assert_eq!(&buffer[0..8], b"caff\x00\x01\x00\x00");
+
+        // Data chunk size -1 ("until EOF"), a zero edit count, then the samples
+        let info = parse_caf_file(Cursor::new(&buffer))?;
+        let size_position = info.data_size_position as usize;
+        assert_eq!(&buffer[size_position - 4..size_position], b"data");
+        assert_eq!(
+            buffer[size_position..size_position + 8],
+            (-1i64).to_be_bytes()
+        );
+        assert_eq!(buffer[size_position + 8..size_position + 12], [0; 4]);
+        assert_eq!(info.data_chunk_start as usize, size_position + 12);
+        assert_eq!(&buffer[size_position + 12..], &audio_data);
+
+        // Seeking back to patch the size is never attempted
+        let mut output = StreamingWriter {
+            writer: Vec::new(),
+            position: 0,
+        };
+        output.write_all(&audio_data)?;
+        assert_eq!(output.stream_position()?, 12);
+        assert_eq!(
+            output.seek(SeekFrom::Start(0)).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+
+        Ok(())
+    }
 }
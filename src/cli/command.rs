@@ -1,6 +1,13 @@
 use std::path::PathBuf;
 
+use anyhow::{Result, bail};
 use clap::{Args, Parser as ClapParser, Subcommand, ValueEnum};
+use truehd::structs::oamd::SpeakerLabels;
+use truehd::utils::errors::{Category, Policy};
+use truehd::utils::events::Event;
+
+/// Log target of the records carrying an [`Event`] as JSON
+pub const EVENT_LOG_TARGET: &str = "truehd::event";
 
 pub const VERSION_INFO: &str = concat!(
     env!("VERGEN_GIT_DESCRIBE"),
@@ -27,6 +34,26 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub strict: bool,
 
+    /// Fail on problems of these categories, with or without --strict
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_delimiter = ',',
+        value_name = "CATEGORIES"
+    )]
+    pub fail_on: Vec<ErrorCategory>,
+
+    /// Only log problems of these categories at debug level, with or without --strict
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_delimiter = ',',
+        value_name = "CATEGORIES"
+    )]
+    pub ignore: Vec<ErrorCategory>,
+
     /// Log output format.
     #[arg(long, global = true, value_enum, default_value_t = LogFormat::Plain)]
     pub log_format: LogFormat,
@@ -40,51 +67,391 @@ pub struct Cli {
     pub command: Commands,
 }
 
+impl Cli {
+    /// Policies of the categories given to `--fail-on` and `--ignore`
+    pub fn category_policies(&self) -> Result<Vec<(Category, Policy)>> {
+        if let Some(category) = self.fail_on.iter().find(|c| self.ignore.contains(c)) {
+            bail!("{category:?} is given to both --fail-on and --ignore");
+        }
+        let fail_on = self.fail_on.iter().map(|&c| (c.into(), Policy::Fail));
+        let ignore = self.ignore.iter().map(|&c| (c.into(), Policy::Ignore));
+        Ok(fail_on.chain(ignore).collect())
+    }
+
+    /// Event sink logging parser and decoder events with `--log-format json`
+    pub fn event_logger(&self) -> Option<fn(Event)> {
+        matches!(self.log_format, LogFormat::Json).then_some(log_event as fn(Event))
+    }
+}
+
+fn log_event(event: Event) {
+    match serde_json::to_string(&event) {
+        Ok(json) => log::log!(target: EVENT_LOG_TARGET, event.level(), "{json}"),
+        Err(e) => log::debug!("Failed to serialize {event:?}: {e}"),
+    }
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     /// Decode the specified TrueHD stream into PCM audio.
-    Decode(DecodeArgs),
+    Decode(Box<DecodeArgs>),
 
     /// Print stream information
     Info(InfoArgs),
+
+    /// Check stream integrity without writing output
+    Verify(VerifyArgs),
+
+    /// Rewrite dialogue normalization and DRC metadata in the major syncs
+    Edit(EditArgs),
+
+    /// Cut a range of access units into a new bitstream
+    Trim(TrimArgs),
+
+    /// Pack the stream into Dolby MAT 2.0 frames for IEC 61937-9 passthrough
+    Mat(MatArgs),
+
+    /// Extract the TrueHD track of a Matroska file as a raw bitstream
+    Demux(DemuxArgs),
 }
 
 #[derive(Debug, Args)]
 pub struct DecodeArgs {
-    /// Input TrueHD bitstream (use "-" for stdin).
-    #[arg(value_name = "INPUT")]
-    pub input: PathBuf,
+    /// Input TrueHD bitstream or Matroska file (use "-" for stdin).
+    #[arg(value_name = "INPUT", required_unless_present = "concat")]
+    pub input: Option<PathBuf>,
+
+    /// Decode the inputs one after another as a single gapless stream, restarting at each join
+    #[arg(long, value_name = "INPUT", num_args = 2.., conflicts_with_all = ["input", "resume"])]
+    pub concat: Vec<PathBuf>,
 
-    /// Output path for audio and metadata files.
+    /// Output path for audio and metadata files (use "-" for raw PCM on stdout, or CAF with --format caf).
     #[arg(long, value_name = "PATH")]
     pub output_path: Option<PathBuf>,
 
-    /// Audio format for output (ignored for presentation 3 which always uses CAF).
-    #[arg(long, value_enum, default_value_t = AudioFormat::Caf)]
-    pub format: AudioFormat,
+    /// Base path of the Atmos metadata files when writing audio to stdout
+    #[arg(long, value_name = "PATH", requires = "output_path")]
+    pub metadata_path: Option<PathBuf>,
 
-    /// Presentation index (0-3).
-    #[arg(long, value_name = "INDEX", default_value_t = 3)]
-    pub presentation: u8,
+    /// Audio format for output (presentation 3 always uses CAF unless adm is selected) [default: caf]
+    #[arg(long, value_enum)]
+    pub format: Option<AudioFormat>,
+
+    /// Sample format of the output; 16, 24 and 32 select the integer formats (float32 requires CAF or stdout output)
+    #[arg(long, value_enum, default_value_t = BitFormat::Int24)]
+    pub bit_format: BitFormat,
+
+    /// Channel order of the output; WAV output always uses the channel mask order
+    #[arg(long, value_enum, default_value_t = ChannelOrder::Native)]
+    pub channel_order: ChannelOrder,
+
+    /// Downmix the output to a standard layout
+    #[arg(long, value_enum)]
+    pub downmix: Option<Downmix>,
+
+    /// Dynamic range compression applied to the output
+    #[arg(long, value_enum, default_value_t = ApplyDrc::None)]
+    pub apply_drc: ApplyDrc,
+
+    /// Attenuate the output by the dialogue normalization of the presentation,
+    /// as players do to reach a -31 dBFS dialogue level
+    #[arg(long)]
+    pub apply_dialnorm: bool,
+
+    /// Resample 44.1, 88.2 and 176.4 kHz output to 48 kHz, as expected of Dolby Atmos master files
+    #[arg(long, conflicts_with = "resume")]
+    pub resample_to_48k: bool,
+
+    /// Presentation index (0-3), or a comma-separated list decoded in one pass to files suffixed .p<INDEX>
+    #[arg(long, value_name = "INDEX", value_delimiter = ',', default_value = "3")]
+    pub presentation: Vec<u8>,
 
     /// Disable progress estimation
     #[arg(long)]
     pub no_estimate_progress: bool,
 
+    /// Expected number of access units, shown as progress instead of counting them
+    #[arg(long, value_name = "N", conflicts_with = "expected_duration")]
+    pub expected_frames: Option<u64>,
+
+    /// Expected stream duration, converted to access units at the first major sync
+    #[arg(long, value_name = "SECONDS")]
+    pub expected_duration: Option<f64>,
+
     /// Enable bed conformance for Atmos content
     #[arg(long)]
     pub bed_conform: bool,
 
+    /// Bed layout the first bed instance is conformed to
+    #[arg(long, value_enum, default_value_t, requires = "bed_conform")]
+    pub bed_conform_layout: BedConformLayout,
+
+    /// Scale the object channels of the bed-conformed output by their Atmos object gain
+    #[arg(long, requires = "bed_conform", conflicts_with = "metadata_only")]
+    pub apply_object_gains: bool,
+
     /// Specify warp mode when not present in metadata
     #[arg(long, value_enum)]
     pub warp_mode: Option<WarpMode>,
+
+    /// Frame rate for the Atmos header when the stream has no SMPTE timestamp [default: 24]
+    #[arg(long, value_enum)]
+    pub fps: Option<Fps>,
+
+    /// Limit position-only Atmos object updates to one per interval (milliseconds)
+    #[arg(long, value_name = "MS")]
+    pub metadata_update_rate: Option<u32>,
+
+    /// Append to the partial output of an interrupted decode
+    #[arg(long, requires = "output_path")]
+    pub resume: bool,
+
+    /// Write only the Atmos metadata files without decoding audio
+    #[arg(long, requires = "output_path", conflicts_with = "resume")]
+    pub metadata_only: bool,
+
+    /// Write each raw OAMD payload to a file in DIR, listed in DIR/index.csv
+    #[arg(long, value_name = "DIR")]
+    pub dump_oamd: Option<PathBuf>,
+
+    /// Write the position, size and gain of each dynamic object per OAMD update block to FILE as JSON
+    #[arg(long, value_name = "FILE")]
+    pub export_trajectories: Option<PathBuf>,
+
+    /// Write the object trajectories as CSV instead of JSON
+    #[arg(long, requires = "export_trajectories")]
+    pub csv: bool,
+
+    /// Measure the integrated loudness, loudness range and true peak of the decoded output (ITU-R BS.1770-4)
+    #[arg(long, conflicts_with_all = ["resume", "metadata_only"])]
+    pub measure_loudness: bool,
+
+    /// Number of threads decoding segments between major syncs in parallel
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    pub threads: usize,
+
+    /// Restart at invalid seamless branches and insert or drop samples to keep the timeline continuous
+    #[arg(long)]
+    pub repair_branch: bool,
+
+    /// Accept substream_info changes that keep the channel counts, restarting with the new presentations
+    #[arg(long)]
+    pub allow_format_change: bool,
+
+    /// Handling of access units duplicating the previous one, as at concatenation joins
+    #[arg(long, value_enum, default_value_t = Duplicates::Skip)]
+    pub duplicates: Duplicates,
+
+    /// Samples to drop from the start of the output instead of those detected from the high-resolution output timing
+    #[arg(long, value_name = "N")]
+    pub trim_start: Option<u64>,
+
+    /// Keep the samples trimmed according to the high-resolution output timing
+    #[arg(long, conflicts_with = "trim_start")]
+    pub no_trim: bool,
+
+    /// Start the output at TIME after the start trim, as HH:MM:SS[.ms] or seconds
+    #[arg(long, value_name = "TIME", value_parser = crate::timestamp::parse_time, conflicts_with = "resume")]
+    pub start: Option<f64>,
+
+    /// Stop the output after SECONDS
+    #[arg(long, value_name = "SECONDS", conflicts_with = "resume")]
+    pub duration: Option<f64>,
+
+    /// Write one mono CAF file per channel, named after its label or Atmos object ID
+    #[arg(long, requires = "output_path", conflicts_with_all = ["resume", "metadata_only"])]
+    pub split_channels: bool,
+
+    /// Stop after N parse or decode errors instead of continuing past every error [default: unlimited]
+    #[arg(long, value_name = "N")]
+    pub max_errors: Option<u64>,
+
+    /// Track number of the TrueHD track to decode from a Matroska input [default: the only TrueHD track]
+    #[arg(long, value_name = "N")]
+    pub track: Option<u64>,
+
+    /// Pass every access unit to the output as soon as it is decoded, for live input
+    #[arg(long, conflicts_with_all = ["threads", "queue_depth"])]
+    pub low_latency: bool,
+
+    /// Number of decoded access units buffered ahead of the output writer
+    #[arg(long, value_name = "N", default_value_t = 64)]
+    pub queue_depth: usize,
+
+    /// Decode the substreams in MASK instead of those required by the presentation, e.g. 8 for substream 3 only
+    #[arg(long, value_name = "MASK", hide = true, value_parser = clap::value_parser!(u8).range(1..16))]
+    pub substream_mask: Option<u8>,
+}
+
+impl DecodeArgs {
+    /// Inputs decoded one after another, INPUT or those given to `--concat`
+    pub fn inputs(&self) -> &[PathBuf] {
+        match &self.input {
+            Some(input) => std::slice::from_ref(input),
+            None => &self.concat,
+        }
+    }
 }
 
 #[derive(Debug, Args)]
 pub struct InfoArgs {
-    /// Input TrueHD bitstream.
+    /// Input TrueHD bitstream or Matroska file.
     #[arg(value_name = "INPUT")]
     pub input: PathBuf,
+
+    /// Track number of the TrueHD track to read from a Matroska input [default: the only TrueHD track]
+    #[arg(long, value_name = "N")]
+    pub track: Option<u64>,
+
+    /// Decode all presentations and report lossless check and saturation failures.
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Print the stream information as a single JSON document.
+    #[arg(long)]
+    pub json: bool,
+
+    /// Parse every access unit and report the minimum, maximum and distribution of the data rate.
+    #[arg(long)]
+    pub analyze_bitrate: bool,
+
+    /// Parse every access unit and model the decoder FIFO occupancy from the input and output timing.
+    #[arg(long)]
+    pub analyze_timing: bool,
+
+    /// Parse every access unit and list the seamless branches, stream restarts and latency changes.
+    #[arg(long)]
+    pub timing: bool,
+
+    /// Parse every access unit and count the evolution frame payloads by payload ID.
+    #[arg(long)]
+    pub evo_payloads: bool,
+
+    /// Read the major sync of every access unit and list the distinct stream configurations.
+    #[arg(long)]
+    pub scan_all: bool,
+
+    /// Decode the highest presentation and measure its integrated loudness, loudness range and true peak.
+    #[arg(long)]
+    pub measure_loudness: bool,
+
+    /// Decode the highest presentation and report the RMS level of each channel and whether it is digital silence.
+    #[arg(long)]
+    pub analyze_channels: bool,
+
+    /// Seconds of output measured by `--analyze-channels`.
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 60.0,
+        requires = "analyze_channels"
+    )]
+    pub analyze_seconds: f64,
+
+    /// Measure the channels of the whole stream instead of its first seconds.
+    #[arg(
+        long,
+        requires = "analyze_channels",
+        conflicts_with = "analyze_seconds"
+    )]
+    pub full: bool,
+
+    /// Print the parse tree of access unit N (counted from 0) instead of the stream information.
+    #[arg(long, value_name = "N", requires = "json")]
+    pub dump_au: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// Input TrueHD bitstream (use "-" for stdin).
+    #[arg(value_name = "INPUT")]
+    pub input: PathBuf,
+
+    /// Maximum number of problems to list.
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub max_problems: usize,
+}
+
+#[derive(Debug, Args)]
+#[command(group = clap::ArgGroup::new("edits").required(true).multiple(true))]
+pub struct EditArgs {
+    /// Input TrueHD bitstream (use "-" for stdin).
+    #[arg(value_name = "INPUT")]
+    pub input: PathBuf,
+
+    /// Output path of the edited bitstream (use "-" for stdout).
+    #[arg(long, value_name = "PATH")]
+    pub output_path: PathBuf,
+
+    /// Presentation index (0-3) whose metadata is rewritten.
+    #[arg(long, value_name = "INDEX")]
+    pub presentation: u8,
+
+    /// Dialogue level in dBFS (-63 to -1 for presentation 0, -31 to -1 otherwise)
+    #[arg(long, value_name = "DB", allow_hyphen_values = true, group = "edits")]
+    pub dialnorm: Option<i8>,
+
+    /// Whether DRC is applied by default (not available for presentation 3)
+    #[arg(long, value_name = "BOOL", group = "edits")]
+    pub drc_enabled: Option<bool>,
+}
+
+#[derive(Debug, Args)]
+#[command(group = clap::ArgGroup::new("cut").required(true).multiple(true))]
+pub struct TrimArgs {
+    /// Input TrueHD bitstream (use "-" for stdin).
+    #[arg(value_name = "INPUT")]
+    pub input: PathBuf,
+
+    /// Output path of the trimmed bitstream (use "-" for stdout).
+    #[arg(long, value_name = "PATH")]
+    pub output_path: PathBuf,
+
+    /// First access unit to keep
+    #[arg(long, value_name = "N", group = "cut", conflicts_with = "start")]
+    pub start_au: Option<u64>,
+
+    /// Last access unit to keep
+    #[arg(long, value_name = "N", group = "cut", conflicts_with = "end")]
+    pub end_au: Option<u64>,
+
+    /// Start of the cut in seconds of decoded output
+    #[arg(long, value_name = "SECONDS", group = "cut")]
+    pub start: Option<f64>,
+
+    /// End of the cut in seconds of decoded output
+    #[arg(long, value_name = "SECONDS", group = "cut")]
+    pub end: Option<f64>,
+}
+
+#[derive(Debug, Args)]
+pub struct MatArgs {
+    /// Input TrueHD bitstream (use "-" for stdin).
+    #[arg(value_name = "INPUT")]
+    pub input: PathBuf,
+
+    /// Output path of the MAT stream (use "-" for stdout with raw format).
+    #[arg(long, value_name = "PATH")]
+    pub output_path: PathBuf,
+
+    /// Container of the MAT frames
+    #[arg(long, value_enum, default_value_t = MatFormat::Wav)]
+    pub format: MatFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct DemuxArgs {
+    /// Input Matroska file (use "-" for stdin).
+    #[arg(value_name = "INPUT")]
+    pub input: PathBuf,
+
+    /// Output path of the TrueHD bitstream (use "-" for stdout).
+    #[arg(long, value_name = "PATH")]
+    pub output_path: PathBuf,
+
+    /// Track number of the TrueHD track to extract [default: the only TrueHD track]
+    #[arg(long, value_name = "N")]
+    pub track: Option<u64>,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
@@ -121,7 +488,8 @@ impl LogLevel {
 pub enum LogFormat {
     /// Colorized human-readable text.
     Plain,
-    /// Structured JSON per log record.
+    /// Structured JSON per log record, plus one record with the fields of
+    /// each parser and decoder event.
     Json,
 }
 
@@ -129,10 +497,151 @@ pub enum LogFormat {
 pub enum AudioFormat {
     /// Core Audio Format.
     Caf,
-    /// Raw PCM format (24-bit little-endian).
+    /// Raw little-endian PCM format.
     Pcm,
     /// Wave64 format (.wav extension).
     W64,
+    /// WAV format, upgraded to RF64 when larger than 4 GiB.
+    Wav,
+    /// ADM BWF (BW64 with axml and chna chunks) for Atmos content.
+    Adm,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+pub enum MatFormat {
+    /// MAT frames back to back, without IEC 61937 encapsulation.
+    Raw,
+    /// IEC 61937 bursts as 8-channel 16-bit WAV, as sent over HDMI.
+    Wav,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+pub enum BitFormat {
+    /// 16-bit signed integer, TPDF dithered.
+    #[value(alias = "16")]
+    Int16,
+    /// 24-bit signed integer.
+    #[value(alias = "24")]
+    Int24,
+    /// 32-bit signed integer, 24-bit samples in the most significant bits.
+    #[value(alias = "32")]
+    Int32,
+    /// 32-bit float, normalized to [-1.0, 1.0).
+    Float32,
+}
+
+impl BitFormat {
+    /// Size of one sample in bits.
+    pub fn bits_per_sample(self) -> u32 {
+        match self {
+            BitFormat::Int16 => 16,
+            BitFormat::Int24 => 24,
+            BitFormat::Int32 | BitFormat::Float32 => 32,
+        }
+    }
+
+    /// The bit format of samples stored in a file.
+    pub fn from_sample_format(bits: u32, is_float: bool) -> Option<Self> {
+        match (bits, is_float) {
+            (16, false) => Some(BitFormat::Int16),
+            (24, false) => Some(BitFormat::Int24),
+            (32, false) => Some(BitFormat::Int32),
+            (32, true) => Some(BitFormat::Float32),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+pub enum ChannelOrder {
+    /// Order of the WAVE channel mask (L R C LFE Lb Rb Ls Rs ...)
+    Smpte,
+    /// Film order (L C R Ls Rs LFE ...)
+    Film,
+    /// Decoded TrueHD channel assignment order
+    Native,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+pub enum Downmix {
+    /// Stereo (Lo/Ro)
+    #[value(name = "2.0")]
+    Stereo,
+    /// 5.1 surround
+    #[value(name = "5.1")]
+    Surround51,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+pub enum ApplyDrc {
+    /// Heavy DRC gain carried in the restart headers
+    Heavy,
+    /// Full dynamic range
+    None,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+pub enum Duplicates {
+    /// Drop duplicates from the output
+    Skip,
+    /// Write duplicates like any other access unit
+    Keep,
+    /// Stop decoding at the first duplicate
+    Error,
+}
+
+impl From<Duplicates> for truehd::process::decode::DuplicateHandling {
+    fn from(duplicates: Duplicates) -> Self {
+        match duplicates {
+            Duplicates::Skip => Self::Skip,
+            Duplicates::Keep => Self::Keep,
+            Duplicates::Error => Self::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+pub enum ErrorCategory {
+    /// Input and output timing, latency and seamless branches
+    Timing,
+    /// Peak and fixed data rates
+    DataRate,
+    /// Parity and CRC checks
+    Crc,
+    /// Major sync fields
+    Sync,
+    /// Substream and block structure
+    Structure,
+    /// Huffman coded residuals
+    Huffman,
+    /// Filter parameters
+    Filter,
+    /// Matrix parameters
+    Matrixing,
+    /// Lossless checks of the decoded output
+    Lossless,
+    /// EVO frames, extra data and Atmos metadata
+    Metadata,
+    /// Output files
+    Output,
+}
+
+impl From<ErrorCategory> for Category {
+    fn from(category: ErrorCategory) -> Self {
+        match category {
+            ErrorCategory::Timing => Self::Timing,
+            ErrorCategory::DataRate => Self::DataRate,
+            ErrorCategory::Crc => Self::Crc,
+            ErrorCategory::Sync => Self::Sync,
+            ErrorCategory::Structure => Self::Structure,
+            ErrorCategory::Huffman => Self::Huffman,
+            ErrorCategory::Filter => Self::Filter,
+            ErrorCategory::Matrixing => Self::Matrixing,
+            ErrorCategory::Lossless => Self::Lossless,
+            ErrorCategory::Metadata => Self::Metadata,
+            ErrorCategory::Output => Self::Output,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
@@ -149,6 +658,81 @@ pub enum WarpMode {
     LoRo,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum BedConformLayout {
+    /// L R C LFE Ls Rs
+    #[value(name = "5.1")]
+    Bed5_1,
+    /// 5.1 with top surround pair
+    #[value(name = "5.1.2")]
+    Bed5_1_2,
+    /// 5.1 with rear surround pair
+    #[value(name = "7.1")]
+    Bed7_1,
+    /// 7.1 with top surround pair
+    #[default]
+    #[value(name = "7.1.2")]
+    Bed7_1_2,
+    /// 7.1 with front and rear height pairs
+    #[value(name = "7.1.4")]
+    Bed7_1_4,
+    /// 7.1.4 with wide pair and top surround pair, the full DAMF bed
+    #[value(name = "9.1.6")]
+    Bed9_1_6,
+}
+
+impl BedConformLayout {
+    /// Bed speakers of the layout, in output order
+    pub fn speakers(self) -> &'static [SpeakerLabels] {
+        use SpeakerLabels::*;
+        match self {
+            Self::Bed5_1 => &[L, R, C, LFE, Lss, Rss],
+            Self::Bed5_1_2 => &[L, R, C, LFE, Lss, Rss, Lts, Rts],
+            Self::Bed7_1 => &[L, R, C, LFE, Lss, Rss, Lrs, Rrs],
+            Self::Bed7_1_2 => &[L, R, C, LFE, Lss, Rss, Lrs, Rrs, Lts, Rts],
+            Self::Bed7_1_4 => &[L, R, C, LFE, Lss, Rss, Lrs, Rrs, Lfh, Rfh, Lrh, Rrh],
+            Self::Bed9_1_6 => &[
+                L, R, C, LFE, Lss, Rss, Lrs, Rrs, Lw, Rw, Lfh, Rfh, Lts, Rts, Lrh, Rrh,
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Fps {
+    /// 23.976 fps
+    #[value(name = "23.976")]
+    R23_976,
+    /// 24 fps
+    #[value(name = "24")]
+    R24,
+    /// 25 fps
+    #[value(name = "25")]
+    R25,
+    /// 29.97 fps non-drop frame
+    #[value(name = "29.97")]
+    R29_97,
+    /// 29.97 fps drop frame
+    #[value(name = "29.97df")]
+    R29_97df,
+    /// 30 fps
+    #[value(name = "30")]
+    R30,
+}
+
+impl From<Fps> for crate::damf::Fps {
+    fn from(fps: Fps) -> Self {
+        match fps {
+            Fps::R23_976 => Self::R23_976,
+            Fps::R24 => Self::R24,
+            Fps::R25 => Self::R25,
+            Fps::R29_97 => Self::R29_97,
+            Fps::R29_97df => Self::R29_97df,
+            Fps::R30 => Self::R30,
+        }
+    }
+}
+
 impl From<WarpMode> for crate::damf::WarpMode {
     fn from(warp_mode: WarpMode) -> Self {
         match warp_mode {
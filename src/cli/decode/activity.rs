@@ -0,0 +1,154 @@
+use serde::Serialize;
+use truehd::process::decode::DecodedAccessUnit;
+use truehd::structs::channel::ChannelLabel;
+
+/// Level and activity of the channels of the decoded output.
+#[derive(Debug, Serialize)]
+pub struct ChannelActivity {
+    /// Duration of the measured output in seconds
+    pub duration: f64,
+    pub channels: Vec<ChannelLevel>,
+}
+
+/// RMS level of a channel
+#[derive(Debug, Serialize)]
+pub struct ChannelLevel {
+    pub channel: String,
+    /// RMS level in dBFS, absent for digital silence
+    pub rms: Option<f64>,
+    /// Whether every sample of the channel is zero
+    pub silent: bool,
+}
+
+/// Accumulated energy of a channel label
+#[derive(Debug)]
+struct ChannelEnergy {
+    label: ChannelLabel,
+    sum_of_squares: f64,
+    samples: u64,
+    nonzero: bool,
+}
+
+/// Per-channel RMS and digital silence meter.
+///
+/// Channels are tracked by their label, so a channel keeps its measurement
+/// when the layout of the access units changes.
+#[derive(Debug, Default)]
+pub struct ChannelActivityMeter {
+    channels: Vec<ChannelEnergy>,
+    /// Measured duration in seconds
+    duration: f64,
+}
+
+impl ChannelActivityMeter {
+    /// Measure the samples of `decoded`.
+    pub fn process(&mut self, decoded: &DecodedAccessUnit) {
+        let full_scale = (1u64 << (decoded.bit_depth - 1)) as f64;
+        let frames = &decoded.pcm_data[..decoded.sample_length];
+
+        for (index, &label) in decoded.channel_labels[..decoded.channel_count]
+            .iter()
+            .enumerate()
+        {
+            let channel = match self.channels.iter().position(|c| c.label == label) {
+                Some(position) => &mut self.channels[position],
+                None => {
+                    self.channels.push(ChannelEnergy {
+                        label,
+                        sum_of_squares: 0.0,
+                        samples: 0,
+                        nonzero: false,
+                    });
+                    self.channels.last_mut().unwrap()
+                }
+            };
+
+            for frame in frames {
+                let sample = frame[index];
+                channel.nonzero |= sample != 0;
+                let x = sample as f64 / full_scale;
+                channel.sum_of_squares += x * x;
+            }
+            channel.samples += frames.len() as u64;
+        }
+
+        self.duration += decoded.sample_length as f64 / decoded.sampling_frequency as f64;
+    }
+
+    /// Measured duration in seconds
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// Levels of the channels measured so far.
+    pub fn result(&self) -> ChannelActivity {
+        ChannelActivity {
+            duration: self.duration,
+            channels: self
+                .channels
+                .iter()
+                .map(|channel| ChannelLevel {
+                    channel: channel.label.to_string(),
+                    rms: channel
+                        .nonzero
+                        .then(|| 10.0 * (channel.sum_of_squares / channel.samples as f64).log10()),
+                    silent: !channel.nonzero,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[test]
+fn silent_channel_detected() {
+    use std::f64::consts::PI;
+    use truehd::utils::buffer_pool::PooledPcm;
+
+    // 1 kHz full scale sine on L, -20 dBFS on R and a silent C
+    let mut meter = ChannelActivityMeter::default();
+    for au in 0..1200 {
+        let mut pcm_data = PooledPcm::default();
+        for (sample_index, frame) in pcm_data[..40].iter_mut().enumerate() {
+            let position = (au * 40 + sample_index) as f64;
+            let sine = (2.0 * PI * 1000.0 * position / 48000.0).sin();
+            frame[0] = (sine * 8388607.0).round() as i32;
+            frame[1] = (sine * 838860.7).round() as i32;
+        }
+        meter.process(&DecodedAccessUnit {
+            sampling_frequency: 48000,
+            sample_length: 40,
+            channel_count: 3,
+            pcm_data,
+            bit_depth: 24,
+            channel_labels: vec![ChannelLabel::L, ChannelLabel::R, ChannelLabel::C],
+            oamd: Vec::new(),
+            evo_payloads: Vec::new(),
+            is_duplicate: false,
+            substream_info_changed: false,
+            config_changed: false,
+            stream_start: false,
+            silence_samples: 0,
+            au_index: 0,
+            sample_position: 0,
+            discontinuity: None,
+            heavy_drc_gain: None,
+            dialogue_level: None,
+            other_presentations: Vec::new(),
+        });
+    }
+
+    let activity = meter.result();
+    assert!((activity.duration - 1.0).abs() < 1e-9);
+    let channels = &activity.channels;
+    assert_eq!(channels.len(), 3);
+
+    // The RMS of a sine is 3 dB below its peak
+    let rms = |index: usize| channels[index].rms.unwrap();
+    assert!((rms(0) + 3.01).abs() < 0.01, "{}", rms(0));
+    assert!((rms(1) + 23.01).abs() < 0.01, "{}", rms(1));
+    assert!(!channels[0].silent && !channels[1].silent);
+
+    assert_eq!(channels[2].channel, ChannelLabel::C.to_string());
+    assert_eq!(channels[2].rms, None);
+    assert!(channels[2].silent);
+}
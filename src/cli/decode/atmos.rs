@@ -1,9 +1,29 @@
 use super::output::create_path_with_suffix;
-use crate::damf::Data;
-use anyhow::Result;
+use super::partial::{PartialOutputs, partial_path};
+use super::resume::MetadataResume;
+use super::sync::output_start_time;
+use crate::cli::command::{Fps, WarpMode};
+use crate::damf::{Configuration, Data, Event, MetadataThinning, ObjectRegistry};
+use anyhow::{Context, Result};
+use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
+use truehd::structs::oamd::{ObjectAudioMetadataPayload, SpeakerLabels};
+use truehd::structs::timestamp::Timestamp;
+
+/// Header values not carried by the OAMD payload
+pub struct HeaderOptions<'a> {
+    /// Warp mode used when not present in metadata
+    pub warp_mode: Option<WarpMode>,
+    /// Frame rate used when the stream has no SMPTE timestamp
+    pub fps: Option<Fps>,
+    /// SMPTE timestamp preceding the first access unit
+    pub timestamp: Option<&'a Timestamp>,
+    /// Samples between the first access unit and the first output sample
+    pub start_samples: u64,
+    pub sample_rate: u32,
+}
 
 fn write_damf_header_to_file(header_path: &Path, damf_data: &Data) -> Result<()> {
     log::info!("Creating DAMF header file: {}", header_path.display());
@@ -14,24 +34,54 @@ fn write_damf_header_to_file(header_path: &Path, damf_data: &Data) -> Result<()>
     Ok(())
 }
 
+fn apply_header_options(damf_data: &mut Data, options: &HeaderOptions) {
+    let Some(presentation) = damf_data.presentations_mut().first_mut() else {
+        return;
+    };
+
+    // Override warp_mode if specified and not present in metadata
+    if let Some(cli_warp_mode) = options.warp_mode {
+        if presentation.warp_mode.is_none() {
+            presentation.warp_mode = Some(cli_warp_mode.into());
+        }
+    }
+
+    if let Some(fps) = options.fps {
+        presentation.fps = Some(fps.into());
+    }
+
+    if let Some(timestamp) = options.timestamp {
+        match crate::damf::Fps::from_framerate(&timestamp.framerate, timestamp.dropframe) {
+            Some(fps) => presentation.fps = Some(fps),
+            None => log::warn!(
+                "SMPTE frame rate {} has no DAMF equivalent, writing {:?}",
+                timestamp.framerate,
+                presentation.fps
+            ),
+        }
+
+        // The output starts at the timestamp once trimmed, which is also
+        // the first frame of action
+        if let Some(offset) =
+            output_start_time(timestamp, options.start_samples, options.sample_rate)
+        {
+            presentation.offset = offset;
+            presentation.ffoa = Some(offset);
+        }
+    }
+}
+
+/// Write the DAMF header of the files named after `base_path` to `header_path`
 pub fn create_damf_header_file(
     base_path: &Path,
+    header_path: &Path,
     oamd: &truehd::structs::oamd::ObjectAudioMetadataPayload,
-    warp_mode: Option<crate::cli::command::WarpMode>,
+    options: &HeaderOptions,
 ) -> Result<()> {
-    let header_path = create_path_with_suffix(base_path, "atmos");
     let mut damf_data = Data::with_oamd_payload(oamd, base_path);
+    apply_header_options(&mut damf_data, options);
 
-    // Override warp_mode if specified and not present in metadata
-    if let Some(cli_warp_mode) = warp_mode {
-        if let Some(presentation) = damf_data.presentations_mut().first_mut() {
-            if presentation.warp_mode.is_none() {
-                presentation.warp_mode = Some(cli_warp_mode.into());
-            }
-        }
-    }
-
-    write_damf_header_to_file(&header_path, &damf_data)
+    write_damf_header_to_file(header_path, &damf_data)
 }
 
 pub fn create_atmos_header_path(base_path: &Path) -> PathBuf {
@@ -40,20 +90,215 @@ pub fn create_atmos_header_path(base_path: &Path) -> PathBuf {
 
 pub fn rewrite_damf_header_for_bed_conform(
     base_path: &Path,
+    header_path: &Path,
     oamd: &truehd::structs::oamd::ObjectAudioMetadataPayload,
-    warp_mode: Option<crate::cli::command::WarpMode>,
+    speakers: &[truehd::structs::oamd::SpeakerLabels],
+    options: &HeaderOptions,
 ) -> Result<()> {
-    let header_path = create_atmos_header_path(base_path);
-    let mut damf_data = Data::with_oamd_payload_bed_conform(oamd, base_path);
+    let mut damf_data = Data::with_oamd_payload_bed_conform(oamd, base_path, speakers);
+    apply_header_options(&mut damf_data, options);
 
-    // Override warp_mode if specified and not present in metadata
-    if let Some(cli_warp_mode) = warp_mode {
-        if let Some(presentation) = damf_data.presentations_mut().first_mut() {
-            if presentation.warp_mode.is_none() {
-                presentation.warp_mode = Some(cli_warp_mode.into());
-            }
+    write_damf_header_to_file(header_path, &damf_data)
+}
+
+/// List every object of `objects` in the DAMF header at `header_path`, so
+/// that objects first seen after the header was written are declared.
+pub fn pad_damf_header_objects(header_path: &Path, objects: &ObjectRegistry) -> Result<()> {
+    let header = std::fs::read_to_string(header_path)
+        .with_context(|| format!("Failed to read {}", header_path.display()))?;
+    let mut damf_data: Data = serde_yaml_ng::from_str(&header)
+        .with_context(|| format!("Failed to parse {}", header_path.display()))?;
+    if damf_data.pad_objects(objects.ids()) {
+        log::info!(
+            "Declaring the {} objects found in the stream in the DAMF header",
+            objects.len()
+        );
+        write_damf_header_to_file(header_path, &damf_data)?;
+    }
+    Ok(())
+}
+
+/// Base path of the files of an output segment, derived from the path of its
+/// audio file
+///
+/// Split channel output records the base path itself.
+pub fn segment_base_path(audio_path: &Path, split_channels: bool) -> PathBuf {
+    let mut base_path = audio_path.to_path_buf();
+    if split_channels {
+        return base_path;
+    }
+
+    // Remove .atmos.audio or any other extension
+    if base_path.extension() == Some(OsStr::new("audio")) {
+        base_path.set_extension("");
+        if base_path.extension() == Some(OsStr::new("atmos")) {
+            base_path.set_extension("");
+        }
+    } else {
+        base_path.set_extension("");
+    }
+    base_path
+}
+
+/// Writes the DAMF header and `.atmos.metadata` events of an output file
+///
+/// Payloads are diffed against the events last written, with object IDs kept
+/// stable for the whole file. The files are written under their partial
+/// paths until the decode finalizes them.
+#[derive(Default)]
+pub struct MetadataWriter {
+    writer: Option<BufWriter<File>>,
+    /// Events as last written, which later payloads are diffed against
+    prev_events: Vec<Event>,
+    /// Stable IDs of the objects of the current output
+    object_registry: ObjectRegistry,
+    /// DAMF header of the current output
+    header_path: Option<PathBuf>,
+    /// Events before this position are in the resumed metadata file
+    resume_samples: Option<u64>,
+}
+
+impl MetadataWriter {
+    /// Append to the metadata file of an interrupted decode.
+    ///
+    /// Events are still diffed from the start of the stream, but those
+    /// already in the file are not written again.
+    pub fn resume(&mut self, metadata: MetadataResume, partial_outputs: &mut PartialOutputs) {
+        partial_outputs.create(&metadata.path);
+        self.writer = Some(metadata.writer);
+        self.resume_samples = Some(metadata.samples);
+    }
+
+    /// Write the DAMF header of the files named after `base_path`, with the
+    /// bed conformed to `bed_conform_speakers` if given
+    pub fn create_header(
+        &mut self,
+        base_path: &Path,
+        oamd: &ObjectAudioMetadataPayload,
+        bed_conform_speakers: Option<&[SpeakerLabels]>,
+        options: &HeaderOptions,
+        partial_outputs: &mut PartialOutputs,
+    ) -> Result<()> {
+        let header_path = create_atmos_header_path(base_path);
+        let partial_header_path = partial_outputs.create(&header_path);
+        self.header_path = Some(header_path);
+
+        match bed_conform_speakers {
+            Some(speakers) => rewrite_damf_header_for_bed_conform(
+                base_path,
+                &partial_header_path,
+                oamd,
+                speakers,
+                options,
+            ),
+            None => create_damf_header_file(base_path, &partial_header_path, oamd, options),
+        }
+    }
+
+    /// Whether the metadata file is open
+    pub fn is_open(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Create the metadata file at `path`
+    pub fn create(&mut self, path: &Path, partial_outputs: &mut PartialOutputs) -> Result<()> {
+        log::info!("Creating metadata file: {}", path.display());
+        let file = File::create(partial_outputs.create(path))?;
+        self.writer = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    /// Events of `oamd` with the stable object IDs of the current output
+    pub fn configuration(
+        &mut self,
+        oamd: &ObjectAudioMetadataPayload,
+        sample_rate: u32,
+        sample_pos: u64,
+    ) -> Configuration {
+        Configuration::with_object_registry(
+            oamd,
+            sample_rate,
+            sample_pos,
+            &mut self.object_registry,
+        )
+    }
+
+    /// Serialized events of `configuration` that differ from those written
+    /// before, `None` if there are none or they are in the resumed file
+    pub fn diff(
+        &mut self,
+        configuration: Configuration,
+        thinning: Option<MetadataThinning>,
+    ) -> Option<String> {
+        // Events already in a resumed metadata file only advance the diff state
+        let resumed = configuration
+            .sample_pos()
+            .zip(self.resume_samples)
+            .is_some_and(|(pos, samples)| pos < samples);
+        let events = configuration.serialize_events_diff(&mut self.prev_events, thinning);
+
+        (!events.is_empty() && !resumed).then_some(events)
+    }
+
+    /// Append serialized events to the metadata file, if open
+    pub fn write(&mut self, events: &str) -> Result<()> {
+        if let Some(writer) = &mut self.writer {
+            write!(writer, "{events}")?;
+            writer.flush()?;
         }
+        Ok(())
     }
 
-    write_damf_header_to_file(&header_path, &damf_data)
+    /// Close the metadata file and declare the objects found after the DAMF
+    /// header was written in it, starting over for the next output
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+
+        if let Some(header_path) = self.header_path.take()
+            && let header_path = partial_path(&header_path)
+            && header_path.exists()
+        {
+            pad_damf_header_objects(&header_path, &self.object_registry)?;
+        }
+        self.object_registry = ObjectRegistry::default();
+        self.prev_events.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use truehd::structs::oamd::{TEST_DATA, TEST_DATA_TRIM};
+
+    #[test]
+    fn resumed_events_only_advance_diff_state() -> Result<()> {
+        let first = ObjectAudioMetadataPayload::read(TEST_DATA)?;
+        let second = ObjectAudioMetadataPayload::read(TEST_DATA_TRIM)?;
+        let mut metadata = MetadataWriter {
+            resume_samples: Some(1600),
+            ..Default::default()
+        };
+
+        // Events before the resume position are already in the file
+        let configuration = metadata.configuration(&first, 48000, 0);
+        assert!(metadata.diff(configuration, None).is_none());
+        let configuration = metadata.configuration(&first, 48000, 1600);
+        assert!(metadata.diff(configuration, None).is_none());
+
+        // Later events are diffed against the resumed ones
+        let configuration = metadata.configuration(&second, 48000, 3200);
+        let events = metadata.diff(configuration, None).expect("changed events");
+        assert!(!events.starts_with("sampleRate"));
+
+        // The next output starts over with the complete configuration
+        metadata.finish()?;
+        metadata.resume_samples = None;
+        let configuration = metadata.configuration(&second, 48000, 4800);
+        let events = metadata.diff(configuration, None).expect("first events");
+        assert!(events.starts_with("sampleRate"));
+        Ok(())
+    }
 }
@@ -1,42 +1,187 @@
-use super::decoder_thread::{DecoderThreadConfig, spawn_decoder_thread};
+use super::decoder_thread::{DecoderThreadConfig, error_summary, spawn_decoder_thread};
 use super::handler::{DecodeHandler, FrameHandlerContext, WriterState};
-use super::progress::{create_progress_bar, estimate_total_frames};
-use crate::cli::command::{AudioFormat, Cli, DecodeArgs};
-use anyhow::Result;
+use super::loudness::{Loudness, LoudnessMeter};
+use super::oamd_dump::OamdDump;
+use super::output::{create_path_with_suffix, is_stdout_path};
+use super::partial::remove_stale_partials;
+use super::progress::{create_progress_bar, estimate_total_frames, metrics_str};
+use super::resume::ResumeState;
+use super::sync::SyncInfo;
+use super::trajectory::TrajectoryWriter;
+use super::trim::detect_start_trim;
+use crate::cli::command::{AudioFormat, BitFormat, Cli, DecodeArgs, Duplicates};
+use anyhow::{Result, bail};
 use indicatif::{MultiProgress, ProgressStyle};
 use log::Level;
-use std::sync::mpsc;
-use truehd::process::{MAX_PRESENTATIONS, decode::Decoder, extract::Extractor, parse::Parser};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, mpsc};
+use std::time::Duration;
+use truehd::process::pipeline::{ErrorBudget, Pipeline, SkipTarget};
+use truehd::process::{
+    Metrics,
+    decode::{DecodeStats, Decoder},
+    parse::Parser,
+};
+use truehd::structs::timestamp::Timestamp;
+use truehd::utils::buffer_pool::PcmPool;
+use truehd::utils::errors::CategoryPolicy;
+use truehd::utils::events::EventSink;
 
-pub fn cmd_decode(args: &DecodeArgs, cli: &Cli, multi: Option<&MultiProgress>) -> Result<()> {
-    if args.presentation > 3 {
+/// Exit code of a decode that completed despite parse or decode errors
+const PARTIAL_FAILURE_EXIT_CODE: u8 = 2;
+
+/// Exit code of a decode interrupted by Ctrl-C, 128 + SIGINT
+const INTERRUPTED_EXIT_CODE: u8 = 130;
+
+/// How often the writer checks for Ctrl-C while waiting for the decoder
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Set by Ctrl-C to finalize the output decoded so far
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Finalize the output at the first Ctrl-C and exit at the second
+fn install_interrupt_handler() {
+    let result = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(INTERRUPTED_EXIT_CODE.into());
+        }
+    });
+    if let Err(e) = result {
+        log::debug!("Ctrl-C handler not installed: {e}");
+    }
+}
+
+pub fn cmd_decode(args: &DecodeArgs, cli: &Cli, multi: Option<&MultiProgress>) -> Result<ExitCode> {
+    let mut presentations = args.presentation.clone();
+    presentations.sort_unstable();
+    presentations.dedup();
+    if let Some(presentation) = presentations.iter().find(|&&presentation| presentation > 3) {
         return Err(anyhow::anyhow!(
-            "Presentation index must be 0-3, got {}",
-            args.presentation
+            "Presentation index must be 0-3, got {presentation}"
         ));
     }
+    let Some(&presentation) = presentations.last() else {
+        bail!("--presentation needs at least one index");
+    };
+    let multiple = presentations.len() > 1;
+
+    if args.threads == 0 {
+        bail!("--threads must be at least 1");
+    }
+
+    if args.substream_mask.is_some() && args.threads > 1 {
+        bail!("--substream-mask is not supported with --threads");
+    }
 
+    let inputs = args.inputs();
     log::info!(
         "Decoding TrueHD stream: {} (strict mode: {}, presentation: {})",
-        args.input.display(),
+        inputs
+            .iter()
+            .map(|input| input.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
         cli.strict,
-        args.presentation
+        presentations
+            .iter()
+            .map(u8::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
     );
 
-    let is_pipe = args.input.to_string_lossy() == "-";
+    let is_pipe = inputs.iter().any(|input| input.to_string_lossy() == "-");
+    if is_pipe && inputs.len() > 1 {
+        bail!("--concat does not read from stdin");
+    }
     let base_path = args.output_path.clone();
 
     if let Some(ref path) = base_path {
         log::info!("Output path specified: {}", path.display());
     }
 
-    // Estimate total frames if needed
-    let should_estimate = !args.no_estimate_progress && !is_pipe && multi.is_some();
+    let to_stdout = base_path.as_deref().is_some_and(is_stdout_path);
+    // Only an explicit --format caf turns stdout output from raw PCM into CAF
+    let stdout_caf = to_stdout && args.format == Some(AudioFormat::Caf);
+    let format = args.format.unwrap_or(AudioFormat::Caf);
+    if to_stdout && args.resume {
+        bail!("--resume is not supported when writing to stdout");
+    }
+    if args.metadata_path.is_some() && !to_stdout {
+        bail!("--metadata-path is only supported with --output-path -");
+    }
+    if to_stdout && args.metadata_only && args.metadata_path.is_none() {
+        bail!("--metadata-only with --output-path - requires --metadata-path");
+    }
+    if stdout_caf && args.bed_conform {
+        bail!("--bed-conform is not supported with --format caf and --output-path -");
+    }
+    if multiple {
+        if to_stdout {
+            bail!("Several presentations cannot be written to stdout");
+        }
+        if args.resume || args.metadata_only || args.substream_mask.is_some() {
+            bail!(
+                "Several presentations are not supported with --resume, --metadata-only or --substream-mask"
+            );
+        }
+        if args.threads > 1 {
+            bail!("Several presentations are not supported with --threads");
+        }
+    }
+    if format == AudioFormat::Adm {
+        if presentation != 3 || multiple {
+            bail!("--format adm is only supported for presentation 3");
+        }
+        if to_stdout || args.metadata_only || args.bed_conform {
+            bail!(
+                "--format adm is not supported with --output-path -, --metadata-only or --bed-conform"
+            );
+        }
+    }
+    // Atmos files are written next to the audio, or to the side path for stdout;
+    // ADM BWF output carries the metadata in the audio file
+    let metadata_path = if to_stdout {
+        args.metadata_path.clone()
+    } else if format == AudioFormat::Adm {
+        None
+    } else {
+        base_path.clone()
+    };
+
+    if args
+        .duration
+        .is_some_and(|duration| !duration.is_finite() || duration <= 0.0)
+    {
+        bail!("--duration must be a positive number of seconds");
+    }
+
+    if args
+        .expected_duration
+        .is_some_and(|duration| !duration.is_finite() || duration <= 0.0)
+    {
+        bail!("--expected-duration must be a positive number of seconds");
+    }
+
+    // Estimate total frames if needed; a length hint replaces counting the input
+    let has_length_hint = args.expected_frames.is_some() || args.expected_duration.is_some();
+    let should_estimate =
+        !args.no_estimate_progress && !is_pipe && !has_length_hint && multi.is_some();
     let total_frames = if should_estimate {
-        Some(estimate_total_frames(&args.input)?)
+        Some(
+            inputs
+                .iter()
+                .map(|input| estimate_total_frames(input, args.track))
+                .sum::<Result<u64>>()?,
+        )
+    } else if args.expected_frames.is_some() {
+        args.expected_frames
     } else {
         if is_pipe {
-            log::debug!("Skipping progress estimation for pipe input");
+            log::debug!(
+                "Skipping progress estimation for pipe input, use --expected-frames or --expected-duration"
+            );
         } else if args.no_estimate_progress {
             log::debug!("Progress estimation disabled by --no-estimate-progress flag");
         }
@@ -50,15 +195,21 @@ pub fn cmd_decode(args: &DecodeArgs, cli: &Cli, multi: Option<&MultiProgress>) -
         None
     };
 
-    // Setup decoder components
-    let (tx, rx) = mpsc::channel();
+    // Setup decoder components; the bounded queue stalls decoding while the
+    // writer catches up, so memory stays flat with slow output
+    let queue_depth = if args.low_latency {
+        1
+    } else {
+        args.queue_depth
+    };
+    let (tx, rx) = mpsc::sync_channel(queue_depth);
     let pb_clone = pb.clone();
     let strict_mode = cli.strict;
-    let presentation = args.presentation;
 
-    let extractor = Extractor::default();
     let mut parser = Parser::default();
     let mut decoder = Decoder::default();
+    // Blocks in the queue plus those held by the decoder and the writer
+    decoder.set_pcm_pool(PcmPool::new(queue_depth + 2));
 
     // Configure fail level based on strict mode
     let fail_level = if strict_mode {
@@ -66,75 +217,293 @@ pub fn cmd_decode(args: &DecodeArgs, cli: &Cli, multi: Option<&MultiProgress>) -
     } else {
         Level::Error
     };
-    parser.set_fail_level(fail_level);
-    decoder.set_fail_level(fail_level);
+    parser.set_repair_branch(args.repair_branch);
+    parser.set_allow_format_change(args.allow_format_change);
+
+    let category_policies = cli.category_policies()?;
+    let mut category_policy = CategoryPolicy::default();
+    for &(category, policy) in &category_policies {
+        category_policy.set(category, policy);
+    }
+    let event_logger = cli.event_logger();
+
+    // Metrics feed the progress display and the final breakdown
+    let collect_metrics = pb.is_some() || log::log_enabled!(Level::Info);
+
+    let state = WriterState {
+        fail_level,
+        category_policy,
+        event_sink: event_logger.map(EventSink::new),
+    };
+
+    let bit_format = args.bit_format;
 
-    let state = WriterState { fail_level };
+    let trim_samples = if args.no_trim {
+        0
+    } else if let Some(trim_start) = args.trim_start {
+        trim_start
+    } else if is_pipe {
+        log::info!("Start trim detection is not available for pipe input, use --trim-start");
+        0
+    } else {
+        match detect_start_trim(&inputs[0], args.track)? {
+            Some(timing) => {
+                if timing > 0 {
+                    log::info!(
+                        "{timing} samples are trimmed from the beginning of the stream, use --no-trim to keep them"
+                    );
+                }
+                timing
+            }
+            None => {
+                log::info!("No high-resolution output timing found, not trimming");
+                0
+            }
+        }
+    };
 
-    // Setup required presentations
-    let mut required_presentations = [false; MAX_PRESENTATIONS];
-    required_presentations[..=presentation as usize]
-        .iter_mut()
-        .for_each(|p| *p = true);
-    parser.set_required_presentations(&required_presentations);
+    // Handle decoded frames, each presentation with its own handler
+    let mut outputs = Vec::with_capacity(presentations.len());
+    for &presentation in &presentations {
+        let effective_format = if presentation == 3 && format != AudioFormat::Adm {
+            if format != AudioFormat::Caf {
+                log::info!(
+                    "Forcing CAF format for presentation 3, ignoring --format {:?}",
+                    format
+                );
+            }
+            AudioFormat::Caf
+        } else {
+            format
+        };
+
+        // Raw PCM on stdout supports every bit format
+        if !to_stdout && bit_format == BitFormat::Float32 && effective_format != AudioFormat::Caf {
+            bail!("--bit-format float32 is only supported with --format caf");
+        }
+        if args.split_channels && (to_stdout || effective_format != AudioFormat::Caf) {
+            bail!("--split-channels is only supported with --format caf and an output file");
+        }
+
+        let mut handler = DecodeHandler::default();
+        handler.split_channels = args.split_channels;
+        handler.bit_format = bit_format;
+        handler.channel_order = args.channel_order;
+        handler.downmix = args.downmix;
+        handler.apply_drc = args.apply_drc;
+        handler.apply_dialnorm = args.apply_dialnorm;
+        handler.resample_to_48k = args.resample_to_48k;
+        handler.bed_conform_layout = args.bed_conform_layout;
+        handler.apply_object_gains = args.apply_object_gains;
+        handler.presentation = presentation;
+        handler.to_stdout = to_stdout;
+        handler.stdout_caf = stdout_caf;
+        handler.low_latency = args.low_latency;
+        handler.loudness = args.measure_loudness.then(LoudnessMeter::default);
+        handler.trim_samples = trim_samples;
+        handler.excerpt_start = args.start;
+        handler.excerpt_duration = args.duration;
+        handler.metadata_only = args.metadata_only;
+
+        // Several presentations are written to files suffixed with their index
+        let suffixed = |path: &Option<PathBuf>| match path {
+            Some(path) if multiple => {
+                Some(create_path_with_suffix(path, &format!("p{presentation}")))
+            }
+            path => path.clone(),
+        };
+        outputs.push(PresentationOutput {
+            presentation,
+            handler,
+            base_path: suffixed(&base_path),
+            metadata_path: suffixed(&metadata_path),
+            format: effective_format,
+        });
+    }
+
+    // The highest presentation carries the object audio metadata and drives
+    // the progress, the others are written alongside it
+    let PresentationOutput {
+        mut handler,
+        base_path,
+        metadata_path,
+        format: effective_format,
+        ..
+    } = outputs
+        .pop()
+        .ok_or_else(|| anyhow::anyhow!("No presentation selected"))?;
+    let mut others = outputs;
+    if let Some(dir) = &args.dump_oamd {
+        handler.oamd_dump = Some(OamdDump::create(dir)?);
+    }
+    if let Some(path) = &args.export_trajectories {
+        handler.trajectories = Some(TrajectoryWriter::create(path, args.csv)?);
+    }
+
+    let mut skip_target = None;
+    let mut resumed = false;
+    if let (true, Some(base_path)) = (args.resume, &base_path) {
+        match ResumeState::open(base_path, effective_format, bit_format)? {
+            Some(resume) => {
+                resumed = true;
+                log::info!(
+                    "Resuming {} after {} samples",
+                    resume.audio_path.display(),
+                    resume.samples
+                );
+                if resume.samples > 0 {
+                    decoder.set_skip_decode(true);
+                }
+                // The decoder counts the trimmed samples the output file lacks
+                skip_target = Some(SkipTarget::Resume(resume.samples + trim_samples));
+                handler.resume_from(resume);
+            }
+            None => log::warn!("No partial output found to resume, starting from the beginning"),
+        }
+    }
+
+    // Files are written under partial names until finalized, those of an
+    // interrupted decode are removed unless resumed
+    let output_base = if to_stdout {
+        &args.metadata_path
+    } else {
+        &base_path
+    };
+    if let (false, Some(output_base)) = (resumed, output_base) {
+        remove_stale_partials(output_base)?;
+    }
+    for output in &others {
+        if let Some(output_base) = &output.base_path {
+            remove_stale_partials(output_base)?;
+        }
+    }
+    if let Some(start) = args.start.filter(|&start| start > 0.0) {
+        decoder.set_skip_decode(true);
+        skip_target = Some(SkipTarget::Start {
+            seconds: start,
+            trim_samples,
+        });
+    }
+
+    // Resuming and metadata-only mode drive the decoder access unit by access unit
+    if args.threads > 1 {
+        if skip_target.is_some() || args.metadata_only {
+            log::warn!("--threads is ignored with --resume, --start and --metadata-only");
+        } else {
+            log::info!("Decoding with {} threads", args.threads);
+        }
+    }
+
+    let mut pipeline = Pipeline::builder()
+        .presentations(
+            &presentations
+                .iter()
+                .map(|&presentation| presentation as usize)
+                .collect::<Vec<_>>(),
+        )
+        .substream_mask(args.substream_mask)
+        .fail_level(fail_level)
+        .duplicate_handling(args.duplicates.into())
+        // The raw OAMD payloads are dumped from the EVO payloads
+        .keep_evo_payloads(args.dump_oamd.is_some())
+        .metrics(collect_metrics)
+        .strict(strict_mode)
+        .max_errors(args.max_errors)
+        .threads(args.threads)
+        .metadata_only(args.metadata_only)
+        .skip_target(skip_target)
+        .parser(parser)
+        .decoder(decoder);
+    for &(category, policy) in &category_policies {
+        pipeline = pipeline.category_policy(category, policy);
+    }
+
+    let timestamp = Arc::new(OnceLock::new());
+    let atmos = Arc::new(OnceLock::new());
+    let metrics = collect_metrics.then(|| Arc::new(Mutex::new(Metrics::default())));
 
     // Spawn decoder thread
     let decode_thread = spawn_decoder_thread(DecoderThreadConfig {
-        input_path: args.input.clone(),
-        presentation,
-        strict_mode,
+        input_paths: inputs.to_vec(),
+        track: args.track,
+        pipeline: pipeline.build()?,
         tx,
         pb_clone,
-        extractor,
-        parser,
-        decoder,
+        // The progress bar ends with the excerpt
+        expected_duration: args
+            .duration
+            .map(|duration| args.start.unwrap_or(0.0) + duration)
+            .into_iter()
+            .chain(args.expected_duration)
+            .reduce(f64::min),
+        timestamp: timestamp.clone(),
+        atmos: atmos.clone(),
+        metrics: metrics.clone(),
+        event_logger,
     });
 
-    // Handle decoded frames
-    let mut handler = DecodeHandler::default();
     let start_time = std::time::Instant::now();
+    install_interrupt_handler();
 
-    let effective_format = if args.presentation == 3 {
-        if args.format != AudioFormat::Caf {
-            log::info!(
-                "Forcing CAF format for presentation 3, ignoring --format {:?}",
-                args.format
-            );
+    let mut interrupted = false;
+    loop {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            log::warn!("Interrupted, finalizing the output decoded so far");
+            interrupted = true;
+            break;
         }
-        AudioFormat::Caf
-    } else {
-        args.format
-    };
 
-    while let Ok(result) = rx.recv() {
+        let result = match rx.recv_timeout(INTERRUPT_POLL_INTERVAL) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
         match result {
-            Ok(decoded) => {
-                // Check if substream info changed and handle it before processing the frame
-                if decoded.substream_info_changed {
-                    // Store the current sample position as the start of the new segment
-                    handler.segment_start_samples = handler.decoded_samples;
-
-                    // Handle stream restart with actual sample rate and channel count from decoded frame
-                    handler.handle_stream_restart(
-                        &base_path,
-                        effective_format,
-                        decoded.sampling_frequency,
-                        decoded.channel_count,
-                        args.bed_conform,
-                    )?;
-                    handler.is_segmented = true; // Mark that we're now in segmented mode
+            Ok(mut decoded) => {
+                // The lower presentations are written first, shaped like the highest
+                for (presentation, other) in std::mem::take(&mut decoded.other_presentations) {
+                    if let Some(output) = others
+                        .iter_mut()
+                        .find(|output| output.presentation as usize == presentation)
+                    {
+                        let ctx = FrameHandlerContext {
+                            base_path: &output.base_path,
+                            metadata_path: &output.metadata_path,
+                            format: output.format,
+                            pb: &None,
+                            state: &state,
+                            start_time,
+                            bed_conform: args.bed_conform,
+                            warp_mode: args.warp_mode,
+                            fps: args.fps,
+                            timestamp: timestamp.get(),
+                            atmos: atmos.get().copied().unwrap_or(false),
+                            metadata_update_rate: args.metadata_update_rate,
+                            metrics: None,
+                        };
+                        output.handler.handle_access_unit(other, &ctx)?;
+                    }
                 }
 
                 let ctx = FrameHandlerContext {
                     base_path: &base_path,
+                    metadata_path: &metadata_path,
                     format: effective_format,
                     pb: &pb,
                     state: &state,
                     start_time,
                     bed_conform: args.bed_conform,
                     warp_mode: args.warp_mode,
+                    fps: args.fps,
+                    timestamp: timestamp.get(),
+                    atmos: atmos.get().copied().unwrap_or(false),
+                    metadata_update_rate: args.metadata_update_rate,
+                    metrics: metrics.as_deref(),
                 };
-                handler.handle_decoded_frame(decoded, &ctx)?;
+                handler.handle_access_unit(decoded, &ctx)?;
+                if handler.finished() {
+                    break;
+                }
             }
             Err(e) => {
                 if let Some(pb) = pb {
@@ -145,20 +514,77 @@ pub fn cmd_decode(args: &DecodeArgs, cli: &Cli, multi: Option<&MultiProgress>) -
         }
     }
 
+    // Stop the decode thread when the excerpt ended early or on Ctrl-C
+    drop(rx);
+
     // Finalize output
     handler.finalize()?;
+    for output in &mut others {
+        output.handler.finalize()?;
+    }
+
+    for (input, position) in inputs.iter().skip(1).zip(&handler.junctions) {
+        log::info!("{} joins the output at sample {position}", input.display());
+    }
+
+    if args.metadata_only && !handler.has_atmos_metadata {
+        log::warn!("No Atmos metadata found, no output written");
+    }
+    if effective_format == AudioFormat::Adm && !handler.has_atmos_metadata {
+        log::warn!("No Atmos metadata found, the ADM BWF has no axml and chna chunks");
+    }
 
     // Wait for decode thread and finalize progress
-    match decode_thread.join() {
-        Ok(Ok(())) => {
+    let errors = match decode_thread.join() {
+        Ok(Ok((stats, errors))) => {
+            let metrics = metrics
+                .as_deref()
+                .and_then(|metrics| metrics.lock().ok().map(|metrics| *metrics));
             finalize_progress_bar(
                 &pb,
-                total_frames,
                 handler.decoded_samples,
                 handler.final_sample_rate,
                 start_time,
+                metrics.as_ref(),
             );
-            log::info!("Decoding completed successfully");
+            if let Some(metrics) = &metrics {
+                log_metrics(metrics, handler.final_sample_rate);
+            }
+            if !args.metadata_only {
+                log_integrity_summary(&stats);
+                log_branch_repairs(&stats);
+                log_duplicates(&stats, args.duplicates);
+                log_skipped_oamd_payloads(&stats);
+            }
+            for output in &others {
+                if let Some(loudness) = &output.handler.loudness {
+                    log::info!("Presentation {}:", output.presentation);
+                    log_loudness(&loudness.result());
+                }
+            }
+            if let Some(loudness) = &handler.loudness {
+                if multiple {
+                    log::info!("Presentation {presentation}:");
+                }
+                log_loudness(&loudness.result());
+            }
+
+            // The sync information is written next to the files, or to the
+            // side path for stdout
+            let sync_base = if to_stdout {
+                &args.metadata_path
+            } else {
+                &base_path
+            };
+            if let Some(sync_base) = sync_base {
+                write_sync_info(&handler, sync_base, timestamp.get(), &errors, &stats)?;
+            }
+            for output in &others {
+                if let Some(sync_base) = &output.base_path {
+                    write_sync_info(&output.handler, sync_base, timestamp.get(), &errors, &stats)?;
+                }
+            }
+            errors
         }
         Ok(Err(e)) => {
             if let Some(pb) = pb {
@@ -172,17 +598,158 @@ pub fn cmd_decode(args: &DecodeArgs, cli: &Cli, multi: Option<&MultiProgress>) -
             }
             return Err(anyhow::anyhow!("Decode thread panicked"));
         }
+    };
+
+    if errors.concealed > 0 {
+        log::warn!(
+            "Concealed {} failed access units with silence",
+            errors.concealed
+        );
+    }
+    if interrupted {
+        log::warn!(
+            "Decoding interrupted, the output ends after {} samples",
+            handler.written_samples()
+        );
+        return Ok(ExitCode::from(INTERRUPTED_EXIT_CODE));
+    }
+    if errors.errors > 0 {
+        log::error!("Decoding completed with errors: {}", error_summary(&errors));
+        return Ok(ExitCode::from(PARTIAL_FAILURE_EXIT_CODE));
+    }
+    if errors.resyncs > 0 {
+        log::warn!("{}", error_summary(&errors));
+    }
+    log::info!("Decoding completed successfully");
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Handler and output paths of one decoded presentation
+struct PresentationOutput {
+    presentation: u8,
+    handler: DecodeHandler,
+    base_path: Option<PathBuf>,
+    metadata_path: Option<PathBuf>,
+    format: AudioFormat,
+}
+
+/// Write the sync information of the output of `handler` next to `base_path`
+fn write_sync_info(
+    handler: &DecodeHandler,
+    base_path: &Path,
+    timestamp: Option<&Timestamp>,
+    errors: &ErrorBudget,
+    stats: &DecodeStats,
+) -> Result<()> {
+    let mut sync = SyncInfo::new(timestamp, handler.final_sample_rate, handler.trim_samples);
+    sync.concealed_access_units = errors.concealed;
+    sync.inserted_silence_samples = errors.concealed_samples + stats.inserted_samples;
+    // Trimmed samples and the start time remain at the stream rate
+    sync.sample_rate = handler.output_sample_rate(handler.final_sample_rate);
+    sync.set_samples(handler.written_samples());
+    sync.write(&create_path_with_suffix(base_path, "sync.json"))
+}
+
+fn log_integrity_summary(stats: &DecodeStats) {
+    if stats.failures() == 0 {
+        log::info!(
+            "Integrity check passed: {} access units without failures",
+            stats.access_units
+        );
+        return;
+    }
+
+    log::warn!(
+        "Integrity check failed: {} failures in {} access units (first at AU {}, last at AU {})",
+        stats.failures(),
+        stats.access_units,
+        stats.first_failure_au().unwrap_or_default(),
+        stats.last_failure_au().unwrap_or_default()
+    );
+    for (index, substream) in stats.substreams.iter().enumerate() {
+        if substream.failures() > 0 {
+            log::warn!(
+                "  Substream {index}: {} lossless check mismatches, {} recorrelator saturations, {} Huffman saturations",
+                substream.lossless_check_mismatches,
+                substream.recorrelator_saturations,
+                substream.huffman_saturations
+            );
+        }
     }
+}
 
-    Ok(())
+fn log_loudness(loudness: &Loudness) {
+    match (loudness.integrated, loudness.range) {
+        (Some(integrated), Some(range)) => {
+            log::info!("Integrated loudness {integrated:.1} LUFS, loudness range {range:.1} LU")
+        }
+        (Some(integrated), None) => log::info!("Integrated loudness {integrated:.1} LUFS"),
+        _ => log::info!("Integrated loudness not measured, the output is silent or too short"),
+    }
+    for peak in &loudness.true_peaks {
+        match peak.true_peak {
+            Some(true_peak) => log::info!("  {}: true peak {true_peak:.1} dBTP", peak.channel),
+            None => log::info!("  {}: silent", peak.channel),
+        }
+    }
+}
+
+fn log_branch_repairs(stats: &DecodeStats) {
+    if stats.repaired_branches == 0 {
+        return;
+    }
+
+    log::warn!(
+        "Repaired {} invalid branches: inserted {} silent samples, dropped {} overlapping samples",
+        stats.repaired_branches,
+        stats.inserted_samples,
+        stats.dropped_samples
+    );
+}
+
+fn log_duplicates(stats: &DecodeStats, duplicates: Duplicates) {
+    match (stats.duplicate_access_units, duplicates) {
+        (0, _) => {}
+        (count, Duplicates::Keep) => log::info!("Kept {count} duplicate access units"),
+        (count, _) => log::info!("Skipped {count} duplicate access units"),
+    }
+}
+
+fn log_skipped_oamd_payloads(stats: &DecodeStats) {
+    if stats.skipped_oamd_payloads > 0 {
+        log::warn!(
+            "Skipped {} OAMD payloads that failed to parse",
+            stats.skipped_oamd_payloads
+        );
+    }
+}
+
+fn log_metrics(metrics: &Metrics, sample_rate: u32) {
+    log::info!(
+        "Parsed {} access units ({} bytes) in {:.3}s, decoded {} samples in {:.3}s",
+        metrics.access_units,
+        metrics.bytes,
+        metrics.parse_time.as_secs_f64(),
+        metrics.samples,
+        metrics.decode_time.as_secs_f64()
+    );
+    if let (Some(bytes_per_second), Some(factor)) = (
+        metrics.parse_bytes_per_second(),
+        metrics.decode_realtime_factor(sample_rate),
+    ) {
+        log::info!(
+            "Parse throughput {:.1} MB/s, decode speed {factor:.1}x realtime",
+            bytes_per_second / 1_000_000.0
+        );
+    }
 }
 
 fn finalize_progress_bar(
     pb: &Option<indicatif::ProgressBar>,
-    total_frames: Option<u64>,
     decoded_samples: u64,
     final_sample_rate: u32,
     start_time: std::time::Instant,
+    metrics: Option<&Metrics>,
 ) {
     if let Some(pb) = pb {
         let elapsed = start_time.elapsed();
@@ -190,7 +757,7 @@ fn finalize_progress_bar(
         let realtime_multiplier = audio_duration_secs / elapsed.as_secs_f64();
         let final_time_str = crate::timestamp::time_str(audio_duration_secs);
 
-        if total_frames.is_some() {
+        if pb.length().is_some() {
             pb.set_style(
                 ProgressStyle::with_template(
                     "{bar:40.cyan/blue} {pos}/{len} frames ({percent}%)\n{msg} | elapsed: {elapsed_precise}",
@@ -206,8 +773,11 @@ fn finalize_progress_bar(
             );
         }
 
+        let metrics = metrics
+            .map(|metrics| metrics_str(metrics, final_sample_rate))
+            .unwrap_or_default();
         pb.finish_with_message(format!(
-            "speed: {realtime_multiplier:.1}x | timestamp: {final_time_str}"
+            "speed: {realtime_multiplier:.1}x{metrics} | timestamp: {final_time_str}"
         ));
     }
 }
@@ -1,67 +1,226 @@
-use super::processor::{ProcessFramesContext, process_frames};
+use super::progress::{frames_for_duration, switch_to_bar};
 use crate::input::InputReader;
 use anyhow::Result;
 use indicatif::ProgressBar;
-use std::sync::mpsc;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex, OnceLock, mpsc};
 use std::thread;
-use truehd::process::{decode::Decoder, extract::Extractor, parse::Parser};
+use truehd::process::Metrics;
+use truehd::process::decode::{DecodeStats, DecodedAccessUnit};
+use truehd::process::pipeline::{ErrorBudget, Pipeline, PipelineEvent, Sink};
+use truehd::structs::timestamp::Timestamp;
+use truehd::utils::errors::PipelineError;
+use truehd::utils::events::Event;
 
 pub struct DecoderThreadConfig {
-    pub input_path: std::path::PathBuf,
-    pub presentation: u8,
-    pub strict_mode: bool,
-    pub tx: mpsc::Sender<Result<truehd::process::decode::DecodedAccessUnit>>,
+    /// Inputs decoded one after another, each starting a new stream
+    pub input_paths: Vec<std::path::PathBuf>,
+    /// Track number of the TrueHD track in a Matroska input
+    pub track: Option<u64>,
+    pub pipeline: Pipeline,
+    pub tx: mpsc::SyncSender<Result<DecodedAccessUnit>>,
     pub pb_clone: Option<ProgressBar>,
-    pub extractor: Extractor,
-    pub parser: Parser,
-    pub decoder: Decoder,
+    /// Stream duration in seconds that sizes the progress bar at the first major sync
+    pub expected_duration: Option<f64>,
+    /// Set from the first frame carrying an SMPTE timestamp
+    pub timestamp: Arc<OnceLock<Timestamp>>,
+    /// Set from the first major sync to whether it signals Atmos
+    pub atmos: Arc<OnceLock<bool>>,
+    /// Updated periodically when the parser and decoder collect metrics
+    pub metrics: Option<Arc<Mutex<Metrics>>>,
+    /// Logs the events of the extractor, parser and decoder
+    pub event_logger: Option<fn(Event)>,
 }
 
-pub fn spawn_decoder_thread(config: DecoderThreadConfig) -> thread::JoinHandle<Result<()>> {
-    thread::spawn(move || -> Result<()> {
+/// Forwards the decoded access units to the writer and the events to the
+/// progress bar
+struct ChannelSink {
+    tx: mpsc::SyncSender<Result<DecodedAccessUnit>>,
+    pb: Option<ProgressBar>,
+    expected_duration: Option<f64>,
+    timestamp: Arc<OnceLock<Timestamp>>,
+    atmos: Arc<OnceLock<bool>>,
+    metrics: Option<Arc<Mutex<Metrics>>>,
+    event_logger: Option<fn(Event)>,
+}
+
+impl Sink for ChannelSink {
+    fn access_unit(&mut self, decoded: DecodedAccessUnit) -> ControlFlow<()> {
+        // The writer stopped receiving, e.g. at the end of an excerpt
+        match self.tx.send(Ok(decoded)) {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(_) => ControlFlow::Break(()),
+        }
+    }
+
+    fn event(&mut self, event: PipelineEvent) {
+        match event {
+            PipelineEvent::Frame { frame_count, .. } => {
+                if let Some(pb) = &self.pb {
+                    // Length hints may fall short of the stream
+                    if pb.length().is_some_and(|length| frame_count > length) {
+                        pb.set_length(frame_count);
+                    }
+                    pb.set_position(frame_count);
+                }
+            }
+            PipelineEvent::Timestamp(timestamp) => {
+                let _ = self.timestamp.set(timestamp);
+            }
+            PipelineEvent::MajorSync {
+                sampling_frequency,
+                samples_per_au,
+                atmos,
+                ..
+            } => {
+                let _ = self.atmos.set(atmos);
+                if let (Some(pb), Some(duration)) = (&self.pb, self.expected_duration.take()) {
+                    switch_to_bar(
+                        pb,
+                        frames_for_duration(duration, sampling_frequency, samples_per_au),
+                    );
+                }
+            }
+            PipelineEvent::Resync { .. } => {
+                if let Some(pb) = &self.pb {
+                    pb.set_message("processing (some extraction errors)");
+                }
+            }
+            PipelineEvent::Metrics(metrics) => {
+                if let Some(shared) = &self.metrics
+                    && let Ok(mut shared) = shared.lock()
+                {
+                    shared.merge(&metrics);
+                }
+            }
+            PipelineEvent::Stream(event) => {
+                if let Some(log_event) = self.event_logger {
+                    log_event(event);
+                }
+            }
+        }
+    }
+}
+
+/// Summary of the errors pointing at the first failed access unit
+pub fn error_summary(errors: &ErrorBudget) -> String {
+    let mut summary = format!(
+        "{} parse or decode errors, {} extractor resyncs",
+        errors.errors, errors.resyncs
+    );
+    if let Some(first) = errors.first_error() {
+        summary.push_str(&format!(
+            "; first error at frame {} (byte offset {} / {:#X}, time {})",
+            first.frame_count,
+            first.byte_offset,
+            first.byte_offset,
+            crate::timestamp::time_str(first.time)
+        ));
+    }
+    summary
+}
+
+/// Stack size of the decoder thread.
+///
+/// The decoder state is moved onto the thread by value, which exceeds the
+/// default 2 MiB stack in debug builds.
+const DECODER_THREAD_STACK_SIZE: usize = 8 << 20;
+
+pub fn spawn_decoder_thread(
+    config: DecoderThreadConfig,
+) -> thread::JoinHandle<Result<(DecodeStats, ErrorBudget)>> {
+    let builder = thread::Builder::new()
+        .name("decoder".into())
+        .stack_size(DECODER_THREAD_STACK_SIZE);
+    let spawned = builder.spawn(move || -> Result<(DecodeStats, ErrorBudget)> {
         let DecoderThreadConfig {
-            input_path,
-            presentation,
-            strict_mode,
+            input_paths,
+            track,
+            mut pipeline,
             tx,
             pb_clone,
-            mut extractor,
-            mut parser,
-            mut decoder,
+            expected_duration,
+            timestamp,
+            atmos,
+            metrics,
+            event_logger,
         } = config;
 
-        let mut frame_count: u64 = 0;
-        let mut total_samples = 0u64;
-        let mut frames_processed = 0;
-        let mut current_substream_info: Option<u8> = None;
-        let mut current_extended_substream_info: Option<u8> = None;
-
-        let mut input_reader = InputReader::new(&input_path)?;
-
-        input_reader.process_chunks(64 * 1024, |chunk| {
-            extractor.push_bytes(chunk);
-
-            let mut ctx = ProcessFramesContext {
-                extractor: &mut extractor,
-                parser: &mut parser,
-                decoder: &mut decoder,
-                frames_processed: &mut frames_processed,
-                frame_count: &mut frame_count,
-                total_samples: &mut total_samples,
-                presentation,
-                strict_mode,
-                tx: &tx,
-                pb_clone: &pb_clone,
-                current_substream_info: &mut current_substream_info,
-                current_extended_substream_info: &mut current_extended_substream_info,
+        let mut sink = ChannelSink {
+            tx,
+            pb: pb_clone,
+            expected_duration,
+            timestamp,
+            atmos,
+            metrics,
+            event_logger,
+        };
+
+        let mut start_time = None;
+        let result = (|| -> Result<()> {
+            for (index, input_path) in input_paths.iter().enumerate() {
+                if index > 0 {
+                    log::debug!("Stream boundary before {}", input_path.display());
+                }
+
+                let mut input_reader = InputReader::with_track(input_path, track)?;
+                let flow = pipeline.decode(&mut input_reader, &mut sink)?;
+                if index == 0 {
+                    start_time = input_reader.start_time();
+                }
+                if flow.is_break() {
+                    break;
+                }
+            }
+            let _ = pipeline.finish(&mut sink)?;
+            Ok(())
+        })();
+
+        // Errors stopping the decode reach the writer in stream order
+        if let Err(e) = result {
+            let e = match e.downcast_ref() {
+                Some(PipelineError::MaxErrorsExceeded { .. }) => anyhow::anyhow!(
+                    "Stopped after exceeding --max-errors: {}",
+                    error_summary(pipeline.errors())
+                ),
+                _ => e,
             };
+            let _ = sink.tx.send(Err(e));
+        }
 
-            let should_exit = process_frames(&mut ctx)?;
+        if let Some(start_time) = start_time {
+            log::info!("TrueHD track starts at {start_time:.3}s in the Matroska input");
+        }
+
+        if let Some(mat) = pipeline.mat_encapsulation() {
+            log::info!(
+                "Unwrapped {} MAT bursts, discarding {} padding bytes",
+                mat.bursts,
+                mat.padding_bytes
+            );
+        }
+
+        log::info!(
+            "Processing complete: {} frames, {} samples",
+            pipeline.frames(),
+            pipeline.samples()
+        );
+
+        Ok((pipeline.stats(), pipeline.errors().clone()))
+    });
+    spawned.expect("failed to spawn the decoder thread")
+}
 
-            Ok(!should_exit) // Convert exit signal to continue signal
-        })?;
+#[test]
+fn error_budget_summary() {
+    let mut errors = ErrorBudget::new(Some(1));
+    errors.record_error(3, 120, 0.0025);
+    errors.resyncs += 1;
+    errors.record_error(7, 300, 0.0058);
 
-        log::info!("Processing complete: {frame_count} frames, {total_samples} samples");
-        Ok(())
-    })
+    // The summary points at the first failed access unit
+    assert_eq!(
+        error_summary(&errors),
+        "2 parse or decode errors, 1 extractor resyncs; first error at frame 3 (byte offset 120 / 0x78, time 00:00:00.002)"
+    );
 }
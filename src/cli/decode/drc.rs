@@ -0,0 +1,116 @@
+use truehd::process::decode::DecodedAccessUnit;
+
+/// Range of decoded samples of `bit_depth` bits.
+pub(super) fn sample_range(bit_depth: u32) -> (f64, f64) {
+    let full_scale = (1u64 << (bit_depth - 1)) as f64;
+    (-full_scale, full_scale - 1.0)
+}
+
+/// Scale the valid samples of `decoded` by its heavy DRC gain.
+///
+/// Scaled samples are rounded and clamped to the range of the decoded bit
+/// depth. Returns `false` and leaves the samples unchanged when the access
+/// unit carries no heavy DRC gain.
+pub fn apply_heavy_drc(decoded: &mut DecodedAccessUnit) -> bool {
+    let Some(gain) = &decoded.heavy_drc_gain else {
+        return false;
+    };
+
+    let (min, max) = sample_range(decoded.bit_depth);
+    let channel_count = decoded.channel_count;
+    for (frame, &gain) in decoded.pcm_data.iter_mut().zip(gain) {
+        for sample in &mut frame[..channel_count] {
+            *sample = (*sample as f32 * gain)
+                .round()
+                .clamp(min as f32, max as f32) as i32;
+        }
+    }
+    true
+}
+
+/// Dialogue level that players normalize dialogue to, in dBFS.
+pub const DIALNORM_REFERENCE: i8 = -31;
+
+/// Linear gain bringing a dialogue level of `dialogue_level` dBFS down to
+/// [`DIALNORM_REFERENCE`].
+///
+/// A level of 0 dBFS means the dialogue level is not indicated and, like
+/// levels at or below the reference, leaves the output unchanged.
+pub fn dialnorm_gain(dialogue_level: i8) -> f64 {
+    if dialogue_level == 0 || dialogue_level <= DIALNORM_REFERENCE {
+        return 1.0;
+    }
+    10f64.powf(f64::from(DIALNORM_REFERENCE - dialogue_level) / 20.0)
+}
+
+/// Scale the valid samples of `decoded` by `gain`.
+///
+/// Scaled samples are rounded and clamped to the range of the decoded bit
+/// depth.
+pub fn apply_gain(decoded: &mut DecodedAccessUnit, gain: f64) {
+    let (min, max) = sample_range(decoded.bit_depth);
+    let channel_count = decoded.channel_count;
+    for frame in &mut decoded.pcm_data[..decoded.sample_length] {
+        for sample in &mut frame[..channel_count] {
+            *sample = (f64::from(*sample) * gain).round().clamp(min, max) as i32;
+        }
+    }
+}
+
+#[test]
+fn heavy_drc_scales_samples() {
+    use truehd::structs::channel::ChannelLabel;
+
+    let (min, max) = sample_range(24);
+    let mut pcm_data = [[0; 16]; 160];
+    pcm_data[0][..3].copy_from_slice(&[1000, -1000, 7]);
+    pcm_data[1][..3].copy_from_slice(&[1000, -1000, 7]);
+    pcm_data[2][..2].copy_from_slice(&[max as i32, min as i32]);
+
+    let mut decoded = DecodedAccessUnit {
+        sampling_frequency: 48000,
+        sample_length: 3,
+        channel_count: 2,
+        pcm_data: pcm_data.into(),
+        bit_depth: 24,
+        channel_labels: vec![ChannelLabel::L, ChannelLabel::R],
+        oamd: Vec::new(),
+        evo_payloads: Vec::new(),
+        is_duplicate: false,
+        substream_info_changed: false,
+        config_changed: false,
+        stream_start: false,
+        silence_samples: 0,
+        au_index: 0,
+        sample_position: 0,
+        discontinuity: None,
+        heavy_drc_gain: None,
+        dialogue_level: None,
+        other_presentations: Vec::new(),
+    };
+    assert!(!apply_heavy_drc(&mut decoded));
+    assert_eq!(decoded.pcm_data[0][..3], [1000, -1000, 7]);
+
+    decoded.heavy_drc_gain = Some(vec![0.5, 0.25, 2.0]);
+    assert!(apply_heavy_drc(&mut decoded));
+    assert_eq!(decoded.pcm_data[0][..3], [500, -500, 7]);
+    assert_eq!(decoded.pcm_data[1][..3], [250, -250, 7]);
+    // Gains above unity clamp to the 24-bit range
+    assert_eq!(decoded.pcm_data[2][..2], [max as i32, min as i32]);
+    assert_eq!(decoded.pcm_data[3][0], 0);
+
+    // 32-bit samples keep their range
+    decoded.bit_depth = 32;
+    decoded.pcm_data[0][0] = 1 << 28;
+    decoded.heavy_drc_gain = Some(vec![2.0]);
+    assert!(apply_heavy_drc(&mut decoded));
+    assert_eq!(decoded.pcm_data[0][0], 1 << 29);
+}
+
+#[test]
+fn dialnorm_gain_attenuates_to_reference() {
+    assert_eq!(dialnorm_gain(0), 1.0);
+    assert_eq!(dialnorm_gain(-31), 1.0);
+    assert!((dialnorm_gain(-27) - 0.630_957_344).abs() < 1e-9);
+    assert!((dialnorm_gain(-11) - 0.1).abs() < 1e-12);
+}
@@ -1,26 +1,51 @@
-use super::atmos::{create_damf_header_file, rewrite_damf_header_for_bed_conform};
-use super::output::{AudioWriter, create_output_paths};
+use super::atmos::{HeaderOptions, MetadataWriter, segment_base_path};
+use super::drc::{apply_gain, apply_heavy_drc, dialnorm_gain, sample_range};
+use super::loudness::LoudnessMeter;
+use super::oamd_dump::OamdDump;
+use super::object_gain::ObjectGains;
+use super::output::{
+    AudioWriter, create_output_paths, raw_pcm_format_name, requantize_samples, split_channel_names,
+    split_channel_paths,
+};
+use super::partial::{PartialOutputs, partial_path};
+use super::progress::metrics_str;
+use super::remap::ChannelRemapper;
+use super::resume::{ResumeFormat, ResumeState};
+use super::trajectory::TrajectoryWriter;
 // wrap_pcm_file_with_caf_header no longer needed since presentation 3 forces CAF
-use crate::cli::command::AudioFormat;
-use crate::damf::{BedInstance, Configuration, Event};
-use crate::timestamp::time_str;
-use anyhow::{Result, anyhow};
+use crate::adm::AdmDocument;
+use crate::caf::{ChannelDescription, ChannelLabel as CafChannelLabel};
+use crate::cli::command::{
+    ApplyDrc, AudioFormat, BedConformLayout, BitFormat, ChannelOrder, Downmix,
+};
+use crate::damf::{BedInstance, MetadataThinning};
+use crate::timestamp::{samples_at, time_str};
+use anyhow::{Context, Result, anyhow, bail};
 use indicatif::ProgressBar;
 use log::Level;
 use std::fs::File;
 use std::io::{BufWriter, Seek, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use truehd::log_or_err;
+use truehd::process::Metrics;
+use truehd::structs::channel::ChannelLabel;
+use truehd::structs::oamd::{ObjectAudioMetadataPayload, SpeakerLabels};
+use truehd::utils::dither::TpdfDither;
+use truehd::utils::errors::{Category, CategoryPolicy};
+use truehd::utils::events::EventSink;
+use truehd::utils::resample::Resampler;
 
 struct AudioFormatHandler;
 
 struct BedConformConversionParams<'a> {
-    writer: AudioWriter,
     current_path: &'a Path,
     new_path: &'a Path,
     channel_count: u32,
     conformed_channel_count: usize,
     sample_rate: f64,
+    /// Sample frames written to the current file
+    sample_count: u64,
     state: &'a WriterState,
 }
 
@@ -42,9 +67,14 @@ impl AudioFormatHandler {
                 Self::rename_and_recreate_caf_writer(current_path, new_path, state)
             }
             // These cases should never happen for presentation 3 due to effective_format forcing CAF
-            AudioWriter::Pcm(_) | AudioWriter::W64(_) => {
+            AudioWriter::Pcm(..)
+            | AudioWriter::W64(_)
+            | AudioWriter::Wav(_)
+            | AudioWriter::Stdout(..)
+            | AudioWriter::StdoutCaf(_)
+            | AudioWriter::Split(_) => {
                 unreachable!(
-                    "PCM/W64 writers should not exist for presentation 3 (Atmos) due to format forcing"
+                    "PCM/W64/WAV/split writers should not exist for presentation 3 (Atmos) due to format forcing"
                 )
             }
         }
@@ -58,6 +88,7 @@ impl AudioFormatHandler {
         if let Err(e) = std::fs::rename(current_path, new_path) {
             log_or_err!(
                 state,
+                Category::Output,
                 Level::Error,
                 anyhow!("Failed to rename audio file: {e}")
             );
@@ -78,18 +109,26 @@ impl AudioFormatHandler {
     }
 
     fn handle_bed_conform_conversion(
+        writer: AudioWriter,
         params: BedConformConversionParams,
-        convert_file_fn: impl Fn(&Path, &Path, usize, usize, f64, &WriterState) -> Result<()>,
+        convert_file_fn: impl Fn(&Path, &BedConformConversionParams) -> Result<()>,
     ) -> Result<AudioWriter> {
         // Close current CAF writer (only CAF expected for presentation 3)
-        match params.writer {
+        match writer {
             AudioWriter::Caf(mut w) => {
                 w.finish()?;
                 drop(w);
             }
             // These should not happen due to effective_format forcing CAF
-            AudioWriter::Pcm(_) | AudioWriter::W64(_) => {
-                unreachable!("PCM/W64 writers should not exist for presentation 3 bed conformance")
+            AudioWriter::Pcm(..)
+            | AudioWriter::W64(_)
+            | AudioWriter::Wav(_)
+            | AudioWriter::Stdout(..)
+            | AudioWriter::StdoutCaf(_)
+            | AudioWriter::Split(_) => {
+                unreachable!(
+                    "PCM/W64/WAV/split writers should not exist for presentation 3 bed conformance"
+                )
             }
         }
 
@@ -106,14 +145,7 @@ impl AudioFormatHandler {
         }
 
         // Convert the audio data with bed conformance
-        convert_file_fn(
-            &temp_path,
-            params.new_path,
-            params.channel_count as usize,
-            params.conformed_channel_count,
-            params.sample_rate,
-            params.state,
-        )?;
+        convert_file_fn(&temp_path, &params)?;
 
         // Clean up temp file
         if let Err(e) = std::fs::remove_file(&temp_path) {
@@ -139,42 +171,79 @@ impl AudioFormatHandler {
 struct AudioDataConverter;
 
 impl AudioDataConverter {
-    fn convert_caf_bytes_to_samples(buffer: &[u8], endianness: crate::caf::Endianness) -> Vec<i32> {
-        const BYTES_PER_SAMPLE: usize = 3;
-        let total_samples = buffer.len() / BYTES_PER_SAMPLE;
+    /// Convert CAF sample data back to samples of the file's bit format
+    fn convert_caf_bytes_to_samples(
+        buffer: &[u8],
+        endianness: crate::caf::Endianness,
+        bit_format: BitFormat,
+    ) -> Vec<i32> {
+        if bit_format == BitFormat::Float32 {
+            return Self::convert_caf_float_bytes_to_samples(buffer, endianness);
+        }
+
+        let bytes_per_sample = bit_format.bits_per_sample() as usize / 8;
+        let total_samples = buffer.len() / bytes_per_sample;
         let mut samples = Vec::with_capacity(total_samples);
 
-        for chunk in buffer.chunks_exact(BYTES_PER_SAMPLE) {
+        for chunk in buffer.chunks_exact(bytes_per_sample) {
+            // Place the sample in the most significant bytes and sign extend
+            let mut bytes = [0u8; 4];
             let sample = match endianness {
                 crate::caf::Endianness::BigEndian => {
-                    i32::from_be_bytes([0, chunk[0], chunk[1], chunk[2]]) >> 8
+                    bytes[..bytes_per_sample].copy_from_slice(chunk);
+                    i32::from_be_bytes(bytes)
                 }
                 crate::caf::Endianness::LittleEndian => {
-                    i32::from_le_bytes([chunk[0], chunk[1], chunk[2], 0]) >> 8
+                    bytes[4 - bytes_per_sample..].copy_from_slice(chunk);
+                    i32::from_le_bytes(bytes)
                 }
             };
-            samples.push(sample);
+            samples.push(sample >> (32 - 8 * bytes_per_sample));
         }
         samples
     }
+
+    /// Convert normalized 32-bit float samples back to 24-bit integers
+    fn convert_caf_float_bytes_to_samples(
+        buffer: &[u8],
+        endianness: crate::caf::Endianness,
+    ) -> Vec<i32> {
+        const SCALE: f32 = (1 << 23) as f32;
+
+        buffer
+            .chunks_exact(4)
+            .map(|chunk| {
+                let bytes = [chunk[0], chunk[1], chunk[2], chunk[3]];
+                let value = match endianness {
+                    crate::caf::Endianness::BigEndian => f32::from_be_bytes(bytes),
+                    crate::caf::Endianness::LittleEndian => f32::from_le_bytes(bytes),
+                };
+                (value * SCALE).round() as i32
+            })
+            .collect()
+    }
 }
 
+/// Conforms the first bed instance to the bed layout selected with
+/// `--bed-conform-layout`.
+///
+/// Channels of further bed instances follow the first in the decoded audio and
+/// are passed through with the objects after the conformed bed.
 struct BedChannelMapper;
 
 struct ChannelCountCalculator;
 
 impl ChannelCountCalculator {
-    const TARGET_BED_CHANNELS: usize = 10; // 7.1.2 layout
-
     /// Calculate the effective channel count for bed conformance
     /// Returns (num_bed_channels, num_object_channels, conformed_channel_count)
     fn calculate_bed_conform_counts(
         original_channel_count: usize,
         bed_indices: &[usize],
+        layout: &[SpeakerLabels],
     ) -> (usize, usize, usize) {
         let num_bed_channels = bed_indices.len();
         let num_object_channels = original_channel_count.saturating_sub(num_bed_channels);
-        let conformed_channel_count = Self::TARGET_BED_CHANNELS + num_object_channels;
+        let conformed_channel_count = layout.len() + num_object_channels;
         (
             num_bed_channels,
             num_object_channels,
@@ -186,48 +255,266 @@ impl ChannelCountCalculator {
     fn calculate_conformed_channel_count(
         original_channel_count: usize,
         bed_indices: &[usize],
+        layout: &[SpeakerLabels],
     ) -> usize {
         let (_, _, conformed_count) =
-            Self::calculate_bed_conform_counts(original_channel_count, bed_indices);
+            Self::calculate_bed_conform_counts(original_channel_count, bed_indices, layout);
         conformed_count
     }
 }
 
+/// Gain of a bed channel folded into another speaker of the layout (-3 dB)
+const BED_FOLD_GAIN: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+/// Source channels of each speaker of the conformed bed.
+///
+/// A source speaker present in the layout is copied to it unchanged. A source
+/// speaker missing from the layout is folded at -3 dB into the first of its
+/// [fallbacks](Self::fallbacks) that is present, and layout speakers without
+/// any source are silent.
+struct BedConformMap {
+    /// Decoded bed channel and fold flag of the sources of each layout speaker
+    sources: Vec<Vec<(usize, bool)>>,
+}
+
+impl BedConformMap {
+    fn new(bed_indices: &[usize], layout: &[SpeakerLabels]) -> Self {
+        let mut sources = vec![Vec::new(); layout.len()];
+        for (source, speaker) in bed_indices
+            .iter()
+            .enumerate()
+            .filter_map(|(source, &i)| Some((source, SpeakerLabels::from_u8(i as u8)?)))
+        {
+            if let Some((target, folded)) = Self::target(speaker, layout) {
+                sources[target].push((source, folded));
+            }
+        }
+
+        Self { sources }
+    }
+
+    /// Warns about each bed speaker folded into another speaker of the layout
+    fn warn_folded(bed_indices: &[usize], layout: &[SpeakerLabels]) {
+        for speaker in bed_indices
+            .iter()
+            .filter_map(|&i| SpeakerLabels::from_u8(i as u8))
+        {
+            if let Some((target, true)) = Self::target(speaker, layout) {
+                log::warn!(
+                    "Bed channel {speaker:?} is not in the conformed layout, folding it into {:?} at -3 dB",
+                    layout[target]
+                );
+            }
+        }
+    }
+
+    /// Index of the layout speaker a bed speaker is copied to, and whether it
+    /// is folded into it because the layout lacks the speaker
+    fn target(speaker: SpeakerLabels, layout: &[SpeakerLabels]) -> Option<(usize, bool)> {
+        let position =
+            |speaker: SpeakerLabels| layout.iter().position(|&s| s as u8 == speaker as u8);
+
+        match position(speaker) {
+            Some(target) => Some((target, false)),
+            None => Self::fallbacks(speaker)
+                .iter()
+                .find_map(|&fallback| position(fallback))
+                .map(|target| (target, true)),
+        }
+    }
+
+    /// Speakers a bed speaker is folded into when the layout lacks it, in
+    /// order of preference. Every list ends in the 5.1 bed shared by all
+    /// layouts.
+    fn fallbacks(speaker: SpeakerLabels) -> &'static [SpeakerLabels] {
+        use SpeakerLabels::*;
+        match speaker {
+            L | R | C | LFE | Lss | Rss => &[],
+            Lrs => &[Lss],
+            Rrs => &[Rss],
+            Lfh => &[Lts, L],
+            Rfh => &[Rts, R],
+            Lts => &[Lfh, Lss],
+            Rts => &[Rfh, Rss],
+            Lrh => &[Lts, Lss],
+            Rrh => &[Rts, Rss],
+            Lw => &[L],
+            Rw => &[R],
+            LFE2 => &[LFE],
+        }
+    }
+
+    /// Appends the conformed bed of one sample of decoded bed channels of
+    /// `bit_depth` bits.
+    fn conform(&self, bed: &[i32], bit_depth: u32, output: &mut Vec<i32>) {
+        let (min, max) = sample_range(bit_depth);
+        output.extend(self.sources.iter().map(|sources| {
+            match sources.as_slice() {
+                [] => 0,
+                &[(source, false)] => bed[source],
+                sources => sources
+                    .iter()
+                    .map(|&(source, folded)| {
+                        let gain = if folded { BED_FOLD_GAIN } else { 1.0 };
+                        bed[source] as f64 * gain
+                    })
+                    .sum::<f64>()
+                    .round()
+                    .clamp(min, max) as i32,
+            }
+        }));
+    }
+}
+
 impl BedChannelMapper {
+    /// Channel labels of the conformed bed, in output order
+    fn conformed_bed_labels(layout: &[SpeakerLabels]) -> Vec<ChannelLabel> {
+        layout
+            .iter()
+            .map(|&speaker| Self::speaker_channel_label(speaker))
+            .collect()
+    }
+
+    /// Describes the channels of the Atmos audio of an OAMD payload.
+    ///
+    /// Bed channels are labelled by their speaker, or as the conformed bed
+    /// layout for the first instance, and dynamic objects by their position in
+    /// the payload. ISF objects have no fixed position and are left `Unknown`.
+    fn atmos_channel_descriptions(
+        oamd: &ObjectAudioMetadataPayload,
+        bed_conform: Option<&[SpeakerLabels]>,
+    ) -> Vec<ChannelDescription> {
+        let bed_instances = BedInstance::with_oamd_payload(oamd);
+        let num_bed_channels = bed_instances
+            .iter()
+            .map(|bed| bed.to_index_vec().len())
+            .sum::<usize>();
+
+        let mut descriptions = Vec::with_capacity(oamd.object_count);
+        for (instance, bed) in bed_instances.iter().enumerate() {
+            if let Some(layout) = bed_conform.filter(|_| instance == 0) {
+                descriptions.extend(
+                    Self::conformed_bed_labels(layout)
+                        .into_iter()
+                        .map(ChannelDescription::from_truehd_label),
+                );
+                continue;
+            }
+            descriptions.extend(
+                bed.to_index_vec()
+                    .into_iter()
+                    .filter_map(|i| SpeakerLabels::from_u8(i as u8))
+                    .map(|speaker| {
+                        ChannelDescription::from_truehd_label(Self::speaker_channel_label(speaker))
+                    }),
+            );
+        }
+
+        let Some(object_element) = &oamd.object_element else {
+            return descriptions;
+        };
+        let positions = oamd.get_damf_pos();
+        for (object_data, position) in object_element
+            .object_data
+            .iter()
+            .zip(&positions)
+            .take(oamd.object_count)
+            .skip(num_bed_channels)
+        {
+            descriptions.push(match (object_data.first(), position.first()) {
+                (Some(object_data), Some(pos)) if !object_data.b_object_in_bed_or_isf => {
+                    ChannelDescription::with_coordinates(pos.map(|coordinate| coordinate as f32))
+                }
+                _ => ChannelDescription::with_label(CafChannelLabel::Unknown),
+            });
+        }
+
+        descriptions
+    }
+
+    /// Names of the Atmos channels in the files of a split output.
+    ///
+    /// Bed channels are named by their label as in
+    /// [`atmos_channel_descriptions`](Self::atmos_channel_descriptions), and
+    /// the objects following them by their ID in the Atmos metadata.
+    fn atmos_split_channel_names(
+        oamd: &ObjectAudioMetadataPayload,
+        bed_conform: Option<&[SpeakerLabels]>,
+    ) -> Vec<String> {
+        let mut names = Vec::with_capacity(oamd.object_count);
+        let mut num_bed_channels = 0;
+        for (instance, bed) in BedInstance::with_oamd_payload(oamd).iter().enumerate() {
+            let labels = if let Some(layout) = bed_conform.filter(|_| instance == 0) {
+                Self::conformed_bed_labels(layout)
+            } else {
+                bed.to_index_vec()
+                    .into_iter()
+                    .filter_map(|i| SpeakerLabels::from_u8(i as u8))
+                    .map(Self::speaker_channel_label)
+                    .collect()
+            };
+            names.extend(labels.iter().map(|label| format!("{label:?}")));
+            num_bed_channels += bed.to_index_vec().len();
+        }
+
+        names.extend(
+            (0..oamd.object_count.saturating_sub(num_bed_channels))
+                .map(|object| format!("obj{}", object + 10)),
+        );
+        names
+    }
+
+    fn speaker_channel_label(speaker: SpeakerLabels) -> ChannelLabel {
+        match speaker {
+            SpeakerLabels::L => ChannelLabel::L,
+            SpeakerLabels::R => ChannelLabel::R,
+            SpeakerLabels::C => ChannelLabel::C,
+            SpeakerLabels::LFE => ChannelLabel::LFE,
+            SpeakerLabels::Lss => ChannelLabel::Ls,
+            SpeakerLabels::Rss => ChannelLabel::Rs,
+            SpeakerLabels::Lrs => ChannelLabel::Lb,
+            SpeakerLabels::Rrs => ChannelLabel::Rb,
+            SpeakerLabels::Lfh => ChannelLabel::Tfl,
+            SpeakerLabels::Rfh => ChannelLabel::Tfr,
+            SpeakerLabels::Lts => ChannelLabel::Tsl,
+            SpeakerLabels::Rts => ChannelLabel::Tsr,
+            SpeakerLabels::Lrh => ChannelLabel::Tbl,
+            SpeakerLabels::Rrh => ChannelLabel::Tbr,
+            SpeakerLabels::Lw => ChannelLabel::Lw,
+            SpeakerLabels::Rw => ChannelLabel::Rw,
+            SpeakerLabels::LFE2 => ChannelLabel::LFE2,
+        }
+    }
+
     fn apply_bed_conformance(
         original_samples: Vec<i32>,
         original_channel_count: usize,
         bed_indices: &[usize],
+        layout: &[SpeakerLabels],
+        bit_depth: u32,
     ) -> Vec<i32> {
         let (num_bed_channels, num_object_channels, conformed_channel_count) =
             ChannelCountCalculator::calculate_bed_conform_counts(
                 original_channel_count,
                 bed_indices,
+                layout,
             );
         let samples_per_frame = original_samples.len() / original_channel_count;
+        let map = BedConformMap::new(bed_indices, layout);
 
         let mut conformed_samples = Vec::with_capacity(samples_per_frame * conformed_channel_count);
 
-        for sample_idx in 0..samples_per_frame {
-            // Handle bed channels (0-9)
-            for target_bed_ch in 0..ChannelCountCalculator::TARGET_BED_CHANNELS {
-                if let Some(source_ch_pos) =
-                    bed_indices.iter().position(|&idx| idx == target_bed_ch)
-                {
-                    let sample =
-                        original_samples[sample_idx * original_channel_count + source_ch_pos];
-                    conformed_samples.push(sample);
-                } else {
-                    conformed_samples.push(0i32);
-                }
-            }
+        for sample in original_samples.chunks_exact(original_channel_count) {
+            map.conform(
+                &sample[..num_bed_channels],
+                bit_depth,
+                &mut conformed_samples,
+            );
 
             // Handle object channels
-            for obj_ch in 0..num_object_channels {
-                let source_ch = num_bed_channels + obj_ch;
-                let sample = original_samples[sample_idx * original_channel_count + source_ch];
-                conformed_samples.push(sample);
-            }
+            conformed_samples.extend_from_slice(
+                &sample[num_bed_channels..num_bed_channels + num_object_channels],
+            );
         }
 
         conformed_samples
@@ -237,30 +524,42 @@ impl BedChannelMapper {
         decoded: &truehd::process::decode::DecodedAccessUnit,
         channel_count: usize,
         bed_indices: &[usize],
+        layout: &[SpeakerLabels],
+        start: usize,
+        mut object_gains: Option<&mut ObjectGains>,
+        frame_start: u64,
     ) -> Vec<i32> {
         let (num_bed_channels, num_object_channels, conformed_channel_count) =
-            ChannelCountCalculator::calculate_bed_conform_counts(channel_count, bed_indices);
-
-        let mut samples = Vec::with_capacity(decoded.sample_length * conformed_channel_count);
-
-        for sample_idx in 0..decoded.sample_length {
-            // Handle bed channels (0-9)
-            for target_bed_ch in 0..ChannelCountCalculator::TARGET_BED_CHANNELS {
-                if let Some(source_ch_pos) =
-                    bed_indices.iter().position(|&idx| idx == target_bed_ch)
-                {
-                    let sample = decoded.pcm_data[sample_idx][source_ch_pos];
-                    samples.push(sample);
-                } else {
-                    samples.push(0i32);
-                }
+            ChannelCountCalculator::calculate_bed_conform_counts(
+                channel_count,
+                bed_indices,
+                layout,
+            );
+        let map = BedConformMap::new(bed_indices, layout);
+
+        let mut samples =
+            Vec::with_capacity((decoded.sample_length - start) * conformed_channel_count);
+
+        for sample_idx in start..decoded.sample_length {
+            let sample_pos = frame_start + sample_idx as u64;
+            if let Some(gains) = object_gains.as_deref_mut() {
+                gains.advance_to(sample_pos);
             }
 
-            // Handle object channels
+            map.conform(
+                &decoded.pcm_data[sample_idx][..num_bed_channels],
+                decoded.bit_depth,
+                &mut samples,
+            );
+
+            // Handle object channels, scaled by their OAMD gain if requested
             for obj_ch in 0..num_object_channels {
                 let source_ch = num_bed_channels + obj_ch;
                 let sample = decoded.pcm_data[sample_idx][source_ch];
-                samples.push(sample);
+                samples.push(match object_gains.as_deref() {
+                    Some(gains) => gains.apply(source_ch, sample, sample_pos, decoded.bit_depth),
+                    None => sample,
+                });
             }
         }
 
@@ -270,22 +569,60 @@ impl BedChannelMapper {
 
 pub struct WriterState {
     pub fail_level: Level,
+    pub category_policy: CategoryPolicy,
+    pub event_sink: Option<EventSink>,
 }
 
 pub struct DecodeHandler {
     pub audio_writer: Option<AudioWriter>,
     pub current_audio_path: Option<PathBuf>,
-    pub damf_metadata_file_writer: Option<BufWriter<File>>,
+    metadata: MetadataWriter, // DAMF header and metadata file of the current output
     pub has_atmos: bool,
-    pub prev_events: Vec<Event>,
+    pub has_atmos_metadata: bool,
+    partial_outputs: PartialOutputs, // Files written under their partial names until finalized
     pub decoded_frames: u64,
     pub decoded_samples: u64,
     pub final_sample_rate: u32,
     pub bed_indices: Option<Vec<usize>>,
     pub au_index: u64,
     pub segment_index: u32,
-    pub is_segmented: bool,         // Track if we're in segmented mode
-    pub segment_start_samples: u64, // Sample position when current segment started
+    pub is_segmented: bool,              // Track if we're in segmented mode
+    pub segment_start_samples: u64,      // Sample position when current segment started
+    pub resume_samples: u64,             // Samples already present in a resumed output file
+    pub trim_samples: u64,               // Samples dropped from the start of the output
+    pub excerpt_start: Option<f64>,      // Seconds after the start trim the output starts at
+    pub excerpt_duration: Option<f64>,   // Seconds the output stops after
+    end_samples: Option<u64>,            // Stream position the output ends at
+    resume_format: Option<ResumeFormat>, // Format of the resumed output file
+    pub bit_format: BitFormat,           // Sample format of the output
+    dither: TpdfDither,                  // Dither for requantizing to a lower bit depth
+    pub channel_order: ChannelOrder,     // Channel order of the output
+    pub downmix: Option<Downmix>,        // Standard layout to downmix the output to
+    pub apply_drc: ApplyDrc,             // Dynamic range compression applied to the output
+    heavy_drc_applied: bool,             // Heavy DRC gain was found in the stream
+    pub apply_dialnorm: bool,            // Attenuate the output by its dialogue normalization
+    dialogue_level: Option<i8>,          // Dialogue level the output is attenuated for
+    pub bed_conform_layout: BedConformLayout, // Bed layout of the bed-conformed output
+    pub apply_object_gains: bool,        // Scale bed-conformed object channels by their OAMD gain
+    object_gains: ObjectGains,           // Gain ramps of the Atmos objects
+    pub presentation: u8,                // Presentation index being decoded
+    remapper: Option<ChannelRemapper>,   // Reorders or downmixes the decoded channels
+    output_layout: Option<(usize, Vec<ChannelLabel>)>, // Decoded channels the output was created with
+    pub metadata_only: bool,                           // Write Atmos metadata files only
+    pub to_stdout: bool,                               // Write raw PCM to stdout instead of files
+    pub stdout_caf: bool, // Write CAF of unknown length to stdout instead of raw PCM
+    pub low_latency: bool, // Flush the output after every access unit
+    pub resample_to_48k: bool, // Resample 44.1 kHz family output to 48 kHz
+    resampler: Option<Resampler>, // Resamples the output of the current stream
+    bed_conform_pending: bool, // Audio was written before the bed layout was known
+    atmos_channel_descriptions: Option<Vec<ChannelDescription>>, // CAF descriptions of the Atmos channels
+    adm: Option<AdmDocument>, // ADM metadata of the current ADM BWF output
+    pub split_channels: bool, // Write a mono CAF file per channel
+    atmos_split_names: Option<Vec<String>>, // Split file names of the Atmos channels
+    pub oamd_dump: Option<OamdDump>, // Writes the raw OAMD payloads
+    pub trajectories: Option<TrajectoryWriter>, // Writes the dynamic object trajectories
+    pub loudness: Option<LoudnessMeter>, // Measures the loudness of the decoded output
+    pub junctions: Vec<u64>,  // Output sample positions where concatenated inputs join
 }
 
 impl Default for DecodeHandler {
@@ -293,9 +630,10 @@ impl Default for DecodeHandler {
         Self {
             audio_writer: None,
             current_audio_path: None,
-            damf_metadata_file_writer: None,
+            metadata: MetadataWriter::default(),
             has_atmos: false,
-            prev_events: Vec::new(),
+            has_atmos_metadata: false,
+            partial_outputs: PartialOutputs::default(),
             decoded_frames: 0,
             decoded_samples: 0,
             final_sample_rate: 48000,
@@ -304,70 +642,274 @@ impl Default for DecodeHandler {
             segment_index: 0,
             is_segmented: false,
             segment_start_samples: 0,
+            resume_samples: 0,
+            trim_samples: 0,
+            excerpt_start: None,
+            excerpt_duration: None,
+            end_samples: None,
+            resume_format: None,
+            bit_format: BitFormat::Int24,
+            dither: TpdfDither::default(),
+            channel_order: ChannelOrder::Native,
+            downmix: None,
+            apply_drc: ApplyDrc::None,
+            heavy_drc_applied: false,
+            apply_dialnorm: false,
+            dialogue_level: None,
+            bed_conform_layout: BedConformLayout::default(),
+            apply_object_gains: false,
+            object_gains: ObjectGains::default(),
+            presentation: 3,
+            remapper: None,
+            output_layout: None,
+            metadata_only: false,
+            to_stdout: false,
+            stdout_caf: false,
+            low_latency: false,
+            resample_to_48k: false,
+            resampler: None,
+            bed_conform_pending: false,
+            atmos_channel_descriptions: None,
+            adm: None,
+            split_channels: false,
+            atmos_split_names: None,
+            oamd_dump: None,
+            trajectories: None,
+            loudness: None,
+            junctions: Vec::new(),
         }
     }
 }
 
 pub struct FrameHandlerContext<'a> {
     pub base_path: &'a Option<PathBuf>,
+    /// Base path of the Atmos header and metadata files
+    pub metadata_path: &'a Option<PathBuf>,
     pub format: AudioFormat,
     pub pb: &'a Option<ProgressBar>,
     pub state: &'a WriterState,
     pub start_time: std::time::Instant,
     pub bed_conform: bool,
     pub warp_mode: Option<crate::cli::command::WarpMode>,
+    pub fps: Option<crate::cli::command::Fps>,
+    pub timestamp: Option<&'a truehd::structs::timestamp::Timestamp>,
+    /// The first major sync signals Atmos
+    pub atmos: bool,
+    pub metadata_update_rate: Option<u32>,
+    /// Parse and decode metrics shared with the decoder thread
+    pub metrics: Option<&'a Mutex<Metrics>>,
 }
 
 impl DecodeHandler {
-    pub fn handle_decoded_frame(
+    /// Append to the partial output of an interrupted decode.
+    ///
+    /// Decoded audio is discarded until `resume.samples` have been decoded.
+    /// Metadata is diffed from the start of the stream, and events already in
+    /// a resumed metadata file are not written again; without one, the
+    /// metadata file is written from the start.
+    pub fn resume_from(&mut self, resume: ResumeState) {
+        self.partial_outputs.create(&resume.audio_path);
+        self.audio_writer = Some(resume.writer);
+        self.current_audio_path = Some(resume.audio_path);
+        self.resume_samples = resume.samples;
+        self.resume_format = Some(resume.format);
+
+        if let Some(metadata) = resume.metadata {
+            self.metadata.resume(metadata, &mut self.partial_outputs);
+        }
+    }
+
+    /// Handle a decoded access unit, starting a new output segment when the
+    /// substream info changed
+    pub fn handle_access_unit(
         &mut self,
         decoded: truehd::process::decode::DecodedAccessUnit,
         ctx: &FrameHandlerContext,
     ) -> Result<()> {
-        let sample_rate = decoded.sampling_frequency;
-        let channel_count = decoded.channel_count;
+        if decoded.substream_info_changed {
+            // Store the current sample position as the start of the new segment
+            self.segment_start_samples = self.decoded_samples;
+
+            // Handle stream restart with actual sample rate and channel count from decoded frame
+            self.handle_stream_restart(
+                ctx.base_path,
+                ctx.format,
+                decoded.sampling_frequency,
+                decoded.channel_count,
+                &decoded.channel_labels,
+                ctx.bed_conform,
+            )?;
+            self.is_segmented = !self.to_stdout; // Mark that we're now in segmented mode
+        }
+
+        self.handle_decoded_frame(decoded, ctx)
+    }
 
+    pub fn handle_decoded_frame(
+        &mut self,
+        mut decoded: truehd::process::decode::DecodedAccessUnit,
+        ctx: &FrameHandlerContext,
+    ) -> Result<()> {
         if decoded.is_duplicate {
             return Ok(());
         }
 
+        if self.audio_writer.is_some()
+            && let Some((channel_count, channel_labels)) = &self.output_layout
+        {
+            conform_channel_count(&mut decoded, *channel_count, channel_labels, self.au_index);
+        }
+        let sample_rate = decoded.sampling_frequency;
+        let channel_count = decoded.channel_count;
+
+        // The excerpt is converted to samples at the first sample rate
+        if let Some(start) = self.excerpt_start.take() {
+            self.trim_samples += samples_at(start, sample_rate);
+        }
+        if let Some(duration) = self.excerpt_duration.take() {
+            self.end_samples = Some(self.trim_samples + samples_at(duration, sample_rate));
+        }
+
+        if decoded.stream_start {
+            self.junctions
+                .push(self.decoded_samples.saturating_sub(self.trim_samples));
+        }
+
+        if let Some(resume_format) = &self.resume_format {
+            resume_format.check_sample_rate(sample_rate)?;
+        }
+
         self.decoded_frames += 1u64;
         self.final_sample_rate = sample_rate;
         self.au_index += 1;
 
-        self.handle_atmos_metadata(
-            &decoded,
-            ctx.base_path,
-            ctx.format,
-            ctx.state,
-            ctx.bed_conform,
-            ctx.warp_mode,
-        )?;
+        // Atmos output is named and laid out from the first access unit on,
+        // even when the first OAMD payload only follows later
+        if ctx.atmos && self.presentation == 3 {
+            self.has_atmos = true;
+        }
+
+        // 32-bit samples are written as 32-bit integers instead of being
+        // dithered to the default 24 bits
+        if self.audio_writer.is_none()
+            && decoded.bit_depth == 32
+            && self.bit_format == BitFormat::Int24
+        {
+            log::info!("Writing 32-bit samples of the 0x31EC substream as int32");
+            self.bit_format = BitFormat::Int32;
+        }
+
+        self.handle_atmos_metadata(&decoded, ctx)?;
 
+        let frame_start = self.decoded_samples;
+        if let Some(oamd_dump) = &mut self.oamd_dump {
+            oamd_dump.write(&decoded, self.au_index - 1, frame_start)?;
+        }
+        if let Some(trajectories) = &mut self.trajectories {
+            trajectories.write(&decoded.oamd, frame_start)?;
+        }
         self.decoded_samples += decoded.sample_length as u64;
 
-        let effective_channel_count = if ctx.bed_conform && self.has_atmos {
+        // Samples past the end of the excerpt are not written
+        if let Some(end_samples) = self.end_samples {
+            decoded.sample_length =
+                (end_samples.saturating_sub(frame_start) as usize).min(decoded.sample_length);
+        }
+
+        if self.metadata_only {
+            return self.update_progress_display(sample_rate, ctx);
+        }
+
+        // Samples of this frame trimmed or already present in a resumed output file
+        let start = ((self.trim_samples + self.resume_samples).saturating_sub(frame_start)
+            as usize)
+            .min(decoded.sample_length);
+        if start == decoded.sample_length {
+            return self.update_progress_display(sample_rate, ctx);
+        }
+
+        if self.apply_drc == ApplyDrc::Heavy {
+            self.heavy_drc_applied |= apply_heavy_drc(&mut decoded);
+        }
+
+        if self.apply_dialnorm
+            && let Some(dialogue_level) = decoded.dialogue_level
+        {
+            let gain = dialnorm_gain(dialogue_level);
+            if self.dialogue_level != Some(dialogue_level) {
+                log::info!(
+                    "Applying dialogue normalization of {dialogue_level} dBFS: gain {:.2} dB",
+                    20.0 * gain.log10()
+                );
+                self.dialogue_level = Some(dialogue_level);
+            }
+            if gain != 1.0 {
+                apply_gain(&mut decoded, gain);
+            }
+        }
+
+        if let Some(loudness) = &mut self.loudness {
+            loudness.process(&decoded, start);
+        }
+
+        self.update_remapper(channel_count, &decoded.channel_labels)?;
+
+        let effective_channel_count = if let Some(remapper) = &self.remapper {
+            remapper.output_labels().len()
+        } else if ctx.bed_conform && self.has_atmos_metadata {
             let empty_vec = Vec::new();
             let bed_indices = self.bed_indices.as_ref().unwrap_or(&empty_vec);
-            ChannelCountCalculator::calculate_conformed_channel_count(channel_count, bed_indices)
+            ChannelCountCalculator::calculate_conformed_channel_count(
+                channel_count,
+                bed_indices,
+                self.bed_conform_layout.speakers(),
+            )
         } else {
             channel_count
         };
 
+        let resume_format = self.resume_format.take();
+        if let Some(resume_format) = &resume_format {
+            resume_format.check_channel_count(effective_channel_count)?;
+        }
+
+        let channel_labels = match &self.remapper {
+            Some(remapper) => remapper.output_labels().to_vec(),
+            None => self.output_channel_labels(&decoded.channel_labels, ctx.bed_conform),
+        };
+
+        if resume_format.is_some()
+            && let Some(writer) = &mut self.audio_writer
+        {
+            writer.resume_channel_labels(&channel_labels)?;
+        }
+
+        if self.audio_writer.is_none() {
+            // The bed layout is only known from the first Atmos metadata
+            self.bed_conform_pending = ctx.bed_conform && !self.has_atmos_metadata;
+            self.output_layout = Some((channel_count, decoded.channel_labels.clone()));
+        }
         self.create_audio_writer_if_needed(
             ctx.base_path,
             ctx.format,
-            sample_rate,
+            self.output_sample_rate(sample_rate),
             effective_channel_count,
+            &channel_labels,
         )?;
 
-        if ctx.bed_conform && self.has_atmos {
-            self.write_audio_samples_bed_conform(&decoded, channel_count)?;
+        if ctx.bed_conform && self.has_atmos_metadata {
+            self.write_audio_samples_bed_conform(&decoded, channel_count, start, frame_start)?;
         } else {
-            self.write_audio_samples(&decoded, channel_count)?;
+            self.write_audio_samples(&decoded, channel_count, start)?;
+        }
+
+        if self.low_latency
+            && let Some(writer) = &mut self.audio_writer
+        {
+            writer.flush()?;
         }
 
-        self.update_progress_display(sample_rate, ctx.start_time, ctx.pb)?;
+        self.update_progress_display(sample_rate, ctx)?;
 
         Ok(())
     }
@@ -375,83 +917,132 @@ impl DecodeHandler {
     fn handle_atmos_metadata(
         &mut self,
         decoded: &truehd::process::decode::DecodedAccessUnit,
-        base_path: &Option<PathBuf>,
-        format: AudioFormat,
-        state: &WriterState,
-        bed_conform: bool,
-        warp_mode: Option<crate::cli::command::WarpMode>,
+        ctx: &FrameHandlerContext,
     ) -> Result<()> {
+        let FrameHandlerContext {
+            base_path,
+            metadata_path,
+            format,
+            state,
+            bed_conform,
+            warp_mode,
+            fps,
+            timestamp,
+            ..
+        } = *ctx;
+
+        let header_options = HeaderOptions {
+            warp_mode,
+            fps,
+            timestamp,
+            start_samples: self.output_origin(),
+            sample_rate: decoded.sampling_frequency,
+        };
+
         for oamd in &decoded.oamd {
-            let was_atmos = self.has_atmos;
+            let first_metadata = !self.has_atmos_metadata;
+            self.has_atmos_metadata = true;
             self.has_atmos = true;
 
-            // Create DAMF header file when we first detect Atmos
-            if !was_atmos {
-                if let Some(base_path) = base_path {
-                    // Use segmented base path if we're in segmented mode
-                    let effective_base_path = if self.is_segmented {
-                        // Derive base path from current audio path by removing the audio extension
-                        if let Some(ref current_path) = self.current_audio_path {
-                            let mut segmented_base = current_path.clone();
-                            // Remove .atmos.audio or other extensions to get base path
-                            if current_path.extension() == Some(std::ffi::OsStr::new("audio")) {
-                                segmented_base.set_extension(""); // Remove .audio
-                                if segmented_base.extension() == Some(std::ffi::OsStr::new("atmos"))
-                                {
-                                    segmented_base.set_extension(""); // Remove .atmos
-                                }
-                            } else {
-                                segmented_base.set_extension(""); // Remove whatever extension
-                            }
-                            segmented_base
-                        } else {
-                            base_path.to_path_buf()
+            if bed_conform && self.apply_object_gains {
+                self.object_gains.update(oamd, self.decoded_samples);
+            }
+
+            // Create DAMF header file at the first metadata
+            if first_metadata {
+                let num_isf_objects = oamd.program_assignment.num_isf_objects;
+                if num_isf_objects > 0 {
+                    log_or_err!(
+                        state,
+                        Category::Metadata,
+                        Level::Warn,
+                        anyhow!(
+                            "{num_isf_objects} ISF objects are written as objects without positions"
+                        )
+                    );
+                }
+
+                if bed_conform {
+                    // Store the speakers of the first bed instance for conformance
+                    self.bed_indices = oamd
+                        .program_assignment
+                        .bed_assignment
+                        .first()
+                        .map(|bed| bed.to_index_vec());
+                    if let Some(bed_indices) = &self.bed_indices {
+                        BedConformMap::warn_folded(bed_indices, self.bed_conform_layout.speakers());
+                    }
+                }
+
+                let conformed_layout = self
+                    .bed_indices
+                    .as_ref()
+                    .map(|_| self.bed_conform_layout.speakers());
+                self.atmos_channel_descriptions = Some(
+                    BedChannelMapper::atmos_channel_descriptions(oamd, conformed_layout),
+                );
+                if self.split_channels {
+                    self.atmos_split_names = Some(BedChannelMapper::atmos_split_channel_names(
+                        oamd,
+                        conformed_layout,
+                    ));
+                }
+
+                if format == AudioFormat::Adm {
+                    self.adm = Some(AdmDocument::with_oamd_payload(
+                        oamd,
+                        self.output_sample_rate(decoded.sampling_frequency),
+                        self.bit_format.bits_per_sample(),
+                    ));
+                }
+
+                if self.to_stdout && metadata_path.is_none() {
+                    log::warn!(
+                        "Atmos metadata is not written when decoding to stdout, use --metadata-path to write it"
+                    );
+                }
+
+                if let Some(base_path) = metadata_path {
+                    // Segments are named after their audio file
+                    let effective_base_path = match &self.current_audio_path {
+                        Some(current_path) if self.is_segmented => {
+                            segment_base_path(current_path, self.split_channels)
                         }
-                    } else {
-                        base_path.to_path_buf()
+                        _ => base_path.to_path_buf(),
                     };
 
-                    if bed_conform {
-                        // Store bed indices first for conformance
-                        self.bed_indices = BedInstance::with_oamd_payload(oamd)
-                            .first()
-                            .map(|bed| bed.to_index_vec());
-
-                        // Create bed-conformed DAMF header
-                        if self.bed_indices.is_some() {
-                            if let Err(e) = rewrite_damf_header_for_bed_conform(
-                                &effective_base_path,
-                                oamd,
-                                warp_mode,
-                            ) {
-                                log_or_err!(state, Level::Error, e);
-                            }
-                        } else {
-                            // Fallback to regular header if no bed indices
-                            if let Err(e) =
-                                create_damf_header_file(&effective_base_path, oamd, warp_mode)
-                            {
-                                log_or_err!(state, Level::Error, e);
-                            }
-                        }
-                    } else {
-                        // Create regular DAMF header
-                        if let Err(e) =
-                            create_damf_header_file(&effective_base_path, oamd, warp_mode)
-                        {
-                            log_or_err!(state, Level::Error, e);
-                        }
+                    let bed_conform_speakers = self
+                        .bed_indices
+                        .as_ref()
+                        .filter(|_| bed_conform)
+                        .map(|_| self.bed_conform_layout.speakers());
+                    if let Err(e) = self.metadata.create_header(
+                        &effective_base_path,
+                        oamd,
+                        bed_conform_speakers,
+                        &header_options,
+                        &mut self.partial_outputs,
+                    ) {
+                        log_or_err!(state, Category::Metadata, Level::Error, e);
                     }
                 }
             }
 
             // Handle file renaming for first Atmos detection (but not if we're in segmented mode)
-            if !was_atmos && self.audio_writer.is_some() && !self.is_segmented {
+            if first_metadata && self.audio_writer.is_some() && self.to_stdout && bed_conform {
+                log::warn!(
+                    "Bed conformance only applies to the audio following the first Atmos metadata on stdout"
+                );
+            } else if first_metadata
+                && self.audio_writer.is_some()
+                && !self.is_segmented
+                && !self.to_stdout
+            {
                 if bed_conform {
                     self.handle_atmos_file_rename_with_bed_conform(
                         base_path,
                         format,
-                        decoded.sampling_frequency,
+                        self.output_sample_rate(decoded.sampling_frequency),
                         decoded.channel_count as u32,
                         state,
                     )?;
@@ -459,18 +1050,24 @@ impl DecodeHandler {
                     self.handle_atmos_file_rename(
                         base_path,
                         format,
-                        decoded.sampling_frequency,
+                        self.output_sample_rate(decoded.sampling_frequency),
                         decoded.channel_count as u32,
                         state,
                     )?;
                 }
             }
 
+            let output_rate = self.output_sample_rate(decoded.sampling_frequency);
+            let thinning = ctx
+                .metadata_update_rate
+                .map(|rate| MetadataThinning::with_update_rate(rate, output_rate));
+
             self.handle_metadata_writing(
                 oamd,
                 decoded.sampling_frequency,
-                self.decoded_samples,
-                base_path,
+                decoded.sample_position,
+                thinning,
+                metadata_path,
                 format,
             )?;
         }
@@ -485,6 +1082,10 @@ impl DecodeHandler {
         channel_count: u32,
         state: &WriterState,
     ) -> Result<()> {
+        if let (Some(base_path), Some(AudioWriter::Split(_))) = (base_path, &self.audio_writer) {
+            return self.rename_split_channels(base_path);
+        }
+
         if let (Some(base_path), Some(current_path)) = (base_path, &self.current_audio_path) {
             let (new_audio_path, _) = create_output_paths(base_path, format, true);
             if current_path != &new_audio_path {
@@ -496,8 +1097,8 @@ impl DecodeHandler {
                 if let Some(writer) = self.audio_writer.take() {
                     let new_writer = AudioFormatHandler::handle_format_specific_rename(
                         writer,
-                        current_path,
-                        &new_audio_path,
+                        &partial_path(current_path),
+                        &self.partial_outputs.create(&new_audio_path),
                         sample_rate,
                         channel_count,
                         state,
@@ -510,6 +1111,28 @@ impl DecodeHandler {
         Ok(())
     }
 
+    /// Rename the files of a split output to the names of the Atmos channels
+    fn rename_split_channels(&mut self, base_path: &Path) -> Result<()> {
+        let (Some(AudioWriter::Split(writer)), Some(names)) =
+            (self.audio_writer.take(), &self.atmos_split_names)
+        else {
+            return Ok(());
+        };
+
+        let new_paths = split_channel_paths(base_path, names)
+            .iter()
+            .map(|path| self.partial_outputs.create(path))
+            .collect::<Vec<_>>();
+        if writer.paths() != new_paths {
+            log::info!(
+                "Atmos detected - renaming {} split channel files",
+                new_paths.len()
+            );
+        }
+        self.audio_writer = Some(AudioWriter::Split(writer.rename(new_paths)?));
+        Ok(())
+    }
+
     fn handle_atmos_file_rename_with_bed_conform(
         &mut self,
         base_path: &Option<PathBuf>,
@@ -518,9 +1141,20 @@ impl DecodeHandler {
         channel_count: u32,
         state: &WriterState,
     ) -> Result<()> {
+        if let Some(AudioWriter::Split(_)) = &self.audio_writer {
+            bail!(
+                "--bed-conform with --split-channels requires Atmos metadata in the first access unit"
+            );
+        }
+
+        // The samples written so far are converted with the file
+        if self.bed_conform_pending {
+            self.flush_resampler()?;
+        }
+
         if let (Some(base_path), Some(current_path)) = (base_path, &self.current_audio_path) {
             let (new_audio_path, _) = create_output_paths(base_path, format, true);
-            if current_path != &new_audio_path {
+            if self.bed_conform_pending {
                 log::info!(
                     "Atmos detected with bed conformance - converting audio file to: {}",
                     new_audio_path.display()
@@ -532,28 +1166,29 @@ impl DecodeHandler {
                     ChannelCountCalculator::calculate_conformed_channel_count(
                         channel_count as usize,
                         bed_indices,
+                        self.bed_conform_layout.speakers(),
                     );
 
                 if let Some(writer) = self.audio_writer.take() {
                     let params = BedConformConversionParams {
-                        writer,
-                        current_path,
-                        new_path: &new_audio_path,
+                        current_path: &partial_path(current_path),
+                        new_path: &self.partial_outputs.create(&new_audio_path),
                         channel_count,
                         conformed_channel_count,
                         sample_rate: sample_rate as f64,
+                        sample_count: self.written_samples(),
                         state,
                     };
                     let new_writer = AudioFormatHandler::handle_bed_conform_conversion(
+                        writer,
                         params,
-                        |temp_path, new_path, orig_ch, conf_ch, sr, st| {
-                            self.convert_audio_file_to_bed_conform(
-                                temp_path, new_path, orig_ch, conf_ch, sr, st,
-                            )
+                        |temp_path, params| {
+                            self.convert_audio_file_to_bed_conform(temp_path, params)
                         },
                     )?;
                     self.audio_writer = Some(new_writer);
                     self.current_audio_path = Some(new_audio_path);
+                    self.bed_conform_pending = false;
                 }
             }
         }
@@ -563,36 +1198,28 @@ impl DecodeHandler {
     fn convert_audio_file_to_bed_conform(
         &self,
         temp_path: &Path,
-        new_path: &Path,
-        original_channel_count: usize,
-        conformed_channel_count: usize,
-        sample_rate: f64,
-        _state: &WriterState,
+        params: &BedConformConversionParams,
     ) -> Result<()> {
         log::info!(
-            "Converting audio from {original_channel_count} to {conformed_channel_count} channels"
+            "Converting audio from {} to {} channels",
+            params.channel_count,
+            params.conformed_channel_count
         );
 
         // For presentation 3, files are always CAF since format is forced by effective_format
-        self.convert_caf_file_to_bed_conform(
-            temp_path,
-            new_path,
-            original_channel_count,
-            conformed_channel_count,
-            sample_rate,
-        )
+        self.convert_caf_file_to_bed_conform(temp_path, params)
     }
 
     fn convert_caf_file_to_bed_conform(
         &self,
         temp_path: &Path,
-        new_path: &Path,
-        original_channel_count: usize,
-        conformed_channel_count: usize,
-        sample_rate: f64,
+        params: &BedConformConversionParams,
     ) -> Result<()> {
         use std::io::{Read, Seek, SeekFrom};
 
+        let original_channel_count = params.channel_count as usize;
+        let conformed_channel_count = params.conformed_channel_count;
+
         let mut temp_file = File::open(temp_path)?;
         let file_info = crate::caf::parse_caf_file(&mut temp_file)?;
 
@@ -600,19 +1227,66 @@ impl DecodeHandler {
         let mut audio_data = Vec::new();
         temp_file.read_to_end(&mut audio_data)?;
 
-        let original_samples =
-            AudioDataConverter::convert_caf_bytes_to_samples(&audio_data, file_info.endianness);
+        let bit_format = file_info
+            .audio_format
+            .as_ref()
+            .and_then(|format| {
+                BitFormat::from_sample_format(format.bits_per_channel, format.is_float())
+            })
+            .unwrap_or(self.bit_format);
+
+        // Convert whole sample frames only, up to the samples written so far
+        let frame_size = original_channel_count * bit_format.bits_per_sample() as usize / 8;
+        if !audio_data.len().is_multiple_of(frame_size) {
+            log_or_err!(
+                params.state,
+                Category::Output,
+                Level::Warn,
+                anyhow!(
+                    "Audio data of {} bytes is not a multiple of the {frame_size} byte sample frame, dropping the partial frame",
+                    audio_data.len()
+                )
+            );
+        }
+        let frames = (audio_data.len() / frame_size) as u64;
+        if frames != params.sample_count {
+            log_or_err!(
+                params.state,
+                Category::Output,
+                Level::Warn,
+                anyhow!(
+                    "Audio file holds {frames} samples, {} were written",
+                    params.sample_count
+                )
+            );
+        }
+        audio_data.truncate(frames.min(params.sample_count) as usize * frame_size);
+
+        let original_samples = AudioDataConverter::convert_caf_bytes_to_samples(
+            &audio_data,
+            file_info.endianness,
+            bit_format,
+        );
 
+        // Float samples are converted back to 24-bit integers
+        let bit_depth = match bit_format {
+            BitFormat::Float32 => 24,
+            bit_format => bit_format.bits_per_sample(),
+        };
         let conformed_samples = self.convert_samples_to_bed_conform(
             original_samples,
             original_channel_count,
-            conformed_channel_count,
+            bit_depth,
         );
 
         let mut caf_writer = AudioWriter::create_caf(
-            new_path.to_path_buf(),
-            sample_rate as u32,
+            params.new_path.to_path_buf(),
+            params.sample_rate as u32,
             conformed_channel_count as u32,
+            self.output_channel_descriptions(&BedChannelMapper::conformed_bed_labels(
+                self.bed_conform_layout.speakers(),
+            )),
+            bit_format,
         )?;
         caf_writer.write_pcm_samples(&conformed_samples, conformed_channel_count)?;
         caf_writer.finish()?;
@@ -624,7 +1298,7 @@ impl DecodeHandler {
         &self,
         original_samples: Vec<i32>,
         original_channel_count: usize,
-        _conformed_channel_count: usize,
+        bit_depth: u32,
     ) -> Vec<i32> {
         let empty_vec = Vec::new();
         let bed_indices = self.bed_indices.as_ref().unwrap_or(&empty_vec);
@@ -632,60 +1306,82 @@ impl DecodeHandler {
             original_samples,
             original_channel_count,
             bed_indices,
+            self.bed_conform_layout.speakers(),
+            bit_depth,
         )
     }
 
+    /// The end of the `--duration` excerpt has been decoded.
+    pub fn finished(&self) -> bool {
+        self.end_samples
+            .is_some_and(|end_samples| self.decoded_samples >= end_samples)
+    }
+
+    /// Samples of the output, from the end of the start trim
+    pub fn output_samples(&self) -> u64 {
+        let end_samples = self.end_samples.unwrap_or(u64::MAX);
+        self.decoded_samples
+            .min(end_samples)
+            .saturating_sub(self.trim_samples)
+    }
+
+    /// Stream position of the first sample of the output file, either the
+    /// start of the current segment or the end of the start trim
+    fn output_origin(&self) -> u64 {
+        if self.is_segmented {
+            self.segment_start_samples.max(self.trim_samples)
+        } else {
+            self.trim_samples
+        }
+    }
+
     fn handle_metadata_writing(
         &mut self,
         oamd: &truehd::structs::oamd::ObjectAudioMetadataPayload,
         sample_rate: u32,
         sample_pos: u64,
+        thinning: Option<MetadataThinning>,
         base_path: &Option<PathBuf>,
         format: AudioFormat,
     ) -> Result<()> {
-        // Calculate the relative sample position within the current segment
-        let segment_relative_sample_pos = if self.is_segmented {
-            let relative_pos = sample_pos.saturating_sub(self.segment_start_samples);
-            log::trace!(
-                "Adjusting OAMD sample position: absolute={}, segment_start={}, relative={}",
-                sample_pos,
-                self.segment_start_samples,
-                relative_pos
-            );
-            relative_pos
-        } else {
-            sample_pos
-        };
+        // Metadata past the end of the excerpt is not written
+        if self
+            .end_samples
+            .is_some_and(|end_samples| sample_pos >= end_samples)
+        {
+            return Ok(());
+        }
 
-        let mut conf =
-            Configuration::with_oamd_payload(oamd, sample_rate, segment_relative_sample_pos);
+        // Events are positioned relative to the first sample of the output file
+        let origin = self.output_origin();
+        log::trace!("Adjusting OAMD sample position: absolute={sample_pos}, origin={origin}");
 
-        let (events_diff, remove_header) = if !self.prev_events.is_empty() {
-            (
-                Event::compare_event_vectors(&self.prev_events, &conf.events),
-                true,
-            )
-        } else {
-            (conf.events.clone(), false)
-        };
+        let mut configuration = self.metadata.configuration(oamd, sample_rate, sample_pos);
+        configuration.rebase_sample_pos(origin);
+        let output_rate = self.output_sample_rate(sample_rate);
+        if output_rate != sample_rate {
+            configuration.resample(output_rate);
+        }
 
-        self.prev_events = conf.events.clone();
-        conf.events = events_diff;
-        let oamd_str = conf.serialize_events(remove_header);
+        // ADM BWF output carries the metadata in its axml chunk instead
+        if let Some(adm) = &mut self.adm {
+            adm.push_configuration(configuration, thinning);
+            return Ok(());
+        }
+
+        let Some(events) = self.metadata.diff(configuration, thinning) else {
+            return Ok(());
+        };
 
         if let Some(base_path) = base_path {
-            if self.damf_metadata_file_writer.is_none() {
+            if !self.metadata.is_open() {
                 let (_, metadata_path) = create_output_paths(base_path, format, self.has_atmos);
                 if !metadata_path.as_os_str().is_empty() {
-                    log::info!("Creating metadata file: {}", metadata_path.display());
-                    self.damf_metadata_file_writer =
-                        Some(BufWriter::new(File::create(metadata_path)?));
+                    self.metadata
+                        .create(&metadata_path, &mut self.partial_outputs)?;
                 }
             }
-            if let Some(ref mut writer) = self.damf_metadata_file_writer {
-                write!(writer, "{oamd_str}")?;
-                writer.flush()?;
-            }
+            self.metadata.write(&events)?;
         }
         Ok(())
     }
@@ -696,11 +1392,29 @@ impl DecodeHandler {
         format: AudioFormat,
         sample_rate: u32,
         channel_count: usize,
+        channel_labels: &[ChannelLabel],
     ) -> Result<()> {
         if let Some(base_path) = base_path {
-            if self.audio_writer.is_none() {
-                // For Atmos content, always use CAF format
-                let effective_format = if self.has_atmos {
+            if self.audio_writer.is_none() && self.stdout_caf {
+                log::info!(
+                    "Writing {channel_count} channels of {} at {sample_rate} Hz to stdout as CAF of unknown length",
+                    raw_pcm_format_name(self.bit_format)
+                );
+                self.audio_writer = Some(AudioWriter::create_stdout_caf(
+                    sample_rate,
+                    channel_count as u32,
+                    self.output_channel_descriptions(channel_labels),
+                    self.bit_format,
+                )?);
+            } else if self.audio_writer.is_none() && self.to_stdout {
+                log::info!(
+                    "Writing {channel_count} channels of {} PCM at {sample_rate} Hz to stdout",
+                    raw_pcm_format_name(self.bit_format)
+                );
+                self.audio_writer = Some(AudioWriter::create_stdout(self.bit_format)?);
+            } else if self.audio_writer.is_none() {
+                // For Atmos content, always use CAF format unless writing ADM BWF
+                let effective_format = if self.has_atmos && format != AudioFormat::Adm {
                     if format != AudioFormat::Caf {
                         log::info!(
                             "Atmos audio detected - forcing CAF format instead of {format:?}"
@@ -711,11 +1425,36 @@ impl DecodeHandler {
                     format
                 };
 
-                let (audio_path, _) =
+                if self.split_channels {
+                    let names = match &self.atmos_split_names {
+                        Some(names) if self.has_atmos => names.clone(),
+                        _ => split_channel_names(channel_labels, channel_count),
+                    };
+                    log::info!(
+                        "Creating {} mono audio files: {}.*.caf",
+                        names.len(),
+                        base_path.display()
+                    );
+                    self.current_audio_path = Some(base_path.clone());
+                    let paths = split_channel_paths(base_path, &names)
+                        .iter()
+                        .map(|path| self.partial_outputs.create(path))
+                        .collect();
+                    self.audio_writer = Some(AudioWriter::create_split(
+                        paths,
+                        sample_rate,
+                        self.output_channel_descriptions(channel_labels),
+                        self.bit_format,
+                    )?);
+                    return Ok(());
+                }
+
+                let (final_path, _) =
                     create_output_paths(base_path, effective_format, self.has_atmos);
-                log::info!("Creating audio file: {}", audio_path.display());
+                log::info!("Creating audio file: {}", final_path.display());
 
-                self.current_audio_path = Some(audio_path.clone());
+                let audio_path = self.partial_outputs.create(&final_path);
+                self.current_audio_path = Some(final_path);
 
                 match effective_format {
                     AudioFormat::Caf => {
@@ -723,37 +1462,142 @@ impl DecodeHandler {
                             audio_path,
                             sample_rate,
                             channel_count as u32,
+                            self.output_channel_descriptions(channel_labels),
+                            self.bit_format,
                         )?);
                     }
                     AudioFormat::Pcm => {
-                        self.audio_writer = Some(AudioWriter::create_pcm(audio_path)?);
+                        self.audio_writer =
+                            Some(AudioWriter::create_pcm(audio_path, self.bit_format)?);
                     }
                     AudioFormat::W64 => {
                         self.audio_writer = Some(AudioWriter::create_w64(
                             audio_path,
                             sample_rate,
                             channel_count as u32,
+                            channel_labels,
+                            self.bit_format,
                         )?);
                     }
-                }
-            }
-        }
-        Ok(())
-    }
-
-    fn write_audio_samples(
-        &mut self,
+                    AudioFormat::Wav => {
+                        self.audio_writer = Some(AudioWriter::create_wav(
+                            audio_path,
+                            sample_rate,
+                            channel_count as u32,
+                            channel_labels,
+                            self.bit_format,
+                        )?);
+                    }
+                    AudioFormat::Adm => {
+                        self.audio_writer = Some(AudioWriter::create_adm(
+                            audio_path,
+                            sample_rate,
+                            channel_count as u32,
+                            self.bit_format,
+                        )?);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// CAF descriptions of the channels as they are written to the output file
+    ///
+    /// Atmos output is described from the object audio metadata, otherwise the
+    /// output channel labels are used.
+    fn output_channel_descriptions(
+        &self,
+        channel_labels: &[ChannelLabel],
+    ) -> Vec<ChannelDescription> {
+        match &self.atmos_channel_descriptions {
+            Some(descriptions) if self.has_atmos => descriptions.clone(),
+            _ => channel_labels
+                .iter()
+                .map(|&label| ChannelDescription::from_truehd_label(label))
+                .collect(),
+        }
+    }
+
+    /// Labels of the channels as they are written to the output file
+    fn output_channel_labels(
+        &self,
+        decoded_labels: &[ChannelLabel],
+        bed_conform: bool,
+    ) -> Vec<ChannelLabel> {
+        if bed_conform && self.has_atmos_metadata {
+            BedChannelMapper::conformed_bed_labels(self.bed_conform_layout.speakers())
+        } else {
+            decoded_labels.to_vec()
+        }
+    }
+
+    /// Rebuild the channel remapper when the decoded channel labels change
+    fn update_remapper(&mut self, channel_count: usize, labels: &[ChannelLabel]) -> Result<()> {
+        if self.channel_order == ChannelOrder::Native && self.downmix.is_none() {
+            return Ok(());
+        }
+        if self.has_atmos {
+            bail!("--channel-order and --downmix are not supported for Atmos output");
+        }
+        if self
+            .remapper
+            .as_ref()
+            .is_some_and(|remapper| remapper.input_labels() == labels)
+        {
+            return Ok(());
+        }
+
+        let remapper =
+            ChannelRemapper::new(self.channel_order, self.downmix, channel_count, labels)
+                .with_context(|| {
+                    format!(
+                        "Cannot apply --channel-order or --downmix to presentation {}",
+                        self.presentation
+                    )
+                })?;
+        log::info!(
+            "Remapping channels {labels:?} to {:?}",
+            remapper.output_labels()
+        );
+        self.remapper = Some(remapper);
+        Ok(())
+    }
+
+    fn write_audio_samples(
+        &mut self,
         decoded: &truehd::process::decode::DecodedAccessUnit,
         channel_count: usize,
+        start: usize,
     ) -> Result<()> {
-        if let Some(ref mut writer) = self.audio_writer {
-            let mut samples = Vec::with_capacity(decoded.sample_length * channel_count);
-            for sample_idx in 0..decoded.sample_length {
-                for ch in 0..channel_count {
-                    let sample = decoded.pcm_data[sample_idx][ch];
-                    samples.push(sample);
-                }
+        if self.audio_writer.is_none() {
+            return Ok(());
+        }
+
+        let mut samples = Vec::with_capacity((decoded.sample_length - start) * channel_count);
+        for sample_idx in start..decoded.sample_length {
+            for ch in 0..channel_count {
+                let sample = decoded.pcm_data[sample_idx][ch];
+                samples.push(sample);
             }
+        }
+        let (samples, channel_count) = match &self.remapper {
+            Some(remapper) => (remapper.apply(&samples), remapper.output_labels().len()),
+            None => (samples, channel_count),
+        };
+        let mut samples = self.resample(
+            samples,
+            channel_count,
+            decoded.sampling_frequency,
+            decoded.bit_depth,
+        )?;
+        requantize_samples(
+            &mut samples,
+            decoded.bit_depth,
+            self.bit_format,
+            &mut self.dither,
+        );
+        if let Some(writer) = &mut self.audio_writer {
             writer.write_pcm_samples(&samples, channel_count)?;
         }
         Ok(())
@@ -763,41 +1607,142 @@ impl DecodeHandler {
         &mut self,
         decoded: &truehd::process::decode::DecodedAccessUnit,
         channel_count: usize,
+        start: usize,
+        frame_start: u64,
     ) -> Result<()> {
-        if let Some(ref mut writer) = self.audio_writer {
-            let empty_vec = Vec::new();
-            let bed_indices = self.bed_indices.as_ref().unwrap_or(&empty_vec);
-            let conformed_channel_count = ChannelCountCalculator::calculate_conformed_channel_count(
-                channel_count,
-                bed_indices,
-            );
+        if self.audio_writer.is_none() {
+            return Ok(());
+        }
 
-            let samples = BedChannelMapper::apply_bed_conformance_to_frame(
-                decoded,
-                channel_count,
-                bed_indices,
-            );
+        let empty_vec = Vec::new();
+        let bed_indices = self.bed_indices.as_ref().unwrap_or(&empty_vec);
+        let conformed_channel_count = ChannelCountCalculator::calculate_conformed_channel_count(
+            channel_count,
+            bed_indices,
+            self.bed_conform_layout.speakers(),
+        );
 
+        let samples = BedChannelMapper::apply_bed_conformance_to_frame(
+            decoded,
+            channel_count,
+            bed_indices,
+            self.bed_conform_layout.speakers(),
+            start,
+            self.apply_object_gains.then_some(&mut self.object_gains),
+            frame_start,
+        );
+        let mut samples = self.resample(
+            samples,
+            conformed_channel_count,
+            decoded.sampling_frequency,
+            decoded.bit_depth,
+        )?;
+        requantize_samples(
+            &mut samples,
+            decoded.bit_depth,
+            self.bit_format,
+            &mut self.dither,
+        );
+
+        if let Some(writer) = &mut self.audio_writer {
             writer.write_pcm_samples(&samples, conformed_channel_count)?;
         }
         Ok(())
     }
 
-    fn update_progress_display(
-        &self,
+    /// Sample rate of the output of a stream at `sample_rate`: 48 kHz for
+    /// the 44.1 kHz family with `--resample-to-48k`
+    pub fn output_sample_rate(&self, sample_rate: u32) -> u32 {
+        if self.resample_to_48k && sample_rate.is_multiple_of(44100) {
+            48000
+        } else {
+            sample_rate
+        }
+    }
+
+    /// Resample interleaved output samples to the output sample rate.
+    ///
+    /// The output of a previous stream with another format is completed
+    /// first. Up to the resampler's lookahead of the samples is held back
+    /// until the next call or [`flush_resampler`](Self::flush_resampler).
+    fn resample(
+        &mut self,
+        samples: Vec<i32>,
+        channel_count: usize,
         sample_rate: u32,
-        start_time: std::time::Instant,
-        pb: &Option<ProgressBar>,
-    ) -> Result<()> {
+        bit_depth: u32,
+    ) -> Result<Vec<i32>> {
+        if self.resampler.as_ref().is_some_and(|resampler| {
+            (
+                resampler.input_rate(),
+                resampler.channels(),
+                resampler.bit_depth(),
+            ) != (sample_rate, channel_count, bit_depth)
+        }) {
+            self.flush_resampler()?;
+        }
+
+        let output_rate = self.output_sample_rate(sample_rate);
+        if output_rate == sample_rate {
+            return Ok(samples);
+        }
+
+        let resampler = self.resampler.get_or_insert_with(|| {
+            log::info!(
+                "Resampling {channel_count} channels from {sample_rate} Hz to {output_rate} Hz"
+            );
+            Resampler::new(sample_rate, output_rate, channel_count, bit_depth)
+        });
+        let mut output = Vec::with_capacity(
+            samples.len() * output_rate as usize / sample_rate as usize + channel_count,
+        );
+        resampler.process(&samples, &mut output);
+        Ok(output)
+    }
+
+    /// Write the samples held back by the resampler to the end of the
+    /// current output
+    fn flush_resampler(&mut self) -> Result<()> {
+        let Some(mut resampler) = self.resampler.take() else {
+            return Ok(());
+        };
+        let mut samples = Vec::new();
+        resampler.flush(&mut samples);
+        requantize_samples(
+            &mut samples,
+            resampler.bit_depth(),
+            self.bit_format,
+            &mut self.dither,
+        );
+        if let Some(writer) = &mut self.audio_writer {
+            writer.write_pcm_samples(&samples, resampler.channels())?;
+        }
+        Ok(())
+    }
+
+    /// Samples of the output at the output sample rate, all written once the
+    /// resampler is flushed
+    pub fn written_samples(&self) -> u64 {
+        let sample_rate = self.final_sample_rate as u64;
+        let output_rate = self.output_sample_rate(self.final_sample_rate) as u64;
+        (self.output_samples() * output_rate).div_ceil(sample_rate)
+    }
+
+    fn update_progress_display(&self, sample_rate: u32, ctx: &FrameHandlerContext) -> Result<()> {
         if self.decoded_frames.is_multiple_of(30) {
-            let elapsed = start_time.elapsed();
+            let elapsed = ctx.start_time.elapsed();
             let audio_duration_secs = self.decoded_samples as f64 / sample_rate as f64;
             let realtime_multiplier = audio_duration_secs / elapsed.as_secs_f64();
             let time_str = time_str(audio_duration_secs);
 
-            if let Some(pb) = pb {
+            if let Some(pb) = ctx.pb {
+                let metrics = ctx
+                    .metrics
+                    .and_then(|metrics| metrics.lock().ok().map(|metrics| *metrics))
+                    .map(|metrics| metrics_str(&metrics, sample_rate))
+                    .unwrap_or_default();
                 pb.set_message(format!(
-                    "speed: {realtime_multiplier:.1}x | timestamp: {time_str}"
+                    "speed: {realtime_multiplier:.1}x{metrics} | timestamp: {time_str}"
                 ));
             }
         }
@@ -805,14 +1750,53 @@ impl DecodeHandler {
     }
 
     pub fn finalize(&mut self) -> Result<()> {
+        self.flush_resampler()?;
+        self.finish_adm()?;
+
+        if self.apply_drc == ApplyDrc::Heavy && !self.heavy_drc_applied && !self.metadata_only {
+            log::warn!("The stream carries no heavy DRC, the output has full dynamic range");
+        }
+        if self.apply_dialnorm && self.dialogue_level.is_none() && !self.metadata_only {
+            log::warn!(
+                "The presentation carries no dialogue normalization, the output is not attenuated"
+            );
+        }
+
         if let Some(ref mut writer) = self.audio_writer {
+            // A shortened final access unit must still end on a whole sample frame
+            if let Some((data_len, frame_size)) = writer.data_chunk_layout()?
+                && frame_size != 0
+                && !data_len.is_multiple_of(frame_size)
+            {
+                log::warn!(
+                    "Audio data of {data_len} bytes ends with a partial {frame_size} byte sample frame"
+                );
+            }
             writer.finish()?;
         }
 
-        if let Some(ref mut writer) = self.damf_metadata_file_writer {
-            writer.flush()?;
+        self.metadata.finish()?;
+
+        // The files are closed before they are renamed into place
+        self.audio_writer = None;
+        self.partial_outputs.commit()?;
+
+        if let Some(oamd_dump) = &mut self.oamd_dump {
+            oamd_dump.finish()?;
         }
 
+        if let Some(trajectories) = &mut self.trajectories {
+            trajectories.finish()?;
+        }
+
+        Ok(())
+    }
+
+    /// Append the ADM chunks to an ADM BWF output file
+    fn finish_adm(&mut self) -> Result<()> {
+        if let (Some(writer), Some(adm)) = (&mut self.audio_writer, self.adm.take()) {
+            writer.finish_adm(&adm)?;
+        }
         Ok(())
     }
 
@@ -822,8 +1806,24 @@ impl DecodeHandler {
         format: AudioFormat,
         sample_rate: u32,
         channel_count: usize,
+        channel_labels: &[ChannelLabel],
         bed_conform: bool,
     ) -> Result<()> {
+        let sample_rate = self.output_sample_rate(sample_rate);
+        if self.stdout_caf {
+            bail!(
+                "Stream restart detected at AU {}, the CAF on stdout cannot change to {channel_count} channels at {sample_rate} Hz",
+                self.au_index
+            );
+        }
+        if self.to_stdout {
+            log::warn!(
+                "Stream restart detected at AU {}, the raw PCM on stdout continues with {channel_count} channels at {sample_rate} Hz",
+                self.au_index
+            );
+            return Ok(());
+        }
+
         if let Some(base_path) = base_path {
             log::info!(
                 "Stream restart detected at AU {}, creating new segment {}",
@@ -831,25 +1831,43 @@ impl DecodeHandler {
                 self.segment_index + 1
             );
 
+            // The channels of the new segment are described once its metadata arrives
+            self.atmos_channel_descriptions = None;
+
             // Close current audio writer
+            self.flush_resampler()?;
+            self.finish_adm()?;
             if let Some(writer) = self.audio_writer.take() {
                 match writer {
                     AudioWriter::Caf(mut w) => {
                         w.finish()?;
                     }
-                    AudioWriter::Pcm(mut w) => {
+                    AudioWriter::Pcm(mut w, _) => {
                         w.flush()?;
                     }
                     AudioWriter::W64(mut w) => {
                         w.finish()?;
                     }
+                    AudioWriter::Wav(mut w) => {
+                        w.finish()?;
+                    }
+                    AudioWriter::Stdout(mut w, _) => {
+                        w.flush()?;
+                    }
+                    AudioWriter::StdoutCaf(mut w) => {
+                        w.finish()?;
+                    }
+                    AudioWriter::Split(mut w) => {
+                        w.finish()?;
+                    }
                 }
             }
 
-            // Close metadata writer if exists
-            if let Some(mut writer) = self.damf_metadata_file_writer.take() {
-                writer.flush()?;
-            }
+            // Close the metadata of the finished segment
+            self.metadata.finish()?;
+
+            // The files of the finished segment are complete
+            self.partial_outputs.commit()?;
 
             // Create new file paths with segment index
             self.segment_index += 1;
@@ -860,46 +1878,93 @@ impl DecodeHandler {
 
             log::info!("Creating output file: {}", new_audio_path.display());
 
-            // Calculate effective channel count for bed conformance
-            let effective_channel_count = if bed_conform && self.has_atmos {
+            self.update_remapper(channel_count, channel_labels)?;
+
+            // Calculate effective channel count for remapping or bed conformance
+            self.bed_conform_pending = bed_conform && !self.has_atmos_metadata;
+            let effective_channel_count = if let Some(remapper) = &self.remapper {
+                remapper.output_labels().len()
+            } else if bed_conform && self.has_atmos_metadata {
                 let empty_vec = Vec::new();
                 let bed_indices = self.bed_indices.as_ref().unwrap_or(&empty_vec);
                 ChannelCountCalculator::calculate_conformed_channel_count(
                     channel_count,
                     bed_indices,
+                    self.bed_conform_layout.speakers(),
                 )
             } else {
                 channel_count
             };
+            let channel_labels = match &self.remapper {
+                Some(remapper) => remapper.output_labels().to_vec(),
+                None => self.output_channel_labels(channel_labels, bed_conform),
+            };
 
             // Create new audio writer based on format
-            let audio_writer = match format {
-                AudioFormat::Pcm => AudioWriter::create_pcm(new_audio_path.clone())?,
-                AudioFormat::Caf => AudioWriter::create_caf(
-                    new_audio_path.clone(),
-                    sample_rate,
-                    effective_channel_count as u32,
-                )?,
-                AudioFormat::W64 => AudioWriter::create_w64(
-                    new_audio_path.clone(),
+            if self.split_channels && !self.metadata_only {
+                let names = match &self.atmos_split_names {
+                    Some(names) if self.has_atmos => names.clone(),
+                    _ => split_channel_names(&channel_labels, effective_channel_count),
+                };
+                let paths = split_channel_paths(&segmented_base_path, &names)
+                    .iter()
+                    .map(|path| self.partial_outputs.create(path))
+                    .collect();
+                self.audio_writer = Some(AudioWriter::create_split(
+                    paths,
                     sample_rate,
-                    effective_channel_count as u32,
-                )?,
-            };
-            self.audio_writer = Some(audio_writer);
-            self.current_audio_path = Some(new_audio_path);
+                    self.output_channel_descriptions(&channel_labels),
+                    self.bit_format,
+                )?);
+            } else if !self.metadata_only {
+                let partial_audio_path = self.partial_outputs.create(&new_audio_path);
+                let audio_writer = match format {
+                    AudioFormat::Pcm => {
+                        AudioWriter::create_pcm(partial_audio_path, self.bit_format)?
+                    }
+                    AudioFormat::Caf => AudioWriter::create_caf(
+                        partial_audio_path,
+                        sample_rate,
+                        effective_channel_count as u32,
+                        self.output_channel_descriptions(&channel_labels),
+                        self.bit_format,
+                    )?,
+                    AudioFormat::W64 => AudioWriter::create_w64(
+                        partial_audio_path,
+                        sample_rate,
+                        effective_channel_count as u32,
+                        &channel_labels,
+                        self.bit_format,
+                    )?,
+                    AudioFormat::Wav => AudioWriter::create_wav(
+                        partial_audio_path,
+                        sample_rate,
+                        effective_channel_count as u32,
+                        &channel_labels,
+                        self.bit_format,
+                    )?,
+                    AudioFormat::Adm => AudioWriter::create_adm(
+                        partial_audio_path,
+                        sample_rate,
+                        effective_channel_count as u32,
+                        self.bit_format,
+                    )?,
+                };
+                self.audio_writer = Some(audio_writer);
+            }
+            self.current_audio_path = Some(if self.split_channels {
+                segmented_base_path
+            } else {
+                new_audio_path
+            });
 
             // Create new metadata writer if needed - DAMF header will be written when next OAMD arrives
             if self.has_atmos && !new_metadata_path.as_os_str().is_empty() {
                 // Create the .atmos.metadata file for future OAMD data
-                let metadata_file = File::create(&new_metadata_path)?;
-                self.damf_metadata_file_writer = Some(BufWriter::new(metadata_file));
-
-                log::info!("Creating metadata file: {}", new_metadata_path.display());
+                self.metadata
+                    .create(&new_metadata_path, &mut self.partial_outputs)?;
             }
 
-            self.prev_events = Vec::new(); // Clear previous events for new segment
-
             // Reset Atmos detection state - treat this segment like a fresh decode start
             // The next OAMD data will trigger DAMF header creation for the new segment
             self.reset_atmos_state_for_segment();
@@ -927,9 +1992,9 @@ impl DecodeHandler {
         // Reset state so the next OAMD data is treated as "first-time Atmos detection"
         // This ensures DAMF header gets created for the new segment when OAMD arrives
 
-        if self.has_atmos {
-            // Temporarily reset has_atmos so next OAMD triggers DAMF header creation
-            self.has_atmos = false;
+        if self.has_atmos_metadata {
+            // Reset the metadata state so next OAMD triggers DAMF header creation
+            self.has_atmos_metadata = false;
             self.bed_indices = None; // Will be recalculated from new OAMD
 
             log::debug!(
@@ -938,3 +2003,960 @@ impl DecodeHandler {
         }
     }
 }
+
+/// Pad or truncate `decoded` to the channels the output was created with.
+///
+/// A restart header may change the channel count of the presentation without
+/// a substream info change, as at a seamless branch into a segment with more
+/// or fewer channels. Added channels are dropped and missing ones are silent
+/// so the samples stay interleaved as the output file declares.
+fn conform_channel_count(
+    decoded: &mut truehd::process::decode::DecodedAccessUnit,
+    channel_count: usize,
+    channel_labels: &[ChannelLabel],
+    au_index: u64,
+) {
+    if decoded.channel_count == channel_count {
+        return;
+    }
+    if decoded.config_changed {
+        log::warn!(
+            "Channel count changed from {channel_count} to {} at access unit {au_index}, {} to keep the output consistent",
+            decoded.channel_count,
+            if decoded.channel_count > channel_count {
+                "dropping the added channels"
+            } else {
+                "writing silence for the missing channels"
+            }
+        );
+    }
+
+    let sample_length = decoded.sample_length;
+    for sample in &mut decoded.pcm_data[..sample_length] {
+        sample[decoded.channel_count.min(channel_count)..channel_count].fill(0);
+    }
+    decoded.channel_count = channel_count;
+    decoded.channel_labels = channel_labels.to_vec();
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use std::io::{Read, SeekFrom};
+    use tempfile::TempDir;
+    use truehd::process::decode::{DecodedAccessUnit, Decoder};
+    use truehd::process::extract::Extractor;
+    use truehd::process::parse::Parser;
+    use truehd::structs::access_unit::AccessUnit;
+
+    /// Settings of the context a [`TestOutput`] writes with.
+    pub struct OutputOptions {
+        pub format: AudioFormat,
+        pub bed_conform: bool,
+        pub atmos: bool,
+        pub fail_level: Level,
+    }
+
+    impl Default for OutputOptions {
+        fn default() -> Self {
+            Self {
+                format: AudioFormat::Caf,
+                bed_conform: false,
+                atmos: false,
+                fail_level: Level::Error,
+            }
+        }
+    }
+
+    /// Decode output written to a temporary directory, removed on drop.
+    pub struct TestOutput {
+        dir: TempDir,
+        options: OutputOptions,
+        state: WriterState,
+    }
+
+    impl TestOutput {
+        pub fn new(options: OutputOptions) -> Result<Self> {
+            let state = WriterState {
+                fail_level: options.fail_level,
+                category_policy: CategoryPolicy::default(),
+                event_sink: None,
+            };
+            Ok(Self {
+                dir: TempDir::with_prefix("truehdd-")?,
+                options,
+                state,
+            })
+        }
+
+        /// Base path of the output named `name`.
+        pub fn path(&self, name: &str) -> PathBuf {
+            self.dir.path().join(name)
+        }
+
+        /// Audio path of the output named `name`.
+        pub fn audio_path(&self, name: &str, atmos: bool) -> PathBuf {
+            create_output_paths(&self.path(name), self.options.format, atmos).0
+        }
+
+        /// Run `f` with a context writing the output named `name`.
+        pub fn with_context<T>(
+            &self,
+            name: &str,
+            f: impl FnOnce(&FrameHandlerContext) -> Result<T>,
+        ) -> Result<T> {
+            let base_path = Some(self.path(name));
+            f(&FrameHandlerContext {
+                base_path: &base_path,
+                metadata_path: &base_path,
+                format: self.options.format,
+                pb: &None,
+                state: &self.state,
+                start_time: std::time::Instant::now(),
+                bed_conform: self.options.bed_conform,
+                warp_mode: None,
+                fps: None,
+                timestamp: None,
+                atmos: self.options.atmos,
+                metadata_update_rate: None,
+                metrics: None,
+            })
+        }
+
+        /// Pass `frames` to `handler` until it is finished and finalize the
+        /// output named `name`.
+        pub fn write(
+            &self,
+            name: &str,
+            handler: &mut DecodeHandler,
+            frames: impl IntoIterator<Item = DecodedAccessUnit>,
+        ) -> Result<()> {
+            self.with_context(name, |ctx| {
+                for frame in frames {
+                    handler.handle_decoded_frame(frame, ctx)?;
+                    if handler.finished() {
+                        break;
+                    }
+                }
+                handler.finalize()
+            })
+        }
+
+        /// Decode `presentation` of `stream` to the output named `name`.
+        pub fn decode(
+            &self,
+            name: &str,
+            handler: &mut DecodeHandler,
+            stream: &[u8],
+            presentation: usize,
+        ) -> Result<()> {
+            let access_units = parse_stream(stream)?;
+            self.write(
+                name,
+                handler,
+                decode_access_units(&access_units, presentation)?,
+            )
+        }
+    }
+
+    /// Extract and parse the access units of `stream`.
+    pub fn parse_stream(stream: &[u8]) -> Result<Vec<AccessUnit>> {
+        let mut extractor = Extractor::default();
+        let mut parser = Parser::default();
+        extractor.push_bytes(stream);
+        extractor
+            .filter_map(Result::ok)
+            .map(|frame| parser.parse(&frame))
+            .collect()
+    }
+
+    /// Decode `presentation` of `access_units` with a new decoder.
+    pub fn decode_access_units(
+        access_units: &[AccessUnit],
+        presentation: usize,
+    ) -> Result<Vec<DecodedAccessUnit>> {
+        let mut decoder = Decoder::default();
+        access_units
+            .iter()
+            .map(|access_unit| decoder.decode_presentation(access_unit, presentation))
+            .collect()
+    }
+
+    /// A silent 48 kHz access unit of 40 samples at position `index`.
+    pub fn silent_frame(index: usize, channel_labels: Vec<ChannelLabel>) -> DecodedAccessUnit {
+        DecodedAccessUnit {
+            sampling_frequency: 48000,
+            sample_length: 40,
+            channel_count: channel_labels.len(),
+            pcm_data: [[0; 16]; 160].into(),
+            bit_depth: 24,
+            channel_labels,
+            oamd: Vec::new(),
+            evo_payloads: Vec::new(),
+            is_duplicate: false,
+            substream_info_changed: false,
+            config_changed: false,
+            stream_start: false,
+            silence_samples: 0,
+            au_index: index as u64,
+            sample_position: index as u64 * 40,
+            discontinuity: None,
+            heavy_drc_gain: None,
+            dialogue_level: None,
+            other_presentations: Vec::new(),
+        }
+    }
+
+    /// The header and audio data of a CAF file.
+    fn read_caf(path: &Path) -> Result<(crate::caf::CAFFileInfo, Vec<u8>)> {
+        let mut file = File::open(path)?;
+        let file_info = crate::caf::parse_caf_file(&mut file)?;
+        file.seek(SeekFrom::Start(file_info.data_chunk_start))?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok((file_info, data))
+    }
+
+    /// Samples of raw little-endian 24-bit PCM.
+    fn pcm_samples(data: &[u8]) -> Vec<i32> {
+        data.chunks_exact(3)
+            .map(|bytes| i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8)
+            .collect()
+    }
+
+    #[test]
+    fn atmos_channel_descriptions() {
+        use truehd::structs::oamd::TEST_DATA_TRIM;
+
+        let oamd = ObjectAudioMetadataPayload::read(TEST_DATA_TRIM).unwrap();
+        let positions = oamd.get_damf_pos();
+
+        let descriptions = BedChannelMapper::atmos_channel_descriptions(&oamd, None);
+        assert_eq!(descriptions.len(), oamd.object_count);
+        assert_eq!(descriptions[0].channel_label, CafChannelLabel::LFEScreen);
+        for (description, position) in descriptions[1..].iter().zip(&positions[1..]) {
+            assert_eq!(description.channel_label, CafChannelLabel::UseCoordinates);
+            assert_eq!(
+                description.channel_flags,
+                ChannelDescription::RECTANGULAR_COORDINATES
+            );
+            assert_eq!(description.coordinates, position[0].map(|c| c as f32));
+        }
+
+        // The conformed bed precedes the objects
+        let layout = BedConformLayout::Bed7_1_4.speakers();
+        let descriptions = BedChannelMapper::atmos_channel_descriptions(&oamd, Some(layout));
+        assert_eq!(descriptions.len(), 12 + oamd.object_count - 1);
+        assert_eq!(descriptions[0].channel_label, CafChannelLabel::Left);
+        assert_eq!(
+            descriptions[9].channel_label,
+            CafChannelLabel::VerticalHeightRight
+        );
+        assert_eq!(
+            descriptions[12].channel_label,
+            CafChannelLabel::UseCoordinates
+        );
+    }
+
+    #[test]
+    fn atmos_split_channel_names() {
+        use truehd::structs::oamd::TEST_DATA_TRIM;
+
+        let oamd = ObjectAudioMetadataPayload::read(TEST_DATA_TRIM).unwrap();
+
+        // The LFE bed is followed by objects numbered from 10 as in the DAMF metadata
+        let names = BedChannelMapper::atmos_split_channel_names(&oamd, None);
+        assert_eq!(names.len(), oamd.object_count);
+        assert_eq!(names[0], "LFE");
+        assert_eq!(names[1], "obj10");
+        assert_eq!(
+            names[oamd.object_count - 1],
+            format!("obj{}", oamd.object_count + 8)
+        );
+
+        let layout = BedConformLayout::Bed7_1_2.speakers();
+        let names = BedChannelMapper::atmos_split_channel_names(&oamd, Some(layout));
+        assert_eq!(names.len(), 10 + oamd.object_count - 1);
+        assert_eq!(&names[..4], ["L", "R", "C", "LFE"]);
+        assert_eq!(&names[8..10], ["Tsl", "Tsr"]);
+        assert_eq!(names[10], "obj10");
+    }
+
+    #[test]
+    fn terminator_zero_samples_trim_output() -> Result<()> {
+        use truehd::process::EXAMPLE_DATA;
+        use truehd::structs::substream::Terminator;
+        use truehd::utils::bitstream_io::BitstreamWriter;
+
+        const ZERO_SAMPLES: u16 = 15;
+
+        // Re-serialize the stream with a terminator in the final access unit
+        let mut access_units = parse_stream(&EXAMPLE_DATA.repeat(2))?;
+        access_units.last_mut().unwrap().substream_segment[0].terminator = Some(Terminator {
+            terminator_a: 0x348D3,
+            zero_samples_indicated: true,
+            zero_samples: ZERO_SAMPLES,
+            terminator_b: 0,
+        });
+        let mut writer = BitstreamWriter::new();
+        let mut stream = Vec::new();
+        for access_unit in &access_units {
+            stream.extend(access_unit.write(&mut writer)?);
+        }
+
+        // The terminator trims the stream whichever presentation is requested
+        let output = TestOutput::new(OutputOptions::default())?;
+        let mut handler = DecodeHandler::default();
+        output.decode("out", &mut handler, &stream, 3)?;
+
+        let expected = access_units.len() as u64 * 40 - ZERO_SAMPLES as u64;
+        assert_eq!(handler.decoded_samples, expected);
+
+        let (file_info, data) = read_caf(&output.audio_path("out", false))?;
+        let channel_count = file_info.audio_format.unwrap().channels_per_frame as u64;
+        assert_eq!(data.len() as u64, expected * channel_count * 3);
+        Ok(())
+    }
+
+    #[test]
+    fn excerpt_trims_and_truncates_output() -> Result<()> {
+        use truehd::process::EXAMPLE_DATA;
+
+        let output = TestOutput::new(OutputOptions::default())?;
+        let mut handler = DecodeHandler {
+            trim_samples: 10,
+            excerpt_start: Some(0.001),
+            excerpt_duration: Some(0.002),
+            ..Default::default()
+        };
+        output.decode("out", &mut handler, &EXAMPLE_DATA.repeat(4), 1)?;
+
+        // The excerpt follows the start trim and ends within the stream
+        let sample_rate = handler.final_sample_rate;
+        let expected = samples_at(0.002, sample_rate);
+        assert_eq!(handler.trim_samples, 10 + samples_at(0.001, sample_rate));
+        assert!(handler.finished() && handler.decoded_samples < 320);
+        assert_eq!(handler.output_samples(), expected);
+
+        let (file_info, data) = read_caf(&output.audio_path("out", false))?;
+        let channel_count = file_info.audio_format.unwrap().channels_per_frame as u64;
+        assert_eq!(data.len() as u64, expected * channel_count * 3);
+        Ok(())
+    }
+
+    fn conform_bed(speakers: &[SpeakerLabels], layout: BedConformLayout) -> Vec<i32> {
+        // One sample of distinct levels per bed channel, followed by an object
+        let bed_indices: Vec<usize> = speakers.iter().map(|&speaker| speaker as usize).collect();
+        let mut samples: Vec<i32> = (1..=bed_indices.len() as i32).map(|i| i * 1000).collect();
+        samples.push(-1);
+
+        let conformed = BedChannelMapper::apply_bed_conformance(
+            samples,
+            bed_indices.len() + 1,
+            &bed_indices,
+            layout.speakers(),
+            24,
+        );
+        assert_eq!(conformed.len(), layout.speakers().len() + 1);
+        assert_eq!(conformed.last(), Some(&-1));
+        conformed[..conformed.len() - 1].to_vec()
+    }
+
+    #[test]
+    fn bed_conform_to_5_1() {
+        use SpeakerLabels::*;
+
+        // 7.1.2: the rear surrounds and top surrounds fold into the side surrounds
+        let bed = conform_bed(
+            &[L, R, C, LFE, Lss, Rss, Lrs, Rrs, Lts, Rts],
+            BedConformLayout::Bed5_1,
+        );
+        let fold = |a: f64, b: f64, c: f64| (a + (b + c) * BED_FOLD_GAIN).round() as i32;
+        assert_eq!(
+            bed,
+            [
+                1000,
+                2000,
+                3000,
+                4000,
+                fold(5000.0, 7000.0, 9000.0),
+                fold(6000.0, 8000.0, 10000.0)
+            ]
+        );
+
+        // Missing channels are silent
+        assert_eq!(
+            conform_bed(&[L, R], BedConformLayout::Bed5_1),
+            [1000, 2000, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn bed_conform_to_5_1_2() {
+        use SpeakerLabels::*;
+
+        // 5.1.4: the front heights fold into the top surrounds, the rear
+        // heights follow them
+        let bed = conform_bed(
+            &[L, R, C, LFE, Lss, Rss, Lfh, Rfh, Lrh, Rrh],
+            BedConformLayout::Bed5_1_2,
+        );
+        let fold = |a: f64, b: f64| ((a + b) * BED_FOLD_GAIN).round() as i32;
+        assert_eq!(
+            bed,
+            [
+                1000,
+                2000,
+                3000,
+                4000,
+                5000,
+                6000,
+                fold(7000.0, 9000.0),
+                fold(8000.0, 10000.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn bed_conform_to_7_1_2() {
+        use SpeakerLabels::*;
+
+        // The source order does not matter for channels present in the layout
+        let bed = conform_bed(
+            &[L, R, C, LFE, Lss, Rss, Lts, Rts],
+            BedConformLayout::Bed7_1_2,
+        );
+        assert_eq!(bed, [1000, 2000, 3000, 4000, 5000, 6000, 0, 0, 7000, 8000]);
+
+        // Wides fold into the fronts and the second LFE into the LFE
+        let bed = conform_bed(&[L, R, LFE, Lw, Rw, LFE2], BedConformLayout::Bed7_1_2);
+        let fold = |a: f64, b: f64| (a + b * BED_FOLD_GAIN).round() as i32;
+        assert_eq!(
+            bed,
+            [
+                fold(1000.0, 4000.0),
+                fold(2000.0, 5000.0),
+                0,
+                fold(3000.0, 6000.0),
+                0,
+                0,
+                0,
+                0,
+                0,
+                0
+            ]
+        );
+    }
+
+    #[test]
+    fn bed_conform_to_7_1_4() {
+        use SpeakerLabels::*;
+
+        // 7.1.2: the top surrounds fold into the front heights
+        let bed = conform_bed(
+            &[L, R, C, LFE, Lss, Rss, Lrs, Rrs, Lts, Rts],
+            BedConformLayout::Bed7_1_4,
+        );
+        let fold = |a: f64| (a * BED_FOLD_GAIN).round() as i32;
+        assert_eq!(
+            bed,
+            [
+                1000,
+                2000,
+                3000,
+                4000,
+                5000,
+                6000,
+                7000,
+                8000,
+                fold(9000.0),
+                fold(10000.0),
+                0,
+                0
+            ]
+        );
+
+        // Folded channels are clamped to the range of the bit depth
+        let bed_indices = [SpeakerLabels::L as usize, SpeakerLabels::Lw as usize];
+        let layout = BedConformLayout::Bed7_1_4.speakers();
+        for bit_depth in [24, 32] {
+            let max = sample_range(bit_depth).1 as i32;
+            let conformed = BedChannelMapper::apply_bed_conformance(
+                vec![max; 2],
+                2,
+                &bed_indices,
+                layout,
+                bit_depth,
+            );
+            assert_eq!(conformed[0], max);
+        }
+    }
+
+    #[test]
+    fn bed_conform_to_7_1() {
+        use SpeakerLabels::*;
+
+        // 5.1.2: the top surrounds fold into the side surrounds, the rear
+        // surrounds are silent
+        let bed = conform_bed(
+            &[L, R, C, LFE, Lss, Rss, Lts, Rts],
+            BedConformLayout::Bed7_1,
+        );
+        let fold = |a: f64, b: f64| (a + b * BED_FOLD_GAIN).round() as i32;
+        assert_eq!(
+            bed,
+            [
+                1000,
+                2000,
+                3000,
+                4000,
+                fold(5000.0, 7000.0),
+                fold(6000.0, 8000.0),
+                0,
+                0
+            ]
+        );
+    }
+
+    #[test]
+    fn bed_conform_wides() {
+        use SpeakerLabels::*;
+
+        // 9.1.4: 7.1.4 with wides
+        let source = [L, R, C, LFE, Lss, Rss, Lrs, Rrs, Lw, Rw, Lfh, Rfh, Lrh, Rrh];
+
+        // 9.1.6 takes every channel, the top surrounds are silent
+        let bed = conform_bed(&source, BedConformLayout::Bed9_1_6);
+        assert_eq!(
+            bed,
+            [
+                1000, 2000, 3000, 4000, 5000, 6000, 7000, 8000, 9000, 10000, 11000, 12000, 0, 0,
+                13000, 14000
+            ]
+        );
+
+        // 7.1.2: the wides fold into the fronts, the heights into the top surrounds
+        let bed = conform_bed(&source, BedConformLayout::Bed7_1_2);
+        let fold = |a: f64, b: f64| (a + b * BED_FOLD_GAIN).round() as i32;
+        assert_eq!(
+            bed,
+            [
+                fold(1000.0, 9000.0),
+                fold(2000.0, 10000.0),
+                3000,
+                4000,
+                5000,
+                6000,
+                7000,
+                8000,
+                ((11000.0 + 13000.0) * BED_FOLD_GAIN).round() as i32,
+                ((12000.0 + 14000.0) * BED_FOLD_GAIN).round() as i32
+            ]
+        );
+    }
+
+    #[test]
+    fn object_gains_mute_object_halfway() -> Result<()> {
+        use truehd::structs::oamd::{GAIN_MINUS_INFINITY, TEST_DATA};
+
+        const RAMP_DURATION: u16 = 32;
+
+        // All objects at 0 dB, with the first dynamic object muted from the fifth access unit
+        let payloads = [0, GAIN_MINUS_INFINITY].map(|muted_gain| {
+            let mut oamd = ObjectAudioMetadataPayload::read(TEST_DATA).expect("valid test payload");
+            let num_beds = BedInstance::with_oamd_payload(&oamd)[0]
+                .to_index_vec()
+                .len();
+            let object_element = oamd.object_element.as_mut().unwrap();
+            object_element.md_update_info.block_update_info[0].ramp_duration = RAMP_DURATION;
+            for (i, blocks) in object_element.object_data.iter_mut().enumerate() {
+                blocks[0].b_object_not_active = false;
+                blocks[0].object_basic_info.object_gain =
+                    if i == num_beds { muted_gain } else { 0 };
+            }
+            (oamd, num_beds)
+        });
+        let (num_beds, channel_count) = (payloads[0].1, payloads[0].0.object_count);
+
+        let frames = (0..8).map(|index| {
+            let mut frame = silent_frame(index, vec![ChannelLabel::L; channel_count]);
+            for sample in &mut frame.pcm_data[..40] {
+                sample[..channel_count].fill(1000);
+            }
+            frame.oamd = vec![payloads[usize::from(index >= 4)].0.clone()];
+            frame
+        });
+
+        let output = TestOutput::new(OutputOptions {
+            bed_conform: true,
+            ..Default::default()
+        })?;
+        let mut handler = DecodeHandler {
+            apply_object_gains: true,
+            ..Default::default()
+        };
+        output.write("out", &mut handler, frames)?;
+
+        let (file_info, audio_data) = read_caf(&output.audio_path("out", true))?;
+        let samples = AudioDataConverter::convert_caf_bytes_to_samples(
+            &audio_data,
+            file_info.endianness,
+            BitFormat::Int24,
+        );
+
+        let num_conformed_beds = BedConformLayout::default().speakers().len();
+        let conformed_channel_count = num_conformed_beds + channel_count - num_beds;
+        assert_eq!(samples.len(), 320 * conformed_channel_count);
+
+        let muted_channel = num_conformed_beds;
+        for (pos, frame) in samples.chunks_exact(conformed_channel_count).enumerate() {
+            let expected = match pos.checked_sub(160) {
+                None => 1000,
+                Some(elapsed) if elapsed < RAMP_DURATION as usize => {
+                    (1000.0 * (1.0 - elapsed as f32 / RAMP_DURATION as f32)).round() as i32
+                }
+                Some(_) => 0,
+            };
+            assert_eq!(frame[muted_channel], expected, "sample {pos}");
+            // The other objects keep their gain
+            assert!(
+                frame[muted_channel + 1..]
+                    .iter()
+                    .all(|&sample| sample == 1000)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn atmos_major_sync_names_output_before_metadata() -> Result<()> {
+        use truehd::structs::oamd::TEST_DATA;
+
+        let oamd = ObjectAudioMetadataPayload::read(TEST_DATA).expect("valid test payload");
+
+        // The first OAMD payload follows in the third access unit
+        let frame = |index: usize| {
+            let mut frame = silent_frame(index, vec![ChannelLabel::L; oamd.object_count]);
+            if index >= 2 {
+                frame.oamd = vec![oamd.clone()];
+            }
+            frame
+        };
+
+        let decode = |output: &TestOutput| -> Result<(PathBuf, PathBuf)> {
+            let mut handler = DecodeHandler::default();
+            let first_path = output.with_context("out", |ctx| {
+                handler.handle_decoded_frame(frame(0), ctx)?;
+                Ok(handler.current_audio_path.clone().unwrap())
+            })?;
+            output.write("out", &mut handler, (1..8).map(frame))?;
+            Ok((first_path, handler.current_audio_path.clone().unwrap()))
+        };
+
+        // Signalled by the major sync, the Atmos file is never renamed
+        let output = TestOutput::new(OutputOptions {
+            atmos: true,
+            ..Default::default()
+        })?;
+        let atmos_audio = output.audio_path("out", true);
+        assert_eq!(decode(&output)?, (atmos_audio.clone(), atmos_audio.clone()));
+        assert!(atmos_audio.exists());
+
+        // Otherwise it is renamed at the first metadata
+        let output = TestOutput::new(OutputOptions::default())?;
+        let (plain_audio, atmos_audio) = (
+            output.audio_path("out", false),
+            output.audio_path("out", true),
+        );
+        assert_eq!(decode(&output)?, (plain_audio.clone(), atmos_audio.clone()));
+        assert!(atmos_audio.exists() && !plain_audio.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn channel_count_change_keeps_output_channels() -> Result<()> {
+        // Sample value channel + 1 in every channel present
+        let frame = |channel_count: usize, config_changed: bool| {
+            let mut frame = silent_frame(
+                0,
+                (0..channel_count as u8)
+                    .map(ChannelLabel::Generic)
+                    .collect(),
+            );
+            for sample in &mut frame.pcm_data[..40] {
+                for (channel, value) in sample[..channel_count].iter_mut().enumerate() {
+                    *value = channel as i32 + 1;
+                }
+            }
+            frame.config_changed = config_changed;
+            frame
+        };
+
+        // Presentation 3 branching between 14 and 16 channels
+        let output = TestOutput::new(OutputOptions::default())?;
+        for (name, before, after) in [("grow", 14, 16), ("shrink", 16, 14)] {
+            let frames = (0..4).map(|index| {
+                let channel_count = if index < 2 { before } else { after };
+                frame(channel_count, index == 2)
+            });
+            let mut handler = DecodeHandler::default();
+            output.write(name, &mut handler, frames)?;
+
+            let (file_info, data) = read_caf(&output.audio_path(name, false))?;
+            assert_eq!(
+                file_info.audio_format.unwrap().channels_per_frame,
+                before as u32
+            );
+            let samples = data
+                .chunks_exact(3)
+                .map(|bytes| i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0]) >> 8)
+                .collect::<Vec<_>>();
+            assert_eq!(samples.len(), 160 * before);
+            for (index, sample) in samples.chunks_exact(before).enumerate() {
+                let channel_count = if index < 80 { before } else { after };
+                let expected = (1..=before as i32)
+                    .map(|value| {
+                        if value as usize <= channel_count {
+                            value
+                        } else {
+                            0
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                assert_eq!(sample, expected);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn concatenated_inputs_report_junctions() -> Result<()> {
+        use truehd::process::EXAMPLE_DATA;
+
+        let mut extractor = Extractor::default();
+        let mut parser = Parser::default();
+        let mut decoder = Decoder::default();
+        parser.set_fail_level(Level::Warn);
+        decoder.set_fail_level(Level::Warn);
+
+        let mut frames = Vec::new();
+        for input in 0..3 {
+            if input > 0 {
+                extractor.push_stream_boundary()?;
+            }
+            extractor.push_bytes(EXAMPLE_DATA);
+            for frame in extractor.by_ref().filter_map(Result::ok) {
+                let access_unit = parser.parse(&frame)?;
+                frames.push(decoder.decode_presentation(&access_unit, 1)?);
+            }
+        }
+
+        let output = TestOutput::new(OutputOptions {
+            fail_level: Level::Warn,
+            ..Default::default()
+        })?;
+        let mut handler = DecodeHandler {
+            trim_samples: 10,
+            ..Default::default()
+        };
+        output.write("out", &mut handler, frames)?;
+
+        // Each input contributes two access units of 40 samples
+        assert_eq!(handler.decoded_samples, 240);
+        assert_eq!(handler.junctions, [70, 150]);
+        assert_eq!(decoder.stats().failures(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn wide_samples_written_as_int32() -> Result<()> {
+        use truehd::process::EXAMPLE_DATA;
+        use truehd::structs::restart_header::RestartSyncWord;
+
+        // Recode the example with the 0x31EC restart sync word, its residuals
+        // scaled to decode above the 24-bit range
+        let mut access_units = parse_stream(EXAMPLE_DATA)?;
+        for block in access_units
+            .iter_mut()
+            .flat_map(|access_unit| &mut access_unit.substream_segment[0].block)
+        {
+            if let Some(restart_header) = &mut block.restart_header {
+                restart_header.restart_sync_word = RestartSyncWord::C;
+            }
+            for sample in block.block_data.iter_mut().flatten() {
+                *sample <<= 16;
+            }
+        }
+
+        for format in [AudioFormat::Caf, AudioFormat::W64] {
+            let frames = decode_access_units(&access_units, 1)?;
+            let mut expected = Vec::new();
+            for decoded in &frames {
+                assert_eq!(decoded.bit_depth, 32);
+                for sample in &decoded.pcm_data[..decoded.sample_length] {
+                    expected.extend_from_slice(&sample[..decoded.channel_count]);
+                }
+            }
+            assert!(
+                expected
+                    .iter()
+                    .any(|sample| sample.unsigned_abs() > 1 << 23)
+            );
+
+            let output = TestOutput::new(OutputOptions {
+                format,
+                ..Default::default()
+            })?;
+            let mut handler = DecodeHandler::default();
+            output.write("out", &mut handler, frames)?;
+            assert_eq!(handler.bit_format, BitFormat::Int32);
+
+            let audio_path = output.audio_path("out", false);
+            let data = if format == AudioFormat::Caf {
+                let (file_info, data) = read_caf(&audio_path)?;
+                let audio_format = file_info.audio_format.unwrap();
+                assert_eq!(audio_format.bits_per_channel, 32);
+                assert_eq!(audio_format.bytes_per_packet, 8);
+                data.chunks_exact(4)
+                    .map(|bytes| i32::from_be_bytes(bytes.try_into().unwrap()))
+                    .collect::<Vec<_>>()
+            } else {
+                let mut file = File::open(&audio_path)?;
+                let file_info = crate::wav::parse_w64_file(&mut file)?;
+                assert_eq!(file_info.bits_per_sample, 32);
+                file.seek(SeekFrom::Start(file_info.data_chunk_start))?;
+                let mut data = vec![0; file_info.data_size.unwrap() as usize];
+                file.read_exact(&mut data)?;
+                data.chunks_exact(4)
+                    .map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(data, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn dialnorm_scales_output() -> Result<()> {
+        use truehd::process::EXAMPLE_DATA;
+
+        // -27 dBFS dialogue in the first major sync, -31 dBFS in the second
+        let mut access_units = parse_stream(&EXAMPLE_DATA.repeat(2))?;
+        for (index, dialogue_norm) in [(0, 27), (2, 31)] {
+            let major_sync_info = access_units[index].major_sync_info.as_mut().unwrap();
+            major_sync_info.channel_meaning.twoch_dialogue_norm = dialogue_norm;
+        }
+        for block in access_units
+            .iter_mut()
+            .flat_map(|access_unit| &mut access_unit.substream_segment[0].block)
+        {
+            for sample in block.block_data.iter_mut().flatten() {
+                *sample <<= 8;
+            }
+        }
+
+        let gain = dialnorm_gain(-27);
+        assert!((gain - 10f64.powf(-4.0 / 20.0)).abs() < 1e-12);
+
+        let frames = decode_access_units(&access_units, 0)?;
+        let mut expected = Vec::new();
+        for (index, decoded) in frames.iter().enumerate() {
+            let gain = if index < 2 { gain } else { 1.0 };
+            for sample in &decoded.pcm_data[..decoded.sample_length] {
+                expected.extend(
+                    sample[..decoded.channel_count]
+                        .iter()
+                        .map(|&sample| (f64::from(sample) * gain).round() as i32),
+                );
+            }
+        }
+        assert!(expected.iter().any(|&sample| sample.unsigned_abs() > 1000));
+
+        let output = TestOutput::new(OutputOptions {
+            format: AudioFormat::Pcm,
+            ..Default::default()
+        })?;
+        let mut handler = DecodeHandler {
+            apply_dialnorm: true,
+            ..Default::default()
+        };
+        output.write("out", &mut handler, frames)?;
+        assert_eq!(handler.dialogue_level, Some(-31));
+
+        let data = std::fs::read(output.audio_path("out", false))?;
+        assert_eq!(pcm_samples(&data), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn resampled_sweep_has_no_aliasing() -> Result<()> {
+        use std::f64::consts::PI;
+
+        // Linear sweep from 20 Hz to 40 kHz at 88.2 kHz, faded in and out
+        const SAMPLE_RATE: u32 = 88200;
+        const SAMPLES_PER_AU: usize = 80;
+        const ACCESS_UNITS: usize = 1100;
+        let duration = (SAMPLES_PER_AU * ACCESS_UNITS) as f64 / SAMPLE_RATE as f64;
+        let (f0, f1) = (20.0, 40000.0);
+        let amplitude = 0.9 * (1 << 23) as f64;
+        let frequency = |t: f64| f0 + (f1 - f0) * t / duration;
+        let sweep = |t: f64| {
+            let fade = (t.min(duration - t) / 0.02).clamp(0.0, 1.0);
+            let fade = 0.5 - 0.5 * (PI * fade).cos();
+            amplitude * fade * (2.0 * PI * (f0 * t + (f1 - f0) * t * t / (2.0 * duration))).sin()
+        };
+
+        let frames = (0..ACCESS_UNITS).map(|index| {
+            let mut frame = silent_frame(index, vec![ChannelLabel::C]);
+            for (i, sample) in frame.pcm_data[..SAMPLES_PER_AU].iter_mut().enumerate() {
+                let t = (index * SAMPLES_PER_AU + i) as f64 / SAMPLE_RATE as f64;
+                sample[0] = sweep(t).round() as i32;
+            }
+            frame.sampling_frequency = SAMPLE_RATE;
+            frame.sample_length = SAMPLES_PER_AU;
+            frame.sample_position = (index * SAMPLES_PER_AU) as u64;
+            frame
+        });
+
+        let output = TestOutput::new(OutputOptions {
+            format: AudioFormat::Pcm,
+            ..Default::default()
+        })?;
+        let mut handler = DecodeHandler {
+            resample_to_48k: true,
+            ..Default::default()
+        };
+        output.write("out", &mut handler, frames)?;
+
+        let samples = pcm_samples(&std::fs::read(output.audio_path("out", false))?);
+        assert_eq!(samples.len() as u64, handler.written_samples());
+        assert_eq!(samples.len(), 47892);
+
+        // Below the passband edge the output is the sweep sampled at 48 kHz, and
+        // above the output Nyquist frequency nothing is folded back
+        let (mut passband, mut stopband) = ((0.0, 0), (0.0, 0));
+        for (index, &sample) in samples.iter().enumerate() {
+            let t = index as f64 / 48000.0;
+            if frequency(t) < 20000.0 {
+                passband.0 += (sample as f64 - sweep(t)).powi(2);
+                passband.1 += 1;
+            } else if frequency(t) > 24500.0 {
+                stopband.0 += (sample as f64).powi(2);
+                stopband.1 += 1;
+            }
+        }
+        let db = |(sum, count): (f64, usize)| 10.0 * (sum / count as f64).log10();
+        let reference = db((amplitude * amplitude / 2.0, 1));
+        assert!(
+            db(passband) - reference < -120.0,
+            "passband error {:.1} dB",
+            db(passband) - reference
+        );
+        assert!(
+            db(stopband) - reference < -120.0,
+            "aliasing {:.1} dB",
+            db(stopband) - reference
+        );
+        Ok(())
+    }
+}
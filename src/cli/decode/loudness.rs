@@ -0,0 +1,533 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use truehd::process::decode::DecodedAccessUnit;
+use truehd::structs::channel::ChannelLabel;
+
+/// Full scale of 24-bit decoded samples
+const FULL_SCALE: f64 = (1 << 23) as f64;
+
+/// Gating blocks are made of 100 ms steps
+const STEPS_PER_SECOND: u32 = 10;
+/// Steps of a 400 ms momentary block
+const MOMENTARY_STEPS: usize = 4;
+/// Steps of a 3 s short-term block
+const SHORT_TERM_STEPS: usize = 30;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const INTEGRATED_RELATIVE_GATE: f64 = -10.0;
+const RANGE_RELATIVE_GATE: f64 = -20.0;
+
+/// Taps of each phase of the true peak interpolation filter
+const TRUE_PEAK_TAPS: usize = 16;
+
+/// Loudness of the decoded output, measured according to ITU-R BS.1770-4
+/// and EBU Tech 3342.
+#[derive(Debug, Serialize)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS, absent for silence
+    pub integrated: Option<f64>,
+    /// Loudness range in LU, absent for silence
+    pub range: Option<f64>,
+    pub true_peaks: Vec<ChannelPeak>,
+}
+
+/// True peak of a channel
+#[derive(Debug, Serialize)]
+pub struct ChannelPeak {
+    pub channel: String,
+    /// True peak in dBTP, absent for a silent channel
+    pub true_peak: Option<f64>,
+}
+
+/// Second order IIR filter in direct form I
+#[derive(Clone, Copy, Debug, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// K-weighting filter of BS.1770-4 for `sampling_frequency`.
+///
+/// The shelving and high-pass stages are derived from their analog
+/// prototypes, which give the coefficients of the recommendation at 48 kHz.
+fn k_weighting(sampling_frequency: u32) -> [Biquad; 2] {
+    let fs = sampling_frequency as f64;
+
+    let k = (PI * 1681.974450955533 / fs).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+
+    let k = (PI * 38.13547087602444 / fs).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+
+    [shelf, high_pass]
+}
+
+/// Channel weight of BS.1770-4, LFE channels are excluded.
+///
+/// Objects have no fixed position and are weighted as front channels.
+fn channel_weight(label: ChannelLabel) -> f64 {
+    use ChannelLabel::*;
+
+    match label {
+        LFE | LFE2 => 0.0,
+        // Ear level channels between 60 and 120 degrees azimuth
+        Ls | Rs | Lsd | Rsd | Lw | Rw => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// Polyphase interpolation filter oversampling to at least 192 kHz.
+///
+/// Phase `k` of `phases` holds the taps `h[k + j * phases]` of a Hann
+/// windowed sinc. Phase 0 reproduces the input samples and is left out.
+fn interpolation_filter(sampling_frequency: u32) -> Vec<[f64; TRUE_PEAK_TAPS]> {
+    let phases = (192_000 / sampling_frequency.max(1)).clamp(1, 4) as usize;
+    let length = (phases * TRUE_PEAK_TAPS) as f64;
+    let center = (phases * TRUE_PEAK_TAPS / 2) as f64;
+
+    (1..phases)
+        .map(|phase| {
+            std::array::from_fn(|tap| {
+                let t = (phase + tap * phases) as f64 - center;
+                let x = t / phases as f64;
+                let sinc = if t == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                sinc * (0.5 + 0.5 * (2.0 * PI * t / length).cos())
+            })
+        })
+        .collect()
+}
+
+/// Filter state of a channel
+#[derive(Debug)]
+struct ChannelState {
+    label: ChannelLabel,
+    /// Index of the channel in the decoded samples
+    index: usize,
+    weight: f64,
+    filter: [Biquad; 2],
+    /// Latest input samples for the true peak interpolation, newest first
+    history: [f64; TRUE_PEAK_TAPS],
+    /// Index of the true peak of the channel label
+    peak_index: usize,
+}
+
+/// Integrated loudness, loudness range and true peak meter.
+///
+/// The meter follows the sampling frequency and channel layout of the
+/// access units; filter state restarts when they change, while the gating
+/// blocks and the true peaks of each channel label are kept.
+#[derive(Debug, Default)]
+pub struct LoudnessMeter {
+    sampling_frequency: u32,
+    channels: Vec<ChannelState>,
+    interpolation: Vec<[f64; TRUE_PEAK_TAPS]>,
+    /// Weighted energy of the current step and its length in samples
+    step_energy: f64,
+    step_samples: usize,
+    /// Mean weighted power of the latest steps, newest last
+    steps: VecDeque<f64>,
+    momentary: Vec<f64>,
+    short_term: Vec<f64>,
+    peaks: Vec<(ChannelLabel, f64)>,
+}
+
+impl LoudnessMeter {
+    /// Measure the samples of `decoded` from sample `start` on.
+    pub fn process(&mut self, decoded: &DecodedAccessUnit, start: usize) {
+        let labels = &decoded.channel_labels[..decoded.channel_count];
+        if self.sampling_frequency != decoded.sampling_frequency
+            || !self
+                .channels
+                .iter()
+                .map(|channel| channel.label)
+                .eq(labels.iter().copied())
+        {
+            self.configure(decoded.sampling_frequency, labels);
+        }
+
+        let frames = &decoded.pcm_data[start..decoded.sample_length];
+        let full_scale = FULL_SCALE * (1 << (decoded.bit_depth - 24)) as f64;
+        self.measure_true_peak(frames, full_scale);
+        self.measure_loudness(frames, full_scale);
+    }
+
+    fn measure_true_peak(&mut self, frames: &[[i32; 16]], full_scale: f64) {
+        for channel in &mut self.channels {
+            let mut peak = self.peaks[channel.peak_index].1;
+            for frame in frames {
+                let x = frame[channel.index] as f64 / full_scale;
+                channel.history.copy_within(..TRUE_PEAK_TAPS - 1, 1);
+                channel.history[0] = x;
+
+                peak = peak.max(x.abs());
+                for taps in &self.interpolation {
+                    let mut y = 0.0;
+                    for (h, x) in taps.iter().zip(&channel.history) {
+                        y += h * x;
+                    }
+                    peak = peak.max(y.abs());
+                }
+            }
+            self.peaks[channel.peak_index].1 = peak;
+        }
+    }
+
+    fn measure_loudness(&mut self, frames: &[[i32; 16]], full_scale: f64) {
+        let step_length = (self.sampling_frequency / STEPS_PER_SECOND) as usize;
+        for frame in frames {
+            for channel in &mut self.channels {
+                if channel.weight != 0.0 {
+                    let x = frame[channel.index] as f64 / full_scale;
+                    let y = channel.filter[0].process(x);
+                    let y = channel.filter[1].process(y);
+                    self.step_energy += channel.weight * y * y;
+                }
+            }
+
+            self.step_samples += 1;
+            if self.step_samples == step_length {
+                self.finish_step();
+            }
+        }
+    }
+
+    fn configure(&mut self, sampling_frequency: u32, labels: &[ChannelLabel]) {
+        if self.sampling_frequency != sampling_frequency {
+            self.step_energy = 0.0;
+            self.step_samples = 0;
+            self.steps.clear();
+        }
+        self.sampling_frequency = sampling_frequency;
+        self.interpolation = interpolation_filter(sampling_frequency);
+
+        let filter = k_weighting(sampling_frequency);
+        self.channels = labels
+            .iter()
+            .enumerate()
+            .map(|(index, &label)| {
+                let peak_index = match self
+                    .peaks
+                    .iter()
+                    .position(|&(peak_label, _)| peak_label == label)
+                {
+                    Some(index) => index,
+                    None => {
+                        self.peaks.push((label, 0.0));
+                        self.peaks.len() - 1
+                    }
+                };
+                ChannelState {
+                    label,
+                    index,
+                    weight: channel_weight(label),
+                    filter,
+                    history: [0.0; TRUE_PEAK_TAPS],
+                    peak_index,
+                }
+            })
+            .collect();
+    }
+
+    fn finish_step(&mut self) {
+        self.steps
+            .push_back(self.step_energy / self.step_samples as f64);
+        if self.steps.len() > SHORT_TERM_STEPS {
+            self.steps.pop_front();
+        }
+        self.step_energy = 0.0;
+        self.step_samples = 0;
+
+        let mean = |steps: usize| self.steps.iter().rev().take(steps).sum::<f64>() / steps as f64;
+        if self.steps.len() >= MOMENTARY_STEPS {
+            self.momentary.push(mean(MOMENTARY_STEPS));
+        }
+        if self.steps.len() == SHORT_TERM_STEPS {
+            self.short_term.push(mean(SHORT_TERM_STEPS));
+        }
+    }
+
+    /// Loudness of the samples measured so far.
+    ///
+    /// A partial final gating block is not measured.
+    pub fn result(&self) -> Loudness {
+        Loudness {
+            integrated: self.integrated(),
+            range: self.range(),
+            true_peaks: self
+                .peaks
+                .iter()
+                .map(|&(label, peak)| ChannelPeak {
+                    channel: label.to_string(),
+                    true_peak: (peak > 0.0).then(|| 20.0 * peak.log10()),
+                })
+                .collect(),
+        }
+    }
+
+    /// Integrated loudness of the momentary blocks above the absolute gate
+    /// and 10 LU below their loudness
+    fn integrated(&self) -> Option<f64> {
+        let gated = gate(&self.momentary, ABSOLUTE_GATE);
+        let threshold = loudness(mean_power(&gated)?) + INTEGRATED_RELATIVE_GATE;
+        Some(loudness(mean_power(&gate(&gated, threshold))?))
+    }
+
+    /// Spread between the 10th and 95th percentile of the short-term
+    /// loudness above the absolute gate and 20 LU below their loudness
+    fn range(&self) -> Option<f64> {
+        let gated = gate(&self.short_term, ABSOLUTE_GATE);
+        let threshold = loudness(mean_power(&gated)?) + RANGE_RELATIVE_GATE;
+
+        let mut values: Vec<f64> = gate(&gated, threshold).into_iter().map(loudness).collect();
+        values.sort_by(f64::total_cmp);
+        let percentile = |p: f64| values[((values.len() - 1) as f64 * p).round() as usize];
+        Some(percentile(0.95) - percentile(0.10))
+    }
+}
+
+/// Loudness in LUFS of a mean weighted power
+fn loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+fn gate(powers: &[f64], threshold: f64) -> Vec<f64> {
+    powers
+        .iter()
+        .copied()
+        .filter(|&power| loudness(power) > threshold)
+        .collect()
+}
+
+fn mean_power(powers: &[f64]) -> Option<f64> {
+    (!powers.is_empty()).then(|| powers.iter().sum::<f64>() / powers.len() as f64)
+}
+
+/// Durations in seconds and levels in dBFS of the channels of a test signal
+#[cfg(test)]
+type Segments<'a> = &'a [(f64, &'a [f64])];
+
+/// Integrated loudness and loudness range of stereo or multichannel 1 kHz
+/// sines at the given levels in dBFS for the given durations in seconds
+#[cfg(test)]
+fn measure_sine(
+    sampling_frequency: u32,
+    labels: &[ChannelLabel],
+    segments: Segments,
+) -> (Option<f64>, Option<f64>) {
+    let mut meter = LoudnessMeter::default();
+    meter.configure(sampling_frequency, labels);
+
+    let mut position = 0usize;
+    let mut frames = [[0; 16]; 160];
+    for &(seconds, levels) in segments {
+        let amplitudes: Vec<f64> = levels
+            .iter()
+            .map(|level| 10f64.powf(level / 20.0) * FULL_SCALE)
+            .collect();
+        let mut remaining = (seconds * sampling_frequency as f64).round() as usize;
+        while remaining > 0 {
+            let sample_length = remaining.min(frames.len());
+            for (index, frame) in frames[..sample_length].iter_mut().enumerate() {
+                let phase =
+                    2.0 * PI * 1000.0 * (position + index) as f64 / sampling_frequency as f64;
+                let sine = phase.sin();
+                for (sample, amplitude) in frame.iter_mut().zip(&amplitudes) {
+                    *sample = (amplitude * sine).round() as i32;
+                }
+            }
+
+            meter.measure_loudness(&frames[..sample_length], FULL_SCALE);
+            position += sample_length;
+            remaining -= sample_length;
+        }
+    }
+    (meter.integrated(), meter.range())
+}
+
+#[test]
+fn integrated_loudness_matches_tech_3341() {
+    use ChannelLabel::*;
+
+    // EBU Tech 3341 test cases 1 to 6, 1 kHz sines at the given dBFS
+    let stereo = [L, R];
+    let cases: [(&[ChannelLabel], Segments, f64); 6] = [
+        (&stereo, &[(20.0, &[-23.0, -23.0])], -23.0),
+        (&stereo, &[(20.0, &[-33.0, -33.0])], -33.0),
+        (
+            &stereo,
+            &[
+                (10.0, &[-36.0, -36.0]),
+                (60.0, &[-23.0, -23.0]),
+                (10.0, &[-36.0, -36.0]),
+            ],
+            -23.0,
+        ),
+        (
+            &stereo,
+            &[
+                (10.0, &[-72.0, -72.0]),
+                (10.0, &[-36.0, -36.0]),
+                (60.0, &[-23.0, -23.0]),
+                (10.0, &[-36.0, -36.0]),
+                (10.0, &[-72.0, -72.0]),
+            ],
+            -23.0,
+        ),
+        (
+            &stereo,
+            &[
+                (20.1, &[-26.0, -26.0]),
+                (20.1, &[-20.0, -20.0]),
+                (20.1, &[-26.0, -26.0]),
+            ],
+            -23.0,
+        ),
+        (
+            &[L, R, C, LFE, Ls, Rs],
+            &[(20.0, &[-28.0, -28.0, -24.0, -20.0, -30.0, -30.0])],
+            -23.0,
+        ),
+    ];
+
+    for (index, (labels, segments, expected)) in cases.into_iter().enumerate() {
+        let integrated = measure_sine(48000, labels, segments).0.unwrap();
+        assert!(
+            (integrated - expected).abs() <= 0.1,
+            "case {}: {integrated} LUFS",
+            index + 1
+        );
+    }
+
+    // The K-weighting follows the sampling frequency
+    let integrated = measure_sine(44100, &stereo, &[(20.0, &[-23.0, -23.0])])
+        .0
+        .unwrap();
+    assert!((integrated + 23.0).abs() <= 0.1, "{integrated} LUFS");
+
+    assert_eq!(
+        measure_sine(48000, &stereo, &[(5.0, &[-200.0, -200.0])]),
+        (None, None)
+    );
+}
+
+#[test]
+fn loudness_range_matches_tech_3342() {
+    use ChannelLabel::*;
+
+    // EBU Tech 3342 test cases 1 to 4, stereo 1 kHz sines at the given dBFS
+    let cases: [(Segments, f64); 4] = [
+        (&[(20.0, &[-20.0; 2]), (20.0, &[-30.0; 2])], 10.0),
+        (&[(20.0, &[-20.0; 2]), (20.0, &[-15.0; 2])], 5.0),
+        (&[(20.0, &[-40.0; 2]), (20.0, &[-20.0; 2])], 20.0),
+        (
+            &[
+                (20.0, &[-50.0; 2]),
+                (20.0, &[-35.0; 2]),
+                (20.0, &[-20.0; 2]),
+                (20.0, &[-35.0; 2]),
+                (20.0, &[-50.0; 2]),
+            ],
+            15.0,
+        ),
+    ];
+
+    for (index, (segments, expected)) in cases.into_iter().enumerate() {
+        let range = measure_sine(48000, &[L, R], segments).1.unwrap();
+        assert!(
+            (range - expected).abs() <= 1.0,
+            "case {}: {range} LU",
+            index + 1
+        );
+    }
+}
+
+#[test]
+fn true_peak_matches_tech_3341() {
+    use truehd::utils::buffer_pool::PooledPcm;
+
+    // EBU Tech 3341 test cases 15 to 18, -6 dBTP sines between the samples,
+    // faded in to avoid the overshoot of an abrupt start
+    let cases: [(f64, f64); 4] = [(4.0, 0.0), (4.0, 45.0), (6.0, 60.0), (8.0, 67.5)];
+
+    for (index, (divisor, phase)) in cases.into_iter().enumerate() {
+        let mut meter = LoudnessMeter::default();
+        for au in 0..100 {
+            let mut pcm_data = PooledPcm::default();
+            for (sample_index, frame) in pcm_data[..40].iter_mut().enumerate() {
+                let position = (au * 40 + sample_index) as f64;
+                let angle = 2.0 * PI * position / divisor + phase.to_radians();
+                let fade = 0.5 - 0.5 * (PI * (position / 960.0).min(1.0)).cos();
+                frame[0] = (0.5 * fade * angle.sin() * FULL_SCALE).round() as i32;
+            }
+            meter.process(
+                &DecodedAccessUnit {
+                    sampling_frequency: 48000,
+                    sample_length: 40,
+                    channel_count: 2,
+                    pcm_data,
+                    bit_depth: 24,
+                    channel_labels: vec![ChannelLabel::C, ChannelLabel::LFE],
+                    oamd: Vec::new(),
+                    evo_payloads: Vec::new(),
+                    is_duplicate: false,
+                    substream_info_changed: false,
+                    config_changed: false,
+                    stream_start: false,
+                    silence_samples: 0,
+                    au_index: 0,
+                    sample_position: 0,
+                    discontinuity: None,
+                    heavy_drc_gain: None,
+                    dialogue_level: None,
+                    other_presentations: Vec::new(),
+                },
+                0,
+            );
+        }
+
+        let true_peaks = meter.result().true_peaks;
+        assert_eq!(true_peaks[1].true_peak, None);
+        let true_peak = true_peaks[0].true_peak.unwrap();
+        assert!(
+            (-6.4..=-5.8).contains(&true_peak),
+            "case {}: {true_peak} dBTP",
+            index + 15
+        );
+    }
+}
@@ -1,10 +1,20 @@
+pub mod activity;
 pub mod atmos;
 mod decode_impl;
 pub mod decoder_thread;
+pub mod drc;
 pub mod handler;
+pub mod loudness;
+pub mod oamd_dump;
+pub mod object_gain;
 pub mod output;
-pub mod processor;
+pub mod partial;
 pub mod progress;
+pub mod remap;
+pub mod resume;
+pub mod sync;
+pub mod trajectory;
+pub mod trim;
 
 // Re-export the main decode function
 pub use decode_impl::cmd_decode;
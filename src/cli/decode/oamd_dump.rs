@@ -0,0 +1,144 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use truehd::process::decode::DecodedAccessUnit;
+use truehd::process::metadata::OAMD_PAYLOAD_ID;
+
+/// Writes the raw OAMD payloads of a stream to a directory.
+///
+/// Each payload is written to its own file named after the index of its
+/// access unit and its sample offset within it, and listed in `index.csv`
+/// with its stream sample position, size and object count.
+pub struct OamdDump {
+    dir: PathBuf,
+    index: BufWriter<File>,
+}
+
+impl OamdDump {
+    /// Create `dir` if needed and start its index file.
+    pub fn create(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create OAMD dump directory {}", dir.display()))?;
+
+        let index_path = dir.join("index.csv");
+        let mut index = BufWriter::new(
+            File::create(&index_path)
+                .with_context(|| format!("Failed to create {}", index_path.display()))?,
+        );
+        writeln!(index, "au,sample_pos,byte_len,object_count")?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            index,
+        })
+    }
+
+    /// Write the OAMD payloads of `decoded`, access unit `au_index` starting
+    /// at stream position `sample_pos`.
+    ///
+    /// The raw payloads are taken from the EVO payloads kept by the decoder,
+    /// which are in the same order as the parsed ones.
+    pub fn write(
+        &mut self,
+        decoded: &DecodedAccessUnit,
+        au_index: u64,
+        sample_pos: u64,
+    ) -> Result<()> {
+        let payloads = decoded
+            .evo_payloads
+            .iter()
+            .filter(|payload| payload.id == OAMD_PAYLOAD_ID);
+
+        for (oamd, payload) in decoded.oamd.iter().zip(payloads) {
+            let path = self
+                .dir
+                .join(format!("{au_index:08}_{}.oamd", payload.sample_offset));
+            std::fs::write(&path, &payload.data)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+
+            writeln!(
+                self.index,
+                "{au_index},{},{},{}",
+                sample_pos + payload.sample_offset,
+                payload.data.len(),
+                oamd.object_count
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        self.index.flush()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn oamd_payloads_dumped_with_index() -> Result<()> {
+    use truehd::process::metadata::RawEvoPayload;
+    use truehd::structs::oamd::{ObjectAudioMetadataPayload, TEST_DATA};
+    use truehd::utils::buffer_pool::PooledPcm;
+
+    let temp_dir = tempfile::TempDir::with_prefix("truehdd-")?;
+    let dir = temp_dir.path();
+    let mut dump = OamdDump::create(dir)?;
+
+    let mut oamd = ObjectAudioMetadataPayload::read(TEST_DATA)?;
+    oamd.evo_sample_offset = 8;
+    let object_count = oamd.object_count;
+    let access_unit = |oamd, evo_payloads| DecodedAccessUnit {
+        sampling_frequency: 48000,
+        sample_length: 40,
+        channel_count: 0,
+        pcm_data: PooledPcm::default(),
+        bit_depth: 24,
+        channel_labels: Vec::new(),
+        oamd,
+        evo_payloads,
+        is_duplicate: false,
+        substream_info_changed: false,
+        config_changed: false,
+        stream_start: false,
+        silence_samples: 0,
+        au_index: 0,
+        sample_position: 0,
+        discontinuity: None,
+        heavy_drc_gain: None,
+        dialogue_level: None,
+        other_presentations: Vec::new(),
+    };
+
+    let decoded = access_unit(
+        vec![oamd],
+        vec![
+            RawEvoPayload {
+                id: 1,
+                sample_offset: 0,
+                data: vec![0xAA],
+            },
+            RawEvoPayload {
+                id: OAMD_PAYLOAD_ID,
+                sample_offset: 8,
+                data: TEST_DATA.to_vec(),
+            },
+        ],
+    );
+
+    dump.write(&decoded, 3, 120)?;
+    dump.write(&access_unit(Vec::new(), Vec::new()), 4, 160)?;
+    dump.finish()?;
+
+    assert_eq!(std::fs::read(dir.join("00000003_8.oamd"))?, TEST_DATA);
+    assert_eq!(
+        std::fs::read_to_string(dir.join("index.csv"))?,
+        format!(
+            "au,sample_pos,byte_len,object_count\n3,128,{},{object_count}\n",
+            TEST_DATA.len()
+        )
+    );
+    assert_eq!(std::fs::read_dir(dir)?.count(), 2);
+
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use super::drc::sample_range;
+use std::collections::VecDeque;
+use truehd::structs::oamd::{GAIN_MINUS_INFINITY, ObjectAudioMetadataPayload};
+
+/// Linear gain of an object ramping from `from` to `to` over `length`
+/// samples from stream position `start`.
+#[derive(Clone, Copy, Debug)]
+struct GainRamp {
+    from: f32,
+    to: f32,
+    start: u64,
+    length: u64,
+}
+
+impl Default for GainRamp {
+    fn default() -> Self {
+        Self {
+            from: 1.0,
+            to: 1.0,
+            start: 0,
+            length: 0,
+        }
+    }
+}
+
+impl GainRamp {
+    fn gain_at(&self, sample_pos: u64) -> f32 {
+        let elapsed = sample_pos.saturating_sub(self.start);
+        if elapsed >= self.length {
+            self.to
+        } else {
+            self.from + (self.to - self.from) * elapsed as f32 / self.length as f32
+        }
+    }
+}
+
+/// Object gains of an OAMD payload taking effect at a stream position.
+#[derive(Debug)]
+struct GainUpdate {
+    sample_pos: u64,
+    ramp_duration: u64,
+    gains: Vec<f32>,
+}
+
+/// Per-object gain state of the OAMD payloads of a stream.
+///
+/// Objects start at unity gain. Each update ramps linearly from the gain
+/// reached at its sample position to the new gain over the ramp duration of
+/// the payload, so that gain changes do not cause zipper noise.
+#[derive(Debug, Default)]
+pub struct ObjectGains {
+    ramps: Vec<GainRamp>,
+    pending: VecDeque<GainUpdate>,
+}
+
+impl ObjectGains {
+    /// Queue the object gains of `oamd`, carried by the access unit starting
+    /// at stream position `sample_pos`.
+    ///
+    /// Only the first object info block is used, as for the DAMF metadata.
+    pub fn update(&mut self, oamd: &ObjectAudioMetadataPayload, sample_pos: u64) {
+        let Some(object_element) = &oamd.object_element else {
+            return;
+        };
+        let md_update_info = &object_element.md_update_info;
+
+        let gains = object_element
+            .object_data
+            .iter()
+            .map(|blocks| {
+                blocks.first().map_or(1.0, |block| {
+                    linear_gain(block.object_basic_info.object_gain)
+                })
+            })
+            .collect();
+
+        self.pending.push_back(GainUpdate {
+            sample_pos: sample_pos + md_update_info.sample_offset as u64 + oamd.evo_sample_offset,
+            ramp_duration: md_update_info
+                .block_update_info
+                .first()
+                .map_or(0, |info| info.ramp_duration as u64),
+            gains,
+        });
+    }
+
+    /// Start the ramps of the updates taking effect at or before `sample_pos`.
+    pub fn advance_to(&mut self, sample_pos: u64) {
+        while self
+            .pending
+            .front()
+            .is_some_and(|update| update.sample_pos <= sample_pos)
+        {
+            let update = self.pending.pop_front().unwrap();
+            self.ramps.resize(update.gains.len(), GainRamp::default());
+            for (ramp, &gain) in self.ramps.iter_mut().zip(&update.gains) {
+                *ramp = GainRamp {
+                    from: ramp.gain_at(update.sample_pos),
+                    to: gain,
+                    start: update.sample_pos,
+                    length: update.ramp_duration,
+                };
+            }
+        }
+    }
+
+    /// Linear gain of `object` at stream position `sample_pos`.
+    pub fn gain(&self, object: usize, sample_pos: u64) -> f32 {
+        self.ramps
+            .get(object)
+            .map_or(1.0, |ramp| ramp.gain_at(sample_pos))
+    }
+
+    /// Scale `sample` of `object` at stream position `sample_pos` by its gain.
+    ///
+    /// The scaled sample is rounded and clamped to the range of `bit_depth`
+    /// bits.
+    pub fn apply(&self, object: usize, sample: i32, sample_pos: u64, bit_depth: u32) -> i32 {
+        let gain = self.gain(object, sample_pos);
+        if gain == 1.0 {
+            return sample;
+        }
+        let (min, max) = sample_range(bit_depth);
+        (sample as f32 * gain).round().clamp(min as f32, max as f32) as i32
+    }
+}
+
+/// Linear gain of an OAMD object gain in dB.
+fn linear_gain(object_gain: i8) -> f32 {
+    match object_gain {
+        GAIN_MINUS_INFINITY => 0.0,
+        gain => 10f32.powf(gain as f32 / 20.0),
+    }
+}
+
+#[test]
+fn object_gains_ramp_between_updates() {
+    use truehd::structs::oamd::TEST_DATA;
+
+    let mut oamd = ObjectAudioMetadataPayload::read(TEST_DATA).unwrap();
+    let object_element = oamd.object_element.as_mut().unwrap();
+    object_element.md_update_info.block_update_info[0].ramp_duration = 100;
+    for blocks in &mut object_element.object_data {
+        blocks[0].object_basic_info.object_gain = -6;
+    }
+    let object_count = object_element.object_data.len();
+
+    let mut gains = ObjectGains::default();
+    gains.advance_to(0);
+    assert_eq!(gains.gain(0, 0), 1.0);
+
+    gains.update(&oamd, 1000);
+    gains.advance_to(999);
+    assert_eq!(gains.apply(0, 1000, 999, 24), 1000);
+
+    gains.advance_to(1000);
+    let target = linear_gain(-6);
+    assert_eq!(gains.gain(0, 1000), 1.0);
+    assert!((gains.gain(0, 1050) - (1.0 + target) / 2.0).abs() < 1e-6);
+    assert_eq!(gains.gain(object_count - 1, 1100), target);
+    assert_eq!(gains.apply(0, 1000, 2000, 24), 501);
+
+    // A mute halfway through the ramp starts from the gain reached so far
+    let object_element = oamd.object_element.as_mut().unwrap();
+    for blocks in &mut object_element.object_data {
+        blocks[0].object_basic_info.object_gain = GAIN_MINUS_INFINITY;
+    }
+    gains.update(&oamd, 1050);
+    gains.advance_to(1050);
+    assert!((gains.gain(0, 1050) - (1.0 + target) / 2.0).abs() < 1e-6);
+    assert_eq!(gains.gain(0, 1150), 0.0);
+    assert_eq!(gains.apply(0, -1000, 1150, 24), 0);
+}
@@ -1,11 +1,15 @@
-use crate::caf::CAFWriter;
+use crate::adm::AdmDocument;
+use crate::caf::{CAFWriter, ChannelDescription, StreamingWriter};
+use crate::riff::RiffWavWriter;
 use crate::wav::WAVWriter;
-use anyhow::Result;
+use anyhow::{Result, bail};
 use std::fs::File;
-use std::io::{BufWriter, Seek, Write};
+use std::io::{BufWriter, Seek, Stdout, Write};
 use std::path::{Path, PathBuf};
+use truehd::structs::channel::ChannelLabel;
+use truehd::utils::dither::TpdfDither;
 
-use super::super::command::AudioFormat;
+use super::super::command::{AudioFormat, BitFormat};
 
 pub fn create_path_with_suffix(base_path: &Path, suffix: &str) -> PathBuf {
     let mut path = base_path.to_path_buf();
@@ -48,12 +52,15 @@ pub fn create_output_paths(
         (AudioFormat::Caf, false) => "caf",
         (AudioFormat::Pcm, false) => "pcm",
         (AudioFormat::W64, false) => "wav",
+        (AudioFormat::Wav, false) => "wav",
+        // ADM BWF carries the Atmos metadata in the audio file
+        (AudioFormat::Adm, _) => "wav",
         (_, true) => "atmos.audio",
     };
 
     let audio_path = create_path_with_extension(base_path, audio_ext);
 
-    let metadata_path = if has_atmos {
+    let metadata_path = if has_atmos && format != AudioFormat::Adm {
         create_path_with_extension(base_path, "atmos.metadata")
     } else {
         PathBuf::new() // Empty path for non-atmos
@@ -63,47 +70,406 @@ pub fn create_output_paths(
 }
 
 pub enum AudioWriter {
-    Pcm(BufWriter<File>),
+    Pcm(BufWriter<File>, BitFormat),
     Caf(CAFWriter<BufWriter<File>>),
     W64(WAVWriter<File>),
+    Wav(RiffWavWriter<File>),
+    /// Headerless interleaved PCM written to stdout
+    Stdout(BufWriter<Stdout>, BitFormat),
+    /// CAF with a data chunk of unknown size written to stdout
+    StdoutCaf(CAFWriter<StreamingWriter<BufWriter<Stdout>>>),
+    /// One mono CAF file per channel
+    Split(SplitWriter),
+}
+
+/// Mono CAF files of the channels of a `--split-channels` output
+pub struct SplitWriter {
+    writers: Vec<CAFWriter<BufWriter<File>>>,
+    paths: Vec<PathBuf>,
+}
+
+/// File names of the channels of a split output, `<base>.<name>.caf`.
+///
+/// Repeated names, such as the beds of a second Atmos bed instance, are
+/// numbered from the second occurrence on.
+pub fn split_channel_paths(base_path: &Path, names: &[String]) -> Vec<PathBuf> {
+    let mut paths = Vec::with_capacity(names.len());
+    for (index, name) in names.iter().enumerate() {
+        let occurrence = names[..index].iter().filter(|&other| other == name).count();
+        let name = if occurrence == 0 {
+            name.clone()
+        } else {
+            format!("{name}_{}", occurrence + 1)
+        };
+        paths.push(create_path_with_suffix(base_path, &format!("{name}.caf")));
+    }
+    paths
+}
+
+/// Channel names of a split output without Atmos metadata
+pub fn split_channel_names(channel_labels: &[ChannelLabel], channel_count: usize) -> Vec<String> {
+    (0..channel_count)
+        .map(|channel| match channel_labels.get(channel) {
+            Some(label) => label.to_string(),
+            None => format!("ch{channel}"),
+        })
+        .collect()
+}
+
+impl SplitWriter {
+    fn create(
+        paths: Vec<PathBuf>,
+        sample_rate: u32,
+        channel_descriptions: Vec<ChannelDescription>,
+        bit_format: BitFormat,
+    ) -> Result<Self> {
+        let mut writers = Vec::with_capacity(paths.len());
+        for (channel, path) in paths.iter().enumerate() {
+            let AudioWriter::Caf(writer) = AudioWriter::create_caf(
+                path.clone(),
+                sample_rate,
+                1,
+                channel_descriptions
+                    .get(channel)
+                    .cloned()
+                    .into_iter()
+                    .collect(),
+                bit_format,
+            )?
+            else {
+                unreachable!("create_caf returns a CAF writer");
+            };
+            writers.push(writer);
+        }
+        Ok(Self { writers, paths })
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Rename the files of the channels to `paths` and continue writing them
+    pub fn rename(mut self, paths: Vec<PathBuf>) -> Result<Self> {
+        if paths.len() != self.paths.len() {
+            bail!(
+                "Cannot rename {} split channel files to {} names",
+                self.paths.len(),
+                paths.len()
+            );
+        }
+
+        self.finish()?;
+        drop(self.writers);
+
+        let mut writers = Vec::with_capacity(paths.len());
+        for (current_path, new_path) in self.paths.iter().zip(&paths) {
+            if current_path != new_path {
+                std::fs::rename(current_path, new_path)?;
+            }
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(new_path)?;
+            writers.push(create_caf_writer_from_existing_file(file)?);
+        }
+        Ok(Self { writers, paths })
+    }
+
+    fn write_pcm_samples(&mut self, samples: &[i32]) -> Result<()> {
+        let channel_count = self.writers.len();
+        for (channel, writer) in self.writers.iter_mut().enumerate() {
+            let channel_samples = samples
+                .iter()
+                .skip(channel)
+                .step_by(channel_count)
+                .copied()
+                .collect::<Vec<_>>();
+            if writer.is_float() {
+                writer.write_pcm_f32(&channel_samples, 24)?;
+            } else {
+                writer.write_pcm_packed(&channel_samples)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        for writer in &mut self.writers {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        for writer in &mut self.writers {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the output path selects stdout.
+pub fn is_stdout_path(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Name of the raw PCM sample format as used by ffmpeg and sox.
+pub fn raw_pcm_format_name(bit_format: BitFormat) -> &'static str {
+    match bit_format {
+        BitFormat::Int16 => "s16le",
+        BitFormat::Int24 => "s24le",
+        BitFormat::Int32 => "s32le",
+        BitFormat::Float32 => "f32le",
+    }
+}
+
+/// Requantize decoded samples of `bit_depth` bits to the resolution of the
+/// bit format.
+///
+/// `Int16` samples are TPDF dithered, and `Int32` samples are shifted to the
+/// most significant bits. `Int24` and `Float32` samples are left untouched,
+/// unless 32-bit samples are TPDF dithered to 24 bits.
+pub fn requantize_samples(
+    samples: &mut [i32],
+    bit_depth: u32,
+    bit_format: BitFormat,
+    dither: &mut TpdfDither,
+) {
+    match bit_format {
+        BitFormat::Int16 => {
+            for sample in samples {
+                *sample = dither.requantize(*sample, bit_depth, bit_depth - 16);
+            }
+        }
+        BitFormat::Int32 => {
+            for sample in samples {
+                *sample <<= 32 - bit_depth;
+            }
+        }
+        BitFormat::Int24 | BitFormat::Float32 if bit_depth > 24 => {
+            for sample in samples {
+                *sample = dither.requantize(*sample, bit_depth, bit_depth - 24);
+            }
+        }
+        BitFormat::Int24 | BitFormat::Float32 => {}
+    }
+}
+
+/// Pack interleaved samples into little-endian bytes of the bit format.
+///
+/// Integer samples are at the resolution of the bit format, see
+/// [`requantize_samples`], and float samples are 24-bit.
+pub fn pack_samples_le(samples: &[i32], bit_format: BitFormat) -> Vec<u8> {
+    match bit_format {
+        BitFormat::Int16 => samples
+            .iter()
+            .flat_map(|sample| {
+                let bytes = sample.to_le_bytes();
+                [bytes[0], bytes[1]]
+            })
+            .collect(),
+        BitFormat::Int24 => samples
+            .iter()
+            .flat_map(|sample| {
+                let bytes = sample.to_le_bytes();
+                [bytes[0], bytes[1], bytes[2]]
+            })
+            .collect(),
+        BitFormat::Int32 => samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect(),
+        BitFormat::Float32 => samples
+            .iter()
+            .flat_map(|&sample| (sample as f32 / (1 << 23) as f32).to_le_bytes())
+            .collect(),
+    }
 }
 
 impl AudioWriter {
-    pub fn create_pcm(path: PathBuf) -> Result<Self> {
+    pub fn create_pcm(path: PathBuf, bit_format: BitFormat) -> Result<Self> {
         let pcm_writer = BufWriter::new(File::create(path)?);
-        Ok(AudioWriter::Pcm(pcm_writer))
+        Ok(AudioWriter::Pcm(pcm_writer, bit_format))
+    }
+
+    pub fn create_stdout(bit_format: BitFormat) -> Result<Self> {
+        Ok(AudioWriter::Stdout(
+            BufWriter::with_capacity(1 << 16, std::io::stdout()),
+            bit_format,
+        ))
     }
 
-    pub fn create_caf(path: PathBuf, sample_rate: u32, channel_count: u32) -> Result<Self> {
+    pub fn create_caf(
+        path: PathBuf,
+        sample_rate: u32,
+        channel_count: u32,
+        channel_descriptions: Vec<ChannelDescription>,
+        bit_format: BitFormat,
+    ) -> Result<Self> {
         let mut caf_writer = CAFWriter::new(BufWriter::new(File::create(path)?));
-        caf_writer.configure_audio_format(sample_rate, channel_count, 24)?;
-        caf_writer.write_header()?;
+        configure_caf(
+            &mut caf_writer,
+            sample_rate,
+            channel_count,
+            channel_descriptions,
+            bit_format,
+        )?;
         Ok(AudioWriter::Caf(caf_writer))
     }
 
-    pub fn create_w64(path: PathBuf, sample_rate: u32, channel_count: u32) -> Result<Self> {
+    /// CAF on stdout, streamed without patching the data chunk size
+    pub fn create_stdout_caf(
+        sample_rate: u32,
+        channel_count: u32,
+        channel_descriptions: Vec<ChannelDescription>,
+        bit_format: BitFormat,
+    ) -> Result<Self> {
+        let mut caf_writer =
+            CAFWriter::new_streaming(BufWriter::with_capacity(1 << 16, std::io::stdout()));
+        configure_caf(
+            &mut caf_writer,
+            sample_rate,
+            channel_count,
+            channel_descriptions,
+            bit_format,
+        )?;
+        Ok(AudioWriter::StdoutCaf(caf_writer))
+    }
+
+    pub fn create_w64(
+        path: PathBuf,
+        sample_rate: u32,
+        channel_count: u32,
+        channel_labels: &[ChannelLabel],
+        bit_format: BitFormat,
+    ) -> Result<Self> {
         let mut w64_writer = WAVWriter::new(File::create(path)?);
-        w64_writer.configure_audio_format(sample_rate, channel_count, 24)?;
+        w64_writer.configure_audio_format(
+            sample_rate,
+            channel_count,
+            bit_format.bits_per_sample(),
+        )?;
+        if !channel_labels.is_empty() {
+            w64_writer.set_channel_labels(channel_labels)?;
+        }
         w64_writer.write_header()?;
         Ok(AudioWriter::W64(w64_writer))
     }
 
+    pub fn create_wav(
+        path: PathBuf,
+        sample_rate: u32,
+        channel_count: u32,
+        channel_labels: &[ChannelLabel],
+        bit_format: BitFormat,
+    ) -> Result<Self> {
+        let mut wav_writer = RiffWavWriter::new(File::create(path)?);
+        wav_writer.configure_audio_format(
+            sample_rate,
+            channel_count,
+            bit_format.bits_per_sample(),
+        )?;
+        if !channel_labels.is_empty() {
+            wav_writer.set_channel_labels(channel_labels)?;
+        }
+        wav_writer.write_header()?;
+        Ok(AudioWriter::Wav(wav_writer))
+    }
+
+    /// Restore the channel order of a resumed file from the labels of the stream.
+    ///
+    /// Fails if the labels do not match the channel mask of the file.
+    pub fn resume_channel_labels(&mut self, channel_labels: &[ChannelLabel]) -> Result<()> {
+        if let AudioWriter::W64(w64_writer) = self
+            && w64_writer.channel_mask() != 0
+            && !channel_labels.is_empty()
+        {
+            let file_mask = w64_writer.channel_mask();
+            w64_writer.set_channel_labels(channel_labels)?;
+            if w64_writer.channel_mask() != file_mask {
+                bail!(
+                    "Cannot resume: existing output has channel mask {file_mask:#x}, stream has {:#x}",
+                    w64_writer.channel_mask()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Create a mono CAF file per channel at the paths of [`split_channel_paths`]
+    pub fn create_split(
+        paths: Vec<PathBuf>,
+        sample_rate: u32,
+        channel_descriptions: Vec<ChannelDescription>,
+        bit_format: BitFormat,
+    ) -> Result<Self> {
+        Ok(AudioWriter::Split(SplitWriter::create(
+            paths,
+            sample_rate,
+            channel_descriptions,
+            bit_format,
+        )?))
+    }
+
+    /// Create a BW64 file, the ADM chunks are appended by [`finish_adm`](Self::finish_adm)
+    pub fn create_adm(
+        path: PathBuf,
+        sample_rate: u32,
+        channel_count: u32,
+        bit_format: BitFormat,
+    ) -> Result<Self> {
+        let mut wav_writer = RiffWavWriter::new(File::create(path)?);
+        wav_writer.configure_audio_format(
+            sample_rate,
+            channel_count,
+            bit_format.bits_per_sample(),
+        )?;
+        wav_writer.set_bw64();
+        wav_writer.write_header()?;
+        Ok(AudioWriter::Wav(wav_writer))
+    }
+
+    /// Append the `chna` and `axml` chunks of `adm` after the audio data
+    pub fn finish_adm(&mut self, adm: &AdmDocument) -> Result<()> {
+        let AudioWriter::Wav(wav_writer) = self else {
+            bail!("ADM metadata can only be written to a BW64 file");
+        };
+        let duration = wav_writer.sample_count();
+        wav_writer.append_chunk(b"chna", &adm.chna_chunk())?;
+        wav_writer.append_chunk(b"axml", &adm.axml_chunk(duration))?;
+        Ok(())
+    }
+
+    /// Write interleaved samples requantized with [`requantize_samples`].
     pub fn write_pcm_samples(&mut self, samples: &[i32], channel_count: usize) -> Result<()> {
         match self {
-            AudioWriter::Pcm(pcm_writer) => {
-                for sample_idx in 0..(samples.len() / channel_count) {
-                    for ch in 0..channel_count {
-                        let sample = samples[sample_idx * channel_count + ch];
-                        let bytes = sample.to_le_bytes();
-                        pcm_writer.write_all(&bytes[..3])?;
-                    }
-                }
+            AudioWriter::Pcm(pcm_writer, bit_format) => {
+                let frames = samples.len() / channel_count * channel_count;
+                pcm_writer.write_all(&pack_samples_le(&samples[..frames], *bit_format))?;
+            }
+            AudioWriter::Caf(caf_writer) if caf_writer.is_float() => {
+                caf_writer.write_pcm_f32(samples, 24)?;
             }
             AudioWriter::Caf(caf_writer) => {
-                caf_writer.write_pcm_24bit_as_packed(samples)?;
+                caf_writer.write_pcm_packed(samples)?;
+            }
+            AudioWriter::StdoutCaf(caf_writer) if caf_writer.is_float() => {
+                caf_writer.write_pcm_f32(samples, 24)?;
+            }
+            AudioWriter::StdoutCaf(caf_writer) => {
+                caf_writer.write_pcm_packed(samples)?;
             }
             AudioWriter::W64(w64_writer) => {
-                w64_writer.write_pcm_24bit_as_packed(samples)?;
+                w64_writer.write_pcm_packed(samples)?;
+            }
+            AudioWriter::Wav(wav_writer) => {
+                wav_writer.write_pcm_packed(samples)?;
+            }
+            AudioWriter::Stdout(stdout_writer, bit_format) => {
+                stdout_writer.write_all(&pack_samples_le(samples, *bit_format))?;
+            }
+            AudioWriter::Split(split_writer) => {
+                split_writer.write_pcm_samples(samples)?;
             }
         }
         Ok(())
@@ -111,7 +477,7 @@ impl AudioWriter {
 
     pub fn close_and_drop(self) -> Result<()> {
         match self {
-            AudioWriter::Pcm(mut w) => {
+            AudioWriter::Pcm(mut w, _) => {
                 w.flush()?;
                 drop(w);
             }
@@ -123,6 +489,22 @@ impl AudioWriter {
                 w.finish()?;
                 drop(w);
             }
+            AudioWriter::StdoutCaf(mut w) => {
+                w.finish()?;
+                drop(w);
+            }
+            AudioWriter::Wav(mut w) => {
+                w.finish()?;
+                drop(w);
+            }
+            AudioWriter::Stdout(mut w, _) => {
+                w.flush()?;
+                drop(w);
+            }
+            AudioWriter::Split(mut w) => {
+                w.finish()?;
+                drop(w);
+            }
         }
         Ok(())
     }
@@ -132,32 +514,90 @@ impl AudioWriter {
             AudioWriter::Caf(caf_writer) => {
                 caf_writer.finish()?;
             }
-            AudioWriter::Pcm(pcm_writer) => {
+            AudioWriter::StdoutCaf(caf_writer) => {
+                caf_writer.finish()?;
+            }
+            AudioWriter::Pcm(pcm_writer, _) => {
                 pcm_writer.flush()?;
             }
             AudioWriter::W64(w64_writer) => {
                 w64_writer.finish()?;
             }
+            AudioWriter::Wav(wav_writer) => {
+                wav_writer.finish()?;
+            }
+            AudioWriter::Stdout(stdout_writer, _) => {
+                stdout_writer.flush()?;
+            }
+            AudioWriter::Split(split_writer) => {
+                split_writer.finish()?;
+            }
         }
         Ok(())
     }
 
+    /// Length of the data chunk and size of a sample frame in bytes, for the
+    /// writers of a single file with a data chunk
+    pub fn data_chunk_layout(&mut self) -> Result<Option<(u64, u64)>> {
+        let layout = match self {
+            AudioWriter::Caf(caf_writer) => match caf_writer.bytes_per_frame() {
+                Some(frame_size) => Some((caf_writer.data_len()?, frame_size as u64)),
+                None => None,
+            },
+            AudioWriter::W64(w64_writer) => {
+                let stats = w64_writer.stats();
+                Some((
+                    stats.data_written,
+                    stats.channels as u64 * (stats.bits_per_sample as u64 / 8),
+                ))
+            }
+            AudioWriter::Wav(wav_writer) => Some((wav_writer.data_len(), wav_writer.block_align())),
+            AudioWriter::StdoutCaf(caf_writer) => match caf_writer.bytes_per_frame() {
+                Some(frame_size) => Some((caf_writer.data_len()?, frame_size as u64)),
+                None => None,
+            },
+            AudioWriter::Pcm(..) | AudioWriter::Stdout(..) | AudioWriter::Split(_) => None,
+        };
+        Ok(layout)
+    }
+
+    /// Flush the buffered audio data without finishing the output
     pub fn flush(&mut self) -> Result<()> {
         match self {
-            AudioWriter::Pcm(pcm_writer) => {
-                pcm_writer.flush()?;
-            }
-            AudioWriter::Caf(_) => {
-                // CAF writer doesn't need explicit flush for our use case
-            }
-            AudioWriter::W64(_) => {
-                // W64 writer handles flushing internally
-            }
+            AudioWriter::Pcm(pcm_writer, _) => pcm_writer.flush()?,
+            AudioWriter::Stdout(stdout_writer, _) => stdout_writer.flush()?,
+            AudioWriter::Caf(caf_writer) => caf_writer.flush()?,
+            AudioWriter::StdoutCaf(caf_writer) => caf_writer.flush()?,
+            AudioWriter::W64(w64_writer) => w64_writer.flush()?,
+            AudioWriter::Wav(wav_writer) => wav_writer.flush()?,
+            AudioWriter::Split(split_writer) => split_writer.flush()?,
         }
         Ok(())
     }
 }
 
+/// Set the audio format and channel layout of a new CAF and write its header
+fn configure_caf<W: Write + Seek>(
+    caf_writer: &mut CAFWriter<W>,
+    sample_rate: u32,
+    channel_count: u32,
+    channel_descriptions: Vec<ChannelDescription>,
+    bit_format: BitFormat,
+) -> Result<()> {
+    match bit_format {
+        BitFormat::Int16 | BitFormat::Int24 | BitFormat::Int32 => caf_writer
+            .configure_audio_format(sample_rate, channel_count, bit_format.bits_per_sample())?,
+        BitFormat::Float32 => {
+            caf_writer.configure_float_audio_format(sample_rate, channel_count)?
+        }
+    }
+    if !channel_descriptions.is_empty() {
+        caf_writer.set_channel_layout_from_descriptions(channel_descriptions)?;
+    }
+    caf_writer.write_header()?;
+    Ok(())
+}
+
 pub fn create_caf_writer_from_existing_file(file: File) -> Result<CAFWriter<BufWriter<File>>> {
     let mut temp_file = file.try_clone()?;
     let file_info = crate::caf::parse_caf_file(&mut temp_file)?;
@@ -167,3 +607,78 @@ pub fn create_caf_writer_from_existing_file(file: File) -> Result<CAFWriter<BufW
         file_info,
     )?)
 }
+
+#[test]
+fn pack_raw_pcm_samples() {
+    let samples = [0x123456, -1, -0x800000];
+    let requantized = |bit_format| {
+        let mut samples = samples;
+        requantize_samples(&mut samples, 24, bit_format, &mut TpdfDither::default());
+        samples
+    };
+
+    assert_eq!(
+        pack_samples_le(&requantized(BitFormat::Int24), BitFormat::Int24),
+        [0x56, 0x34, 0x12, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x80]
+    );
+    assert_eq!(
+        pack_samples_le(&requantized(BitFormat::Int32), BitFormat::Int32),
+        [
+            0x00, 0x56, 0x34, 0x12, 0x00, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x80
+        ]
+    );
+    assert_eq!(
+        pack_samples_le(&samples[2..], BitFormat::Float32),
+        (-1.0f32).to_le_bytes()
+    );
+
+    let int16 = pack_samples_le(&requantized(BitFormat::Int16), BitFormat::Int16);
+    let int16 = int16
+        .chunks_exact(2)
+        .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+        .collect::<Vec<_>>();
+    assert!((0x1233..=0x1235).contains(&int16[0]));
+    assert!((-1..=0).contains(&int16[1]));
+    assert!((-0x8000..=-0x7FFF).contains(&int16[2]));
+
+    // 32-bit samples are packed as they are
+    let mut wide = [0x1234_5678, i32::MIN];
+    requantize_samples(&mut wide, 32, BitFormat::Int32, &mut TpdfDither::default());
+    assert_eq!(
+        pack_samples_le(&wide, BitFormat::Int32),
+        [0x78, 0x56, 0x34, 0x12, 0x00, 0x00, 0x00, 0x80]
+    );
+}
+
+#[test]
+fn split_channel_file_names() {
+    let base_path = Path::new("out");
+
+    let names = split_channel_names(&[ChannelLabel::L, ChannelLabel::R, ChannelLabel::LFE], 4);
+    assert_eq!(names, ["L", "R", "LFE", "ch3"]);
+    assert_eq!(
+        split_channel_names(&[ChannelLabel::LFE, ChannelLabel::Object(0)], 2),
+        ["LFE", "Obj0"]
+    );
+
+    let paths = split_channel_paths(base_path, &names);
+    assert_eq!(
+        paths,
+        ["out.L.caf", "out.R.caf", "out.LFE.caf", "out.ch3.caf"].map(PathBuf::from)
+    );
+
+    // Beds of a second instance repeat the names of the first
+    let names = ["L", "R", "L", "R", "L", "obj12"].map(String::from);
+    assert_eq!(
+        split_channel_paths(Path::new("dir/movie"), &names),
+        [
+            "dir/movie.L.caf",
+            "dir/movie.R.caf",
+            "dir/movie.L_2.caf",
+            "dir/movie.R_2.caf",
+            "dir/movie.L_3.caf",
+            "dir/movie.obj12.caf",
+        ]
+        .map(PathBuf::from)
+    );
+}
@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+
+/// Suffix of output files still being written
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Path an output file is written to until the decode finalizes it,
+/// `<path>.partial`
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(PARTIAL_SUFFIX);
+    PathBuf::from(partial)
+}
+
+/// Output files written under their [`partial_path`] until they are complete
+///
+/// A decode killed midway only leaves `.partial` files behind, which are not
+/// mistaken for finished output. [`commit`](Self::commit) syncs the files to
+/// disk and renames them into place, the DAMF headers last, so that a
+/// `.atmos` file only appears once the audio and metadata files it refers to
+/// are complete.
+#[derive(Debug, Default)]
+pub struct PartialOutputs {
+    /// Final paths of the output files
+    paths: Vec<PathBuf>,
+}
+
+impl PartialOutputs {
+    /// Register an output file, returning the path to write it to
+    pub fn create(&mut self, path: &Path) -> PathBuf {
+        if !self.paths.iter().any(|registered| registered == path) {
+            self.paths.push(path.to_path_buf());
+        }
+        partial_path(path)
+    }
+
+    /// Sync the partial files to disk and rename them into place
+    ///
+    /// Files renamed or removed while writing are skipped.
+    pub fn commit(&mut self) -> Result<()> {
+        let (headers, files): (Vec<_>, Vec<_>) = std::mem::take(&mut self.paths)
+            .into_iter()
+            .partition(|path| path.extension().is_some_and(|ext| ext == "atmos"));
+
+        let mut dirs = Vec::new();
+        for path in files.iter().chain(&headers) {
+            let partial = partial_path(path);
+            if !partial.is_file() {
+                continue;
+            }
+
+            OpenOptions::new()
+                .write(true)
+                .open(&partial)
+                .and_then(|file| file.sync_all())
+                .with_context(|| format!("Failed to sync {}", partial.display()))?;
+            std::fs::rename(&partial, path)
+                .with_context(|| format!("Failed to move {} into place", path.display()))?;
+
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+
+        for dir in dirs {
+            sync_dir(dir)?;
+        }
+        Ok(())
+    }
+}
+
+/// Make the renames in `dir` durable
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<()> {
+    std::fs::File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Failed to sync {}", dir.display()))
+}
+
+/// Directories cannot be opened for syncing on other platforms
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Partial output files left behind for `base_path` by an interrupted decode
+///
+/// Matches `<base>.*.partial` and the segments `<base>_*.partial`.
+pub fn stale_partials(base_path: &Path) -> Result<Vec<PathBuf>> {
+    let Some(base_name) = base_path.file_name().map(|name| name.to_string_lossy()) else {
+        return Ok(Vec::new());
+    };
+    let dir = match base_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut partials = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let is_output = name
+            .strip_prefix(base_name.as_ref())
+            .is_some_and(|rest| rest.starts_with(['.', '_']) && rest.ends_with(PARTIAL_SUFFIX));
+        if is_output && path.is_file() {
+            partials.push(path);
+        }
+    }
+    partials.sort();
+    Ok(partials)
+}
+
+/// Remove the partial output files of an interrupted decode to `base_path`
+pub fn remove_stale_partials(base_path: &Path) -> Result<()> {
+    for path in stale_partials(base_path)? {
+        log::warn!(
+            "Removing {} left behind by an interrupted decode, use --resume to continue it",
+            path.display()
+        );
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::command::AudioFormat;
+    use crate::cli::decode::atmos::create_atmos_header_path;
+    use crate::cli::decode::handler::DecodeHandler;
+    use crate::cli::decode::handler::tests::{OutputOptions, TestOutput, silent_frame};
+    use crate::cli::decode::output::create_output_paths;
+    use truehd::structs::channel::ChannelLabel;
+    use truehd::structs::oamd::{ObjectAudioMetadataPayload, TEST_DATA};
+
+    #[test]
+    fn interrupted_decode_leaves_partial_outputs() -> Result<()> {
+        let oamd = ObjectAudioMetadataPayload::read(TEST_DATA).expect("valid test payload");
+        let frame = |index: usize| {
+            let mut frame = silent_frame(index, vec![ChannelLabel::L; oamd.object_count]);
+            frame.oamd = vec![oamd.clone()];
+            frame
+        };
+
+        let output = TestOutput::new(OutputOptions {
+            atmos: true,
+            ..Default::default()
+        })?;
+        let base = output.path("out");
+        let (audio_path, metadata_path) = create_output_paths(&base, AudioFormat::Caf, true);
+        let triplet = [create_atmos_header_path(&base), metadata_path, audio_path];
+
+        // Dropping the handler without finalizing simulates an interruption
+        let mut handler = DecodeHandler::default();
+        output.with_context("out", |ctx| {
+            (0..8).try_for_each(|index| handler.handle_decoded_frame(frame(index), ctx))
+        })?;
+        drop(handler);
+        assert!(triplet.iter().all(|path| !path.exists()));
+        let mut partials = triplet
+            .iter()
+            .map(|path| partial_path(path))
+            .collect::<Vec<_>>();
+        partials.sort();
+        assert_eq!(stale_partials(&base)?, partials);
+
+        let mut handler = DecodeHandler::default();
+        output.write("out", &mut handler, (0..8).map(frame))?;
+        assert!(triplet.iter().all(|path| path.is_file()));
+        assert!(stale_partials(&base)?.is_empty());
+        Ok(())
+    }
+}
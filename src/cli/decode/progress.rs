@@ -2,13 +2,13 @@ use crate::input::InputReader;
 use anyhow::Result;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use std::path::Path;
-use truehd::process::extract::Extractor;
+use truehd::process::{Metrics, extract::Extractor};
 
-pub fn estimate_total_frames(input_path: &Path) -> Result<u64> {
+pub fn estimate_total_frames(input_path: &Path, track: Option<u64>) -> Result<u64> {
     log::info!("Counting frames for progress estimation");
     let count_start = std::time::Instant::now();
 
-    let mut input_reader_count = InputReader::new(input_path)?;
+    let mut input_reader_count = InputReader::with_track(input_path, track)?;
     let mut extractor_count = Extractor::default();
     let mut successful_frames = 0u64;
     let mut bytes_read = 0u64;
@@ -49,15 +49,24 @@ pub fn estimate_total_frames(input_path: &Path) -> Result<u64> {
     Ok(successful_frames)
 }
 
+/// Number of access units in `duration` seconds of a stream
+pub fn frames_for_duration(duration: f64, sampling_frequency: u32, samples_per_au: usize) -> u64 {
+    (duration * sampling_frequency as f64 / samples_per_au as f64).ceil() as u64
+}
+
+fn bar_style() -> Result<ProgressStyle> {
+    Ok(ProgressStyle::with_template(
+        "{bar:40.cyan/blue} {pos}/{len} frames ({percent}%)\n{msg} | elapsed: {elapsed_precise} | ETA: {eta_precise}",
+    )?)
+}
+
 pub fn create_progress_bar(
     multi: &MultiProgress,
     total_frames: Option<u64>,
 ) -> Result<ProgressBar> {
     let pb = if let Some(total) = total_frames {
         let pb = multi.add(ProgressBar::new(total));
-        pb.set_style(ProgressStyle::with_template(
-            "{bar:40.cyan/blue} {pos}/{len} frames ({percent}%)\n{msg} | elapsed: {elapsed_precise} | ETA: {eta_precise}",
-        )?);
+        pb.set_style(bar_style()?);
 
         pb.enable_steady_tick(std::time::Duration::from_millis(100));
         pb
@@ -72,3 +81,39 @@ pub fn create_progress_bar(
     pb.set_message("initializing decoder");
     Ok(pb)
 }
+
+/// Turn a spinner into a bar of `total_frames` once the length of the input is known
+pub fn switch_to_bar(pb: &ProgressBar, total_frames: u64) {
+    if pb.length().is_some() {
+        return;
+    }
+    pb.set_length(total_frames.max(pb.position()));
+    if let Ok(style) = bar_style() {
+        pb.set_style(style);
+    }
+    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+}
+
+/// Formats decode-only speed and parse throughput for the progress message.
+///
+/// Parts without measured time are left out.
+pub fn metrics_str(metrics: &Metrics, sample_rate: u32) -> String {
+    let mut parts = String::new();
+    if let Some(factor) = metrics.decode_realtime_factor(sample_rate) {
+        parts.push_str(&format!(" | decode: {factor:.1}x"));
+    }
+    if let Some(bytes_per_second) = metrics.parse_bytes_per_second() {
+        parts.push_str(&format!(
+            " | parse: {:.1} MB/s",
+            bytes_per_second / 1_000_000.0
+        ));
+    }
+    parts
+}
+
+#[test]
+fn duration_hint_frames() {
+    assert_eq!(frames_for_duration(1.0, 48000, 40), 1200);
+    assert_eq!(frames_for_duration(0.5, 96000, 80), 600);
+    assert_eq!(frames_for_duration(0.001, 48000, 40), 2);
+}
@@ -0,0 +1,343 @@
+use crate::cli::command::{ChannelOrder, Downmix};
+use crate::riff::speaker_position;
+use anyhow::{Result, bail};
+use std::f64::consts::FRAC_1_SQRT_2;
+use truehd::structs::channel::ChannelLabel;
+
+/// Channel order of [`ChannelOrder::Film`], channels missing here follow in input order
+const FILM_ORDER: [ChannelLabel; 24] = [
+    ChannelLabel::L,
+    ChannelLabel::Lsc,
+    ChannelLabel::C,
+    ChannelLabel::Rsc,
+    ChannelLabel::R,
+    ChannelLabel::Lw,
+    ChannelLabel::Rw,
+    ChannelLabel::Ls,
+    ChannelLabel::Rs,
+    ChannelLabel::Lsd,
+    ChannelLabel::Rsd,
+    ChannelLabel::Lb,
+    ChannelLabel::Rb,
+    ChannelLabel::Cb,
+    ChannelLabel::LFE,
+    ChannelLabel::LFE2,
+    ChannelLabel::Tfl,
+    ChannelLabel::Tfc,
+    ChannelLabel::Tfr,
+    ChannelLabel::Tsl,
+    ChannelLabel::Tsr,
+    ChannelLabel::Tc,
+    ChannelLabel::Tbl,
+    ChannelLabel::Tbr,
+];
+
+const STEREO_LABELS: [ChannelLabel; 2] = [ChannelLabel::L, ChannelLabel::R];
+
+const SURROUND_51_LABELS: [ChannelLabel; 6] = [
+    ChannelLabel::L,
+    ChannelLabel::R,
+    ChannelLabel::C,
+    ChannelLabel::LFE,
+    ChannelLabel::Ls,
+    ChannelLabel::Rs,
+];
+
+const SAMPLE_MIN: f64 = -(1 << 23) as f64;
+const SAMPLE_MAX: f64 = ((1 << 23) - 1) as f64;
+
+/// Contributions of a channel to the 5.1 channels.
+///
+/// Wide and extra surround channels fold into their neighbours at unity gain,
+/// centre and height channels at -3 dB.
+fn fold_to_51(label: ChannelLabel) -> &'static [(ChannelLabel, f64)] {
+    use ChannelLabel::*;
+
+    const H: f64 = FRAC_1_SQRT_2;
+    match label {
+        L | Lw => &[(L, 1.0)],
+        R | Rw => &[(R, 1.0)],
+        C => &[(C, 1.0)],
+        LFE | LFE2 => &[(LFE, 1.0)],
+        Ls | Lb | Lsd => &[(Ls, 1.0)],
+        Rs | Rb | Rsd => &[(Rs, 1.0)],
+        Lsc => &[(L, H), (C, H)],
+        Rsc => &[(R, H), (C, H)],
+        Cb => &[(Ls, H), (Rs, H)],
+        Tfl => &[(L, H)],
+        Tfr => &[(R, H)],
+        Tfc => &[(C, H)],
+        Tsl | Tbl => &[(Ls, H)],
+        Tsr | Tbr => &[(Rs, H)],
+        Tc => &[(L, 0.5), (R, 0.5), (Ls, 0.5), (Rs, 0.5)],
+        Object(_) | Generic(_) => &[],
+    }
+}
+
+/// ITU-R BS.775 Lo/Ro contributions of a 5.1 channel, the LFE is dropped.
+fn fold_to_stereo(label: ChannelLabel) -> &'static [(ChannelLabel, f64)] {
+    use ChannelLabel::*;
+
+    const H: f64 = FRAC_1_SQRT_2;
+    match label {
+        L => &[(L, 1.0)],
+        R => &[(R, 1.0)],
+        C => &[(L, H), (R, H)],
+        Ls => &[(L, H)],
+        Rs => &[(R, H)],
+        _ => &[],
+    }
+}
+
+enum Mapping {
+    /// Input channel of each output channel
+    Permute(Vec<usize>),
+    /// Coefficient of each input channel for each output channel
+    Matrix(Vec<Vec<f64>>),
+}
+
+/// Reorders or downmixes interleaved decoded samples by their channel labels.
+pub struct ChannelRemapper {
+    input_labels: Vec<ChannelLabel>,
+    output_labels: Vec<ChannelLabel>,
+    mapping: Mapping,
+}
+
+impl ChannelRemapper {
+    /// Create a remapper for `channel_count` decoded channels with `labels`.
+    ///
+    /// Fails when a decoded channel has no label or no fixed position, or when
+    /// downmixing to a layout with more channels than decoded.
+    pub fn new(
+        order: ChannelOrder,
+        downmix: Option<Downmix>,
+        channel_count: usize,
+        labels: &[ChannelLabel],
+    ) -> Result<Self> {
+        if labels.len() != channel_count {
+            bail!(
+                "{} of {channel_count} decoded channels have no channel label",
+                channel_count.saturating_sub(labels.len())
+            );
+        }
+        if let Some(label) = labels
+            .iter()
+            .find(|label| matches!(label, ChannelLabel::Object(_) | ChannelLabel::Generic(_)))
+        {
+            bail!("Channel {label} has no fixed position");
+        }
+
+        let (output_labels, mapping) = match downmix {
+            None => {
+                let order = ordered_indices(order, labels);
+                let output_labels = order.iter().map(|&ch| labels[ch]).collect();
+                (output_labels, Mapping::Permute(order))
+            }
+            Some(downmix) => {
+                let target: &[ChannelLabel] = match downmix {
+                    Downmix::Stereo => &STEREO_LABELS,
+                    Downmix::Surround51 => &SURROUND_51_LABELS,
+                };
+                if channel_count < target.len() {
+                    bail!(
+                        "Cannot downmix {channel_count} channels to {} channels",
+                        target.len()
+                    );
+                }
+
+                let matrix = downmix_matrix(downmix, target, labels);
+                let order = ordered_indices(order, target);
+                let output_labels = order.iter().map(|&ch| target[ch]).collect();
+                let matrix = order.iter().map(|&ch| matrix[ch].clone()).collect();
+                (output_labels, Mapping::Matrix(matrix))
+            }
+        };
+
+        Ok(Self {
+            input_labels: labels.to_vec(),
+            output_labels,
+            mapping,
+        })
+    }
+
+    /// Labels of the decoded channels the remapper was created for
+    pub fn input_labels(&self) -> &[ChannelLabel] {
+        &self.input_labels
+    }
+
+    /// Labels of the output channels in output order
+    pub fn output_labels(&self) -> &[ChannelLabel] {
+        &self.output_labels
+    }
+
+    /// Remap interleaved samples of the input channels to the output channels.
+    ///
+    /// Downmixed samples are rounded and clamped to the 24-bit range.
+    pub fn apply(&self, samples: &[i32]) -> Vec<i32> {
+        let input_count = self.input_labels.len();
+        let frames = samples.chunks_exact(input_count);
+        let mut output = Vec::with_capacity(frames.len() * self.output_labels.len());
+
+        match &self.mapping {
+            Mapping::Permute(order) => {
+                for frame in frames {
+                    output.extend(order.iter().map(|&ch| frame[ch]));
+                }
+            }
+            Mapping::Matrix(matrix) => {
+                for frame in frames {
+                    output.extend(matrix.iter().map(|row| {
+                        let sum = row
+                            .iter()
+                            .zip(frame)
+                            .map(|(&coeff, &sample)| coeff * sample as f64)
+                            .sum::<f64>();
+                        sum.round().clamp(SAMPLE_MIN, SAMPLE_MAX) as i32
+                    }));
+                }
+            }
+        }
+        output
+    }
+}
+
+/// Indices of `labels` sorted by their position in the channel order
+fn ordered_indices(order: ChannelOrder, labels: &[ChannelLabel]) -> Vec<usize> {
+    let mut indices = (0..labels.len()).collect::<Vec<_>>();
+    match order {
+        ChannelOrder::Native => {}
+        ChannelOrder::Smpte => {
+            indices.sort_by_key(|&ch| speaker_position(labels[ch]).unwrap_or(u32::MAX));
+        }
+        ChannelOrder::Film => {
+            indices.sort_by_key(|&ch| {
+                FILM_ORDER
+                    .iter()
+                    .position(|&label| label == labels[ch])
+                    .unwrap_or(usize::MAX)
+            });
+        }
+    }
+    indices
+}
+
+/// Downmix coefficients of each input channel for each `target` channel.
+///
+/// When an output channel would sum to more than full scale, all coefficients
+/// are attenuated by the same gain so the downmix cannot clip.
+fn downmix_matrix(
+    downmix: Downmix,
+    target: &[ChannelLabel],
+    labels: &[ChannelLabel],
+) -> Vec<Vec<f64>> {
+    let mut matrix = vec![vec![0.0; labels.len()]; target.len()];
+
+    for (input, &label) in labels.iter().enumerate() {
+        for &(surround, gain) in fold_to_51(label) {
+            let contributions: &[(ChannelLabel, f64)] = match downmix {
+                Downmix::Surround51 => &[(surround, 1.0)],
+                Downmix::Stereo => fold_to_stereo(surround),
+            };
+            for &(output_label, output_gain) in contributions {
+                if let Some(output) = target.iter().position(|&l| l == output_label) {
+                    matrix[output][input] += gain * output_gain;
+                }
+            }
+        }
+    }
+
+    let peak = matrix
+        .iter()
+        .map(|row| row.iter().map(|coeff| coeff.abs()).sum::<f64>())
+        .fold(0.0, f64::max);
+    if peak > 1.0 {
+        log::info!("Attenuating the downmix by {:.2} dB", 20.0 * peak.log10());
+        for coeff in matrix.iter_mut().flatten() {
+            *coeff /= peak;
+        }
+    }
+
+    matrix
+}
+
+#[test]
+fn remap_channel_order() -> Result<()> {
+    use ChannelLabel::*;
+
+    let labels = [L, R, C, LFE, Ls, Rs, Lb, Rb];
+    let samples = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    let smpte = ChannelRemapper::new(ChannelOrder::Smpte, None, 8, &labels)?;
+    assert_eq!(smpte.output_labels(), [L, R, C, LFE, Lb, Rb, Ls, Rs]);
+    assert_eq!(smpte.apply(&samples), [1, 2, 3, 4, 7, 8, 5, 6]);
+
+    let film = ChannelRemapper::new(ChannelOrder::Film, None, 6, &labels[..6])?;
+    assert_eq!(film.output_labels(), [L, C, R, Ls, Rs, LFE]);
+    assert_eq!(
+        film.apply(&[1, 2, 3, 4, 5, 6, 11, 12, 13, 14, 15, 16]),
+        [1, 3, 2, 5, 6, 4, 11, 13, 12, 15, 16, 14]
+    );
+
+    // Channels without a speaker position keep their input order at the end
+    let wide = ChannelRemapper::new(ChannelOrder::Smpte, None, 4, &[Lw, L, Rw, R])?;
+    assert_eq!(wide.output_labels(), [L, R, Lw, Rw]);
+
+    let native = ChannelRemapper::new(ChannelOrder::Native, None, 8, &labels)?;
+    assert_eq!(native.apply(&samples), samples);
+
+    Ok(())
+}
+
+#[test]
+fn remap_downmix() -> Result<()> {
+    use ChannelLabel::*;
+
+    let labels = [L, R, C, LFE, Ls, Rs];
+
+    // Lo = L + 0.707 C + 0.707 Ls, normalized by 1 + 2 * 0.707
+    let stereo = ChannelRemapper::new(ChannelOrder::Native, Some(Downmix::Stereo), 6, &labels)?;
+    assert_eq!(stereo.output_labels(), [L, R]);
+    let peak = 1.0 + 2.0 * FRAC_1_SQRT_2;
+    assert_eq!(
+        stereo.apply(&[1_000_000, 0, 0, 1_000_000, 0, 0]),
+        [(1_000_000.0 / peak).round() as i32, 0]
+    );
+    assert_eq!(
+        stereo.apply(&[0, 0, 1_000_000, 0, 0, 1_000_000]),
+        [
+            (FRAC_1_SQRT_2 * 1_000_000.0 / peak).round() as i32,
+            (2.0 * FRAC_1_SQRT_2 * 1_000_000.0 / peak).round() as i32
+        ]
+    );
+
+    // Full scale in every channel does not clip
+    let full_scale = [(1 << 23) - 1; 6];
+    assert!(stereo.apply(&full_scale).iter().all(|&s| s < 1 << 23));
+
+    // 7.1 to 5.1 folds the back surrounds into the side surrounds, halving
+    // all channels to keep the summed surrounds from clipping
+    let surround = ChannelRemapper::new(
+        ChannelOrder::Film,
+        Some(Downmix::Surround51),
+        8,
+        &[L, R, C, LFE, Ls, Rs, Lb, Rb],
+    )?;
+    assert_eq!(surround.output_labels(), [L, C, R, Ls, Rs, LFE]);
+    assert_eq!(
+        surround.apply(&[100, 200, 300, 400, 50, 60, 70, 80]),
+        [50, 150, 100, 60, 70, 200]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn remap_rejects_missing_labels() {
+    use ChannelLabel::*;
+
+    assert!(ChannelRemapper::new(ChannelOrder::Smpte, None, 6, &[L, R]).is_err());
+    assert!(
+        ChannelRemapper::new(ChannelOrder::Native, Some(Downmix::Surround51), 2, &[L, R]).is_err()
+    );
+    assert!(ChannelRemapper::new(ChannelOrder::Smpte, None, 3, &[L, R, Object(0)]).is_err());
+}
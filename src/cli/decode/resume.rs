@@ -0,0 +1,521 @@
+use super::output::{AudioWriter, create_output_paths};
+use super::partial::partial_path;
+use crate::cli::command::{AudioFormat, BitFormat};
+use anyhow::{Result, anyhow, bail};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Partial output of an interrupted decode, reopened for appending
+pub struct ResumeState {
+    /// Final path of the audio file, written under its partial path
+    pub audio_path: PathBuf,
+    pub writer: AudioWriter,
+    pub format: ResumeFormat,
+    /// Number of complete sample frames already present in the file
+    pub samples: u64,
+    /// Atmos metadata file belonging to the audio file, if any
+    pub metadata: Option<MetadataResume>,
+}
+
+/// Format of a resumed audio file, which the decoded stream must match
+#[derive(Debug, Clone, Copy)]
+pub struct ResumeFormat {
+    pub sample_rate: u32,
+    pub channel_count: usize,
+}
+
+impl ResumeFormat {
+    /// Fail unless the stream has the sample rate of the resumed file
+    pub fn check_sample_rate(&self, sample_rate: u32) -> Result<()> {
+        if self.sample_rate != sample_rate {
+            bail!(
+                "Cannot resume: existing output is {} Hz, stream is {sample_rate} Hz",
+                self.sample_rate
+            );
+        }
+        Ok(())
+    }
+
+    /// Fail unless the output has the channel count of the resumed file
+    pub fn check_channel_count(&self, channel_count: usize) -> Result<()> {
+        if self.channel_count != channel_count {
+            bail!(
+                "Cannot resume: existing output has {} channels, stream has {channel_count}",
+                self.channel_count
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Partial `.atmos.metadata` file, reopened for appending
+pub struct MetadataResume {
+    /// Final path of the metadata file, written under its partial path
+    pub path: PathBuf,
+    pub writer: BufWriter<File>,
+    /// Events before this output sample position are present in the file
+    pub samples: u64,
+}
+
+impl ResumeState {
+    /// Open the existing audio output for `base_path`, if any.
+    ///
+    /// The Atmos audio file is preferred over the plain output file since a
+    /// decode renames its output once Atmos metadata is detected. The partial
+    /// files of an interrupted decode are preferred over finished files,
+    /// which are moved back to their partial names to be finalized again. A
+    /// trailing partial sample frame is truncated, and the file must hold
+    /// samples of `bit_format`.
+    pub fn open(
+        base_path: &Path,
+        format: AudioFormat,
+        bit_format: BitFormat,
+    ) -> Result<Option<Self>> {
+        if format == AudioFormat::Pcm {
+            bail!("--resume is not supported with --format pcm (no header to validate against)");
+        }
+        if format == AudioFormat::Wav {
+            bail!("--resume is not supported with --format wav, use caf or w64");
+        }
+        if format == AudioFormat::Adm {
+            bail!("--resume is not supported with --format adm, use caf or w64");
+        }
+
+        let (atmos_path, metadata_path) = create_output_paths(base_path, format, true);
+        let (plain_path, _) = create_output_paths(base_path, format, false);
+
+        let candidates = [atmos_path.clone(), plain_path];
+        let Some(audio_path) = candidates
+            .iter()
+            .find(|p| partial_path(p).is_file())
+            .or_else(|| candidates.iter().find(|p| p.is_file()))
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        // A finished file is only moved once it is found to be resumable
+        let existing_path = match partial_path(&audio_path) {
+            partial if partial.is_file() => partial,
+            _ => audio_path.clone(),
+        };
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&existing_path)?;
+        let is_caf = audio_path.extension().is_some_and(|ext| ext != "wav");
+
+        let (data_start, data_size, sample_rate, channel_count, bits, is_float) = if is_caf {
+            let info = crate::caf::parse_caf_file(&mut file)?;
+            let desc = info
+                .audio_format
+                .as_ref()
+                .ok_or_else(|| anyhow!("{}: missing desc chunk", audio_path.display()))?;
+            (
+                info.data_chunk_start,
+                None,
+                desc.sample_rate as u32,
+                desc.channels_per_frame as usize,
+                desc.bits_per_channel,
+                desc.is_float(),
+            )
+        } else {
+            let info = crate::wav::parse_w64_file(&mut file)?;
+            (
+                info.data_chunk_start,
+                info.data_size,
+                info.sample_rate,
+                info.channels as usize,
+                info.bits_per_sample,
+                false,
+            )
+        };
+
+        let file_bit_format = BitFormat::from_sample_format(bits, is_float);
+        if file_bit_format.is_none() || channel_count == 0 {
+            bail!(
+                "{}: cannot resume a {bits}-bit, {channel_count} channel file",
+                audio_path.display()
+            );
+        }
+        if file_bit_format != Some(bit_format) {
+            bail!(
+                "{}: cannot resume {bits}-bit{} samples as {bit_format:?}",
+                audio_path.display(),
+                if is_float { " float" } else { "" }
+            );
+        }
+
+        let frame_bytes = bits as u64 / 8 * channel_count as u64;
+        // The padding after the data chunk of a finished W64 file is not sample data
+        let file_data_len = file.metadata()?.len().saturating_sub(data_start);
+        let data_len = data_size.map_or(file_data_len, |size| size.min(file_data_len));
+        let samples = data_len / frame_bytes;
+        let complete_len = data_start + samples * frame_bytes;
+
+        if complete_len != data_start + file_data_len {
+            log::warn!(
+                "Truncating {} trailing bytes of an incomplete sample frame",
+                data_start + file_data_len - complete_len
+            );
+        }
+        file.set_len(complete_len)?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let writer = Self::reopen_writer(file, is_caf)?;
+        move_to_partial(&audio_path)?;
+
+        let metadata = if audio_path == atmos_path
+            && (partial_path(&metadata_path).is_file() || metadata_path.is_file())
+        {
+            move_to_partial(&metadata_path)?;
+            MetadataResume::open(&partial_path(&metadata_path), samples)?.map(|metadata| {
+                MetadataResume {
+                    path: metadata_path,
+                    ..metadata
+                }
+            })
+        } else {
+            None
+        };
+
+        Ok(Some(Self {
+            audio_path,
+            writer,
+            format: ResumeFormat {
+                sample_rate,
+                channel_count,
+            },
+            samples,
+            metadata,
+        }))
+    }
+
+    fn reopen_writer(mut file: File, is_caf: bool) -> Result<AudioWriter> {
+        if is_caf {
+            let info = crate::caf::parse_caf_file(&mut file)?;
+            Ok(AudioWriter::Caf(crate::caf::CAFWriter::from_parsed_info(
+                BufWriter::new(file),
+                info,
+            )?))
+        } else {
+            let info = crate::wav::parse_w64_file(&mut file)?;
+            Ok(AudioWriter::W64(crate::wav::WAVWriter::from_parsed_info(
+                file, info,
+            )?))
+        }
+    }
+}
+
+/// Move a finished output file back to its partial path to continue it
+fn move_to_partial(path: &Path) -> Result<()> {
+    let partial = partial_path(path);
+    if !partial.is_file() {
+        log::info!(
+            "Moving {} to {} to continue it",
+            path.display(),
+            partial.display()
+        );
+        std::fs::rename(path, &partial)?;
+    }
+    Ok(())
+}
+
+impl MetadataResume {
+    /// Open an existing metadata file to continue it at `max_samples` at most.
+    ///
+    /// Events from the last position in the file on may be incomplete, and
+    /// events from `max_samples` on have no audio yet, so both are truncated
+    /// to be written again. Returns `None` if nothing before that is left,
+    /// in which case the file is written from the start.
+    pub fn open(path: &Path, max_samples: u64) -> Result<Option<Self>> {
+        let content = std::fs::read_to_string(path)?;
+
+        // Byte offset and sample position of each event
+        let mut events = Vec::new();
+        let mut offset = 0;
+        for line in content.split_inclusive('\n') {
+            if line.starts_with("  - ") {
+                events.push((offset, None));
+            }
+            if let (Some(value), Some((_, sample_pos))) = (
+                line.trim_start_matches(['-', ' '])
+                    .strip_prefix("samplePos: "),
+                events.last_mut(),
+            ) {
+                *sample_pos = value.trim().parse::<u64>().ok();
+            }
+            offset += line.len();
+        }
+
+        let Some(last_pos) = events.iter().rev().find_map(|&(_, sample_pos)| sample_pos) else {
+            return Ok(None);
+        };
+        let samples = max_samples.min(last_pos);
+        if samples == 0 {
+            return Ok(None);
+        }
+
+        let complete_len = events
+            .iter()
+            .find(|(_, sample_pos)| sample_pos.is_none_or(|pos| pos >= samples))
+            .map_or(content.len(), |&(offset, _)| offset);
+
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(complete_len as u64)?;
+        file.seek(SeekFrom::End(0))?;
+
+        log::info!(
+            "Resuming {} at sample {samples}, truncated {} bytes",
+            path.display(),
+            content.len() - complete_len
+        );
+
+        Ok(Some(Self {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(file),
+            samples,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::decode::handler::DecodeHandler;
+    use crate::cli::decode::handler::tests::{OutputOptions, TestOutput, silent_frame};
+    use std::io::Write;
+    use tempfile::TempDir;
+    use truehd::structs::channel::ChannelLabel;
+    use truehd::structs::oamd::ObjectAudioMetadataPayload;
+
+    #[test]
+    fn resume_truncates_partial_frame() -> Result<()> {
+        let dir = TempDir::with_prefix("truehdd-")?;
+        let base_path = dir.path().join("out");
+        let (audio_path, _) = create_output_paths(&base_path, AudioFormat::W64, false);
+
+        let mut writer =
+            AudioWriter::create_w64(audio_path.clone(), 48000, 2, &[], BitFormat::Int24)?;
+        writer.write_pcm_samples(&[1, 2, 3, 4, 5, 6], 2)?;
+        writer.finish()?;
+        drop(writer);
+
+        // Simulate an interrupted write that left half a sample frame behind
+        let mut file = OpenOptions::new().append(true).open(&audio_path)?;
+        file.write_all(&[7, 0, 0, 8])?;
+        drop(file);
+
+        assert!(ResumeState::open(&base_path, AudioFormat::W64, BitFormat::Int16).is_err());
+        let resume = ResumeState::open(&base_path, AudioFormat::W64, BitFormat::Int24)?
+            .expect("file exists");
+        assert_eq!(resume.audio_path, audio_path);
+        assert_eq!(resume.format.sample_rate, 48000);
+        assert_eq!(resume.format.channel_count, 2);
+        assert_eq!(resume.samples, 3);
+
+        let mut writer = resume.writer;
+        writer.write_pcm_samples(&[9, 10], 2)?;
+        writer.close_and_drop()?;
+
+        // The finished file is continued under its partial path
+        let partial = partial_path(&audio_path);
+        assert!(!audio_path.exists());
+        let info = crate::wav::parse_w64_file(File::open(&partial)?)?;
+        assert_eq!(
+            std::fs::metadata(&partial)?.len(),
+            info.data_chunk_start + 4 * 6
+        );
+
+        assert!(
+            ResumeState::open(
+                &dir.path().join("missing"),
+                AudioFormat::W64,
+                BitFormat::Int24
+            )?
+            .is_none()
+        );
+        assert!(ResumeState::open(&base_path, AudioFormat::Pcm, BitFormat::Int24).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn resume_truncates_metadata() -> Result<()> {
+        let dir = TempDir::with_prefix("truehdd-")?;
+        let path = dir.path().join("out.atmos.metadata");
+
+        let header = "sampleRate: 48000\nevents:\n";
+        let event = |id: u32, sample_pos: u64| {
+            format!("  - ID: {id}\n    samplePos: {sample_pos}\n    active: true\n")
+        };
+        let complete = format!("{header}{}{}{}", event(10, 0), event(11, 0), event(10, 160));
+
+        // The last position may be cut off in the middle of an event
+        std::fs::write(
+            &path,
+            format!("{complete}{}  - ID: 10\n    samp", event(10, 320)),
+        )?;
+        let resume = MetadataResume::open(&path, 1000)?.expect("events before the audio end");
+        assert_eq!(resume.samples, 320);
+        drop(resume);
+        assert_eq!(std::fs::read_to_string(&path)?, complete);
+
+        // Events without audio are truncated
+        let resume = MetadataResume::open(&path, 100)?.expect("events before the audio end");
+        assert_eq!(resume.samples, 100);
+        drop(resume);
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            format!("{header}{}{}", event(10, 0), event(11, 0))
+        );
+
+        // Nothing is kept before the first position
+        assert!(MetadataResume::open(&path, 0)?.is_none());
+        std::fs::write(&path, header)?;
+        assert!(MetadataResume::open(&path, 1000)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn resume_ignores_w64_padding() -> Result<()> {
+        let dir = TempDir::with_prefix("truehdd-")?;
+        let base_path = dir.path().join("out");
+        let (audio_path, _) = create_output_paths(&base_path, AudioFormat::W64, false);
+
+        let mut writer =
+            AudioWriter::create_w64(audio_path.clone(), 48000, 1, &[], BitFormat::Int24)?;
+        writer.write_pcm_samples(&[1, 2, 3], 1)?;
+        writer.finish()?;
+        drop(writer);
+
+        // The data chunk of the finished file is padded to a multiple of 8 bytes
+        let info = crate::wav::parse_w64_file(File::open(&audio_path)?)?;
+        assert!(std::fs::metadata(&audio_path)?.len() > info.data_chunk_start + 3 * 3);
+
+        let resume = ResumeState::open(&base_path, AudioFormat::W64, BitFormat::Int24)?
+            .expect("file exists");
+        assert_eq!(resume.samples, 3);
+        drop(resume);
+
+        assert_eq!(
+            std::fs::metadata(partial_path(&audio_path))?.len(),
+            info.data_chunk_start + 3 * 3
+        );
+
+        Ok(())
+    }
+
+    /// Decode `input` to `output` through the decode command
+    fn decode(input: &Path, output: &Path, resume: bool) -> Result<()> {
+        use crate::cli::command::{Cli, Commands};
+        use clap::Parser as _;
+
+        let mut argv = vec![
+            "truehdd".into(),
+            "decode".into(),
+            input.as_os_str().to_owned(),
+            "--format".into(),
+            "w64".into(),
+            "--presentation".into(),
+            "0".into(),
+            "--output-path".into(),
+            output.as_os_str().to_owned(),
+        ];
+        if resume {
+            argv.push("--resume".into());
+        }
+        let cli = Cli::parse_from(argv);
+        let Commands::Decode(ref args) = cli.command else {
+            unreachable!("decode subcommand");
+        };
+        super::super::cmd_decode(args, &cli, None)?;
+        Ok(())
+    }
+
+    #[test]
+    fn resumed_decode_matches_single_pass() -> Result<()> {
+        let dir = TempDir::with_prefix("truehdd-")?;
+        let input = dir.path().join("in.thd");
+        std::fs::write(&input, truehd::process::EXAMPLE_DATA.repeat(64))?;
+
+        let full = dir.path().join("full");
+        decode(&input, &full, false)?;
+        let (full_audio, _) = create_output_paths(&full, AudioFormat::W64, false);
+
+        // Cut the output off in the middle of a sample frame, as an
+        // interrupted decode leaves it, and continue it
+        let partial = dir.path().join("partial");
+        decode(&input, &partial, false)?;
+        let (partial_audio, _) = create_output_paths(&partial, AudioFormat::W64, false);
+        let len = std::fs::metadata(&partial_audio)?.len();
+        OpenOptions::new()
+            .write(true)
+            .open(&partial_audio)?
+            .set_len(len / 2 + 1)?;
+        decode(&input, &partial, true)?;
+
+        assert_eq!(std::fs::read(&partial_audio)?, std::fs::read(&full_audio)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resume_continues_metadata_at_seam() -> Result<()> {
+        use truehd::structs::oamd::{TEST_DATA, TEST_DATA_TRIM};
+
+        let payloads = [TEST_DATA, TEST_DATA_TRIM]
+            .map(|data| ObjectAudioMetadataPayload::read(data).expect("valid test payload"));
+        let channel_count = payloads[0].object_count;
+
+        // Access units of 40 samples with metadata changing every fourth
+        let frame = |index: usize| {
+            let mut frame = silent_frame(index, vec![ChannelLabel::L; channel_count]);
+            for (i, sample) in frame.pcm_data[..40].iter_mut().enumerate() {
+                sample[..channel_count].fill((index * 40 + i) as i32);
+            }
+            if index.is_multiple_of(4) {
+                frame.oamd = vec![payloads[index / 4 % 2].clone()];
+            }
+            frame
+        };
+
+        let output = TestOutput::new(OutputOptions::default())?;
+        let decode = |name: &str, frames: usize, resume: bool| -> Result<PathBuf> {
+            let mut handler = DecodeHandler::default();
+            if resume {
+                let resume =
+                    ResumeState::open(&output.path(name), AudioFormat::Caf, BitFormat::Int24)?;
+                handler.resume_from(resume.expect("partial output exists"));
+            }
+            output.write(name, &mut handler, (0..frames).map(frame))?;
+            Ok(output.path(name))
+        };
+
+        let full = decode("full", 120, false)?;
+
+        // Interrupted in the middle of writing an event
+        let partial = decode("partial", 70, false)?;
+        let (_, metadata_path) = create_output_paths(&partial, AudioFormat::Caf, true);
+        let mut metadata = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&metadata_path)?;
+        metadata.write_all(b"  - ID: 10\n    samplePos: 2800\n    act")?;
+        drop(metadata);
+
+        decode("partial", 120, true)?;
+
+        let (full_audio, full_metadata) = create_output_paths(&full, AudioFormat::Caf, true);
+        let (partial_audio, partial_metadata) =
+            create_output_paths(&partial, AudioFormat::Caf, true);
+        assert_eq!(
+            std::fs::read_to_string(&partial_metadata)?,
+            std::fs::read_to_string(&full_metadata)?
+        );
+        assert_eq!(std::fs::read(&partial_audio)?, std::fs::read(&full_audio)?);
+
+        Ok(())
+    }
+}
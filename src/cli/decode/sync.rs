@@ -0,0 +1,116 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use truehd::structs::timestamp::Timestamp;
+
+/// Container time of the output sample `start_samples` samples after the
+/// first access unit in seconds, counted from its SMPTE `timestamp`.
+pub fn output_start_time(
+    timestamp: &Timestamp,
+    start_samples: u64,
+    sample_rate: u32,
+) -> Option<f64> {
+    let seconds = timestamp.seconds(sample_rate)?;
+    if sample_rate == 0 {
+        return Some(seconds);
+    }
+    Some(seconds + start_samples as f64 / sample_rate as f64)
+}
+
+/// Timing of the output relative to the source stream.
+///
+/// Written next to the output as `<output>.sync.json` so that the decoded
+/// audio can be lined up with its container without measuring offsets.
+#[derive(Debug, Serialize)]
+pub struct SyncInfo {
+    /// SMPTE timestamp preceding the first access unit
+    pub timecode: Option<String>,
+    /// Container time of the first output sample in seconds, absent without
+    /// SMPTE timestamp
+    pub first_sample_time: Option<f64>,
+    pub sample_rate: u32,
+    /// Samples trimmed from the start of the stream
+    pub trimmed_samples: u64,
+    /// Failed access units replaced by silence
+    pub concealed_access_units: u64,
+    /// Silent samples inserted by error concealment and branch repair
+    pub inserted_silence_samples: u64,
+    /// Samples of the output
+    pub samples: u64,
+    /// Duration of the output in seconds
+    pub duration: f64,
+}
+
+impl SyncInfo {
+    pub fn new(timestamp: Option<&Timestamp>, sample_rate: u32, trimmed_samples: u64) -> Self {
+        Self {
+            timecode: timestamp.map(Timestamp::to_string),
+            first_sample_time: timestamp
+                .and_then(|timestamp| output_start_time(timestamp, trimmed_samples, sample_rate)),
+            sample_rate,
+            trimmed_samples,
+            concealed_access_units: 0,
+            inserted_silence_samples: 0,
+            samples: 0,
+            duration: 0.0,
+        }
+    }
+
+    /// Set the length of the output to `samples`.
+    pub fn set_samples(&mut self, samples: u64) {
+        self.samples = samples;
+        self.duration = if self.sample_rate > 0 {
+            samples as f64 / self.sample_rate as f64
+        } else {
+            0.0
+        };
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        log::info!("Writing sync information: {}", path.display());
+        let mut writer = BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+        );
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn first_sample_follows_timestamp_and_trim() -> Result<()> {
+    use truehd::structs::timestamp::Framerate;
+
+    let timestamp = Timestamp {
+        hours: 0,
+        minutes: 59,
+        seconds: 58,
+        frames: 12,
+        samples: 0,
+        _reserved1: 0,
+        framerate: Framerate::R24,
+        _reserved2: false,
+        dropframe: false,
+    };
+
+    // 00:59:58:12 at 24 fps is 3598.5 s, the trim of 2400 samples adds 50 ms
+    let mut sync = SyncInfo::new(Some(&timestamp), 48000, 2400);
+    assert!((sync.first_sample_time.unwrap() - 3598.55).abs() < 1e-9);
+    sync.concealed_access_units = 1;
+    sync.inserted_silence_samples = 40;
+    sync.set_samples(96000);
+    assert_eq!(sync.duration, 2.0);
+
+    let value = serde_json::to_value(&sync)?;
+    assert_eq!(value["timecode"], timestamp.to_string());
+    assert_eq!(value["trimmed_samples"], 2400);
+    assert_eq!(value["inserted_silence_samples"], 40);
+
+    let sync = SyncInfo::new(None, 48000, 2400);
+    assert_eq!(sync.first_sample_time, None);
+    assert!(serde_json::to_value(&sync)?["timecode"].is_null());
+    Ok(())
+}
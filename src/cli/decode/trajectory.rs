@@ -0,0 +1,209 @@
+use crate::damf::BedInstance;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use truehd::structs::oamd::{GAIN_MINUS_INFINITY, ObjectAudioMetadataPayload};
+
+/// Samples between the update blocks of an OAMD payload per unit of their
+/// block offset factor.
+const BLOCK_OFFSET_UNIT: u64 = 32;
+
+/// Position, size and gain of a dynamic object in one update block.
+#[derive(Debug, PartialEq, Serialize)]
+struct TrajectoryPoint {
+    id: u32,
+    sample_pos: u64,
+    x: f64,
+    y: f64,
+    z: f64,
+    size: f64,
+    /// Gain in dB, `None` when the object is muted
+    gain: Option<f64>,
+}
+
+/// Streams the trajectories of the dynamic Atmos objects to a file.
+///
+/// Every update block of every dynamic object is written as a row as soon as
+/// its payload is decoded, either as an element of a JSON array or as a CSV
+/// line. Bed and ISF objects are speaker-anchored and left out; object IDs
+/// match those of the `.atmos.metadata` file.
+pub struct TrajectoryWriter {
+    writer: BufWriter<File>,
+    csv: bool,
+    rows: u64,
+}
+
+impl TrajectoryWriter {
+    /// Create the file at `path` and write its header.
+    pub fn create(path: &Path, csv: bool) -> Result<Self> {
+        let mut writer = BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+        );
+        if csv {
+            writeln!(writer, "id,sample_pos,x,y,z,size,gain")?;
+        } else {
+            write!(writer, "[")?;
+        }
+
+        Ok(Self {
+            writer,
+            csv,
+            rows: 0,
+        })
+    }
+
+    /// Write the dynamic objects of the OAMD payloads of the access unit
+    /// starting at stream position `sample_pos`.
+    pub fn write(&mut self, oamd: &[ObjectAudioMetadataPayload], sample_pos: u64) -> Result<()> {
+        for oamd in oamd {
+            for point in trajectory_points(oamd, sample_pos) {
+                self.write_point(&point)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_point(&mut self, point: &TrajectoryPoint) -> Result<()> {
+        if self.csv {
+            let gain = point
+                .gain
+                .map_or_else(|| "-inf".to_string(), |gain| gain.to_string());
+            writeln!(
+                self.writer,
+                "{},{},{},{},{},{},{gain}",
+                point.id, point.sample_pos, point.x, point.y, point.z, point.size
+            )?;
+        } else {
+            if self.rows > 0 {
+                write!(self.writer, ",")?;
+            }
+            writeln!(self.writer)?;
+            serde_json::to_writer(&mut self.writer, point)?;
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Close the JSON array and flush the file.
+    pub fn finish(&mut self) -> Result<()> {
+        if !self.csv {
+            writeln!(self.writer, "\n]")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Points of the dynamic objects of `oamd`, carried by the access unit
+/// starting at stream position `sample_pos`.
+///
+/// Each update block takes effect at the sample offset of the payload plus
+/// its block offset factor in units of 32 samples.
+fn trajectory_points(oamd: &ObjectAudioMetadataPayload, sample_pos: u64) -> Vec<TrajectoryPoint> {
+    let Some(object_element) = &oamd.object_element else {
+        return Vec::new();
+    };
+    let md_update_info = &object_element.md_update_info;
+    let sample_pos = sample_pos + md_update_info.sample_offset as u64 + oamd.evo_sample_offset;
+
+    // Bed objects of all instances precede the ISF and dynamic objects
+    let bed_channels = BedInstance::channel_ids(&oamd.program_assignment.bed_assignment)
+        .iter()
+        .map(Vec::len)
+        .sum::<usize>();
+    let positions = oamd.get_damf_pos();
+
+    let mut points = Vec::new();
+    for (object, (blocks, positions)) in object_element
+        .object_data
+        .iter()
+        .zip(&positions)
+        .enumerate()
+        .take(oamd.object_count)
+    {
+        for ((block, position), update_info) in blocks
+            .iter()
+            .zip(positions)
+            .zip(&md_update_info.block_update_info)
+        {
+            if block.b_object_in_bed_or_isf {
+                continue;
+            }
+
+            points.push(TrajectoryPoint {
+                id: (object + 10 - bed_channels) as u32,
+                sample_pos: sample_pos
+                    + update_info.block_offset_factor_bits as u64 * BLOCK_OFFSET_UNIT,
+                x: position[0],
+                y: position[1],
+                z: position[2],
+                size: block.object_render_info.object_size[0],
+                gain: match block.object_basic_info.object_gain {
+                    GAIN_MINUS_INFINITY => None,
+                    gain => Some(gain as f64),
+                },
+            });
+        }
+    }
+    points
+}
+
+#[test]
+fn trajectories_of_test_payload() -> Result<()> {
+    use truehd::structs::oamd::TEST_DATA;
+
+    let mut oamd = ObjectAudioMetadataPayload::read(TEST_DATA)?;
+    oamd.evo_sample_offset = 8;
+    let points = trajectory_points(&oamd, 1000);
+    assert!(!points.is_empty());
+
+    // One point per update block of each dynamic object, with the DAMF position
+    let object_element = oamd.object_element.as_ref().unwrap();
+    let positions = oamd.get_damf_pos();
+    let sample_pos = 1000 + 8 + object_element.md_update_info.sample_offset as u64;
+    let dynamic = object_element
+        .object_data
+        .iter()
+        .enumerate()
+        .filter(|(_, blocks)| !blocks[0].b_object_in_bed_or_isf)
+        .collect::<Vec<_>>();
+    assert_eq!(points.len(), dynamic.len());
+    for (point, &(object, blocks)) in points.iter().zip(&dynamic) {
+        assert_eq!(point.sample_pos, sample_pos);
+        assert_eq!([point.x, point.y, point.z], positions[object][0]);
+        assert_eq!(point.size, blocks[0].object_render_info.object_size[0]);
+        assert!((-1.0..=1.0).contains(&point.x));
+    }
+    let first_id = points[0].id;
+    assert!(first_id >= 10);
+    assert!(points.windows(2).all(|pair| pair[1].id == pair[0].id + 1));
+
+    // Both formats stream one row per point
+    let temp_dir = tempfile::TempDir::with_prefix("truehdd-")?;
+    let dir = temp_dir.path();
+    for csv in [false, true] {
+        let path = dir.join(if csv { "out.csv" } else { "out.json" });
+        let mut writer = TrajectoryWriter::create(&path, csv)?;
+        writer.write(std::slice::from_ref(&oamd), 1000)?;
+        writer.write(&[], 1040)?;
+        writer.write(std::slice::from_ref(&oamd), 1040)?;
+        writer.finish()?;
+
+        let text = std::fs::read_to_string(&path)?;
+        if csv {
+            let lines = text.lines().collect::<Vec<_>>();
+            assert_eq!(lines[0], "id,sample_pos,x,y,z,size,gain");
+            assert_eq!(lines.len(), 1 + 2 * points.len());
+            assert!(lines[1].starts_with(&format!("{first_id},{sample_pos},")));
+        } else {
+            let rows: Vec<serde_json::Value> = serde_json::from_str(&text)?;
+            assert_eq!(rows.len(), 2 * points.len());
+            assert_eq!(rows[0]["id"], first_id);
+            assert_eq!(rows[0]["sample_pos"], sample_pos);
+            assert_eq!(rows[points.len()]["sample_pos"], sample_pos + 40);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use crate::input::InputReader;
+use anyhow::Result;
+use std::path::Path;
+use truehd::process::{MAX_PRESENTATIONS, extract::Extractor, parse::Parser};
+
+/// Access units scanned for the high-resolution output timing before giving up.
+///
+/// The timing is serialized one bit per restart header, so the field takes
+/// several dozen major sync intervals to complete.
+const TRIM_DETECTION_MAX_ACCESS_UNITS: usize = 16384;
+
+/// Scan the start of the stream for the samples trimmed before its first access unit.
+///
+/// Only substream 0 is parsed. Returns `None` when the stream carries no
+/// high-resolution output timing.
+pub fn detect_start_trim(input_path: &Path, track: Option<u64>) -> Result<Option<u64>> {
+    log::info!("Scanning high-resolution output timing for start trim detection");
+
+    let mut input_reader = InputReader::with_track(input_path, track)?;
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+
+    let mut required_presentations = [false; MAX_PRESENTATIONS];
+    required_presentations[0] = true;
+    parser.set_required_presentations(&required_presentations);
+
+    let mut access_units = 0;
+    let mut timing = None;
+
+    input_reader.process_chunks(64 * 1024, |chunk| {
+        extractor.push_bytes(chunk);
+
+        for frame in extractor.by_ref().filter_map(Result::ok) {
+            if let Err(e) = parser.parse(&frame) {
+                log::debug!("Parse error during trim detection: {e:#}");
+            }
+            access_units += 1;
+
+            timing = parser.hires_output_timing();
+            if timing.is_some() || access_units >= TRIM_DETECTION_MAX_ACCESS_UNITS {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    })?;
+
+    Ok(timing.map(|timing| timing as u64))
+}
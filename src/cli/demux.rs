@@ -0,0 +1,84 @@
+use anyhow::{Context, Result, bail};
+use indicatif::MultiProgress;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use super::command::{Cli, DemuxArgs};
+use super::decode::output::is_stdout_path;
+use crate::input::InputReader;
+
+pub fn cmd_demux(args: &DemuxArgs, _cli: &Cli, _multi: Option<&MultiProgress>) -> Result<()> {
+    log::info!("Demuxing TrueHD track: {}", args.input.display());
+
+    let mut input_reader = open_matroska(&args.input, args.track)?;
+    let mut output: Box<dyn Write> = if is_stdout_path(&args.output_path) {
+        Box::new(BufWriter::new(std::io::stdout().lock()))
+    } else {
+        let file = File::create(&args.output_path)
+            .with_context(|| format!("Failed to create {}", args.output_path.display()))?;
+        Box::new(BufWriter::new(file))
+    };
+
+    let bytes = demux(&mut input_reader, &mut output)?;
+    output.flush()?;
+
+    match input_reader.track_number() {
+        Some(track) => log::info!("Extracted {bytes} bytes of TrueHD track {track}"),
+        None => log::warn!("The TrueHD track has no blocks"),
+    }
+
+    Ok(())
+}
+
+/// Opens the TrueHD track of a Matroska input, failing for other input
+fn open_matroska(input: &Path, track: Option<u64>) -> Result<InputReader> {
+    let input_reader = InputReader::with_track(input, track)?;
+    if !input_reader.is_matroska() {
+        bail!("{} is not a Matroska file", input.display());
+    }
+    Ok(input_reader)
+}
+
+/// Writes the TrueHD bitstream of `input_reader` to `output`, returning the
+/// bytes written
+fn demux(input_reader: &mut InputReader, output: &mut impl Write) -> Result<u64> {
+    let mut bytes = 0;
+    input_reader.process_chunks(64 * 1024, |chunk| {
+        output.write_all(chunk)?;
+        bytes += chunk.len() as u64;
+        Ok(true)
+    })?;
+
+    Ok(bytes)
+}
+
+#[cfg(feature = "mkv")]
+#[test]
+fn demux_matroska_fixture() -> Result<()> {
+    use truehd::process::EXAMPLE_DATA;
+
+    // An AC-3 track and a TrueHD track, each split into two blocks
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/cli/testdata/example.mkv");
+    let mut input_reader = open_matroska(&fixture, None)?;
+    let mut output = Vec::new();
+    assert_eq!(
+        demux(&mut input_reader, &mut output)?,
+        2 * EXAMPLE_DATA.len() as u64
+    );
+    assert_eq!(output, EXAMPLE_DATA.repeat(2));
+    assert_eq!(input_reader.track_number(), Some(2));
+
+    let mut input_reader = open_matroska(&fixture, Some(1))?;
+    let error = demux(&mut input_reader, &mut Vec::new()).unwrap_err();
+    assert!(error.to_string().contains("A_AC3"), "{error}");
+
+    // A raw bitstream is not demuxed
+    let temp_dir = tempfile::TempDir::with_prefix("truehdd-")?;
+    let dir = temp_dir.path();
+    let path = dir.join("example.thd");
+    std::fs::write(&path, &output)?;
+    assert!(open_matroska(&path, None).is_err());
+
+    Ok(())
+}
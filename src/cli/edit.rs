@@ -0,0 +1,365 @@
+use anyhow::{Context, Result, bail};
+use indicatif::MultiProgress;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use super::command::{Cli, EditArgs};
+use super::decode::output::is_stdout_path;
+use crate::input::InputReader;
+use truehd::process::PresentationType;
+use truehd::process::extract::Extractor;
+use truehd::process::parse::ParserState;
+use truehd::structs::sync::{MAJOR_SYNC_FBB, MajorSyncInfo};
+use truehd::utils::bitstream_io::{BitstreamWriter, BsIoSliceReader};
+use truehd::utils::errors::ExtractError;
+
+/// Size of the access unit header in front of the major sync
+pub(super) const ACCESS_UNIT_HEADER_SIZE: usize = 4;
+
+pub fn cmd_edit(args: &EditArgs, _cli: &Cli, _multi: Option<&MultiProgress>) -> Result<()> {
+    let edit = MetadataEdit {
+        presentation: args.presentation as usize,
+        dialnorm: args.dialnorm,
+        drc_enabled: args.drc_enabled,
+    };
+    edit.validate()?;
+
+    log::info!(
+        "Editing TrueHD stream: {} (presentation: {})",
+        args.input.display(),
+        args.presentation
+    );
+
+    let mut input_reader = InputReader::new(&args.input)?;
+    let mut output: Box<dyn Write> = if is_stdout_path(&args.output_path) {
+        Box::new(BufWriter::new(std::io::stdout().lock()))
+    } else {
+        let file = File::create(&args.output_path)
+            .with_context(|| format!("Failed to create {}", args.output_path.display()))?;
+        Box::new(BufWriter::new(file))
+    };
+
+    let mut editor = StreamEditor::new(edit);
+    input_reader.process_chunks(64 * 1024, |chunk| {
+        editor.push_bytes(chunk, &mut output)?;
+        Ok(true)
+    })?;
+    editor.finish(&mut output)?;
+    output.flush()?;
+
+    log::info!(
+        "Rewrote {} major syncs, copied {} bytes",
+        editor.major_syncs,
+        editor.bytes_written
+    );
+
+    Ok(())
+}
+
+/// Metadata changes applied to every major sync of a stream.
+#[derive(Debug, Clone, Copy)]
+struct MetadataEdit {
+    presentation: usize,
+    /// Dialogue level in dBFS
+    dialnorm: Option<i8>,
+    drc_enabled: Option<bool>,
+}
+
+impl MetadataEdit {
+    fn validate(&self) -> Result<()> {
+        if self.presentation > 3 {
+            bail!("Presentation index must be 0-3, got {}", self.presentation);
+        }
+        if self.dialnorm.is_none() && self.drc_enabled.is_none() {
+            bail!("Nothing to edit, use --dialnorm or --drc-enabled");
+        }
+        if let Some(dialnorm) = self.dialnorm {
+            // The 2ch dialogue norm is 6 bits, the others are 5 bits
+            let min = if self.presentation == 0 { -63 } else { -31 };
+            if !(min..=-1).contains(&dialnorm) {
+                bail!(
+                    "--dialnorm for presentation {} must be {min} to -1 dBFS, got {dialnorm}",
+                    self.presentation
+                );
+            }
+        }
+        if self.presentation == 3 && self.drc_enabled.is_some() {
+            bail!("Presentation 3 has no DRC enable flag");
+        }
+        Ok(())
+    }
+
+    /// Rewrites the major sync of an access unit in place.
+    ///
+    /// The major sync is re-serialized and its CRC recomputed, the length is
+    /// unchanged so the rest of the access unit stays valid.
+    fn apply(&self, access_unit: &mut [u8]) -> Result<()> {
+        let major_sync_data = access_unit
+            .get(ACCESS_UNIT_HEADER_SIZE..)
+            .context("Access unit is too short for a major sync")?;
+        let mut reader = BsIoSliceReader::from_slice(major_sync_data);
+        let mut major_sync = MajorSyncInfo::read(&mut ParserState::default(), &mut reader)?;
+        let len = (reader.position()? / 8) as usize;
+
+        if major_sync.format_sync == MAJOR_SYNC_FBB {
+            bail!("FBB streams carry no dialogue normalization or DRC metadata");
+        }
+        if major_sync
+            .presentation_map()
+            .presentation_type_by_index(self.presentation)
+            == PresentationType::Invalid
+        {
+            bail!("Presentation {} is not present", self.presentation);
+        }
+
+        // Refuse to touch anything the writer would not reproduce exactly
+        if serialize(&major_sync)? != major_sync_data[..len] {
+            bail!("Major sync does not re-serialize bit-exactly");
+        }
+
+        let channel_meaning = &mut major_sync.channel_meaning;
+        let (dialogue_norm, control_enabled) = match self.presentation {
+            0 => (
+                &mut channel_meaning.twoch_dialogue_norm,
+                Some(&mut channel_meaning.twoch_control_enabled),
+            ),
+            1 => (
+                &mut channel_meaning.sixch_dialogue_norm,
+                Some(&mut channel_meaning.sixch_control_enabled),
+            ),
+            2 => (
+                &mut channel_meaning.eightch_dialogue_norm,
+                Some(&mut channel_meaning.eightch_control_enabled),
+            ),
+            _ => match channel_meaning.extra_channel_meaning.as_mut() {
+                Some(extra) => (&mut extra.sixteench_dialogue_norm, None),
+                None => bail!("Presentation 3 has no extra channel meaning"),
+            },
+        };
+
+        if let Some(dialnorm) = self.dialnorm {
+            *dialogue_norm = dialnorm.unsigned_abs();
+        }
+        if let (Some(drc_enabled), Some(control_enabled)) = (self.drc_enabled, control_enabled) {
+            *control_enabled = drc_enabled;
+        }
+
+        access_unit[ACCESS_UNIT_HEADER_SIZE..ACCESS_UNIT_HEADER_SIZE + len]
+            .copy_from_slice(&serialize(&major_sync)?);
+
+        Ok(())
+    }
+}
+
+fn serialize(major_sync: &MajorSyncInfo) -> Result<Vec<u8>> {
+    let mut writer = BitstreamWriter::new();
+    major_sync.write(&mut ParserState::default(), &mut writer)?;
+    Ok(writer.take_bytes())
+}
+
+/// Copies a stream while rewriting the metadata of its major syncs.
+///
+/// Bytes are held back until the access unit containing them has been
+/// extracted, everything outside the major syncs is copied byte for byte.
+struct StreamEditor {
+    edit: MetadataEdit,
+    extractor: Extractor,
+    pending: Vec<u8>,
+    bytes_written: u64,
+    major_syncs: u64,
+}
+
+impl StreamEditor {
+    fn new(edit: MetadataEdit) -> Self {
+        Self {
+            edit,
+            extractor: Extractor::default(),
+            pending: Vec::new(),
+            bytes_written: 0,
+            major_syncs: 0,
+        }
+    }
+
+    fn push_bytes(&mut self, data: &[u8], output: &mut impl Write) -> Result<()> {
+        self.pending.extend_from_slice(data);
+        self.extractor.push_bytes(data);
+
+        while let Some(result) = self.extractor.next() {
+            match result {
+                Ok(frame) => {
+                    let start = (frame.byte_offset - self.bytes_written) as usize;
+                    let end = start + frame.data.len();
+
+                    if frame.is_major_sync() {
+                        self.edit
+                            .apply(&mut self.pending[start..end])
+                            .with_context(|| {
+                                format!(
+                                    "Failed to edit the major sync at byte offset {}",
+                                    frame.byte_offset
+                                )
+                            })?;
+                        self.major_syncs += 1;
+                    }
+
+                    output.write_all(&self.pending[..end])?;
+                    self.pending.drain(..end);
+                    self.bytes_written += end as u64;
+                }
+                Err(ExtractError::InsufficientData) => break,
+                Err(e) => log::warn!(
+                    "{e} at byte offset {}, copying the data unchanged",
+                    self.extractor.byte_offset()
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut impl Write) -> Result<()> {
+        output.write_all(&self.pending)?;
+        self.bytes_written += self.pending.len() as u64;
+        self.pending.clear();
+
+        if self.major_syncs == 0 {
+            bail!("No major sync found, nothing was edited");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn edit_stream(data: &[u8], edit: MetadataEdit) -> Result<Vec<u8>> {
+    let mut editor = StreamEditor::new(edit);
+    let mut output = Vec::new();
+    for chunk in data.chunks(1000) {
+        editor.push_bytes(chunk, &mut output)?;
+    }
+    editor.finish(&mut output)?;
+    Ok(output)
+}
+
+#[cfg(test)]
+fn decode_stream(data: &[u8], presentation: usize) -> Vec<(Option<MajorSyncInfo>, Vec<i32>)> {
+    use truehd::process::decode::Decoder;
+    use truehd::process::parse::Parser;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    let mut decoder = Decoder::default();
+    extractor.push_bytes(data);
+
+    extractor
+        .filter_map(Result::ok)
+        .map(|frame| {
+            let access_unit = parser.parse(&frame).unwrap();
+            let decoded = decoder
+                .decode_presentation(&access_unit, presentation)
+                .unwrap();
+            let pcm = decoded.pcm_data[..decoded.sample_length]
+                .iter()
+                .flat_map(|sample| sample[..decoded.channel_count].to_vec())
+                .collect();
+            (access_unit.major_sync_info, pcm)
+        })
+        .collect()
+}
+
+#[test]
+fn edit_rewrites_metadata_only() -> Result<()> {
+    use truehd::process::EXAMPLE_DATA;
+
+    let data = EXAMPLE_DATA.repeat(8);
+    let edited = edit_stream(
+        &data,
+        MetadataEdit {
+            presentation: 0,
+            dialnorm: Some(-20),
+            drc_enabled: Some(false),
+        },
+    )?;
+    assert_eq!(edited.len(), data.len());
+    assert_ne!(edited, data);
+
+    let original = decode_stream(&data, 0);
+    let decoded = decode_stream(&edited, 0);
+    assert_eq!(decoded.len(), original.len());
+
+    for ((major_sync, pcm), (original_major_sync, original_pcm)) in decoded.iter().zip(&original) {
+        assert_eq!(pcm, original_pcm);
+        assert_eq!(major_sync.is_some(), original_major_sync.is_some());
+        if let (Some(major_sync), Some(original_major_sync)) = (major_sync, original_major_sync) {
+            let channel_meaning = &major_sync.channel_meaning;
+            assert_eq!(channel_meaning.twoch_dialogue_norm, 20);
+            assert!(!channel_meaning.twoch_control_enabled);
+            // The other presentations are untouched
+            let original_channel_meaning = &original_major_sync.channel_meaning;
+            assert_eq!(
+                channel_meaning.sixch_dialogue_norm,
+                original_channel_meaning.sixch_dialogue_norm
+            );
+            assert_eq!(
+                channel_meaning.sixch_control_enabled,
+                original_channel_meaning.sixch_control_enabled
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn edit_keeps_data_outside_access_units() -> Result<()> {
+    use truehd::process::EXAMPLE_DATA;
+
+    let mut data = vec![0x55; 7];
+    data.extend_from_slice(&EXAMPLE_DATA.repeat(2));
+    data.extend_from_slice(&[0xAA; 3]);
+
+    let edited = edit_stream(
+        &data,
+        MetadataEdit {
+            presentation: 0,
+            dialnorm: Some(-27),
+            drc_enabled: None,
+        },
+    )?;
+    assert_eq!(edited.len(), data.len());
+    assert_eq!(edited[..7], data[..7]);
+    assert_eq!(edited[edited.len() - 3..], data[data.len() - 3..]);
+
+    Ok(())
+}
+
+#[test]
+fn edit_refuses_invalid_requests() {
+    use truehd::process::EXAMPLE_DATA;
+
+    let edit = |presentation, dialnorm, drc_enabled| MetadataEdit {
+        presentation,
+        dialnorm,
+        drc_enabled,
+    };
+    assert!(edit(0, None, None).validate().is_err());
+    assert!(edit(1, Some(-32), None).validate().is_err());
+    assert!(edit(0, Some(-63), None).validate().is_ok());
+    assert!(edit(3, None, Some(true)).validate().is_err());
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    let frame = extractor.next().unwrap().unwrap();
+    let mut major_sync = MajorSyncInfo::read(
+        &mut ParserState::default(),
+        &mut BsIoSliceReader::from_slice(&frame.data[ACCESS_UNIT_HEADER_SIZE..]),
+    )
+    .unwrap();
+    major_sync.format_sync = MAJOR_SYNC_FBB;
+    let mut access_unit = frame.data[..ACCESS_UNIT_HEADER_SIZE].to_vec();
+    access_unit.extend(serialize(&major_sync).unwrap());
+
+    let error = edit(0, Some(-20), None)
+        .apply(&mut access_unit)
+        .unwrap_err();
+    assert!(error.to_string().contains("FBB"));
+}
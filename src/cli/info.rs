@@ -1,27 +1,96 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use log::Level;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 
 use super::command::{Cli, InfoArgs};
+use super::decode::activity::{ChannelActivity, ChannelActivityMeter};
+use super::decode::loudness::{Loudness, LoudnessMeter};
+use super::verify::Verifier;
 use crate::input::InputReader;
 use crate::timestamp::time_str;
 use truehd::process::{
-    PresentationMap, PresentationType,
+    MAX_PRESENTATIONS, Metrics,
+    analyze::{
+        ConfigurationScanner, ConfigurationSpan, CorruptRegion, MAX_CORRUPT_REGIONS,
+        PresentationInfo, StreamInfo, StreamReport, build_presentations, channel_counts_known,
+    },
+    decode::{DecodeStats, Decoder},
     extract::{Extractor, Frame},
-    parse::Parser,
+    metadata::OAMD_PAYLOAD_ID,
+    parse::{DataRateStats, Parser, TimingBranchKind, TimingReport},
 };
 use truehd::structs::access_unit::AccessUnit;
-use truehd::structs::channel::{ChannelGroup, ChannelLabel};
+use truehd::structs::channel::ChannelLabel;
+use truehd::structs::sync::MAJOR_SYNC_FBB;
+use truehd::utils::timing::{FifoModel, FifoOccupancy, FifoStats};
 
 pub fn cmd_info(args: &InfoArgs, cli: &Cli, multi: Option<&MultiProgress>) -> Result<()> {
     log::info!("Analyzing TrueHD stream: {}", args.input.display());
 
-    let analysis_result = analyze_stream(&args.input, cli, multi)?;
+    if let Some(index) = args.dump_au {
+        let access_unit = find_access_unit(args, cli, index)?;
+        println!("{}", access_unit_json(&access_unit)?);
+        return Ok(());
+    }
+
+    let analysis_result = analyze_stream(args, cli, multi)?;
+
+    if args.json {
+        let Some((analysis, timestamp, frame_count, total_bytes, integrity)) = analysis_result
+        else {
+            bail!("No TrueHD major sync found in the file");
+        };
+        let report = InfoReport::new(
+            &analysis,
+            timestamp.as_ref(),
+            frame_count,
+            total_bytes,
+            integrity.as_ref().map(|(stats, _)| stats),
+        );
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
 
     match analysis_result {
-        Some((stream_info, _timestamp, frame_count, total_bytes)) => {
+        Some((stream_info, timestamp, frame_count, total_bytes, integrity)) => {
             // Final update with total frames and duration
-            update_final_stats(&stream_info, frame_count, total_bytes);
+            update_final_stats(&stream_info.report(timestamp.as_ref(), frame_count, total_bytes));
+
+            if let Some(data_rate) = &stream_info.data_rate {
+                display_data_rate(data_rate, stream_info.stream_info.sampling_frequency);
+            }
+
+            if let Some(fifo) = &stream_info.fifo {
+                display_fifo(fifo, stream_info.stream_info.sampling_frequency);
+            }
+
+            if let Some(timing) = &stream_info.timing {
+                display_timing(timing, stream_info.stream_info.sampling_frequency);
+            }
+
+            if let Some(evo_payloads) = &stream_info.evo_payloads {
+                display_evo_payloads(evo_payloads);
+            }
+
+            if let Some(configurations) = &stream_info.configurations {
+                display_configurations(configurations);
+            }
+
+            if let Some(loudness) = &stream_info.loudness {
+                display_loudness(loudness);
+            }
+
+            if let Some(activity) = &stream_info.channel_activity {
+                display_channel_activity(activity);
+            }
+
+            if let Some((stats, metrics)) = integrity {
+                display_integrity(&stats);
+                display_performance(&metrics, stream_info.stream_info.sampling_frequency);
+            }
         }
         None => {
             println!("No TrueHD major sync found in the file.");
@@ -32,19 +101,69 @@ pub fn cmd_info(args: &InfoArgs, cli: &Cli, multi: Option<&MultiProgress>) -> Re
     Ok(())
 }
 
+/// Parses the stream up to access unit `index` and returns it.
+///
+/// The preceding access units are parsed for the parser state the access
+/// unit depends on; their errors are only logged outside strict mode.
+fn find_access_unit(args: &InfoArgs, cli: &Cli, index: usize) -> Result<AccessUnit> {
+    let mut input_reader = InputReader::with_track(&args.input, args.track)?;
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    if cli.strict {
+        parser.set_fail_level(Level::Warn);
+    }
+    for (category, policy) in cli.category_policies()? {
+        extractor.set_category_policy(category, policy);
+        parser.set_category_policy(category, policy);
+    }
+
+    let mut frame_count = 0;
+    let mut found = None;
+    input_reader.process_chunks(64 * 1024, |chunk| {
+        extractor.push_bytes(chunk);
+
+        for frame in extractor.by_ref().filter_map(Result::ok) {
+            let result = parser.parse(&frame);
+            frame_count += 1;
+            if frame_count > index {
+                found = Some(result?);
+                return Ok(false);
+            }
+
+            if let Err(e) = result {
+                if cli.strict {
+                    return Err(e);
+                }
+                log::warn!("Parse error at frame {}: {e:#}", frame_count - 1);
+            }
+        }
+
+        Ok(true)
+    })?;
+
+    found.ok_or_else(|| anyhow!("Access unit {index} not found, the stream has {frame_count}"))
+}
+
+/// Parse tree of an access unit as JSON
+fn access_unit_json(access_unit: &AccessUnit) -> Result<String> {
+    Ok(serde_json::to_string_pretty(access_unit)?)
+}
+
 type AnalysisResultTuple = (
     AnalysisResult,
     Option<truehd::structs::timestamp::Timestamp>,
     usize,
     usize,
+    Option<(DecodeStats, Metrics)>,
 );
 
 fn analyze_stream(
-    input_path: &std::path::Path,
+    args: &InfoArgs,
     cli: &Cli,
     multi: Option<&MultiProgress>,
 ) -> Result<Option<AnalysisResultTuple>> {
-    let mut input_reader = InputReader::new(input_path)?;
+    let verify = args.verify;
+    let mut input_reader = InputReader::with_track(&args.input, args.track)?;
     let mut extractor = Extractor::default();
     let mut parser = Parser::default();
 
@@ -55,8 +174,53 @@ fn analyze_stream(
         Level::Error
     };
     parser.set_fail_level(fail_level);
-
-    let mut context = AnalysisContext::default();
+    let category_policies = cli.category_policies()?;
+    for &(category, policy) in &category_policies {
+        extractor.set_category_policy(category, policy);
+        parser.set_category_policy(category, policy);
+    }
+    parser.enable_metrics(verify);
+
+    let fifo_model = args.analyze_timing.then(|| {
+        let model = Arc::new(Mutex::new(FifoModel::default()));
+        let callback_model = model.clone();
+        parser.set_timing_callback(move |timing| {
+            callback_model.lock().unwrap().push(timing);
+        });
+        model
+    });
+
+    let mut context = AnalysisContext {
+        verifier: verify.then(|| {
+            let mut verifier = Verifier::new(fail_level, &category_policies);
+            verifier.enable_metrics();
+            verifier
+        }),
+        json: args.json,
+        analyze_bitrate: args.analyze_bitrate,
+        fifo_model,
+        timing: args.timing,
+        evo_payloads: args.evo_payloads.then(BTreeMap::new),
+        configurations: args.scan_all.then(ConfigurationScanner::default),
+        loudness: args.measure_loudness.then(|| {
+            let mut decoder = Decoder::default();
+            decoder.set_fail_level(fail_level);
+            for &(category, policy) in &category_policies {
+                decoder.set_category_policy(category, policy);
+            }
+            (decoder, LoudnessMeter::default())
+        }),
+        channel_activity: args.analyze_channels.then(|| {
+            let mut decoder = Decoder::default();
+            decoder.set_fail_level(fail_level);
+            for &(category, policy) in &category_policies {
+                decoder.set_category_policy(category, policy);
+            }
+            let limit = (!args.full).then_some(args.analyze_seconds);
+            (decoder, ChannelActivityMeter::default(), limit)
+        }),
+        ..Default::default()
+    };
 
     // Create progress bar for frame counting if enabled
     if let Some(multi) = multi {
@@ -74,7 +238,21 @@ fn analyze_stream(
         for frame_result in extractor.by_ref() {
             let frame = match frame_result {
                 Ok(frame) => frame,
-                Err(_) => continue,
+                Err(e) => {
+                    if let Some(region) = CorruptRegion::from_error(&e)
+                        && context.corrupt_regions.len() < MAX_CORRUPT_REGIONS
+                    {
+                        log::debug!(
+                            "Corrupt region at byte offset {} ({:#X}), {} bytes: {} check failed",
+                            region.offset,
+                            region.offset,
+                            region.length,
+                            region.check
+                        );
+                        context.corrupt_regions.push(region);
+                    }
+                    continue;
+                }
             };
 
             context.process_frame(&frame, &mut parser, cli)?;
@@ -83,7 +261,7 @@ fn analyze_stream(
         Ok(true)
     })?;
 
-    Ok(context.into_result())
+    Ok(context.into_result(&mut parser))
 }
 
 #[derive(Default)]
@@ -95,81 +273,174 @@ struct AnalysisContext {
     info_displayed: bool,
     pb: Option<ProgressBar>,
     total_bytes: usize,
+    verifier: Option<Verifier>,
+    /// Print nothing while analyzing, the result is written as JSON at the end
+    json: bool,
+    /// Parse every access unit to collect data rate statistics
+    analyze_bitrate: bool,
+    /// Decoder FIFO model fed by the parser, with `--analyze-timing`
+    fifo_model: Option<Arc<Mutex<FifoModel>>>,
+    /// Parse every access unit to report the timing branches, with `--timing`
+    timing: bool,
+    /// Number of evolution frame payloads by payload ID, with `--evo-payloads`
+    evo_payloads: Option<BTreeMap<u32, u64>>,
+    /// Stream configurations of the major syncs, with `--scan-all`
+    configurations: Option<ConfigurationScanner>,
+    /// Decoder and loudness meter of the highest presentation, with `--measure-loudness`
+    loudness: Option<(Decoder, LoudnessMeter)>,
+    /// Decoder, channel meter and seconds to measure of the highest
+    /// presentation, with `--analyze-channels`
+    channel_activity: Option<(Decoder, ChannelActivityMeter, Option<f64>)>,
+    /// The first regions skipped by the extractor
+    corrupt_regions: Vec<CorruptRegion>,
 }
 
 struct AnalysisResult {
     stream_info: StreamInfo,
     access_unit: AccessUnit,
     hires_timing: Option<u32>,
+    /// Drift of the last SMPTE timestamp in samples, if the stream has any
+    timestamp_drift: Option<i64>,
+    /// Data rate statistics over all access units, with `--analyze-bitrate`
+    data_rate: Option<DataRateStats>,
+    /// Decoder FIFO occupancy over all access units, with `--analyze-timing`
+    fifo: Option<FifoStats>,
+    /// Output timing, branches and latency changes, with `--timing`
+    timing: Option<TimingReport>,
+    /// Number of evolution frame payloads by payload ID, with `--evo-payloads`
+    evo_payloads: Option<BTreeMap<u32, u64>>,
+    /// Distinct stream configurations and their access units, with `--scan-all`
+    configurations: Option<Vec<ConfigurationSpan>>,
+    /// Loudness of the highest presentation, with `--measure-loudness`
+    loudness: Option<Loudness>,
+    /// Channel levels of the highest presentation, with `--analyze-channels`
+    channel_activity: Option<ChannelActivity>,
+    /// The first regions skipped by the extractor
+    corrupt_regions: Vec<CorruptRegion>,
 }
 
 impl AnalysisContext {
     fn process_frame(&mut self, frame: &Frame, parser: &mut Parser, cli: &Cli) -> Result<()> {
-        if self.analysis_result.is_none() || !self.hires_timing_displayed {
+        // Major syncs are read on their own, the access units need not be parsed
+        if let Some(scanner) = &mut self.configurations {
+            scanner.push_frame(frame);
+        }
+
+        // Timestamp drift is tracked over every access unit of streams carrying timestamps
+        if self.analysis_result.is_none()
+            || !self.hires_timing_displayed
+            || self.verifier.is_some()
+            || self.timestamp.is_some()
+            || self.analyze_bitrate
+            || self.fifo_model.is_some()
+            || self.timing
+            || self.evo_payloads.is_some()
+            || self.loudness.is_some()
+            || self.channel_activity_pending()
+            || !self.channel_counts_known()
+        {
             match parser.parse(frame) {
                 Ok(access_unit) => {
+                    for warning in parser.take_warnings() {
+                        log::warn!("{warning}");
+                    }
+
+                    if let Some(verifier) = &mut self.verifier {
+                        for (substream, e) in verifier.decode(&access_unit) {
+                            if cli.strict {
+                                return Err(e);
+                            }
+                            log::warn!(
+                                "Decode error at frame {} (substream {substream}): {e:#}",
+                                self.frame_count
+                            );
+                        }
+                    }
+
+                    if let Some((decoder, meter)) = &mut self.loudness {
+                        match decoder.decode_presentation(&access_unit, MAX_PRESENTATIONS - 1) {
+                            Ok(decoded) => meter.process(&decoded, 0),
+                            Err(e) if cli.strict => return Err(e),
+                            Err(e) => {
+                                log::warn!("Decode error at frame {}: {e:#}", self.frame_count)
+                            }
+                        }
+                    }
+
+                    let channel_activity_pending = self.channel_activity_pending();
+                    if let Some((decoder, meter, _)) = &mut self.channel_activity
+                        && channel_activity_pending
+                    {
+                        match decoder.decode_presentation(&access_unit, MAX_PRESENTATIONS - 1) {
+                            Ok(decoded) => meter.process(&decoded),
+                            Err(e) if cli.strict => return Err(e),
+                            Err(e) => {
+                                log::warn!("Decode error at frame {}: {e:#}", self.frame_count)
+                            }
+                        }
+                    }
+
+                    if let (Some(counts), Some(evo_frame)) = (
+                        &mut self.evo_payloads,
+                        access_unit
+                            .extra_data
+                            .as_ref()
+                            .and_then(|extra_data| extra_data.evo_frame.as_ref()),
+                    ) {
+                        for evo_payload in &evo_frame.evo_payloads {
+                            *counts.entry(evo_payload.evo_payload_id).or_default() += 1;
+                        }
+                    }
+
                     if let Some(ts) = &frame.timestamp {
                         if self.timestamp.is_none() {
                             self.timestamp = Some(ts.clone());
                         }
                     }
 
-                    if let Some(major_sync) = &access_unit.major_sync_info {
-                        if self.analysis_result.is_none() {
-                            let stream_info = StreamInfo::from_major_sync(major_sync)?;
-                            self.analysis_result = Some(AnalysisResult {
-                                stream_info,
-                                access_unit,
-                                hires_timing: None,
-                            });
-
-                            // Display immediate info now that we have the major sync
-                            if !self.info_displayed {
-                                self.display_immediate_info();
-                                self.info_displayed = true;
+                    match &mut self.analysis_result {
+                        None => {
+                            if let Some(major_sync) = &access_unit.major_sync_info {
+                                let stream_info = StreamInfo::from_major_sync(major_sync)?;
+                                self.analysis_result = Some(AnalysisResult {
+                                    stream_info,
+                                    access_unit,
+                                    hires_timing: None,
+                                    timestamp_drift: None,
+                                    data_rate: None,
+                                    fifo: None,
+                                    timing: None,
+                                    evo_payloads: None,
+                                    configurations: None,
+                                    loudness: None,
+                                    channel_activity: None,
+                                    corrupt_regions: Vec::new(),
+                                });
                             }
                         }
-
-                        if !self.hires_timing_displayed {
-                            if let Some(timing) = parser.hires_output_timing() {
-                                if let Some(result) = &mut self.analysis_result {
-                                    result.hires_timing = Some(timing as u32);
-                                }
-
-                                // Print trim detection immediately when available
-                                // Temporarily pause progress bar for clean output
-                                if let Some(ref pb) = self.pb {
-                                    pb.suspend(|| {
-                                        print!("Trim detection              ");
-                                        if timing != 0 {
-                                            println!("{timing} samples are trimmed from the beginning of the stream");
-                                        } else {
-                                            println!("No trimmed samples detected");
-                                        }
-                                        println!();
-                                    });
-                                } else {
-                                    print!("Trim detection              ");
-                                    if timing != 0 {
-                                        println!(
-                                            "{timing} samples are trimmed from the beginning of the stream"
-                                        );
-                                    } else {
-                                        println!("No trimmed samples detected");
-                                    }
-                                    println!();
-                                }
-
-                                self.hires_timing_displayed = true;
-                            }
+                        // Substreams without a restart header in the major sync
+                        // access unit get their channel count from a later one
+                        Some(result) if !result.channel_counts_known() => {
+                            result.access_unit.channel_counts = access_unit.channel_counts;
                         }
+                        Some(_) => {}
+                    }
+
+                    // Display immediate info once the presentations are known
+                    if !self.info_displayed && !self.json && self.channel_counts_known() {
+                        self.display_immediate_info();
+                        self.info_displayed = true;
+                    }
+
+                    if !self.hires_timing_displayed && (self.info_displayed || self.json) {
+                        self.display_trim_detection(parser);
                     }
                 }
                 Err(e) => {
                     if cli.strict {
                         return Err(e);
                     }
-                    log::warn!("Parse error at frame {}: {e}", self.frame_count);
+                    log::warn!("Parse error at frame {}: {e:#}", self.frame_count);
                 }
             }
         }
@@ -186,6 +457,50 @@ impl AnalysisContext {
         Ok(())
     }
 
+    /// Whether `--analyze-channels` has not yet measured its duration
+    fn channel_activity_pending(&self) -> bool {
+        self.channel_activity
+            .as_ref()
+            .is_some_and(|(_, meter, limit)| limit.is_none_or(|limit| meter.duration() < limit))
+    }
+
+    /// Whether the channel counts of all presentations are known, or no
+    /// major sync has been found yet
+    fn channel_counts_known(&self) -> bool {
+        self.analysis_result
+            .as_ref()
+            .is_none_or(AnalysisResult::channel_counts_known)
+    }
+
+    /// Print trim detection as soon as the high-resolution output timing is available
+    fn display_trim_detection(&mut self, parser: &Parser) {
+        let Some(timing) = parser.hires_output_timing() else {
+            return;
+        };
+        if let Some(result) = &mut self.analysis_result {
+            result.hires_timing = Some(timing as u32);
+        }
+        self.hires_timing_displayed = true;
+
+        if self.json {
+            return;
+        }
+        let display = || {
+            print!("Trim detection              ");
+            if timing != 0 {
+                println!("{timing} samples are trimmed from the beginning of the stream");
+            } else {
+                println!("No trimmed samples detected");
+            }
+            println!();
+        };
+        // Temporarily pause progress bar for clean output
+        match &self.pb {
+            Some(pb) => pb.suspend(display),
+            None => display(),
+        }
+    }
+
     fn display_immediate_info(&self) {
         if let Some(ref analysis) = self.analysis_result {
             if let Some(ref pb) = self.pb {
@@ -220,71 +535,474 @@ impl AnalysisContext {
         }
     }
 
-    fn into_result(self) -> Option<AnalysisResultTuple> {
+    fn into_result(mut self, parser: &mut Parser) -> Option<AnalysisResultTuple> {
+        // Channel counts of substreams that never restarted remain unknown
+        if !self.info_displayed && !self.json && self.analysis_result.is_some() {
+            self.display_immediate_info();
+            self.info_displayed = true;
+            if !self.hires_timing_displayed {
+                self.display_trim_detection(parser);
+            }
+        }
+
         // Finish progress bar
         if let Some(ref pb) = self.pb {
             pb.finish_and_clear();
         }
 
-        self.analysis_result
-            .map(|result| (result, self.timestamp, self.frame_count, self.total_bytes))
+        let integrity = self.verifier.map(|mut verifier| {
+            let mut metrics = parser.take_metrics().unwrap_or_default();
+            if let Some(decode_metrics) = verifier.take_metrics() {
+                metrics.merge(&decode_metrics);
+            }
+            (verifier.stats(parser), metrics)
+        });
+
+        let timestamp_drift = self.timestamp.is_some().then(|| parser.timestamp_drift());
+        let data_rate = self
+            .analyze_bitrate
+            .then(|| parser.data_rate_stats())
+            .flatten();
+        let timing = self.timing.then(|| parser.timing_report());
+        self.analysis_result.map(|mut result| {
+            result.timestamp_drift = timestamp_drift;
+            result.data_rate = data_rate;
+            result.fifo = self
+                .fifo_model
+                .map(|model| model.lock().unwrap().stats().clone());
+            result.timing = timing;
+            result.evo_payloads = self.evo_payloads;
+            result.configurations = self.configurations.map(ConfigurationScanner::finish);
+            result.loudness = self.loudness.map(|(_, meter)| meter.result());
+            result.channel_activity = self.channel_activity.map(|(_, meter, _)| meter.result());
+            result.corrupt_regions = self.corrupt_regions;
+            (
+                result,
+                self.timestamp,
+                self.frame_count,
+                self.total_bytes,
+                integrity,
+            )
+        })
     }
 }
 
-fn update_final_stats(analysis: &AnalysisResult, frame_count: usize, total_bytes: usize) {
+fn update_final_stats(report: &StreamReport) {
     println!("Analysis Summary");
-    println!("  Frames processed          {frame_count}");
+    println!("  Frames processed          {}", report.frames);
 
     // Format file size
-    let size_mb = total_bytes as f64 / 1_000_000.0;
-    println!("  Size                      {size_mb:.2} MB ({total_bytes} bytes)");
+    let size_mb = report.bytes as f64 / 1_000_000.0;
+    println!(
+        "  Size                      {size_mb:.2} MB ({} bytes)",
+        report.bytes
+    );
 
-    // Calculate and display duration
-    if let Ok(samples_per_au) = analysis
-        .access_unit
-        .major_sync_info
-        .as_ref()
-        .unwrap()
-        .format_info
-        .samples_per_au()
-    {
-        let total_samples = frame_count * samples_per_au;
-        let duration_secs = total_samples as f64 / analysis.stream_info.sampling_frequency as f64;
+    if let Some(duration_secs) = report.duration {
         let duration_str = time_str(duration_secs);
         println!("  Duration                  {duration_str}");
 
-        // Calculate average data rate
-        if duration_secs > 0.0 {
-            let avg_data_rate_kbps = (total_bytes as f64 * 8.0) / (duration_secs * 1000.0);
+        if let Some(avg_data_rate_kbps) = report.average_data_rate {
             println!("  Average data rate         {avg_data_rate_kbps:.1} kbps");
         }
     }
 
+    if let Some(drift) = report.timestamp_drift {
+        let drift_ms = drift as f64 * 1000.0 / report.stream.sampling_frequency as f64;
+        println!("  Timestamp drift           {drift} samples ({drift_ms:+.3} ms)");
+    }
+
     println!();
 }
 
-struct StreamInfo {
-    format_sync: String,
-    sampling_frequency: u32,
-    variable_rate: bool,
-    peak_data_rate: u32,
-    substreams: usize,
-    is_atmos: bool,
-}
-
-impl StreamInfo {
-    fn from_major_sync(major_sync: &truehd::structs::sync::MajorSyncInfo) -> Result<Self> {
-        Ok(Self {
-            format_sync: format!("{:08X}", major_sync.format_sync),
-            sampling_frequency: major_sync.format_info.sampling_frequency_1()?,
-            variable_rate: major_sync.variable_rate,
-            peak_data_rate: (major_sync.peak_data_rate as u32
-                * major_sync.format_info.sampling_frequency_1()?)
-                / 16000,
-            substreams: major_sync.substreams,
-            is_atmos: major_sync.substream_info >> 7 != 0,
-        })
+fn display_data_rate(data_rate: &DataRateStats, sampling_frequency: u32) {
+    let kbps = |rate: u64| rate as f64 / 1000.0;
+
+    println!("Data rate analysis");
+    println!("  Access units              {}", data_rate.access_units);
+    println!(
+        "  Minimum                   {:.1} kbps",
+        kbps(data_rate.min)
+    );
+    println!(
+        "  Maximum                   {:.1} kbps",
+        kbps(data_rate.max)
+    );
+    println!(
+        "  Mean                      {:.1} kbps",
+        data_rate.mean / 1000.0
+    );
+
+    let peak_time = data_rate.peak_sample as f64 / sampling_frequency as f64;
+    println!(
+        "  Peak                      AU {} at {}",
+        data_rate.peak_au,
+        time_str(peak_time)
+    );
+
+    println!("  Histogram");
+    for bucket in &data_rate.histogram {
+        let share = bucket.access_units as f64 * 100.0 / data_rate.access_units as f64;
+        let range = format!("{:.1} - {:.1} kbps", kbps(bucket.min), kbps(bucket.max));
+        println!("    {range:<26}{:>10} ({share:5.1}%)", bucket.access_units);
     }
+
+    println!();
+}
+
+fn display_fifo(fifo: &FifoStats, sampling_frequency: u32) {
+    let occupancy = |occupancy: &FifoOccupancy| {
+        format!(
+            "{} bytes, {} samples ({:.1} ms) at AU {}",
+            occupancy.bytes,
+            occupancy.samples,
+            occupancy.samples as f64 * 1000.0 / sampling_frequency as f64,
+            occupancy.au_index
+        )
+    };
+
+    println!("Decoder FIFO timing");
+    println!("  Access units              {}", fifo.access_units);
+    if let Some(max) = &fifo.max {
+        println!("  Peak occupancy            {}", occupancy(max));
+    }
+    if let Some(min) = &fifo.min {
+        println!("  Minimum occupancy         {}", occupancy(min));
+    }
+    match &fifo.first_underflow {
+        Some(underflow) => println!(
+            "  First underflow           AU {}, {} samples late",
+            underflow.au_index, underflow.samples
+        ),
+        None => println!("  First underflow           none"),
+    }
+    match &fifo.first_overflow {
+        Some(overflow) => println!(
+            "  First overflow            AU {}, {} samples buffered",
+            overflow.au_index, overflow.samples
+        ),
+        None => println!("  First overflow            none"),
+    }
+
+    println!();
+}
+
+fn display_timing(timing: &TimingReport, sampling_frequency: u32) {
+    let ms = |samples: usize| samples as f64 * 1000.0 / sampling_frequency as f64;
+
+    println!("Timing");
+    println!("  Access units              {}", timing.access_units);
+    println!("  Last input timing         {}", timing.input_timing);
+    println!("  Last output timing        {}", timing.output_timing);
+    println!(
+        "  Last latency              {} samples ({:.1} ms)",
+        timing.latency,
+        ms(timing.latency)
+    );
+    println!("  Last advance              {} samples", timing.advance);
+    println!("  Output timing samples     {}", timing.output_samples);
+    println!(
+        "  Peak data rate            {:.1} kbps at AU {}",
+        timing.max_data_rate as f64 / 1000.0,
+        timing.max_data_rate_au
+    );
+
+    if timing.branches.is_empty() {
+        println!("  Branches                  none");
+    } else {
+        println!("  Branches");
+        println!("    {:<10}{:<20}Latency", "AU", "Kind");
+        for branch in &timing.branches {
+            let kind = match branch.kind {
+                TimingBranchKind::SeamlessBranch => "seamless branch".to_string(),
+                TimingBranchKind::StreamRestart => "stream restart".to_string(),
+                TimingBranchKind::BranchRepaired { samples } => format!("repaired {samples:+}"),
+                TimingBranchKind::StreamStart => "stream start".to_string(),
+                TimingBranchKind::LatencyChange => "latency change".to_string(),
+            };
+            println!(
+                "    {:<10}{kind:<20}{} -> {} samples",
+                branch.au, branch.latency_before, branch.latency_after
+            );
+        }
+        if timing.omitted_branches > 0 {
+            println!("    ... and {} more", timing.omitted_branches);
+        }
+    }
+
+    println!();
+}
+
+fn display_loudness(loudness: &Loudness) {
+    println!("Loudness");
+    match loudness.integrated {
+        Some(integrated) => println!("  Integrated                {integrated:.1} LUFS"),
+        None => println!("  Integrated                -"),
+    }
+    match loudness.range {
+        Some(range) => println!("  Loudness range            {range:.1} LU"),
+        None => println!("  Loudness range            -"),
+    }
+    println!("  True peak");
+    for peak in &loudness.true_peaks {
+        let name = format!("    {}", peak.channel);
+        match peak.true_peak {
+            Some(true_peak) => println!("{name:<28}{true_peak:.1} dBTP"),
+            None => println!("{name:<28}-inf dBTP"),
+        }
+    }
+    println!();
+}
+
+fn display_channel_activity(activity: &ChannelActivity) {
+    println!("Channel activity");
+    println!(
+        "  Duration measured         {}",
+        time_str(activity.duration)
+    );
+    for channel in &activity.channels {
+        let name = format!("  {}", channel.channel);
+        match channel.rms {
+            Some(rms) => println!("{name:<28}{rms:.1} dBFS RMS"),
+            None => println!("{name:<28}digital silence"),
+        }
+    }
+    println!();
+}
+
+fn display_configurations(configurations: &[ConfigurationSpan]) {
+    println!("Stream configuration changes");
+    match configurations.len() {
+        0 => println!("  None"),
+        1 => println!("  None, the stream keeps one configuration"),
+        _ => {}
+    }
+    for (index, span) in configurations.iter().enumerate() {
+        let configuration = &span.configuration;
+        println!("  Configuration {index}");
+        println!(
+            "    Access units            {} - {} ({} major syncs)",
+            span.first_au, span.last_au, span.major_syncs
+        );
+        println!(
+            "    Substream info          {:#04X}",
+            configuration.substream_info
+        );
+        println!(
+            "    Extended substream info {:#03X}",
+            configuration.extended_substream_info
+        );
+        match configuration.format_info.sampling_frequency_1() {
+            Ok(sampling_frequency) => {
+                println!("    Sampling rate           {sampling_frequency} Hz")
+            }
+            Err(_) => println!("    Sampling rate           unknown"),
+        }
+        let channels = |channels: &[ChannelLabel]| {
+            channels
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        if !configuration.sixch_channels.is_empty() {
+            println!(
+                "    6ch assignment          {}",
+                channels(&configuration.sixch_channels)
+            );
+        }
+        if !configuration.eightch_channels.is_empty() {
+            println!(
+                "    8ch assignment          {}",
+                channels(&configuration.eightch_channels)
+            );
+        }
+        if let Some(sixteench_channels) = &configuration.sixteench_channels {
+            println!(
+                "    16ch assignment         {}",
+                channels(sixteench_channels)
+            );
+        }
+    }
+    println!();
+}
+
+fn display_evo_payloads(evo_payloads: &BTreeMap<u32, u64>) {
+    println!("Evolution payloads");
+    if evo_payloads.is_empty() {
+        println!("  None");
+    }
+    for (&id, count) in evo_payloads {
+        let name = format!("ID {id}{}", evo_payload_name(id));
+        println!("  {name:<26}{count}");
+    }
+    println!();
+}
+
+/// Description of the known evolution payload IDs
+fn evo_payload_name(id: u32) -> &'static str {
+    match id {
+        OAMD_PAYLOAD_ID => " (OAMD)",
+        _ => "",
+    }
+}
+
+impl AnalysisResult {
+    /// Whether the channel count of every substream is known
+    fn channel_counts_known(&self) -> bool {
+        channel_counts_known(&self.access_unit)
+    }
+
+    /// Summary of `frame_count` access units of `total_bytes` bytes
+    fn report(
+        &self,
+        timestamp: Option<&truehd::structs::timestamp::Timestamp>,
+        frame_count: usize,
+        total_bytes: usize,
+    ) -> StreamReport {
+        let mut report = StreamReport::new(
+            self.stream_info.clone(),
+            &self.access_unit,
+            timestamp,
+            frame_count,
+            total_bytes,
+        );
+        report.trim_samples = self.hires_timing;
+        report.timestamp_drift = self.timestamp_drift;
+        report.corrupt_regions = self.corrupt_regions.clone();
+        report
+    }
+}
+
+/// Stream analysis written by `info --json`
+#[derive(Serialize)]
+struct InfoReport<'a> {
+    #[serde(flatten)]
+    report: StreamReport,
+    /// Per access unit data rate statistics in bits per second, with `--analyze-bitrate`
+    data_rate: Option<&'a DataRateStats>,
+    /// Decoder FIFO occupancy, with `--analyze-timing`
+    fifo: Option<&'a FifoStats>,
+    /// Output timing, branches and latency changes, with `--timing`
+    timing: Option<&'a TimingReport>,
+    /// Number of evolution frame payloads by payload ID, with `--evo-payloads`
+    evo_payloads: Option<Vec<EvoPayloadCount>>,
+    /// Distinct stream configurations and their access units, with `--scan-all`
+    configurations: Option<&'a [ConfigurationSpan]>,
+    integrity: Option<IntegrityReport>,
+    /// Loudness of the highest presentation, with `--measure-loudness`
+    loudness: Option<&'a Loudness>,
+    /// Channel levels of the highest presentation, with `--analyze-channels`
+    channel_activity: Option<&'a ChannelActivity>,
+}
+
+/// Occurrences of an evolution payload ID in `info --evo-payloads --json`
+#[derive(Serialize)]
+struct EvoPayloadCount {
+    id: u32,
+    count: u64,
+}
+
+/// Integrity summary of `info --verify --json`
+#[derive(Serialize)]
+struct IntegrityReport {
+    access_units: u64,
+    failures: u64,
+    first_failure_au: Option<u64>,
+    last_failure_au: Option<u64>,
+}
+
+impl<'a> InfoReport<'a> {
+    fn new(
+        analysis: &'a AnalysisResult,
+        timestamp: Option<&truehd::structs::timestamp::Timestamp>,
+        frame_count: usize,
+        total_bytes: usize,
+        stats: Option<&DecodeStats>,
+    ) -> Self {
+        Self {
+            report: analysis.report(timestamp, frame_count, total_bytes),
+            data_rate: analysis.data_rate.as_ref(),
+            fifo: analysis.fifo.as_ref(),
+            timing: analysis.timing.as_ref(),
+            evo_payloads: analysis.evo_payloads.as_ref().map(|evo_payloads| {
+                evo_payloads
+                    .iter()
+                    .map(|(&id, &count)| EvoPayloadCount { id, count })
+                    .collect()
+            }),
+            configurations: analysis.configurations.as_deref(),
+            integrity: stats.map(|stats| IntegrityReport {
+                access_units: stats.access_units,
+                failures: stats.failures(),
+                first_failure_au: stats.first_failure_au(),
+                last_failure_au: stats.last_failure_au(),
+            }),
+            loudness: analysis.loudness.as_ref(),
+            channel_activity: analysis.channel_activity.as_ref(),
+        }
+    }
+}
+
+fn display_integrity(stats: &DecodeStats) {
+    println!("Integrity");
+    println!("  Access units verified     {}", stats.access_units);
+    println!("  Failures                  {}", stats.failures());
+
+    if let (Some(first), Some(last)) = (stats.first_failure_au(), stats.last_failure_au()) {
+        println!("  First failure             AU {first}");
+        println!("  Last failure              AU {last}");
+    }
+
+    for (index, substream) in stats.substreams.iter().enumerate() {
+        if substream.failures() == 0 {
+            continue;
+        }
+        println!("  Substream {index}");
+        println!(
+            "    Lossless check          {} mismatches",
+            substream.lossless_check_mismatches
+        );
+        println!(
+            "    Recorrelator            {} saturations",
+            substream.recorrelator_saturations
+        );
+        println!(
+            "    Huffman decode          {} saturations",
+            substream.huffman_saturations
+        );
+    }
+
+    println!();
+}
+
+fn display_performance(metrics: &Metrics, sampling_frequency: u32) {
+    println!("Performance");
+    println!(
+        "  Parse time                {:.3} s",
+        metrics.parse_time.as_secs_f64()
+    );
+    if let Some(bytes_per_second) = metrics.parse_bytes_per_second() {
+        println!(
+            "  Parse throughput          {:.1} MB/s",
+            bytes_per_second / 1_000_000.0
+        );
+    }
+    println!(
+        "  Decode time               {:.3} s (all presentations)",
+        metrics.decode_time.as_secs_f64()
+    );
+    if let Some(factor) = metrics.decode_realtime_factor(sampling_frequency) {
+        println!("  Decode speed              {factor:.1}x realtime");
+    }
+
+    for (index, blocks) in metrics.blocks.iter().enumerate() {
+        if *blocks > 0 {
+            println!("  Substream {index} blocks        {blocks}");
+        }
+    }
+
+    println!();
 }
 
 fn display_stream_info(info: &StreamInfo) {
@@ -298,21 +1016,6 @@ fn display_stream_info(info: &StreamInfo) {
     println!();
 }
 
-#[derive(Default, Clone)]
-struct PresentationInfo {
-    index: usize,
-    channels: u8,
-    presentation_type: Option<PresentationType>,
-    twoch_format: Option<ChannelGroup>,
-    sixch_ex: Option<String>,
-    assignments: Vec<ChannelLabel>,
-    control: Option<bool>,
-    dialogue_level: i8,
-    mix_level: u8,
-    // 16ch
-    chan_distribution: Option<bool>,
-}
-
 fn display_presentation_info(info: &PresentationInfo) {
     println!("  Presentation {}", info.index);
 
@@ -328,7 +1031,10 @@ fn display_basic_info(info: &PresentationInfo) {
     } else {
         "channels"
     };
-    println!("    Number of {entity_type:10}    {}", info.channels);
+    match info.channels {
+        Some(channels) => println!("    Number of {entity_type:10}    {channels}"),
+        None => println!("    Number of {entity_type:10}    unknown"),
+    }
 
     if let Some(presentation_type) = &info.presentation_type {
         println!("    Presentation type       {presentation_type}");
@@ -346,16 +1052,18 @@ fn display_format_info(info: &PresentationInfo) {
 }
 
 fn display_channel_info(info: &PresentationInfo) {
-    if !info.assignments.is_empty() {
+    if let Some(assignment) = info.unknown_assignment {
+        println!("    Channel assignment      unknown assignment ({assignment:#X})");
+    } else if !info.assignments.is_empty() {
         let label = if info.index == 3 {
-            "Bed configuration "
+            "Element assignment"
         } else {
             "Channel assignment"
         };
         let assignments = info
             .assignments
             .iter()
-            .map(|c| format!("{c:?}"))
+            .map(|c| c.to_string())
             .collect::<Vec<_>>()
             .join(", ");
         println!("    {label:20}    {assignments}");
@@ -380,170 +1088,57 @@ fn display_audio_control_info(info: &PresentationInfo) {
 
 fn display_presentations(access_unit: &AccessUnit) {
     println!("Presentation Information");
-    let major_sync = access_unit.major_sync_info.as_ref().unwrap();
-
-    let presentation_builder = PresentationBuilder::new(major_sync, access_unit);
-    let presentations = presentation_builder.build_all_presentations();
-
-    for presentation in presentations {
-        display_presentation_info(&presentation);
+    let is_fbb = access_unit
+        .major_sync_info
+        .as_ref()
+        .is_some_and(|major_sync| major_sync.format_sync == MAJOR_SYNC_FBB);
+
+    for presentation in build_presentations(access_unit) {
+        if is_fbb {
+            // FBB streams carry no downmix or loudness parameters
+            println!("  Presentation {}", presentation.index);
+            display_basic_info(&presentation);
+            display_channel_info(&presentation);
+        } else {
+            display_presentation_info(&presentation);
+        }
     }
     println!();
 }
 
-struct PresentationBuilder<'a> {
-    major_sync: &'a truehd::structs::sync::MajorSyncInfo,
-    access_unit: &'a AccessUnit,
-    presentation_map: PresentationMap,
-}
-
-impl<'a> PresentationBuilder<'a> {
-    fn new(
-        major_sync: &'a truehd::structs::sync::MajorSyncInfo,
-        access_unit: &'a AccessUnit,
-    ) -> Self {
-        let presentation_map = PresentationMap::with_substream_info(
-            major_sync.substream_info,
-            major_sync.extended_substream_info,
-        );
-
-        Self {
-            major_sync,
-            access_unit,
-            presentation_map,
-        }
-    }
-
-    fn build_all_presentations(&self) -> Vec<PresentationInfo> {
-        let mut presentations = Vec::new();
-        let mut last_presentation = PresentationInfo::default();
-
-        for index in 0..self.major_sync.substreams.max(3) {
-            let presentation = if index < self.major_sync.substreams {
-                let info = self.build_presentation_for_substream(index);
-                last_presentation = info.clone();
-                info
-            } else {
-                last_presentation.clone()
-            };
-
-            presentations.push(self.finalize_presentation(presentation, index));
-        }
-
-        presentations
-    }
-
-    fn build_presentation_for_substream(&self, index: usize) -> PresentationInfo {
-        let mut presentation = PresentationInfo {
-            channels: self.access_unit.substream_segment[index].block[0]
-                .restart_header
-                .as_ref()
-                .unwrap()
-                .max_matrix_chan
-                + 1,
-            ..Default::default()
-        };
+#[test]
+fn example_access_unit_json() -> Result<()> {
+    use truehd::process::EXAMPLE_DATA;
 
-        match index {
-            0 => self.configure_twoch_presentation(&mut presentation),
-            1 => self.configure_sixch_presentation(&mut presentation),
-            2 => self.configure_eightch_presentation(&mut presentation),
-            3 => self.configure_sixteench_presentation(&mut presentation),
-            _ => unreachable!(),
-        }
-
-        presentation
-    }
-
-    fn configure_twoch_presentation(&self, presentation: &mut PresentationInfo) {
-        let format_info = &self.major_sync.format_info;
-        let channel_meaning = &self.major_sync.channel_meaning;
-
-        presentation.twoch_format =
-            Some(ChannelGroup::from_modifier(format_info.twoch_decoder_channel_modifier).unwrap());
-        presentation.control = Some(channel_meaning.twoch_control_enabled);
-        presentation.dialogue_level = -(channel_meaning.twoch_dialogue_norm as i8);
-        presentation.mix_level = channel_meaning.twoch_mix_level + 70;
-    }
-
-    fn configure_sixch_presentation(&self, presentation: &mut PresentationInfo) {
-        let format_info = &self.major_sync.format_info;
-        let channel_meaning = &self.major_sync.channel_meaning;
-
-        let assignment = format_info.sixch_decoder_channel_assignment;
-        if assignment == 1 {
-            presentation.twoch_format = Some(
-                ChannelGroup::from_modifier(format_info.twoch_decoder_channel_modifier).unwrap(),
-            );
-        }
-
-        if assignment & 8 != 0 {
-            presentation.sixch_ex = Some(
-                match format_info.twoch_decoder_channel_modifier {
-                    0 => "Not indicated",
-                    1 => "Not encoded",
-                    2 => "Encoded",
-                    _ => "Reserved",
-                }
-                .to_string(),
-            );
-        }
-
-        presentation.assignments =
-            ChannelLabel::from_sixch_channel(format_info.sixch_decoder_channel_assignment).unwrap();
-        presentation.control = Some(channel_meaning.sixch_control_enabled);
-        presentation.dialogue_level = -(channel_meaning.sixch_dialogue_norm as i8);
-        presentation.mix_level = channel_meaning.sixch_mix_level + 70;
-    }
-
-    fn configure_eightch_presentation(&self, presentation: &mut PresentationInfo) {
-        let format_info = &self.major_sync.format_info;
-        let channel_meaning = &self.major_sync.channel_meaning;
-
-        presentation.assignments = ChannelLabel::from_eightch_channel(
-            format_info.eightch_decoder_channel_assignment,
-            self.major_sync.flags,
-        )
-        .unwrap();
-        presentation.control = Some(channel_meaning.eightch_control_enabled);
-        presentation.dialogue_level = -(channel_meaning.eightch_dialogue_norm as i8);
-        presentation.mix_level = channel_meaning.eightch_mix_level + 70;
-    }
-
-    fn configure_sixteench_presentation(&self, presentation: &mut PresentationInfo) {
-        let channel_meaning = &self.major_sync.channel_meaning;
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    let frame = extractor.next().unwrap()?;
+    let access_unit = Parser::default().parse(&frame)?;
 
-        let Some(extra) = &channel_meaning.extra_channel_meaning else {
-            return;
-        };
+    assert_eq!(
+        access_unit_json(&access_unit)?,
+        include_str!("testdata/example_au0.json").trim_end()
+    );
+    Ok(())
+}
 
-        presentation.dialogue_level = -(extra.sixteench_dialogue_norm as i8);
-        presentation.mix_level = extra.sixteench_mix_level + 70;
+#[test]
+fn reserved_sixch_assignment_reported_unknown() {
+    use truehd::structs::sync::MajorSyncInfo;
 
-        if extra.dyn_object_only && extra.lfe_present {
-            presentation.assignments = vec![ChannelLabel::LFE];
-        } else {
-            let desc = extra.sixteench_content_description;
-
-            if desc & 1 != 0 {
-                presentation.chan_distribution = Some(extra.chan_distribute);
-                if !extra.lfe_only {
-                    presentation.assignments =
-                        ChannelLabel::from_sixteenth_channel(extra.sixteench_channel_assignment)
-                            .unwrap();
-                }
-            }
-        }
-    }
+    let mut major_sync_info = MajorSyncInfo {
+        substreams: 2,
+        ..Default::default()
+    };
+    major_sync_info.format_info.sixch_decoder_channel_assignment = 0x1F;
+    let access_unit = AccessUnit {
+        major_sync_info: Some(major_sync_info),
+        ..Default::default()
+    };
 
-    fn finalize_presentation(
-        &self,
-        mut presentation: PresentationInfo,
-        index: usize,
-    ) -> PresentationInfo {
-        presentation.index = index;
-        presentation.presentation_type =
-            Some(self.presentation_map.presentation_type_by_index(index));
-        presentation
-    }
+    let presentations = build_presentations(&access_unit);
+    assert_eq!(presentations[1].unknown_assignment, Some(0x1F));
+    assert!(presentations[1].assignments.is_empty());
+    assert_eq!(presentations[0].unknown_assignment, None);
+    display_presentations(&access_unit);
 }
@@ -0,0 +1,229 @@
+use anyhow::{Context, Result, bail};
+use indicatif::MultiProgress;
+use std::fs::File;
+use std::io::{BufWriter, Seek, Write};
+
+use super::command::{Cli, MatArgs, MatFormat};
+use super::decode::output::is_stdout_path;
+use crate::input::InputReader;
+use crate::riff::RiffWavWriter;
+use truehd::process::MAX_PRESENTATIONS;
+use truehd::process::extract::{Extractor, Frame};
+use truehd::process::mat::{MatPacker, iec61937_burst, iec61937_sample_rate};
+use truehd::process::parse::Parser;
+use truehd::utils::errors::ExtractError;
+
+/// Channels of the link carrying the IEC 61937 bursts
+const IEC61937_CHANNELS: u32 = 8;
+
+pub fn cmd_mat(args: &MatArgs, _cli: &Cli, _multi: Option<&MultiProgress>) -> Result<()> {
+    log::info!(
+        "Packing TrueHD stream into MAT frames: {}",
+        args.input.display()
+    );
+
+    let mut output = match args.format {
+        MatFormat::Raw => {
+            let writer: Box<dyn Write> = if is_stdout_path(&args.output_path) {
+                Box::new(BufWriter::new(std::io::stdout().lock()))
+            } else {
+                let file = File::create(&args.output_path)
+                    .with_context(|| format!("Failed to create {}", args.output_path.display()))?;
+                Box::new(BufWriter::new(file))
+            };
+            MatOutput::Raw(writer)
+        }
+        MatFormat::Wav => {
+            if is_stdout_path(&args.output_path) {
+                bail!("WAV output cannot be written to stdout, use --format raw");
+            }
+            let file = File::create(&args.output_path)
+                .with_context(|| format!("Failed to create {}", args.output_path.display()))?;
+            MatOutput::Wav {
+                writer: RiffWavWriter::new(file),
+                started: false,
+            }
+        }
+    };
+
+    let mut input_reader = InputReader::new(&args.input)?;
+    let mut packer = StreamPacker::default();
+    input_reader.process_chunks(64 * 1024, |chunk| {
+        packer.push_bytes(chunk, &mut output)?;
+        Ok(true)
+    })?;
+    packer.finish(&mut output)?;
+
+    log::info!(
+        "Packed {} access units into {} MAT frames",
+        packer.access_units,
+        packer.packer.mat_frames()
+    );
+
+    Ok(())
+}
+
+/// Destination of the MAT frames.
+enum MatOutput<W: Write + Seek> {
+    /// MAT frames back to back, without IEC 61937 encapsulation
+    Raw(Box<dyn Write>),
+    /// IEC 61937 bursts as 8-channel 16-bit PCM, as sent over HDMI
+    Wav {
+        writer: RiffWavWriter<W>,
+        started: bool,
+    },
+}
+
+impl<W: Write + Seek> MatOutput<W> {
+    fn write_frame(&mut self, mat_frame: &[u8], sampling_frequency: u32) -> Result<()> {
+        match self {
+            Self::Raw(writer) => writer.write_all(mat_frame)?,
+            Self::Wav { writer, started } => {
+                if !*started {
+                    writer.configure_audio_format(
+                        iec61937_sample_rate(sampling_frequency),
+                        IEC61937_CHANNELS,
+                        16,
+                    )?;
+                    writer.write_header()?;
+                    *started = true;
+                }
+
+                let samples: Vec<i32> = iec61937_burst(mat_frame)
+                    .chunks_exact(2)
+                    .map(|word| i16::from_le_bytes([word[0], word[1]]) as i32)
+                    .collect();
+                writer.write_pcm_packed(&samples)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match self {
+            Self::Raw(writer) => writer.flush()?,
+            Self::Wav { writer, started } => {
+                if !*started {
+                    bail!("No access units to pack");
+                }
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Extracts access units and packs them into MAT frames by their input timing.
+struct StreamPacker {
+    extractor: Extractor,
+    parser: Parser,
+    packer: MatPacker,
+    access_units: u64,
+}
+
+impl Default for StreamPacker {
+    fn default() -> Self {
+        // Only the access unit headers and major syncs are needed
+        let mut parser = Parser::default();
+        parser.set_required_presentations(&[false; MAX_PRESENTATIONS]);
+
+        Self {
+            extractor: Extractor::default(),
+            parser,
+            packer: MatPacker::default(),
+            access_units: 0,
+        }
+    }
+}
+
+impl StreamPacker {
+    fn push_bytes<W: Write + Seek>(
+        &mut self,
+        data: &[u8],
+        output: &mut MatOutput<W>,
+    ) -> Result<()> {
+        self.extractor.push_bytes(data);
+
+        loop {
+            match self.extractor.next() {
+                Some(Ok(frame)) => self.push_frame(&frame, output)?,
+                Some(Err(ExtractError::InsufficientData)) | None => break,
+                Some(Err(e)) => log::warn!(
+                    "{e} at byte offset {}, skipping the data",
+                    self.extractor.byte_offset()
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn push_frame<W: Write + Seek>(
+        &mut self,
+        frame: &Frame,
+        output: &mut MatOutput<W>,
+    ) -> Result<()> {
+        let access_unit = self.parser.parse(frame).with_context(|| {
+            format!(
+                "Failed to parse the access unit at byte offset {}",
+                frame.byte_offset
+            )
+        })?;
+
+        let packed = self.packer.pack(&frame.data, &access_unit)?;
+        if let Some(mat_frame) = packed.mat_frame {
+            output.write_frame(&mat_frame, self.parser.sampling_frequency())?;
+        }
+        self.access_units += 1;
+
+        Ok(())
+    }
+
+    fn finish<W: Write + Seek>(&mut self, output: &mut MatOutput<W>) -> Result<()> {
+        if let Some(mat_frame) = self.packer.finish() {
+            output.write_frame(&mat_frame, self.parser.sampling_frequency())?;
+        }
+        output.finish()
+    }
+}
+
+#[test]
+fn wav_output_carries_iec61937_bursts() -> Result<()> {
+    use std::io::Cursor;
+    use truehd::process::mat::MAT_BURST_SIZE;
+
+    let mut output = MatOutput::Wav {
+        writer: RiffWavWriter::new(Cursor::new(Vec::new())),
+        started: false,
+    };
+    let mut packer = StreamPacker::default();
+    for chunk in super::trim::continuous_stream().chunks(1000) {
+        packer.push_bytes(chunk, &mut output)?;
+    }
+    packer.finish(&mut output)?;
+    assert_eq!(packer.access_units, 80);
+
+    let MatOutput::Wav { writer, .. } = output else {
+        unreachable!();
+    };
+    let wav = writer.into_inner()?.into_inner();
+
+    // 8 channels of 16-bit PCM at 4 times 48 kHz
+    assert_eq!(&wav[48..52], b"fmt ");
+    assert_eq!(wav[58..60], 8u16.to_le_bytes());
+    assert_eq!(wav[60..64], 192000u32.to_le_bytes());
+    assert_eq!(&wav[96..100], b"data");
+
+    // 80 access units of 40 samples take 3 1/3 bursts
+    let data = &wav[104..];
+    assert_eq!(data.len(), 4 * MAT_BURST_SIZE);
+    assert_eq!(
+        u32::from_le_bytes(wav[100..104].try_into()?) as usize,
+        data.len()
+    );
+    for burst in data.chunks_exact(MAT_BURST_SIZE) {
+        assert_eq!(burst[..8], [0x72, 0xF8, 0x1F, 0x4E, 0x16, 0x00, 0xF0, 0xEF]);
+    }
+
+    Ok(())
+}
@@ -1,3 +1,8 @@
 pub(crate) mod command;
 pub(crate) mod decode;
+pub(crate) mod demux;
+pub(crate) mod edit;
 pub(crate) mod info;
+pub(crate) mod mat;
+pub(crate) mod trim;
+pub(crate) mod verify;
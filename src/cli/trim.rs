@@ -0,0 +1,573 @@
+use anyhow::{Context, Result, bail};
+use indicatif::MultiProgress;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use super::command::{Cli, TrimArgs};
+use super::decode::output::is_stdout_path;
+use super::decode::trim::detect_start_trim;
+use super::edit::ACCESS_UNIT_HEADER_SIZE;
+use crate::input::InputReader;
+use truehd::process::extract::{Extractor, Frame};
+use truehd::process::parse::{Parser, ParserState};
+use truehd::structs::access_unit::AccessUnit;
+use truehd::structs::substream::Terminator;
+use truehd::structs::sync::MajorSyncInfo;
+use truehd::utils::bitstream_io::{BitstreamWriter, BsIoSliceReader};
+use truehd::utils::errors::ExtractError;
+use truehd::utils::timing::HiresOutputTimingEncoder;
+
+pub fn cmd_trim(args: &TrimArgs, _cli: &Cli, _multi: Option<&MultiProgress>) -> Result<()> {
+    let range = TrimRange {
+        start: match (args.start_au, args.start) {
+            (Some(au), _) => Some(CutPoint::AccessUnit(au)),
+            (_, Some(seconds)) => Some(CutPoint::Seconds(seconds)),
+            _ => None,
+        },
+        end: match (args.end_au, args.end) {
+            (Some(au), _) => Some(CutPoint::AccessUnit(au + 1)),
+            (_, Some(seconds)) => Some(CutPoint::Seconds(seconds)),
+            _ => None,
+        },
+    };
+    range.validate()?;
+
+    log::info!("Trimming TrueHD stream: {}", args.input.display());
+
+    let start_trim = if args.input.to_string_lossy() == "-" {
+        log::info!("Start trim detection is not available for pipe input, assuming none");
+        0
+    } else {
+        detect_start_trim(&args.input, None)?.unwrap_or(0)
+    };
+
+    let mut input_reader = InputReader::new(&args.input)?;
+    let mut output: Box<dyn Write> = if is_stdout_path(&args.output_path) {
+        Box::new(BufWriter::new(std::io::stdout().lock()))
+    } else {
+        let file = File::create(&args.output_path)
+            .with_context(|| format!("Failed to create {}", args.output_path.display()))?;
+        Box::new(BufWriter::new(file))
+    };
+
+    let mut trimmer = StreamTrimmer::new(range, start_trim);
+    input_reader.process_chunks(64 * 1024, |chunk| {
+        trimmer.push_bytes(chunk, &mut output)?;
+        Ok(!trimmer.is_done())
+    })?;
+    trimmer.finish(&mut output)?;
+    output.flush()?;
+
+    Ok(())
+}
+
+/// Boundary of a cut.
+#[derive(Debug, Clone, Copy)]
+enum CutPoint {
+    /// Access unit index, the end boundary is exclusive
+    AccessUnit(u64),
+    /// Seconds of decoded output, after the start trim of the stream
+    Seconds(f64),
+}
+
+impl CutPoint {
+    fn to_samples(self, samples_per_au: u64, sampling_frequency: u32, start_trim: u64) -> u64 {
+        match self {
+            Self::AccessUnit(au) => au * samples_per_au,
+            Self::Seconds(seconds) => {
+                start_trim + (seconds * sampling_frequency as f64).round() as u64
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TrimRange {
+    start: Option<CutPoint>,
+    end: Option<CutPoint>,
+}
+
+impl TrimRange {
+    fn validate(&self) -> Result<()> {
+        for point in [self.start, self.end].into_iter().flatten() {
+            if let CutPoint::Seconds(seconds) = point
+                && !(seconds >= 0.0 && seconds.is_finite())
+            {
+                bail!("Cut points must be non-negative, got {seconds}");
+            }
+        }
+        if let (Some(CutPoint::AccessUnit(start)), Some(CutPoint::AccessUnit(end))) =
+            (self.start, self.end)
+            && end <= start
+        {
+            bail!("--end-au must not be before --start-au");
+        }
+        Ok(())
+    }
+
+    /// Resolves the range with the stream parameters of the first major sync.
+    fn resolve(
+        &self,
+        samples_per_au: u64,
+        sampling_frequency: u32,
+        start_trim: u64,
+    ) -> Result<SampleRange> {
+        let start = self.start.map_or(0, |point| {
+            point.to_samples(samples_per_au, sampling_frequency, start_trim)
+        });
+        // Samples trimmed by the stream itself stay trimmed
+        let start = start.max(start_trim);
+        let end = self
+            .end
+            .map(|point| point.to_samples(samples_per_au, sampling_frequency, start_trim));
+
+        if end.is_some_and(|end| end <= start) {
+            bail!("The end of the cut must be after its start");
+        }
+
+        Ok(SampleRange {
+            samples_per_au,
+            start,
+            end,
+        })
+    }
+}
+
+/// Cut range in decoded samples, counted from the first access unit.
+#[derive(Debug, Clone, Copy)]
+struct SampleRange {
+    samples_per_au: u64,
+    start: u64,
+    end: Option<u64>,
+}
+
+impl SampleRange {
+    fn start_au(&self) -> u64 {
+        self.start / self.samples_per_au
+    }
+
+    fn last_au(&self) -> Option<u64> {
+        self.end.map(|end| end.div_ceil(self.samples_per_au) - 1)
+    }
+
+    /// Samples of the last access unit after the end of the cut.
+    fn zero_samples(&self) -> u16 {
+        self.end.map_or(0, |end| {
+            (end.div_ceil(self.samples_per_au) * self.samples_per_au - end) as u16
+        })
+    }
+}
+
+/// Copies the access units of a cut into a new stream.
+///
+/// A decoder can only start at a major sync, so the copy starts at the last
+/// major sync at or before the start of the cut; no major sync is synthesized.
+/// The samples in between are marked for removal by rewriting the
+/// high-resolution output timing.
+struct StreamTrimmer {
+    range: TrimRange,
+    start_trim: u64,
+    extractor: Extractor,
+    resolved: Option<SampleRange>,
+    au_index: u64,
+    /// Access units from the last major sync before the start of the cut
+    pending: Vec<Frame>,
+    writer: Option<CutWriter>,
+    done: bool,
+}
+
+impl StreamTrimmer {
+    fn new(range: TrimRange, start_trim: u64) -> Self {
+        Self {
+            range,
+            start_trim,
+            extractor: Extractor::default(),
+            resolved: None,
+            au_index: 0,
+            pending: Vec::new(),
+            writer: None,
+            done: false,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn push_bytes(&mut self, data: &[u8], output: &mut impl Write) -> Result<()> {
+        self.extractor.push_bytes(data);
+
+        while !self.done {
+            match self.extractor.next() {
+                Some(Ok(frame)) => self.push_frame(frame, output)?,
+                Some(Err(ExtractError::InsufficientData)) | None => break,
+                Some(Err(e)) => log::warn!(
+                    "{e} at byte offset {}, skipping the data",
+                    self.extractor.byte_offset()
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn push_frame(&mut self, frame: Frame, output: &mut impl Write) -> Result<()> {
+        let au_index = self.au_index;
+        self.au_index += 1;
+
+        if frame.is_major_sync() {
+            if self.resolved.is_none() {
+                let major_sync = read_major_sync(&frame)?;
+                let samples_per_au = major_sync.format_info.samples_per_au()? as u64;
+                let sampling_frequency = major_sync.format_info.sampling_frequency_1()?;
+                self.resolved = Some(self.range.resolve(
+                    samples_per_au,
+                    sampling_frequency,
+                    self.start_trim,
+                )?);
+            }
+            if self.writer.is_none() {
+                self.pending.clear();
+            }
+        }
+
+        // Nothing before the first major sync can be decoded
+        let Some(range) = self.resolved else {
+            return Ok(());
+        };
+
+        match &mut self.writer {
+            Some(writer) => writer.push(&frame, output)?,
+            None => {
+                self.pending.push(frame);
+                if au_index < range.start_au() {
+                    return Ok(());
+                }
+
+                let major_sync_index = au_index + 1 - self.pending.len() as u64;
+                let start_timing = range
+                    .start
+                    .saturating_sub(major_sync_index * range.samples_per_au);
+                log::info!(
+                    "Cut starts at the major sync of access unit {major_sync_index}, trimming {start_timing} samples"
+                );
+
+                let mut writer = CutWriter::new(start_timing, range.samples_per_au);
+                for frame in self.pending.drain(..) {
+                    writer.push(&frame, output)?;
+                }
+                self.writer = Some(writer);
+            }
+        }
+
+        if range.last_au() == Some(au_index) {
+            self.done = true;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut impl Write) -> Result<()> {
+        let Some(writer) = &mut self.writer else {
+            bail!("The start of the cut is beyond the end of the stream");
+        };
+        let range = self.resolved.unwrap();
+        let zero_samples = if self.done {
+            range.zero_samples()
+        } else {
+            log::warn!("The stream ends before the end of the cut");
+            0
+        };
+        writer.finish(zero_samples, output)?;
+
+        log::info!(
+            "Wrote {} access units, {} bytes",
+            writer.access_units,
+            writer.bytes_written
+        );
+
+        Ok(())
+    }
+}
+
+fn read_major_sync(frame: &Frame) -> Result<MajorSyncInfo> {
+    let major_sync_data = frame
+        .data
+        .get(ACCESS_UNIT_HEADER_SIZE..)
+        .context("Access unit is too short for a major sync")?;
+    MajorSyncInfo::read(
+        &mut ParserState::default(),
+        &mut BsIoSliceReader::from_slice(major_sync_data),
+    )
+}
+
+/// Re-serializes the access units of a cut starting at a major sync.
+///
+/// All timing fields are shifted by the same amount, which keeps the latency
+/// and data rate constraints of the original stream, so that the output timing
+/// of the first access unit is the number of samples to trim.
+struct CutWriter {
+    start_timing: u64,
+    parser: Parser,
+    writer: BitstreamWriter,
+    hires_encoders: Vec<HiresOutputTimingEncoder>,
+    timing_shift: Option<u16>,
+    /// Last access unit, held back for the terminator
+    held: Option<AccessUnit>,
+    access_units: u64,
+    bytes_written: u64,
+}
+
+impl CutWriter {
+    fn new(start_timing: u64, samples_per_au: u64) -> Self {
+        Self {
+            start_timing,
+            parser: Parser::default(),
+            writer: BitstreamWriter::new(),
+            hires_encoders: vec![
+                HiresOutputTimingEncoder::new(
+                    start_timing as usize,
+                    samples_per_au as usize
+                );
+                truehd::process::MAX_PRESENTATIONS
+            ],
+            timing_shift: None,
+            held: None,
+            access_units: 0,
+            bytes_written: 0,
+        }
+    }
+
+    fn push(&mut self, frame: &Frame, output: &mut impl Write) -> Result<()> {
+        let mut access_unit = self.parser.parse(frame).with_context(|| {
+            format!(
+                "Failed to parse the access unit at byte offset {}",
+                frame.byte_offset
+            )
+        })?;
+
+        let shift = match self.timing_shift {
+            Some(shift) => shift,
+            None => {
+                let output_timing = access_unit.substream_segment[0]
+                    .block
+                    .iter()
+                    .find_map(|block| block.restart_header.as_ref())
+                    .context("The first access unit of the cut has no restart header")?
+                    .output_timing;
+                *self
+                    .timing_shift
+                    .insert(output_timing.wrapping_sub(self.start_timing as u16))
+            }
+        };
+
+        let au_index = self.access_units as usize;
+        access_unit.input_timing = access_unit.input_timing.wrapping_sub(shift);
+        let substreams = access_unit.substreams;
+        for (segment, encoder) in access_unit.substream_segment[..substreams]
+            .iter_mut()
+            .zip(&mut self.hires_encoders)
+        {
+            for restart_header in segment
+                .block
+                .iter_mut()
+                .filter_map(|block| block.restart_header.as_mut())
+            {
+                restart_header.output_timing = restart_header.output_timing.wrapping_sub(shift);
+                restart_header.hires_output_timing =
+                    encoder.next_bit(au_index, restart_header.output_timing);
+            }
+        }
+        self.access_units += 1;
+
+        if let Some(previous) = self.held.replace(access_unit) {
+            self.write(&previous, output)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the last access unit with a terminator in every substream.
+    fn finish(&mut self, zero_samples: u16, output: &mut impl Write) -> Result<()> {
+        let Some(mut access_unit) = self.held.take() else {
+            return Ok(());
+        };
+
+        let substreams = access_unit.substreams;
+        for segment in &mut access_unit.substream_segment[..substreams] {
+            if zero_samples > 0 || segment.terminator.is_none() {
+                segment.terminator = Some(Terminator {
+                    terminator_a: 0x348D3,
+                    zero_samples_indicated: zero_samples > 0,
+                    zero_samples,
+                    terminator_b: if zero_samples > 0 { 0 } else { 0x1234 },
+                });
+            }
+        }
+        self.write(&access_unit, output)
+    }
+
+    fn write(&mut self, access_unit: &AccessUnit, output: &mut impl Write) -> Result<()> {
+        let data = access_unit.write(&mut self.writer)?;
+        output.write_all(&data)?;
+        self.bytes_written += data.len() as u64;
+        Ok(())
+    }
+}
+
+/// Builds a stream of 80 access units from the example data, retimed so that
+/// it decodes as one continuous stream.
+#[cfg(test)]
+pub(super) fn continuous_stream() -> Vec<u8> {
+    use truehd::process::EXAMPLE_DATA;
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    let frames: Vec<_> = extractor.filter_map(Result::ok).collect();
+
+    let mut writer = BitstreamWriter::new();
+    let mut stream = Vec::new();
+    for repeat in 0..40u16 {
+        let mut parser = Parser::default();
+        for (i, frame) in frames.iter().enumerate() {
+            let mut access_unit = parser.parse(frame).unwrap();
+            let au_index = 2 * repeat + i as u16;
+            access_unit.input_timing = (au_index * 40).wrapping_sub(84);
+            if let Some(major_sync) = access_unit.major_sync_info.as_mut() {
+                major_sync.peak_data_rate *= 4;
+            }
+            for block in &mut access_unit.substream_segment[0].block {
+                if let Some(restart_header) = block.restart_header.as_mut() {
+                    restart_header.output_timing += repeat * 80;
+                }
+            }
+            stream.extend(access_unit.write(&mut writer).unwrap());
+        }
+    }
+    stream
+}
+
+#[cfg(test)]
+fn trim_stream(data: &[u8], range: TrimRange) -> Result<Vec<u8>> {
+    let mut trimmer = StreamTrimmer::new(range, 0);
+    let mut output = Vec::new();
+    for chunk in data.chunks(1000) {
+        trimmer.push_bytes(chunk, &mut output)?;
+    }
+    trimmer.finish(&mut output)?;
+    Ok(output)
+}
+
+/// Zero samples indicated, zero samples and terminator B of a terminator.
+#[cfg(test)]
+type TerminatorFields = (bool, u16, u16);
+
+/// Decodes a stream, failing on any warning, and returns the PCM of each
+/// access unit, the last terminator and the high-resolution output timing.
+#[cfg(test)]
+fn decode_strictly(data: &[u8]) -> (Vec<Vec<i32>>, Option<TerminatorFields>, Option<usize>) {
+    use truehd::process::decode::Decoder;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    let mut decoder = Decoder::default();
+    parser.set_fail_level(log::Level::Warn);
+    decoder.set_fail_level(log::Level::Warn);
+    extractor.push_bytes(data);
+
+    let mut pcm = Vec::new();
+    let mut terminator = None;
+    for frame in extractor.filter_map(Result::ok) {
+        let access_unit = parser.parse(&frame).unwrap();
+        assert!(parser.take_warnings().is_empty());
+        let decoded = decoder.decode_presentation(&access_unit, 1).unwrap();
+        pcm.push(
+            decoded.pcm_data[..decoded.sample_length]
+                .iter()
+                .flat_map(|sample| sample[..decoded.channel_count].to_vec())
+                .collect(),
+        );
+        terminator = access_unit.substream_segment[0]
+            .terminator
+            .as_ref()
+            .map(|tm| (tm.zero_samples_indicated, tm.zero_samples, tm.terminator_b));
+    }
+    (pcm, terminator, parser.hires_output_timing())
+}
+
+#[test]
+fn trim_by_access_unit_decodes_cleanly() -> Result<()> {
+    let data = continuous_stream();
+    let (original, _, _) = decode_strictly(&data);
+    assert_eq!(original.len(), 80);
+
+    let trimmed = trim_stream(
+        &data,
+        TrimRange {
+            start: Some(CutPoint::AccessUnit(5)),
+            end: Some(CutPoint::AccessUnit(61)),
+        },
+    )?;
+    let (pcm, terminator, hires_output_timing) = decode_strictly(&trimmed);
+
+    // The cut starts at the major sync of AU 4 and trims its 40 samples
+    assert_eq!(pcm, original[4..=60]);
+    assert_eq!(hires_output_timing, Some(40));
+    assert_eq!(terminator, Some((false, 0, 0x1234)));
+
+    Ok(())
+}
+
+#[test]
+fn trim_by_seconds_marks_trailing_samples() -> Result<()> {
+    let data = continuous_stream();
+    let (original, _, _) = decode_strictly(&data);
+
+    // 0.0105 s is sample 504 in AU 12, 0.0501 s is sample 2405 in AU 60
+    let trimmed = trim_stream(
+        &data,
+        TrimRange {
+            start: Some(CutPoint::Seconds(0.0105)),
+            end: Some(CutPoint::Seconds(0.0501)),
+        },
+    )?;
+    let (pcm, terminator, hires_output_timing) = decode_strictly(&trimmed);
+
+    // The decoder drops the zero samples of the last access unit
+    let (last, pcm) = pcm.split_last().unwrap();
+    assert_eq!(pcm, &original[12..60]);
+    assert_eq!(last[..], original[60][..5 * 2]);
+    assert_eq!(hires_output_timing, Some(24));
+    assert_eq!(terminator, Some((true, 35, 0)));
+
+    Ok(())
+}
+
+#[test]
+fn trim_refuses_invalid_ranges() {
+    let range = |start, end| TrimRange {
+        start: Some(start),
+        end: Some(end),
+    };
+    assert!(
+        range(CutPoint::AccessUnit(5), CutPoint::AccessUnit(5))
+            .validate()
+            .is_err()
+    );
+    assert!(
+        range(CutPoint::Seconds(-1.0), CutPoint::AccessUnit(5))
+            .validate()
+            .is_err()
+    );
+    assert!(
+        range(CutPoint::Seconds(1.0), CutPoint::Seconds(0.5))
+            .resolve(40, 48000, 0)
+            .is_err()
+    );
+    assert!(
+        trim_stream(
+            &continuous_stream(),
+            range(CutPoint::AccessUnit(100), CutPoint::AccessUnit(101))
+        )
+        .is_err()
+    );
+}
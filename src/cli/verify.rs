@@ -0,0 +1,312 @@
+use anyhow::{Error, Result, bail};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::Level;
+
+use super::command::{Cli, VerifyArgs};
+use crate::input::InputReader;
+use truehd::process::{
+    MAX_PRESENTATIONS, Metrics,
+    decode::{DecodeStats, Decoder},
+    extract::Extractor,
+    parse::Parser,
+};
+use truehd::structs::access_unit::AccessUnit;
+use truehd::utils::errors::{
+    AccessUnitError, BlockError, Category, ChannelError, DecodeError, ExtraDataError, ExtractError,
+    FilterError, MatrixError, Policy, RestartHeaderError, SubstreamError, SyncError,
+};
+
+pub fn cmd_verify(args: &VerifyArgs, cli: &Cli, multi: Option<&MultiProgress>) -> Result<()> {
+    log::info!("Verifying TrueHD stream: {}", args.input.display());
+
+    let mut input_reader = InputReader::new(&args.input)?;
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    parser.set_fail_level(Level::Warn);
+    let category_policies = cli.category_policies()?;
+    for &(category, policy) in &category_policies {
+        extractor.set_category_policy(category, policy);
+        parser.set_category_policy(category, policy);
+    }
+
+    let mut context = VerifyContext {
+        verifier: Verifier::new(Level::Warn, &category_policies),
+        problems: Vec::new(),
+        access_units: 0,
+        total_bytes: 0,
+    };
+
+    let pb = match multi {
+        Some(multi) => {
+            let pb = multi.add(ProgressBar::new_spinner());
+            pb.set_style(ProgressStyle::with_template("{spinner:.green} {msg}")?);
+            pb.enable_steady_tick(std::time::Duration::from_millis(100));
+            pb.set_message("Verifying frames...");
+            Some(pb)
+        }
+        None => None,
+    };
+
+    input_reader.process_chunks(64 * 1024, |chunk| {
+        context.total_bytes += chunk.len() as u64;
+        extractor.push_bytes(chunk);
+
+        while let Some(result) = extractor.next() {
+            match result {
+                Ok(frame) => {
+                    match parser.parse(&frame) {
+                        Ok(access_unit) => {
+                            for (substream, e) in context.verifier.decode(&access_unit) {
+                                context.report(frame.byte_offset, Some(substream), &e);
+                            }
+                        }
+                        Err(e) => {
+                            let substream =
+                                is_substream_error(&e).then(|| parser.substream_index());
+                            context.report(frame.byte_offset, substream, &e);
+                        }
+                    }
+                    context.access_units += 1;
+
+                    if let Some(pb) = &pb
+                        && context.access_units.is_multiple_of(100)
+                    {
+                        pb.set_message(format!(
+                            "Verifying frames...        {}",
+                            context.access_units
+                        ));
+                    }
+                }
+                Err(ExtractError::InsufficientData) => break,
+                Err(e) => context.report(extractor.byte_offset(), None, &e.into()),
+            }
+        }
+
+        Ok(true)
+    })?;
+
+    if let Some(pb) = &pb {
+        pb.finish_and_clear();
+    }
+
+    // A complete stream leaves no bytes behind in the extractor
+    let trailing_bytes = context.total_bytes - extractor.byte_offset();
+    if trailing_bytes > 0 {
+        let offset = extractor.byte_offset();
+        context.problems.push(Problem {
+            access_unit: context.access_units,
+            byte_offset: offset,
+            substream: None,
+            kind: "truncation",
+            message: format!(
+                "stream ends with {trailing_bytes} bytes of an incomplete access unit"
+            ),
+        });
+    }
+
+    let stats = context.verifier.stats(&parser);
+    display_summary(&context, &stats, args.max_problems);
+
+    if !context.problems.is_empty() {
+        bail!(
+            "Verification failed with {} problems",
+            context.problems.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Decodes every presentation of each access unit
+pub(crate) struct Verifier {
+    decoders: [Decoder; MAX_PRESENTATIONS],
+}
+
+impl Verifier {
+    pub(crate) fn new(fail_level: Level, category_policies: &[(Category, Policy)]) -> Self {
+        Self {
+            decoders: std::array::from_fn(|_| {
+                let mut decoder = Decoder::default();
+                decoder.set_fail_level(fail_level);
+                for &(category, policy) in category_policies {
+                    decoder.set_category_policy(category, policy);
+                }
+                decoder
+            }),
+        }
+    }
+
+    /// Returns the decode errors with the failing substream.
+    ///
+    /// Presentations that are copies of another report no errors of their own.
+    pub(crate) fn decode(&mut self, access_unit: &AccessUnit) -> Vec<(usize, Error)> {
+        let mut errors = Vec::new();
+        let mut seen = [false; MAX_PRESENTATIONS];
+
+        for (presentation, decoder) in self.decoders.iter_mut().enumerate() {
+            let result = decoder.decode_presentation(access_unit, presentation);
+
+            let effective = decoder.presentation().min(MAX_PRESENTATIONS - 1);
+            if let Err(e) = result
+                && !seen[effective]
+            {
+                errors.push((decoder.substream_index(), e));
+            }
+            seen[effective] = true;
+        }
+
+        errors
+    }
+
+    pub(crate) fn enable_metrics(&mut self) {
+        for decoder in &mut self.decoders {
+            decoder.enable_metrics(true);
+        }
+    }
+
+    /// Combines the decode metrics of all presentations.
+    ///
+    /// Every presentation decodes the same access units, so the sample count
+    /// is taken once while decode times add up.
+    pub(crate) fn take_metrics(&mut self) -> Option<Metrics> {
+        let mut metrics: Option<Metrics> = None;
+        for decoder_metrics in self.decoders.iter_mut().filter_map(Decoder::take_metrics) {
+            let metrics = metrics.get_or_insert_default();
+            metrics.samples = metrics.samples.max(decoder_metrics.samples);
+            metrics.decode_time += decoder_metrics.decode_time;
+        }
+        metrics
+    }
+
+    /// Combines parser statistics with those of each distinct decoded presentation.
+    ///
+    /// Lossless checks are verified for the last substream of a presentation,
+    /// so each substream is taken from the decoder whose presentation ends there.
+    pub(crate) fn stats(&self, parser: &Parser) -> DecodeStats {
+        let mut stats = parser.stats();
+        let mut seen = [false; MAX_PRESENTATIONS];
+
+        for decoder in &self.decoders {
+            let presentation = decoder.presentation();
+            if presentation >= MAX_PRESENTATIONS || std::mem::replace(&mut seen[presentation], true)
+            {
+                continue;
+            }
+
+            let decoder_stats = decoder.stats();
+            stats.substreams[presentation].merge(&decoder_stats.substreams[presentation]);
+            stats.access_units = stats.access_units.max(decoder_stats.access_units);
+        }
+
+        stats
+    }
+}
+
+struct Problem {
+    access_unit: u64,
+    byte_offset: u64,
+    substream: Option<usize>,
+    kind: &'static str,
+    message: String,
+}
+
+struct VerifyContext {
+    verifier: Verifier,
+    problems: Vec<Problem>,
+    access_units: u64,
+    total_bytes: u64,
+}
+
+impl VerifyContext {
+    fn report(&mut self, byte_offset: u64, substream: Option<usize>, error: &Error) {
+        log::debug!("AU {}: {error:#}", self.access_units);
+
+        self.problems.push(Problem {
+            access_unit: self.access_units,
+            byte_offset,
+            substream,
+            kind: error_kind(error),
+            message: error.root_cause().to_string(),
+        });
+    }
+}
+
+/// Whether the error was raised while reading or decoding a substream segment
+fn is_substream_error(error: &Error) -> bool {
+    error.downcast_ref::<SubstreamError>().is_some()
+        || error.downcast_ref::<RestartHeaderError>().is_some()
+        || error.downcast_ref::<BlockError>().is_some()
+        || error.downcast_ref::<ChannelError>().is_some()
+        || error.downcast_ref::<FilterError>().is_some()
+        || error.downcast_ref::<MatrixError>().is_some()
+}
+
+fn error_kind(error: &Error) -> &'static str {
+    if error.downcast_ref::<ExtractError>().is_some() {
+        "extraction"
+    } else if error.downcast_ref::<SyncError>().is_some() {
+        "major sync"
+    } else if error.downcast_ref::<AccessUnitError>().is_some() {
+        "access unit"
+    } else if error.downcast_ref::<SubstreamError>().is_some() {
+        "substream"
+    } else if error.downcast_ref::<RestartHeaderError>().is_some() {
+        "restart header"
+    } else if error.downcast_ref::<BlockError>().is_some() {
+        "block"
+    } else if error.downcast_ref::<ChannelError>().is_some() {
+        "channel"
+    } else if error.downcast_ref::<FilterError>().is_some() {
+        "filter"
+    } else if error.downcast_ref::<MatrixError>().is_some() {
+        "matrix"
+    } else if error.downcast_ref::<ExtraDataError>().is_some() {
+        "extra data"
+    } else if error.downcast_ref::<DecodeError>().is_some() {
+        "decode"
+    } else {
+        "other"
+    }
+}
+
+fn display_summary(context: &VerifyContext, stats: &DecodeStats, max_problems: usize) {
+    println!();
+    println!("Verification Summary");
+    println!("  Access units              {}", context.access_units);
+    println!("  Size                      {} bytes", context.total_bytes);
+    println!("  Problems                  {}", context.problems.len());
+    println!("  Integrity failures        {}", stats.failures());
+
+    let status = if context.problems.is_empty() {
+        "OK"
+    } else {
+        "FAILED"
+    };
+    println!("  Status                    {status}");
+    println!();
+
+    if context.problems.is_empty() {
+        return;
+    }
+
+    println!("Problems");
+    for problem in context.problems.iter().take(max_problems) {
+        let substream = problem
+            .substream
+            .map(|substream| format!(", substream {substream}"))
+            .unwrap_or_default();
+        println!(
+            "  AU {} (byte offset {} / {:#X}{substream}) {}: {}",
+            problem.access_unit,
+            problem.byte_offset,
+            problem.byte_offset,
+            problem.kind,
+            problem.message
+        );
+    }
+
+    if context.problems.len() > max_problems {
+        println!("  ... and {} more", context.problems.len() - max_problems);
+    }
+    println!();
+}
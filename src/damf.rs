@@ -1,10 +1,16 @@
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt::Display;
 use std::path::Path;
-use truehd::structs::oamd::{ObjectAudioMetadataPayload, SpeakerLabels, Trim};
+use truehd::structs::oamd::{BedAssignment, ObjectAudioMetadataPayload, SpeakerLabels, Trim};
+use truehd::structs::timestamp::Framerate;
+use truehd::utils::resample::convert_position;
 
 pub const DAMF_VERSION: &str = "0.5.1";
 
+/// First ID of the channels of bed instances after the first, following the
+/// IDs of unusual channels of the first instance (130-136).
+const EXTRA_BED_INSTANCE_FIRST_ID: u32 = 137;
+
 #[derive(Deserialize, Serialize)]
 pub struct Data {
     version: String,
@@ -19,11 +25,11 @@ pub struct Presentation {
     simplified: bool,
     metadata: String,
     audio: String,
-    offset: f64,
+    pub offset: f64,
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    ffoa: Option<f64>,
+    pub ffoa: Option<f64>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    fps: Option<Fps>,
+    pub fps: Option<Fps>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     sc_number_of_elements: Option<u32>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -61,8 +67,8 @@ enum PresentationType {
     Cinema,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-enum Fps {
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Fps {
     #[serde(rename = "23.976")]
     R23_976,
     #[serde(rename = "24")]
@@ -77,6 +83,21 @@ enum Fps {
     R30,
 }
 
+impl Fps {
+    /// Maps an SMPTE framerate, `None` for rates DAMF has no value for.
+    pub fn from_framerate(framerate: &Framerate, dropframe: bool) -> Option<Self> {
+        match framerate {
+            Framerate::R23_976 => Some(Self::R23_976),
+            Framerate::R24 => Some(Self::R24),
+            Framerate::R25 => Some(Self::R25),
+            Framerate::R29_97 if dropframe => Some(Self::R29_97df),
+            Framerate::R29_97 => Some(Self::R29_97),
+            Framerate::R30 => Some(Self::R30),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 enum DownmixMode {
     #[serde(rename = "LoRo_Stereo")]
@@ -217,28 +238,65 @@ impl BedInstance {
     }
 
     pub fn with_oamd_payload(oamd: &ObjectAudioMetadataPayload) -> Vec<Self> {
-        oamd.program_assignment
-            .bed_assignment
+        let bed_assignment = &oamd.program_assignment.bed_assignment;
+
+        bed_assignment
             .iter()
-            .map(|bed| BedInstance {
+            .zip(Self::channel_ids(bed_assignment))
+            .map(|(bed, ids)| BedInstance {
                 description: None,
                 group_name: None,
                 channels: bed
                     .to_index_vec()
                     .iter()
-                    .map(|&i| Channel {
+                    .zip(ids)
+                    .map(|(&i, id)| Channel {
                         channel: format!("{:?}", SpeakerLabels::from_u8(i as u8).unwrap()),
-                        id: match i {
-                            0..8 => i,
-                            8..10 => i + 122,
-                            10..12 => i - 2,
-                            _ => i + 120,
-                        } as u32,
+                        id,
                     })
                     .collect(),
             })
             .collect()
     }
+
+    /// Returns the IDs of the channels of each bed instance.
+    ///
+    /// The first instance uses the 7.1.2 bed IDs 0-9 and assigns unusual
+    /// channels to the end from 130, skipping 128 and 129. Channels of further
+    /// instances are numbered consecutively from 137.
+    pub fn channel_ids(bed_assignment: &[BedAssignment]) -> Vec<Vec<u32>> {
+        let mut next_id = EXTRA_BED_INSTANCE_FIRST_ID;
+
+        bed_assignment
+            .iter()
+            .enumerate()
+            .map(|(instance, bed)| {
+                bed.to_index_vec()
+                    .into_iter()
+                    .map(|i| {
+                        if instance > 0 {
+                            next_id += 1;
+                            return next_id - 1;
+                        }
+
+                        Self::first_instance_channel_id(i)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// ID of a speaker of the first bed instance, 0-9 for the 7.1.2 bed and
+    /// from 128 for the other bed channels.
+    pub fn first_instance_channel_id(speaker: usize) -> u32 {
+        let id = match speaker {
+            0..8 => speaker,
+            8..10 => speaker + 122,
+            10..12 => speaker - 2,
+            _ => speaker + 120,
+        };
+        id as u32
+    }
 }
 
 impl Data {
@@ -250,31 +308,55 @@ impl Data {
         &mut self.presentations
     }
 
+    /// Append the objects of `ids` missing from the object list, returning
+    /// whether any were added.
+    pub fn pad_objects(&mut self, ids: impl IntoIterator<Item = u32>) -> bool {
+        let Some(presentation) = self.presentations.first_mut() else {
+            return false;
+        };
+
+        let count = presentation.objects.len();
+        for id in ids {
+            if !presentation.objects.iter().any(|object| object.id == id) {
+                presentation.objects.push(Object {
+                    description: None,
+                    group_name: None,
+                    id,
+                });
+            }
+        }
+        presentation.objects.len() > count
+    }
+
+    /// Header data with the first bed instance and the bed configuration
+    /// replaced by the speakers of the bed-conformed output, in output order.
     pub fn with_oamd_payload_bed_conform(
         oamd: &ObjectAudioMetadataPayload,
         base_path: &Path,
+        speakers: &[SpeakerLabels],
     ) -> Self {
         let mut data = Self::with_oamd_payload(oamd, base_path);
 
-        if let Some(presentation) = data.presentations.first_mut() {
-            presentation.bed_instances = vec![BedInstance {
+        // Only the first bed instance is conformed, further instances pass through
+        if let Some(bed_instance) = data
+            .presentations
+            .first_mut()
+            .and_then(|presentation| presentation.bed_instances.first_mut())
+        {
+            *bed_instance = BedInstance {
                 description: None,
                 group_name: None,
-                channels: (0..10)
-                    .map(|i| Channel {
-                        channel: format!(
-                            "{:?}",
-                            SpeakerLabels::from_u8(match i {
-                                0..8 => i,
-                                8..10 => i + 2,
-                                _ => unreachable!(),
-                            })
-                            .unwrap()
-                        ),
-                        id: i as u32,
+                channels: speakers
+                    .iter()
+                    .map(|&speaker| Channel {
+                        channel: format!("{speaker:?}"),
+                        id: BedInstance::first_instance_channel_id(speaker as usize),
                     })
                     .collect(),
-            }];
+            };
+            data.presentations[0].sc_bed_configuration = Some(VecDisplay(
+                speakers.iter().map(|&speaker| speaker as u32).collect(),
+            ));
         }
 
         data
@@ -291,7 +373,10 @@ impl Data {
         // TODO: move elsewhere?
         let bed_instances = BedInstance::with_oamd_payload(oamd);
 
-        let objects = (0..oamd.program_assignment.num_dynamic_objects)
+        // ISF objects precede the dynamic objects
+        let program_assignment = &oamd.program_assignment;
+        let objects = (0..program_assignment.num_isf_objects
+            + program_assignment.num_dynamic_objects)
             .map(|i| Object {
                 description: None,
                 group_name: None,
@@ -320,7 +405,7 @@ impl Data {
                 audio: format!("{base_name}.atmos.audio"),
                 offset: 0.0,
                 ffoa: None,
-                fps: Some(Fps::R24), // TODO: derive
+                fps: Some(Fps::R24),
                 sc_number_of_elements: None,
                 sc_bed_configuration,
                 creation_tool: Some(env!("CARGO_PKG_NAME").to_string()),
@@ -336,6 +421,57 @@ impl Data {
     }
 }
 
+/// Kind of a non-bed object, whose position within its kind identifies it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectClass {
+    Isf,
+    Dynamic,
+}
+
+/// Object IDs kept for the life of an output.
+///
+/// The ISF and dynamic objects are keyed by their class and position in the
+/// program, so their IDs do not shift when the object count or bed layout
+/// changes. Objects first seen later get the next free ID.
+#[derive(Debug, Clone, Default)]
+pub struct ObjectRegistry {
+    objects: Vec<(ObjectClass, usize)>,
+}
+
+impl ObjectRegistry {
+    /// First ID of the ISF and dynamic objects
+    pub const FIRST_ID: u32 = 10;
+
+    /// ID of the object at `position` within `class`, assigned on first use
+    pub fn id(&mut self, class: ObjectClass, position: usize) -> u32 {
+        let index = match self
+            .objects
+            .iter()
+            .position(|&key| key == (class, position))
+        {
+            Some(index) => index,
+            None => {
+                self.objects.push((class, position));
+                self.objects.len() - 1
+            }
+        };
+        index as u32 + Self::FIRST_ID
+    }
+
+    /// IDs of all objects seen so far, in order of appearance
+    pub fn ids(&self) -> impl Iterator<Item = u32> {
+        (0..self.objects.len() as u32).map(|index| index + Self::FIRST_ID)
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Configuration {
@@ -344,7 +480,165 @@ pub struct Configuration {
     pub events: Vec<Event>,
 }
 
+/// Decimation of position-only object updates, similar to the Dolby conversion tool.
+///
+/// An update that only moves an object by less than `position_threshold` (in DAMF
+/// coordinates, on any axis) is held back until `min_interval` samples have passed
+/// since the last update written for that object.
+#[derive(Debug, Clone, Copy)]
+pub struct MetadataThinning {
+    pub min_interval: u64,
+    pub position_threshold: f64,
+}
+
+impl MetadataThinning {
+    /// One coarse OAMD position step expressed in DAMF coordinates
+    pub const DEFAULT_POSITION_THRESHOLD: f64 = 2.0 / 62.0;
+
+    pub fn with_update_rate(update_rate_ms: u32, sample_rate: u32) -> Self {
+        Self {
+            min_interval: update_rate_ms as u64 * sample_rate as u64 / 1000,
+            position_threshold: Self::DEFAULT_POSITION_THRESHOLD,
+        }
+    }
+
+    fn should_skip(&self, prev: &Event, next: &Event, diff: &Event) -> bool {
+        if !diff.is_position_only() {
+            return false;
+        }
+
+        let elapsed = next
+            .sample_pos
+            .unwrap_or_default()
+            .saturating_sub(prev.sample_pos.unwrap_or_default());
+
+        elapsed < self.min_interval && prev.position_delta(next) < self.position_threshold
+    }
+}
+
 impl Configuration {
+    /// Make event positions relative to `origin`.
+    ///
+    /// Events before `origin` are moved to it, so they take effect at the
+    /// first sample.
+    pub fn rebase_sample_pos(&mut self, origin: u64) {
+        for event in &mut self.events {
+            event.sample_pos = event.sample_pos.map(|pos| pos.saturating_sub(origin));
+        }
+    }
+
+    /// Convert the sample rate and the event positions and ramps to `sample_rate`.
+    ///
+    /// Positions are rounded to the nearest sample at the new rate.
+    pub fn resample(&mut self, sample_rate: u32) {
+        let Some(input_rate) = self.sample_rate else {
+            return;
+        };
+        for event in &mut self.events {
+            event.sample_pos = event
+                .sample_pos
+                .map(|pos| convert_position(pos, input_rate, sample_rate));
+            event.ramp_length = event
+                .ramp_length
+                .map(|ramp| convert_position(ramp as u64, input_rate, sample_rate) as u32);
+        }
+        self.sample_rate = Some(sample_rate);
+    }
+
+    /// Sample position of the events, which share the position of their payload
+    pub fn sample_pos(&self) -> Option<u64> {
+        self.events.first().and_then(|event| event.sample_pos)
+    }
+
+    /// Serialize the events that differ from `prev_events`.
+    ///
+    /// The first call writes the complete configuration including the header.
+    /// Afterwards only changed fields are written, and `prev_events` is updated
+    /// in place to the state the renderer has seen, so thinned updates are
+    /// measured against the last written event rather than the last decoded one.
+    pub fn serialize_events_diff(
+        mut self,
+        prev_events: &mut Vec<Event>,
+        thinning: Option<MetadataThinning>,
+    ) -> String {
+        if prev_events.is_empty() {
+            let yaml_str = self.serialize_events(false);
+            *prev_events = self.events;
+            return yaml_str;
+        }
+
+        let diffs = self.diff_events(prev_events, thinning);
+        if diffs.is_empty() {
+            return String::new();
+        }
+
+        Self {
+            sample_rate: None,
+            events: diffs,
+        }
+        .serialize_events(true)
+    }
+
+    /// Changed fields of the events that differ from `prev_events`.
+    ///
+    /// All events are returned when `prev_events` is empty. `prev_events` is
+    /// updated in place like in [`serialize_events_diff`](Self::serialize_events_diff).
+    ///
+    /// Events are matched by object ID. Objects new to `prev_events` are
+    /// written in full, and active objects missing from a payload with
+    /// objects are deactivated.
+    pub fn diff_events(
+        self,
+        prev_events: &mut Vec<Event>,
+        thinning: Option<MetadataThinning>,
+    ) -> Vec<Event> {
+        if prev_events.is_empty() {
+            *prev_events = self.events.clone();
+            return self.events;
+        }
+
+        let sample_pos = self.sample_pos();
+        let ids = self.events.iter().map(|event| event.id).collect::<Vec<_>>();
+
+        let mut diffs = Vec::new();
+        for next in self.events {
+            let Some(prev) = prev_events.iter_mut().find(|prev| prev.id == next.id) else {
+                diffs.push(next.clone());
+                prev_events.push(next);
+                continue;
+            };
+
+            let Some(diff) = prev.diff(&next) else {
+                continue;
+            };
+
+            if thinning.is_some_and(|thinning| thinning.should_skip(prev, &next, &diff)) {
+                continue;
+            }
+
+            diffs.push(diff);
+            *prev = next;
+        }
+
+        if ids.is_empty() {
+            return diffs;
+        }
+        for prev in prev_events
+            .iter_mut()
+            .filter(|prev| prev.is_active() && !ids.contains(&prev.id))
+        {
+            prev.active = Some(false);
+            prev.sample_pos = sample_pos;
+            diffs.push(Event {
+                id: prev.id,
+                sample_pos,
+                active: Some(false),
+                ..Default::default()
+            });
+        }
+        diffs
+    }
+
     pub fn serialize_events(&mut self, remove_header: bool) -> String {
         if remove_header {
             self.events.retain(|e| e != &Event::default());
@@ -368,6 +662,22 @@ impl Configuration {
         oamd: &ObjectAudioMetadataPayload,
         sample_rate: u32,
         sample_pos: u64,
+    ) -> Self {
+        Self::with_object_registry(
+            oamd,
+            sample_rate,
+            sample_pos,
+            &mut ObjectRegistry::default(),
+        )
+    }
+
+    /// Events of `oamd` with the ISF and dynamic object IDs of `objects`,
+    /// which registers the objects it has not seen before.
+    pub fn with_object_registry(
+        oamd: &ObjectAudioMetadataPayload,
+        sample_rate: u32,
+        sample_pos: u64,
+        objects: &mut ObjectRegistry,
     ) -> Self {
         let object_count = oamd.object_count;
         let Some(object_element) = &oamd.object_element else {
@@ -397,17 +707,6 @@ impl Configuration {
             "Found multiple update blocks, please submit a sample"
         );
 
-        assert_eq!(
-            oamd.program_assignment.bed_assignment.len(),
-            1,
-            "Found multiple bed instances, please submit a sample"
-        );
-
-        assert_eq!(
-            oamd.program_assignment.num_isf_objects, 0,
-            "Found ISF objects, please submit a sample"
-        );
-
         let sample_offset = object_element.md_update_info.sample_offset as u64;
         let ramp_duration =
             object_element.md_update_info.block_update_info[0].ramp_duration as usize;
@@ -416,29 +715,26 @@ impl Configuration {
 
         let mut events = Vec::with_capacity(object_count);
 
-        let bed_index_vec = if let Some(bed) = oamd.program_assignment.bed_assignment.first() {
-            bed.to_index_vec()
-        } else {
-            Vec::new()
-        };
+        // Bed objects of all instances precede the ISF and dynamic objects
+        let bed_ids = BedInstance::channel_ids(&oamd.program_assignment.bed_assignment)
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
 
+        let num_isf_objects = oamd.program_assignment.num_isf_objects;
         for i in 0..object_count {
             let object_data = &object_element.object_data[i][0];
-            let id = if object_data.b_object_in_bed_or_isf {
-                let index = bed_index_vec[i];
-
-                // assign unusual bed objects to the end (skip 128 & 129)
-                match index {
-                    0..8 => index,
-                    8..10 => index + 122,
-                    10..12 => index - 2,
-                    _ => index + 120,
-                }
-            } else {
-                i + 10 - bed_index_vec.len()
+            let id = match bed_ids.get(i) {
+                Some(&id) if object_data.b_object_in_bed_or_isf => id,
+                _ => match i.saturating_sub(bed_ids.len()) {
+                    position if position < num_isf_objects => {
+                        objects.id(ObjectClass::Isf, position)
+                    }
+                    position => objects.id(ObjectClass::Dynamic, position - num_isf_objects),
+                },
             };
 
-            let mut event: Event = Event::with_id(id as u32);
+            let mut event: Event = Event::with_id(id);
             event.active = Some(!object_data.b_object_not_active);
             event.sample_pos = Some(sample_pos);
 
@@ -462,12 +758,25 @@ impl Configuration {
                 event.screen_factor = Some(render.screen_factor);
                 event.depth_factor = Some(render.depth_factor);
 
-                // Unimplemented, seems in ObjectDescriptionElement
-                event.dialog = Some(-1);
-                event.music = Some(-1);
-
-                event.binaural_render_mode = Some("undefined".to_string());
+                // -1 when the content is not classified
+                let description = oamd
+                    .object_description_element
+                    .as_ref()
+                    .and_then(|element| element.object_description.get(i).copied().flatten());
+                event.dialog = Some(description.map_or(-1, |d| d.b_dialog as i32));
+                event.music = Some(description.map_or(-1, |d| d.b_music as i32));
+
+                let render_mode = oamd
+                    .headphone_element
+                    .as_ref()
+                    .and_then(|element| element.binaural_render_mode.get(i).copied().flatten());
+                event.binaural_render_mode = Some(
+                    render_mode
+                        .map_or("undefined", |mode| mode.as_str())
+                        .to_string(),
+                );
             } else {
+                // Bed and ISF objects are speaker-anchored
                 event.binaural_render_mode = Some("off".to_string());
             }
 
@@ -571,7 +880,44 @@ impl Event {
         }
     }
 
-    fn diff(&self, b: &Self) -> Self {
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    pub fn sample_pos(&self) -> Option<u64> {
+        self.sample_pos
+    }
+
+    /// Whether the object is active, which it is unless stated otherwise
+    pub fn is_active(&self) -> bool {
+        self.active.unwrap_or(true)
+    }
+
+    /// Object position in DAMF coordinates
+    pub fn position(&self) -> Option<[f64; 3]> {
+        self.pos
+            .as_ref()
+            .and_then(|pos| <[f64; 3]>::try_from(pos.0.as_slice()).ok())
+    }
+
+    /// Object gain in dB, negative infinity when muted
+    pub fn gain_db(&self) -> Option<f64> {
+        self.gain.as_deref().map(|gain| match gain {
+            "-inf" => f64::NEG_INFINITY,
+            gain => gain.parse().unwrap_or(0.0),
+        })
+    }
+
+    pub fn ramp_length(&self) -> Option<u32> {
+        self.ramp_length
+    }
+
+    pub fn size(&self) -> Option<f64> {
+        self.size
+    }
+
+    /// Fields of `b` that differ from `self`, or `None` if nothing changed
+    fn diff(&self, b: &Self) -> Option<Self> {
         let mut out = Self::default();
 
         macro_rules! diff {
@@ -607,20 +953,36 @@ impl Event {
             binaural_render_mode
         );
 
-        if out != Self::default() {
-            out.id = b.id;
-            out.sample_pos = b.sample_pos;
+        if out == Self::default() {
+            return None;
         }
 
-        out
+        out.id = b.id;
+        out.sample_pos = b.sample_pos;
+
+        Some(out)
     }
 
-    pub fn compare_event_vectors(events1: &[Event], events2: &[Event]) -> Vec<Event> {
-        events1
-            .iter()
-            .zip(events2)
-            .map(|(event1, event2)| event1.diff(event2))
-            .collect()
+    fn is_position_only(&self) -> bool {
+        self.pos.is_some()
+            && Self {
+                id: None,
+                sample_pos: None,
+                pos: None,
+                ..self.clone()
+            } == Self::default()
+    }
+
+    /// Largest per-axis position change between two events
+    fn position_delta(&self, b: &Self) -> f64 {
+        match (&self.pos, &b.pos) {
+            (Some(a), Some(b)) => {
+                a.0.iter()
+                    .zip(&b.0)
+                    .fold(0.0, |delta, (a, b)| f64::max(delta, (a - b).abs()))
+            }
+            _ => f64::INFINITY,
+        }
     }
 }
 
@@ -815,3 +1177,413 @@ presentations:
 
     assert_eq!(test_str, format_yaml_string(yaml_str));
 }
+
+#[test]
+fn event_diff_thinning() {
+    let event_at = |sample_pos: u64, x: f64| Event {
+        sample_pos: Some(sample_pos),
+        pos: Some(VecDisplay(vec![x, 0.0, 0.0])),
+        gain: Some("0".to_string()),
+        ..Event::with_id(10)
+    };
+    let conf_at = |sample_pos: u64, x: f64| Configuration {
+        sample_rate: Some(48000),
+        events: vec![event_at(sample_pos, x)],
+    };
+
+    let thinning = Some(MetadataThinning::with_update_rate(100, 48000));
+    let mut prev_events = Vec::new();
+
+    let header = conf_at(0, 0.0).serialize_events_diff(&mut prev_events, thinning);
+    assert!(header.starts_with("sampleRate: 48000"));
+
+    // Unchanged events produce no output
+    assert!(
+        conf_at(40, 0.0)
+            .serialize_events_diff(&mut prev_events, thinning)
+            .is_empty()
+    );
+
+    // Small move within the update interval is held back
+    assert!(
+        conf_at(80, 0.01)
+            .serialize_events_diff(&mut prev_events, thinning)
+            .is_empty()
+    );
+    assert_eq!(prev_events[0].sample_pos, Some(0));
+
+    // Large move is written immediately, and only the changed field is kept
+    let diff = conf_at(120, 0.5).serialize_events_diff(&mut prev_events, thinning);
+    assert!(diff.contains("pos: [0.5, 0, 0]"));
+    assert!(!diff.contains("gain"));
+
+    // Small move is written once the update interval has passed
+    assert!(
+        conf_at(240, 0.51)
+            .serialize_events_diff(&mut prev_events, thinning)
+            .is_empty()
+    );
+    assert!(
+        !conf_at(4920, 0.51)
+            .serialize_events_diff(&mut prev_events, thinning)
+            .is_empty()
+    );
+    assert_eq!(prev_events[0].sample_pos, Some(4920));
+}
+
+#[test]
+fn event_diff_objects_added_mid_stream() {
+    let event_at = |id: u32, sample_pos: u64| Event {
+        sample_pos: Some(sample_pos),
+        pos: Some(VecDisplay(vec![0.0, 0.0, 0.0])),
+        gain: Some("0".to_string()),
+        ..Event::with_id(id)
+    };
+    let conf_at = |ids: &[u32], sample_pos: u64| Configuration {
+        sample_rate: Some(48000),
+        events: ids.iter().map(|&id| event_at(id, sample_pos)).collect(),
+    };
+
+    let mut prev_events = Vec::new();
+    conf_at(&[10], 0).serialize_events_diff(&mut prev_events, None);
+    assert_eq!(prev_events.len(), 1);
+
+    // Objects appearing later are written in full and tracked from then on
+    let diff = conf_at(&[10, 11, 12], 1600).serialize_events_diff(&mut prev_events, None);
+    assert!(!diff.contains("ID: 10"));
+    for id in [11, 12] {
+        assert!(diff.contains(&format!("ID: {id}")));
+    }
+    assert_eq!(diff.matches("pos: [0, 0, 0]").count(), 2);
+    assert_eq!(diff.matches("gain: 0").count(), 2);
+    assert_eq!(
+        prev_events.iter().map(|event| event.id).collect::<Vec<_>>(),
+        [Some(10), Some(11), Some(12)]
+    );
+
+    assert!(
+        conf_at(&[10, 11, 12], 3200)
+            .serialize_events_diff(&mut prev_events, None)
+            .is_empty()
+    );
+}
+
+#[test]
+fn fps_from_framerate() {
+    assert_eq!(
+        Fps::from_framerate(&Framerate::R23_976, false),
+        Some(Fps::R23_976)
+    );
+    assert_eq!(Fps::from_framerate(&Framerate::R24, false), Some(Fps::R24));
+    assert_eq!(
+        Fps::from_framerate(&Framerate::R29_97, false),
+        Some(Fps::R29_97)
+    );
+    assert_eq!(
+        Fps::from_framerate(&Framerate::R29_97, true),
+        Some(Fps::R29_97df)
+    );
+    assert_eq!(Fps::from_framerate(&Framerate::R50, false), None);
+}
+
+#[test]
+fn multiple_bed_instances() {
+    use truehd::structs::oamd::TEST_DATA_TRIM;
+
+    let mut oamd = ObjectAudioMetadataPayload::read(TEST_DATA_TRIM).unwrap();
+    // 5.1 with Lts/Rts, followed by a second 5.1 instance
+    let mut first = BedAssignment::from_std(0b0000_1111);
+    first.0[SpeakerLabels::Lts as usize] = true;
+    first.0[SpeakerLabels::Rts as usize] = true;
+    let second = BedAssignment::from_std(0b0000_1111);
+    oamd.program_assignment.bed_assignment = vec![first, second];
+
+    let ids = BedInstance::channel_ids(&oamd.program_assignment.bed_assignment);
+    assert_eq!(ids[0], vec![0, 1, 2, 3, 4, 5, 8, 9]);
+    assert_eq!(ids[1], vec![137, 138, 139, 140, 141, 142]);
+
+    let instances = BedInstance::with_oamd_payload(&oamd);
+    assert_eq!(instances.len(), 2);
+    assert_eq!(
+        instances[1].to_index_vec(),
+        ids[1].iter().map(|&id| id as usize).collect::<Vec<_>>()
+    );
+    assert_eq!(instances[1].channels[0].channel, "L");
+
+    // Bed conformance replaces the first instance only
+    use SpeakerLabels::*;
+    let speakers = [L, R, C, LFE, Lss, Rss, Lrs, Rrs, Lts, Rts];
+    let data = Data::with_oamd_payload_bed_conform(&oamd, Path::new("test"), &speakers);
+    let bed_instances = &data.presentations[0].bed_instances;
+    assert_eq!(bed_instances.len(), 2);
+    assert_eq!(bed_instances[0].to_index_vec(), (0..10).collect::<Vec<_>>());
+    assert_eq!(bed_instances[0].channels[8].channel, "Lts");
+    assert_eq!(bed_instances[1].to_index_vec(), instances[1].to_index_vec());
+}
+
+#[test]
+fn isf_objects_follow_beds() {
+    use truehd::structs::oamd::TEST_DATA_TRIM;
+
+    let mut oamd = ObjectAudioMetadataPayload::read(TEST_DATA_TRIM).unwrap();
+    let program_assignment = &mut oamd.program_assignment;
+    assert_eq!(program_assignment.num_bed_objects, 1);
+
+    // Reinterpret the first four dynamic objects as ISF objects
+    program_assignment.num_isf_objects = 4;
+    program_assignment.num_dynamic_objects -= 4;
+    let object_element = oamd.object_element.as_mut().unwrap();
+    for object_data in &mut object_element.object_data[1..5] {
+        object_data[0].b_object_in_bed_or_isf = true;
+    }
+
+    let data = Data::with_oamd_payload(&oamd, Path::new("test"));
+    let objects = &data.presentations[0].objects;
+    assert_eq!(objects.len(), 15);
+    assert_eq!(objects.first().map(|object| object.id), Some(10));
+    assert_eq!(objects.last().map(|object| object.id), Some(24));
+
+    let configuration = Configuration::with_oamd_payload(&oamd, 48000, 0);
+    let ids = configuration
+        .events
+        .iter()
+        .map(|event| event.id.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, [3].into_iter().chain(10..25).collect::<Vec<_>>());
+
+    for event in &configuration.events[1..5] {
+        assert_eq!(event.binaural_render_mode.as_deref(), Some("off"));
+        assert!(event.pos.is_none());
+    }
+    assert_eq!(
+        configuration.events[5].binaural_render_mode.as_deref(),
+        Some("undefined")
+    );
+}
+
+#[test]
+fn headphone_and_description_events() {
+    use truehd::structs::oamd::{
+        BinauralRenderMode, HeadphoneElement, ObjectContentDescription, ObjectDescriptionElement,
+        TEST_DATA_TRIM,
+    };
+
+    let mut oamd = ObjectAudioMetadataPayload::read(TEST_DATA_TRIM).unwrap();
+    let object_count = oamd.object_count;
+
+    let mut binaural_render_mode = vec![None; object_count];
+    binaural_render_mode[1] = Some(BinauralRenderMode::Far);
+    oamd.headphone_element = Some(HeadphoneElement {
+        binaural_render_mode,
+    });
+
+    let mut object_description = vec![None; object_count];
+    object_description[1] = Some(ObjectContentDescription {
+        b_dialog: true,
+        b_music: false,
+    });
+    object_description[2] = Some(ObjectContentDescription {
+        b_dialog: false,
+        b_music: true,
+    });
+    oamd.object_description_element = Some(ObjectDescriptionElement { object_description });
+
+    let configuration = Configuration::with_oamd_payload(&oamd, 48000, 0);
+    let events = &configuration.events;
+
+    // The LFE bed is not rendered binaurally
+    assert_eq!(events[0].binaural_render_mode.as_deref(), Some("off"));
+    assert_eq!(events[0].dialog, None);
+
+    assert_eq!(events[1].binaural_render_mode.as_deref(), Some("far"));
+    assert_eq!((events[1].dialog, events[1].music), (Some(1), Some(0)));
+    assert_eq!(events[2].binaural_render_mode.as_deref(), Some("undefined"));
+    assert_eq!((events[2].dialog, events[2].music), (Some(0), Some(1)));
+    assert_eq!((events[3].dialog, events[3].music), (Some(-1), Some(-1)));
+}
+
+#[test]
+fn rebase_sample_pos() {
+    let mut configuration = Configuration {
+        sample_rate: Some(48000),
+        events: [100, 1500, 2000]
+            .into_iter()
+            .map(|sample_pos| Event {
+                sample_pos: Some(sample_pos),
+                ..Event::with_id(10)
+            })
+            .collect(),
+    };
+
+    configuration.rebase_sample_pos(1600);
+    let positions = configuration
+        .events
+        .iter()
+        .map(|event| event.sample_pos)
+        .collect::<Vec<_>>();
+    assert_eq!(positions, [Some(0), Some(0), Some(400)]);
+}
+
+#[test]
+fn resample_sample_pos() {
+    let mut configuration = Configuration {
+        sample_rate: Some(44100),
+        events: [0, 147, 44100]
+            .into_iter()
+            .map(|sample_pos| Event {
+                sample_pos: Some(sample_pos),
+                ramp_length: Some(32),
+                ..Event::with_id(10)
+            })
+            .collect(),
+    };
+
+    configuration.resample(48000);
+    assert_eq!(configuration.sample_rate, Some(48000));
+    let events = configuration
+        .events
+        .iter()
+        .map(|event| (event.sample_pos, event.ramp_length))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        [
+            (Some(0), Some(35)),
+            (Some(160), Some(35)),
+            (Some(48000), Some(35))
+        ]
+    );
+}
+
+#[test]
+fn bed_conform_header_follows_layout() {
+    use SpeakerLabels::*;
+    use truehd::structs::oamd::TEST_DATA_TRIM;
+
+    let oamd = ObjectAudioMetadataPayload::read(TEST_DATA_TRIM).unwrap();
+    let bed_ids = |speakers: &[SpeakerLabels]| {
+        let data = Data::with_oamd_payload_bed_conform(&oamd, Path::new("test"), speakers);
+        let bed = &data.presentations[0].bed_instances[0];
+        assert!(
+            bed.channels
+                .iter()
+                .zip(speakers)
+                .all(|(channel, speaker)| channel.channel == format!("{speaker:?}"))
+        );
+        bed.to_index_vec()
+    };
+
+    assert_eq!(
+        bed_ids(&[L, R, C, LFE, Lss, Rss]),
+        (0..6).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        bed_ids(&[L, R, C, LFE, Lss, Rss, Lts, Rts]),
+        [0, 1, 2, 3, 4, 5, 8, 9]
+    );
+
+    // Front and rear heights take the IDs of the extra bed channels
+    assert_eq!(
+        bed_ids(&[L, R, C, LFE, Lss, Rss, Lrs, Rrs, Lfh, Rfh, Lrh, Rrh]),
+        [0, 1, 2, 3, 4, 5, 6, 7, 130, 131, 132, 133]
+    );
+
+    // The full bed, with the wides after the rear surrounds
+    let speakers = [
+        L, R, C, LFE, Lss, Rss, Lrs, Rrs, Lw, Rw, Lfh, Rfh, Lts, Rts, Lrh, Rrh,
+    ];
+    assert_eq!(
+        bed_ids(&speakers),
+        [0, 1, 2, 3, 4, 5, 6, 7, 134, 135, 130, 131, 8, 9, 132, 133]
+    );
+    let data = Data::with_oamd_payload_bed_conform(&oamd, Path::new("test"), &speakers);
+    assert!(
+        data.serialize_damf()
+            .contains("scBedConfiguration: [0, 1, 2, 3, 4, 5, 6, 7, 14, 15, 8, 9, 10, 11, 12, 13]")
+    );
+}
+
+#[test]
+fn object_ids_stable_across_program_changes() {
+    use truehd::structs::oamd::TEST_DATA_TRIM;
+
+    let first = ObjectAudioMetadataPayload::read(TEST_DATA_TRIM).unwrap();
+    assert_eq!(first.program_assignment.num_dynamic_objects, 15);
+
+    // The next payload turns the first two dynamic objects into ISF objects
+    let mut second = first.clone();
+    second.program_assignment.num_isf_objects = 2;
+    second.program_assignment.num_dynamic_objects -= 2;
+    for object_data in &mut second.object_element.as_mut().unwrap().object_data[1..3] {
+        object_data[0].b_object_in_bed_or_isf = true;
+    }
+
+    let mut objects = ObjectRegistry::default();
+    let mut prev_events = Vec::new();
+    let ids = |configuration: &Configuration| {
+        configuration
+            .events
+            .iter()
+            .map(|event| event.id.unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    let configuration = Configuration::with_object_registry(&first, 48000, 0, &mut objects);
+    assert_eq!(
+        ids(&configuration),
+        [3].into_iter().chain(10..25).collect::<Vec<_>>()
+    );
+    configuration.diff_events(&mut prev_events, None);
+
+    // Dynamic objects keep their IDs, the ISF objects are new and the last
+    // two dynamic objects are gone
+    let configuration = Configuration::with_object_registry(&second, 48000, 1600, &mut objects);
+    assert_eq!(
+        ids(&configuration),
+        [3, 25, 26].into_iter().chain(10..23).collect::<Vec<_>>()
+    );
+    let diffs = configuration.diff_events(&mut prev_events, None);
+    let added = diffs
+        .iter()
+        .filter(|event| event.pos.is_none() && event.gain.is_some())
+        .map(|event| event.id.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(added, [25, 26]);
+    let removed = diffs
+        .iter()
+        .filter(|event| event.active == Some(false))
+        .map(|event| (event.id.unwrap(), event.sample_pos))
+        .collect::<Vec<_>>();
+    assert_eq!(removed, [(23, Some(1600)), (24, Some(1600))]);
+
+    // Returning to the first program reactivates the dynamic objects
+    let configuration = Configuration::with_object_registry(&first, 48000, 3200, &mut objects);
+    assert_eq!(
+        ids(&configuration),
+        [3].into_iter().chain(10..25).collect::<Vec<_>>()
+    );
+    let diffs = configuration.diff_events(&mut prev_events, None);
+    let active = |id| {
+        diffs
+            .iter()
+            .find(|event| event.id == Some(id))
+            .unwrap()
+            .active
+    };
+    assert_eq!(
+        [23, 24, 25, 26].map(active),
+        [Some(true), Some(true), Some(false), Some(false)]
+    );
+    assert_eq!(objects.len(), 17);
+
+    // The header declares every object of the output
+    let mut data = Data::with_oamd_payload(&first, Path::new("test"));
+    assert!(data.pad_objects(objects.ids()));
+    let header_ids = data.presentations[0]
+        .objects
+        .iter()
+        .map(|object| object.id)
+        .collect::<Vec<_>>();
+    assert_eq!(header_ids, (10..27).collect::<Vec<_>>());
+    assert!(!data.pad_objects(objects.ids()));
+}
@@ -1,12 +1,21 @@
 use std::fs::File;
-use std::io::{self, BufReader, Read};
+use std::io::{self, BufReader, Cursor, Read};
 use std::path::Path;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
+
+#[cfg(feature = "mkv")]
+pub mod mkv;
+
+/// Leading bytes of an EBML (Matroska) file.
+const EBML_MAGIC: [u8; 4] = [0x1A, 0x45, 0xDF, 0xA3];
 
 /// Unified input reader that handles both file and pipe input with buffered reading
 pub struct InputReader {
     reader: Box<dyn Read>,
+    /// Demuxer in front of the reader for Matroska input
+    #[cfg(feature = "mkv")]
+    matroska: Option<mkv::MkvReader<Box<dyn Read>>>,
     is_pipe: bool,
 }
 
@@ -14,24 +23,87 @@ impl InputReader {
     /// Create a new InputReader from a path
     /// Use "-" for stdin pipe input
     pub fn new<P: AsRef<Path>>(input_path: P) -> Result<Self> {
+        Self::with_track(input_path, None)
+    }
+
+    /// Create a new InputReader from a path, reading the given track of a
+    /// Matroska input or its only TrueHD track
+    pub fn with_track<P: AsRef<Path>>(input_path: P, track: Option<u64>) -> Result<Self> {
         let path_str = input_path.as_ref().to_string_lossy();
         let is_pipe = path_str == "-";
 
-        let reader: Box<dyn Read> = if is_pipe {
+        let mut reader: Box<dyn Read> = if is_pipe {
             Box::new(io::stdin().lock())
         } else {
             let file = File::open(input_path)?;
             Box::new(BufReader::new(file))
         };
 
-        Ok(Self { reader, is_pipe })
+        // Sniff the container from the first bytes and put them back
+        let mut magic = Vec::with_capacity(EBML_MAGIC.len());
+        (&mut reader)
+            .take(EBML_MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+        let is_matroska = magic == EBML_MAGIC;
+        let reader: Box<dyn Read> = Box::new(Cursor::new(magic).chain(reader));
+
+        if !is_matroska && track.is_some() {
+            bail!("--track is only supported for Matroska input");
+        }
+
+        #[cfg(feature = "mkv")]
+        if is_matroska {
+            return Ok(Self {
+                reader: Box::new(io::empty()),
+                matroska: Some(mkv::MkvReader::new(reader, track)?),
+                is_pipe,
+            });
+        }
+        #[cfg(not(feature = "mkv"))]
+        if is_matroska {
+            bail!("Matroska input requires the mkv feature");
+        }
+
+        Ok(Self {
+            reader,
+            #[cfg(feature = "mkv")]
+            matroska: None,
+            is_pipe,
+        })
     }
 
     /// Read a chunk of data into the provided buffer
     /// Returns the number of bytes read, 0 indicates EOF
     pub fn read_chunk(&mut self, buffer: &mut [u8]) -> Result<usize> {
-        let bytes_read = self.reader.read(buffer)?;
-        Ok(bytes_read)
+        Ok(self.read(buffer)?)
+    }
+
+    /// Container timestamp in seconds of the first access unit read from a
+    /// Matroska input
+    pub fn start_time(&self) -> Option<f64> {
+        #[cfg(feature = "mkv")]
+        if let Some(matroska) = &self.matroska {
+            return matroska.start_time();
+        }
+        None
+    }
+
+    /// Number of the Matroska track read, known after the first block
+    pub fn track_number(&self) -> Option<u64> {
+        #[cfg(feature = "mkv")]
+        if let Some(matroska) = &self.matroska {
+            return matroska.track_number();
+        }
+        None
+    }
+
+    /// Check if the TrueHD bitstream is demuxed from a Matroska input
+    pub fn is_matroska(&self) -> bool {
+        #[cfg(feature = "mkv")]
+        if self.matroska.is_some() {
+            return true;
+        }
+        false
     }
 
     /// Check if this is pipe input
@@ -43,6 +115,11 @@ impl InputReader {
     /// Note: This should only be used for small files or when you need all data at once
     pub fn read_all(&mut self) -> Result<Vec<u8>> {
         let mut data = Vec::new();
+        #[cfg(feature = "mkv")]
+        if let Some(matroska) = &mut self.matroska {
+            matroska.read_to_end(&mut data)?;
+            return Ok(data);
+        }
         self.reader.read_to_end(&mut data)?;
         Ok(data)
     }
@@ -69,3 +146,14 @@ impl InputReader {
         Ok(())
     }
 }
+
+/// Reads the TrueHD bitstream, demuxed from a Matroska input
+impl Read for InputReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "mkv")]
+        if let Some(matroska) = &mut self.matroska {
+            return matroska.read(buf);
+        }
+        self.reader.read(buf)
+    }
+}
@@ -0,0 +1,623 @@
+//! Minimal Matroska demuxer for TrueHD tracks.
+//!
+//! Reads the EBML header, the segment info, the tracks and the blocks of the
+//! clusters linearly, and yields the block payloads of one TrueHD track as a
+//! plain bitstream. Lacing and header stripping are supported, other content
+//! encodings are not.
+
+use std::io::{self, ErrorKind, Read};
+
+use anyhow::{Result, anyhow, bail};
+
+const ID_EBML: u32 = 0x1A45_DFA3;
+const ID_DOC_TYPE: u32 = 0x4282;
+const ID_SEGMENT: u32 = 0x1853_8067;
+const ID_INFO: u32 = 0x1549_A966;
+const ID_TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const ID_TRACKS: u32 = 0x1654_AE6B;
+const ID_TRACK_ENTRY: u32 = 0xAE;
+const ID_TRACK_NUMBER: u32 = 0xD7;
+const ID_CODEC_ID: u32 = 0x86;
+const ID_CONTENT_ENCODINGS: u32 = 0x6D80;
+const ID_CONTENT_ENCODING: u32 = 0x6240;
+const ID_CONTENT_COMPRESSION: u32 = 0x5034;
+const ID_CONTENT_COMP_ALGO: u32 = 0x4254;
+const ID_CONTENT_COMP_SETTINGS: u32 = 0x4255;
+const ID_CONTENT_ENCRYPTION: u32 = 0x5035;
+const ID_CLUSTER: u32 = 0x1F43_B675;
+const ID_CLUSTER_TIMESTAMP: u32 = 0xE7;
+const ID_BLOCK_GROUP: u32 = 0xA0;
+const ID_BLOCK: u32 = 0xA1;
+const ID_SIMPLE_BLOCK: u32 = 0xA3;
+
+/// Master elements whose children are read in place, which also covers
+/// elements of unknown size.
+const DESCENDED: [u32; 9] = [
+    ID_SEGMENT,
+    ID_INFO,
+    ID_TRACKS,
+    ID_TRACK_ENTRY,
+    ID_CONTENT_ENCODINGS,
+    ID_CONTENT_ENCODING,
+    ID_CONTENT_COMPRESSION,
+    ID_CLUSTER,
+    ID_BLOCK_GROUP,
+];
+
+/// Codec IDs of the tracks that can be decoded.
+const TRUEHD_CODEC_IDS: [&str; 2] = ["A_TRUEHD", "A_MLP"];
+
+/// Header removal compression, the only content encoding supported.
+const COMP_ALGO_HEADER_STRIPPING: u64 = 3;
+
+/// Largest element read into memory.
+const MAX_ELEMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Default, Clone)]
+struct TrackEntry {
+    number: u64,
+    codec_id: String,
+    comp_algo: Option<u64>,
+    stripped_header: Vec<u8>,
+    encrypted: bool,
+}
+
+impl TrackEntry {
+    fn is_truehd(&self) -> bool {
+        TRUEHD_CODEC_IDS.contains(&self.codec_id.as_str())
+    }
+}
+
+/// Reader of the TrueHD bitstream in a Matroska file.
+pub struct MkvReader<R> {
+    reader: R,
+    requested_track: Option<u64>,
+    tracks: Vec<TrackEntry>,
+    track: Option<TrackEntry>,
+    timestamp_scale: u64,
+    cluster_timestamp: u64,
+    /// Timestamp of the first block of the track in seconds
+    start_time: Option<f64>,
+    payload: Vec<u8>,
+    payload_pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> MkvReader<R> {
+    /// Opens a Matroska stream, selecting the track with the given track
+    /// number or the only TrueHD track.
+    pub fn new(mut reader: R, track: Option<u64>) -> Result<Self> {
+        let (id, size) =
+            read_element_header(&mut reader)?.ok_or_else(|| anyhow!("Empty Matroska input"))?;
+        if id != ID_EBML {
+            bail!("Input is not a Matroska file");
+        }
+        let size = size.ok_or_else(|| anyhow!("EBML header has an unknown size"))?;
+        let header = read_payload(&mut reader, size)?;
+        check_doc_type(&header)?;
+
+        Ok(Self {
+            reader,
+            requested_track: track,
+            tracks: Vec::new(),
+            track: None,
+            timestamp_scale: 1_000_000,
+            cluster_timestamp: 0,
+            start_time: None,
+            payload: Vec::new(),
+            payload_pos: 0,
+            eof: false,
+        })
+    }
+
+    /// Track number of the TrueHD track, known after the first block.
+    pub fn track_number(&self) -> Option<u64> {
+        self.track.as_ref().map(|track| track.number)
+    }
+
+    /// Container timestamp of the first TrueHD block in seconds.
+    pub fn start_time(&self) -> Option<f64> {
+        self.start_time
+    }
+
+    /// Reads elements until the next block of the track has been buffered.
+    fn fill_payload(&mut self) -> Result<()> {
+        while self.payload_pos == self.payload.len() && !self.eof {
+            let Some((id, size)) = read_element_header(&mut self.reader)? else {
+                self.eof = true;
+                break;
+            };
+
+            if DESCENDED.contains(&id) {
+                if id == ID_TRACK_ENTRY {
+                    self.tracks.push(TrackEntry::default());
+                }
+                continue;
+            }
+
+            let Some(size) = size else {
+                bail!("Element {id:#X} has an unknown size");
+            };
+
+            match id {
+                ID_TIMESTAMP_SCALE => self.timestamp_scale = read_uint(&mut self.reader, size)?,
+                ID_CLUSTER_TIMESTAMP => self.cluster_timestamp = read_uint(&mut self.reader, size)?,
+                ID_TRACK_NUMBER => {
+                    let number = read_uint(&mut self.reader, size)?;
+                    self.current_track()?.number = number;
+                }
+                ID_CODEC_ID => {
+                    let codec_id = read_payload(&mut self.reader, size)?;
+                    self.current_track()?.codec_id = String::from_utf8_lossy(&codec_id)
+                        .trim_end_matches('\0')
+                        .to_string();
+                }
+                ID_CONTENT_COMP_ALGO => {
+                    let algo = read_uint(&mut self.reader, size)?;
+                    self.current_track()?.comp_algo = Some(algo);
+                }
+                ID_CONTENT_COMP_SETTINGS => {
+                    let settings = read_payload(&mut self.reader, size)?;
+                    self.current_track()?.stripped_header = settings;
+                }
+                ID_CONTENT_ENCRYPTION => {
+                    skip(&mut self.reader, size)?;
+                    self.current_track()?.encrypted = true;
+                }
+                ID_SIMPLE_BLOCK | ID_BLOCK => {
+                    let block = match read_payload(&mut self.reader, size) {
+                        Ok(block) => block,
+                        Err(e) if is_eof(&e) => {
+                            log::warn!("Matroska input ends inside a block");
+                            self.eof = true;
+                            break;
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    self.handle_block(&block)?;
+                }
+                _ => skip(&mut self.reader, size)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn current_track(&mut self) -> Result<&mut TrackEntry> {
+        self.tracks
+            .last_mut()
+            .ok_or_else(|| anyhow!("Track element outside a track entry"))
+    }
+
+    fn select_track(&mut self) -> Result<TrackEntry> {
+        let track = match self.requested_track {
+            Some(number) => {
+                let track = self
+                    .tracks
+                    .iter()
+                    .find(|track| track.number == number)
+                    .ok_or_else(|| anyhow!("Matroska track {number} does not exist"))?;
+                if !track.is_truehd() {
+                    bail!("Matroska track {number} is {}, not TrueHD", track.codec_id);
+                }
+                track
+            }
+            None => {
+                let truehd_tracks: Vec<_> = self
+                    .tracks
+                    .iter()
+                    .filter(|track| track.is_truehd())
+                    .collect();
+                match truehd_tracks[..] {
+                    [] => bail!("No TrueHD track found in the Matroska input"),
+                    [track] => track,
+                    _ => bail!(
+                        "Matroska input has {} TrueHD tracks ({}), select one with --track",
+                        truehd_tracks.len(),
+                        truehd_tracks
+                            .iter()
+                            .map(|track| track.number.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                }
+            }
+        };
+
+        if track.encrypted {
+            bail!("Matroska track {} is encrypted", track.number);
+        }
+        if let Some(algo) = track.comp_algo
+            && algo != COMP_ALGO_HEADER_STRIPPING
+        {
+            bail!(
+                "Matroska track {} uses unsupported compression {algo}",
+                track.number
+            );
+        }
+
+        log::debug!(
+            "Reading TrueHD track {} of the Matroska input",
+            track.number
+        );
+        Ok(track.clone())
+    }
+
+    fn handle_block(&mut self, block: &[u8]) -> Result<()> {
+        let mut data = block;
+        let track_number = read_vint(&mut data)?.0;
+
+        if self.track.is_none() {
+            self.track = Some(self.select_track()?);
+        }
+        let Some(track) = &self.track else {
+            unreachable!()
+        };
+        if track_number != track.number {
+            return Ok(());
+        }
+
+        if data.len() < 3 {
+            bail!("Matroska block is too short");
+        }
+        let relative_timestamp = i16::from_be_bytes([data[0], data[1]]);
+        let flags = data[2];
+        data = &data[3..];
+
+        if self.start_time.is_none() {
+            let timestamp = self.cluster_timestamp as i64 + relative_timestamp as i64;
+            let start_time = timestamp as f64 * self.timestamp_scale as f64 / 1e9;
+            log::debug!("TrueHD track starts at {start_time:.6} s");
+            self.start_time = Some(start_time);
+        }
+
+        let frame_sizes = read_lacing(&mut data, flags)?;
+
+        self.payload.clear();
+        self.payload_pos = 0;
+        if track.stripped_header.is_empty() {
+            self.payload.extend_from_slice(data);
+        } else {
+            for size in frame_sizes {
+                let (frame, rest) = data
+                    .split_at_checked(size)
+                    .ok_or_else(|| anyhow!("Matroska lace exceeds the block"))?;
+                self.payload.extend_from_slice(&track.stripped_header);
+                self.payload.extend_from_slice(frame);
+                data = rest;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for MkvReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill_payload().map_err(io::Error::other)?;
+
+        let available = &self.payload[self.payload_pos..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.payload_pos += len;
+        Ok(len)
+    }
+}
+
+fn check_doc_type(mut header: &[u8]) -> Result<()> {
+    while let Some((id, size)) = read_element_header(&mut header)? {
+        let size = size.ok_or_else(|| anyhow!("EBML header element has an unknown size"))?;
+        let payload = read_payload(&mut header, size)?;
+        if id == ID_DOC_TYPE {
+            let doc_type = String::from_utf8_lossy(&payload);
+            let doc_type = doc_type.trim_end_matches('\0');
+            if doc_type != "matroska" && doc_type != "webm" {
+                bail!("Unsupported EBML document type {doc_type}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the sizes of the frames in a block, leaving `data` at the first frame.
+fn read_lacing(data: &mut &[u8], flags: u8) -> Result<Vec<usize>> {
+    let lacing = (flags >> 1) & 3;
+    if lacing == 0 {
+        return Ok(vec![data.len()]);
+    }
+
+    let (&count, rest) = data
+        .split_first()
+        .ok_or_else(|| anyhow!("Matroska block is too short"))?;
+    *data = rest;
+    let frames = count as usize + 1;
+
+    let mut sizes = Vec::with_capacity(frames);
+    match lacing {
+        // Xiph lacing
+        1 => {
+            for _ in 1..frames {
+                let mut size = 0;
+                loop {
+                    let (&byte, rest) = data
+                        .split_first()
+                        .ok_or_else(|| anyhow!("Matroska lace header is truncated"))?;
+                    *data = rest;
+                    size += byte as usize;
+                    if byte != 255 {
+                        break;
+                    }
+                }
+                sizes.push(size);
+            }
+        }
+        // EBML lacing
+        3 => {
+            let mut size = read_vint(data)?.0 as i64;
+            sizes.push(size as usize);
+            for _ in 2..frames {
+                let (value, len) = read_vint(data)?;
+                let bias = (1i64 << (7 * len - 1)) - 1;
+                size += value as i64 - bias;
+                if size < 0 {
+                    bail!("Matroska lace has a negative size");
+                }
+                sizes.push(size as usize);
+            }
+        }
+        // Fixed-size lacing
+        _ => {
+            if !data.len().is_multiple_of(frames) {
+                bail!("Matroska fixed-size lace does not divide the block");
+            }
+            sizes.resize(frames - 1, data.len() / frames);
+        }
+    }
+
+    let laced: usize = sizes.iter().sum();
+    let last = data
+        .len()
+        .checked_sub(laced)
+        .ok_or_else(|| anyhow!("Matroska lace exceeds the block"))?;
+    sizes.push(last);
+    Ok(sizes)
+}
+
+/// Reads a variable-size integer with its length marker removed.
+fn read_vint(data: &mut &[u8]) -> Result<(u64, usize)> {
+    let mut reader = *data;
+    let (value, len) =
+        read_vint_from(&mut reader, true)?.ok_or_else(|| anyhow!("Matroska block is too short"))?;
+    *data = &data[len..];
+    Ok((value, len))
+}
+
+fn read_vint_from(reader: &mut impl Read, strip_marker: bool) -> Result<Option<(u64, usize)>> {
+    let mut first = [0u8];
+    if reader.read(&mut first)? == 0 {
+        return Ok(None);
+    }
+
+    let len = first[0].leading_zeros() as usize + 1;
+    if len > 8 {
+        bail!("Invalid EBML variable-size integer");
+    }
+
+    let mut value = first[0] as u64;
+    if strip_marker {
+        value &= 0xFF >> len;
+    }
+    let mut rest = [0u8; 7];
+    reader.read_exact(&mut rest[..len - 1])?;
+    for &byte in &rest[..len - 1] {
+        value = (value << 8) | byte as u64;
+    }
+
+    Ok(Some((value, len)))
+}
+
+/// Reads an element ID and size, the size is `None` when unknown.
+fn read_element_header(reader: &mut impl Read) -> Result<Option<(u32, Option<u64>)>> {
+    let Some((id, id_len)) = read_vint_from(reader, false)? else {
+        return Ok(None);
+    };
+    if id_len > 4 {
+        bail!("Invalid EBML element ID {id:#X}");
+    }
+
+    let (size, size_len) = read_vint_from(reader, true)?
+        .ok_or_else(|| anyhow!("Matroska input ends inside an element header"))?;
+    let unknown = (1u64 << (7 * size_len)) - 1;
+
+    Ok(Some((id as u32, (size != unknown).then_some(size))))
+}
+
+fn read_payload(reader: &mut impl Read, size: u64) -> Result<Vec<u8>> {
+    if size > MAX_ELEMENT_SIZE {
+        bail!("Matroska element of {size} bytes is too large");
+    }
+    let mut payload = vec![0; size as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+fn read_uint(reader: &mut impl Read, size: u64) -> Result<u64> {
+    if size > 8 {
+        bail!("Matroska integer of {size} bytes is too large");
+    }
+    Ok(read_payload(reader, size)?
+        .iter()
+        .fold(0, |value, &byte| (value << 8) | byte as u64))
+}
+
+fn skip(reader: &mut impl Read, size: u64) -> Result<()> {
+    let skipped = io::copy(&mut reader.take(size), &mut io::sink())?;
+    if skipped != size {
+        return Err(io::Error::from(ErrorKind::UnexpectedEof).into());
+    }
+    Ok(())
+}
+
+fn is_eof(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use truehd::process::EXAMPLE_DATA;
+
+    fn element(id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut data: Vec<u8> = id
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&b| b == 0)
+            .collect();
+        // Eight byte sizes as written by muxers that reserve space
+        data.extend_from_slice(&(payload.len() as u64 | 1 << 56).to_be_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn unknown_size(id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut data: Vec<u8> = id
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&b| b == 0)
+            .collect();
+        data.push(0xFF);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn track(number: u8, codec_id: &str, stripped_header: &[u8]) -> Vec<u8> {
+        let mut entry = element(ID_TRACK_NUMBER, &[number]);
+        entry.extend(element(ID_CODEC_ID, codec_id.as_bytes()));
+        if !stripped_header.is_empty() {
+            let mut compression = element(ID_CONTENT_COMP_ALGO, &[3]);
+            compression.extend(element(ID_CONTENT_COMP_SETTINGS, stripped_header));
+            let encoding = element(ID_CONTENT_COMPRESSION, &compression);
+            let encodings = element(ID_CONTENT_ENCODING, &encoding);
+            entry.extend(element(ID_CONTENT_ENCODINGS, &encodings));
+        }
+        element(ID_TRACK_ENTRY, &entry)
+    }
+
+    fn block(track: u8, timestamp: i16, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x80 | track];
+        data.extend_from_slice(&timestamp.to_be_bytes());
+        data.push(flags);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn simple_block(track: u8, timestamp: i16, flags: u8, payload: &[u8]) -> Vec<u8> {
+        element(ID_SIMPLE_BLOCK, &block(track, timestamp, flags, payload))
+    }
+
+    fn matroska(tracks: &[Vec<u8>], clusters: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = element(ID_EBML, &element(ID_DOC_TYPE, b"matroska"));
+        let mut segment = element(ID_INFO, &element(ID_TIMESTAMP_SCALE, &[0x0F, 0x42, 0x40]));
+        segment.extend(element(ID_TRACKS, &tracks.concat()));
+        for cluster in clusters {
+            segment.extend(cluster);
+        }
+        data.extend(unknown_size(ID_SEGMENT, &segment));
+        data
+    }
+
+    fn demux(data: &[u8], track: Option<u64>) -> Result<Vec<u8>> {
+        let mut reader = MkvReader::new(data, track)?;
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn demux_interleaved_tracks() -> Result<()> {
+        let data = EXAMPLE_DATA.repeat(16);
+        let (first, second) = data.split_at(1000);
+        let mut cluster = element(ID_CLUSTER_TIMESTAMP, &[0x01, 0xF4]);
+        cluster.extend(simple_block(1, 0, 0x80, &[0xEE; 100]));
+        cluster.extend(simple_block(2, 12, 0x80, first));
+        cluster.extend(simple_block(1, 40, 0x00, &[0xEE; 50]));
+        let block_group = element(ID_BLOCK, &block(2, 13, 0x00, second));
+        cluster.extend(element(ID_BLOCK_GROUP, &block_group));
+        let data = matroska(
+            &[track(1, "V_MPEG4/ISO/AVC", &[]), track(2, "A_TRUEHD", &[])],
+            &[unknown_size(ID_CLUSTER, &cluster)],
+        );
+
+        let mut reader = MkvReader::new(data.as_slice(), None)?;
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output)?;
+        assert_eq!(output, [first, second].concat());
+        assert_eq!(reader.track_number(), Some(2));
+        assert_eq!(reader.start_time(), Some(0.512));
+
+        Ok(())
+    }
+
+    #[test]
+    fn demux_laced_and_stripped_frames() -> Result<()> {
+        let header = [0xAB, 0xCD];
+        let data = EXAMPLE_DATA.repeat(16);
+        let frames: Vec<&[u8]> = vec![
+            &data[..600],
+            &data[600..700],
+            &data[700..1000],
+            &data[1000..1100],
+            &data[1100..],
+        ];
+
+        // Xiph lacing of two frames with the first one 600 bytes long
+        let mut xiph = vec![1, 255, 255, 90];
+        xiph.extend(frames[..2].concat());
+        // EBML lacing of three frames, sizes 300 and 300 - 200
+        let mut ebml = vec![2, 0x41, 0x2C];
+        ebml.extend_from_slice(&(0x4000u16 | (8191 - 200)).to_be_bytes());
+        ebml.extend(frames[2..].concat());
+
+        let mut cluster = element(ID_CLUSTER_TIMESTAMP, &[0]);
+        cluster.extend(simple_block(1, 0, 0x82, &xiph));
+        cluster.extend(simple_block(1, 20, 0x86, &ebml));
+        let data = matroska(
+            &[track(1, "A_TRUEHD", &header)],
+            &[element(ID_CLUSTER, &cluster)],
+        );
+
+        let expected: Vec<u8> = frames
+            .iter()
+            .flat_map(|frame| [&header[..], frame].concat())
+            .collect();
+        assert_eq!(demux(&data, Some(1))?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn track_selection() {
+        let mut cluster = element(ID_CLUSTER_TIMESTAMP, &[0]);
+        cluster.extend(simple_block(1, 0, 0x80, &[0; 8]));
+        let data = matroska(
+            &[track(1, "A_AC3", &[]), track(2, "A_TRUEHD", &[])],
+            &[element(ID_CLUSTER, &cluster)],
+        );
+
+        let error = demux(&data, Some(1)).unwrap_err();
+        assert!(error.to_string().contains("A_AC3"), "{error}");
+        assert!(demux(&data, Some(3)).is_err());
+        assert_eq!(demux(&data, Some(2)).unwrap(), Vec::<u8>::new());
+        assert!(demux(&EXAMPLE_DATA[..64], None).is_err());
+
+        // Several TrueHD tracks need a track number
+        let data = matroska(
+            &[track(1, "A_TRUEHD", &[]), track(2, "A_TRUEHD", &[])],
+            &[element(ID_CLUSTER, &cluster)],
+        );
+        let error = demux(&data, None).unwrap_err();
+        assert!(error.to_string().contains("(1, 2)"), "{error}");
+        assert_eq!(demux(&data, Some(1)).unwrap(), [0; 8]);
+    }
+}
@@ -2,22 +2,30 @@
 
 use anyhow::Result;
 use clap::Parser as ClapParser;
-use cli::command::{Cli, Commands, LogFormat};
+use cli::command::{Cli, Commands, EVENT_LOG_TARGET, LogFormat};
 use cli::decode::cmd_decode;
+use cli::demux::cmd_demux;
+use cli::edit::cmd_edit;
 use cli::info::cmd_info;
+use cli::mat::cmd_mat;
+use cli::trim::cmd_trim;
+use cli::verify::cmd_verify;
 use indicatif::MultiProgress;
 use indicatif_log_bridge::LogWrapper;
 use log::info;
+use std::process::ExitCode;
 
+mod adm;
 mod byteorder;
 mod caf;
 mod cli;
 mod damf;
 mod input;
+mod riff;
 pub(crate) mod timestamp;
 mod wav;
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
 
     let base_level = cli.loglevel.to_level_filter();
@@ -26,6 +34,8 @@ fn main() -> Result<()> {
 
     let mut env_builder = env_logger::Builder::from_default_env();
     env_builder.filter_level(base_level);
+    // Keep stdout free for decoded audio
+    env_builder.target(env_logger::Target::Stderr);
     match cli.log_format {
         LogFormat::Plain => {
             env_builder.format_timestamp_secs();
@@ -33,6 +43,16 @@ fn main() -> Result<()> {
         LogFormat::Json => {
             env_builder.format(|buf, record| {
                 use std::io::Write;
+                // Event records already hold a JSON object
+                if record.target() == EVENT_LOG_TARGET {
+                    return writeln!(
+                        buf,
+                        "{{\"ts\":{},\"lvl\":\"{}\",\"event\":{}}}",
+                        buf.timestamp(),
+                        record.level(),
+                        record.args()
+                    );
+                }
                 writeln!(
                     buf,
                     "{{\"ts\":{},\"lvl\":\"{}\",\"msg\":\"{}\"}}",
@@ -56,9 +76,12 @@ fn main() -> Result<()> {
     info!("{}", cli::command::VERSION_INFO);
 
     match cli.command {
-        Commands::Decode(ref args) => cmd_decode(args, &cli, pb)?,
-        Commands::Info(ref args) => cmd_info(args, &cli, pb)?,
+        Commands::Decode(ref args) => cmd_decode(args, &cli, pb),
+        Commands::Info(ref args) => cmd_info(args, &cli, pb).map(|_| ExitCode::SUCCESS),
+        Commands::Verify(ref args) => cmd_verify(args, &cli, pb).map(|_| ExitCode::SUCCESS),
+        Commands::Edit(ref args) => cmd_edit(args, &cli, pb).map(|_| ExitCode::SUCCESS),
+        Commands::Trim(ref args) => cmd_trim(args, &cli, pb).map(|_| ExitCode::SUCCESS),
+        Commands::Mat(ref args) => cmd_mat(args, &cli, pb).map(|_| ExitCode::SUCCESS),
+        Commands::Demux(ref args) => cmd_demux(args, &cli, pb).map(|_| ExitCode::SUCCESS),
     }
-
-    Ok(())
 }
@@ -0,0 +1,450 @@
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+
+use truehd::structs::channel::ChannelLabel;
+
+// KSDATAFORMAT_SUBTYPE_PCM
+pub const WAVE_SUBTYPE_PCM_GUID: [u8; 16] = [
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// fmt chunk data of WAVE_FORMAT_EXTENSIBLE
+pub const FMT_EXTENSIBLE_SIZE: u32 = 40;
+
+// Header layout: RIFF(12) + JUNK/ds64(36) + fmt(48) + data header(8)
+const DS64_CHUNK_POSITION: u64 = 12;
+const DS64_DATA_SIZE: u32 = 28;
+const DATA_SIZE_POSITION: u64 = 100;
+
+/// Speaker position bit of the WAVE_FORMAT_EXTENSIBLE channel mask, if any
+pub fn speaker_position(label: ChannelLabel) -> Option<u32> {
+    let bit = match label {
+        ChannelLabel::L => 0x1,
+        ChannelLabel::R => 0x2,
+        ChannelLabel::C => 0x4,
+        ChannelLabel::LFE => 0x8,
+        ChannelLabel::Lb => 0x10,
+        ChannelLabel::Rb => 0x20,
+        ChannelLabel::Lsc => 0x40,
+        ChannelLabel::Rsc => 0x80,
+        ChannelLabel::Cb => 0x100,
+        ChannelLabel::Ls => 0x200,
+        ChannelLabel::Rs => 0x400,
+        ChannelLabel::Tc => 0x800,
+        ChannelLabel::Tfl => 0x1000,
+        ChannelLabel::Tfc => 0x2000,
+        ChannelLabel::Tfr => 0x4000,
+        ChannelLabel::Tbl => 0x8000,
+        ChannelLabel::Tbr => 0x20000,
+        ChannelLabel::Tsl
+        | ChannelLabel::Tsr
+        | ChannelLabel::Lsd
+        | ChannelLabel::Rsd
+        | ChannelLabel::Lw
+        | ChannelLabel::Rw
+        | ChannelLabel::LFE2
+        | ChannelLabel::Object(_)
+        | ChannelLabel::Generic(_) => return None,
+    };
+
+    Some(bit)
+}
+
+/// Channel mask of `labels` and the input channel for each channel in
+/// ascending speaker bit order, followed by channels without a speaker
+/// position in their input order
+pub fn channel_mask_and_order(labels: &[ChannelLabel]) -> (u32, Vec<usize>) {
+    let mut order = (0..labels.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&ch| speaker_position(labels[ch]).unwrap_or(u32::MAX));
+
+    let mask = labels
+        .iter()
+        .filter_map(|&label| speaker_position(label))
+        .fold(0, |mask, bit| mask | bit);
+
+    (mask, order)
+}
+
+/// PCM format and channel order of a WAVE_FORMAT_EXTENSIBLE file
+///
+/// Shared by [`RiffWavWriter`] and [`WAVWriter`](crate::wav::WAVWriter), which
+/// only differ in their chunk headers.
+#[derive(Debug, Clone)]
+pub struct WaveFormat {
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub bits_per_sample: u32,
+    /// Channel mask of the fmt chunk, 0 if no speaker positions are assigned
+    pub channel_mask: u32,
+    /// Input channel for each channel in file order
+    pub channel_order: Vec<usize>,
+}
+
+impl WaveFormat {
+    /// Format without speaker positions, storing channels in input order
+    pub fn new(sample_rate: u32, channels: u32, bits_per_sample: u32) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            bits_per_sample,
+            channel_mask: 0,
+            channel_order: (0..channels as usize).collect(),
+        }
+    }
+
+    /// Set the channel mask and file channel order from the labels of the
+    /// input channels
+    pub fn set_channel_labels(&mut self, labels: &[ChannelLabel]) -> io::Result<()> {
+        if labels.len() != self.channels as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} channel labels given for {} channels",
+                    labels.len(),
+                    self.channels
+                ),
+            ));
+        }
+
+        (self.channel_mask, self.channel_order) = channel_mask_and_order(labels);
+        Ok(())
+    }
+
+    /// Size of a sample frame in bytes
+    pub fn block_align(&self) -> u32 {
+        self.channels * (self.bits_per_sample / 8)
+    }
+
+    /// Write the data of a WAVE_FORMAT_EXTENSIBLE fmt chunk
+    pub fn write_fmt_extensible(&self, writer: &mut impl Write) -> io::Result<()> {
+        let block_align = self.block_align();
+
+        writer.write_all(&WAVE_FORMAT_EXTENSIBLE.to_le_bytes())?;
+        writer.write_all(&(self.channels as u16).to_le_bytes())?;
+        writer.write_all(&self.sample_rate.to_le_bytes())?;
+        writer.write_all(&(self.sample_rate * block_align).to_le_bytes())?;
+        writer.write_all(&(block_align as u16).to_le_bytes())?;
+        writer.write_all(&(self.bits_per_sample as u16).to_le_bytes())?; // Container bits per sample
+        writer.write_all(&22u16.to_le_bytes())?; // Extension size
+        writer.write_all(&(self.bits_per_sample as u16).to_le_bytes())?; // Valid bits per sample
+        writer.write_all(&self.channel_mask.to_le_bytes())?;
+        writer.write_all(&WAVE_SUBTYPE_PCM_GUID)
+    }
+
+    /// Write interleaved samples in file channel order, keeping the low
+    /// `bytes_per_sample` bytes of each, and return the number of bytes written
+    pub fn write_samples(
+        &self,
+        writer: &mut impl Write,
+        samples: &[i32],
+        bytes_per_sample: usize,
+    ) -> io::Result<u64> {
+        for frame in samples.chunks_exact(self.channels as usize) {
+            for &ch in &self.channel_order {
+                writer.write_all(&frame[ch].to_le_bytes()[..bytes_per_sample])?;
+            }
+        }
+        Ok(
+            (samples.len() / self.channels as usize * self.channel_order.len() * bytes_per_sample)
+                as u64,
+        )
+    }
+}
+
+/// Pad a chunk of `len` bytes with zeros to a multiple of `alignment` bytes
+pub fn write_chunk_padding(writer: &mut impl Write, len: u64, alignment: u64) -> io::Result<()> {
+    let padding = len.next_multiple_of(alignment) - len;
+    io::copy(&mut io::repeat(0).take(padding), writer)?;
+    Ok(())
+}
+
+/// Overwrite the bytes at `position`, such as a chunk size, and return to the
+/// current position
+pub fn patch_at<W: Write + Seek>(writer: &mut W, position: u64, bytes: &[u8]) -> io::Result<()> {
+    let current = writer.stream_position()?;
+    writer.seek(SeekFrom::Start(position))?;
+    writer.write_all(bytes)?;
+    writer.seek(SeekFrom::Start(current))?;
+    Ok(())
+}
+
+/// RIFF WAVE writer for 16, 24 or 32-bit PCM audio with automatic RF64 upgrade
+///
+/// A JUNK chunk reserves room for the `ds64` chunk, which replaces it in
+/// [`finish`](Self::finish) when the file exceeds the 32-bit RIFF size limit.
+pub struct RiffWavWriter<W: Write + Seek> {
+    writer: BufWriter<W>,
+    format: WaveFormat,
+    data_written: u64,
+    /// Chunk ID of files exceeding the RIFF size limit
+    large_file_id: [u8; 4],
+    /// Whether chunks follow the data chunk, which is then complete
+    data_closed: bool,
+    max_riff_size: u64,
+    finished: bool,
+}
+
+impl<W: Write + Seek> RiffWavWriter<W> {
+    /// Create a new WAV writer
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+            format: WaveFormat::new(48000, 2, 24),
+            data_written: 0,
+            large_file_id: *b"RF64",
+            data_closed: false,
+            max_riff_size: u32::MAX as u64,
+            finished: false,
+        }
+    }
+
+    /// Configure audio format parameters
+    pub fn configure_audio_format(
+        &mut self,
+        sample_rate: u32,
+        channels: u32,
+        bits_per_sample: u32,
+    ) -> io::Result<()> {
+        if self.data_written > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot change format after writing data",
+            ));
+        }
+
+        self.format = WaveFormat::new(sample_rate, channels, bits_per_sample);
+        Ok(())
+    }
+
+    /// Set the channel mask from the labels of the input channels.
+    ///
+    /// WAVE files must store channels in ascending speaker bit order, so input
+    /// channels are reordered on write. Channels without a speaker position
+    /// follow in their input order.
+    pub fn set_channel_labels(&mut self, labels: &[ChannelLabel]) -> io::Result<()> {
+        self.format.set_channel_labels(labels)
+    }
+
+    /// Use `BW64` (ITU-R BS.2088) instead of `RF64` for files over 4 GiB
+    pub fn set_bw64(&mut self) {
+        self.large_file_id = *b"BW64";
+    }
+
+    /// Write the WAVE header
+    pub fn write_header(&mut self) -> io::Result<()> {
+        self.writer.write_all(b"RIFF")?;
+        self.writer.write_all(&0u32.to_le_bytes())?; // Updated in finish()
+        self.writer.write_all(b"WAVE")?;
+
+        // Placeholder for ds64
+        self.writer.write_all(b"JUNK")?;
+        self.writer.write_all(&DS64_DATA_SIZE.to_le_bytes())?;
+        self.writer.write_all(&[0u8; DS64_DATA_SIZE as usize])?;
+
+        self.writer.write_all(b"fmt ")?;
+        self.writer.write_all(&FMT_EXTENSIBLE_SIZE.to_le_bytes())?;
+        self.format.write_fmt_extensible(&mut self.writer)?;
+
+        self.writer.write_all(b"data")?;
+        self.writer.write_all(&0u32.to_le_bytes())?; // Updated in finish()
+
+        Ok(())
+    }
+
+    /// Write interleaved samples (input as i32, written as 24-bit little-endian)
+    pub fn write_pcm_24bit_as_packed(&mut self, samples: &[i32]) -> io::Result<()> {
+        self.data_written += self.format.write_samples(&mut self.writer, samples, 3)?;
+        Ok(())
+    }
+
+    /// Write interleaved samples packed to the configured bits per sample, little-endian
+    pub fn write_pcm_packed(&mut self, samples: &[i32]) -> io::Result<()> {
+        let bytes_per_sample = self.format.bits_per_sample as usize / 8;
+        self.data_written +=
+            self.format
+                .write_samples(&mut self.writer, samples, bytes_per_sample)?;
+        Ok(())
+    }
+
+    /// Number of sample frames written so far
+    pub fn sample_count(&self) -> u64 {
+        self.data_written / self.block_align()
+    }
+
+    /// Length of the sample data written so far in bytes
+    pub fn data_len(&self) -> u64 {
+        self.data_written
+    }
+
+    /// Size of a sample frame in bytes
+    pub fn block_align(&self) -> u64 {
+        self.format.channel_order.len() as u64 * (self.format.bits_per_sample as u64 / 8)
+    }
+
+    /// Append a chunk after the data chunk, which must be complete
+    pub fn append_chunk(&mut self, id: &[u8; 4], data: &[u8]) -> io::Result<()> {
+        self.close_data()?;
+        self.writer.write_all(id)?;
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        write_chunk_padding(&mut self.writer, data.len() as u64, 2)
+    }
+
+    fn close_data(&mut self) -> io::Result<()> {
+        if !self.data_closed {
+            write_chunk_padding(&mut self.writer, self.data_written, 2)?;
+        }
+        self.data_closed = true;
+        Ok(())
+    }
+
+    /// Flush the audio data written so far without finishing the file
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Pad the data chunk and patch the chunk sizes, switching to RF64 if needed
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+
+        self.close_data()?;
+        self.writer.flush()?;
+
+        let riff_size = self.writer.stream_position()? - 8;
+
+        if riff_size > self.max_riff_size {
+            let mut ds64 = Vec::with_capacity(8 + DS64_DATA_SIZE as usize);
+            ds64.extend_from_slice(b"ds64");
+            ds64.extend_from_slice(&DS64_DATA_SIZE.to_le_bytes());
+            ds64.extend_from_slice(&riff_size.to_le_bytes());
+            ds64.extend_from_slice(&self.data_written.to_le_bytes());
+            ds64.extend_from_slice(&self.sample_count().to_le_bytes());
+            ds64.extend_from_slice(&0u32.to_le_bytes()); // Table length
+
+            patch_at(&mut self.writer, 0, &self.large_file_id)?;
+            patch_at(&mut self.writer, 4, &u32::MAX.to_le_bytes())?;
+            patch_at(&mut self.writer, DS64_CHUNK_POSITION, &ds64)?;
+            patch_at(
+                &mut self.writer,
+                DATA_SIZE_POSITION,
+                &u32::MAX.to_le_bytes(),
+            )?;
+        } else {
+            patch_at(&mut self.writer, 4, &(riff_size as u32).to_le_bytes())?;
+            patch_at(
+                &mut self.writer,
+                DATA_SIZE_POSITION,
+                &(self.data_written as u32).to_le_bytes(),
+            )?;
+        }
+
+        self.writer.flush()?;
+        self.finished = true;
+
+        Ok(())
+    }
+
+    /// Get the underlying writer
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u32_at(buffer: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(buffer[pos..pos + 4].try_into().unwrap())
+    }
+
+    fn u64_at(buffer: &[u8], pos: usize) -> u64 {
+        u64::from_le_bytes(buffer[pos..pos + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_wav_extensible_header_and_reorder() -> io::Result<()> {
+        use ChannelLabel::*;
+
+        let mut writer = RiffWavWriter::new(Cursor::new(Vec::new()));
+        writer.configure_audio_format(48000, 8, 24)?;
+        writer.set_channel_labels(&[L, R, C, LFE, Ls, Rs, Lb, Rb])?;
+        writer.write_header()?;
+        writer.write_pcm_24bit_as_packed(&[1, 2, 3, 4, 5, 6, 7, 8])?;
+        writer.finish()?;
+        let buffer = writer.into_inner()?.into_inner();
+
+        assert_eq!(&buffer[0..4], b"RIFF");
+        assert_eq!(u32_at(&buffer, 4) as usize, buffer.len() - 8);
+        assert_eq!(&buffer[12..16], b"JUNK");
+        assert_eq!(&buffer[48..52], b"fmt ");
+        assert_eq!(u32_at(&buffer, 76), 0x63F); // 7.1 with side surrounds
+        assert_eq!(&buffer[80..96], &WAVE_SUBTYPE_PCM_GUID);
+        assert_eq!(&buffer[96..100], b"data");
+        assert_eq!(u32_at(&buffer, 100), 24);
+
+        // Back surrounds precede side surrounds in file order
+        let order = buffer[104..]
+            .chunks_exact(3)
+            .map(|s| s[0])
+            .collect::<Vec<_>>();
+        assert_eq!(order, [1, 2, 3, 4, 7, 8, 5, 6]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wav_rf64_upgrade() -> io::Result<()> {
+        let mut writer = RiffWavWriter::new(Cursor::new(Vec::new()));
+        writer.configure_audio_format(48000, 1, 24)?;
+        writer.max_riff_size = 100;
+        writer.write_header()?;
+        writer.write_pcm_24bit_as_packed(&[0; 5])?;
+        writer.finish()?;
+        let buffer = writer.into_inner()?.into_inner();
+
+        // 15 data bytes plus a pad byte
+        assert_eq!(buffer.len(), 104 + 16);
+        assert_eq!(&buffer[0..4], b"RF64");
+        assert_eq!(u32_at(&buffer, 4), u32::MAX);
+        assert_eq!(&buffer[12..16], b"ds64");
+        assert_eq!(u64_at(&buffer, 20), buffer.len() as u64 - 8);
+        assert_eq!(u64_at(&buffer, 28), 15);
+        assert_eq!(u64_at(&buffer, 36), 5);
+        assert_eq!(u32_at(&buffer, 100), u32::MAX);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bw64_appended_chunks() -> io::Result<()> {
+        let mut writer = RiffWavWriter::new(Cursor::new(Vec::new()));
+        writer.configure_audio_format(48000, 1, 24)?;
+        writer.set_bw64();
+        writer.max_riff_size = 100;
+        writer.write_header()?;
+        writer.write_pcm_24bit_as_packed(&[0; 5])?;
+        assert_eq!(writer.sample_count(), 5);
+        writer.append_chunk(b"axml", b"<x/>")?;
+        writer.append_chunk(b"chna", &[1, 2, 3])?;
+        writer.finish()?;
+        let buffer = writer.into_inner()?.into_inner();
+
+        // Padded data chunk, then the appended chunks with their pad bytes
+        assert_eq!(&buffer[0..4], b"BW64");
+        assert_eq!(u64_at(&buffer, 20), buffer.len() as u64 - 8);
+        assert_eq!(u64_at(&buffer, 28), 15);
+        assert_eq!(&buffer[120..124], b"axml");
+        assert_eq!(u32_at(&buffer, 124), 4);
+        assert_eq!(&buffer[128..132], b"<x/>");
+        assert_eq!(&buffer[132..136], b"chna");
+        assert_eq!(u32_at(&buffer, 136), 3);
+        assert_eq!(buffer.len(), 140 + 4);
+
+        Ok(())
+    }
+}
@@ -10,3 +10,48 @@ pub fn time_str(sec: f64) -> String {
         width = if hours >= 100 { 0 } else { 2 }
     )
 }
+
+/// Parses a time given as `HH:MM:SS[.fff]`, `MM:SS[.fff]` or seconds.
+pub fn parse_time(time: &str) -> Result<f64, String> {
+    let invalid = || format!("invalid time '{time}', expected HH:MM:SS[.ms] or seconds");
+
+    let fields = time.rsplit(':').collect::<Vec<_>>();
+    if fields.len() > 3 {
+        return Err(invalid());
+    }
+
+    let mut seconds = 0.0;
+    for (index, field) in fields.iter().enumerate() {
+        let value: f64 = field.parse().map_err(|_| invalid())?;
+        // Only the seconds have a fraction, and hours are the only unbounded field
+        let valid = value.is_finite()
+            && value >= 0.0
+            && (index == 0 || value.fract() == 0.0)
+            && (index + 1 == fields.len() || value < 60.0);
+        if !valid {
+            return Err(invalid());
+        }
+        seconds += value * 60f64.powi(index as i32);
+    }
+
+    Ok(seconds)
+}
+
+/// Number of samples in `seconds` at `sample_rate`, rounded to the nearest sample.
+pub fn samples_at(seconds: f64, sample_rate: u32) -> u64 {
+    (seconds * sample_rate as f64).round() as u64
+}
+
+#[test]
+fn parse_times() {
+    assert_eq!(parse_time("01:02:03.5"), Ok(3723.5));
+    assert_eq!(parse_time("02:03"), Ok(123.0));
+    assert_eq!(parse_time("90.25"), Ok(90.25));
+    assert_eq!(parse_time("100:00:00"), Ok(360000.0));
+    for invalid in [
+        "", "1:60:00", "00:00:60", "1.5:00", "-1", "a:00", "1:2:3:4", "inf",
+    ] {
+        assert!(parse_time(invalid).is_err(), "{invalid}");
+    }
+    assert_eq!(samples_at(1.5, 48000), 72000);
+}
@@ -1,4 +1,10 @@
-use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+
+use truehd::structs::channel::ChannelLabel;
+
+use crate::riff::{
+    FMT_EXTENSIBLE_SIZE, WAVE_FORMAT_EXTENSIBLE, WaveFormat, patch_at, write_chunk_padding,
+};
 
 // W64 GUIDs as defined in Sony Wave64 specification
 pub const W64_RIFF_GUID: [u8; 16] = [
@@ -14,15 +20,14 @@ pub const W64_DATA_GUID: [u8; 16] = [
     0x64, 0x61, 0x74, 0x61, 0xF3, 0xAC, 0xD3, 0x11, 0x8C, 0xD1, 0x00, 0xC0, 0x4F, 0x8E, 0xDB, 0x8A,
 ];
 
-/// Sony Wave64 file writer for 24-bit PCM audio (.wav extension)
+/// Sony Wave64 file writer for 16, 24 or 32-bit PCM audio (.wav extension)
 pub struct WAVWriter<W: Write + Seek> {
     writer: BufWriter<W>,
+    format: WaveFormat,
     data_size_position: u64,
     data_written: u64,
-    sample_rate: u32,
-    channels: u32,
-    bits_per_sample: u32,
     file_size_position: u64,
+    finished: bool,
 }
 
 impl<W: Write + Seek> WAVWriter<W> {
@@ -30,12 +35,11 @@ impl<W: Write + Seek> WAVWriter<W> {
     pub fn new(writer: W) -> Self {
         Self {
             writer: BufWriter::new(writer),
+            format: WaveFormat::new(48000, 2, 24),
             data_size_position: 0,
             data_written: 0,
-            sample_rate: 48000,
-            channels: 2,
-            bits_per_sample: 24,
             file_size_position: 0,
+            finished: false,
         }
     }
 
@@ -53,12 +57,24 @@ impl<W: Write + Seek> WAVWriter<W> {
             ));
         }
 
-        self.sample_rate = sample_rate;
-        self.channels = channels;
-        self.bits_per_sample = bits_per_sample;
+        self.format = WaveFormat::new(sample_rate, channels, bits_per_sample);
         Ok(())
     }
 
+    /// Set the channel mask from the labels of the input channels.
+    ///
+    /// Channels are stored in ascending speaker bit order as in WAVE files, so
+    /// input channels are reordered on write. Channels without a speaker
+    /// position follow in their input order.
+    pub fn set_channel_labels(&mut self, labels: &[ChannelLabel]) -> io::Result<()> {
+        self.format.set_channel_labels(labels)
+    }
+
+    /// Channel mask of the fmt chunk, 0 if no speaker positions are assigned
+    pub fn channel_mask(&self) -> u32 {
+        self.format.channel_mask
+    }
+
     /// Write W64 file header
     pub fn write_header(&mut self) -> io::Result<()> {
         // W64 RIFF chunk
@@ -69,22 +85,9 @@ impl<W: Write + Seek> WAVWriter<W> {
 
         // W64 fmt chunk
         self.writer.write_all(&W64_FMT_GUID)?;
-        let fmt_chunk_size = 24u64 + 16u64; // 16 bytes for fmt data + 24 bytes for GUID + size
+        let fmt_chunk_size = 24 + FMT_EXTENSIBLE_SIZE as u64; // fmt data + 24 bytes for GUID + size
         self.writer.write_all(&fmt_chunk_size.to_le_bytes())?;
-
-        // fmt data (same as WAV)
-        self.writer.write_all(&1u16.to_le_bytes())?; // PCM format
-        self.writer
-            .write_all(&(self.channels as u16).to_le_bytes())?;
-        self.writer.write_all(&self.sample_rate.to_le_bytes())?;
-
-        let byte_rate = self.sample_rate * self.channels * (self.bits_per_sample / 8);
-        self.writer.write_all(&byte_rate.to_le_bytes())?;
-
-        let block_align = self.channels * (self.bits_per_sample / 8);
-        self.writer.write_all(&(block_align as u16).to_le_bytes())?;
-        self.writer
-            .write_all(&(self.bits_per_sample as u16).to_le_bytes())?;
+        self.format.write_fmt_extensible(&mut self.writer)?;
 
         // W64 data chunk
         self.writer.write_all(&W64_DATA_GUID)?;
@@ -94,40 +97,82 @@ impl<W: Write + Seek> WAVWriter<W> {
         Ok(())
     }
 
-    /// Write 24-bit PCM samples (input as i32, written as 24-bit little-endian)
+    /// Write interleaved samples (input as i32, written as 24-bit little-endian)
     pub fn write_pcm_24bit_as_packed(&mut self, samples: &[i32]) -> io::Result<()> {
-        for &sample in samples {
-            // Convert i32 to 24-bit little-endian
-            let bytes = sample.to_le_bytes();
-            self.writer.write_all(&bytes[0..3])?; // Take the 3 least significant bytes
-            self.data_written += 3;
-        }
+        self.data_written += self.format.write_samples(&mut self.writer, samples, 3)?;
+        Ok(())
+    }
+
+    /// Write interleaved samples packed to the configured bits per sample, little-endian
+    pub fn write_pcm_packed(&mut self, samples: &[i32]) -> io::Result<()> {
+        let bytes_per_sample = self.format.bits_per_sample as usize / 8;
+        self.data_written +=
+            self.format
+                .write_samples(&mut self.writer, samples, bytes_per_sample)?;
         Ok(())
     }
 
-    /// Finish writing and update file size headers
+    /// Flush the audio data written so far without finishing the file
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Pad the data chunk to 8 bytes and patch the 64-bit chunk sizes
     pub fn finish(&mut self) -> io::Result<()> {
-        // Flush any remaining data
+        if self.finished {
+            return Ok(());
+        }
+
+        // Chunks are aligned to 8 bytes, the padding is not part of the chunk size
+        write_chunk_padding(&mut self.writer, self.data_written, 8)?;
         self.writer.flush()?;
 
-        let current_pos = self.writer.stream_position()?;
+        let end = self.writer.stream_position()?;
 
-        // Update data chunk size (includes GUID + size = 24 bytes)
-        self.writer.seek(SeekFrom::Start(self.data_size_position))?;
+        // Data chunk size includes GUID + size = 24 bytes
         let data_chunk_size = self.data_written + 24;
-        self.writer.write_all(&data_chunk_size.to_le_bytes())?;
+        patch_at(
+            &mut self.writer,
+            self.data_size_position,
+            &data_chunk_size.to_le_bytes(),
+        )?;
+        patch_at(
+            &mut self.writer,
+            self.file_size_position,
+            &end.to_le_bytes(),
+        )?;
 
-        // Update W64 file size
-        self.writer.seek(SeekFrom::Start(self.file_size_position))?;
-        self.writer.write_all(&current_pos.to_le_bytes())?;
-
-        // Return to end of file
-        self.writer.seek(SeekFrom::Start(current_pos))?;
         self.writer.flush()?;
+        self.finished = true;
 
         Ok(())
     }
 
+    /// Create a writer that appends to an existing W64 file
+    ///
+    /// `writer` must end with the sample data, without the padding written by
+    /// [`finish`](Self::finish). Channels are written in input order until
+    /// [`set_channel_labels`](Self::set_channel_labels) restores the order.
+    pub fn from_parsed_info(mut writer: W, file_info: W64FileInfo) -> io::Result<Self> {
+        let end = writer.seek(SeekFrom::End(0))?;
+
+        let mut format = WaveFormat::new(
+            file_info.sample_rate,
+            file_info.channels,
+            file_info.bits_per_sample,
+        );
+        format.channel_mask = file_info.channel_mask;
+
+        Ok(Self {
+            writer: BufWriter::new(writer),
+            format,
+            data_size_position: file_info.data_size_position,
+            data_written: end.saturating_sub(file_info.data_chunk_start),
+            file_size_position: 16,
+            finished: false,
+        })
+    }
+
     /// Get the underlying writer
     pub fn into_inner(self) -> io::Result<W> {
         self.writer.into_inner().map_err(|e| e.into_error())
@@ -137,9 +182,9 @@ impl<W: Write + Seek> WAVWriter<W> {
     pub fn stats(&self) -> WAVStats {
         WAVStats {
             data_written: self.data_written,
-            sample_rate: self.sample_rate,
-            channels: self.channels,
-            bits_per_sample: self.bits_per_sample,
+            sample_rate: self.format.sample_rate,
+            channels: self.format.channels,
+            bits_per_sample: self.format.bits_per_sample,
         }
     }
 }
@@ -153,9 +198,108 @@ pub struct WAVStats {
     pub bits_per_sample: u32,
 }
 
+/// Header positions and format of an existing W64 file
+#[derive(Debug, Clone)]
+pub struct W64FileInfo {
+    pub data_size_position: u64,
+    pub data_chunk_start: u64,
+    /// Length of the sample data from the data chunk size, `None` if the file
+    /// was not finished
+    pub data_size: Option<u64>,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub bits_per_sample: u32,
+    /// Channel mask of a WAVE_FORMAT_EXTENSIBLE fmt chunk, 0 otherwise
+    pub channel_mask: u32,
+}
+
+/// Parse an existing W64 file to extract header positions and format
+pub fn parse_w64_file<R: Read + Seek>(mut reader: R) -> io::Result<W64FileInfo> {
+    let mut guid = [0u8; 16];
+    let mut size_bytes = [0u8; 8];
+
+    reader.read_exact(&mut guid)?;
+    reader.read_exact(&mut size_bytes)?;
+    if guid != W64_RIFF_GUID {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not a valid W64 file - missing RIFF GUID",
+        ));
+    }
+
+    reader.read_exact(&mut guid)?;
+    if guid != W64_WAVE_GUID {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not a valid W64 file - missing WAVE GUID",
+        ));
+    }
+
+    let mut format = None;
+
+    loop {
+        let chunk_start = reader.stream_position()?;
+        reader.read_exact(&mut guid)?;
+        reader.read_exact(&mut size_bytes)?;
+        let chunk_size = u64::from_le_bytes(size_bytes);
+
+        if guid == W64_DATA_GUID {
+            let (sample_rate, channels, bits_per_sample, channel_mask) =
+                format.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "W64 data chunk before fmt chunk",
+                    )
+                })?;
+
+            return Ok(W64FileInfo {
+                data_size_position: chunk_start + 16,
+                data_chunk_start: chunk_start + 24,
+                data_size: chunk_size.checked_sub(24),
+                sample_rate,
+                channels,
+                bits_per_sample,
+                channel_mask,
+            });
+        }
+
+        if chunk_size < 24 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid W64 chunk size {chunk_size}"),
+            ));
+        }
+
+        if guid == W64_FMT_GUID {
+            let mut fmt = [0u8; 16];
+            reader.read_exact(&mut fmt)?;
+            let channels = u16::from_le_bytes([fmt[2], fmt[3]]) as u32;
+            let sample_rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+            let bits_per_sample = u16::from_le_bytes([fmt[14], fmt[15]]) as u32;
+
+            let format_tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+            let channel_mask = if format_tag == WAVE_FORMAT_EXTENSIBLE
+                && chunk_size >= 24 + FMT_EXTENSIBLE_SIZE as u64
+            {
+                let mut extension = [0u8; 8];
+                reader.read_exact(&mut extension)?;
+                u32::from_le_bytes([extension[4], extension[5], extension[6], extension[7]])
+            } else {
+                0
+            };
+            format = Some((sample_rate, channels, bits_per_sample, channel_mask));
+        }
+
+        // Chunks are aligned to 8 bytes
+        let next = chunk_start + chunk_size.next_multiple_of(8);
+        reader.seek(SeekFrom::Start(next))?;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::riff::WAVE_SUBTYPE_PCM_GUID;
     use std::io::Cursor;
 
     #[test]
@@ -200,4 +344,200 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_w64_16bit_write() -> io::Result<()> {
+        let mut writer = WAVWriter::new(Cursor::new(Vec::new()));
+        writer.configure_audio_format(48000, 2, 16)?;
+        writer.write_header()?;
+        writer.write_pcm_packed(&[0x1234, -2])?;
+        writer.finish()?;
+        let buffer = writer.into_inner()?.into_inner();
+
+        // Block align and bits per sample of the fmt chunk
+        assert_eq!(&buffer[76..80], &[4, 0, 16, 0]);
+        // Data padded to 8 bytes
+        assert_eq!(&buffer[128..], &[0x34, 0x12, 0xFE, 0xFF, 0, 0, 0, 0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_w64_parse_and_append() -> io::Result<()> {
+        let mut writer = WAVWriter::new(Cursor::new(Vec::new()));
+        writer.configure_audio_format(96000, 3, 24)?;
+        writer.write_header()?;
+        writer.write_pcm_24bit_as_packed(&[1, 2, 3])?;
+        writer.finish()?;
+        let mut cursor = writer.into_inner()?;
+        cursor.set_position(0);
+
+        let info = parse_w64_file(&mut cursor)?;
+        assert_eq!(info.sample_rate, 96000);
+        assert_eq!(info.channels, 3);
+        assert_eq!(info.bits_per_sample, 24);
+        assert_eq!(info.data_chunk_start, 128);
+        assert_eq!(info.data_size, Some(9));
+
+        // Drop the padding before appending
+        cursor.get_mut().truncate(128 + 9);
+        let mut writer = WAVWriter::from_parsed_info(cursor, info)?;
+        assert_eq!(writer.stats().data_written, 9);
+        writer.write_pcm_24bit_as_packed(&[4, 5, 6])?;
+        writer.finish()?;
+        let buffer = writer.into_inner()?.into_inner();
+
+        assert_eq!(buffer.len(), 128 + 24);
+        let data_chunk_size = u64::from_le_bytes(buffer[120..128].try_into().unwrap());
+        assert_eq!(data_chunk_size, 24 + 18);
+        let file_size = u64::from_le_bytes(buffer[16..24].try_into().unwrap());
+        assert_eq!(file_size, buffer.len() as u64);
+        assert_eq!(&buffer[143..146], &[6, 0, 0]);
+
+        Ok(())
+    }
+
+    /// File keeping only its first bytes, to write headers of files larger
+    /// than memory. Bytes past `HEADER_LEN` are dropped and read as zeros.
+    #[derive(Default)]
+    struct SparseFile {
+        header: Vec<u8>,
+        position: u64,
+        len: u64,
+    }
+
+    impl SparseFile {
+        const HEADER_LEN: u64 = 4096;
+    }
+
+    impl Write for SparseFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for (i, &byte) in buf.iter().enumerate() {
+                let pos = self.position + i as u64;
+                if pos < Self::HEADER_LEN {
+                    let pos = pos as usize;
+                    if self.header.len() <= pos {
+                        self.header.resize(pos + 1, 0);
+                    }
+                    self.header[pos] = byte;
+                }
+            }
+            self.position += buf.len() as u64;
+            self.len = self.len.max(self.position);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for SparseFile {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf
+                .len()
+                .min(self.len.saturating_sub(self.position) as usize);
+            for (i, byte) in buf[..n].iter_mut().enumerate() {
+                let pos = (self.position + i as u64) as usize;
+                *byte = self.header.get(pos).copied().unwrap_or(0);
+            }
+            self.position += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for SparseFile {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.position = match pos {
+                SeekFrom::Start(pos) => pos,
+                SeekFrom::End(offset) => self.len.saturating_add_signed(offset),
+                SeekFrom::Current(offset) => self.position.saturating_add_signed(offset),
+            };
+            Ok(self.position)
+        }
+    }
+
+    /// Offset and size of each chunk of a W64 file
+    fn read_w64_chunks(file: &mut SparseFile) -> io::Result<Vec<([u8; 16], u64, u64)>> {
+        let mut chunks = Vec::new();
+        let mut offset = 40;
+        while offset < file.len {
+            file.seek(SeekFrom::Start(offset))?;
+            let mut guid = [0u8; 16];
+            let mut size = [0u8; 8];
+            file.read_exact(&mut guid)?;
+            file.read_exact(&mut size)?;
+            let size = u64::from_le_bytes(size);
+            chunks.push((guid, offset, size));
+            offset += size.next_multiple_of(8);
+        }
+        Ok(chunks)
+    }
+
+    #[test]
+    fn test_w64_extensible_over_4gib() -> io::Result<()> {
+        use ChannelLabel::*;
+
+        let labels = [L, R, C, LFE, Ls, Rs, Lb, Rb, Tfl, Tfr];
+        let mut writer = WAVWriter::new(SparseFile::default());
+        writer.configure_audio_format(48000, 10, 24)?;
+        writer.set_channel_labels(&labels)?;
+        writer.write_header()?;
+        let mut file = writer.into_inner()?;
+
+        // Simulate an interrupted decode of more than 4 GiB of samples
+        let frames = u32::MAX as u64 / 30 + 1000;
+        file.len = 128 + frames * 30;
+        file.seek(SeekFrom::Start(0))?;
+        let info = parse_w64_file(&mut file)?;
+        assert_eq!(info.data_size, None);
+        assert_eq!(info.channel_mask, 0x563F);
+
+        let mut writer = WAVWriter::from_parsed_info(file, info)?;
+        writer.set_channel_labels(&labels)?;
+        writer.write_pcm_24bit_as_packed(&(1..=10).collect::<Vec<_>>())?;
+        let data_written = writer.stats().data_written;
+        assert_eq!(data_written, (frames + 1) * 30);
+        writer.finish()?;
+        writer.finish()?;
+        let mut file = writer.into_inner()?;
+
+        assert_eq!(&file.header[0..16], &W64_RIFF_GUID);
+        assert_eq!(
+            u64::from_le_bytes(file.header[16..24].try_into().unwrap()),
+            file.len
+        );
+        assert_eq!(&file.header[24..40], &W64_WAVE_GUID);
+        assert!(file.len.is_multiple_of(8));
+
+        let chunks = read_w64_chunks(&mut file)?;
+        assert_eq!(chunks.len(), 2);
+
+        let (guid, offset, size) = chunks[0];
+        assert_eq!(guid, W64_FMT_GUID);
+        assert_eq!(size, 64);
+        let fmt = &file.header[offset as usize + 24..(offset + size) as usize];
+        assert_eq!(u16::from_le_bytes([fmt[0], fmt[1]]), WAVE_FORMAT_EXTENSIBLE);
+        assert_eq!(u16::from_le_bytes([fmt[2], fmt[3]]), 10);
+        assert_eq!(u32::from_le_bytes(fmt[4..8].try_into().unwrap()), 48000);
+        assert_eq!(
+            u32::from_le_bytes(fmt[8..12].try_into().unwrap()),
+            48000 * 30
+        );
+        assert_eq!(u16::from_le_bytes([fmt[12], fmt[13]]), 30);
+        assert_eq!(u16::from_le_bytes([fmt[14], fmt[15]]), 24);
+        assert_eq!(u16::from_le_bytes([fmt[16], fmt[17]]), 22);
+        assert_eq!(u16::from_le_bytes([fmt[18], fmt[19]]), 24);
+        assert_eq!(u32::from_le_bytes(fmt[20..24].try_into().unwrap()), 0x563F);
+        assert_eq!(&fmt[24..40], &WAVE_SUBTYPE_PCM_GUID);
+
+        let (guid, offset, size) = chunks[1];
+        assert_eq!(guid, W64_DATA_GUID);
+        assert_eq!(offset, 104);
+        assert!(size > u32::MAX as u64);
+        assert_eq!(size, data_written + 24);
+        assert_eq!(file.len, (offset + size).next_multiple_of(8));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,87 @@
+//! End-to-end latency of decoding a live stream from stdin to stdout.
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use truehd::process::EXAMPLE_DATA;
+
+/// PCM bytes of one copy of `EXAMPLE_DATA`: two access units of 40 stereo
+/// 24-bit samples.
+const PCM_BYTES_PER_COPY: usize = 2 * 40 * 2 * 3;
+
+/// Feed `copies` copies of `EXAMPLE_DATA` to the decoder one at a time, as a
+/// slow live source would, and return how long the PCM of each copy took to
+/// reach stdout, or `None` when it did not arrive within `interval`. The next
+/// copy is written once the output arrived or `interval` has passed.
+fn feed(extra_args: &[&str], copies: usize, interval: Duration) -> Vec<Option<Duration>> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_truehdd"))
+        .args(["--loglevel", "off", "decode", "-", "--no-trim"])
+        .args(["--output-path", "-"])
+        .args(extra_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let mut stdout = child.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let reader = thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+        let mut total = 0;
+        loop {
+            let len = stdout.read(&mut buffer).unwrap();
+            if len == 0 {
+                return total;
+            }
+            total += len;
+            let _ = tx.send(total);
+        }
+    });
+
+    let mut stdin = child.stdin.take().unwrap();
+    let mut received = 0;
+    let mut latencies = Vec::with_capacity(copies);
+    for copy in 1..=copies {
+        let sent = Instant::now();
+        let deadline = sent + interval;
+        stdin.write_all(EXAMPLE_DATA).unwrap();
+        stdin.flush().unwrap();
+
+        let mut latency = None;
+        while received < copy * PCM_BYTES_PER_COPY {
+            let Ok(total) = rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            else {
+                break;
+            };
+            received = total;
+            if received >= copy * PCM_BYTES_PER_COPY {
+                latency = Some(sent.elapsed());
+            }
+        }
+        latencies.push(latency);
+    }
+
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+    assert_eq!(reader.join().unwrap(), copies * PCM_BYTES_PER_COPY);
+    latencies
+}
+
+#[test]
+fn low_latency_output_follows_input() {
+    // Each access unit is written out without waiting for more input
+    let latencies = feed(&["--low-latency"], 20, Duration::from_secs(10));
+    assert!(latencies.iter().all(Option::is_some), "{latencies:?}");
+}
+
+#[test]
+fn buffered_output_waits_for_input() {
+    // Without --low-latency the output is only written once the writer
+    // buffer fills or the input ends
+    let latencies = feed(&[], 10, Duration::from_millis(50));
+    assert!(latencies.iter().all(Option::is_none), "{latencies:?}");
+}
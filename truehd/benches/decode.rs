@@ -0,0 +1,73 @@
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+use truehd::process::decode::DecoderState;
+
+/// Samples of each benchmarked access unit
+const SAMPLES_PER_AU: usize = 160;
+
+/// Decoder state of a 0x31EB substream with `channels` channels decoded in
+/// blocks of `block_size` samples.
+///
+/// Every channel has a 4th order FIR and a 4th order IIR filter and its
+/// residuals are pseudo-random 17-bit values, keeping the filters stable and
+/// the output within 24 bits.
+fn decoder_state(channels: usize, block_size: usize) -> DecoderState {
+    let mut state = DecoderState {
+        samples_per_au: SAMPLES_PER_AU,
+        ..Default::default()
+    };
+
+    let ss_state = &mut state.substream_state[0];
+    ss_state.restart_sync_word = 0x31EB;
+    ss_state.max_chan = channels - 1;
+    ss_state.max_matrix_chan = channels - 1;
+    ss_state.ch_assign = std::array::from_fn(|chi| chi);
+    ss_state.block_size = block_size;
+
+    let mut seed = 0x2545_F491_4F6C_DD1Du64;
+    for chi in 0..channels {
+        ss_state.order[0][chi] = 4;
+        ss_state.order[1][chi] = 4;
+        ss_state.coeff_q[0][chi] = 14;
+        ss_state.coeff[0][chi][..4].copy_from_slice(&[8192, -4096, 2048, -1024]);
+        ss_state.coeff[1][chi][..4].copy_from_slice(&[4096, -2048, 1024, -512]);
+
+        for sample in &mut ss_state.block_data[..block_size] {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            sample[chi] = (seed as i32) >> 15;
+        }
+    }
+
+    state
+}
+
+fn block_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("block_decode");
+
+    for block_size in [8, SAMPLES_PER_AU] {
+        for channels in [2, 6, 8, 16] {
+            let mut state = decoder_state(channels, block_size);
+            group.throughput(Throughput::Elements((SAMPLES_PER_AU * channels) as u64));
+            group.bench_function(
+                BenchmarkId::new(format!("{block_size}_samples"), channels),
+                |b| {
+                    b.iter(|| {
+                        // Decode the blocks of an access unit
+                        state.substream_state[0].decoded_sample_len = 0;
+                        for _ in 0..SAMPLES_PER_AU / block_size {
+                            state.decode_block().unwrap();
+                        }
+                        black_box(&state.output_buffer);
+                    })
+                },
+            );
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, block_decode);
+criterion_main!(benches);
@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use truehd::process::extract::Extractor;
+
+// The first byte selects the chunk size, so sync patterns and access units
+// are split across push_bytes calls
+fuzz_target!(|data: &[u8]| {
+    let Some((&chunk_size, data)) = data.split_first() else {
+        return;
+    };
+
+    let mut extractor = Extractor::default();
+    for chunk in data.chunks(chunk_size.max(1) as usize) {
+        extractor.push_bytes(chunk);
+        for _ in extractor.by_ref() {}
+    }
+});
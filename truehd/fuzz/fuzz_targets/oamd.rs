@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use truehd::structs::oamd::ObjectAudioMetadataPayload;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = ObjectAudioMetadataPayload::read(data);
+});
@@ -0,0 +1,31 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use truehd::process::decode::Decoder;
+use truehd::process::extract::Frame;
+use truehd::process::parse::Parser;
+
+// The input is read as consecutive access units delimited by their
+// access_unit_length, bypassing the parity checks of the extractor
+fuzz_target!(|data: &[u8]| {
+    let mut parser = Parser::default();
+    let mut decoder = Decoder::default();
+    let mut rest = data;
+
+    while rest.len() >= 4 {
+        let length = ((((rest[0] & 0xF) as usize) << 8) | rest[1] as usize) * 2;
+        let (frame, tail) = rest.split_at(length.clamp(4, rest.len()));
+        rest = tail;
+
+        let frame = Frame {
+            timestamp: None,
+            byte_offset: 0,
+            stream_start: false,
+            data: frame.into(),
+        };
+
+        if let Ok(access_unit) = parser.parse(&frame) {
+            let _ = decoder.decode_presentation(&access_unit, 3);
+        }
+    }
+});
@@ -0,0 +1,161 @@
+#ifndef TRUEHD_H
+#define TRUEHD_H
+
+/* Generated with cbindgen from src/capi.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// An argument is a null pointer or out of range.
+#define TRUEHD_ERROR_INVALID_ARGUMENT -1
+
+// The extractor skipped invalid data; extraction continues with the next call.
+#define TRUEHD_ERROR_EXTRACT -2
+
+// The frame could not be parsed.
+#define TRUEHD_ERROR_PARSE -3
+
+// The access unit could not be decoded.
+#define TRUEHD_ERROR_DECODE -4
+
+// The library panicked; the handle should not be used further.
+#define TRUEHD_ERROR_PANIC -5
+
+// Opaque handle of a parsed [`AccessUnit`].
+typedef struct TruehdAccessUnit TruehdAccessUnit;
+
+// Opaque handle of a [`Decoder`] and its interleaved output buffer.
+typedef struct TruehdDecoder TruehdDecoder;
+
+// Opaque handle of an [`Extractor`].
+typedef struct TruehdExtractor TruehdExtractor;
+
+// Opaque handle of an extracted [`Frame`].
+typedef struct TruehdFrame TruehdFrame;
+
+// Opaque handle of a [`Parser`].
+typedef struct TruehdParser TruehdParser;
+
+// Decoded audio of one access unit.
+//
+// `pcm` points to `sample_count * channel_count` interleaved samples of
+// `bit_depth` bits owned by the decoder, valid until its next decode call or
+// until it is freed.
+typedef struct TruehdDecodedAu {
+  const int32_t *pcm;
+  uint32_t channel_count;
+  uint32_t sample_count;
+  uint32_t sampling_frequency;
+  // Non-zero if the access unit repeats the previous one and may be discarded.
+  uint8_t is_duplicate;
+  // Precision of the samples in bits, 24 or 32.
+  uint32_t bit_depth;
+} TruehdDecodedAu;
+
+
+
+
+
+
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Returns the message of the last error on the calling thread, or null.
+//
+// The string is valid until the next call into the library on this thread.
+const char *truehd_last_error_message(void);
+
+// Creates an extractor.
+struct TruehdExtractor *truehd_extractor_new(void);
+
+// Frees an extractor.
+//
+// # Safety
+//
+// `extractor` must be null or created by [`truehd_extractor_new`] and not freed before.
+void truehd_extractor_free(struct TruehdExtractor *extractor);
+
+// Appends `len` bytes of the bitstream to the extractor.
+//
+// # Safety
+//
+// `extractor` must be a valid handle and `data` valid for reads of `len` bytes.
+int32_t truehd_extractor_push(struct TruehdExtractor *extractor, const uint8_t *data, size_t len);
+
+// Extracts the next frame from the pushed data.
+//
+// Returns 1 and stores a new frame in `*frame` if one is complete, 0 if more
+// data is needed, or [`TRUEHD_ERROR_EXTRACT`] if invalid data was skipped.
+// The frame is freed with [`truehd_frame_free`].
+//
+// # Safety
+//
+// `extractor` must be a valid handle and `frame` valid for writes.
+int32_t truehd_extractor_next_frame(struct TruehdExtractor *extractor, struct TruehdFrame **frame);
+
+// Frees a frame.
+//
+// # Safety
+//
+// `frame` must be null or returned by [`truehd_extractor_next_frame`] and not freed before.
+void truehd_frame_free(struct TruehdFrame *frame);
+
+// Creates a parser.
+struct TruehdParser *truehd_parser_new(void);
+
+// Frees a parser.
+//
+// # Safety
+//
+// `parser` must be null or created by [`truehd_parser_new`] and not freed before.
+void truehd_parser_free(struct TruehdParser *parser);
+
+// Parses a frame into an access unit.
+//
+// Stores a new access unit in `*access_unit` on success. The access unit is
+// freed with [`truehd_access_unit_free`].
+//
+// # Safety
+//
+// `parser` and `frame` must be valid handles and `access_unit` valid for writes.
+int32_t truehd_parser_parse(struct TruehdParser *parser,
+                            const struct TruehdFrame *frame,
+                            struct TruehdAccessUnit **access_unit);
+
+// Frees an access unit.
+//
+// # Safety
+//
+// `access_unit` must be null or returned by [`truehd_parser_parse`] and not freed before.
+void truehd_access_unit_free(struct TruehdAccessUnit *access_unit);
+
+// Creates a decoder.
+struct TruehdDecoder *truehd_decoder_new(void);
+
+// Frees a decoder and its output buffer.
+//
+// # Safety
+//
+// `decoder` must be null or created by [`truehd_decoder_new`] and not freed before.
+void truehd_decoder_free(struct TruehdDecoder *decoder);
+
+// Decodes presentation `presentation` (0-3) of an access unit into `*decoded`.
+//
+// # Safety
+//
+// `decoder` and `access_unit` must be valid handles and `decoded` valid for writes.
+int32_t truehd_decoder_decode_presentation(struct TruehdDecoder *decoder,
+                                           const struct TruehdAccessUnit *access_unit,
+                                           uint32_t presentation,
+                                           struct TruehdDecodedAu *decoded);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TRUEHD_H */
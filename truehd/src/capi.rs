@@ -0,0 +1,443 @@
+//! C API for the extractor, parser and decoder.
+//!
+//! Every component is an opaque handle created with a `*_new` function and
+//! released with the matching `*_free` function. Functions returning `int32_t`
+//! return a non-negative value on success and one of the negative
+//! `TRUEHD_ERROR_*` codes on failure, in which case
+//! [`truehd_last_error_message`] describes the error on the calling thread.
+//!
+//! The header `include/truehd.h` is generated with cbindgen:
+//!
+//! ```sh
+//! cbindgen --config cbindgen.toml --output include/truehd.h
+//! ```
+//!
+//! A static or dynamic library is built with
+//! `cargo rustc -p truehd --release --features capi --crate-type staticlib`
+//! (or `cdylib`).
+
+use crate::process::decode::Decoder;
+use crate::process::extract::{Extractor, Frame};
+use crate::process::parse::Parser;
+use crate::structs::access_unit::AccessUnit;
+use crate::utils::errors::ExtractError;
+use std::cell::RefCell;
+use std::ffi::{CString, c_char};
+use std::fmt::Display;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// An argument is a null pointer or out of range.
+pub const TRUEHD_ERROR_INVALID_ARGUMENT: i32 = -1;
+/// The extractor skipped invalid data; extraction continues with the next call.
+pub const TRUEHD_ERROR_EXTRACT: i32 = -2;
+/// The frame could not be parsed.
+pub const TRUEHD_ERROR_PARSE: i32 = -3;
+/// The access unit could not be decoded.
+pub const TRUEHD_ERROR_DECODE: i32 = -4;
+/// The library panicked; the handle should not be used further.
+pub const TRUEHD_ERROR_PANIC: i32 = -5;
+
+/// Opaque handle of an [`Extractor`].
+pub struct TruehdExtractor(Extractor);
+
+/// Opaque handle of an extracted [`Frame`].
+pub struct TruehdFrame(Frame);
+
+/// Opaque handle of a [`Parser`].
+pub struct TruehdParser(Parser);
+
+/// Opaque handle of a parsed [`AccessUnit`].
+pub struct TruehdAccessUnit(AccessUnit);
+
+/// Opaque handle of a [`Decoder`] and its interleaved output buffer.
+pub struct TruehdDecoder {
+    decoder: Decoder,
+    pcm: Vec<i32>,
+}
+
+/// Decoded audio of one access unit.
+///
+/// `pcm` points to `sample_count * channel_count` interleaved samples of
+/// `bit_depth` bits owned by the decoder, valid until its next decode call or
+/// until it is freed.
+#[repr(C)]
+pub struct TruehdDecodedAu {
+    pub pcm: *const i32,
+    pub channel_count: u32,
+    pub sample_count: u32,
+    pub sampling_frequency: u32,
+    /// Non-zero if the access unit repeats the previous one and may be discarded.
+    pub is_duplicate: u8,
+    /// Precision of the samples in bits, 24 or 32.
+    pub bit_depth: u32,
+}
+
+struct CapiError {
+    code: i32,
+    message: String,
+}
+
+impl CapiError {
+    fn new(code: i32, error: impl Display) -> Self {
+        Self {
+            code,
+            message: format!("{error:#}"),
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, converting errors and panics to negative codes.
+fn guard(f: impl FnOnce() -> Result<i32, CapiError>) -> i32 {
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = None);
+
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(error)) => {
+            set_last_error(error.message);
+            error.code
+        }
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            set_last_error(format!("panic: {message}"));
+            TRUEHD_ERROR_PANIC
+        }
+    }
+}
+
+/// Dereferences a handle, failing on null pointers.
+///
+/// # Safety
+///
+/// `ptr` must be null or valid for the returned lifetime.
+unsafe fn handle<'a, T>(ptr: *mut T, name: &str) -> Result<&'a mut T, CapiError> {
+    unsafe { ptr.as_mut() }
+        .ok_or_else(|| CapiError::new(TRUEHD_ERROR_INVALID_ARGUMENT, format!("{name} is null")))
+}
+
+fn into_handle<T>(value: T) -> *mut T {
+    Box::into_raw(Box::new(value))
+}
+
+/// Frees a handle created with [`into_handle`].
+///
+/// # Safety
+///
+/// `ptr` must be null or created by [`into_handle`] and not freed before.
+unsafe fn free_handle<T>(ptr: *mut T) {
+    if !ptr.is_null() {
+        drop(unsafe { Box::from_raw(ptr) });
+    }
+}
+
+/// Returns the message of the last error on the calling thread, or null.
+///
+/// The string is valid until the next call into the library on this thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn truehd_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Creates an extractor.
+#[unsafe(no_mangle)]
+pub extern "C" fn truehd_extractor_new() -> *mut TruehdExtractor {
+    into_handle(TruehdExtractor(Extractor::default()))
+}
+
+/// Frees an extractor.
+///
+/// # Safety
+///
+/// `extractor` must be null or created by [`truehd_extractor_new`] and not freed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn truehd_extractor_free(extractor: *mut TruehdExtractor) {
+    unsafe { free_handle(extractor) }
+}
+
+/// Appends `len` bytes of the bitstream to the extractor.
+///
+/// # Safety
+///
+/// `extractor` must be a valid handle and `data` valid for reads of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn truehd_extractor_push(
+    extractor: *mut TruehdExtractor,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    guard(|| {
+        let extractor = unsafe { handle(extractor, "extractor") }?;
+        if data.is_null() && len > 0 {
+            return Err(CapiError::new(
+                TRUEHD_ERROR_INVALID_ARGUMENT,
+                "data is null",
+            ));
+        }
+
+        if len > 0 {
+            extractor
+                .0
+                .push_bytes(unsafe { std::slice::from_raw_parts(data, len) });
+        }
+        Ok(0)
+    })
+}
+
+/// Extracts the next frame from the pushed data.
+///
+/// Returns 1 and stores a new frame in `*frame` if one is complete, 0 if more
+/// data is needed, or [`TRUEHD_ERROR_EXTRACT`] if invalid data was skipped.
+/// The frame is freed with [`truehd_frame_free`].
+///
+/// # Safety
+///
+/// `extractor` must be a valid handle and `frame` valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn truehd_extractor_next_frame(
+    extractor: *mut TruehdExtractor,
+    frame: *mut *mut TruehdFrame,
+) -> i32 {
+    guard(|| {
+        let extractor = unsafe { handle(extractor, "extractor") }?;
+        let frame = unsafe { handle(frame, "frame") }?;
+        *frame = ptr::null_mut();
+
+        match extractor.0.next() {
+            Some(Ok(next)) => {
+                *frame = into_handle(TruehdFrame(next));
+                Ok(1)
+            }
+            Some(Err(ExtractError::InsufficientData)) | None => Ok(0),
+            Some(Err(e)) => Err(CapiError::new(TRUEHD_ERROR_EXTRACT, e)),
+        }
+    })
+}
+
+/// Frees a frame.
+///
+/// # Safety
+///
+/// `frame` must be null or returned by [`truehd_extractor_next_frame`] and not freed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn truehd_frame_free(frame: *mut TruehdFrame) {
+    unsafe { free_handle(frame) }
+}
+
+/// Creates a parser.
+#[unsafe(no_mangle)]
+pub extern "C" fn truehd_parser_new() -> *mut TruehdParser {
+    into_handle(TruehdParser(Parser::default()))
+}
+
+/// Frees a parser.
+///
+/// # Safety
+///
+/// `parser` must be null or created by [`truehd_parser_new`] and not freed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn truehd_parser_free(parser: *mut TruehdParser) {
+    unsafe { free_handle(parser) }
+}
+
+/// Parses a frame into an access unit.
+///
+/// Stores a new access unit in `*access_unit` on success. The access unit is
+/// freed with [`truehd_access_unit_free`].
+///
+/// # Safety
+///
+/// `parser` and `frame` must be valid handles and `access_unit` valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn truehd_parser_parse(
+    parser: *mut TruehdParser,
+    frame: *const TruehdFrame,
+    access_unit: *mut *mut TruehdAccessUnit,
+) -> i32 {
+    guard(|| {
+        let parser = unsafe { handle(parser, "parser") }?;
+        let frame = unsafe { handle(frame.cast_mut(), "frame") }?;
+        let access_unit = unsafe { handle(access_unit, "access_unit") }?;
+        *access_unit = ptr::null_mut();
+
+        let parsed = parser
+            .0
+            .parse(&frame.0)
+            .map_err(|e| CapiError::new(TRUEHD_ERROR_PARSE, e))?;
+        *access_unit = into_handle(TruehdAccessUnit(parsed));
+        Ok(0)
+    })
+}
+
+/// Frees an access unit.
+///
+/// # Safety
+///
+/// `access_unit` must be null or returned by [`truehd_parser_parse`] and not freed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn truehd_access_unit_free(access_unit: *mut TruehdAccessUnit) {
+    unsafe { free_handle(access_unit) }
+}
+
+/// Creates a decoder.
+#[unsafe(no_mangle)]
+pub extern "C" fn truehd_decoder_new() -> *mut TruehdDecoder {
+    into_handle(TruehdDecoder {
+        decoder: Decoder::default(),
+        pcm: Vec::new(),
+    })
+}
+
+/// Frees a decoder and its output buffer.
+///
+/// # Safety
+///
+/// `decoder` must be null or created by [`truehd_decoder_new`] and not freed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn truehd_decoder_free(decoder: *mut TruehdDecoder) {
+    unsafe { free_handle(decoder) }
+}
+
+/// Decodes presentation `presentation` (0-3) of an access unit into `*decoded`.
+///
+/// # Safety
+///
+/// `decoder` and `access_unit` must be valid handles and `decoded` valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn truehd_decoder_decode_presentation(
+    decoder: *mut TruehdDecoder,
+    access_unit: *const TruehdAccessUnit,
+    presentation: u32,
+    decoded: *mut TruehdDecodedAu,
+) -> i32 {
+    guard(|| {
+        let decoder = unsafe { handle(decoder, "decoder") }?;
+        let access_unit = unsafe { handle(access_unit.cast_mut(), "access_unit") }?;
+        let decoded = unsafe { handle(decoded, "decoded") }?;
+        if presentation > 3 {
+            return Err(CapiError::new(
+                TRUEHD_ERROR_INVALID_ARGUMENT,
+                format!("presentation must be 0-3, got {presentation}"),
+            ));
+        }
+
+        let result = decoder
+            .decoder
+            .decode_presentation(&access_unit.0, presentation as usize)
+            .map_err(|e| CapiError::new(TRUEHD_ERROR_DECODE, e))?;
+
+        let channel_count = result.channel_count;
+        decoder.pcm.clear();
+        decoder.pcm.extend(
+            result.pcm_data[..result.sample_length]
+                .iter()
+                .flat_map(|sample| &sample[..channel_count]),
+        );
+
+        *decoded = TruehdDecodedAu {
+            pcm: decoder.pcm.as_ptr(),
+            channel_count: channel_count as u32,
+            sample_count: result.sample_length as u32,
+            sampling_frequency: result.sampling_frequency,
+            is_duplicate: result.is_duplicate as u8,
+            bit_depth: result.bit_depth,
+        };
+        Ok(0)
+    })
+}
+
+#[test]
+fn capi_round_trip() {
+    use crate::process::EXAMPLE_DATA;
+    use std::ffi::CStr;
+
+    let data = EXAMPLE_DATA.repeat(2);
+
+    // Expected output from the Rust API
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    let mut decoder = Decoder::default();
+    extractor.push_bytes(&data);
+    let mut expected = Vec::new();
+    for frame in extractor.filter_map(Result::ok) {
+        let decoded = decoder
+            .decode_presentation(&parser.parse(&frame).unwrap(), 1)
+            .unwrap();
+        for sample in &decoded.pcm_data[..decoded.sample_length] {
+            expected.extend_from_slice(&sample[..decoded.channel_count]);
+        }
+    }
+
+    unsafe {
+        let extractor = truehd_extractor_new();
+        let parser = truehd_parser_new();
+        let decoder = truehd_decoder_new();
+        assert_eq!(
+            truehd_extractor_push(extractor, data.as_ptr(), data.len()),
+            0
+        );
+
+        let mut pcm = Vec::new();
+        let mut frame = ptr::null_mut();
+        loop {
+            let status = truehd_extractor_next_frame(extractor, &mut frame);
+            if status == 0 {
+                break;
+            }
+            if status == TRUEHD_ERROR_EXTRACT {
+                continue;
+            }
+            assert_eq!(status, 1);
+
+            let mut access_unit = ptr::null_mut();
+            assert_eq!(truehd_parser_parse(parser, frame, &mut access_unit), 0);
+            truehd_frame_free(frame);
+
+            let mut decoded = std::mem::zeroed::<TruehdDecodedAu>();
+            assert_eq!(
+                truehd_decoder_decode_presentation(decoder, access_unit, 1, &mut decoded),
+                0
+            );
+            truehd_access_unit_free(access_unit);
+
+            assert_eq!(decoded.channel_count, 2);
+            assert_eq!(decoded.sampling_frequency, 48000);
+            pcm.extend_from_slice(std::slice::from_raw_parts(
+                decoded.pcm,
+                (decoded.sample_count * decoded.channel_count) as usize,
+            ));
+        }
+        assert_eq!(pcm, expected);
+        assert!(truehd_last_error_message().is_null());
+
+        // Errors map to negative codes with a message
+        let mut decoded = std::mem::zeroed::<TruehdDecodedAu>();
+        assert_eq!(
+            truehd_decoder_decode_presentation(decoder, ptr::null(), 1, &mut decoded),
+            TRUEHD_ERROR_INVALID_ARGUMENT
+        );
+        let message = CStr::from_ptr(truehd_last_error_message());
+        assert_eq!(message.to_str().unwrap(), "access_unit is null");
+
+        truehd_decoder_free(decoder);
+        truehd_parser_free(parser);
+        truehd_extractor_free(extractor);
+    }
+}
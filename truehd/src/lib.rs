@@ -29,6 +29,9 @@
 //! 2. Parse access units into structured data using [`process::parse::Parser`]
 //! 3. Decode audio to PCM samples using [`process::decode::Decoder`]
 //!
+//! When reading from an [`std::io::Read`] source, [`process::stream::StreamingDecoder`]
+//! performs all three steps and yields decoded access units as an iterator.
+//!
 //! ```rust,no_run
 //! use truehd::process::{extract::Extractor, parse::Parser, decode::Decoder, EXAMPLE_DATA};
 //!
@@ -93,3 +96,17 @@ pub mod structs;
 /// - **Dithering** ([`utils::dither`]): Noise shaping
 /// - **Buffer Management** ([`utils::buffer_pool`]): Memory allocation
 pub mod utils;
+
+/// C API for extracting, parsing and decoding.
+///
+/// Enabled with the `capi` feature. See [`capi`] for the calling conventions
+/// and how to build the library and header.
+#[cfg(feature = "capi")]
+pub mod capi;
+
+/// JavaScript bindings for stream analysis.
+///
+/// Enabled with the `wasm` feature. See [`wasm`] for how to build the
+/// WebAssembly module and its bindings.
+#[cfg(feature = "wasm")]
+pub mod wasm;
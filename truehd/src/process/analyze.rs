@@ -0,0 +1,733 @@
+use anyhow::Result;
+
+use crate::process::extract::{Extractor, Frame};
+use crate::process::parse::{Parser, ParserState};
+use crate::process::{PresentationMap, PresentationType};
+use crate::structs::access_unit::AccessUnit;
+use crate::structs::channel::{ChannelGroup, ChannelLabel};
+use crate::structs::sync::{FormatInfo, MAJOR_SYNC_FBB, MajorSyncInfo};
+use crate::structs::timestamp::Timestamp;
+use crate::utils::bitstream_io::BsIoSliceReader;
+use crate::utils::errors::{Category, ExtractError, FrameCheck, ParseError, Policy};
+
+/// Number of corrupt regions kept in a [`StreamReport`].
+pub const MAX_CORRUPT_REGIONS: usize = 100;
+
+/// Input bytes skipped by the extractor after an access unit failed a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CorruptRegion {
+    /// Byte offset of the access unit that failed the check
+    pub offset: u64,
+    /// Bytes skipped until sync was regained
+    pub length: u64,
+    pub check: FrameCheck,
+}
+
+impl CorruptRegion {
+    /// Returns the region reported by [`ExtractError::Resynchronized`].
+    pub fn from_error(error: &ExtractError) -> Option<Self> {
+        match *error {
+            ExtractError::Resynchronized {
+                offset,
+                skipped_bytes,
+                check,
+            } => Some(Self {
+                offset,
+                length: skipped_bytes,
+                check,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Stream parameters of a major sync.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StreamInfo {
+    pub format_sync: String,
+    pub sampling_frequency: u32,
+    pub variable_rate: bool,
+    /// Peak data rate in kbps
+    pub peak_data_rate: u32,
+    pub substreams: usize,
+    pub is_atmos: bool,
+}
+
+impl StreamInfo {
+    pub fn from_major_sync(major_sync: &MajorSyncInfo) -> Result<Self> {
+        Ok(Self {
+            format_sync: format!("{:08X}", major_sync.format_sync),
+            sampling_frequency: major_sync.format_info.sampling_frequency_1()?,
+            variable_rate: major_sync.variable_rate,
+            peak_data_rate: (major_sync.peak_data_rate as u32
+                * major_sync.format_info.sampling_frequency_1()?)
+                / 16000,
+            substreams: major_sync.substreams,
+            is_atmos: major_sync.is_atmos(),
+        })
+    }
+}
+
+/// Channel configuration and loudness parameters of a presentation.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PresentationInfo {
+    pub index: usize,
+    /// Unknown until the substream has restarted
+    pub channels: Option<u8>,
+    pub presentation_type: Option<PresentationType>,
+    pub twoch_format: Option<ChannelGroup>,
+    pub sixch_ex: Option<String>,
+    pub assignments: Vec<ChannelLabel>,
+    /// Reserved channel assignment the channels are not known for
+    pub unknown_assignment: Option<u16>,
+    pub control: Option<bool>,
+    pub dialogue_level: i8,
+    pub mix_level: u8,
+    // 16ch
+    pub chan_distribution: Option<bool>,
+}
+
+impl PresentationInfo {
+    /// Sets the channels of a channel assignment, or records it as unknown
+    /// when it is reserved.
+    fn set_assignments(&mut self, labels: Result<Vec<ChannelLabel>>, assignment: u16) {
+        match labels {
+            Ok(labels) => self.assignments = labels,
+            Err(e) => {
+                log::warn!("Presentation {}: {e}", self.index);
+                self.unknown_assignment = Some(assignment);
+            }
+        }
+    }
+}
+
+/// Describes the presentations of a major sync access unit.
+///
+/// Streams with fewer than three substreams repeat the last substream's
+/// presentation up to presentation 2. FBB streams list one presentation per
+/// substream, without downmix or loudness parameters. Presentations
+/// requiring substreams missing from the access unit are invalid.
+///
+/// # Panics
+///
+/// Panics if the access unit has no major sync.
+pub fn build_presentations(access_unit: &AccessUnit) -> Vec<PresentationInfo> {
+    let major_sync = access_unit.major_sync_info.as_ref().unwrap();
+    let presentation_builder = PresentationBuilder::new(major_sync, access_unit);
+
+    if major_sync.format_sync == MAJOR_SYNC_FBB {
+        presentation_builder.build_fbb_presentations()
+    } else {
+        presentation_builder.build_all_presentations()
+    }
+}
+
+struct PresentationBuilder<'a> {
+    major_sync: &'a MajorSyncInfo,
+    access_unit: &'a AccessUnit,
+    presentation_map: PresentationMap,
+}
+
+impl<'a> PresentationBuilder<'a> {
+    fn new(major_sync: &'a MajorSyncInfo, access_unit: &'a AccessUnit) -> Self {
+        let mut presentation_map = major_sync.presentation_map();
+        let advertised = presentation_map.required_substreams();
+        let present = access_unit.substreams;
+        if advertised > present {
+            log::warn!(
+                "{}",
+                ParseError::MissingSubstream {
+                    advertised,
+                    present
+                }
+            );
+            presentation_map = presentation_map.limited_to(present);
+        }
+
+        Self {
+            major_sync,
+            access_unit,
+            presentation_map,
+        }
+    }
+
+    fn build_all_presentations(&self) -> Vec<PresentationInfo> {
+        let mut presentations = Vec::new();
+        let mut last_presentation = PresentationInfo::default();
+
+        for index in 0..self.major_sync.substreams.max(3) {
+            let presentation = if index < self.major_sync.substreams {
+                let info = self.build_presentation_for_substream(index);
+                last_presentation = info.clone();
+                info
+            } else {
+                last_presentation.clone()
+            };
+
+            presentations.push(self.finalize_presentation(presentation, index));
+        }
+
+        presentations
+    }
+
+    fn build_fbb_presentations(&self) -> Vec<PresentationInfo> {
+        (0..self.major_sync.substreams)
+            .map(|index| {
+                let assignments = self
+                    .access_unit
+                    .get_channel_labels(index)
+                    .unwrap_or_default();
+
+                PresentationInfo {
+                    index,
+                    channels: Some(assignments.len() as u8),
+                    presentation_type: Some(
+                        self.presentation_map.presentation_type_by_index(index),
+                    ),
+                    assignments,
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    fn build_presentation_for_substream(&self, index: usize) -> PresentationInfo {
+        let mut presentation = PresentationInfo {
+            index,
+            channels: self.access_unit.presentation_channel_count(index),
+            ..Default::default()
+        };
+
+        match index {
+            0 => self.configure_twoch_presentation(&mut presentation),
+            1 => self.configure_sixch_presentation(&mut presentation),
+            2 => self.configure_eightch_presentation(&mut presentation),
+            3 => self.configure_sixteench_presentation(&mut presentation),
+            _ => unreachable!(),
+        }
+
+        presentation
+    }
+
+    fn configure_twoch_presentation(&self, presentation: &mut PresentationInfo) {
+        let format_info = &self.major_sync.format_info;
+        let channel_meaning = &self.major_sync.channel_meaning;
+
+        presentation.twoch_format =
+            Some(ChannelGroup::from_modifier(format_info.twoch_decoder_channel_modifier).unwrap());
+        presentation.control = Some(channel_meaning.twoch_control_enabled);
+        presentation.dialogue_level = -(channel_meaning.twoch_dialogue_norm as i8);
+        presentation.mix_level = channel_meaning.twoch_mix_level + 70;
+    }
+
+    fn configure_sixch_presentation(&self, presentation: &mut PresentationInfo) {
+        let format_info = &self.major_sync.format_info;
+        let channel_meaning = &self.major_sync.channel_meaning;
+
+        let assignment = format_info.sixch_decoder_channel_assignment;
+        if assignment == 1 {
+            presentation.twoch_format = Some(
+                ChannelGroup::from_modifier(format_info.twoch_decoder_channel_modifier).unwrap(),
+            );
+        }
+
+        if assignment & 8 != 0 {
+            presentation.sixch_ex = Some(
+                match format_info.twoch_decoder_channel_modifier {
+                    0 => "Not indicated",
+                    1 => "Not encoded",
+                    2 => "Encoded",
+                    _ => "Reserved",
+                }
+                .to_string(),
+            );
+        }
+
+        presentation.set_assignments(
+            ChannelLabel::from_sixch_channel(assignment),
+            assignment as u16,
+        );
+        presentation.control = Some(channel_meaning.sixch_control_enabled);
+        presentation.dialogue_level = -(channel_meaning.sixch_dialogue_norm as i8);
+        presentation.mix_level = channel_meaning.sixch_mix_level + 70;
+    }
+
+    fn configure_eightch_presentation(&self, presentation: &mut PresentationInfo) {
+        let format_info = &self.major_sync.format_info;
+        let channel_meaning = &self.major_sync.channel_meaning;
+
+        let assignment = format_info.eightch_decoder_channel_assignment;
+        presentation.set_assignments(
+            ChannelLabel::from_eightch_channel(assignment, self.major_sync.flags),
+            assignment,
+        );
+        presentation.control = Some(channel_meaning.eightch_control_enabled);
+        presentation.dialogue_level = -(channel_meaning.eightch_dialogue_norm as i8);
+        presentation.mix_level = channel_meaning.eightch_mix_level + 70;
+    }
+
+    fn configure_sixteench_presentation(&self, presentation: &mut PresentationInfo) {
+        let channel_meaning = &self.major_sync.channel_meaning;
+
+        let Some(extra) = &channel_meaning.extra_channel_meaning else {
+            return;
+        };
+
+        presentation.dialogue_level = -(extra.sixteench_dialogue_norm as i8);
+        presentation.mix_level = extra.sixteench_mix_level + 70;
+
+        if !extra.dyn_object_only && extra.sixteench_content_description & 1 != 0 {
+            presentation.chan_distribution = Some(extra.chan_distribute);
+        }
+        // Bed channels followed by the dynamic objects
+        presentation.assignments = self.access_unit.get_channel_labels(3).unwrap_or_default();
+    }
+
+    fn finalize_presentation(
+        &self,
+        mut presentation: PresentationInfo,
+        index: usize,
+    ) -> PresentationInfo {
+        presentation.index = index;
+        presentation.presentation_type =
+            Some(self.presentation_map.presentation_type_by_index(index));
+        presentation
+    }
+}
+
+/// Whether the channel count of every substream of a major sync access unit
+/// is known.
+///
+/// Substreams without a restart header in the major sync access unit get
+/// their channel count from a later access unit. FBB streams list their
+/// channels in the major sync.
+pub fn channel_counts_known(access_unit: &AccessUnit) -> bool {
+    access_unit
+        .major_sync_info
+        .as_ref()
+        .is_some_and(|major_sync| major_sync.format_sync == MAJOR_SYNC_FBB)
+        || (0..access_unit.substreams)
+            .all(|index| access_unit.presentation_channel_count(index).is_some())
+}
+
+/// Summary of a stream, as written by `truehdd info --json`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StreamReport {
+    pub stream: StreamInfo,
+    pub presentations: Vec<PresentationInfo>,
+    /// Substream segment sizes in bytes in the first major sync access unit
+    pub substream_sizes: Vec<usize>,
+    /// Samples trimmed from the start according to the high-resolution output timing
+    pub trim_samples: Option<u32>,
+    pub timestamp: Option<String>,
+    /// Drift of the last SMPTE timestamp in samples
+    pub timestamp_drift: Option<i64>,
+    pub frames: usize,
+    pub bytes: usize,
+    /// Duration in seconds
+    pub duration: Option<f64>,
+    /// Average data rate in kbps
+    pub average_data_rate: Option<f64>,
+    /// The first [`MAX_CORRUPT_REGIONS`] regions skipped by the extractor
+    pub corrupt_regions: Vec<CorruptRegion>,
+}
+
+impl StreamReport {
+    /// Summarizes `frames` access units of `bytes` bytes, described by the
+    /// first major sync access unit.
+    pub fn new(
+        stream: StreamInfo,
+        access_unit: &AccessUnit,
+        timestamp: Option<&Timestamp>,
+        frames: usize,
+        bytes: usize,
+    ) -> Self {
+        let duration = access_unit
+            .major_sync_info
+            .as_ref()
+            .and_then(|major_sync| major_sync.format_info.samples_per_au().ok())
+            .map(|samples_per_au| {
+                (frames * samples_per_au) as f64 / stream.sampling_frequency as f64
+            });
+        let average_data_rate = duration
+            .filter(|&duration| duration > 0.0)
+            .map(|duration| (bytes as f64 * 8.0) / (duration * 1000.0));
+
+        Self {
+            presentations: build_presentations(access_unit),
+            substream_sizes: access_unit.substream_segment_sizes(),
+            stream,
+            trim_samples: None,
+            timestamp: timestamp.map(|timestamp| timestamp.to_string()),
+            timestamp_drift: None,
+            frames,
+            bytes,
+            duration,
+            average_data_rate,
+            corrupt_regions: Vec::new(),
+        }
+    }
+}
+
+/// Summarizes a stream pushed in chunks, without performing any IO.
+///
+/// Access units are parsed until the first major sync, its presentations and
+/// the high-resolution output timing are known, and throughout streams with
+/// SMPTE timestamps to track their drift. The remaining access units are only
+/// counted.
+#[derive(Default)]
+pub struct StreamAnalyzer {
+    extractor: Extractor,
+    parser: Parser,
+    first: Option<(StreamInfo, AccessUnit)>,
+    timestamp: Option<Timestamp>,
+    trim_samples: Option<u32>,
+    frames: usize,
+    bytes: usize,
+    corrupt_regions: Vec<CorruptRegion>,
+}
+
+impl StreamAnalyzer {
+    /// Extracts and analyzes the access units completed by `data`.
+    ///
+    /// Extraction and parse errors are logged and the access units skipped.
+    /// The regions skipped by the extractor are collected for the report.
+    pub fn push_bytes(&mut self, data: &[u8]) {
+        self.bytes += data.len();
+        self.extractor.push_bytes(data);
+
+        while let Some(result) = self.extractor.next() {
+            match result {
+                Ok(frame) => self.push_frame(&frame),
+                Err(e) => {
+                    if let Some(region) = CorruptRegion::from_error(&e)
+                        && self.corrupt_regions.len() < MAX_CORRUPT_REGIONS
+                    {
+                        self.corrupt_regions.push(region);
+                    }
+                }
+            }
+        }
+    }
+
+    fn push_frame(&mut self, frame: &Frame) {
+        let channel_counts_known = self
+            .first
+            .as_ref()
+            .is_none_or(|(_, access_unit)| channel_counts_known(access_unit));
+
+        if self.first.is_none()
+            || self.trim_samples.is_none()
+            || self.timestamp.is_some()
+            || !channel_counts_known
+        {
+            match self.parser.parse(frame) {
+                Ok(access_unit) => {
+                    for warning in self.parser.take_warnings() {
+                        log::warn!("{warning}");
+                    }
+
+                    if self.timestamp.is_none() {
+                        self.timestamp = frame.timestamp.clone();
+                    }
+
+                    match &mut self.first {
+                        None => {
+                            if let Some(major_sync) = &access_unit.major_sync_info {
+                                match StreamInfo::from_major_sync(major_sync) {
+                                    Ok(stream) => self.first = Some((stream, access_unit)),
+                                    Err(e) => log::warn!(
+                                        "Invalid major sync at frame {}: {e:#}",
+                                        self.frames
+                                    ),
+                                }
+                            }
+                        }
+                        Some((_, first)) if !channel_counts_known => {
+                            first.channel_counts = access_unit.channel_counts;
+                        }
+                        Some(_) => {}
+                    }
+
+                    if self.first.is_some() && self.trim_samples.is_none() {
+                        self.trim_samples = self
+                            .parser
+                            .hires_output_timing()
+                            .map(|timing| timing as u32);
+                    }
+                }
+                Err(e) => log::warn!("Parse error at frame {}: {e:#}", self.frames),
+            }
+        }
+
+        self.frames += 1;
+    }
+
+    /// Summarizes the stream, or returns `None` if it has no major sync.
+    pub fn finish(self) -> Option<StreamReport> {
+        let (stream, access_unit) = self.first?;
+        let mut report = StreamReport::new(
+            stream,
+            &access_unit,
+            self.timestamp.as_ref(),
+            self.frames,
+            self.bytes,
+        );
+        report.trim_samples = self.trim_samples;
+        report.timestamp_drift = self
+            .timestamp
+            .is_some()
+            .then(|| self.parser.timestamp_drift());
+        report.corrupt_regions = self.corrupt_regions;
+        Some(report)
+    }
+}
+
+/// Major sync parameters selecting the presentations and channels of a stream.
+///
+/// A stream changing its configuration restarts decoding at the change, and
+/// its presentations may no longer match those of the first major sync.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StreamConfiguration {
+    pub substream_info: u8,
+    pub extended_substream_info: u8,
+    pub format_info: FormatInfo,
+    /// Channels of the 6ch presentation, or of an FBB stream
+    pub sixch_channels: Vec<ChannelLabel>,
+    /// Channels of the 8ch presentation, empty for FBB streams
+    pub eightch_channels: Vec<ChannelLabel>,
+    /// Bed channels of the 16ch presentation, if signalled
+    pub sixteench_channels: Option<Vec<ChannelLabel>>,
+}
+
+impl StreamConfiguration {
+    /// Returns the configuration of a major sync. Reserved channel
+    /// assignments have no channels.
+    pub fn from_major_sync(major_sync: &MajorSyncInfo) -> Self {
+        let format_info = &major_sync.format_info;
+        let (sixch_channels, eightch_channels) = if major_sync.format_sync == MAJOR_SYNC_FBB {
+            (
+                ChannelLabel::from_fbb_channel(format_info.fbb_channel_assignment)
+                    .unwrap_or_default(),
+                Vec::new(),
+            )
+        } else {
+            (
+                ChannelLabel::from_sixch_channel(format_info.sixch_decoder_channel_assignment)
+                    .unwrap_or_default(),
+                ChannelLabel::from_eightch_channel(
+                    format_info.eightch_decoder_channel_assignment,
+                    major_sync.flags,
+                )
+                .unwrap_or_default(),
+            )
+        };
+
+        Self {
+            substream_info: major_sync.substream_info,
+            extended_substream_info: major_sync.extended_substream_info,
+            format_info: format_info.clone(),
+            sixch_channels,
+            eightch_channels,
+            sixteench_channels: major_sync
+                .channel_meaning
+                .extra_channel_meaning
+                .as_ref()
+                .map(|extra| {
+                    ChannelLabel::from_sixteenth_channel(extra.sixteench_channel_assignment)
+                        .unwrap_or_default()
+                }),
+        }
+    }
+}
+
+/// Access units of a stream sharing a [`StreamConfiguration`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ConfigurationSpan {
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub configuration: StreamConfiguration,
+    /// Index of the first access unit with the configuration, counted from 0
+    pub first_au: usize,
+    /// Index of the last access unit with the configuration
+    pub last_au: usize,
+    /// Number of major syncs carrying the configuration
+    pub major_syncs: usize,
+}
+
+/// Collects the distinct [`StreamConfiguration`]s of a stream.
+///
+/// Only the major syncs are read, without parsing the access units, so
+/// scanning a whole stream is cheap. Every access unit belongs to the
+/// configuration of the last major sync before it. A configuration that
+/// returns after another one keeps its first span, extended to its last
+/// access unit.
+#[derive(Debug, Default)]
+pub struct ConfigurationScanner {
+    spans: Vec<ConfigurationSpan>,
+    /// Span of the last major sync
+    current: Option<usize>,
+    frames: usize,
+}
+
+impl ConfigurationScanner {
+    /// Accounts for the next access unit of the stream.
+    ///
+    /// Major syncs that fail to read are logged at debug level, leaving the
+    /// access unit with the previous configuration.
+    pub fn push_frame(&mut self, frame: &Frame) {
+        if frame.is_major_sync() {
+            match read_major_sync(frame) {
+                Ok(major_sync) => {
+                    let configuration = StreamConfiguration::from_major_sync(&major_sync);
+                    let index = match self
+                        .spans
+                        .iter()
+                        .position(|span| span.configuration == configuration)
+                    {
+                        Some(index) => index,
+                        None => {
+                            self.spans.push(ConfigurationSpan {
+                                configuration,
+                                first_au: self.frames,
+                                last_au: self.frames,
+                                major_syncs: 0,
+                            });
+                            self.spans.len() - 1
+                        }
+                    };
+                    self.spans[index].major_syncs += 1;
+                    self.current = Some(index);
+                }
+                Err(e) => log::debug!("Unreadable major sync at frame {}: {e:#}", self.frames),
+            }
+        }
+
+        if let Some(current) = self.current {
+            self.spans[current].last_au = self.frames;
+        }
+        self.frames += 1;
+    }
+
+    /// Returns the configurations in the order of their first access unit.
+    pub fn configurations(&self) -> &[ConfigurationSpan] {
+        &self.spans
+    }
+
+    /// Consumes the scanner, returning the configurations.
+    pub fn finish(self) -> Vec<ConfigurationSpan> {
+        self.spans
+    }
+}
+
+/// Reads the major sync of a major sync frame on its own.
+///
+/// Checks against the previous access units are not possible this way, and
+/// problems are left to the parser of the stream.
+fn read_major_sync(frame: &Frame) -> Result<MajorSyncInfo> {
+    let mut state = ParserState::default();
+    for category in Category::ALL {
+        state.category_policy.set(category, Policy::Ignore);
+    }
+    let mut reader = BsIoSliceReader::from_slice(&frame.data[4..]);
+    MajorSyncInfo::read(&mut state, &mut reader)
+}
+
+#[test]
+fn example_stream_report() {
+    use crate::process::EXAMPLE_DATA;
+
+    let mut analyzer = StreamAnalyzer::default();
+    for chunk in EXAMPLE_DATA.chunks(100) {
+        analyzer.push_bytes(chunk);
+    }
+    let report = analyzer.finish().unwrap();
+
+    assert_eq!(report.stream.sampling_frequency, 48000);
+    assert_eq!(report.stream.substreams, report.substream_sizes.len());
+    assert_eq!(report.presentations.len(), 3);
+    assert_eq!(report.bytes, EXAMPLE_DATA.len());
+    assert!(report.frames > 0);
+    assert_eq!(report.duration, Some(report.frames as f64 * 40.0 / 48000.0));
+    assert_eq!(
+        report.timestamp.as_deref(),
+        Some("01:23:45:16 +25 @ 29.97 fps DF")
+    );
+    assert!(report.timestamp_drift.is_some());
+    assert!(report.corrupt_regions.is_empty());
+
+    assert!(StreamAnalyzer::default().finish().is_none());
+}
+
+#[test]
+fn missing_substream_presentations_invalid() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    let frame = extractor.next().unwrap()?;
+    let mut access_unit = Parser::default().parse(&frame)?;
+
+    // Presentations 1 and 2 of substream 1, which the access unit lacks
+    access_unit.major_sync_info.as_mut().unwrap().substream_info = 0x28;
+    let presentations = build_presentations(&access_unit);
+    assert_eq!(
+        presentations[0].presentation_type,
+        Some(PresentationType::Independent)
+    );
+    assert!(
+        presentations[1..]
+            .iter()
+            .all(|presentation| presentation.presentation_type == Some(PresentationType::Invalid))
+    );
+    Ok(())
+}
+
+#[test]
+fn configuration_changes_scanned() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(3));
+    let frames = extractor.filter_map(Result::ok).collect::<Vec<_>>();
+    assert!(frames[0].is_major_sync() && !frames[1].is_major_sync());
+
+    // The first configuration returns after a different one, which differs
+    // only in substream_info
+    let mut data = frames[2].data.to_vec();
+    data[4 + 17] ^= 0x10;
+    let changed = Frame {
+        data: data.into(),
+        ..frames[2].clone()
+    };
+    let mut scanner = ConfigurationScanner::default();
+    for frame in [
+        &frames[0], &frames[1], &changed, &frames[3], &frames[4], &frames[5],
+    ] {
+        scanner.push_frame(frame);
+    }
+
+    let spans = scanner.finish();
+    assert_eq!(spans.len(), 2);
+    assert_eq!(
+        spans
+            .iter()
+            .map(|span| (span.first_au, span.last_au, span.major_syncs))
+            .collect::<Vec<_>>(),
+        [(0, 5, 2), (2, 3, 1)]
+    );
+    assert_eq!(
+        spans[0].configuration.substream_info ^ spans[1].configuration.substream_info,
+        0x10
+    );
+    assert_eq!(
+        spans[0].configuration.format_info.sampling_frequency_1()?,
+        48000
+    );
+    Ok(())
+}
@@ -1,47 +1,334 @@
-use crate::process::{MAX_PRESENTATIONS, PresentationMap, PresentationType};
+use crate::log_or_err;
+use crate::process::metadata::{
+    RawEvoPayload, oamd_evo_payloads, read_evo_payloads, read_oamd_payload,
+};
+use crate::process::{Instant, MAX_PRESENTATIONS, Metrics, PresentationMap, PresentationType};
 use crate::structs::access_unit::AccessUnit;
 use crate::structs::channel::ChannelLabel;
 use crate::structs::oamd::ObjectAudioMetadataPayload;
+use crate::utils::buffer_pool::{PcmPool, PooledPcm};
 use crate::utils::dither::dither_31eb;
-use crate::utils::errors::DecodeError;
-use anyhow::{Result, bail};
+use crate::utils::errors::{Category, CategoryPolicy, DecodeError, ParseError, Policy};
+use crate::utils::events::{Event, EventSink};
+use anyhow::{Result, anyhow, bail};
 use log::{info, trace};
 use std::collections::VecDeque;
 
 /// Decodes access units to PCM audio samples.
 ///
-/// Converts parsed [`AccessUnit`] structures into 24-bit PCM audio data.
+/// Converts parsed [`AccessUnit`] structures into `i32` PCM samples at the
+/// bit depth of the stream: 24 bits, or 32 bits for substreams coded with the
+/// 0x31EC restart sync word.
 #[derive(Default)]
 pub struct Decoder {
-    state: DecoderState,
+    pub(crate) state: Box<DecoderState>,
+    metrics: Option<Metrics>,
+    pcm_pool: PcmPool,
+    keep_evo_payloads: bool,
 }
 
 impl Decoder {
     /// Decodes an access unit to PCM audio samples.
     ///
-    /// Returns a [`DecodedAccessUnit`] containing 24-bit PCM samples organized
-    /// as `[sample_index][channel_index]` with up to 160 samples and 16 channels.
+    /// Returns a [`DecodedAccessUnit`] containing PCM samples of
+    /// [`bit_depth`](DecodedAccessUnit::bit_depth) bits organized as
+    /// `[sample_index][channel_index]` with up to 160 samples and 16 channels.
     pub fn decode_presentation(
         &mut self,
         access_unit: &AccessUnit,
         presentation: usize,
     ) -> Result<DecodedAccessUnit> {
-        self.state.decode_access_unit(access_unit, presentation)?;
-        let decoded = DecodedAccessUnit {
+        self.state.substream_mask_override = None;
+        self.state.requested_presentations = [false; MAX_PRESENTATIONS];
+        self.decode(access_unit, presentation)
+            .map(|(decoded, _)| decoded)
+    }
+
+    /// Decodes an access unit for several presentations in one pass.
+    ///
+    /// The substreams up to the highest requested presentation are decoded
+    /// once. Lower presentations apply the lossless matrix and remapping of
+    /// their substream to a copy of the shared channels, and presentations
+    /// that are a copy of another presentation or not available reuse the
+    /// output of the presentation decoded in their place. Returns a
+    /// [`DecodedAccessUnit`] for every requested presentation, in ascending
+    /// order of the requested index.
+    ///
+    /// As with [`decode_presentation`](Self::decode_presentation), the
+    /// presentations are resolved at the start of a stream. Only the
+    /// highest decoded presentation carries OAMD and is checked for
+    /// duplicates, its [`DecodedAccessUnit::is_duplicate`] applies to all.
+    pub fn decode_presentations(
+        &mut self,
+        access_unit: &AccessUnit,
+        presentations: &[bool; MAX_PRESENTATIONS],
+    ) -> Result<Vec<(usize, DecodedAccessUnit)>> {
+        let Some(highest) = presentations.iter().rposition(|&requested| requested) else {
+            bail!("No presentation requested");
+        };
+        self.state.substream_mask_override = None;
+        self.state.requested_presentations = *presentations;
+        let (main, others) = self.decode(access_unit, highest)?;
+
+        let mut outputs: [Option<DecodedAccessUnit>; MAX_PRESENTATIONS] = Default::default();
+        outputs[self.state.presentation] = Some(main);
+        for (presentation, decoded) in others {
+            outputs[presentation] = Some(decoded);
+        }
+
+        let targets = self.state.presentation_targets;
+        let mut decoded = Vec::with_capacity(targets.len());
+        for (presentation, &target) in targets.iter().enumerate() {
+            let Some(target) = target else {
+                continue;
+            };
+            // Requests resolving to the same presentation get their own copy
+            let output = if targets[presentation + 1..].contains(&Some(target)) {
+                outputs[target]
+                    .as_ref()
+                    .map(|output| self.copy_access_unit(output))
+            } else {
+                outputs[target].take()
+            };
+            let output =
+                output.ok_or_else(|| anyhow!("Presentation {presentation} was not decoded"))?;
+            decoded.push((presentation, output));
+        }
+        Ok(decoded)
+    }
+
+    /// Decodes an access unit to normalized planar samples in double precision.
+    ///
+    /// Returns the [`DecodedAccessUnit`] along with its valid samples laid out
+    /// and normalized as by [`DecodedAccessUnit::to_planar_f64`]. Where a
+    /// channel has a negative output shift, the bits the shift truncates from
+    /// the integer samples are kept as a binary fraction, so each value is the
+    /// integer sample plus a fraction of less than one LSB before normalizing.
+    /// Positive shifts are exact in both outputs.
+    pub fn decode_presentation_f64(
+        &mut self,
+        access_unit: &AccessUnit,
+        presentation: usize,
+    ) -> Result<(DecodedAccessUnit, Vec<Vec<f64>>)> {
+        self.state.substream_mask_override = None;
+        self.state.requested_presentations = [false; MAX_PRESENTATIONS];
+        self.state
+            .output_buffer_f64
+            .get_or_insert_with(|| Box::new([[0.0; 16]; 160]));
+        let (decoded, _) = self.decode(access_unit, presentation)?;
+
+        let output = self.state.output_buffer_f64.as_deref().unwrap();
+        let full_scale = decoded.full_scale();
+        let planar = (0..decoded.channel_count)
+            .map(|channel| {
+                output[..decoded.sample_length]
+                    .iter()
+                    .map(|sample| sample[channel] / full_scale)
+                    .collect()
+            })
+            .collect();
+        Ok((decoded, planar))
+    }
+
+    /// Decodes an access unit from the substreams in `mask` instead of those
+    /// required by the presentation.
+    ///
+    /// Meant for debugging partial decodes, such as decoding presentation 3
+    /// without the substreams of the lower presentations. The mask must cover
+    /// the substreams the decoded presentation depends on and no substream
+    /// beyond the presentations up to it, otherwise
+    /// [`DecodeError::InvalidSubstreamMask`] is returned.
+    pub fn decode_with_substream_mask(
+        &mut self,
+        access_unit: &AccessUnit,
+        mask: u8,
+        presentation: usize,
+    ) -> Result<DecodedAccessUnit> {
+        self.state.substream_mask_override = Some(mask);
+        self.state.requested_presentations = [false; MAX_PRESENTATIONS];
+        self.decode(access_unit, presentation)
+            .map(|(decoded, _)| decoded)
+    }
+
+    /// Decodes an access unit, returning the decoded presentation and those
+    /// decoded below it for [`decode_presentations`](Self::decode_presentations)
+    fn decode(
+        &mut self,
+        access_unit: &AccessUnit,
+        presentation: usize,
+    ) -> Result<(DecodedAccessUnit, Vec<(usize, DecodedAccessUnit)>)> {
+        let start = self.metrics.is_some().then(Instant::now);
+        let au_index = self.state.au_index;
+        self.state.au_index += 1;
+        let result = self.state.decode_access_unit(access_unit, presentation);
+
+        if let (Some(metrics), Some(start)) = (&mut self.metrics, start) {
+            metrics.decode_time += start.elapsed();
+        }
+        result?;
+
+        let is_duplicate = self.state.has_duplicate_timing && self.state.has_duplicate_sample;
+        if is_duplicate {
+            self.state.stats.duplicate_access_units += 1;
+            if self.state.duplicate_handling == DuplicateHandling::Error {
+                bail!(DecodeError::DuplicateAccessUnit(
+                    self.state.stats.current_au()
+                ));
+            }
+        }
+
+        let mut pcm_data = self.pcm_pool.acquire();
+        *pcm_data = self.state.output_buffer;
+
+        let presentation_state = &self.state.substream_state[self.state.presentation];
+        let mut decoded = DecodedAccessUnit {
             channel_labels: self.state.channel_labels.clone(),
             sampling_frequency: self.state.sampling_frequency,
-            sample_length: self.state.samples_per_au - self.state.zero_samples,
-            channel_count: self.state.substream_state[self.state.presentation].max_matrix_chan + 1,
-            pcm_data: self.state.output_buffer,
+            sample_length: self
+                .state
+                .samples_per_au
+                .saturating_sub(self.state.zero_samples),
+            channel_count: presentation_state.max_matrix_chan + 1,
+            pcm_data,
+            bit_depth: presentation_state.bit_depth(),
             oamd: self.state.oamd.iter().cloned().collect::<Vec<_>>(),
-            is_duplicate: self.state.has_duplicate_timing && self.state.has_duplicate_sample,
+            evo_payloads: if self.keep_evo_payloads {
+                read_evo_payloads(access_unit)
+            } else {
+                Vec::new()
+            },
+            is_duplicate: is_duplicate && self.state.duplicate_handling == DuplicateHandling::Skip,
             substream_info_changed: self.state.substream_info_changed,
+            config_changed: self.state.config_changed,
+            stream_start: access_unit.stream_start,
+            silence_samples: self.state.silence_samples,
+            au_index,
+            sample_position: 0,
+            discontinuity: self.state.discontinuity,
+            heavy_drc_gain: self.state.heavy_drc_gain.take(),
+            dialogue_level: self.state.dialogue_level[self.state.presentation],
+            other_presentations: Vec::new(),
         };
 
+        if let Some(gain) = &mut decoded.heavy_drc_gain {
+            gain.truncate(decoded.sample_length);
+        }
+
+        let mut others = Vec::new();
+        for (presentation, output) in self.state.extra_outputs.iter_mut().enumerate() {
+            let Some(output) = output else {
+                continue;
+            };
+            let mut pcm_data = self.pcm_pool.acquire();
+            *pcm_data = *output.output_buffer;
+
+            let sample_length = self
+                .state
+                .samples_per_au
+                .saturating_sub(output.zero_samples);
+            let mut heavy_drc_gain = output.heavy_drc_gain.take();
+            if let Some(gain) = &mut heavy_drc_gain {
+                gain.truncate(sample_length);
+            }
+            others.push((
+                presentation,
+                DecodedAccessUnit {
+                    sample_length,
+                    channel_count: output.channel_count,
+                    pcm_data,
+                    bit_depth: self.state.substream_state[presentation].bit_depth(),
+                    channel_labels: output.channel_labels.clone(),
+                    oamd: Vec::new(),
+                    evo_payloads: decoded.evo_payloads.clone(),
+                    config_changed: output.config_changed,
+                    heavy_drc_gain,
+                    dialogue_level: self.state.dialogue_level[presentation],
+                    other_presentations: Vec::new(),
+                    ..decoded
+                },
+            ));
+        }
+
+        // Drop the samples overlapping the timeline before a repaired branch
+        let dropped = self.state.pending_drop.min(decoded.sample_length);
+        if dropped > 0 {
+            if let Some(output) = &mut self.state.output_buffer_f64 {
+                output.copy_within(dropped..decoded.sample_length, 0);
+            }
+            decoded.drop_samples(dropped);
+            for (_, other) in &mut others {
+                other.drop_samples(dropped.min(other.sample_length));
+            }
+            self.state.pending_drop -= dropped;
+            self.state.stats.dropped_samples += dropped as u64;
+        }
+
+        self.place(&mut decoded);
+        for (_, other) in &mut others {
+            other.sample_position = decoded.sample_position;
+        }
+
+        if let Some(metrics) = &mut self.metrics {
+            metrics.samples += decoded.sample_length as u64;
+        }
+
         // Reset the flag after reading it
         self.state.substream_info_changed = false;
 
-        Ok(decoded)
+        Ok((decoded, others))
+    }
+
+    /// Places `decoded` on the output timeline after the access units decoded
+    /// before it and the silence inserted before it.
+    ///
+    /// Duplicates handled with [`DuplicateHandling::Skip`] do not advance the
+    /// position, as they may be discarded.
+    pub(crate) fn place(&mut self, decoded: &mut DecodedAccessUnit) {
+        decoded.sample_position = self.state.sample_position + decoded.silence_samples as u64;
+        self.state.sample_position = decoded.sample_position;
+        if !decoded.is_duplicate {
+            self.state.sample_position += decoded.sample_length as u64;
+        }
+    }
+
+    /// Returns a copy of `decoded` with its own PCM block from the pool
+    fn copy_access_unit(&self, decoded: &DecodedAccessUnit) -> DecodedAccessUnit {
+        let mut pcm_data = self.pcm_pool.acquire();
+        *pcm_data = *decoded.pcm_data;
+        DecodedAccessUnit {
+            pcm_data,
+            channel_labels: decoded.channel_labels.clone(),
+            oamd: decoded.oamd.clone(),
+            evo_payloads: decoded.evo_payloads.clone(),
+            heavy_drc_gain: decoded.heavy_drc_gain.clone(),
+            other_presentations: Vec::new(),
+            ..*decoded
+        }
+    }
+
+    /// Sets the pool the PCM blocks of decoded access units are taken from.
+    ///
+    /// Blocks return to the pool when the [`DecodedAccessUnit`] is dropped. A
+    /// pool can be shared by several decoders.
+    pub fn set_pcm_pool(&mut self, pool: PcmPool) {
+        self.pcm_pool = pool;
+    }
+
+    /// Enables or disables passing EVO frame payloads through to
+    /// [`DecodedAccessUnit::evo_payloads`].
+    ///
+    /// Disabled by default, so the payload bytes are only copied for callers
+    /// that inspect them.
+    pub fn set_keep_evo_payloads(&mut self, keep: bool) {
+        self.keep_evo_payloads = keep;
+    }
+
+    /// Sets how access units detected as duplicates are returned.
+    ///
+    /// See [`DuplicateHandling`] for the detection. Detected duplicates are
+    /// counted in [`DecodeStats::duplicate_access_units`] in every mode.
+    pub fn set_duplicate_handling(&mut self, handling: DuplicateHandling) {
+        self.state.duplicate_handling = handling;
     }
 
     /// Sets the failure level for validation errors.
@@ -51,12 +338,280 @@ impl Decoder {
     pub fn set_fail_level(&mut self, level: log::Level) {
         self.state.fail_level = level;
     }
+
+    /// Sets the handling of the validation errors of `category`,
+    /// overriding the failure level for them.
+    pub fn set_category_policy(&mut self, category: Category, policy: Policy) {
+        self.state.category_policy.set(category, policy);
+    }
+
+    /// Sets a sink receiving an [`Event`] for duplicate access units and
+    /// every validation problem, in addition to the log.
+    ///
+    /// The sink is shared with the decoders forked by
+    /// [`ParallelDecoder`](crate::process::parallel::ParallelDecoder).
+    pub fn set_event_sink(&mut self, sink: impl Fn(Event) + Send + Sync + 'static) {
+        self.state.event_sink = Some(EventSink::new(sink));
+    }
+
+    /// Enables or disables skip mode for fast-forwarding.
+    ///
+    /// In skip mode access units only update the decoder state from restart
+    /// and block headers. Recorrelation, rematrixing and remapping are skipped,
+    /// so `pcm_data` of the returned [`DecodedAccessUnit`] is not valid and
+    /// `is_duplicate` is never set. OAMD is still parsed.
+    ///
+    /// Filter and matrix state are reset by restart headers, so output becomes
+    /// exact again from the first major sync access unit decoded after skip
+    /// mode is disabled. The lossless check covering the skipped interval is
+    /// not verified.
+    pub fn set_skip_decode(&mut self, skip: bool) {
+        self.state.skip_decode = skip;
+        if skip {
+            self.state.lossless_check_unverified = true;
+        }
+    }
+
+    /// Returns `true` while the decoder is in skip mode.
+    pub fn is_skipping(&self) -> bool {
+        self.state.skip_decode
+    }
+
+    /// Returns the presentation actually decoded.
+    ///
+    /// Differs from the requested presentation when that is a copy of another
+    /// presentation or not available in the stream.
+    pub fn presentation(&self) -> usize {
+        self.state.presentation
+    }
+
+    /// Returns the index of the substream decoded last.
+    ///
+    /// After a decode error, this is the failing substream.
+    pub fn substream_index(&self) -> usize {
+        self.state.substream_index
+    }
+
+    /// Returns integrity counters accumulated over all decoded access units.
+    ///
+    /// Lossless checks are only verified for the substream of the decoded
+    /// presentation. Huffman saturations are detected while parsing and are
+    /// reported by [`Parser::stats`](crate::process::parse::Parser::stats).
+    pub fn stats(&self) -> DecodeStats {
+        self.state.stats
+    }
+
+    /// Enables or disables collection of [`Metrics`].
+    ///
+    /// Only the decoded sample count and decode time are filled in.
+    /// Disabling discards the metrics collected so far.
+    pub fn enable_metrics(&mut self, enable: bool) {
+        self.metrics = enable.then(|| self.metrics.unwrap_or_default());
+    }
+
+    /// Returns the metrics collected since the last call and resets them.
+    ///
+    /// Returns `None` if metrics are not enabled.
+    pub fn take_metrics(&mut self) -> Option<Metrics> {
+        self.metrics.as_mut().map(std::mem::take)
+    }
+
+    /// Returns a copy of this decoder to continue at a later split point,
+    /// `access_units` ahead.
+    ///
+    /// The lossless check at the first restart header covers audio the copy
+    /// has not decoded, so it is not verified.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub(crate) fn fork(&self, access_units: usize) -> Self {
+        let mut state = self.state.clone();
+        state.counter += access_units;
+        state.au_index += access_units as u64;
+        state.lossless_check_unverified = true;
+        Self {
+            state,
+            metrics: self.metrics.map(|_| Metrics::default()),
+            pcm_pool: self.pcm_pool.clone(),
+            keep_evo_payloads: self.keep_evo_payloads,
+        }
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub(crate) fn sample_position(&self) -> u64 {
+        self.state.sample_position
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub(crate) fn set_sample_position(&mut self, sample_position: u64) {
+        self.state.sample_position = sample_position;
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub(crate) fn has_pending_drop(&self) -> bool {
+        self.state.pending_drop > 0
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub(crate) fn reset_stats(&mut self) {
+        self.state.stats = DecodeStats::default();
+    }
+}
+
+/// Integrity counters of a single substream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubstreamStats {
+    /// Number of restart headers whose lossless check did not match.
+    pub lossless_check_mismatches: u64,
+    /// Number of access units that failed with a recorrelator saturation.
+    pub recorrelator_saturations: u64,
+    /// Number of access units that failed with a Huffman decode saturation.
+    pub huffman_saturations: u64,
+    /// Index of the access unit of the first failure.
+    pub first_failure_au: Option<u64>,
+    /// Index of the access unit of the last failure.
+    pub last_failure_au: Option<u64>,
+}
+
+impl SubstreamStats {
+    /// Returns the total number of failures.
+    pub fn failures(&self) -> u64 {
+        self.lossless_check_mismatches + self.recorrelator_saturations + self.huffman_saturations
+    }
+
+    /// Adds the counters of `other` and widens the failure range to cover it.
+    pub fn merge(&mut self, other: &SubstreamStats) {
+        self.lossless_check_mismatches += other.lossless_check_mismatches;
+        self.recorrelator_saturations += other.recorrelator_saturations;
+        self.huffman_saturations += other.huffman_saturations;
+        self.first_failure_au = match (self.first_failure_au, other.first_failure_au) {
+            (Some(first), Some(other_first)) => Some(first.min(other_first)),
+            (first, other_first) => first.or(other_first),
+        };
+        self.last_failure_au = self.last_failure_au.max(other.last_failure_au);
+    }
+
+    fn record_failure(&mut self, au_index: u64) {
+        self.first_failure_au.get_or_insert(au_index);
+        self.last_failure_au = Some(au_index);
+    }
+}
+
+/// Stream integrity summary collected while parsing or decoding.
+///
+/// Access unit indices count the access units passed to the
+/// [`Parser`](crate::process::parse::Parser) or [`Decoder`] that collected
+/// the statistics, starting from 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeStats {
+    /// Number of access units processed.
+    pub access_units: u64,
+    /// Counters per substream.
+    pub substreams: [SubstreamStats; MAX_PRESENTATIONS],
+    /// Number of invalid branches repaired as a new stream.
+    pub repaired_branches: u64,
+    /// Number of silent samples inserted at repaired branches.
+    pub inserted_samples: u64,
+    /// Number of overlapping samples dropped after repaired branches.
+    pub dropped_samples: u64,
+    /// Number of access units detected as duplicates of the previous one.
+    pub duplicate_access_units: u64,
+    /// Number of OAMD payloads skipped because they failed to parse.
+    pub skipped_oamd_payloads: u64,
+}
+
+impl DecodeStats {
+    /// Returns the total number of failures over all substreams.
+    pub fn failures(&self) -> u64 {
+        self.substreams.iter().map(SubstreamStats::failures).sum()
+    }
+
+    /// Returns the index of the access unit of the first failure in any substream.
+    pub fn first_failure_au(&self) -> Option<u64> {
+        self.substreams
+            .iter()
+            .filter_map(|s| s.first_failure_au)
+            .min()
+    }
+
+    /// Returns the index of the access unit of the last failure in any substream.
+    pub fn last_failure_au(&self) -> Option<u64> {
+        self.substreams
+            .iter()
+            .filter_map(|s| s.last_failure_au)
+            .max()
+    }
+
+    /// Combines statistics collected over the same access units, e.g. by a
+    /// parser and a decoder.
+    pub fn merge(&mut self, other: &DecodeStats) {
+        self.access_units = self.access_units.max(other.access_units);
+        for (stats, other) in self.substreams.iter_mut().zip(&other.substreams) {
+            stats.merge(other);
+        }
+        self.repaired_branches = self.repaired_branches.max(other.repaired_branches);
+        self.inserted_samples = self.inserted_samples.max(other.inserted_samples);
+        self.dropped_samples = self.dropped_samples.max(other.dropped_samples);
+        self.duplicate_access_units = self
+            .duplicate_access_units
+            .max(other.duplicate_access_units);
+        self.skipped_oamd_payloads = self.skipped_oamd_payloads.max(other.skipped_oamd_payloads);
+    }
+
+    /// Combines statistics collected over the access units following those
+    /// of `self`, offsetting the failure indices of `other`.
+    pub fn append(&mut self, other: &DecodeStats) {
+        let offset = self.access_units;
+        for (stats, other) in self.substreams.iter_mut().zip(&other.substreams) {
+            stats.merge(&SubstreamStats {
+                first_failure_au: other.first_failure_au.map(|au| au + offset),
+                last_failure_au: other.last_failure_au.map(|au| au + offset),
+                ..*other
+            });
+        }
+        self.access_units += other.access_units;
+        self.repaired_branches += other.repaired_branches;
+        self.inserted_samples += other.inserted_samples;
+        self.dropped_samples += other.dropped_samples;
+        self.duplicate_access_units += other.duplicate_access_units;
+        self.skipped_oamd_payloads += other.skipped_oamd_payloads;
+    }
+
+    pub(crate) fn begin_access_unit(&mut self) {
+        self.access_units += 1;
+    }
+
+    fn current_au(&self) -> u64 {
+        self.access_units.saturating_sub(1)
+    }
+
+    pub(crate) fn record_lossless_check_mismatch(&mut self, substream: usize) {
+        let au_index = self.current_au();
+        if let Some(stats) = self.substreams.get_mut(substream) {
+            stats.lossless_check_mismatches += 1;
+            stats.record_failure(au_index);
+        }
+    }
+
+    pub(crate) fn record_recorrelator_saturation(&mut self, substream: usize) {
+        let au_index = self.current_au();
+        if let Some(stats) = self.substreams.get_mut(substream) {
+            stats.recorrelator_saturations += 1;
+            stats.record_failure(au_index);
+        }
+    }
+
+    pub(crate) fn record_huffman_saturation(&mut self, substream: usize) {
+        let au_index = self.current_au();
+        if let Some(stats) = self.substreams.get_mut(substream) {
+            stats.huffman_saturations += 1;
+            stats.record_failure(au_index);
+        }
+    }
 }
 
 /// The result of decoding an access unit to PCM audio.
 ///
-/// Contains 24-bit signed integer samples in sample-major ordering
-/// (`pcm_data[sample_index][channel_index]`) with associated metadata.
+/// Contains signed integer samples of `bit_depth` bits in sample-major
+/// ordering (`pcm_data[sample_index][channel_index]`) with associated metadata.
 #[derive(Debug)]
 pub struct DecodedAccessUnit {
     /// Sampling frequency in Hz.
@@ -78,11 +633,21 @@ pub struct DecodedAccessUnit {
 
     /// PCM audio samples organized as `[sample_index][channel_index]`.
     ///
-    /// Contains 24-bit signed integer samples with sample-major ordering.
+    /// Contains signed integer samples of `bit_depth` bits with sample-major
+    /// ordering.
     /// - Array dimensions: [160 samples][16 channels]
     /// - Valid data length: Determined by `sample_length`
     /// - Channel count: Determined by stream configuration
-    pub pcm_data: [[i32; 16]; 160],
+    ///
+    /// The block is borrowed from the decoder's [`PcmPool`] and returned to
+    /// it when this access unit is dropped.
+    pub pcm_data: PooledPcm,
+
+    /// Precision of the samples in `pcm_data` in bits.
+    ///
+    /// 32 when the presentation substream is coded with the 0x31EC restart
+    /// sync word, 24 otherwise.
+    pub bit_depth: u32,
 
     /// Channel labels for the audio data.
     ///
@@ -95,18 +660,236 @@ pub struct DecodedAccessUnit {
     /// Contains spatial audio metadata when present in the stream.
     pub oamd: Vec<ObjectAudioMetadataPayload>,
 
+    /// All payloads of the EVO frame, including OAMD, in stream order.
+    ///
+    /// Empty unless enabled with [`Decoder::set_keep_evo_payloads`]. Unlike
+    /// `oamd`, payloads are passed through whichever presentation is decoded.
+    pub evo_payloads: Vec<RawEvoPayload>,
+
     /// Indicates whether this access unit is a duplicate of the previous one.
     ///
-    /// This is `true` when both the output timing and the decoded audio sample
-    /// checksum match the previous access unit.
+    /// This is `true` when both the input timing and the lossless check of
+    /// the decoded samples match the previous access unit, and duplicates are
+    /// handled with [`DuplicateHandling::Skip`].
     /// Downstream applications may safely discard this frame.
     pub is_duplicate: bool,
 
     /// Indicates whether this access unit triggered a substream info change.
     ///
-    /// This is `true` when substream_info or extended_substream_info changed,
-    /// indicating that channel layout may have changed requiring new output files.
+    /// This is `true` when the substream count, substream_info or
+    /// extended_substream_info changed at a major sync. The decoder restarts at
+    /// this access unit, so `channel_count` and `channel_labels` reflect the new
+    /// layout and output files may need to be split or reconfigured.
     pub substream_info_changed: bool,
+
+    /// Indicates whether the channel count changed from the previous access
+    /// unit.
+    ///
+    /// This is `true` when a restart header declares another `max_matrix_chan`
+    /// for the decoded presentation, as at a seamless branch into a segment
+    /// with more or fewer channels, or with a substream info change.
+    /// `channel_count` and `channel_labels` reflect the new configuration.
+    pub config_changed: bool,
+
+    /// Indicates whether this access unit starts a stream concatenated at a
+    /// boundary, see [`AccessUnit::stream_start`].
+    pub stream_start: bool,
+
+    /// Number of silent samples to insert before this access unit.
+    ///
+    /// Non-zero only at a branch repaired with
+    /// [`Parser::set_repair_branch`](crate::process::parse::Parser::set_repair_branch)
+    /// whose timeline continues after a gap. Samples overlapping the timeline
+    /// before the branch are dropped from `pcm_data` instead.
+    pub silence_samples: usize,
+
+    /// Index of the access unit since the start of the stream.
+    ///
+    /// Counts every access unit passed to the decoder, also those that failed
+    /// to decode. The [`Pipeline`](crate::process::pipeline::Pipeline)
+    /// numbers access units by frame, which also counts those that failed to
+    /// parse.
+    pub au_index: u64,
+
+    /// Output position of the first valid sample since the start of the
+    /// stream.
+    ///
+    /// Follows the valid samples of the access units before and the
+    /// `silence_samples` inserted before this one, so positions stay
+    /// contiguous across repaired branches and trimmed samples. Duplicates
+    /// handled with [`DuplicateHandling::Skip`] do not advance the position.
+    /// The [`Pipeline`](crate::process::pipeline::Pipeline) also counts the
+    /// silence concealing failed access units.
+    pub sample_position: u64,
+
+    /// Size in samples of the gap in the stream timeline before this access
+    /// unit.
+    ///
+    /// `Some` at a repaired branch: positive when the timeline continues
+    /// after a gap filled with `silence_samples`, negative when it overlaps
+    /// the timeline before and the overlapping samples are dropped.
+    pub discontinuity: Option<i64>,
+
+    /// Linear heavy DRC gain of each valid sample.
+    ///
+    /// `None` until a restart header of the decoded substream carries heavy
+    /// DRC parameters. The gain starts at the heavy DRC start-up gain of the
+    /// major sync and ramps linearly in dB to each update over
+    /// `2^heavy_drc_time_update` access units. The gain is not applied to
+    /// `pcm_data`.
+    pub heavy_drc_gain: Option<Vec<f32>>,
+
+    /// Dialogue level of the decoded presentation in dBFS.
+    ///
+    /// Read from the channel meaning of the last major sync, so it follows a
+    /// change at a later major sync. `None` for FBB streams and for
+    /// presentation 3 without extra channel meaning. Dialogue normalization
+    /// is not applied to `pcm_data`.
+    pub dialogue_level: Option<i8>,
+
+    /// Access units of the lower presentations decoded in the same pass, by
+    /// requested presentation index.
+    ///
+    /// Filled by the [`Pipeline`](crate::process::pipeline::Pipeline) when
+    /// decoding several presentations, empty otherwise. See
+    /// [`Decoder::decode_presentations`].
+    pub other_presentations: Vec<(usize, DecodedAccessUnit)>,
+}
+
+impl DecodedAccessUnit {
+    /// Returns the valid samples as one vector per channel, normalized to
+    /// [-1, 1).
+    ///
+    /// Each sample is divided by `2^(bit_depth - 1)`, so the integer range of
+    /// `bit_depth` bits maps to -1.0 up to `1 - 2^(1 - bit_depth)`. The
+    /// conversion is exact.
+    pub fn to_planar_f64(&self) -> Vec<Vec<f64>> {
+        let full_scale = self.full_scale();
+        (0..self.channel_count)
+            .map(|channel| {
+                self.pcm_data[..self.sample_length]
+                    .iter()
+                    .map(|sample| f64::from(sample[channel]) / full_scale)
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn full_scale(&self) -> f64 {
+        (1u64 << (self.bit_depth - 1)) as f64
+    }
+
+    /// Removes the first `count` valid samples
+    fn drop_samples(&mut self, count: usize) {
+        self.pcm_data.copy_within(count..self.sample_length, 0);
+        if let Some(gain) = &mut self.heavy_drc_gain {
+            gain.drain(..count);
+        }
+        self.sample_length -= count;
+    }
+}
+
+/// Handling of access units that duplicate the previous access unit.
+///
+/// An access unit is a duplicate when its input timing equals that of the
+/// previous access unit and the lossless check over the samples of the
+/// decoded presentation matches, as when the same access unit is fed twice
+/// at a concatenation join. Access units decoded in skip mode are never
+/// detected as duplicates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateHandling {
+    /// Set [`DecodedAccessUnit::is_duplicate`] so callers discard the access unit.
+    #[default]
+    Skip,
+    /// Return duplicates like any other access unit.
+    Keep,
+    /// Fail with [`DecodeError::DuplicateAccessUnit`].
+    Error,
+}
+
+/// Heavy DRC gain ramp of a substream, in log2 units.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HeavyDrcRamp {
+    /// Set once a restart header carried heavy DRC parameters.
+    pub active: bool,
+    /// Gain of the last sample.
+    pub gain: f32,
+    /// Gain reached at the end of the ramp.
+    pub target: f32,
+    /// Samples left until `target` is reached.
+    pub remaining: usize,
+}
+
+impl HeavyDrcRamp {
+    /// Starts a ramp from the current gain, or from `start_up_gain` when
+    /// inactive, to `target` over `length` samples.
+    pub fn update(&mut self, start_up_gain: f32, target: f32, length: usize) {
+        if !self.active {
+            self.active = true;
+            self.gain = start_up_gain;
+        }
+        self.target = target;
+        self.remaining = length;
+    }
+
+    /// Advances the ramp by one sample and returns its linear gain.
+    pub fn next_gain(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.gain += (self.target - self.gain) / self.remaining as f32;
+            self.remaining -= 1;
+        }
+        self.gain.exp2()
+    }
+}
+
+/// Maximum order of the prediction filters, reached by filter A
+const MAX_ORDER: usize = 8;
+
+/// The last [`MAX_ORDER`] states of a prediction filter.
+///
+/// The states are stored twice so that the latest ones are contiguous
+/// without moving them for every sample.
+struct FilterHistory {
+    buffer: [i32; 2 * MAX_ORDER],
+    position: usize,
+}
+
+impl FilterHistory {
+    fn new(states: &[i32; MAX_ORDER]) -> Self {
+        let mut buffer = [0; 2 * MAX_ORDER];
+        buffer[..MAX_ORDER].copy_from_slice(states);
+        buffer[MAX_ORDER..].copy_from_slice(states);
+        Self {
+            buffer,
+            position: 0,
+        }
+    }
+
+    /// The states, most recent first
+    fn states(&self) -> &[i32; MAX_ORDER] {
+        self.buffer[self.position..][..MAX_ORDER]
+            .try_into()
+            .unwrap()
+    }
+
+    fn push(&mut self, state: i32) {
+        self.position = (self.position + MAX_ORDER - 1) % MAX_ORDER;
+        self.buffer[self.position] = state;
+        self.buffer[self.position + MAX_ORDER] = state;
+    }
+
+    /// Prediction of the first `order` taps of `coeff` but the first,
+    /// before the coefficient shift
+    #[inline(always)]
+    fn predict_older(&self, coeff: &[i32; MAX_ORDER], order: usize) -> i64 {
+        coeff
+            .iter()
+            .zip(self.states())
+            .take(order)
+            .skip(1)
+            .map(|(&coeff, &state)| coeff as i64 * state as i64)
+            .sum()
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -140,13 +923,15 @@ pub struct DecoderSubstreamState {
 
     pub order: [[usize; 16]; 2],
     pub coeff_q: [[i32; 16]; 2],
-    pub coeff: [[[i32; 8]; 16]; 2],
-    pub coeff_state: [[[i32; 8]; 16]; 2],
+    pub coeff: [[[i32; MAX_ORDER]; 16]; 2],
+    pub coeff_state: [[[i32; MAX_ORDER]; 16]; 2],
 
     pub bypassed_lsb: [[i32; 16]; 160],
     pub block_data: [[i32; 16]; 160],
     pub dither_table: [i32; 256],
     pub decoded_sample_len: usize,
+
+    pub heavy_drc: HeavyDrcRamp,
 }
 
 impl Default for DecoderSubstreamState {
@@ -180,38 +965,171 @@ impl Default for DecoderSubstreamState {
 
             order: [[0; 16]; 2],
             coeff_q: [[0; 16]; 2],
-            coeff: [[[0; 8]; 16]; 2],
-            coeff_state: [[[0; 8]; 16]; 2],
+            coeff: [[[0; MAX_ORDER]; 16]; 2],
+            coeff_state: [[[0; MAX_ORDER]; 16]; 2],
 
             bypassed_lsb: [[0; 16]; 160],
             block_data: [[0; 16]; 160],
             dither_table: [0; 256],
             decoded_sample_len: 0,
+
+            heavy_drc: HeavyDrcRamp::default(),
         }
     }
 }
 
-#[derive(Debug)]
+impl DecoderSubstreamState {
+    /// Precision of the output samples in bits
+    fn bit_depth(&self) -> u32 {
+        if self.restart_sync_word == 0x31EC {
+            32
+        } else {
+            24
+        }
+    }
+
+    /// Reconstructs channel `chi` of the block in `block_data` with its
+    /// prediction filters, writing the samples to `rematrix_buffer`.
+    fn recorrelate(&mut self, chi: usize, rematrix_buffer: &mut [[i32; 16]]) -> Result<()> {
+        let (max_val, min_val) = if self.restart_sync_word == 0x31EC {
+            (1 << 31, -(1 << 31))
+        } else {
+            (1 << 23, -(1 << 23))
+        };
+
+        let fir_order = self.order[0][chi];
+        let iir_order = self.order[1][chi];
+        let fir_coeff = &self.coeff[0][chi];
+        let iir_coeff = &self.coeff[1][chi];
+        let coeff_q_shift = self.coeff_q[0][chi];
+        let quantiser_mask = !((1 << self.quantiser_step_size[chi]) - 1);
+
+        let mut fir_history = FilterHistory::new(&self.coeff_state[0][chi]);
+        let mut iir_history = FilterHistory::new(&self.coeff_state[1][chi]);
+
+        // The taps of the latest states, produced by the previous sample, are
+        // added last so that the older taps are summed while that sample is
+        // still being computed
+        let fir_latest_coeff = if fir_order > 0 {
+            fir_coeff[0] as i64
+        } else {
+            0
+        };
+        let iir_latest_coeff = if iir_order > 0 {
+            iir_coeff[0] as i64
+        } else {
+            0
+        };
+        let mut fir_latest = self.coeff_state[0][chi][0] as i64;
+        let mut iir_latest = self.coeff_state[1][chi][0] as i64;
+
+        for (block_data, rematrix_buffer) in self.block_data.iter().zip(rematrix_buffer) {
+            let audio_data = block_data[chi] as i64;
+
+            let acc = fir_history.predict_older(fir_coeff, fir_order)
+                + iir_history.predict_older(iir_coeff, iir_order)
+                + fir_latest_coeff * fir_latest
+                + iir_latest_coeff * iir_latest;
+
+            let pred = acc >> coeff_q_shift;
+            let fir_state = audio_data + (pred & quantiser_mask);
+            let iir_state = fir_state - pred;
+
+            if fir_state >= max_val {
+                bail!(DecodeError::RecorrelatorPositiveSaturation(fir_state));
+            } else if fir_state < min_val {
+                bail!(DecodeError::RecorrelatorNegativeSaturation(fir_state));
+            }
+
+            if !(min_val..max_val).contains(&iir_state) {
+                if self.restart_sync_word == 0x31EC {
+                    bail!(DecodeError::FilterBInputTooWide32(iir_state));
+                } else {
+                    bail!(DecodeError::FilterBInputTooWide24(iir_state));
+                }
+            }
+
+            fir_history.push(fir_state as i32);
+            iir_history.push(iir_state as i32);
+            fir_latest = fir_state;
+            iir_latest = iir_state;
+
+            rematrix_buffer[chi] = fir_state as i32;
+        }
+
+        self.coeff_state[0][chi] = *fir_history.states();
+        self.coeff_state[1][chi] = *iir_history.states();
+
+        Ok(())
+    }
+}
+
+/// Output of a presentation decoded below the highest one with
+/// [`Decoder::decode_presentations`]
+#[derive(Debug, Clone)]
+pub struct PresentationOutput {
+    pub output_buffer: Box<[[i32; 16]; 160]>,
+    pub channel_labels: Vec<ChannelLabel>,
+    /// Channel count of the last access unit, 0 before the first
+    pub channel_count: usize,
+    pub config_changed: bool,
+    pub zero_samples: usize,
+    pub heavy_drc_gain: Option<Vec<f32>>,
+}
+
+impl Default for PresentationOutput {
+    fn default() -> Self {
+        Self {
+            output_buffer: Box::new([[0; 16]; 160]),
+            channel_labels: vec![],
+            channel_count: 0,
+            config_changed: false,
+            zero_samples: 0,
+            heavy_drc_gain: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 #[repr(C)]
 pub struct DecoderState {
     pub fail_level: log::Level,
+    pub category_policy: CategoryPolicy,
+    pub event_sink: Option<EventSink>,
 
     pub valid: bool,
     pub counter: usize,
     pub has_valid_branch: bool,
     pub has_duplicate_timing: bool,
     pub has_duplicate_sample: bool,
+    pub prev_input_timing: Option<u16>,
+    pub duplicate_handling: DuplicateHandling,
 
     pub sampling_frequency: u32,
     pub samples_per_au: usize,
 
     pub presentation_map: Option<PresentationMap>,
     pub presentation: usize,
+    /// Presentations requested by [`Decoder::decode_presentations`], none
+    /// for a single presentation
+    pub requested_presentations: [bool; MAX_PRESENTATIONS],
+    /// Presentation decoded for each requested presentation
+    pub presentation_targets: [Option<usize>; MAX_PRESENTATIONS],
+    /// Outputs of the requested presentations decoded below `presentation`
+    pub extra_outputs: [Option<Box<PresentationOutput>>; MAX_PRESENTATIONS],
 
     pub channel_labels: Vec<ChannelLabel>,
+    /// Channel count of the presentation decoded last, 0 before the first
+    pub channel_count: usize,
+    pub config_changed: bool,
 
     pub substreams: usize,
     pub substream_mask: u8,
+    /// Substream mask replacing the one required by the presentation
+    pub substream_mask_override: Option<u8>,
+    /// `substream_mask` holds the override instead of the derived mask
+    pub substream_mask_overridden: bool,
+    pub format_sync: u32,
     pub substream_info: u8,
     pub extended_substream_info: u8,
 
@@ -220,41 +1138,91 @@ pub struct DecoderState {
 
     pub rematrix_buffer: [[i32; 16]; 160],
     pub output_buffer: [[i32; 16]; 160],
+    /// Output samples before truncation by negative output shifts, kept once
+    /// [`Decoder::decode_presentation_f64`] is used
+    pub output_buffer_f64: Option<Box<[[f64; 16]; 160]>>,
     pub zero_samples: usize,
+    pub silence_samples: usize,
+    pub pending_drop: usize,
+    pub discontinuity: Option<i64>,
+    pub au_index: u64,
+    pub sample_position: u64,
     pub oamd: VecDeque<ObjectAudioMetadataPayload>,
     pub substream_info_changed: bool,
+
+    /// Heavy DRC start-up gain of the last major sync, in log2 units
+    pub heavy_drc_start_up_gain: f32,
+    pub heavy_drc_gain: Option<Vec<f32>>,
+    /// Dialogue level of each presentation from the last major sync, in dBFS
+    pub dialogue_level: [Option<i8>; MAX_PRESENTATIONS],
+
+    pub skip_decode: bool,
+    pub lossless_check_unverified: bool,
+
+    pub stats: DecodeStats,
 }
 
 impl Default for DecoderState {
     fn default() -> Self {
         Self {
             fail_level: log::Level::Error,
+            category_policy: CategoryPolicy::default(),
+            event_sink: None,
             valid: false,
             counter: 0,
             has_valid_branch: false,
             has_duplicate_timing: false,
             has_duplicate_sample: false,
+            prev_input_timing: None,
+            duplicate_handling: DuplicateHandling::Skip,
             sampling_frequency: 0,
             samples_per_au: 0,
             presentation_map: None,
             presentation: 0,
+            requested_presentations: [false; MAX_PRESENTATIONS],
+            presentation_targets: [None; MAX_PRESENTATIONS],
+            extra_outputs: Default::default(),
             channel_labels: vec![],
+            channel_count: 0,
+            config_changed: false,
             substreams: 0,
             substream_mask: 0,
+            substream_mask_override: None,
+            substream_mask_overridden: false,
+            format_sync: 0,
             substream_info: 0,
             extended_substream_info: 0,
             substream_index: 0,
             substream_state: [DecoderSubstreamState::default(); MAX_PRESENTATIONS],
             rematrix_buffer: [[0; 16]; 160],
             output_buffer: [[0; 16]; 160],
+            output_buffer_f64: None,
             zero_samples: 0,
+            silence_samples: 0,
+            pending_drop: 0,
+            discontinuity: None,
+            au_index: 0,
+            sample_position: 0,
             oamd: VecDeque::with_capacity(4),
             substream_info_changed: false,
+            heavy_drc_start_up_gain: 0.0,
+            heavy_drc_gain: None,
+            dialogue_level: [None; MAX_PRESENTATIONS],
+            skip_decode: false,
+            lossless_check_unverified: false,
+            stats: DecodeStats::default(),
         }
     }
 }
 
 impl DecoderState {
+    /// Passes `event` to the event sink, if any.
+    pub fn emit(&self, event: Event) {
+        if let Some(sink) = &self.event_sink {
+            sink.emit(event);
+        }
+    }
+
     pub fn substream_state_mut(&mut self) -> Result<&mut DecoderSubstreamState> {
         Ok(&mut self.substream_state[self.substream_index])
     }
@@ -262,22 +1230,54 @@ impl DecoderState {
     pub fn substream_state(&self) -> Result<&DecoderSubstreamState> {
         Ok(&self.substream_state[self.substream_index])
     }
+
+    /// Returns `true` when the current substream is that of the decoded
+    /// presentation or of a presentation decoded below it.
+    pub fn is_output_substream(&self) -> bool {
+        self.substream_index == self.presentation
+            || self.extra_outputs[self.substream_index].is_some()
+    }
+
     pub fn decode_access_unit(
         &mut self,
         access_unit: &AccessUnit,
         presentation: usize,
     ) -> Result<()> {
+        self.stats.begin_access_unit();
+        self.silence_samples = 0;
+        self.discontinuity = None;
         access_unit.update_decoder_state(self)?;
-
-        if !self.valid {
-            self.update_presentation(presentation)?;
+        self.check_substreams(access_unit)?;
+
+        let targets_changed = self
+            .requested_presentations
+            .iter()
+            .zip(&self.presentation_targets)
+            .any(|(&requested, target)| requested != target.is_some());
+        if !self.valid
+            || self.substream_mask_overridden && self.substream_mask_override.is_none()
+            || targets_changed
+        {
+            if self.requested_presentations.contains(&true) {
+                self.update_presentations(access_unit)?;
+            } else {
+                self.update_presentation(presentation)?;
+                self.presentation_targets = [None; MAX_PRESENTATIONS];
+                self.extra_outputs = Default::default();
+            }
             self.channel_labels = access_unit
                 .get_channel_labels(self.presentation)
                 .unwrap_or_default();
         }
 
-        self.has_duplicate_timing = false;
+        if let Some(mask) = self.substream_mask_override {
+            self.override_substream_mask(mask)?;
+        }
+
+        self.has_duplicate_timing = self.prev_input_timing == Some(access_unit.input_timing);
+        self.prev_input_timing = Some(access_unit.input_timing);
         self.has_duplicate_sample = false;
+        self.zero_samples = 0;
         self.oamd.clear();
 
         for i in 0..=self.presentation {
@@ -285,28 +1285,20 @@ impl DecoderState {
                 continue;
             }
 
+            // The highest decoded substream carrying a terminator trims the
+            // access unit, whichever presentation was requested
             let substream_segment = &access_unit.substream_segment[i];
-            if i == presentation
-                && let Some(terminator) = &substream_segment.terminator
+            if let Some(terminator) = &substream_segment.terminator
                 && terminator.zero_samples_indicated
             {
                 self.zero_samples = terminator.zero_samples as usize;
             }
+            if let Some(output) = &mut self.extra_outputs[i] {
+                output.zero_samples = self.zero_samples;
+            }
 
-            if i == 3
-                && let Some(extra_data) = &access_unit.extra_data
-                && let Some(evo_frame) = &extra_data.evo_frame
-            {
-                for evo_payload in &evo_frame.evo_payloads {
-                    if evo_payload.evo_payload_id == 11 {
-                        let smploffst =
-                            evo_payload.evo_payload_config.smploffst.unwrap_or_default() as u64;
-                        let mut oamd =
-                            ObjectAudioMetadataPayload::read(&evo_payload.evo_payload_byte)?;
-                        oamd.evo_sample_offset = smploffst;
-                        self.oamd.push_back(oamd);
-                    }
-                }
+            if i == 3 {
+                self.read_oamd(access_unit)?;
             }
 
             self.substream_index = i;
@@ -315,16 +1307,131 @@ impl DecoderState {
 
             for block in substream_segment.block.iter() {
                 block.update_decoder_state(self)?;
-                self.decode()?;
+
+                if self.skip_decode {
+                    let ss_state = &mut self.substream_state[self.substream_index];
+                    ss_state.decoded_sample_len += ss_state.block_size;
+                } else if let Err(e) = self.decode_block() {
+                    if matches!(
+                        e.downcast_ref::<DecodeError>(),
+                        Some(
+                            DecodeError::RecorrelatorPositiveSaturation(_)
+                                | DecodeError::RecorrelatorNegativeSaturation(_)
+                        )
+                    ) {
+                        self.stats
+                            .record_recorrelator_saturation(self.substream_index);
+                    }
+                    return Err(e);
+                }
             }
         }
 
+        // A restart header may change the channel count without a major sync
+        // change, as at a seamless branch into another segment
+        let channel_count = self.substream_state[self.presentation].max_matrix_chan + 1;
+        self.config_changed = self.channel_count != 0 && channel_count != self.channel_count;
+        if self.config_changed {
+            log::info!(
+                "AU {}: channel count changed from {} to {channel_count}",
+                self.counter,
+                self.channel_count
+            );
+            self.channel_labels =
+                changed_channel_labels(access_unit, self.presentation, channel_count);
+        }
+        self.channel_count = channel_count;
+
+        let samples_per_au = self.samples_per_au;
+        let ramp = &mut self.substream_state[self.presentation].heavy_drc;
+        self.heavy_drc_gain = ramp
+            .active
+            .then(|| (0..samples_per_au).map(|_| ramp.next_gain()).collect());
+
+        for (presentation, output) in self.extra_outputs.iter_mut().enumerate() {
+            let Some(output) = output else {
+                continue;
+            };
+            let ss_state = &mut self.substream_state[presentation];
+            let channel_count = ss_state.max_matrix_chan + 1;
+            output.config_changed =
+                output.channel_count != 0 && channel_count != output.channel_count;
+            if output.config_changed {
+                log::info!(
+                    "AU {}: channel count of presentation {presentation} changed from {} to {channel_count}",
+                    self.counter,
+                    output.channel_count
+                );
+                output.channel_labels =
+                    changed_channel_labels(access_unit, presentation, channel_count);
+            }
+            output.channel_count = channel_count;
+
+            let ramp = &mut ss_state.heavy_drc;
+            output.heavy_drc_gain = ramp
+                .active
+                .then(|| (0..samples_per_au).map(|_| ramp.next_gain()).collect());
+        }
+
         self.valid = true;
         self.counter += 1;
 
         Ok(())
     }
 
+    /// Limits the presentation map to the substreams of the access unit, for
+    /// streams advertising presentations of substreams they do not carry.
+    fn check_substreams(&mut self, access_unit: &AccessUnit) -> Result<()> {
+        let Some(presentation_map) = self.presentation_map else {
+            return Ok(());
+        };
+
+        let advertised = presentation_map.required_substreams();
+        let present = access_unit.substreams;
+        if advertised <= present {
+            return Ok(());
+        }
+
+        // Reported once per stream, the map is restored at every major sync
+        if !self.valid {
+            log_or_err!(
+                self,
+                Category::Structure,
+                log::Level::Warn,
+                anyhow!(ParseError::MissingSubstream {
+                    advertised,
+                    present
+                })
+            );
+        }
+        self.presentation_map = Some(presentation_map.limited_to(present));
+        Ok(())
+    }
+
+    /// Reads the OAMD payloads of an access unit, skipping those that fail
+    /// to parse so the audio is still decoded.
+    fn read_oamd(&mut self, access_unit: &AccessUnit) -> Result<()> {
+        for evo_payload in oamd_evo_payloads(access_unit) {
+            match read_oamd_payload(evo_payload) {
+                Ok(oamd) => self.oamd.push_back(oamd),
+                Err(err) => {
+                    self.stats.skipped_oamd_payloads += 1;
+                    log_or_err!(
+                        self,
+                        Category::Metadata,
+                        log::Level::Warn,
+                        anyhow!(DecodeError::OamdPayloadSkipped {
+                            au: self.counter,
+                            length: evo_payload.evo_payload_byte.len(),
+                            reason: format!("{err:#}"),
+                        })
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn update_presentation(&mut self, presentation: usize) -> Result<()> {
         let Some(presentation_map) = self.presentation_map else {
             bail!("Presentation map not initialized");
@@ -337,42 +1444,149 @@ impl DecoderState {
 
         self.substream_mask =
             presentation_map.substream_mask_by_required_presentations(&presentations);
-        match presentation_map.presentation_type_by_index(presentation) {
-            PresentationType::Invalid => {
-                if !self.valid {
+        self.substream_mask_overridden = false;
+        self.presentation = self.presentation_target(&presentation_map, presentation)?;
+
+        Ok(())
+    }
+
+    /// Returns the presentation decoded for a request of `presentation`.
+    fn presentation_target(
+        &self,
+        presentation_map: &PresentationMap,
+        presentation: usize,
+    ) -> Result<usize> {
+        Ok(
+            match presentation_map.presentation_type_by_index(presentation) {
+                PresentationType::Invalid => {
                     let Some(max_independent) = presentation_map.max_independent_presentation()
                     else {
                         bail!("No presentation is available");
                     };
-                    info!(
-                        "Presentation {presentation} is not available, using presentation {max_independent}"
-                    );
-                    self.presentation = max_independent;
+                    if !self.valid {
+                        info!(
+                            "Presentation {presentation} is not available, using presentation {max_independent}"
+                        );
+                    }
+                    max_independent
                 }
-            }
-            PresentationType::CopyOf(copy_index) => {
-                if !self.valid {
-                    info!("Presentation {presentation} is a copy of presentation {copy_index}")
+                PresentationType::CopyOf(copy_index) => {
+                    if !self.valid {
+                        info!("Presentation {presentation} is a copy of presentation {copy_index}")
+                    }
+                    copy_index
                 }
-                self.presentation = copy_index;
+                _ => presentation,
+            },
+        )
+    }
+
+    /// Resolves the presentations requested by
+    /// [`Decoder::decode_presentations`], decoding up to the highest
+    /// presentation they resolve to.
+    fn update_presentations(&mut self, access_unit: &AccessUnit) -> Result<()> {
+        let Some(presentation_map) = self.presentation_map else {
+            bail!("Presentation map not initialized");
+        };
+
+        let mut targets = [None; MAX_PRESENTATIONS];
+        for (presentation, target) in targets.iter_mut().enumerate() {
+            if self.requested_presentations[presentation] {
+                *target = Some(self.presentation_target(&presentation_map, presentation)?);
             }
-            _ => {
-                self.presentation = presentation;
+        }
+        let Some(highest) = targets.iter().flatten().copied().max() else {
+            bail!("No presentation requested");
+        };
+        self.update_presentation(highest)?;
+
+        for (presentation, output) in self.extra_outputs.iter_mut().enumerate() {
+            if presentation < highest && targets.contains(&Some(presentation)) {
+                output.get_or_insert_default().channel_labels = access_unit
+                    .get_channel_labels(presentation)
+                    .unwrap_or_default();
+            } else {
+                *output = None;
             }
+        }
+
+        // The accumulated lossless checks of newly decoded presentations are incomplete
+        if self.valid && targets != self.presentation_targets {
+            self.lossless_check_unverified = true;
+        }
+        self.presentation_targets = targets;
+
+        Ok(())
+    }
+
+    /// Replaces the substream mask after checking it against the dependencies
+    /// of the decoded presentation.
+    fn override_substream_mask(&mut self, mask: u8) -> Result<()> {
+        let Some(presentation_map) = self.presentation_map else {
+            bail!("Presentation map not initialized");
         };
 
+        let mut presentations = [false; MAX_PRESENTATIONS];
+        presentations[..=self.presentation]
+            .iter_mut()
+            .for_each(|p| *p = true);
+
+        let required = presentation_map.masks[self.presentation];
+        let available = presentation_map.substream_mask_by_required_presentations(&presentations);
+        if mask & required != required || mask & !available != 0 {
+            bail!(DecodeError::InvalidSubstreamMask {
+                mask,
+                presentation: self.presentation,
+                required,
+                available,
+            });
+        }
+
+        self.substream_mask = mask;
+        self.substream_mask_overridden = true;
+
         Ok(())
     }
 
+    /// Flushes all substream state so the next access unit is decoded as the
+    /// start of a new stream, re-deriving the presentation and channel labels.
+    pub fn reset_for_stream_restart(&mut self) {
+        self.valid = false;
+        self.substream_state = [DecoderSubstreamState::default(); MAX_PRESENTATIONS];
+    }
+
+    /// Restarts decoding at a repaired branch and schedules the samples to
+    /// insert (positive `gap`) or drop (negative `gap`).
+    pub fn reset_for_branch_repair(&mut self, gap: isize) {
+        self.reset_for_stream_restart();
+        self.stats.repaired_branches += 1;
+        self.discontinuity = (gap != 0).then_some(gap as i64);
+
+        if gap > 0 {
+            self.silence_samples = gap as usize;
+            self.stats.inserted_samples += gap as u64;
+        } else {
+            self.pending_drop += gap.unsigned_abs();
+        }
+    }
+
     pub fn reset_decoder_substream_state(&mut self) {
         let ss_state = &mut self.substream_state[self.substream_index];
         *ss_state = DecoderSubstreamState {
             lossless_check_i32_prev_au: ss_state.lossless_check_i32_prev_au,
+            heavy_drc: ss_state.heavy_drc,
             ..Default::default()
         }
     }
 
-    fn decode(&mut self) -> Result<()> {
+    /// Decodes the block in `block_data` of substream `substream_index`,
+    /// appending its samples at `decoded_sample_len`.
+    ///
+    /// The lossless matrix and output remapping are applied when the
+    /// substream is the one of the decoded presentation, or of a
+    /// presentation decoded below it. The latter are applied to a copy of
+    /// the recorrelated channels, which the higher substreams continue from.
+    pub fn decode_block(&mut self) -> Result<()> {
         let DecoderSubstreamState {
             restart_sync_word,
             min_chan,
@@ -391,10 +1605,6 @@ impl DecoderState {
 
             output_shift,
             quantiser_step_size,
-
-            order,
-            coeff,
-            coeff_q,
             ..
         } = *self.substream_state()?;
 
@@ -402,84 +1612,33 @@ impl DecoderState {
 
         let ss_state = &mut self.substream_state[self.substream_index];
 
-        let decoded_sample_len = &mut ss_state.decoded_sample_len;
-        let dither_seed = &mut ss_state.dither_seed;
-        let bypassed_lsb = &mut ss_state.bypassed_lsb;
-        let coeff_state = &mut ss_state.coeff_state;
-        let m_coeff = &mut ss_state.m_coeff;
-
-        let (max_val, min_val) = if restart_sync_word == 0x31EC {
-            (1 << 31, -(1 << 31))
-        } else {
-            (1 << 23, -(1 << 23))
-        };
-
         // recorrelation
         {
-            let block_data = &ss_state.block_data;
-            let rematrix_buffer = &mut self.rematrix_buffer[*decoded_sample_len..];
+            let rematrix_buffer =
+                &mut self.rematrix_buffer[ss_state.decoded_sample_len..][..block_size];
 
-            #[allow(clippy::needless_range_loop)]
             for chi in min_chan..=max_chan {
-                let mut state_buffer = [[0; 168]; 2];
-
-                state_buffer[0][160..].copy_from_slice(&coeff_state[0][chi]);
-                state_buffer[1][160..].copy_from_slice(&coeff_state[1][chi]);
-
-                let fir_order = order[0][chi];
-                let iir_order = order[1][chi];
-                let coeff_q_shift = coeff_q[0][chi];
-                let quantiser_mask = !((1 << quantiser_step_size[chi]) - 1);
-                let fir_coeff = &coeff[0][chi];
-                let iir_coeff = &coeff[1][chi];
-
-                for blki in 0..block_size {
-                    let audio_data = block_data[blki][chi] as i64;
-                    let state_base = 160 - blki;
-
-                    let mut acc = 0i64;
-
-                    for oi in 0..fir_order {
-                        acc += (fir_coeff[oi] as i64) * (state_buffer[0][state_base + oi] as i64);
-                    }
-
-                    for oi in 0..iir_order {
-                        acc += (iir_coeff[oi] as i64) * (state_buffer[1][state_base + oi] as i64);
-                    }
-
-                    let pred = acc >> coeff_q_shift;
-                    let fir_state = audio_data + (pred & quantiser_mask);
-                    let iir_state = fir_state - pred;
-
-                    if fir_state >= max_val {
-                        bail!(DecodeError::RecorrelatorPositiveSaturation(fir_state));
-                    } else if fir_state < min_val {
-                        bail!(DecodeError::RecorrelatorNegativeSaturation(fir_state));
-                    }
-
-                    if !(min_val..max_val).contains(&iir_state) {
-                        if restart_sync_word == 0x31EC {
-                            bail!(DecodeError::FilterBInputTooWide32(iir_state));
-                        } else {
-                            bail!(DecodeError::FilterBInputTooWide24(iir_state));
-                        }
-                    }
-
-                    state_buffer[0][159 - blki] = fir_state as i32;
-                    state_buffer[1][159 - blki] = iir_state as i32;
-
-                    rematrix_buffer[blki][chi] = fir_state as i32;
-                }
-
-                coeff_state[0][chi][..].copy_from_slice(&state_buffer[0][160 - block_size..][..8]);
-                coeff_state[1][chi][..].copy_from_slice(&state_buffer[1][160 - block_size..][..8]);
+                ss_state.recorrelate(chi, rematrix_buffer)?;
             }
         }
 
+        let decoded_sample_len = &mut ss_state.decoded_sample_len;
+        let dither_seed = &mut ss_state.dither_seed;
+        let bypassed_lsb = &mut ss_state.bypassed_lsb;
+        let m_coeff = &mut ss_state.m_coeff;
+
+        let is_presentation = self.substream_index == self.presentation;
+        let mut extra_output = self.extra_outputs[self.substream_index].as_deref_mut();
+
         // lossless matrix
-        if self.substream_index == self.presentation {
+        if is_presentation || extra_output.is_some() {
             let dither_table = &mut ss_state.dither_table;
             let rematrix_buffer = &mut self.rematrix_buffer[*decoded_sample_len..];
+            let recorrelated = extra_output.is_some().then(|| {
+                let mut recorrelated = [[0; 16]; 160];
+                recorrelated[..block_size].copy_from_slice(&rematrix_buffer[..block_size]);
+                recorrelated
+            });
 
             match restart_sync_word {
                 0x31EA => {
@@ -609,7 +1768,16 @@ impl DecoderState {
 
             // remap
             {
-                let output_buffer = &mut self.output_buffer[*decoded_sample_len..];
+                let (output_buffer, output_buffer_f64) = match &mut extra_output {
+                    Some(output) => (&mut output.output_buffer[..], None),
+                    None => (
+                        &mut self.output_buffer[..],
+                        self.output_buffer_f64.as_deref_mut(),
+                    ),
+                };
+                let output_buffer = &mut output_buffer[*decoded_sample_len..];
+                let mut output_buffer_f64 =
+                    output_buffer_f64.map(|output| &mut output[*decoded_sample_len..]);
 
                 if *decoded_sample_len == 0 {
                     ss_state.lossless_check_i32 = 0;
@@ -620,6 +1788,7 @@ impl DecoderState {
                 for blki in 0..block_size {
                     let sample = rematrix_buffer[blki];
                     let mut output = [0; 16];
+                    let mut output_f64 = [0.0; 16];
 
                     for chi in 0..=max_matrix_chan {
                         let ch_assign = ch_assign[chi];
@@ -634,10 +1803,27 @@ impl DecoderState {
                             *output <<= output_shift;
                         }
 
-                        lossless_check_data ^= (*output & 0xFFFFFF) << (chi & 7);
+                        if output_buffer_f64.is_some() {
+                            output_f64[ch_assign] = if output_shift < 0 {
+                                f64::from(sample[chi]) * 2f64.powi(output_shift.into())
+                            } else {
+                                f64::from(*output)
+                            };
+                        }
+
+                        // 32-bit samples are rotated so that no bit drops out
+                        let check_data = if restart_sync_word == 0x31EC {
+                            *output as u32
+                        } else {
+                            *output as u32 & 0xFFFFFF
+                        };
+                        lossless_check_data ^= check_data.rotate_left(chi as u32 & 7) as i32;
                     }
 
                     output_buffer[blki] = output;
+                    if let Some(output_buffer_f64) = &mut output_buffer_f64 {
+                        output_buffer_f64[blki] = output_f64;
+                    }
                 }
 
                 ss_state.lossless_check_i32 ^= lossless_check_data;
@@ -652,18 +1838,23 @@ impl DecoderState {
                     );
 
                     if self.has_duplicate_timing
+                        && is_presentation
                         && ss_state.lossless_check_i32 == ss_state.lossless_check_i32_prev_au
                     {
                         self.has_duplicate_sample = true;
-                        info!(
-                            "AU {}: duplicate samples at branch, should be discarded",
-                            self.counter
-                        );
+                        info!("AU {}: duplicate of the previous access unit", self.counter);
+                        if let Some(sink) = &self.event_sink {
+                            sink.emit(Event::DuplicateAccessUnit { au: self.counter });
+                        }
                     }
 
                     ss_state.lossless_check_i32_prev_au = ss_state.lossless_check_i32;
                 }
             }
+
+            if let Some(recorrelated) = recorrelated {
+                rematrix_buffer[..block_size].copy_from_slice(&recorrelated[..block_size]);
+            }
         }
 
         *decoded_sample_len += block_size;
@@ -671,3 +1862,887 @@ impl DecoderState {
         Ok(())
     }
 }
+
+/// Channel labels of `presentation` after a change to `channel_count`
+/// channels, generic labels if the major sync does not describe them
+fn changed_channel_labels(
+    access_unit: &AccessUnit,
+    presentation: usize,
+    channel_count: usize,
+) -> Vec<ChannelLabel> {
+    access_unit
+        .get_channel_labels(presentation)
+        .filter(|labels| labels.len() == channel_count)
+        .unwrap_or_else(|| {
+            (0..channel_count as u8)
+                .map(ChannelLabel::Generic)
+                .collect()
+        })
+}
+
+#[test]
+fn skip_decode_resumes_at_major_sync() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let mut data = EXAMPLE_DATA.to_vec();
+    data.extend_from_slice(EXAMPLE_DATA);
+
+    let decode_all = |skip_until: usize| -> Result<Vec<DecodedAccessUnit>> {
+        let mut extractor = Extractor::default();
+        let mut parser = Parser::default();
+        let mut decoder = Decoder::default();
+        extractor.push_bytes(&data);
+        decoder.set_skip_decode(skip_until > 0);
+
+        let mut decoded = Vec::new();
+        for (index, frame) in extractor.filter_map(Result::ok).enumerate() {
+            if index == skip_until {
+                assert!(frame.is_major_sync());
+                decoder.set_skip_decode(false);
+            }
+            let access_unit = parser.parse(&frame)?;
+            decoded.push(decoder.decode_presentation(&access_unit, 1)?);
+        }
+        Ok(decoded)
+    };
+
+    let full = decode_all(0)?;
+    let skipped = decode_all(2)?;
+    assert_eq!(full.len(), 4);
+    assert_eq!(skipped.len(), 4);
+
+    for (full, skipped) in full.iter().zip(&skipped) {
+        assert_eq!(full.sample_length, skipped.sample_length);
+    }
+    assert!(full[2].pcm_data.iter().flatten().any(|&s| s != 0));
+    assert!(skipped[0].pcm_data.iter().flatten().all(|&s| s == 0));
+    for (full, skipped) in full[2..].iter().zip(&skipped[2..]) {
+        assert_eq!(full.pcm_data, skipped.pcm_data);
+    }
+    Ok(())
+}
+
+#[test]
+fn decode_stats_summary() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    let mut decoder = Decoder::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(2));
+    for frame in extractor.filter_map(Result::ok) {
+        let access_unit = parser.parse(&frame)?;
+        decoder.decode_presentation(&access_unit, 1)?;
+    }
+
+    let mut stats = decoder.stats();
+    assert_eq!(stats.access_units, 4);
+    assert_eq!(stats.failures(), 0);
+    assert_eq!(stats.first_failure_au(), None);
+
+    let mut parser_stats = parser.stats();
+    parser_stats.record_huffman_saturation(0);
+    stats.record_lossless_check_mismatch(1);
+    stats.access_units = 8;
+    stats.record_recorrelator_saturation(1);
+    stats.merge(&parser_stats);
+
+    assert_eq!(stats.access_units, 8);
+    assert_eq!(stats.failures(), 3);
+    assert_eq!(stats.substreams[1].lossless_check_mismatches, 1);
+    assert_eq!(stats.substreams[1].first_failure_au, Some(3));
+    assert_eq!(stats.substreams[1].last_failure_au, Some(7));
+    assert_eq!(stats.first_failure_au(), Some(3));
+    assert_eq!(stats.last_failure_au(), Some(7));
+    Ok(())
+}
+
+#[test]
+fn branch_repair_drops_and_inserts_samples() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(2));
+    let mut access_units = extractor
+        .filter_map(Result::ok)
+        .map(|frame| parser.parse(&frame))
+        .collect::<Result<Vec<_>>>()?;
+
+    let decode_all =
+        |access_units: &[AccessUnit]| -> Result<(Vec<DecodedAccessUnit>, DecodeStats)> {
+            let mut decoder = Decoder::default();
+            let decoded = access_units
+                .iter()
+                .map(|access_unit| decoder.decode_presentation(access_unit, 1))
+                .collect::<Result<Vec<_>>>()?;
+            Ok((decoded, decoder.stats()))
+        };
+    let (full, _) = decode_all(&access_units)?;
+
+    // Overlap spanning more than one access unit
+    access_units[2].branch_repair = Some(-50);
+    let (repaired, stats) = decode_all(&access_units)?;
+    assert_eq!(repaired[2].sample_length, 0);
+    assert_eq!(repaired[3].sample_length, 30);
+    assert_eq!(repaired[3].pcm_data[..30], full[3].pcm_data[10..40]);
+    assert_eq!(repaired[2].discontinuity, Some(-50));
+    assert_eq!(repaired[3].discontinuity, None);
+    // The positions continue without the dropped samples
+    assert_eq!(
+        repaired
+            .iter()
+            .map(|decoded| decoded.sample_position)
+            .collect::<Vec<_>>(),
+        [0, 40, 80, 80]
+    );
+    assert_eq!(stats.repaired_branches, 1);
+    assert_eq!(stats.dropped_samples, 50);
+
+    access_units[2].branch_repair = Some(25);
+    let (repaired, stats) = decode_all(&access_units)?;
+    assert_eq!(repaired[2].silence_samples, 25);
+    assert_eq!(repaired[2].pcm_data, full[2].pcm_data);
+    assert_eq!(repaired[3].silence_samples, 0);
+    assert_eq!(repaired[2].discontinuity, Some(25));
+    assert_eq!(
+        repaired
+            .iter()
+            .map(|decoded| (decoded.au_index, decoded.sample_position))
+            .collect::<Vec<_>>(),
+        [(0, 0), (1, 40), (2, 105), (3, 145)]
+    );
+    assert_eq!(stats.inserted_samples, 25);
+    assert_eq!(stats.dropped_samples, 0);
+    Ok(())
+}
+
+#[test]
+fn heavy_drc_ramp() {
+    let mut ramp = HeavyDrcRamp::default();
+
+    // Start-up gain -6 dB ramping to -12 dB over 4 samples
+    ramp.update(-1.0, -2.0, 4);
+    let gains = (0..6).map(|_| ramp.next_gain()).collect::<Vec<_>>();
+    assert_eq!(
+        gains,
+        [
+            (-1.25f32).exp2(),
+            (-1.5f32).exp2(),
+            (-1.75f32).exp2(),
+            0.25,
+            0.25,
+            0.25
+        ]
+    );
+
+    // Later updates ramp from the current gain
+    ramp.update(-1.0, 0.0, 2);
+    assert_eq!(ramp.next_gain(), 0.5);
+    assert_eq!(ramp.next_gain(), 1.0);
+    assert_eq!(ramp.next_gain(), 1.0);
+}
+
+#[test]
+fn heavy_drc_gain_of_decoded_access_units() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(2));
+    let mut access_units = extractor
+        .filter_map(Result::ok)
+        .map(|frame| parser.parse(&frame))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut decoder = Decoder::default();
+    assert!(
+        decoder
+            .decode_presentation(&access_units[0], 1)?
+            .heavy_drc_gain
+            .is_none()
+    );
+
+    // Start-up gain -6 dB, ramping to -12 dB over one access unit of 40 samples
+    let major_sync_info = access_units[0].major_sync_info.as_mut().unwrap();
+    major_sync_info.channel_meaning.heavy_drc_start_up_gain = -4;
+    let restart_header = access_units[0].substream_segment[0].block[0]
+        .restart_header
+        .as_mut()
+        .unwrap();
+    restart_header.heavy_drc_present = true;
+    restart_header.heavy_drc_gain_update = -64;
+    restart_header.heavy_drc_time_update = 0;
+
+    let mut decoder = Decoder::default();
+    let gains = access_units
+        .iter()
+        .map(|access_unit| {
+            let decoded = decoder.decode_presentation(access_unit, 1)?;
+            Ok(decoded.heavy_drc_gain.unwrap())
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for (i, &gain) in gains[0].iter().enumerate() {
+        let expected = (-1.0 - (i + 1) as f32 / 40.0).exp2();
+        assert!((gain - expected).abs() < 1e-6, "sample {i}: {gain}");
+    }
+    assert!((gains[0][19] - 0.353_553_4).abs() < 1e-6);
+    assert!(gains[1..].iter().flatten().all(|&gain| gain == 0.25));
+    assert_eq!(gains.iter().map(Vec::len).sum::<usize>(), 160);
+    Ok(())
+}
+
+#[test]
+fn decode_with_substream_mask() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    let access_units = extractor
+        .filter_map(Result::ok)
+        .map(|frame| parser.parse(&frame))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut decoder = Decoder::default();
+    let mut masked = Decoder::default();
+    for access_unit in &access_units {
+        let expected = decoder.decode_presentation(access_unit, 1)?;
+        let decoded = masked.decode_with_substream_mask(access_unit, 0b0001, 1)?;
+        assert_eq!(decoded.sample_length, expected.sample_length);
+        assert_eq!(*decoded.pcm_data, *expected.pcm_data);
+    }
+
+    // Without the substream of the presentation, and with a substream beyond it
+    for mask in [0b0000, 0b0011] {
+        let err = Decoder::default()
+            .decode_with_substream_mask(&access_units[0], mask, 1)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(DecodeError::InvalidSubstreamMask {
+                mask: m,
+                presentation: 0,
+                required: 0b0001,
+                available: 0b0001,
+            }) if *m == mask
+        ));
+    }
+
+    // Switching back re-derives the mask of the presentation
+    masked.decode_presentation(&access_units[0], 1)?;
+    assert!(!masked.state.substream_mask_overridden);
+    Ok(())
+}
+
+#[test]
+fn evo_payloads_passed_through() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::metadata::OAMD_PAYLOAD_ID;
+    use crate::process::parse::Parser;
+    use crate::structs::evolution::{EvoFrame, EvoPayload, EvoPayloadConfig};
+    use crate::structs::extra_data::ExtraData;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    let frame = extractor.next().unwrap()?;
+    let mut access_unit = parser.parse(&frame)?;
+    access_unit.extra_data = Some(ExtraData {
+        evo_frame: Some(EvoFrame {
+            evo_payloads: vec![
+                EvoPayload {
+                    evo_payload_id: OAMD_PAYLOAD_ID,
+                    evo_payload_config: EvoPayloadConfig {
+                        smploffst: Some(8),
+                        ..Default::default()
+                    },
+                    evo_payload_byte: vec![0xAA; 4],
+                },
+                EvoPayload {
+                    evo_payload_id: 14,
+                    evo_payload_byte: vec![1, 2, 3],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+
+    let mut decoder = Decoder::default();
+    assert!(
+        decoder
+            .decode_presentation(&access_unit, 1)?
+            .evo_payloads
+            .is_empty()
+    );
+
+    // Payloads are kept even though OAMD is only parsed for presentation 3
+    let mut decoder = Decoder::default();
+    decoder.set_keep_evo_payloads(true);
+    let decoded = decoder.decode_presentation(&access_unit, 1)?;
+    assert!(decoded.oamd.is_empty());
+    assert_eq!(
+        decoded.evo_payloads,
+        [
+            RawEvoPayload {
+                id: OAMD_PAYLOAD_ID,
+                sample_offset: 8,
+                data: vec![0xAA; 4],
+            },
+            RawEvoPayload {
+                id: 14,
+                sample_offset: 0,
+                data: vec![1, 2, 3],
+            },
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn duplicate_access_unit_handling() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(2));
+    let access_units = extractor
+        .filter_map(Result::ok)
+        .map(|frame| parser.parse(&frame))
+        .collect::<Result<Vec<_>>>()?;
+
+    // The first access unit fed again, as at a concatenation join
+    let joined = [&access_units[0], &access_units[0], &access_units[1]];
+
+    let decode = |handling| -> Result<(Vec<bool>, DecodeStats)> {
+        let mut decoder = Decoder::default();
+        decoder.set_duplicate_handling(handling);
+        let duplicates = joined
+            .iter()
+            .map(|access_unit| Ok(decoder.decode_presentation(access_unit, 1)?.is_duplicate))
+            .collect::<Result<Vec<_>>>()?;
+        Ok((duplicates, decoder.stats()))
+    };
+
+    let (duplicates, stats) = decode(DuplicateHandling::Skip)?;
+    assert_eq!(duplicates, [false, true, false]);
+    assert_eq!(stats.duplicate_access_units, 1);
+
+    let (duplicates, stats) = decode(DuplicateHandling::Keep)?;
+    assert_eq!(duplicates, [false; 3]);
+    assert_eq!(stats.duplicate_access_units, 1);
+
+    let err = decode(DuplicateHandling::Error).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(DecodeError::DuplicateAccessUnit(1))
+    ));
+
+    // Consecutive access units of the stream are not duplicates
+    let (duplicates, stats) = {
+        let mut decoder = Decoder::default();
+        let duplicates = access_units
+            .iter()
+            .map(|access_unit| Ok(decoder.decode_presentation(access_unit, 1)?.is_duplicate))
+            .collect::<Result<Vec<_>>>()?;
+        (duplicates, decoder.stats())
+    };
+    assert!(duplicates.iter().all(|&duplicate| !duplicate));
+    assert_eq!(stats.duplicate_access_units, 0);
+    Ok(())
+}
+
+#[test]
+fn concatenated_streams_decode_without_warnings() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    // Warnings fail the parse or decode
+    let decode = |boundary: bool| -> Result<Vec<bool>> {
+        let mut extractor = Extractor::default();
+        let mut parser = Parser::default();
+        let mut decoder = Decoder::default();
+        parser.set_fail_level(log::Level::Warn);
+        decoder.set_fail_level(log::Level::Warn);
+
+        let mut stream_starts = Vec::new();
+        for i in 0..2 {
+            if i > 0 && boundary {
+                extractor.push_stream_boundary()?;
+            }
+            extractor.push_bytes(EXAMPLE_DATA);
+            for frame in extractor.by_ref().filter_map(Result::ok) {
+                let access_unit = parser.parse(&frame)?;
+                let decoded = decoder.decode_presentation(&access_unit, 1)?;
+                assert!(!decoded.is_duplicate);
+                stream_starts.push(decoded.stream_start);
+            }
+        }
+
+        assert_eq!(decoder.stats().failures(), 0);
+        Ok(stream_starts)
+    };
+
+    assert_eq!(decode(true)?, [false, false, true, false]);
+    // Without the boundary the join is validated as a seamless branch
+    assert!(decode(false).is_err());
+    Ok(())
+}
+
+/// Example access units recoded with the 0x31EC restart sync word, their
+/// residuals scaled to decode above the 24-bit range.
+#[cfg(test)]
+fn wide_access_units() -> Result<Vec<AccessUnit>> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+    use crate::structs::restart_header::RestartSyncWord;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(3));
+    let mut access_units = extractor
+        .filter_map(Result::ok)
+        .map(|frame| parser.parse(&frame))
+        .collect::<Result<Vec<_>>>()?;
+
+    for access_unit in &mut access_units {
+        for block in &mut access_unit.substream_segment[0].block {
+            if let Some(restart_header) = &mut block.restart_header {
+                restart_header.restart_sync_word = RestartSyncWord::C;
+            }
+            for sample in block.block_data.iter_mut().flatten() {
+                *sample <<= 16;
+            }
+        }
+    }
+    Ok(access_units)
+}
+
+#[test]
+fn decode_32bit_substream() -> Result<()> {
+    let mut access_units = wide_access_units()?;
+
+    // Lossless checks of the intervals ending at each restart header but the first
+    let mut decoder = Decoder::default();
+    let mut checks = Vec::new();
+    let mut checks_24bit = Vec::new();
+    let mut check_data = 0u32;
+    let mut check_data_24bit = 0u32;
+    let mut peak = 0;
+    for (i, access_unit) in access_units.iter().enumerate() {
+        if i > 0
+            && access_unit.substream_segment[0].block[0]
+                .restart_header
+                .is_some()
+        {
+            let fold = |data: u32| data.to_le_bytes().iter().fold(0, |acc, b| acc ^ b);
+            checks.push(fold(check_data));
+            checks_24bit.push(fold(check_data_24bit));
+            check_data = 0;
+            check_data_24bit = 0;
+        }
+
+        let decoded = decoder.decode_presentation(access_unit, 1)?;
+        assert_eq!(decoded.bit_depth, 32);
+        for sample in &decoded.pcm_data[..decoded.sample_length] {
+            for (chi, &value) in sample[..decoded.channel_count].iter().enumerate() {
+                check_data ^= (value as u32).rotate_left(chi as u32);
+                check_data_24bit ^= (value as u32 & 0xFFFFFF).rotate_left(chi as u32);
+                peak = peak.max(value.unsigned_abs());
+            }
+        }
+    }
+    assert!(peak > 1 << 23, "peak {peak}");
+    assert_eq!(checks.len(), 2);
+
+    // The lossless check covers all 32 bits of the samples
+    let mismatches = |access_units: &mut [AccessUnit], checks: &[u8]| -> Result<u64> {
+        let mut checks = checks.iter();
+        for access_unit in access_units.iter_mut().skip(1) {
+            if let Some(restart_header) =
+                &mut access_unit.substream_segment[0].block[0].restart_header
+            {
+                restart_header.lossless_check = *checks.next().unwrap();
+            }
+        }
+        let mut decoder = Decoder::default();
+        for access_unit in access_units.iter() {
+            decoder.decode_presentation(access_unit, 1)?;
+        }
+        Ok(decoder.stats().substreams[0].lossless_check_mismatches)
+    };
+    assert_eq!(mismatches(&mut access_units, &checks)?, 0);
+    assert_eq!(mismatches(&mut access_units, &checks_24bit)?, 2);
+    Ok(())
+}
+
+#[test]
+fn event_sink_receives_lossless_check_mismatch() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+    use std::sync::{Arc, Mutex};
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(3));
+    let mut access_units = extractor
+        .filter_map(Result::ok)
+        .map(|frame| parser.parse(&frame))
+        .collect::<Result<Vec<_>>>()?;
+
+    let restart_header = access_units[2].substream_segment[0].block[0]
+        .restart_header
+        .as_mut()
+        .unwrap();
+    let lossless_check = restart_header.lossless_check;
+    restart_header.lossless_check ^= 1;
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink_events = events.clone();
+    let mut decoder = Decoder::default();
+    decoder.set_event_sink(move |event| sink_events.lock().unwrap().push(event));
+    for access_unit in &access_units {
+        decoder.decode_presentation(access_unit, 1)?;
+    }
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        [Event::LosslessCheckMismatch {
+            substream: 0,
+            calculated: lossless_check as i32,
+            read: lossless_check ^ 1,
+        }]
+    );
+    Ok(())
+}
+
+#[test]
+fn dialogue_level_follows_major_syncs() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(2));
+    let mut access_units = extractor
+        .filter_map(Result::ok)
+        .map(|frame| parser.parse(&frame))
+        .collect::<Result<Vec<_>>>()?;
+
+    for (index, dialogue_norm) in [(0, 27), (2, 20)] {
+        let major_sync_info = access_units[index].major_sync_info.as_mut().unwrap();
+        major_sync_info.channel_meaning.twoch_dialogue_norm = dialogue_norm;
+    }
+
+    let mut decoder = Decoder::default();
+    let levels = access_units
+        .iter()
+        .map(|access_unit| Ok(decoder.decode_presentation(access_unit, 0)?.dialogue_level))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(levels, [Some(-27), Some(-27), Some(-20), Some(-20)]);
+    Ok(())
+}
+
+#[test]
+fn planar_f64_within_one_lsb_of_integer_output() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let mut seed = 0x2545_F491_4F6C_DD1Du64;
+    let mut next_random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+
+    let mut fractional = false;
+    for _ in 0..32 {
+        let mut extractor = Extractor::default();
+        let mut parser = Parser::default();
+        extractor.push_bytes(EXAMPLE_DATA);
+        let mut access_units = extractor
+            .filter_map(Result::ok)
+            .map(|frame| parser.parse(&frame))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Random residuals with a random output shift of -7 to 0 per channel
+        let output_shift = [-((next_random() % 8) as i8), -((next_random() % 8) as i8)];
+        for block in access_units
+            .iter_mut()
+            .flat_map(|access_unit| &mut access_unit.substream_segment[0].block)
+        {
+            if let Some(block_header) = &mut block.block_header {
+                block_header.output_shift[..2].copy_from_slice(&output_shift.map(Some));
+            }
+            for sample in block.block_data.iter_mut().flatten() {
+                *sample = (next_random() % (1 << 16)) as i32 - (1 << 15);
+            }
+        }
+
+        let mut decoder = Decoder::default();
+        let mut decoder_f64 = Decoder::default();
+        for access_unit in &access_units {
+            let decoded = decoder.decode_presentation(access_unit, 0)?;
+            let (decoded_f64, planar) = decoder_f64.decode_presentation_f64(access_unit, 0)?;
+            assert_eq!(*decoded.pcm_data, *decoded_f64.pcm_data);
+
+            let full_scale = decoded.full_scale();
+            let planar_int = decoded.to_planar_f64();
+            assert_eq!(planar.len(), decoded.channel_count);
+            for (channel, channel_int) in planar.iter().zip(&planar_int) {
+                assert_eq!(channel.len(), decoded.sample_length);
+                for (&sample, &sample_int) in channel.iter().zip(channel_int) {
+                    assert!((-1.0..1.0).contains(&sample));
+                    let lsbs = (sample - sample_int) * full_scale;
+                    assert!((0.0..1.0).contains(&lsbs), "{sample} vs {sample_int}");
+                    fractional |= lsbs != 0.0;
+                }
+            }
+        }
+    }
+    assert!(fractional);
+    Ok(())
+}
+
+#[test]
+fn malformed_oamd_payload_skipped() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::metadata::OAMD_PAYLOAD_ID;
+    use crate::process::parse::Parser;
+    use crate::structs::evolution::{EvoFrame, EvoPayload};
+    use crate::structs::extra_data::ExtraData;
+    use crate::structs::oamd::{TEST_DATA, TEST_DATA_BROKEN};
+    use std::sync::{Arc, Mutex};
+
+    // The broken payload cut short of its program assignment
+    let broken = &TEST_DATA_BROKEN[..4];
+    assert!(ObjectAudioMetadataPayload::read(broken).is_err());
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    let frame = extractor.next().unwrap()?;
+    let mut access_unit = parser.parse(&frame)?;
+    access_unit.extra_data = Some(ExtraData {
+        evo_frame: Some(EvoFrame {
+            evo_payloads: [TEST_DATA, broken, TEST_DATA]
+                .map(|data| EvoPayload {
+                    evo_payload_id: OAMD_PAYLOAD_ID,
+                    evo_payload_byte: data.to_vec(),
+                    ..Default::default()
+                })
+                .into(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+
+    // The audio decodes and the payloads around the broken one are kept
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut decoder = Decoder::default();
+    let sink = events.clone();
+    decoder.set_event_sink(move |event| sink.lock().unwrap().push(event));
+    let decoded = decoder.decode_presentation(&access_unit, 0)?;
+    assert_eq!(decoded.sample_length, 40);
+    decoder.state.oamd.clear();
+    decoder.state.read_oamd(&access_unit)?;
+    assert_eq!(decoder.state.oamd.len(), 2);
+    assert_eq!(decoder.stats().skipped_oamd_payloads, 1);
+    assert_eq!(
+        *events.lock().unwrap(),
+        [Event::OamdPayloadSkipped { au: 1, length: 4 }]
+    );
+
+    // Strict decoding fails on the broken payload
+    let mut decoder = Decoder::default();
+    decoder.set_fail_level(log::Level::Warn);
+    let err = decoder.state.read_oamd(&access_unit).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(DecodeError::OamdPayloadSkipped { length: 4, .. })
+    ));
+    Ok(())
+}
+
+#[test]
+fn missing_substream_degrades_presentation() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let parse = || {
+        let mut extractor = Extractor::default();
+        let mut parser = Parser::default();
+        extractor.push_bytes(EXAMPLE_DATA);
+        extractor
+            .filter_map(Result::ok)
+            .map(|frame| parser.parse(&frame))
+            .collect::<Result<Vec<_>>>()
+    };
+    let access_units = parse()?;
+
+    // A 16-channel presentation advertised in a stream of one substream
+    let mut truncated = parse()?;
+    truncated[0]
+        .major_sync_info
+        .as_mut()
+        .unwrap()
+        .substream_info = 0x80;
+    assert_eq!(truncated[0].substreams, 1);
+
+    let mut decoder = Decoder::default();
+    let mut reference = Decoder::default();
+    for (access_unit, expected) in truncated.iter().zip(&access_units) {
+        let decoded = decoder.decode_presentation(access_unit, 3)?;
+        let expected = reference.decode_presentation(expected, 0)?;
+        assert_eq!(decoded.channel_count, 2);
+        assert_eq!(
+            decoded.pcm_data[..decoded.sample_length],
+            expected.pcm_data[..expected.sample_length]
+        );
+    }
+
+    let mut decoder = Decoder::default();
+    decoder.set_fail_level(log::Level::Warn);
+    let err = decoder.decode_presentation(&truncated[0], 3).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(ParseError::MissingSubstream {
+            advertised: 4,
+            present: 1
+        })
+    ));
+    Ok(())
+}
+
+#[test]
+fn channel_count_change_at_restart_header() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(2));
+    let mut access_units = extractor
+        .filter_map(Result::ok)
+        .map(|frame| parser.parse(&frame))
+        .collect::<Result<Vec<_>>>()?;
+
+    // The second part declares a third channel in its restart headers
+    let join = access_units.len() / 2;
+    for access_unit in &mut access_units[join..] {
+        let blocks = access_unit.substream_segment[0].block.iter_mut();
+        for restart_header in blocks.flat_map(|block| &mut block.restart_header) {
+            restart_header.max_matrix_chan = 2;
+            restart_header.ch_assign[2] = 2;
+        }
+    }
+
+    let mut decoder = Decoder::default();
+    for (index, access_unit) in access_units.iter().enumerate() {
+        let decoded = decoder.decode_presentation(access_unit, 1)?;
+        assert_eq!(decoded.config_changed, index == join);
+        assert!(!decoded.substream_info_changed);
+        let channel_count = if index < join { 2 } else { 3 };
+        assert_eq!(decoded.channel_count, channel_count);
+        assert_eq!(decoded.channel_labels.len(), channel_count);
+    }
+    Ok(())
+}
+
+#[test]
+fn decode_presentations_in_one_pass() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let parse = || {
+        let mut extractor = Extractor::default();
+        let mut parser = Parser::default();
+        extractor.push_bytes(&EXAMPLE_DATA.repeat(2));
+        extractor
+            .filter_map(Result::ok)
+            .map(|frame| parser.parse(&frame))
+            .collect::<Result<Vec<_>>>()
+    };
+
+    // A second substream adds a third channel for presentation 1, matrixed
+    // with the recorrelated channels of substream 0
+    let mut access_units = parse()?;
+    for (access_unit, mut copy) in access_units.iter_mut().zip(parse()?) {
+        if let Some(major_sync) = &mut access_unit.major_sync_info {
+            major_sync.substream_info = 0x0C;
+        }
+        let mut substream = std::mem::take(&mut copy.substream_segment[0]);
+        for block in &mut substream.block {
+            if let Some(restart_header) = &mut block.restart_header {
+                restart_header.min_chan = 2;
+                restart_header.max_chan = 2;
+                restart_header.max_matrix_chan = 2;
+                restart_header.ch_assign[2] = 2;
+            }
+            for sample in block.block_data.iter_mut() {
+                sample[2] = sample[0];
+            }
+        }
+        access_unit.substream_segment[1] = substream;
+        access_unit.substreams = 2;
+    }
+
+    // Presentation 2 is not available and decoded as presentation 1
+    let mut decoder = Decoder::default();
+    let mut references: Vec<_> = (0..3).map(|_| Decoder::default()).collect();
+    for access_unit in &access_units {
+        let decoded = decoder.decode_presentations(access_unit, &[true, true, true, false])?;
+        assert_eq!(decoded.len(), 3);
+        for ((presentation, decoded), reference) in decoded.iter().zip(&mut references) {
+            let expected = reference.decode_presentation(access_unit, *presentation)?;
+            assert_eq!(decoded.channel_count, expected.channel_count);
+            assert_eq!(decoded.sample_length, expected.sample_length);
+            assert_eq!(
+                decoded.pcm_data[..decoded.sample_length],
+                expected.pcm_data[..expected.sample_length]
+            );
+        }
+        assert_eq!(decoded[0].1.channel_count, 2);
+        assert_eq!(decoded[1].1.channel_count, 3);
+    }
+    assert_eq!(decoder.presentation(), 1);
+
+    // The lossless checks of both substreams are verified
+    let stats = decoder.stats();
+    assert_eq!(stats.substreams[0], references[0].stats().substreams[0]);
+    assert_eq!(stats.substreams[1], references[1].stats().substreams[1]);
+    assert_eq!(stats.substreams[0].lossless_check_mismatches, 0);
+
+    assert!(
+        decoder
+            .decode_presentations(&access_units[0], &[false; 4])
+            .is_err()
+    );
+    Ok(())
+}
@@ -1,10 +1,12 @@
 use crate::log_or_err;
-use crate::structs::timestamp::Timestamp;
+use crate::process::mat::{MAT_START_CODE, MatEncapsulation, MatUnpacker};
+use crate::structs::timestamp::{TIMESTAMP_PACKET_LEN, Timestamp};
 use crate::utils::buffer_pool::BufferPool;
 use crate::utils::crc::{CRC_MAJOR_SYNC_INFO_ALG, Crc16};
-use crate::utils::errors::ExtractError;
+use crate::utils::errors::{Category, CategoryPolicy, ExtractError, FrameCheck, Policy};
+use crate::utils::events::{Event, EventSink};
 use anyhow::Result;
-use log::error;
+use log::{error, warn};
 use std::collections::VecDeque;
 use std::sync::Arc;
 
@@ -40,6 +42,27 @@ use std::sync::Arc;
 /// - Uses a ring buffer with 120KB capacity for efficient processing
 /// - Implements streaming extraction to handle large files
 /// - CRC validation ensures frame integrity
+///
+/// # Error Recovery
+///
+/// When an access unit fails its parity check, the extractor scans forward
+/// byte by byte for the next plausible minor sync, within a window set by
+/// [`Extractor::set_resync_window`], and falls back to searching for the next
+/// major sync beyond it. Once sync is regained it yields
+/// [`ExtractError::Resynchronized`] with the byte offset of the failed access
+/// unit, the check it failed and the number of bytes skipped before resuming
+/// with the next frame.
+///
+/// # MAT Encapsulation
+///
+/// Input carrying Dolby MAT frames in IEC 61937-9 bursts, as captured from an
+/// HDMI link, is detected by the burst preamble before the first major sync,
+/// and bare MAT frames by their start code. The access units are then
+/// unwrapped with a [`MatUnpacker`] before extraction, and
+/// [`Extractor::mat_encapsulation`] reports the MAT frames and the padding
+/// discarded. Byte offsets count the unwrapped access units instead of the
+/// input bytes from there on. [`Extractor::set_input_framing`] overrides the
+/// detection.
 #[derive(Debug)]
 pub struct Extractor {
     buffer: VecDeque<u8>,
@@ -47,12 +70,37 @@ pub struct Extractor {
     inited: bool,
     locked: bool,
     io_counter: usize,
+    bytes_consumed: u64,
     substreams: usize,
     crc: Crc16,
     buffer_pool: BufferPool,
     error_count: usize,
     frames_processed: usize,
     fail_level: log::Level,
+    category_policy: CategoryPolicy,
+    event_sink: Option<EventSink>,
+    resync_window: usize,
+    resync_start: Option<u64>,
+    resync_check: Option<FrameCheck>,
+    stream_start: bool,
+    mat: Option<MatUnpacker>,
+    unwrap_mat: bool,
+    framing: Framing,
+}
+
+/// Framing of the input pushed to an [`Extractor`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// Access units, unless MAT frames are detected before the first major
+    /// sync.
+    #[default]
+    Auto,
+
+    /// Access units back to back, without detecting MAT frames.
+    Raw,
+
+    /// Dolby MAT frames, bare or in IEC 61937-9 bursts.
+    Mat,
 }
 
 impl Default for Extractor {
@@ -63,12 +111,22 @@ impl Default for Extractor {
             inited: false,
             locked: false,
             io_counter: 0,
+            bytes_consumed: 0,
             substreams: 0,
             crc: Crc16::new(&CRC_MAJOR_SYNC_INFO_ALG),
             buffer_pool: BufferPool::default(),
             error_count: 0,
             frames_processed: 0,
             fail_level: log::Level::Error,
+            category_policy: CategoryPolicy::default(),
+            event_sink: None,
+            resync_window: 65536,
+            resync_start: None,
+            resync_check: None,
+            stream_start: false,
+            mat: None,
+            unwrap_mat: false,
+            framing: Framing::Auto,
         }
     }
 }
@@ -97,10 +155,65 @@ impl Extractor {
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
     pub fn push_bytes(&mut self, data: &[u8]) {
-        self.buffer.extend(data);
+        match &mut self.mat {
+            Some(mat) if self.unwrap_mat => mat.push(data, &mut self.buffer),
+            _ => {
+                self.buffer.extend(data);
+                if !self.inited && self.framing == Framing::Auto {
+                    self.detect_mat(data.len());
+                }
+            }
+        }
         self.io_counter += 1;
     }
 
+    /// Switches to unwrapping MAT frames if a burst preamble or MAT start code
+    /// is among the last `pushed` bytes of the buffer.
+    fn detect_mat(&mut self, pushed: usize) {
+        // A preamble or start code may start in the bytes pushed before
+        let from = self
+            .buffer
+            .len()
+            .saturating_sub(pushed + MAT_START_CODE.len() - 1);
+        let Some(at) = MatUnpacker::find_frame(&self.buffer.make_contiguous()[from..]) else {
+            return;
+        };
+
+        log::debug!(
+            "Dolby MAT encapsulation detected at byte offset {}, unwrapping the access units",
+            self.bytes_consumed + (from + at) as u64
+        );
+        self.unwrap_buffered();
+    }
+
+    /// Unwraps the buffered bytes and the bytes pushed from now on as MAT
+    /// frames.
+    fn unwrap_buffered(&mut self) {
+        let mat = self.mat.get_or_insert_default();
+        let data: Vec<u8> = self.buffer.drain(..).collect();
+        mat.push(&data, &mut self.buffer);
+        self.unwrap_mat = true;
+    }
+
+    /// Sets the framing of the input.
+    ///
+    /// With [`Framing::Mat`], bytes already pushed are unwrapped as well.
+    /// Defaults to [`Framing::Auto`].
+    pub fn set_input_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+        match framing {
+            Framing::Mat if !self.unwrap_mat => self.unwrap_buffered(),
+            Framing::Raw => self.unwrap_mat = false,
+            _ => {}
+        }
+    }
+
+    /// Returns the MAT frames unwrapped and the padding discarded so far, or
+    /// `None` unless MAT encapsulation was detected or set for the input.
+    pub fn mat_encapsulation(&self) -> Option<MatEncapsulation> {
+        self.mat.as_ref().map(MatUnpacker::stats)
+    }
+
     /// Forces the extractor to search for the next sync pattern.
     ///
     /// This method clears the current sync lock and searches for the next major sync
@@ -161,14 +274,19 @@ impl Extractor {
             }
 
             if state != 4 {
-                self.consume_front(search_range);
+                // A sync pattern may continue past the searched range, so keep the
+                // candidates not fully checked yet (and the timestamp before the first one)
+                let keep = if self.inited { 7 } else { 7 + 16 };
+                self.consume_front(search_range.saturating_sub(keep));
                 return self.insufficient();
             }
 
             // Try only once
             self.timestamp = if !self.inited && offset >= 16 {
                 self.consume_front(offset - 16);
-                Timestamp::from_bytes(&self.buffer.drain(..16).collect::<Vec<_>>()).ok()
+                let timestamp_bytes = self.buffer.range(..16).copied().collect::<Vec<_>>();
+                self.consume_front(16);
+                Timestamp::from_bytes(&timestamp_bytes).ok()
             } else {
                 self.consume_front(offset);
                 None
@@ -193,18 +311,17 @@ impl Extractor {
                 return self.insufficient();
             }
 
-            let access_unit_bytes = self
-                .buffer
-                .range(..access_unit_len)
-                .copied()
-                .collect::<Vec<_>>();
-
-            let crc_bytes = &(&access_unit_bytes[4 + major_sync_info_len..])[..2];
-            let crc = u16::from_be_bytes([crc_bytes[0], crc_bytes[1]]);
-            if crc != self.crc16_major_sync_info(&(&access_unit_bytes[4..])[..major_sync_info_len])
-            {
+            if !self.major_sync_info_crc_valid(major_sync_info_len) {
+                let offset = self.bytes_consumed;
                 self.consume_front(access_unit_len);
-                log_or_err!(&self, log::Level::Error, ExtractError::ParityCheckFailed);
+                log_or_err!(
+                    &self,
+                    Category::Crc,
+                    log::Level::Error,
+                    ExtractError::ParityCheckFailed
+                );
+                self.resync_start.get_or_insert(offset);
+                self.resync_check.get_or_insert(FrameCheck::Crc);
                 continue;
             }
 
@@ -215,23 +332,223 @@ impl Extractor {
         }
     }
 
+    /// Sets how many bytes are scanned for a minor sync after lost sync.
+    ///
+    /// Beyond the window, the extractor only searches for the next major
+    /// sync. A window of 0 disables the minor sync scan. Defaults to 64 KiB.
+    pub fn set_resync_window(&mut self, bytes: usize) {
+        self.resync_window = bytes;
+    }
+
+    /// Sets the handling of failed parity checks with [`Category::Crc`].
+    ///
+    /// By default a failed check is an error.
+    pub fn set_category_policy(&mut self, category: Category, policy: Policy) {
+        self.category_policy.set(category, policy);
+    }
+
+    /// Sets a sink receiving an [`Event`] for every failed parity check.
+    pub fn set_event_sink(&mut self, sink: impl Fn(Event) + Send + Sync + 'static) {
+        self.event_sink = Some(EventSink::new(sink));
+    }
+
+    /// Records the start of the bytes skipped after a failed check of the
+    /// access unit at the front of the buffer.
+    fn fail_check(&mut self, check: FrameCheck) {
+        if self.resync_start.is_none() {
+            self.resync_start = Some(self.bytes_consumed);
+            self.resync_check = Some(check);
+        }
+    }
+
+    /// Regains sync after a failed check, or at the start of the stream.
+    fn regain_sync(&mut self) -> Result<(), ExtractError> {
+        if self.resync_start.is_some() {
+            self.scan_minor_sync()
+        } else {
+            self.resync()
+        }
+    }
+
+    /// Scans forward byte by byte for the next plausible access unit.
+    ///
+    /// Minor syncs carry no sync word, so a candidate is accepted when its
+    /// length and header parity are valid and the access unit following it
+    /// checks out as well. A major sync pattern hands over to [`Self::resync`],
+    /// as does running past the resync window.
+    fn scan_minor_sync(&mut self) -> Result<(), ExtractError> {
+        self.locked = false;
+
+        let scanned = self.bytes_consumed - self.resync_start.unwrap_or(self.bytes_consumed);
+        let window = self
+            .resync_window
+            .saturating_sub(scanned as usize)
+            .min(self.buffer.len());
+
+        // Access units never straddle a major sync, so candidates before one
+        // which cannot be checked completely yet are not genuine
+        let major_sync = (0..window).find(|&at| self.major_sync_pattern_at(at) == Some(true));
+        let end = major_sync.unwrap_or(window);
+
+        for at in 0..end {
+            let plausible = match self.minor_sync_plausible(at) {
+                Some(plausible) => plausible,
+                None if major_sync.is_some() => false,
+                None => {
+                    self.consume_front(at);
+                    return self.insufficient();
+                }
+            };
+
+            if plausible {
+                self.consume_front(at);
+                self.locked = true;
+                return Ok(());
+            }
+        }
+
+        self.consume_front(end);
+        self.resync()
+    }
+
+    fn major_sync_pattern_at(&self, at: usize) -> Option<bool> {
+        Some(
+            *self.buffer.get(at + 4)? == 0xF8
+                && *self.buffer.get(at + 5)? == 0x72
+                && *self.buffer.get(at + 6)? == 0x6F
+                && matches!(*self.buffer.get(at + 7)?, 0xBA | 0xBB),
+        )
+    }
+
+    /// Checks whether a minor sync access unit plausibly starts at `at`.
+    ///
+    /// Returns `None` if the buffer ends before the check is complete.
+    fn minor_sync_plausible(&self, at: usize) -> Option<bool> {
+        // Header and one substream directory entry per substream at least
+        let min_len = 4 + 2 * self.substreams;
+
+        let len = self.access_unit_len_at(at)?;
+        if len <= min_len || !self.header_parity_valid(at, 0)? {
+            return Some(false);
+        }
+
+        let next = at + len;
+        if self.major_sync_pattern_at(next)? {
+            return Some(true);
+        }
+
+        let next_len = self.access_unit_len_at(next)?;
+        Some(next_len > min_len && self.header_parity_valid(next, 0)?)
+    }
+
+    /// Checks the check nibble of the access unit header at `at`.
+    ///
+    /// The parity covers the header and the substream directory, which starts
+    /// `skip` bytes after the header. Returns `None` if the buffer ends before
+    /// the directory does.
+    fn header_parity_valid(&self, at: usize, skip: usize) -> Option<bool> {
+        let mut parity = 0;
+        for i in at..at + 4 {
+            parity ^= self.buffer.get(i)?;
+        }
+
+        let mut offset = at + 4 + skip;
+        for _ in 0..self.substreams {
+            let entry_len = if self.buffer.get(offset)? >> 7 != 0 {
+                4
+            } else {
+                2
+            };
+            for i in offset..offset + entry_len {
+                parity ^= self.buffer.get(i)?;
+            }
+            offset += entry_len;
+        }
+
+        Some(((parity >> 4) ^ parity) & 0xF == 0xF)
+    }
+
+    /// Discards the bytes left in the buffer once the input has ended.
+    ///
+    /// Without further input, the bytes of an incomplete access unit would
+    /// stay buffered indefinitely. Returns [`ExtractError::PartialAccessUnit`]
+    /// with their number if any were left, after which the extractor
+    /// searches for a sync pattern in the bytes pushed next.
+    pub fn flush_partial(&mut self) -> Result<(), ExtractError> {
+        // Including the bytes of a burst cut short, besides its padding
+        let unwrapped = match &mut self.mat {
+            Some(mat) if self.unwrap_mat => mat.finish(),
+            _ => 0,
+        };
+
+        let bytes = self.buffer.len();
+        if bytes + unwrapped == 0 {
+            return Ok(());
+        }
+
+        self.consume_front(bytes);
+        self.locked = false;
+        Err(ExtractError::PartialAccessUnit {
+            bytes: bytes + unwrapped,
+        })
+    }
+
+    /// Marks the end of a logical stream at the end of the data pushed so far.
+    ///
+    /// Used to concatenate independently encoded streams without a gap. The
+    /// frames pushed before the boundary should be extracted first; the bytes
+    /// left in the buffer are discarded as with [`Self::flush_partial`], and
+    /// the extractor searches for the first major sync of the next stream.
+    /// The first frame extracted after the boundary has
+    /// [`Frame::stream_start`] set, so the parser starts over at it instead of
+    /// validating it as a branch.
+    pub fn push_stream_boundary(&mut self) -> Result<(), ExtractError> {
+        self.timestamp = None;
+        self.inited = false;
+        self.locked = false;
+        self.substreams = 0;
+        self.resync_start = None;
+        self.resync_check = None;
+        self.stream_start = true;
+
+        // The next stream may or may not be encapsulated
+        let result = self.flush_partial();
+        self.unwrap_mat = self.framing == Framing::Mat;
+        result
+    }
+
     pub fn timestamp(&self) -> Option<Timestamp> {
         self.timestamp.clone()
     }
 
+    /// Returns the absolute input position of the first buffered byte.
+    ///
+    /// This is the total number of bytes pushed so far minus the bytes still
+    /// held in the internal buffer, i.e. the offset at which the next frame
+    /// candidate starts.
+    pub fn byte_offset(&self) -> u64 {
+        self.bytes_consumed
+    }
+
     fn consume_front(&mut self, cnt: usize) {
         self.buffer.drain(..cnt);
+        self.bytes_consumed += cnt as u64;
     }
 
     fn access_unit_len(&self) -> Option<usize> {
+        self.access_unit_len_at(0)
+    }
+
+    fn access_unit_len_at(&self, at: usize) -> Option<usize> {
         Some(
-            ((u16::from_be_bytes([*self.buffer.front()?, *self.buffer.get(1)?]) & 0xFFF) << 1)
+            ((u16::from_be_bytes([*self.buffer.get(at)?, *self.buffer.get(at + 1)?]) & 0xFFF) << 1)
                 as usize,
         )
     }
 
     fn major_sync_info_len(&mut self) -> Option<usize> {
-        let len = if self.buffer.get(29)? & 0x01 == 0 {
+        // Only FBA streams may carry extra channel meaning
+        let len = if *self.buffer.get(7)? != 0xBA || self.buffer.get(29)? & 0x01 == 0 {
             26
         } else {
             28 + ((self.buffer.get(30)? >> 3) & 0x1Eu8) as usize
@@ -240,6 +557,20 @@ impl Extractor {
         Some(len)
     }
 
+    /// Checks the major sync info CRC of the frame at the front of the buffer.
+    ///
+    /// The buffer must hold at least `major_sync_info_len + 6` bytes.
+    fn major_sync_info_crc_valid(&mut self, major_sync_info_len: usize) -> bool {
+        let major_sync_info = self
+            .buffer
+            .range(4..6 + major_sync_info_len)
+            .copied()
+            .collect::<Vec<_>>();
+
+        let (data, crc_bytes) = major_sync_info.split_at(major_sync_info_len);
+        u16::from_be_bytes([crc_bytes[0], crc_bytes[1]]) == self.crc16_major_sync_info(data)
+    }
+
     fn insufficient(&mut self) -> Result<(), ExtractError> {
         self.io_counter -= 1;
         Err(ExtractError::InsufficientData)
@@ -264,101 +595,98 @@ impl Iterator for Extractor {
             return None;
         }
 
-        loop {
+        'extract: loop {
             'locked: {
-                if !self.locked && self.resync().is_err() {
+                if !self.locked && self.regain_sync().is_err() {
                     return None;
                 }
 
+                // Report the skipped data before the first frame after it
+                if let Some(offset) = self.resync_start.take() {
+                    return Some(Err(ExtractError::Resynchronized {
+                        offset,
+                        skipped_bytes: self.bytes_consumed - offset,
+                        check: self.resync_check.take().unwrap_or(FrameCheck::Parity),
+                    }));
+                }
+
                 if self.buffer.len() < 6 {
                     return self.iter_insufficient();
                 };
 
-                let mut offset = 0;
-                let mut pre = 4;
-                let mut skip = if self.buffer[4] == 0xF8 && self.buffer[5] == 0x72 {
+                let skip = if self.buffer[4] == 0xF8 && self.buffer[5] == 0x72 {
                     if self.buffer.len() < 21 {
                         return self.iter_insufficient();
                     };
 
-                    let substreams = self.buffer[20] as usize >> 4;
-                    if self.substreams != substreams {
-                        let error = ExtractError::SubstreamMismatch {
-                            found: substreams,
-                            expected: self.substreams,
-                        };
-                        error!("Substream count mismatch: {error}");
-
-                        break 'locked;
-                    }
-
                     let Some(major_sync_info_len) = self.major_sync_info_len() else {
                         return self.iter_insufficient();
                     };
 
-                    major_sync_info_len + 2
-                } else {
-                    0
-                };
-
-                let mut post = 0;
-                let mut substreams = self.substreams;
-                let mut parity = 0;
-
-                'outer: {
-                    'inner: loop {
-                        if pre > 0 {
-                            pre -= 1;
+                    let substreams = self.buffer[20] as usize >> 4;
+                    if self.substreams != substreams {
+                        if self.buffer.len() < major_sync_info_len + 6 {
+                            return self.iter_insufficient();
+                        }
 
-                            let Some(byte) = self.buffer.get(offset) else {
-                                break 'inner;
+                        // A major sync with a valid CRC may change the stream configuration
+                        if !self.major_sync_info_crc_valid(major_sync_info_len) {
+                            let error = ExtractError::SubstreamMismatch {
+                                found: substreams,
+                                expected: self.substreams,
                             };
+                            error!(
+                                "Substream count mismatch at byte offset {} ({:#X}): {error}",
+                                self.bytes_consumed, self.bytes_consumed
+                            );
 
-                            parity ^= byte;
-                            offset += 1;
-
-                            continue;
+                            self.fail_check(FrameCheck::Crc);
+                            break 'locked;
                         }
-                        if skip > 0 {
-                            skip -= 1;
-                            offset += 1;
 
-                            continue;
-                        }
-                        if post > 0 {
-                            post -= 1;
+                        warn!(
+                            "Substream count changed at byte offset {} ({:#X}): {} -> {substreams}",
+                            self.bytes_consumed, self.bytes_consumed, self.substreams
+                        );
+                        self.substreams = substreams;
+                    }
 
-                            let Some(byte) = self.buffer.get(offset) else {
-                                break 'inner;
-                            };
+                    major_sync_info_len + 2
+                } else {
+                    0
+                };
 
-                            parity ^= byte;
-                            offset += 1;
+                let Some(parity_valid) = self.header_parity_valid(0, skip) else {
+                    return self.iter_insufficient();
+                };
 
-                            continue;
+                if !parity_valid {
+                    // Timestamp packets may also precede later access units,
+                    // e.g. at the joins of concatenated streams
+                    if self.buffer.front() == Some(&0x01) && self.buffer.get(1) == Some(&0x10) {
+                        if self.buffer.len() < TIMESTAMP_PACKET_LEN {
+                            return self.iter_insufficient();
                         }
-                        if substreams > 0 {
-                            substreams -= 1;
 
-                            let Some(byte) = self.buffer.get(offset) else {
-                                break 'inner;
-                            };
-
-                            post += 2 + if (*byte >> 7) != 0 { 2 } else { 0 };
-
-                            continue;
+                        let packet = self
+                            .buffer
+                            .range(..TIMESTAMP_PACKET_LEN)
+                            .copied()
+                            .collect::<Vec<_>>();
+                        if let Ok(timestamp) = Timestamp::from_bytes(&packet) {
+                            self.consume_front(TIMESTAMP_PACKET_LEN);
+                            self.timestamp = Some(timestamp);
+                            continue 'extract;
                         }
-
-                        break 'outer;
                     }
 
-                    return self.iter_insufficient();
-                }
-
-                if ((parity >> 4) ^ parity) & 0xF != 0xF {
                     let error = ExtractError::ParityCheckFailed;
-                    error!("Frame parity check failed: {error}");
+                    error!(
+                        "Frame parity check failed at byte offset {} ({:#X}): {error}",
+                        self.bytes_consumed, self.bytes_consumed
+                    );
 
+                    self.fail_check(FrameCheck::Parity);
                     break 'locked;
                 }
 
@@ -366,13 +694,25 @@ impl Iterator for Extractor {
                     return self.iter_insufficient();
                 };
 
+                if access_unit_len <= 4 + 2 * self.substreams {
+                    error!(
+                        "Invalid access unit length {access_unit_len} at byte offset {} ({:#X})",
+                        self.bytes_consumed, self.bytes_consumed
+                    );
+
+                    self.fail_check(FrameCheck::Length);
+                    break 'locked;
+                }
+
                 if self.buffer.len() < access_unit_len {
                     return self.iter_insufficient();
                 };
 
                 // Use pooled buffer for zero-copy frame creation
+                let byte_offset = self.bytes_consumed;
                 let mut frame_buffer = self.buffer_pool.acquire();
                 frame_buffer.extend(self.buffer.drain(..access_unit_len));
+                self.bytes_consumed += access_unit_len as u64;
 
                 let timestamp = if self.timestamp.is_some() {
                     let timestamp = self.timestamp.clone();
@@ -385,6 +725,8 @@ impl Iterator for Extractor {
 
                 let frame = Frame {
                     timestamp,
+                    byte_offset,
+                    stream_start: std::mem::take(&mut self.stream_start),
                     data: frame_buffer.into(),
                 };
 
@@ -395,11 +737,11 @@ impl Iterator for Extractor {
             if self.inited {
                 self.error_count += 1;
                 if !self.buffer.is_empty() {
-                    self.buffer.pop_front();
+                    self.consume_front(1);
                 }
             }
 
-            match self.resync() {
+            match self.regain_sync() {
                 Ok(_) => continue,
                 Err(_) => return None,
             }
@@ -427,9 +769,18 @@ impl Iterator for Extractor {
 /// ```
 ///
 /// Major sync frames are identified by the sync pattern `0xF872` at bytes 4-5.
+///
+/// `byte_offset` is the absolute position of the first byte of the access unit
+/// in the input, counted over all data pushed into the [`Extractor`].
+/// `timestamp` is the SMPTE timestamp packet directly preceding the access
+/// unit, at the start of the stream or anywhere later. `stream_start` is set
+/// on the first frame after a boundary marked with
+/// [`Extractor::push_stream_boundary`].
 #[derive(Debug, Clone)]
 pub struct Frame {
     pub timestamp: Option<Timestamp>,
+    pub byte_offset: u64,
+    pub stream_start: bool,
     pub data: Arc<[u8]>,
 }
 
@@ -501,9 +852,11 @@ fn buf_extract() -> anyhow::Result<()> {
 
     let frame = extractor.next().unwrap()?;
     assert_eq!(frame.as_ref().len(), 84);
+    assert_eq!(frame.byte_offset, 120_000 + 16);
 
     let frame = extractor.next().unwrap().unwrap();
     assert_eq!(frame.as_ref().len(), 20);
+    assert_eq!(frame.byte_offset, 120_000 + 16 + 84);
     Ok(())
 }
 
@@ -537,3 +890,171 @@ fn skip_invalid_data() -> Result<()> {
     assert!(end_with_insufficient_data);
     Ok(())
 }
+
+#[test]
+fn resync_at_minor_sync() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+
+    // One major sync access unit followed by minor sync ones, twice
+    let (major_sync, minor_sync) = EXAMPLE_DATA[16..].split_at(84);
+    let mut stream = Vec::new();
+    for _ in 0..2 {
+        stream.extend_from_slice(major_sync);
+        for _ in 0..20 {
+            stream.extend_from_slice(minor_sync);
+        }
+    }
+
+    // Corrupt the header of the fifth minor sync access unit
+    let corrupted = 84 + 4 * minor_sync.len();
+    stream[corrupted..corrupted + 3].copy_from_slice(&[0x12, 0x34, 0x56]);
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(&stream);
+
+    let mut offsets = Vec::new();
+    let mut skipped = Vec::new();
+    for result in &mut extractor {
+        match result {
+            Ok(frame) => offsets.push(frame.byte_offset as usize),
+            Err(ExtractError::Resynchronized { skipped_bytes, .. }) => skipped.push(skipped_bytes),
+            Err(_) => {}
+        }
+    }
+
+    assert_eq!(skipped, [minor_sync.len() as u64]);
+    assert_eq!(offsets.len(), 41);
+    assert!(!offsets.contains(&corrupted));
+    assert!(offsets.contains(&(corrupted + minor_sync.len())));
+
+    // Without the minor sync scan, extraction resumes at the next major sync
+    let mut extractor = Extractor::default();
+    extractor.set_resync_window(0);
+    extractor.push_bytes(&stream);
+
+    let frames = extractor.filter(|result| result.is_ok()).count();
+    assert_eq!(frames, 26);
+    Ok(())
+}
+
+#[test]
+fn flush_partial_access_unit() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    extractor.push_bytes(&EXAMPLE_DATA[16..26]);
+
+    let frames = extractor.by_ref().filter(|result| result.is_ok()).count();
+    assert_eq!(frames, 2);
+    assert_eq!(extractor.byte_offset(), EXAMPLE_DATA.len() as u64);
+
+    assert!(matches!(
+        extractor.flush_partial(),
+        Err(ExtractError::PartialAccessUnit { bytes: 10 })
+    ));
+    assert_eq!(extractor.byte_offset(), EXAMPLE_DATA.len() as u64 + 10);
+    assert!(extractor.flush_partial().is_ok());
+
+    // Extraction starts over at the next sync pattern
+    extractor.push_bytes(EXAMPLE_DATA);
+    assert_eq!(extractor.filter(|result| result.is_ok()).count(), 2);
+    Ok(())
+}
+
+#[test]
+fn stream_boundary_marks_next_frame() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    extractor.push_bytes(&EXAMPLE_DATA[16..26]);
+    let starts = extractor
+        .by_ref()
+        .filter_map(Result::ok)
+        .map(|frame| frame.stream_start)
+        .collect::<Vec<_>>();
+    assert_eq!(starts, [false, false]);
+
+    // The partial access unit before the boundary is discarded
+    assert!(matches!(
+        extractor.push_stream_boundary(),
+        Err(ExtractError::PartialAccessUnit { bytes: 10 })
+    ));
+
+    // The timestamp packet of the next stream is read again
+    extractor.push_bytes(EXAMPLE_DATA);
+    let frames = extractor.filter_map(Result::ok).collect::<Vec<_>>();
+    assert_eq!(
+        frames
+            .iter()
+            .map(|frame| frame.stream_start)
+            .collect::<Vec<_>>(),
+        [true, false]
+    );
+    assert!(frames[0].timestamp.is_some());
+    assert_eq!(frames[0].byte_offset, EXAMPLE_DATA.len() as u64 + 10 + 16);
+    Ok(())
+}
+
+#[test]
+fn corrupt_regions_reported_at_their_offsets() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+
+    let (major_sync, minor_sync) = EXAMPLE_DATA[16..].split_at(84);
+    let mut stream = Vec::new();
+    let mut offsets = Vec::new();
+    // Followed by enough data for the resync scan to check its candidates
+    for _ in 0..20 {
+        offsets.push(stream.len());
+        stream.extend_from_slice(major_sync);
+        for _ in 0..20 {
+            offsets.push(stream.len());
+            stream.extend_from_slice(minor_sync);
+        }
+    }
+
+    // Header parity of the third access unit
+    let parity = offsets[2];
+    stream[parity + 2] ^= 0x40;
+
+    // Substream count of the second major sync, failing its CRC
+    let crc = offsets[21];
+    stream[crc + 20] ^= 0x10;
+
+    // Length of a minor sync access unit cut to its substream directory,
+    // with the check nibble fixed up to pass the parity check
+    let length = offsets[30];
+    stream[length] &= 0xF0;
+    stream[length + 1] = 0x03;
+    let header_parity = stream[length..length + 6]
+        .iter()
+        .fold(0, |parity, byte| parity ^ byte);
+    let check_nibble = (0xF ^ (header_parity >> 4) ^ header_parity) & 0xF;
+    stream[length] ^= check_nibble << 4;
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(&stream);
+
+    let mut regions = Vec::new();
+    for result in &mut extractor {
+        if let Err(ExtractError::Resynchronized {
+            offset,
+            skipped_bytes,
+            check,
+        }) = result
+        {
+            regions.push((offset as usize, skipped_bytes as usize, check));
+        }
+    }
+
+    assert_eq!(
+        regions,
+        [
+            (parity, offsets[3] - parity, FrameCheck::Parity),
+            (crc, offsets[22] - crc, FrameCheck::Crc),
+            (length, offsets[31] - length, FrameCheck::Length),
+        ]
+    );
+    Ok(())
+}
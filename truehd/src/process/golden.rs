@@ -0,0 +1,235 @@
+//! Golden decode tests.
+//!
+//! Each case decodes access units generated from [`EXAMPLE_DATA`] and
+//! compares the SHA-256 of the interleaved PCM and the lossless checks of the
+//! decoded substream against [`GOLDEN`]. The streams are built in memory, so
+//! the results do not depend on files or the environment.
+//!
+//! After a deliberate change of the decoded output, print the new table with
+//!
+//! ```text
+//! TRUEHD_BLESS_GOLDEN=1 cargo test -p truehd golden -- --nocapture
+//! ```
+//!
+//! and replace [`GOLDEN`] with it.
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+
+use crate::process::EXAMPLE_DATA;
+use crate::process::decode::{DecodedAccessUnit, Decoder};
+use crate::process::extract::Extractor;
+use crate::process::parse::Parser;
+use crate::structs::access_unit::AccessUnit;
+use crate::structs::restart_header::RestartSyncWord;
+
+/// Name, PCM SHA-256 and restart header lossless checks of each case
+const GOLDEN: &[(&str, &str, &[u8])] = &[
+    (
+        "example",
+        "55de6aaead45e7bb5abf792533cbdfc415ee90fc707a21b85bc61addc84b1978",
+        &[0x00, 0x00, 0x00],
+    ),
+    (
+        "31ea_2ch",
+        "3fa79617225b6c9392bc7986de4172b4bde8ba85bc48398f9523e0893e631dba",
+        &[0x00, 0x71, 0xEF],
+    ),
+    (
+        "31eb_6ch",
+        "71f8c1f23d4acfdfd74dda93aa67b5eaeca615620ad44c9a256917afd3eabe73",
+        &[0x00, 0xD1, 0xD8],
+    ),
+    (
+        "31ec_2ch",
+        "5c831abb4f50fd21607391cfbee8d38df181acfec8574f5ff90754765999c498",
+        &[0x00, 0xDD, 0xAF],
+    ),
+    (
+        "31ec_6ch",
+        "c117155529fe139963ff07f28f350a3ba8e99f3a74622c512e775f4ae166454b",
+        &[0x00, 0xC7, 0x0C],
+    ),
+];
+
+/// Example access units parsed three times over, so that the restart headers
+/// after the first verify the lossless check of their interval.
+fn example_access_units() -> Result<Vec<AccessUnit>> {
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(3));
+    extractor
+        .filter_map(Result::ok)
+        .map(|frame| parser.parse(&frame))
+        .collect()
+}
+
+/// Example access units recoded to `channels` channels of the first substream
+/// with `restart_sync_word`.
+///
+/// The residuals are pseudo-random values of `bits` bits, and channels past
+/// the second are unfiltered and take the shifts of the first. Without an
+/// encoder the lossless checks are computed by [`seal`] from the decoded
+/// output.
+fn generated_access_units(
+    restart_sync_word: RestartSyncWord,
+    channels: usize,
+    bits: u32,
+) -> Result<Vec<AccessUnit>> {
+    let mut access_units = example_access_units()?;
+
+    let mut seed = 0x2545_F491_4F6C_DD1Du64;
+    for block in access_units
+        .iter_mut()
+        .flat_map(|access_unit| &mut access_unit.substream_segment[0].block)
+    {
+        if let Some(restart_header) = &mut block.restart_header {
+            restart_header.restart_sync_word = restart_sync_word;
+            restart_header.max_chan = channels as u8 - 1;
+            restart_header.max_matrix_chan = channels as u8 - 1;
+            restart_header.ch_assign = std::array::from_fn(|chi| chi);
+        }
+        if block.restart_header.is_some()
+            && let Some(block_header) = &mut block.block_header
+        {
+            for chi in 2..channels {
+                block_header.quantiser_step_size[chi] = block_header.quantiser_step_size[0];
+                block_header.output_shift[chi] = block_header.output_shift[0];
+            }
+        }
+
+        for sample in &mut block.block_data {
+            for value in &mut sample[..channels] {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                *value = (seed as i32) >> (32 - bits);
+            }
+        }
+    }
+
+    seal(&mut access_units)?;
+    Ok(access_units)
+}
+
+/// Set the lossless check of every restart header of the first substream to
+/// the check of the decoded interval it closes.
+fn seal(access_units: &mut [AccessUnit]) -> Result<()> {
+    let mut decoder = Decoder::default();
+    let mut check = 0;
+    for access_unit in access_units {
+        if let Some(restart_header) = &mut access_unit.substream_segment[0].block[0].restart_header
+        {
+            restart_header.lossless_check = fold(check);
+            check = 0;
+        }
+        check ^= lossless_check_data(&decoder.decode_presentation(access_unit, 0)?);
+    }
+    Ok(())
+}
+
+/// Lossless check data of the decoded samples, with 32-bit samples rotated
+/// whole and other samples truncated to 24 bits
+fn lossless_check_data(decoded: &DecodedAccessUnit) -> u32 {
+    let mask = if decoded.bit_depth == 32 {
+        u32::MAX
+    } else {
+        0xFFFFFF
+    };
+    decoded.pcm_data[..decoded.sample_length]
+        .iter()
+        .flat_map(|sample| sample[..decoded.channel_count].iter().enumerate())
+        .fold(0, |check, (chi, &value)| {
+            check ^ (value as u32 & mask).rotate_left(chi as u32 & 7)
+        })
+}
+
+/// Lossless check as carried by the restart header, the bytes of `check_data`
+/// xored together
+fn fold(check_data: u32) -> u8 {
+    check_data.to_le_bytes().iter().fold(0, |acc, b| acc ^ b)
+}
+
+fn cases() -> Result<Vec<(&'static str, Vec<AccessUnit>)>> {
+    Ok(vec![
+        ("example", example_access_units()?),
+        (
+            "31ea_2ch",
+            generated_access_units(RestartSyncWord::A, 2, 16)?,
+        ),
+        (
+            "31eb_6ch",
+            generated_access_units(RestartSyncWord::B, 6, 16)?,
+        ),
+        (
+            "31ec_2ch",
+            generated_access_units(RestartSyncWord::C, 2, 28)?,
+        ),
+        (
+            "31ec_6ch",
+            generated_access_units(RestartSyncWord::C, 6, 28)?,
+        ),
+    ])
+}
+
+/// SHA-256 of the interleaved little-endian PCM of presentation 0 and the
+/// lossless checks of the restart headers
+fn decode(access_units: &[AccessUnit]) -> Result<(String, Vec<u8>)> {
+    let mut decoder = Decoder::default();
+    let mut hasher = Sha256::new();
+    let mut checks = Vec::new();
+    for access_unit in access_units {
+        if let Some(restart_header) = &access_unit.substream_segment[0].block[0].restart_header {
+            checks.push(restart_header.lossless_check);
+        }
+
+        let decoded = decoder.decode_presentation(access_unit, 0)?;
+        for sample in &decoded.pcm_data[..decoded.sample_length] {
+            for value in &sample[..decoded.channel_count] {
+                hasher.update(value.to_le_bytes());
+            }
+        }
+    }
+
+    // The decoder verified the lossless checks against its output
+    assert_eq!(decoder.stats().failures(), 0);
+
+    let hash = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    Ok((hash, checks))
+}
+
+#[test]
+fn golden_decodes() -> Result<()> {
+    let mut results = Vec::new();
+    for (name, access_units) in cases()? {
+        let (hash, checks) = decode(&access_units)?;
+        results.push((name, hash, checks));
+    }
+
+    if std::env::var_os("TRUEHD_BLESS_GOLDEN").is_some() {
+        println!("const GOLDEN: &[(&str, &str, &[u8])] = &[");
+        for (name, hash, checks) in &results {
+            let checks = checks
+                .iter()
+                .map(|check| format!("{check:#04X}"))
+                .collect::<Vec<_>>();
+            println!("    (\"{name}\", \"{hash}\", &[{}]),", checks.join(", "));
+        }
+        println!("];");
+        return Ok(());
+    }
+
+    assert_eq!(
+        results.iter().map(|(name, ..)| *name).collect::<Vec<_>>(),
+        GOLDEN.iter().map(|(name, ..)| *name).collect::<Vec<_>>()
+    );
+    for ((name, hash, checks), (_, golden_hash, golden_checks)) in results.iter().zip(GOLDEN) {
+        assert_eq!(hash, golden_hash, "PCM of {name} changed");
+        assert_eq!(checks, golden_checks, "lossless checks of {name} changed");
+    }
+    Ok(())
+}
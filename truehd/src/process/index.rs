@@ -0,0 +1,224 @@
+use crate::process::extract::{Extractor, Frame};
+use crate::structs::sync::{FormatInfo, MAJOR_SYNC_FBA, MAJOR_SYNC_FBB};
+use crate::utils::errors::ExtractError;
+use std::io::{ErrorKind, Read};
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Seek point at a major sync access unit.
+///
+/// Decoding can start from `byte_offset` with a fresh [`Extractor`],
+/// [`Parser`](crate::process::parse::Parser) and
+/// [`Decoder`](crate::process::decode::Decoder), since a major sync
+/// reinitializes all stream state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IndexEntry {
+    /// Input position of the first byte of the access unit
+    pub byte_offset: u64,
+    /// Number of access units preceding this one
+    pub access_unit: u64,
+    /// Number of samples preceding this access unit
+    pub sample: u64,
+    /// Sample rate signalled by the major sync
+    pub sample_rate: u32,
+}
+
+/// Major sync index of a TrueHD stream, built by [`Indexer`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StreamIndex {
+    /// Seek points in stream order
+    pub entries: Vec<IndexEntry>,
+    /// Total number of access units in the stream
+    pub access_units: u64,
+    /// Total number of samples in the stream
+    pub samples: u64,
+}
+
+impl StreamIndex {
+    /// Returns the last seek point at or before `sample`.
+    ///
+    /// Returns `None` if `sample` lies before the first major sync or beyond
+    /// the end of the stream.
+    pub fn au_for_sample(&self, sample: u64) -> Option<IndexEntry> {
+        if sample >= self.samples {
+            return None;
+        }
+
+        let pos = self.entries.partition_point(|e| e.sample <= sample);
+        pos.checked_sub(1).map(|i| self.entries[i])
+    }
+
+    /// Returns the last seek point at or before access unit `access_unit`.
+    pub fn au_for_access_unit(&self, access_unit: u64) -> Option<IndexEntry> {
+        if access_unit >= self.access_units {
+            return None;
+        }
+
+        let pos = self
+            .entries
+            .partition_point(|e| e.access_unit <= access_unit);
+        pos.checked_sub(1).map(|i| self.entries[i])
+    }
+}
+
+/// Scans a TrueHD stream and records a seek point at each major sync.
+///
+/// Only access unit headers and major sync format information are inspected;
+/// no parsing or decoding takes place. Sample positions are counted from the
+/// nominal access unit length of the most recent major sync. Access units
+/// before the first major sync are counted but cannot be indexed.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use truehd::process::index::Indexer;
+///
+/// let file = std::fs::File::open("stream.thd")?;
+/// let index = Indexer::scan(file)?;
+///
+/// if let Some(entry) = index.au_for_sample(48000 * 60) {
+///     println!("Start decoding at byte {}", entry.byte_offset);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct Indexer {
+    extractor: Extractor,
+    index: StreamIndex,
+    sample_rate: u32,
+    samples_per_au: u64,
+}
+
+impl Indexer {
+    /// Builds an index for the whole of `reader`.
+    pub fn scan<R: Read>(mut reader: R) -> std::io::Result<StreamIndex> {
+        let mut indexer = Self::default();
+        let mut buffer = vec![0; READ_CHUNK_SIZE];
+
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => indexer.push_bytes(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(indexer.finish())
+    }
+
+    /// Adds stream data and indexes all complete access units in it.
+    pub fn push_bytes(&mut self, data: &[u8]) {
+        self.extractor.push_bytes(data);
+
+        while let Some(result) = self.extractor.next() {
+            match result {
+                Ok(frame) => self.add_frame(&frame),
+                Err(ExtractError::InsufficientData) => break,
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// Returns the index built so far.
+    pub fn index(&self) -> &StreamIndex {
+        &self.index
+    }
+
+    /// Consumes the indexer, returning the index.
+    pub fn finish(self) -> StreamIndex {
+        self.index
+    }
+
+    fn add_frame(&mut self, frame: &Frame) {
+        if frame.is_major_sync() {
+            if let Some(audio_sampling_frequency_1) = Self::audio_sampling_frequency_1(frame) {
+                let format_info = FormatInfo {
+                    audio_sampling_frequency_1,
+                    ..Default::default()
+                };
+                if let (Ok(rate), Ok(samples_per_au)) = (
+                    format_info.sampling_frequency_1(),
+                    format_info.samples_per_au(),
+                ) {
+                    self.sample_rate = rate;
+                    self.samples_per_au = samples_per_au as u64;
+                }
+            }
+
+            if self.samples_per_au > 0 {
+                self.index.entries.push(IndexEntry {
+                    byte_offset: frame.byte_offset,
+                    access_unit: self.index.access_units,
+                    sample: self.index.samples,
+                    sample_rate: self.sample_rate,
+                });
+            }
+        }
+
+        self.index.access_units += 1;
+        self.index.samples += self.samples_per_au;
+    }
+
+    /// Reads the raw `audio_sampling_frequency_1` field of a major sync
+    fn audio_sampling_frequency_1(frame: &Frame) -> Option<u8> {
+        let data = frame.as_ref();
+        let format_sync = u32::from_be_bytes(data.get(4..8)?.try_into().ok()?);
+
+        match format_sync {
+            MAJOR_SYNC_FBA => Some(data.get(8)? >> 4),
+            MAJOR_SYNC_FBB => Some(data.get(9)? >> 4),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn index_seek_matches_full_decode() -> anyhow::Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::decode::Decoder;
+    use crate::process::parse::Parser;
+
+    let data = EXAMPLE_DATA.repeat(5);
+
+    let index = Indexer::scan(data.as_slice())?;
+    assert_eq!(index.entries.len(), 5);
+    assert_eq!(index.access_units, 10);
+    assert_eq!(index.samples, 400);
+    assert_eq!(
+        index.entries[2].byte_offset,
+        index.entries[0].byte_offset + 2 * EXAMPLE_DATA.len() as u64
+    );
+    assert_eq!(index.entries[2].access_unit, 4);
+    assert_eq!(index.entries[2].sample_rate, 48000);
+
+    assert_eq!(index.au_for_sample(0), Some(index.entries[0]));
+    assert_eq!(index.au_for_sample(239), Some(index.entries[2]));
+    assert_eq!(index.au_for_sample(240), Some(index.entries[3]));
+    assert_eq!(index.au_for_sample(400), None);
+    assert_eq!(index.au_for_access_unit(9), Some(index.entries[4]));
+
+    let decode = |data: &[u8]| -> anyhow::Result<Vec<[[i32; 16]; 160]>> {
+        let mut extractor = Extractor::default();
+        let mut parser = Parser::default();
+        let mut decoder = Decoder::default();
+        extractor.push_bytes(data);
+
+        let mut decoded = Vec::new();
+        for frame in extractor.filter_map(Result::ok) {
+            let access_unit = parser.parse(&frame)?;
+            decoded.push(*decoder.decode_presentation(&access_unit, 1)?.pcm_data);
+        }
+        Ok(decoded)
+    };
+
+    let full = decode(&data)?;
+    let entry = index.au_for_sample(250).unwrap();
+    let seeked = decode(&data[entry.byte_offset as usize..])?;
+
+    assert_eq!(seeked.len(), 4);
+    assert_eq!(seeked[..], full[entry.access_unit as usize..]);
+    Ok(())
+}
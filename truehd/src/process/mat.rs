@@ -0,0 +1,668 @@
+use crate::structs::access_unit::AccessUnit;
+use crate::utils::errors::DecodeError;
+use anyhow::{Result, bail};
+use log::warn;
+
+/// Size of an IEC 61937-9 burst carrying one MAT frame, in bytes.
+pub const MAT_BURST_SIZE: usize = 61440;
+
+/// Size of a MAT frame, the payload of a burst, in bytes.
+pub const MAT_FRAME_SIZE: usize = 61424;
+
+/// Number of access units a burst carries at the nominal rate.
+pub const MAT_ACCESS_UNITS: usize = 24;
+
+/// IEC 61937 data type of MAT bursts
+pub const IEC61937_DATA_TYPE_MAT: u16 = 0x16;
+
+const IEC61937_SYNC_PA: u16 = 0xF872;
+const IEC61937_SYNC_PB: u16 = 0x4E1F;
+const BURST_PREAMBLE_SIZE: usize = 8;
+
+/// Burst stream bytes of one access unit at the nominal rate
+const BYTES_PER_ACCESS_UNIT: usize = MAT_BURST_SIZE / MAT_ACCESS_UNITS;
+
+pub(crate) const MAT_START_CODE: [u8; 20] = [
+    0x07, 0x9E, 0x00, 0x03, 0x84, 0x01, 0x01, 0x01, 0x80, 0x00, 0x56, 0xA5, 0x3B, 0xF4, 0x81, 0x83,
+    0x49, 0x80, 0x77, 0xE0,
+];
+const MAT_MIDDLE_CODE: [u8; 12] = [
+    0xC3, 0xC1, 0x42, 0x49, 0x3B, 0xFA, 0x82, 0x83, 0x49, 0x80, 0x77, 0xE0,
+];
+const MAT_END_CODE: [u8; 16] = [
+    0xC3, 0xC2, 0xC0, 0xC4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x97, 0x11, 0x00, 0x00,
+];
+
+/// Positions of the codes in a MAT frame, the middle code ends half way
+/// through the burst.
+const MAT_CODES: [(usize, &[u8]); 3] = [
+    (0, &MAT_START_CODE),
+    (MAT_BURST_SIZE / 2 - MAT_MIDDLE_CODE.len(), &MAT_MIDDLE_CODE),
+    (MAT_FRAME_SIZE - MAT_END_CODE.len(), &MAT_END_CODE),
+];
+
+/// Access unit placed in the MAT frames by [`MatPacker::pack`].
+#[derive(Debug, Clone)]
+pub struct PackedAccessUnit {
+    /// Offset of the first byte of the access unit in the burst stream.
+    pub burst_offset: u64,
+
+    /// MAT frame completed while placing the access unit.
+    pub mat_frame: Option<Vec<u8>>,
+}
+
+/// Packs access units into Dolby MAT 2.0 frames for IEC 61937-9 transmission.
+///
+/// The burst stream carries [`MAT_ACCESS_UNITS`] access units per burst, so
+/// every sample of input timing is worth a fixed number of bytes. Each access
+/// unit is placed at the distance its input timing gives from the previous
+/// one, the gap is filled with zeros. The MAT codes count as padding where
+/// they fall into a gap, and are skipped over where they fall into an access
+/// unit. The same schedule keeps the decoder FIFO of the receiver within the
+/// limits the encoder checked.
+///
+/// ```rust,no_run
+/// use truehd::process::{MAX_PRESENTATIONS, extract::Extractor, parse::Parser};
+/// use truehd::process::mat::{MatPacker, iec61937_burst};
+///
+/// let mut extractor = Extractor::default();
+/// let mut parser = Parser::default();
+/// let mut packer = MatPacker::default();
+/// let mut output = Vec::new();
+///
+/// // Only the input timing is needed
+/// parser.set_required_presentations(&[false; MAX_PRESENTATIONS]);
+/// extractor.push_bytes(&std::fs::read("stream.thd")?);
+///
+/// for frame in extractor.filter_map(Result::ok) {
+///     let access_unit = parser.parse(&frame)?;
+///     if let Some(mat_frame) = packer.pack(&frame.data, &access_unit)?.mat_frame {
+///         output.extend(iec61937_burst(&mat_frame));
+///     }
+/// }
+/// if let Some(mat_frame) = packer.finish() {
+///     output.extend(iec61937_burst(&mat_frame));
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct MatPacker {
+    samples_per_au: usize,
+    /// Input timing and burst stream bytes taken by the previous access unit
+    prev: Option<(u16, usize)>,
+    /// MAT frame being filled
+    frame: Vec<u8>,
+    next_code: usize,
+    bursts: u64,
+}
+
+impl MatPacker {
+    /// Places the bytes of an access unit after the previous one.
+    ///
+    /// `data` is the complete access unit as extracted, `access_unit` is the
+    /// same access unit parsed. Fails for access units preceding the first
+    /// major sync, as the access unit length is not yet known.
+    pub fn pack(&mut self, data: &[u8], access_unit: &AccessUnit) -> Result<PackedAccessUnit> {
+        if let Some(major_sync_info) = &access_unit.major_sync_info {
+            self.samples_per_au = major_sync_info.format_info.samples_per_au()?;
+        }
+
+        if self.samples_per_au == 0 {
+            bail!(DecodeError::MissingMajorSync);
+        }
+
+        if access_unit.stream_start {
+            self.prev = None;
+        }
+
+        let input_timing = access_unit.input_timing;
+        let mut padding = match self.prev {
+            Some((prev_input_timing, prev_size)) => {
+                let delta = input_timing.wrapping_sub(prev_input_timing) as usize;
+                let spacing = delta * BYTES_PER_ACCESS_UNIT / self.samples_per_au;
+                match spacing.checked_sub(prev_size) {
+                    Some(padding) if padding < MAT_FRAME_SIZE / 2 => padding,
+                    _ => {
+                        warn!(
+                            "Input timing {input_timing} after {prev_input_timing} does not fit {prev_size} bytes of the previous access unit, packing without padding"
+                        );
+                        0
+                    }
+                }
+            }
+            None => 0,
+        };
+
+        let mut size = data.len();
+        let mut remaining = data;
+        let mut burst_offset = None;
+        let mut mat_frame = None;
+
+        while padding > 0 || !remaining.is_empty() || self.at_code() {
+            if self.at_code() {
+                let mut code_len = self.insert_code();
+                if self.next_code == 0 {
+                    // The gap to the next burst and its preamble take up space as well
+                    code_len += MAT_BURST_SIZE - MAT_FRAME_SIZE;
+                    mat_frame = Some(self.take_frame());
+                }
+
+                let as_padding = padding.min(code_len);
+                padding -= as_padding;
+                size += code_len - as_padding;
+            }
+
+            let room = MAT_CODES[self.next_code].0 - self.frame.len();
+            if padding > 0 {
+                let zeros = padding.min(room);
+                self.frame.resize(self.frame.len() + zeros, 0);
+                padding -= zeros;
+                continue;
+            }
+
+            if !remaining.is_empty() {
+                burst_offset.get_or_insert(self.burst_offset());
+                let (head, tail) = remaining.split_at(remaining.len().min(room));
+                self.frame.extend_from_slice(head);
+                remaining = tail;
+            }
+        }
+
+        self.prev = Some((input_timing, size));
+
+        Ok(PackedAccessUnit {
+            burst_offset: burst_offset.unwrap_or_else(|| self.burst_offset()),
+            mat_frame,
+        })
+    }
+
+    /// Completes the MAT frame being filled with zeros.
+    ///
+    /// Returns `None` when no access unit is waiting for transmission.
+    pub fn finish(&mut self) -> Option<Vec<u8>> {
+        self.prev = None;
+        if self.frame.len() <= MAT_START_CODE.len() {
+            self.frame.clear();
+            self.next_code = 0;
+            return None;
+        }
+
+        while self.next_code != 0 {
+            self.frame.resize(MAT_CODES[self.next_code].0, 0);
+            self.insert_code();
+        }
+        Some(self.take_frame())
+    }
+
+    /// Returns the number of MAT frames completed so far.
+    pub fn mat_frames(&self) -> u64 {
+        self.bursts
+    }
+
+    fn at_code(&self) -> bool {
+        MAT_CODES[self.next_code].0 == self.frame.len()
+    }
+
+    /// Appends the next MAT code and returns its length.
+    fn insert_code(&mut self) -> usize {
+        let code = MAT_CODES[self.next_code].1;
+        self.frame.extend_from_slice(code);
+        self.next_code = (self.next_code + 1) % MAT_CODES.len();
+        code.len()
+    }
+
+    fn take_frame(&mut self) -> Vec<u8> {
+        self.bursts += 1;
+        std::mem::replace(&mut self.frame, Vec::with_capacity(MAT_FRAME_SIZE))
+    }
+
+    fn burst_offset(&self) -> u64 {
+        self.bursts * MAT_BURST_SIZE as u64 + (BURST_PREAMBLE_SIZE + self.frame.len()) as u64
+    }
+}
+
+/// Wraps a MAT frame in an IEC 61937 burst of 16-bit little-endian words.
+pub fn iec61937_burst(mat_frame: &[u8]) -> Vec<u8> {
+    let mut burst = Vec::with_capacity(MAT_BURST_SIZE);
+    for word in [
+        IEC61937_SYNC_PA,
+        IEC61937_SYNC_PB,
+        IEC61937_DATA_TYPE_MAT,
+        MAT_FRAME_SIZE as u16,
+    ] {
+        burst.extend_from_slice(&word.to_be_bytes());
+    }
+    burst.extend_from_slice(mat_frame);
+    burst.resize(MAT_BURST_SIZE, 0);
+
+    for word in burst.chunks_exact_mut(2) {
+        word.swap(0, 1);
+    }
+    burst
+}
+
+/// Statistics of the MAT encapsulation unwrapped by a [`MatUnpacker`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MatEncapsulation {
+    /// Number of MAT frames unwrapped, in bursts or bare.
+    pub bursts: u64,
+
+    /// Number of padding bytes discarded, the zeros between access units and
+    /// the bytes outside of MAT frames.
+    pub padding_bytes: u64,
+}
+
+/// Unwraps access units from MAT frames, bare or in IEC 61937-9 bursts.
+///
+/// The inverse of [`MatPacker`] and [`iec61937_burst`]: the bursts are found by
+/// their preamble in a stream of 16-bit little-endian words, bare MAT frames
+/// by their start code. The MAT codes are stripped from the MAT frames and the
+/// zeros padding the gaps between access units are discarded, leaving the
+/// access units back to back, including those continuing from one MAT frame
+/// into the next. Bytes before a preamble or start code and bursts of other
+/// data types are skipped.
+#[derive(Debug, Default)]
+pub struct MatUnpacker {
+    /// Input bytes not yet unwrapped
+    pending: Vec<u8>,
+    /// Bytes of the access unit left to copy from the next MAT frame
+    access_unit_remaining: usize,
+    stats: MatEncapsulation,
+}
+
+impl MatUnpacker {
+    /// Returns the position of the first MAT burst preamble in `data`.
+    pub fn find_burst(data: &[u8]) -> Option<usize> {
+        let [pa_hi, pa_lo] = IEC61937_SYNC_PA.to_be_bytes();
+        let [pb_hi, pb_lo] = IEC61937_SYNC_PB.to_be_bytes();
+        data.windows(5).position(|window| {
+            window[..4] == [pa_lo, pa_hi, pb_lo, pb_hi]
+                && u16::from(window[4] & 0x7F) == IEC61937_DATA_TYPE_MAT
+        })
+    }
+
+    /// Returns the position of the first MAT burst preamble or bare MAT frame
+    /// in `data`.
+    pub fn find_frame(data: &[u8]) -> Option<usize> {
+        Self::locate_frame(data).map(|(at, _)| at)
+    }
+
+    /// Returns the position of the first MAT frame in `data`, and whether it
+    /// is carried in a burst.
+    fn locate_frame(data: &[u8]) -> Option<(usize, bool)> {
+        let burst = Self::find_burst(data);
+        // The start code of a burst is byte swapped, only search before it
+        let end = burst.map_or(data.len(), |at| {
+            (at + MAT_START_CODE.len() - 1).min(data.len())
+        });
+        match data[..end]
+            .windows(MAT_START_CODE.len())
+            .position(|window| window == MAT_START_CODE)
+        {
+            Some(at) if burst.is_none_or(|burst| at < burst) => Some((at, false)),
+            _ => burst.map(|at| (at, true)),
+        }
+    }
+
+    /// Appends the access unit bytes of the bursts completed by `data` to
+    /// `output`.
+    pub fn push(&mut self, data: &[u8], output: &mut impl Extend<u8>) {
+        self.pending.extend_from_slice(data);
+
+        let mut start = 0;
+        loop {
+            let Some((at, burst)) = Self::locate_frame(&self.pending[start..]) else {
+                // A preamble or start code may continue in the next data
+                let skipped = (self.pending.len() - start).saturating_sub(MAT_START_CODE.len() - 1);
+                self.stats.padding_bytes += skipped as u64;
+                start += skipped;
+                break;
+            };
+            self.stats.padding_bytes += at as u64;
+            start += at;
+
+            let (header, length) = if burst {
+                let Some(preamble) = self.pending.get(start..start + BURST_PREAMBLE_SIZE) else {
+                    break;
+                };
+                let length = u16::from_le_bytes([preamble[6], preamble[7]]) as usize;
+                (BURST_PREAMBLE_SIZE, length)
+            } else {
+                (0, MAT_FRAME_SIZE)
+            };
+            let end = start + header + length;
+            if self.pending.len() < end {
+                break;
+            }
+
+            let mut mat_frame = self.pending[start + header..end].to_vec();
+            if burst {
+                for word in mat_frame.chunks_exact_mut(2) {
+                    word.swap(0, 1);
+                }
+            }
+            self.unwrap_frame(&mat_frame, output);
+            self.stats.bursts += 1;
+            start = end;
+        }
+
+        self.pending.drain(..start);
+    }
+
+    /// Discards the bytes of an incomplete MAT frame at the end of the input.
+    ///
+    /// Returns the number of bytes discarded other than padding.
+    pub fn finish(&mut self) -> usize {
+        self.access_unit_remaining = 0;
+        let discarded = if self.pending.iter().all(|&b| b == 0) {
+            self.stats.padding_bytes += self.pending.len() as u64;
+            0
+        } else {
+            self.pending.len()
+        };
+        self.pending.clear();
+        discarded
+    }
+
+    /// Returns the MAT frames unwrapped and the padding discarded so far.
+    pub fn stats(&self) -> MatEncapsulation {
+        self.stats
+    }
+
+    fn unwrap_frame(&mut self, mat_frame: &[u8], output: &mut impl Extend<u8>) {
+        let mut position = 0;
+        for (code_position, code) in MAT_CODES {
+            let code_position = code_position.clamp(position, mat_frame.len());
+            if mat_frame[code_position..].get(..code.len()) != Some(code) {
+                warn!(
+                    "MAT code missing at byte {code_position} of MAT frame {}",
+                    self.stats.bursts
+                );
+            }
+            self.unwrap_payload(&mat_frame[position..code_position], output);
+            position = (code_position + code.len()).min(mat_frame.len());
+        }
+        self.unwrap_payload(&mat_frame[position..], output);
+    }
+
+    /// Copies the access units of a MAT frame section between codes, skipping
+    /// the padding between them.
+    fn unwrap_payload(&mut self, mut payload: &[u8], output: &mut impl Extend<u8>) {
+        while !payload.is_empty() {
+            if self.access_unit_remaining == 0 {
+                let Some(&[hi, lo]) = payload.first_chunk() else {
+                    self.stats.padding_bytes += payload.len() as u64;
+                    return;
+                };
+
+                // The first word of an access unit holds its length in words
+                let word = u16::from_be_bytes([hi, lo]);
+                if word == 0 {
+                    self.stats.padding_bytes += 2;
+                    payload = &payload[2..];
+                    continue;
+                }
+                self.access_unit_remaining = (((word & 0xFFF) as usize) << 1).max(2);
+            }
+
+            let (head, tail) = payload.split_at(self.access_unit_remaining.min(payload.len()));
+            output.extend(head.iter().copied());
+            self.access_unit_remaining -= head.len();
+            payload = tail;
+        }
+    }
+}
+
+/// Returns the frame rate of the 8-channel 16-bit link carrying the bursts of
+/// a stream at the given sampling frequency.
+pub fn iec61937_sample_rate(sampling_frequency: u32) -> u32 {
+    if sampling_frequency.is_multiple_of(44100) {
+        176400
+    } else {
+        192000
+    }
+}
+
+/// Parses the example access units over and over, with the given input
+/// timing for each of them.
+#[cfg(test)]
+fn example_access_units(input_timings: &[u16]) -> Vec<(Vec<u8>, AccessUnit)> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    let frames: Vec<_> = extractor.filter_map(Result::ok).collect();
+
+    let mut access_units = Vec::new();
+    let mut parser = Parser::default();
+    for (i, &input_timing) in input_timings.iter().enumerate() {
+        let frame = &frames[i % frames.len()];
+        if frame.is_major_sync() {
+            parser = Parser::default();
+        }
+        let mut access_unit = parser.parse(frame).unwrap();
+        access_unit.input_timing = input_timing;
+        access_units.push((frame.data.to_vec(), access_unit));
+    }
+    access_units
+}
+
+#[test]
+fn burst_spacing_follows_input_timing() -> Result<()> {
+    // Uneven intervals around the nominal 40 samples, wrapping around
+    let input_timings: Vec<u16> = (0..200u16)
+        .scan(0xF000u16, |timing, i| {
+            *timing = timing.wrapping_add([40, 31, 49, 40, 36][i as usize % 5]);
+            Some(*timing)
+        })
+        .collect();
+    let access_units = example_access_units(&input_timings);
+
+    let mut packer = MatPacker::default();
+    let mut mat_frames = Vec::new();
+    let mut offsets = Vec::new();
+    for (data, access_unit) in &access_units {
+        let packed = packer.pack(data, access_unit)?;
+        offsets.push(packed.burst_offset);
+        mat_frames.extend(packed.mat_frame);
+    }
+    mat_frames.extend(packer.finish());
+
+    // The first access unit follows the start code, which pushes it past its
+    // place at the start of the burst payload. 2560 bytes per 40 samples
+    // from there on.
+    assert_eq!(
+        offsets[0],
+        (BURST_PREAMBLE_SIZE + MAT_START_CODE.len()) as u64
+    );
+    for (i, offset) in offsets.iter().enumerate().skip(1) {
+        let samples = input_timings[i].wrapping_sub(input_timings[0]) as u64;
+        assert_eq!(
+            *offset,
+            BURST_PREAMBLE_SIZE as u64 + samples * 64,
+            "access unit {i}"
+        );
+    }
+
+    assert_eq!(mat_frames.len(), 9);
+    assert_eq!(packer.mat_frames(), 9);
+    for mat_frame in &mat_frames {
+        assert_eq!(mat_frame.len(), MAT_FRAME_SIZE);
+        for (position, code) in MAT_CODES {
+            assert_eq!(&mat_frame[position..position + code.len()], code);
+        }
+    }
+
+    // Access units running into a MAT code continue after it
+    let mut payload = Vec::new();
+    for mat_frame in &mat_frames {
+        let mut bytes: Vec<_> = mat_frame.iter().map(Some).collect();
+        for (position, code) in MAT_CODES {
+            bytes[position..position + code.len()].fill(None);
+        }
+        payload.extend([None; BURST_PREAMBLE_SIZE]);
+        payload.extend(bytes);
+        payload.extend([None; MAT_BURST_SIZE - BURST_PREAMBLE_SIZE - MAT_FRAME_SIZE]);
+    }
+    for ((data, _), offset) in access_units.iter().zip(offsets) {
+        let packed: Vec<u8> = payload[offset as usize..]
+            .iter()
+            .flatten()
+            .take(data.len())
+            .map(|&&b| b)
+            .collect();
+        assert_eq!(packed, *data);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn finish_completes_the_burst() -> Result<()> {
+    let access_units = example_access_units(&[0, 40, 80]);
+
+    let mut packer = MatPacker::default();
+    for (data, access_unit) in &access_units {
+        assert!(packer.pack(data, access_unit)?.mat_frame.is_none());
+    }
+    let mat_frame = packer.finish().unwrap();
+    assert!(packer.finish().is_none());
+
+    let burst = iec61937_burst(&mat_frame);
+    assert_eq!(burst.len(), MAT_BURST_SIZE);
+    assert_eq!(burst[..8], [0x72, 0xF8, 0x1F, 0x4E, 0x16, 0x00, 0xF0, 0xEF]);
+    assert_eq!(burst[8..10], [MAT_START_CODE[1], MAT_START_CODE[0]]);
+    assert!(
+        burst[BURST_PREAMBLE_SIZE + MAT_FRAME_SIZE..]
+            .iter()
+            .all(|&b| b == 0)
+    );
+
+    // Access units before the first major sync have no known length
+    let (_, access_unit) = &access_units[1];
+    assert!(MatPacker::default().pack(&[0; 4], access_unit).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn mat_encapsulation_unwrapped() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+
+    // Two access units after the timestamp, the second copy running into the middle code
+    let access_units = &EXAMPLE_DATA[16..];
+    let mut mat_frame = MAT_START_CODE.to_vec();
+    mat_frame.extend_from_slice(access_units);
+    mat_frame.resize(mat_frame.len() + 100, 0);
+    mat_frame.resize(MAT_CODES[1].0 - 30, 0);
+    mat_frame.extend_from_slice(&access_units[..30]);
+    mat_frame.extend_from_slice(&MAT_MIDDLE_CODE);
+    mat_frame.extend_from_slice(&access_units[30..]);
+    mat_frame.resize(MAT_CODES[2].0, 0);
+    mat_frame.extend_from_slice(&MAT_END_CODE);
+
+    let mut burst = vec![0x72, 0xF8, 0x1F, 0x4E, 0x16, 0x00, 0xF0, 0xEF];
+    for word in mat_frame.chunks_exact(2) {
+        burst.extend([word[1], word[0]]);
+    }
+    burst.resize(MAT_BURST_SIZE, 0);
+    assert_eq!(burst, iec61937_burst(&mat_frame));
+
+    // Preceded by a header, as in a WAV file
+    let mut input = vec![0x55; 44];
+    input.extend_from_slice(&burst);
+    input.extend_from_slice(&burst);
+
+    let mut extractor = Extractor::default();
+    let mut frames = Vec::new();
+    for chunk in input.chunks(4099) {
+        extractor.push_bytes(chunk);
+        frames.extend(extractor.by_ref().filter_map(Result::ok));
+    }
+    extractor.flush_partial()?;
+
+    let sizes = [84, 20].repeat(4);
+    assert_eq!(frames.len(), sizes.len());
+    let mut offset = 0;
+    for (frame, size) in frames.iter().zip(sizes) {
+        let position = offset % access_units.len();
+        assert_eq!(frame.byte_offset, offset as u64);
+        assert_eq!(*frame.data, access_units[position..position + size]);
+        offset += size;
+    }
+
+    let padding = MAT_BURST_SIZE - BURST_PREAMBLE_SIZE - 48 - 2 * access_units.len();
+    assert_eq!(
+        extractor.mat_encapsulation(),
+        Some(MatEncapsulation {
+            bursts: 2,
+            padding_bytes: (44 + 2 * padding) as u64,
+        })
+    );
+
+    // Plain access units pass through
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    assert_eq!(extractor.by_ref().filter_map(Result::ok).count(), 2);
+    assert_eq!(extractor.mat_encapsulation(), None);
+
+    Ok(())
+}
+
+#[test]
+fn bare_mat_frames_unwrapped() -> Result<()> {
+    use crate::process::extract::{Extractor, Framing};
+
+    let input_timings: Vec<u16> = (0..100u16).map(|i| i * 40).collect();
+    let access_units = example_access_units(&input_timings);
+
+    // MAT frames without access units between two runs of access units
+    let mut padding_frame = vec![0; MAT_FRAME_SIZE];
+    for (position, code) in MAT_CODES {
+        padding_frame[position..position + code.len()].copy_from_slice(code);
+    }
+    let mut mat_frames = vec![padding_frame.clone()];
+    for run in access_units.chunks(50) {
+        let mut packer = MatPacker::default();
+        for (data, access_unit) in run {
+            mat_frames.extend(packer.pack(data, access_unit)?.mat_frame);
+        }
+        mat_frames.extend(packer.finish());
+        mat_frames.push(padding_frame.clone());
+    }
+
+    let mut input = vec![0x55; 44];
+    input.extend(mat_frames.concat());
+
+    for (framing, chunk_size) in [(Framing::Auto, 65536), (Framing::Mat, 1000)] {
+        let mut extractor = Extractor::default();
+        extractor.set_input_framing(framing);
+        let mut frames = Vec::new();
+        for chunk in input.chunks(chunk_size) {
+            extractor.push_bytes(chunk);
+            frames.extend(extractor.by_ref().filter_map(Result::ok));
+        }
+        extractor.flush_partial()?;
+
+        assert_eq!(frames.len(), access_units.len(), "{framing:?}");
+        for (frame, (data, _)) in frames.iter().zip(&access_units) {
+            assert_eq!(*frame.data, data[..]);
+        }
+        assert_eq!(
+            extractor.mat_encapsulation().map(|mat| mat.bursts),
+            Some(mat_frames.len() as u64)
+        );
+    }
+
+    // Not unwrapped when the input is known to be access units
+    let mut extractor = Extractor::default();
+    extractor.set_input_framing(Framing::Raw);
+    extractor.push_bytes(&input);
+    assert_eq!(extractor.mat_encapsulation(), None);
+
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use crate::process::MAX_PRESENTATIONS;
+use crate::structs::access_unit::AccessUnit;
+use crate::structs::evolution::EvoPayload;
+use crate::structs::oamd::ObjectAudioMetadataPayload;
+use crate::utils::errors::DecodeError;
+use anyhow::{Result, bail};
+
+/// EVO payload ID of object audio metadata
+pub const OAMD_PAYLOAD_ID: u32 = 11;
+
+/// Undecoded payload of the EVO frame of an access unit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawEvoPayload {
+    /// EVO payload ID, [`OAMD_PAYLOAD_ID`] for object audio metadata.
+    pub id: u32,
+
+    /// Sample offset of the payload relative to the start of the access unit.
+    pub sample_offset: u64,
+
+    /// Payload bytes.
+    pub data: Vec<u8>,
+}
+
+/// Object audio metadata of a single access unit.
+#[derive(Debug, Clone)]
+pub struct AccessUnitMetadata {
+    /// Stream position of the first sample of this access unit.
+    pub sample_position: u64,
+
+    /// Sampling frequency in Hz from the most recent major sync.
+    pub sampling_frequency: u32,
+
+    /// Number of samples in this access unit.
+    pub sample_length: usize,
+
+    /// OAMD payloads carried by the access unit.
+    ///
+    /// `evo_sample_offset` is relative to `sample_position`.
+    pub oamd: Vec<ObjectAudioMetadataPayload>,
+}
+
+/// Extracts object audio metadata from access units without decoding audio.
+///
+/// Only the EVO frame in the access unit extra data and the major sync are
+/// read, so substream segments need not be parsed at all. Disabling every
+/// presentation on the [`Parser`](crate::process::parse::Parser) skips them:
+///
+/// ```rust,no_run
+/// use truehd::process::{MAX_PRESENTATIONS, extract::Extractor, parse::Parser};
+/// use truehd::process::metadata::OamdExtractor;
+///
+/// let mut extractor = Extractor::default();
+/// let mut parser = Parser::default();
+/// let mut oamd_extractor = OamdExtractor::default();
+///
+/// parser.set_required_presentations(&[false; MAX_PRESENTATIONS]);
+/// extractor.push_bytes(&std::fs::read("stream.thd")?);
+///
+/// for frame in extractor.filter_map(Result::ok) {
+///     let metadata = oamd_extractor.extract(&parser.parse(&frame)?)?;
+///     for oamd in &metadata.oamd {
+///         println!("OAMD at sample {}", metadata.sample_position + oamd.evo_sample_offset);
+///     }
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct OamdExtractor {
+    sampling_frequency: u32,
+    samples_per_au: usize,
+    sample_position: u64,
+}
+
+impl OamdExtractor {
+    /// Returns the OAMD payloads of an access unit and advances the sample position.
+    ///
+    /// Fails for access units preceding the first major sync, as the access
+    /// unit length is not yet known.
+    pub fn extract(&mut self, access_unit: &AccessUnit) -> Result<AccessUnitMetadata> {
+        if let Some(major_sync_info) = &access_unit.major_sync_info {
+            self.sampling_frequency = major_sync_info.format_info.sampling_frequency_1()?;
+            self.samples_per_au = major_sync_info.format_info.samples_per_au()?;
+        }
+
+        if self.samples_per_au == 0 {
+            bail!(DecodeError::MissingMajorSync);
+        }
+
+        // Trailing zero samples are only known when a substream was parsed
+        let zero_samples = (0..MAX_PRESENTATIONS)
+            .rev()
+            .find_map(|i| access_unit.substream_segment[i].terminator.as_ref())
+            .filter(|terminator| terminator.zero_samples_indicated)
+            .map_or(0, |terminator| terminator.zero_samples as usize);
+
+        let metadata = AccessUnitMetadata {
+            sample_position: self.sample_position,
+            sampling_frequency: self.sampling_frequency,
+            sample_length: self.samples_per_au.saturating_sub(zero_samples),
+            oamd: read_oamd_payloads(access_unit)?,
+        };
+        self.sample_position += metadata.sample_length as u64;
+
+        Ok(metadata)
+    }
+
+    /// Returns the number of samples in all access units extracted so far.
+    pub fn sample_position(&self) -> u64 {
+        self.sample_position
+    }
+}
+
+/// Copies all payloads from the EVO frame of an access unit, including OAMD.
+pub fn read_evo_payloads(access_unit: &AccessUnit) -> Vec<RawEvoPayload> {
+    let Some(evo_frame) = access_unit
+        .extra_data
+        .as_ref()
+        .and_then(|extra_data| extra_data.evo_frame.as_ref())
+    else {
+        return Vec::new();
+    };
+
+    evo_frame
+        .evo_payloads
+        .iter()
+        .map(|evo_payload| RawEvoPayload {
+            id: evo_payload.evo_payload_id,
+            sample_offset: evo_payload.evo_payload_config.smploffst.unwrap_or_default() as u64,
+            data: evo_payload.evo_payload_byte.clone(),
+        })
+        .collect()
+}
+
+/// Reads all OAMD payloads from the EVO frame of an access unit.
+pub(crate) fn read_oamd_payloads(
+    access_unit: &AccessUnit,
+) -> Result<Vec<ObjectAudioMetadataPayload>> {
+    oamd_evo_payloads(access_unit)
+        .map(read_oamd_payload)
+        .collect()
+}
+
+/// Returns the undecoded OAMD payloads of the EVO frame of an access unit.
+pub(crate) fn oamd_evo_payloads(access_unit: &AccessUnit) -> impl Iterator<Item = &EvoPayload> {
+    access_unit
+        .extra_data
+        .as_ref()
+        .and_then(|extra_data| extra_data.evo_frame.as_ref())
+        .into_iter()
+        .flat_map(|evo_frame| &evo_frame.evo_payloads)
+        .filter(|evo_payload| evo_payload.evo_payload_id == OAMD_PAYLOAD_ID)
+}
+
+/// Reads an OAMD payload and its sample offset from an EVO payload.
+pub(crate) fn read_oamd_payload(evo_payload: &EvoPayload) -> Result<ObjectAudioMetadataPayload> {
+    let mut oamd = ObjectAudioMetadataPayload::read(&evo_payload.evo_payload_byte)?;
+    oamd.evo_sample_offset = evo_payload.evo_payload_config.smploffst.unwrap_or_default() as u64;
+    Ok(oamd)
+}
+
+#[test]
+fn metadata_only_positions_match_decode() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::decode::Decoder;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let data = EXAMPLE_DATA.repeat(3);
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    let mut decoder = Decoder::default();
+    extractor.push_bytes(&data);
+    let mut decoded_lengths = Vec::new();
+    for frame in extractor.filter_map(Result::ok) {
+        let access_unit = parser.parse(&frame)?;
+        decoded_lengths.push(decoder.decode_presentation(&access_unit, 1)?.sample_length);
+    }
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    let mut oamd_extractor = OamdExtractor::default();
+    parser.set_required_presentations(&[false; MAX_PRESENTATIONS]);
+    extractor.push_bytes(&data);
+
+    let mut position = 0;
+    for (frame, decoded_length) in extractor.filter_map(Result::ok).zip(&decoded_lengths) {
+        let access_unit = parser.parse(&frame)?;
+        assert!(access_unit.substream_segment[0].block.is_empty());
+
+        let metadata = oamd_extractor.extract(&access_unit)?;
+        assert_eq!(metadata.sample_position, position);
+        assert_eq!(metadata.sample_length, *decoded_length);
+        assert_eq!(metadata.sampling_frequency, 48000);
+        assert!(metadata.oamd.is_empty());
+        position += metadata.sample_length as u64;
+    }
+    assert_eq!(oamd_extractor.sample_position(), 240);
+
+    Ok(())
+}
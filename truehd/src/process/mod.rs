@@ -1,4 +1,5 @@
 use std::fmt::Display;
+use std::time::Duration;
 
 /// Frame extraction from audio bitstreams.
 ///
@@ -18,6 +19,79 @@ pub mod parse;
 /// [`DecodedAccessUnit`](decode::DecodedAccessUnit) objects containing PCM audio data.
 pub mod decode;
 
+/// Pull-based decoding from an [`io::Read`](std::io::Read) source.
+///
+/// Provides the [`StreamingDecoder`](stream::StreamingDecoder), which combines
+/// extraction, parsing and decoding into an iterator of decoded access units.
+pub mod stream;
+
+/// Major sync indexing for seeking.
+///
+/// Provides the [`Indexer`](index::Indexer), which scans a bitstream and builds a
+/// [`StreamIndex`](index::StreamIndex) mapping sample positions to byte offsets.
+pub mod index;
+
+/// Object audio metadata extraction without audio decoding.
+///
+/// Provides the [`OamdExtractor`](metadata::OamdExtractor), which reads OAMD
+/// payloads and their sample positions from parsed access units.
+pub mod metadata;
+
+/// Multi-threaded decoding of access unit batches.
+///
+/// Provides the [`ParallelDecoder`](parallel::ParallelDecoder), which splits
+/// batches at major syncs and decodes the segments concurrently. Not available
+/// on `wasm32-unknown-unknown`, which cannot spawn threads.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub mod parallel;
+
+/// Dolby MAT 2.0 encapsulation for IEC 61937-9 transmission.
+///
+/// Provides the [`MatPacker`](mat::MatPacker), which places access units in MAT
+/// frames according to their input timing.
+pub mod mat;
+
+/// Stream summary without audio decoding.
+///
+/// Provides the [`StreamAnalyzer`](analyze::StreamAnalyzer), which summarizes
+/// a stream pushed in chunks as a [`StreamReport`](analyze::StreamReport).
+pub mod analyze;
+
+/// Threaded decoding of a bitstream into a sink.
+///
+/// Provides the [`Pipeline`](pipeline::Pipeline), which extracts, parses and
+/// decodes an [`io::Read`](std::io::Read) source on one or more threads,
+/// conceals failed access units and delivers the decoded access units and
+/// [`PipelineEvent`](pipeline::PipelineEvent)s to a
+/// [`Sink`](pipeline::Sink). Not available on `wasm32-unknown-unknown`.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub mod pipeline;
+
+#[cfg(test)]
+mod golden;
+
+/// Clock of the processing time [`Metrics`].
+///
+/// `wasm32-unknown-unknown` has no clock and `std::time::Instant::now` panics
+/// there, so processing times stay zero.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) use std::time::Instant;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+#[derive(Clone, Copy)]
+pub(crate) struct Instant;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl Instant {
+    pub(crate) fn now() -> Self {
+        Self
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
 pub const EXAMPLE_DATA: &[u8] = &[
     0x01, 0x10, 0x00, 0x01, 0x00, 0x23, 0x00, 0x45, 0x00, 0x16, 0x00, 0x19, 0x00, 0x11, 0x80, 0x00,
     0xF0, 0x2A, 0xFF, 0xAC, 0xF8, 0x72, 0x6F, 0xBA, 0x00, 0x00, 0x80, 0x01, 0xB7, 0x52, 0x00, 0x00,
@@ -48,6 +122,20 @@ impl PresentationMap {
         }
     }
 
+    /// Returns the presentation map of an FBB stream.
+    ///
+    /// With two substreams, substream 0 carries a 2ch downmix of the
+    /// presentation decoded from both substreams.
+    pub fn with_fbb_substreams(substreams: usize) -> Self {
+        Self {
+            masks: if substreams > 1 {
+                [1, 3, 0, 0]
+            } else {
+                [1, 0, 0, 0]
+            },
+        }
+    }
+
     pub fn presentation_type_by_index(&self, index: usize) -> PresentationType {
         if index >= self.masks.len() {
             return PresentationType::Invalid;
@@ -79,6 +167,22 @@ impl PresentationMap {
             .map(|(i, _)| i)
     }
 
+    /// Returns the number of substreams required by the presentations.
+    pub fn required_substreams(&self) -> usize {
+        let mask = self.masks.iter().fold(0, |mask, &m| mask | m);
+        (u8::BITS - mask.leading_zeros()) as usize
+    }
+
+    /// Returns the map without the presentations requiring a substream
+    /// beyond the first `substreams`.
+    pub fn limited_to(&self, substreams: usize) -> Self {
+        Self {
+            masks: self
+                .masks
+                .map(|mask| if mask >> substreams != 0 { 0 } else { mask }),
+        }
+    }
+
     pub fn substream_mask_by_required_presentations(
         &self,
         required_presentations: &[bool; MAX_PRESENTATIONS],
@@ -94,6 +198,7 @@ impl PresentationMap {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PresentationType {
     Invalid,
     CopyOf(usize),
@@ -112,6 +217,56 @@ impl Display for PresentationType {
     }
 }
 
+/// Timing and throughput counters of parsing and decoding.
+///
+/// Collected by [`Parser`](parse::Parser) and [`Decoder`](decode::Decoder)
+/// once enabled with `enable_metrics`, and taken with `take_metrics`. The
+/// parser fills the access unit, byte and block counts, the decoder the
+/// sample count, and each records the time spent in its own calls.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Metrics {
+    /// Number of access units parsed successfully.
+    pub access_units: u64,
+    /// Number of bytes of the parsed access units.
+    pub bytes: u64,
+    /// Number of blocks parsed per substream.
+    pub blocks: [u64; MAX_PRESENTATIONS],
+    /// Number of samples decoded successfully.
+    pub samples: u64,
+    /// Time spent in [`Parser::parse`](parse::Parser::parse).
+    pub parse_time: Duration,
+    /// Time spent in [`Decoder::decode_presentation`](decode::Decoder::decode_presentation).
+    pub decode_time: Duration,
+}
+
+impl Metrics {
+    /// Adds the counters of `other`, e.g. collected by another parser or decoder.
+    pub fn merge(&mut self, other: &Metrics) {
+        self.access_units += other.access_units;
+        self.bytes += other.bytes;
+        self.blocks
+            .iter_mut()
+            .zip(other.blocks)
+            .for_each(|(blocks, other)| *blocks += other);
+        self.samples += other.samples;
+        self.parse_time += other.parse_time;
+        self.decode_time += other.decode_time;
+    }
+
+    /// Returns the bytes parsed per second of parse time.
+    pub fn parse_bytes_per_second(&self) -> Option<f64> {
+        let secs = self.parse_time.as_secs_f64();
+        (secs > 0.0).then(|| self.bytes as f64 / secs)
+    }
+
+    /// Returns the decoded audio duration per second of decode time.
+    pub fn decode_realtime_factor(&self, sampling_frequency: u32) -> Option<f64> {
+        let secs = self.decode_time.as_secs_f64();
+        (secs > 0.0 && sampling_frequency > 0)
+            .then(|| self.samples as f64 / sampling_frequency as f64 / secs)
+    }
+}
+
 #[test]
 fn test_presentation_map() {
     let map = PresentationMap::with_substream_info(0b11001100, 0b00000001);
@@ -144,3 +299,40 @@ fn test_presentation_map() {
         PresentationType::Independent
     );
 }
+
+#[test]
+fn metrics_collection() -> anyhow::Result<()> {
+    use decode::Decoder;
+    use extract::Extractor;
+    use parse::Parser;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    let mut decoder = Decoder::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(2));
+
+    assert_eq!(parser.take_metrics(), None);
+    parser.enable_metrics(true);
+    decoder.enable_metrics(true);
+
+    let mut bytes = 0;
+    for frame in extractor.filter_map(Result::ok) {
+        bytes += frame.data.len() as u64;
+        let access_unit = parser.parse(&frame)?;
+        decoder.decode_presentation(&access_unit, 1)?;
+    }
+
+    let mut metrics = parser.take_metrics().unwrap();
+    assert_eq!(metrics.access_units, 4);
+    assert_eq!(metrics.bytes, bytes);
+    assert!(metrics.blocks[0] > 0);
+
+    let decode_metrics = decoder.take_metrics().unwrap();
+    assert_eq!(decode_metrics.access_units, 0);
+    assert_eq!(decode_metrics.samples, 160);
+
+    metrics.merge(&decode_metrics);
+    assert_eq!(metrics.samples, 160);
+    assert_eq!(parser.take_metrics(), Some(Metrics::default()));
+    Ok(())
+}
@@ -0,0 +1,424 @@
+use crate::process::decode::{DecodeStats, DecodedAccessUnit, Decoder, DuplicateHandling};
+use crate::process::{MAX_PRESENTATIONS, Metrics};
+use crate::structs::access_unit::AccessUnit;
+use crate::utils::errors::{Category, Policy};
+use crate::utils::events::Event;
+use anyhow::Result;
+use std::thread;
+use std::time::Instant;
+
+/// Decodes batches of access units on multiple threads.
+///
+/// Each batch is split into segments at access units with a major sync and
+/// restart headers in every substream of the presentation, where the decoder
+/// state is re-initialized. Segments are decoded concurrently and the results
+/// are returned in input order, identical to those of
+/// [`Decoder::decode_presentation`].
+///
+/// Every segment except the last also decodes the first access unit of the
+/// following segment and its result is used for that access unit, so the
+/// lossless check at the split point is verified against the preceding audio
+/// and duplicate access units at seamless branches are still detected.
+/// A segment whose first access unit fails to decode is decoded again by the
+/// decoder of the preceding segment.
+///
+/// Decoder state carries over between batches, so a stream may be passed in
+/// batches of any size. Segments should span several major sync intervals
+/// for the threads to be used.
+///
+/// # Examples
+///
+/// ```
+/// use truehd::process::{extract::Extractor, parse::Parser, parallel::ParallelDecoder};
+///
+/// let mut extractor = Extractor::default();
+/// let mut parser = Parser::default();
+/// let mut decoder = ParallelDecoder::new(1, 4);
+/// extractor.push_bytes(&truehd::process::EXAMPLE_DATA.repeat(8));
+///
+/// let access_units = extractor
+///     .filter_map(Result::ok)
+///     .map(|frame| parser.parse(&frame))
+///     .collect::<anyhow::Result<Vec<_>>>()?;
+///
+/// for decoded in decoder.decode(&access_units) {
+///     assert_eq!(decoded?.sample_length, 40);
+/// }
+/// assert_eq!(decoder.stats().access_units, 16);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct ParallelDecoder {
+    decoder: Decoder,
+    presentation: usize,
+    threads: usize,
+    stats: DecodeStats,
+    metrics: Option<Metrics>,
+}
+
+impl ParallelDecoder {
+    /// Creates a decoder for `presentation` using up to `threads` threads.
+    pub fn new(presentation: usize, threads: usize) -> Self {
+        Self {
+            decoder: Decoder::default(),
+            presentation,
+            threads: threads.max(1),
+            stats: DecodeStats::default(),
+            metrics: None,
+        }
+    }
+
+    /// Sets the failure level for validation errors.
+    ///
+    /// See [`Decoder::set_fail_level`].
+    pub fn set_fail_level(&mut self, level: log::Level) {
+        self.decoder.set_fail_level(level);
+    }
+
+    /// Sets the handling of the validation errors of `category`.
+    ///
+    /// See [`Decoder::set_category_policy`].
+    pub fn set_category_policy(&mut self, category: Category, policy: Policy) {
+        self.decoder.set_category_policy(category, policy);
+    }
+
+    /// Sets a sink receiving the [`Event`]s of all decoding threads.
+    ///
+    /// See [`Decoder::set_event_sink`].
+    pub fn set_event_sink(&mut self, sink: impl Fn(Event) + Send + Sync + 'static) {
+        self.decoder.set_event_sink(sink);
+    }
+
+    /// Sets how access units detected as duplicates are returned.
+    ///
+    /// See [`Decoder::set_duplicate_handling`].
+    pub fn set_duplicate_handling(&mut self, handling: DuplicateHandling) {
+        self.decoder.set_duplicate_handling(handling);
+    }
+
+    /// Enables or disables passing EVO frame payloads through.
+    ///
+    /// See [`Decoder::set_keep_evo_payloads`].
+    pub fn set_keep_evo_payloads(&mut self, keep: bool) {
+        self.decoder.set_keep_evo_payloads(keep);
+    }
+
+    /// Returns the maximum number of threads used per batch.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Decodes a batch of access units following those of the previous batch.
+    ///
+    /// Returns one result per access unit in input order.
+    pub fn decode(&mut self, access_units: &[AccessUnit]) -> Vec<Result<DecodedAccessUnit>> {
+        let start = self.metrics.is_some().then(Instant::now);
+
+        let mut bounds = vec![0];
+        bounds.extend(self.split_points(access_units));
+        bounds.push(access_units.len());
+
+        let sample_position = self.decoder.sample_position();
+        let mut decoders = Vec::with_capacity(bounds.len() - 1);
+        for &start in &bounds[1..bounds.len() - 1] {
+            decoders.push(self.decoder.fork(start));
+        }
+        decoders.insert(0, std::mem::take(&mut self.decoder));
+
+        let presentation = self.presentation;
+        let segments = bounds
+            .windows(2)
+            .map(|bounds| {
+                // All but the last segment include the first access unit of the next
+                let end = (bounds[1] + 1).min(access_units.len());
+                (bounds[0] > 0, &access_units[bounds[0]..end])
+            })
+            .collect::<Vec<_>>();
+
+        let decoded = if decoders.len() == 1 {
+            segments
+                .iter()
+                .copied()
+                .zip(decoders)
+                .map(|((is_fork, segment), decoder)| {
+                    decode_segment(decoder, segment, is_fork, presentation)
+                })
+                .collect::<Vec<_>>()
+        } else {
+            thread::scope(|scope| {
+                let handles = segments
+                    .iter()
+                    .copied()
+                    .zip(decoders)
+                    .map(|((is_fork, segment), decoder)| {
+                        scope.spawn(move || decode_segment(decoder, segment, is_fork, presentation))
+                    })
+                    .collect::<Vec<_>>();
+
+                handles
+                    .into_iter()
+                    .map(|handle| {
+                        handle
+                            .join()
+                            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                    })
+                    .collect()
+            })
+        };
+
+        let mut results = Vec::with_capacity(access_units.len());
+        for ((mut decoder, mut segment_results, fork_failed), (_, segment)) in
+            decoded.into_iter().zip(segments)
+        {
+            if fork_failed {
+                // The state left by a failed first access unit depends on
+                // the access units before it, so the segment is decoded
+                // again by the decoder of the previous segment
+                let mut previous = std::mem::take(&mut self.decoder);
+                previous.reset_stats();
+                (decoder, segment_results, _) =
+                    decode_segment(previous, &segment[1..], false, presentation);
+            }
+            self.stats.append(&decoder.stats());
+            if let (Some(metrics), Some(segment_metrics)) =
+                (&mut self.metrics, decoder.take_metrics())
+            {
+                metrics.samples += segment_metrics.samples;
+            }
+            results.extend(segment_results);
+            self.decoder = decoder;
+        }
+        self.decoder.reset_stats();
+
+        // Forked decoders do not know the samples decoded before their segment
+        self.decoder.set_sample_position(sample_position);
+        for decoded in results.iter_mut().flatten() {
+            self.decoder.place(decoded);
+        }
+
+        if let (Some(metrics), Some(start)) = (&mut self.metrics, start) {
+            metrics.decode_time += start.elapsed();
+        }
+
+        results
+    }
+
+    /// Returns integrity counters accumulated over all decoded batches.
+    ///
+    /// See [`Decoder::stats`].
+    pub fn stats(&self) -> DecodeStats {
+        self.stats
+    }
+
+    /// Enables or disables collection of [`Metrics`].
+    ///
+    /// The decode time is the wall-clock time of [`decode`](Self::decode),
+    /// not the sum over all threads.
+    pub fn enable_metrics(&mut self, enable: bool) {
+        self.metrics = enable.then(|| self.metrics.unwrap_or_default());
+        self.decoder.enable_metrics(enable);
+    }
+
+    /// Returns the metrics collected since the last call and resets them.
+    ///
+    /// Returns `None` if metrics are not enabled.
+    pub fn take_metrics(&mut self) -> Option<Metrics> {
+        self.metrics.as_mut().map(std::mem::take)
+    }
+
+    /// Returns the indices at which the batch is split into segments of
+    /// roughly equal length, one per thread.
+    fn split_points(&self, access_units: &[AccessUnit]) -> Vec<usize> {
+        let segment_len = access_units.len().div_ceil(self.threads);
+        let mut splits = Vec::new();
+
+        // Samples still to be dropped after a repaired branch are not known
+        // to decoders forked at a later split point
+        if self.decoder.has_pending_drop() {
+            return splits;
+        }
+
+        for (index, access_unit) in access_units.iter().enumerate() {
+            if splits.len() + 1 >= self.threads {
+                break;
+            }
+            if index > 0
+                && index >= (splits.len() + 1) * segment_len
+                && is_split_point(access_unit, self.presentation)
+            {
+                splits.push(index);
+            }
+            if access_unit.branch_repair.is_some_and(|gap| gap < 0) {
+                break;
+            }
+        }
+
+        splits
+    }
+}
+
+/// Decodes a segment, dropping the first result for a forked decoder.
+///
+/// A forked decoder only decodes the first access unit to initialize its
+/// state; its result and statistics belong to the preceding segment. Also
+/// returns whether that access unit failed to decode.
+fn decode_segment(
+    mut decoder: Decoder,
+    segment: &[AccessUnit],
+    is_fork: bool,
+    presentation: usize,
+) -> (Decoder, Vec<Result<DecodedAccessUnit>>, bool) {
+    let mut results = Vec::with_capacity(segment.len());
+    let mut fork_failed = false;
+
+    for (index, access_unit) in segment.iter().enumerate() {
+        let result = decoder.decode_presentation(access_unit, presentation);
+        if is_fork && index == 0 {
+            fork_failed = result.is_err();
+            decoder.reset_stats();
+        } else {
+            results.push(result);
+        }
+    }
+
+    (decoder, results, fork_failed)
+}
+
+/// Whether decoding can start at this access unit without earlier state.
+fn is_split_point(access_unit: &AccessUnit, presentation: usize) -> bool {
+    let Some(major_sync_info) = &access_unit.major_sync_info else {
+        return false;
+    };
+
+    let mut presentations = [false; MAX_PRESENTATIONS];
+    presentations[..=presentation.min(MAX_PRESENTATIONS - 1)]
+        .iter_mut()
+        .for_each(|p| *p = true);
+    let substream_mask = major_sync_info
+        .presentation_map()
+        .substream_mask_by_required_presentations(&presentations);
+
+    substream_mask != 0
+        && access_unit
+            .substream_segment
+            .iter()
+            .take(major_sync_info.substreams)
+            .enumerate()
+            .filter(|(i, _)| (substream_mask >> i) & 1 != 0)
+            .all(|(_, segment)| {
+                segment
+                    .block
+                    .first()
+                    .is_some_and(|block| block.restart_header.is_some())
+            })
+}
+
+#[test]
+fn parallel_decode_matches_sequential() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(12));
+    let access_units = extractor
+        .filter_map(Result::ok)
+        .map(|frame| parser.parse(&frame))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(access_units.len(), 24);
+
+    let mut decoder = Decoder::default();
+    let expected = access_units
+        .iter()
+        .map(|access_unit| decoder.decode_presentation(access_unit, 1))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut parallel = ParallelDecoder::new(1, 3);
+    assert_eq!(parallel.split_points(&access_units[..14]), vec![6, 10]);
+
+    // Batches that do not end at a split point continue with the same decoder
+    let mut decoded = parallel.decode(&access_units[..13]);
+    decoded.extend(parallel.decode(&access_units[13..]));
+    let decoded = decoded.into_iter().collect::<Result<Vec<_>>>()?;
+
+    assert_eq!(decoded.len(), expected.len());
+    for (decoded, expected) in decoded.iter().zip(&expected) {
+        assert_eq!(decoded.sample_length, expected.sample_length);
+        assert_eq!(decoded.channel_labels, expected.channel_labels);
+        assert_eq!(decoded.pcm_data, expected.pcm_data);
+        assert_eq!(decoded.is_duplicate, expected.is_duplicate);
+        assert_eq!(decoded.au_index, expected.au_index);
+        assert_eq!(decoded.sample_position, expected.sample_position);
+    }
+    assert_eq!(parallel.stats(), decoder.stats());
+    Ok(())
+}
+
+#[test]
+fn parallel_decode_matches_sequential_after_corrupt_block() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(12));
+    let mut access_units = extractor
+        .filter_map(Result::ok)
+        .map(|frame| parser.parse(&frame))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Residuals out of range saturate the recorrelator, at the split point
+    // of the second segment and within the third
+    assert_eq!(
+        ParallelDecoder::new(1, 3).split_points(&access_units),
+        [8, 16]
+    );
+    for index in [8, 19] {
+        access_units[index].substream_segment[0].block[0].block_data[0][0] = 1 << 23;
+    }
+
+    let mut decoder = Decoder::default();
+    let expected = access_units
+        .iter()
+        .map(|access_unit| decoder.decode_presentation(access_unit, 1))
+        .collect::<Vec<_>>();
+    assert!(expected[8].is_err() && expected[19].is_err());
+
+    let mut parallel = ParallelDecoder::new(1, 3);
+    let decoded = parallel.decode(&access_units);
+
+    assert_eq!(decoded.len(), expected.len());
+    for (decoded, expected) in decoded.iter().zip(&expected) {
+        match (decoded, expected) {
+            (Ok(decoded), Ok(expected)) => {
+                assert_eq!(decoded.sample_length, expected.sample_length);
+                assert_eq!(decoded.pcm_data, expected.pcm_data);
+                assert_eq!(decoded.au_index, expected.au_index);
+                assert_eq!(decoded.sample_position, expected.sample_position);
+            }
+            (Err(decoded), Err(expected)) => {
+                assert_eq!(decoded.to_string(), expected.to_string());
+            }
+            _ => panic!("parallel and sequential results differ"),
+        }
+    }
+    assert_eq!(parallel.stats(), decoder.stats());
+
+    // The decoder left after the batch continues as the sequential one
+    for (state, expected) in parallel
+        .decoder
+        .state
+        .substream_state
+        .iter()
+        .zip(&decoder.state.substream_state)
+    {
+        assert_eq!(state.coeff_state, expected.coeff_state);
+        assert_eq!(
+            state.lossless_check_i32_accum,
+            expected.lossless_check_i32_accum
+        );
+        assert_eq!(state.dither_seed, expected.dither_seed);
+    }
+    Ok(())
+}
@@ -1,15 +1,18 @@
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
 
+use crate::process::decode::DecodeStats;
 use crate::process::extract::Frame;
-use crate::process::{MAX_PRESENTATIONS, PresentationMap};
+use crate::process::{Instant, MAX_PRESENTATIONS, Metrics, PresentationMap};
 use crate::structs::access_unit::AccessUnit;
-use crate::structs::restart_header::Guards;
+use crate::structs::restart_header::{Guards, RestartHeader};
+use crate::structs::sync::FormatChange;
 use crate::utils::bitstream_io::BsIoSliceReader;
 use crate::utils::crc::{
     CRC_MAJOR_SYNC_INFO_ALG, CRC_RESTART_BLOCK_HEADER_ALG, CRC_SUBSTREAM_ALG, Crc8, Crc16,
 };
-use crate::utils::errors::ParseError;
-use crate::utils::timing::HiresOutputTimingState;
+use crate::utils::errors::{Category, CategoryPolicy, ParseError, ParseWarning, Policy};
+use crate::utils::events::{Event, EventSink};
+use crate::utils::timing::{AccessUnitTiming, HiresOutputTimingState};
 
 /// Parses audio frames into structured access units.
 ///
@@ -18,8 +21,148 @@ use crate::utils::timing::HiresOutputTimingState;
 #[derive(Default)]
 pub struct Parser {
     state: ParserState,
+    metrics: Option<Metrics>,
+    timestamps: TimestampTracker,
+    warnings: Vec<ParseWarning>,
+    timing_callback: Option<TimingCallback>,
+    timing: TimingTracker,
 }
 
+/// Receiver of the timing of each access unit, see [`Parser::set_timing_callback`]
+type TimingCallback = Box<dyn FnMut(&AccessUnitTiming) + Send>;
+
+/// Follows the sample position of the stream against its SMPTE timestamps
+#[derive(Default)]
+struct TimestampTracker {
+    /// Timestamp position and stream position of the first timestamp
+    origin: Option<(u64, u64)>,
+    /// Samples parsed so far
+    position: u64,
+    /// Timestamp position minus expected position at the last timestamp
+    drift: i64,
+}
+
+/// Follows the output timing of the stream for [`Parser::timing_report`]
+#[derive(Default)]
+struct TimingTracker {
+    /// Access units parsed successfully
+    access_units: u64,
+    /// Output timing of the last access unit
+    output_timing: Option<u16>,
+    /// Latency of the last access unit
+    latency: usize,
+    /// Samples the output timing advanced so far
+    output_samples: i64,
+}
+
+/// Data rate of the access units parsed so far.
+///
+/// Returned by [`Parser::data_rate_stats`]. Rates are in bits per second,
+/// derived from the access unit length and the sampling frequency of the
+/// last major sync.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DataRateStats {
+    /// Number of access units parsed successfully.
+    pub access_units: u64,
+    /// Lowest data rate of an access unit.
+    pub min: u64,
+    /// Highest data rate of an access unit.
+    pub max: u64,
+    /// Mean data rate over all access units.
+    pub mean: f64,
+    /// Index of the first access unit with the highest data rate.
+    pub peak_au: u64,
+    /// Sample position of the first access unit with the highest data rate.
+    pub peak_sample: u64,
+    /// Access unit counts of equal width rate ranges from `min` to `max`.
+    pub histogram: Vec<DataRateBucket>,
+}
+
+/// Range of data rates in a [`DataRateStats`] histogram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DataRateBucket {
+    /// Lowest data rate of the range, inclusive.
+    pub min: u64,
+    /// Highest data rate of the range, inclusive.
+    pub max: u64,
+    /// Number of access units in the range.
+    pub access_units: u64,
+}
+
+/// Number of ranges in the [`DataRateStats`] histogram.
+const DATA_RATE_BUCKETS: usize = 8;
+
+/// Timing of the access units parsed so far.
+///
+/// Returned by [`Parser::timing_report`]. Timings are in samples of the
+/// sampling frequency of the last major sync, and the output timing is read
+/// from the restart headers, so substream segments must be parsed.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimingReport {
+    /// Number of access units parsed successfully.
+    pub access_units: u64,
+    /// Input timing of the last access unit.
+    pub input_timing: u16,
+    /// Output timing of the last access unit.
+    pub output_timing: u16,
+    /// FIFO latency of the last access unit, its output minus its input timing.
+    pub latency: usize,
+    /// Advance of the last access unit, its latency minus the samples per access unit.
+    pub advance: usize,
+    /// Samples the output timing advanced from the first access unit to the
+    /// end of the last one.
+    ///
+    /// Negative when a branch jumps back before the first access unit. Each
+    /// input concatenated at a stream boundary continues the count with the
+    /// samples per access unit instead of its output timing.
+    pub output_samples: i64,
+    /// Highest data rate between consecutive access units, in bits per second.
+    pub max_data_rate: u64,
+    /// Index of the access unit with the highest data rate.
+    pub max_data_rate_au: usize,
+    /// Branches, restarts and latency changes in stream order, up to
+    /// [`MAX_TIMING_BRANCHES`].
+    pub branches: Vec<TimingBranch>,
+    /// Number of branches after the first [`MAX_TIMING_BRANCHES`].
+    pub omitted_branches: usize,
+}
+
+/// Timing discontinuity in a [`TimingReport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimingBranch {
+    /// Index of the access unit.
+    pub au: usize,
+    pub kind: TimingBranchKind,
+    /// Latency before the access unit.
+    pub latency_before: usize,
+    /// Latency from the access unit on.
+    pub latency_after: usize,
+}
+
+/// Cause of a [`TimingBranch`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimingBranchKind {
+    /// A timing jump passed the seamless branch checks.
+    SeamlessBranch,
+    /// Timing restarted as a new stream at a zero input timing interval.
+    StreamRestart,
+    /// An invalid seamless branch was repaired, inserting (positive) or
+    /// dropping (negative) `samples`.
+    BranchRepaired { samples: isize },
+    /// A new input started at a stream boundary.
+    StreamStart,
+    /// The latency changed although the stream declares it constant.
+    LatencyChange,
+}
+
+/// Number of branches kept in a [`TimingReport`].
+pub const MAX_TIMING_BRANCHES: usize = 1024;
+
 impl Parser {
     /// Parses an audio frame into a structured access unit.
     ///
@@ -27,8 +170,252 @@ impl Parser {
     /// and timing information. Handles both major sync frames (with stream
     /// configuration) and continuation frames (audio data only).
     pub fn parse(&mut self, frame: &Frame) -> Result<AccessUnit> {
+        let start = self.metrics.is_some().then(Instant::now);
+
+        // A concatenated stream starts over instead of continuing as a branch
+        if frame.stream_start {
+            self.state.reset_for_stream_start();
+            self.timestamps.origin = None;
+            self.timestamps.drift = 0;
+        }
+
+        self.state.stats.begin_access_unit();
         let reader = &mut BsIoSliceReader::from_slice(frame.as_ref());
-        AccessUnit::read(&mut self.state, reader)
+        let mut result = AccessUnit::read(&mut self.state, reader).with_context(|| {
+            format!(
+                "access unit at byte offset {} ({:#X})",
+                frame.byte_offset, frame.byte_offset
+            )
+        });
+
+        // Strict parsing stops at the error instead
+        if result.is_err() && self.state.fail_level == log::Level::Error {
+            self.resync();
+        }
+
+        if let Ok(access_unit) = &mut result {
+            access_unit.stream_start = frame.stream_start;
+            self.state
+                .record_access_unit_length(self.timestamps.position);
+            self.track_timestamp(frame, access_unit);
+            self.track_output_timing(frame);
+            self.report_timing(access_unit);
+        }
+
+        if let (Some(metrics), Some(start)) = (&mut self.metrics, start) {
+            metrics.parse_time += start.elapsed();
+            if let Ok(access_unit) = &result {
+                metrics.access_units += 1;
+                metrics.bytes += frame.as_ref().len() as u64;
+                for (blocks, segment) in metrics
+                    .blocks
+                    .iter_mut()
+                    .zip(&access_unit.substream_segment)
+                {
+                    *blocks += segment.block.len() as u64;
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Compares the timestamp preceding an access unit with the number of
+    /// samples parsed since the first timestamp.
+    fn track_timestamp(&mut self, frame: &Frame, access_unit: &AccessUnit) {
+        let sampling_frequency = self.state.audio_sampling_frequency_1;
+        let tracker = &mut self.timestamps;
+
+        if let Some(actual) = frame
+            .timestamp
+            .as_ref()
+            .and_then(|timestamp| timestamp.sample_position(sampling_frequency))
+        {
+            match tracker.origin {
+                None => tracker.origin = Some((actual, tracker.position)),
+                Some((origin, origin_position)) => {
+                    let expected = origin + (tracker.position - origin_position);
+                    let drift = actual as i64 - expected as i64;
+                    if drift != tracker.drift {
+                        tracker.drift = drift;
+                        self.warnings.push(ParseWarning::TimestampDrift {
+                            au: self.state.au_counter - 1,
+                            expected,
+                            actual,
+                        });
+                    }
+                }
+            }
+        }
+
+        let samples =
+            self.state.samples_per_au as i64 + access_unit.branch_repair.unwrap_or(0) as i64;
+        tracker.position = tracker.position.saturating_add_signed(samples);
+    }
+
+    /// Output timing of the access unit parsed last, continuing the output
+    /// timing of the last restart header.
+    fn output_timing(&self) -> u16 {
+        let state = &self.state;
+        let au_offset = state.au_counter - 1 - state.last_major_sync_index;
+        (state.output_timing + au_offset * state.samples_per_au) as u16
+    }
+
+    /// Adds the output timing interval of an access unit to the output
+    /// samples and records latency changes of a constant latency stream.
+    fn track_output_timing(&mut self, frame: &Frame) {
+        let output_timing = self.output_timing();
+        let state = &mut self.state;
+        let au = state.au_counter - 1;
+        let latency = state.substream_state[0].latency;
+        let tracker = &mut self.timing;
+
+        // A branch may jump backwards on the output timeline
+        let interval = match tracker.output_timing {
+            Some(prev) if !frame.stream_start => output_timing.wrapping_sub(prev) as i16 as i64,
+            _ => state.samples_per_au as i64,
+        };
+        tracker.output_samples += interval;
+
+        if frame.stream_start && tracker.output_timing.is_some() {
+            state.record_timing_branch(TimingBranch {
+                au,
+                kind: TimingBranchKind::StreamStart,
+                latency_before: tracker.latency,
+                latency_after: latency,
+            });
+        } else if state.flags & 0x8000 != 0
+            && !state.has_valid_branch
+            && !state.resyncing
+            && tracker.access_units > 0
+            && latency != tracker.latency
+        {
+            state.record_timing_branch(TimingBranch {
+                au,
+                kind: TimingBranchKind::LatencyChange,
+                latency_before: tracker.latency,
+                latency_after: latency,
+            });
+        }
+
+        tracker.access_units += 1;
+        tracker.output_timing = Some(output_timing);
+        tracker.latency = latency;
+    }
+
+    /// Passes the timing of an access unit to the timing callback.
+    fn report_timing(&mut self, access_unit: &AccessUnit) {
+        let output_timing = self.output_timing();
+        let Some(callback) = &mut self.timing_callback else {
+            return;
+        };
+        let state = &self.state;
+        let au_index = state.au_counter - 1;
+
+        callback(&AccessUnitTiming {
+            au_index,
+            input_timing: access_unit.input_timing,
+            output_timing,
+            length: (access_unit.access_unit_length as usize) << 1,
+            sampling_frequency: state.audio_sampling_frequency_1,
+            samples_per_au: state.samples_per_au,
+        });
+    }
+
+    /// Returns the warnings collected since the last call and clears them.
+    ///
+    /// A [`ParseWarning::TimestampDrift`] is collected whenever the drift of
+    /// an SMPTE timestamp against the samples parsed since the first one
+    /// differs from the drift at the previous timestamp.
+    pub fn take_warnings(&mut self) -> Vec<ParseWarning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Returns the data rate statistics of the access units parsed so far.
+    ///
+    /// Returns `None` before the first major sync.
+    pub fn data_rate_stats(&self) -> Option<DataRateStats> {
+        let state = &self.state;
+        if state.samples_per_au == 0 || state.audio_sampling_frequency_1 == 0 {
+            return None;
+        }
+
+        // Bits per second of an access unit of `length` 16-bit words
+        let rate = |length: usize| {
+            (length as u64 * 16 * state.audio_sampling_frequency_1 as u64)
+                / state.samples_per_au as u64
+        };
+
+        let counts = &state.access_unit_length_counts;
+        let min_length = counts.iter().position(|&count| count != 0)?;
+        let max_length = counts.iter().rposition(|&count| count != 0)?;
+
+        let access_units = counts.iter().sum::<u64>();
+        let total_length = counts
+            .iter()
+            .enumerate()
+            .map(|(length, &count)| length as u64 * count)
+            .sum::<u64>();
+
+        let buckets = DATA_RATE_BUCKETS.min(max_length - min_length + 1);
+        let span = max_length - min_length + 1;
+        let histogram = (0..buckets)
+            .map(|i| {
+                let first = min_length + span * i / buckets;
+                let last = min_length + span * (i + 1) / buckets - 1;
+                DataRateBucket {
+                    min: rate(first),
+                    max: rate(last),
+                    access_units: counts[first..=last].iter().sum(),
+                }
+            })
+            .collect();
+
+        Some(DataRateStats {
+            access_units,
+            min: rate(min_length),
+            max: rate(max_length),
+            mean: (total_length as f64 / access_units as f64)
+                * 16.0
+                * state.audio_sampling_frequency_1 as f64
+                / state.samples_per_au as f64,
+            peak_au: state.peak_access_unit_index as u64,
+            peak_sample: state.peak_access_unit_position,
+            histogram,
+        })
+    }
+
+    /// Returns the timing of the access units parsed so far, with the
+    /// seamless branches, stream restarts and latency changes detected.
+    pub fn timing_report(&self) -> TimingReport {
+        let state = &self.state;
+        let tracker = &self.timing;
+        TimingReport {
+            access_units: tracker.access_units,
+            input_timing: state.input_timing as u16,
+            output_timing: tracker.output_timing.unwrap_or_default(),
+            latency: tracker.latency,
+            advance: state.advance,
+            output_samples: tracker.output_samples,
+            max_data_rate: state.max_data_rate as u64,
+            max_data_rate_au: state.max_data_rate_au_index,
+            branches: state.timing_branches.clone(),
+            omitted_branches: state.omitted_timing_branches,
+        }
+    }
+
+    /// Returns the samples the output timing advanced so far, see
+    /// [`TimingReport::output_samples`].
+    pub fn output_samples(&self) -> i64 {
+        self.timing.output_samples
+    }
+
+    /// Returns the drift of the last SMPTE timestamp in samples.
+    ///
+    /// This is the position derived from the timestamp minus the position
+    /// expected from the samples parsed since the first timestamp.
+    pub fn timestamp_drift(&self) -> i64 {
+        self.timestamps.drift
     }
 
     pub fn set_required_presentations(
@@ -47,6 +434,29 @@ impl Parser {
         self.state.hires_output_timing
     }
 
+    /// Returns the sampling frequency from the last major sync, or 0 before the first.
+    pub fn sampling_frequency(&self) -> u32 {
+        self.state.audio_sampling_frequency_1
+    }
+
+    /// Returns the samples per access unit from the last major sync, or 0 before the first.
+    pub fn samples_per_au(&self) -> usize {
+        self.state.samples_per_au
+    }
+
+    /// Resets the timing after an access unit failed to parse.
+    ///
+    /// The latency and output timing history of the substreams are reset,
+    /// keeping the format information of the last major sync, and timing is
+    /// not checked until the next major sync restarts it as a new stream.
+    /// Otherwise every access unit up to the next major sync may be checked
+    /// against the timing the failed one left half updated. [`Parser::parse`]
+    /// does this on failure unless the failure level is stricter than
+    /// `log::Level::Error`.
+    pub fn resync(&mut self) {
+        self.state.reset_for_resync();
+    }
+
     /// Sets the failure level for validation errors.
     ///
     /// - `log::Level::Error`: Only fail on Error level messages (default)
@@ -54,6 +464,84 @@ impl Parser {
     pub fn set_fail_level(&mut self, level: log::Level) {
         self.state.fail_level = level;
     }
+
+    /// Sets the handling of the validation errors of `category`,
+    /// overriding the failure level for them.
+    pub fn set_category_policy(&mut self, category: Category, policy: Policy) {
+        self.state.category_policy.set(category, policy);
+    }
+
+    /// Enables repair of invalid seamless branches.
+    ///
+    /// A timing jump failing the seamless branch checks, typically the join
+    /// of a cropped segment and the next, restarts timing as a new stream
+    /// instead of failing. The parsed [`AccessUnit::branch_repair`] holds the
+    /// number of samples to insert or drop to continue the timeline before
+    /// the branch.
+    pub fn set_repair_branch(&mut self, repair: bool) {
+        self.state.repair_branch = repair;
+    }
+
+    /// Accepts changes of the substream configuration that keep the channel
+    /// counts of the required presentations.
+    ///
+    /// A major sync with a different `substream_info` or
+    /// `extended_substream_info`, typically the join of two differently
+    /// authored segments, restarts parsing with the new presentation map and
+    /// flags the access unit with [`AccessUnit::format_change`] for the
+    /// decoder to restart. The change is only reported as a problem when a
+    /// required presentation changes its channel count.
+    pub fn set_allow_format_change(&mut self, allow: bool) {
+        self.state.allow_format_change = allow;
+    }
+
+    /// Returns the index of the substream parsed last.
+    ///
+    /// After a parse error in a substream segment, this is the failing substream.
+    pub fn substream_index(&self) -> usize {
+        self.state.substream_index
+    }
+
+    /// Returns integrity counters accumulated while parsing.
+    ///
+    /// Only Huffman decode saturations are detected by the parser; see
+    /// [`Decoder::stats`](crate::process::decode::Decoder::stats).
+    pub fn stats(&self) -> DecodeStats {
+        self.state.stats
+    }
+
+    /// Sets a callback receiving the timing of every access unit parsed.
+    ///
+    /// The output timing is read from the restart headers, so the callback
+    /// should only be set when substream segments are parsed. Feeding a
+    /// [`FifoModel`](crate::utils::timing::FifoModel) gives the decoder FIFO
+    /// occupancy over the stream.
+    pub fn set_timing_callback(
+        &mut self,
+        callback: impl FnMut(&AccessUnitTiming) + Send + 'static,
+    ) {
+        self.timing_callback = Some(Box::new(callback));
+    }
+
+    /// Sets a sink receiving an [`Event`] for seamless branches, stream
+    /// restarts and every validation problem, in addition to the log.
+    pub fn set_event_sink(&mut self, sink: impl Fn(Event) + Send + Sync + 'static) {
+        self.state.event_sink = Some(EventSink::new(sink));
+    }
+
+    /// Enables or disables collection of [`Metrics`].
+    ///
+    /// Disabling discards the metrics collected so far.
+    pub fn enable_metrics(&mut self, enable: bool) {
+        self.metrics = enable.then(|| self.metrics.unwrap_or_default());
+    }
+
+    /// Returns the metrics collected since the last call and resets them.
+    ///
+    /// Returns `None` if metrics are not enabled.
+    pub fn take_metrics(&mut self) -> Option<Metrics> {
+        self.metrics.as_mut().map(std::mem::take)
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -173,12 +661,18 @@ impl Default for ParserSubstreamState {
 pub struct ParserState {
     // hyper
     pub fail_level: log::Level,
+    pub category_policy: CategoryPolicy,
+    pub event_sink: Option<EventSink>,
     pub allow_seamless_branch: bool,
+    pub repair_branch: bool,
+    pub allow_format_change: bool,
     pub check_fifo: bool,
 
     pub restart_gap: [usize; MAX_PRESENTATIONS],
     pub last_major_sync_index: usize,
     pub au_counter: usize,
+    /// Index of the first access unit after the last stream boundary
+    pub stream_start_au: usize,
     pub is_major_sync: bool,
     pub has_parsed_au: bool,
 
@@ -193,6 +687,12 @@ pub struct ParserState {
     pub max_data_rate: usize,
     pub max_data_rate_au_index: usize,
 
+    /// Number of parsed access units per access unit length
+    pub access_unit_length_counts: Vec<u64>,
+    pub peak_access_unit_index: usize,
+    /// Sample position of the longest access unit
+    pub peak_access_unit_position: u64,
+
     pub advance: usize,
     pub prev_advance: usize,
 
@@ -220,6 +720,17 @@ pub struct ParserState {
     pub peak_data_rate_jump: bool,
     pub has_valid_branch: bool,
     pub has_substream_info_changed: bool,
+    /// Timing checks are suspended until the next major sync after an access
+    /// unit failed to parse
+    pub resyncing: bool,
+    /// Configuration change accepted at the last major sync, checked once the
+    /// access unit is parsed
+    pub format_change: Option<FormatChange>,
+    /// Timeline correction of an invalid branch repaired in this access unit
+    pub branch_repair: Option<isize>,
+    /// The first timing discontinuities, for [`Parser::timing_report`]
+    pub timing_branches: Vec<TimingBranch>,
+    pub omitted_timing_branches: usize,
 
     pub variable_rate: bool,
     pub peak_data_rate: usize,
@@ -246,6 +757,8 @@ pub struct ParserState {
     pub substream_index: usize,
     pub substream_mask: u8,
     pub substream_state: [ParserSubstreamState; MAX_PRESENTATIONS],
+    /// Last restart header of each substream
+    pub restart_headers: [Option<RestartHeader>; MAX_PRESENTATIONS],
 
     pub crc_restart_block_header: Crc8,
     pub crc_substream: Crc8,
@@ -253,18 +766,25 @@ pub struct ParserState {
 
     pub bypassed_lsb: [[i32; 16]; 160],
     pub sample_buffer: [[i32; 16]; 160],
+
+    pub stats: DecodeStats,
 }
 
 impl Default for ParserState {
     fn default() -> Self {
         Self {
             fail_level: log::Level::Error,
+            category_policy: CategoryPolicy::default(),
+            event_sink: None,
             allow_seamless_branch: true,
+            repair_branch: false,
+            allow_format_change: false,
             check_fifo: true,
             restart_gap: [0, 8, 8, 8],
 
             last_major_sync_index: 0,
             au_counter: 0,
+            stream_start_au: 0,
             is_major_sync: false,
             has_parsed_au: false,
 
@@ -279,6 +799,10 @@ impl Default for ParserState {
             max_data_rate: 0,
             max_data_rate_au_index: 0,
 
+            access_unit_length_counts: Vec::new(),
+            peak_access_unit_index: 0,
+            peak_access_unit_position: 0,
+
             advance: 0,
             prev_advance: 0,
 
@@ -306,6 +830,11 @@ impl Default for ParserState {
             peak_data_rate_jump: false,
             has_valid_branch: false,
             has_substream_info_changed: false,
+            resyncing: false,
+            format_change: None,
+            branch_repair: None,
+            timing_branches: Vec::new(),
+            omitted_timing_branches: 0,
 
             variable_rate: false,
             peak_data_rate: 0,
@@ -330,6 +859,7 @@ impl Default for ParserState {
             substream_index: 0,
             substream_mask: 0,
             substream_state: [ParserSubstreamState::default(); MAX_PRESENTATIONS],
+            restart_headers: Default::default(),
 
             crc_restart_block_header: Crc8::new(&CRC_RESTART_BLOCK_HEADER_ALG),
             crc_substream: Crc8::new(&CRC_SUBSTREAM_ALG),
@@ -337,11 +867,20 @@ impl Default for ParserState {
 
             bypassed_lsb: [[0; 16]; 160],
             sample_buffer: [[0; 16]; 160],
+
+            stats: DecodeStats::default(),
         }
     }
 }
 
 impl ParserState {
+    /// Passes `event` to the event sink, if any.
+    pub fn emit(&self, event: Event) {
+        if let Some(sink) = &self.event_sink {
+            sink.emit(event);
+        }
+    }
+
     pub fn expected_au_end_pos(&self) -> usize {
         self.au_start_pos + (self.access_unit_length << 4)
     }
@@ -379,6 +918,10 @@ impl ParserState {
             drc_gain_update: ss_state.drc_gain_update,
             drc_time_update: ss_state.drc_time_update,
             drc_count: ss_state.drc_count,
+            heavy_drc_active: ss_state.heavy_drc_active,
+            heavy_drc_gain_update: ss_state.heavy_drc_gain_update,
+            heavy_drc_time_update: ss_state.heavy_drc_time_update,
+            heavy_drc_count: ss_state.heavy_drc_count,
             hires_output_timing_state: ss_state.hires_output_timing_state,
             latency: ss_state.latency,
             prev_latency: ss_state.prev_latency,
@@ -390,12 +933,128 @@ impl ParserState {
         }
     }
 
+    /// Counts the length of the access unit parsed last, starting at sample `position`.
+    pub fn record_access_unit_length(&mut self, position: u64) {
+        let length = self.access_unit_length;
+        if length >= self.access_unit_length_counts.len() {
+            self.access_unit_length_counts.resize(length + 1, 0);
+        }
+
+        // Only the first of the longest access units is the peak
+        if self.access_unit_length_counts[length..]
+            .iter()
+            .all(|&count| count == 0)
+        {
+            self.peak_access_unit_index = self.au_counter - 1;
+            self.peak_access_unit_position = position;
+        }
+
+        self.access_unit_length_counts[length] += 1;
+    }
+
+    /// Records a timing discontinuity for the timing report.
+    pub fn record_timing_branch(&mut self, branch: TimingBranch) {
+        if self.timing_branches.len() < MAX_TIMING_BRANCHES {
+            self.timing_branches.push(branch);
+        } else {
+            self.omitted_timing_branches += 1;
+        }
+    }
+
     pub fn reset_for_branch(&mut self) {
         for ss_state in &mut self.substream_state {
             ss_state.hires_output_timing_state.reset_for_branch()
         }
     }
 
+    /// Resets all stream state as before the first access unit, for a stream
+    /// concatenated at a boundary.
+    ///
+    /// The configuration and the statistics collected so far are kept, and
+    /// access units are still counted from the start of the input.
+    pub fn reset_for_stream_start(&mut self) {
+        let state = std::mem::take(self);
+        *self = Self {
+            fail_level: state.fail_level,
+            category_policy: state.category_policy,
+            event_sink: state.event_sink,
+            allow_seamless_branch: state.allow_seamless_branch,
+            repair_branch: state.repair_branch,
+            allow_format_change: state.allow_format_change,
+            check_fifo: state.check_fifo,
+            restart_gap: state.restart_gap,
+            required_presentations: state.required_presentations,
+
+            last_major_sync_index: state.au_counter,
+            au_counter: state.au_counter,
+            stream_start_au: state.au_counter,
+
+            max_data_rate: state.max_data_rate,
+            max_data_rate_au_index: state.max_data_rate_au_index,
+            access_unit_length_counts: state.access_unit_length_counts,
+            peak_access_unit_index: state.peak_access_unit_index,
+            peak_access_unit_position: state.peak_access_unit_position,
+            timing_branches: state.timing_branches,
+            omitted_timing_branches: state.omitted_timing_branches,
+
+            stats: state.stats,
+            ..Default::default()
+        };
+    }
+
+    /// Suspends timing checks after an access unit failed to parse, until the
+    /// next major sync restarts timing as a new stream.
+    ///
+    /// The failed access unit may have left the timing half updated, so the
+    /// latency and output timing history of the substreams are reset. The
+    /// format information and the substream parameters are kept.
+    pub fn reset_for_resync(&mut self) {
+        if !self.has_parsed_au {
+            return;
+        }
+
+        self.resyncing = true;
+        self.has_valid_branch = false;
+        for ss_state in &mut self.substream_state {
+            ss_state.hires_output_timing_state.reset_for_branch();
+            ss_state.latency = 0;
+            ss_state.prev_latency = 0;
+            ss_state.output_timing_history = [0; 128];
+            ss_state.substream_size_history = [0; 128];
+            ss_state.history_index = 0;
+        }
+    }
+
+    /// Restarts timing and FIFO tracking at the current access unit as if a new
+    /// stream started here.
+    ///
+    /// The access unit is flagged as a branch so the decoder tolerates the
+    /// lossless check spanning the discontinuity.
+    pub fn reset_for_stream_restart(&mut self) {
+        self.reset_for_branch();
+        self.has_valid_branch = true;
+
+        self.output_timing_deviation = self
+            .output_timing
+            .wrapping_sub(self.first_output_timing)
+            .wrapping_sub((self.au_counter - self.stream_start_au) * self.samples_per_au)
+            & 0xFFFF;
+
+        self.unwrapped_input_timing =
+            self.input_timing.wrapping_sub(self.output_timing_deviation) & 0xFFFF;
+        self.first_input_timing = self.unwrapped_input_timing;
+        self.first_unwrapped_input_timing = self.unwrapped_input_timing;
+        self.total_access_unit_length = 0;
+
+        // The next access unit skips the usual update while a branch is flagged
+        self.prev_access_unit_length = self.access_unit_length;
+        self.prev_advance = self.advance;
+        self.prev_fifo_duration = self.fifo_duration;
+        self.prev_input_timing = self.input_timing;
+        self.prev_unwrapped_input_timing = self.unwrapped_input_timing;
+        self.prev_peak_data_rate = self.peak_data_rate;
+    }
+
     fn check_substream(&self, i: usize) -> Result<()> {
         let Some(substreams) = self.substreams else {
             bail!(ParseError::NoSubstream);
@@ -408,3 +1067,384 @@ impl ParserState {
         Ok(())
     }
 }
+
+#[test]
+fn timestamp_drift() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+
+    // The second copy repeats the timestamp of the first, two access units later
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(2));
+
+    let mut parser = Parser::default();
+    for frame in extractor.filter_map(Result::ok) {
+        parser.parse(&frame)?;
+    }
+
+    let warnings = parser.take_warnings();
+    let [
+        ParseWarning::TimestampDrift {
+            au,
+            expected,
+            actual,
+        },
+    ] = warnings[..]
+    else {
+        panic!("expected one drift warning, got {warnings:?}");
+    };
+    assert_eq!(au, 2);
+    assert_eq!(expected - actual, 80);
+    assert_eq!(parser.timestamp_drift(), -80);
+    assert!(parser.take_warnings().is_empty());
+    Ok(())
+}
+
+#[test]
+fn data_rate_stats() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(&EXAMPLE_DATA.repeat(3));
+
+    let mut parser = Parser::default();
+    assert_eq!(parser.data_rate_stats(), None);
+
+    let mut lengths = Vec::new();
+    for frame in extractor.filter_map(Result::ok) {
+        parser.parse(&frame)?;
+        lengths.push(frame.as_ref().len());
+    }
+
+    let stats = parser.data_rate_stats().expect("stats after a major sync");
+    assert_eq!(stats.access_units, lengths.len() as u64);
+    assert!(stats.min as f64 <= stats.mean && stats.mean <= stats.max as f64);
+    assert_eq!(
+        stats.histogram.iter().map(|b| b.access_units).sum::<u64>(),
+        stats.access_units
+    );
+    assert_eq!(stats.histogram.first().map(|b| b.min), Some(stats.min));
+    assert_eq!(stats.histogram.last().map(|b| b.max), Some(stats.max));
+
+    let peak = lengths
+        .iter()
+        .position(|&l| l == *lengths.iter().max().unwrap());
+    assert_eq!(Some(stats.peak_au as usize), peak);
+    assert_eq!(
+        stats.peak_sample,
+        stats.peak_au * parser.state.samples_per_au as u64
+    );
+    Ok(())
+}
+
+#[test]
+fn timing_callback() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use std::sync::{Arc, Mutex};
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+
+    let timings = Arc::new(Mutex::new(Vec::new()));
+    let mut parser = Parser::default();
+    let callback_timings = timings.clone();
+    parser.set_timing_callback(move |timing| callback_timings.lock().unwrap().push(*timing));
+
+    let mut lengths = Vec::new();
+    for frame in extractor.filter_map(Result::ok) {
+        let access_unit = parser.parse(&frame)?;
+        lengths.push((access_unit.input_timing, frame.as_ref().len()));
+    }
+
+    let timings = timings.lock().unwrap();
+    assert_eq!(
+        timings
+            .iter()
+            .map(|timing| (timing.input_timing, timing.length))
+            .collect::<Vec<_>>(),
+        lengths
+    );
+    for (i, timing) in timings.iter().enumerate() {
+        assert_eq!(timing.au_index, i);
+        assert_eq!(
+            (timing.sampling_frequency, timing.samples_per_au),
+            (48000, 40)
+        );
+        // Latency as checked by the parser
+        let latency = timing.output_timing.wrapping_sub(timing.input_timing);
+        assert!((40..=3600).contains(&latency), "latency {latency}");
+    }
+    assert_eq!(
+        timings[1].output_timing,
+        timings[0].output_timing.wrapping_add(40)
+    );
+    Ok(())
+}
+
+#[test]
+fn zero_input_timing_interval_at_join() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::decode::Decoder;
+    use crate::process::extract::Extractor;
+    use crate::utils::bitstream_io::BitstreamWriter;
+    use crate::utils::errors::RestartHeaderError;
+    use std::sync::{Arc, Mutex};
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    let frames = extractor.filter_map(Result::ok).collect::<Vec<_>>();
+
+    let mut parser = Parser::default();
+    let access_units = frames
+        .iter()
+        .map(|frame| parser.parse(frame))
+        .collect::<Result<Vec<_>>>()?;
+    let samples_per_au = parser.state.samples_per_au;
+    let prev_advance = parser.state.advance;
+
+    // Shift the output timing of a second copy so that its advance at the
+    // join exceeds the last one by exactly samples_per_au
+    let first_output_timing = access_units[0].substream_segment[0].block[0]
+        .restart_header
+        .as_ref()
+        .expect("restart header at the major sync")
+        .output_timing as usize;
+    let advance = first_output_timing
+        .wrapping_sub(samples_per_au)
+        .wrapping_sub(access_units[0].input_timing as usize);
+    let shift = prev_advance
+        .wrapping_add(samples_per_au)
+        .wrapping_sub(advance) as u16;
+
+    let mut writer = BitstreamWriter::new();
+    let mut stream = EXAMPLE_DATA.to_vec();
+    for mut access_unit in access_units {
+        for segment in &mut access_unit.substream_segment {
+            for restart_header in segment.block.iter_mut().flat_map(|b| &mut b.restart_header) {
+                restart_header.output_timing = restart_header.output_timing.wrapping_add(shift);
+            }
+        }
+        stream.extend(access_unit.write(&mut writer)?);
+    }
+
+    let parse_all = |fail_level| -> Result<Vec<Event>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink_events = events.clone();
+        let mut parser = Parser::default();
+        parser.set_fail_level(fail_level);
+        parser.set_event_sink(move |event| sink_events.lock().unwrap().push(event));
+
+        let mut extractor = Extractor::default();
+        extractor.push_bytes(&stream);
+        let mut decoder = Decoder::default();
+        for frame in extractor.filter_map(Result::ok) {
+            decoder.decode_presentation(&parser.parse(&frame)?, 1)?;
+        }
+        Ok(std::mem::take(&mut *events.lock().unwrap()))
+    };
+
+    // Timing restarts at the join and the rest of the stream decodes
+    let events = parse_all(log::Level::Error)?;
+    assert!(events.contains(&Event::StreamRestart { au: frames.len() }));
+
+    let err = parse_all(log::Level::Warn).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(RestartHeaderError::ZeroInputTimingInterval { .. })
+    ));
+    Ok(())
+}
+
+#[test]
+fn timing_report_seamless_branch() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::utils::bitstream_io::BitstreamWriter;
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    let frames = extractor.filter_map(Result::ok).collect::<Vec<_>>();
+
+    let mut parser = Parser::default();
+    let access_units = frames
+        .iter()
+        .map(|frame| parser.parse(frame))
+        .collect::<Result<Vec<_>>>()?;
+    let samples_per_au = parser.state.samples_per_au;
+    let prev_advance = parser.state.advance;
+    let report = parser.timing_report();
+    assert_eq!(report.access_units, frames.len() as u64);
+    assert_eq!(
+        report.output_samples,
+        (frames.len() * samples_per_au) as i64
+    );
+    assert!(report.branches.is_empty());
+
+    // Shift the output timing of a second copy so that its advance at the
+    // join equals the last one, a valid seamless branch
+    let first_output_timing = access_units[0].substream_segment[0].block[0]
+        .restart_header
+        .as_ref()
+        .expect("restart header at the major sync")
+        .output_timing as usize;
+    let advance = first_output_timing
+        .wrapping_sub(samples_per_au)
+        .wrapping_sub(access_units[0].input_timing as usize);
+    let shift = prev_advance.wrapping_sub(advance) as u16;
+
+    let mut writer = BitstreamWriter::new();
+    let mut stream = EXAMPLE_DATA.to_vec();
+    for mut access_unit in access_units {
+        for segment in &mut access_unit.substream_segment {
+            for restart_header in segment.block.iter_mut().flat_map(|b| &mut b.restart_header) {
+                restart_header.output_timing = restart_header.output_timing.wrapping_add(shift);
+            }
+        }
+        stream.extend(access_unit.write(&mut writer)?);
+    }
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(&stream);
+    let mut parser = Parser::default();
+    for frame in extractor.filter_map(Result::ok) {
+        parser.parse(&frame)?;
+    }
+
+    let report = parser.timing_report();
+    assert_eq!(report.access_units, 2 * frames.len() as u64);
+    let [branch] = report.branches[..] else {
+        panic!("one branch expected, got {:?}", report.branches);
+    };
+    assert_eq!(branch.au, frames.len());
+    assert_eq!(branch.kind, TimingBranchKind::SeamlessBranch);
+    assert_eq!(report.omitted_branches, 0);
+
+    // The second copy restarts the output timeline at the start of the first
+    // moved by `shift`, although both copies are parsed in full
+    let samples = (frames.len() * samples_per_au) as i64;
+    assert_eq!(report.output_samples, samples + shift as i16 as i64);
+    Ok(())
+}
+
+#[test]
+fn malformed_major_sync() {
+    // A substream_info below 20, and a 16-channel meaning longer than its
+    // extra_channel_meaning_length
+    let substream_info = [
+        &[
+            0xF0, 0x2A, 0xFF, 0xAC, 0xF8, 0x72, 0x6F, 0xBA, 0x00, 0xEF, 0xF3, 0xDE, 0xD0,
+        ][..],
+        &[0; 71],
+    ]
+    .concat();
+    let extra_channel_meaning = [
+        0xF0, 0x2A, 0xFF, 0xAC, 0xF8, 0x72, 0x6F, 0xBA, 0x00, 0xEF, 0xF3, 0xDE, 0x00, 0x00, 0x26,
+        0x00, 0x6F, 0xBA, 0x00, 0xF0, 0x2A, 0xFF, 0xAC, 0x00, 0x31, 0x6F, 0xBA, 0x01, 0x72, 0x6F,
+        0x00, 0xD5, 0x04, 0x00, 0x72, 0x6F, 0x00, 0xD5, 0x04, 0x00, 0x00,
+    ];
+
+    for data in [&substream_info[..], &extra_channel_meaning[..]] {
+        let frame = Frame {
+            timestamp: None,
+            byte_offset: 0,
+            stream_start: false,
+            data: data.into(),
+        };
+
+        assert!(Parser::default().parse(&frame).is_err());
+    }
+}
+
+#[test]
+fn resync_after_parse_error() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::utils::bitstream_io::BitstreamWriter;
+    use std::sync::{Arc, Mutex};
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+    let frames = extractor.filter_map(Result::ok).collect::<Vec<_>>();
+    let samples_per_au = 40;
+
+    // Groups of a major sync and seven more access units 40 samples apart,
+    // with a peak data rate that leaves room for the major sync
+    let mut writer = BitstreamWriter::new();
+    let mut stream = Vec::new();
+    let mut input_timing = 0u16.wrapping_sub(samples_per_au);
+    for group in 0..10u16 {
+        let mut parser = Parser::default();
+        for i in 0..8 {
+            let mut access_unit = parser.parse(&frames[(i > 0) as usize])?;
+            access_unit.input_timing = input_timing;
+            input_timing = input_timing.wrapping_add(samples_per_au);
+            if let Some(major_sync_info) = &mut access_unit.major_sync_info {
+                major_sync_info.peak_data_rate *= 4;
+            }
+            for segment in &mut access_unit.substream_segment {
+                for restart_header in segment.block.iter_mut().flat_map(|b| &mut b.restart_header) {
+                    restart_header.output_timing = restart_header
+                        .output_timing
+                        .wrapping_add(group * 8 * samples_per_au);
+                }
+            }
+            stream.extend(access_unit.write(&mut writer)?);
+        }
+    }
+
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(&stream);
+    let mut frames = extractor.filter_map(Result::ok).collect::<Vec<_>>();
+    assert_eq!(frames.len(), 80);
+
+    // Bit errors in a substream segment in the middle of a group
+    let mut data = frames[43].data.to_vec();
+    data[14] ^= 0x5A;
+    frames[43].data = data.into();
+
+    let parse_all = |fail_level, resync| {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink_events = events.clone();
+        let mut parser = Parser::default();
+        parser.set_fail_level(fail_level);
+        parser.set_event_sink(move |event| sink_events.lock().unwrap().push(event));
+
+        let mut errors = 0;
+        for frame in &frames {
+            if parser.parse(frame).is_err() {
+                errors += 1;
+                if resync {
+                    parser.resync();
+                }
+            }
+        }
+        let events = std::mem::take(&mut *events.lock().unwrap());
+        (errors, events, parser.timing_report())
+    };
+
+    // The failed access unit is the only problem, resynchronized
+    // automatically unless parsing strictly
+    for (fail_level, resync) in [(log::Level::Error, false), (log::Level::Warn, true)] {
+        let (errors, events, report) = parse_all(fail_level, resync);
+        assert_eq!(errors, 1);
+        let [
+            Event::Problem {
+                category: Category::Crc,
+                level: log::Level::Error,
+                ..
+            },
+        ] = events[..]
+        else {
+            panic!("expected the parity check failure only, got {events:?}");
+        };
+        assert_eq!(report.access_units, 79);
+        assert!(report.branches.is_empty());
+    }
+
+    // Without resynchronizing, the timing of the following access units fails
+    let (errors, _, _) = parse_all(log::Level::Warn, false);
+    assert!(errors > 1);
+    Ok(())
+}
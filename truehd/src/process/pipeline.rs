@@ -0,0 +1,1536 @@
+use crate::process::decode::{DecodeStats, DecodedAccessUnit, Decoder, DuplicateHandling};
+use crate::process::extract::{Extractor, Frame};
+use crate::process::mat::MatEncapsulation;
+use crate::process::metadata::{
+    AccessUnitMetadata, OamdExtractor, RawEvoPayload, read_evo_payloads,
+};
+use crate::process::parallel::ParallelDecoder;
+use crate::process::parse::Parser;
+use crate::process::{MAX_PRESENTATIONS, Metrics};
+use crate::structs::access_unit::AccessUnit;
+use crate::structs::timestamp::Timestamp;
+use crate::utils::buffer_pool::PooledPcm;
+use crate::utils::errors::{
+    Category, DecodeError, ExtractError, ParseWarning, PipelineError, Policy,
+};
+use crate::utils::events::Event;
+use anyhow::{Result, anyhow, bail};
+use std::io::{ErrorKind, Read};
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+
+/// Maximum number of access units between major syncs.
+///
+/// Skip mode is left at the first major sync within this many access units
+/// before the skip target, so decoding always restarts before it.
+const MAX_MAJOR_SYNC_INTERVAL: u64 = 128;
+
+/// Frames between [`PipelineEvent::Metrics`] updates.
+const METRICS_UPDATE_FRAMES: u64 = 30;
+
+/// Access units per thread collected before decoding a parallel batch.
+///
+/// Spans several major sync intervals, which are at most 128 access units.
+const PARALLEL_ACCESS_UNITS_PER_THREAD: usize = 256;
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Receiver of the output of a [`Pipeline`].
+///
+/// Access units and events are delivered in stream order on the thread
+/// driving the pipeline. Events of the parser and decoder are delivered
+/// before the access unit they were reported for.
+pub trait Sink {
+    /// Receives the next access unit, returning [`ControlFlow::Break`] to
+    /// stop the pipeline.
+    fn access_unit(&mut self, decoded: DecodedAccessUnit) -> ControlFlow<()>;
+
+    /// Receives a [`PipelineEvent`]. Ignores events by default.
+    fn event(&mut self, event: PipelineEvent) {
+        let _ = event;
+    }
+}
+
+/// Collects the access units, ignoring events.
+impl Sink for Vec<DecodedAccessUnit> {
+    fn access_unit(&mut self, decoded: DecodedAccessUnit) -> ControlFlow<()> {
+        self.push(decoded);
+        ControlFlow::Continue(())
+    }
+}
+
+/// Structured progress of a [`Pipeline`] delivered to [`Sink::event`].
+#[derive(Clone, Debug)]
+pub enum PipelineEvent {
+    /// A frame was extracted, `frame_count` counting from 1
+    Frame { frame_count: u64, byte_offset: u64 },
+    /// The frame carried an SMPTE timestamp
+    Timestamp(Timestamp),
+    /// The access unit of the frame carries a major sync
+    MajorSync {
+        frame_count: u64,
+        sampling_frequency: u32,
+        samples_per_au: usize,
+        atmos: bool,
+    },
+    /// Invalid data was skipped, continuing at `byte_offset`
+    Resync { byte_offset: u64 },
+    /// Parse and decode metrics collected since the previous update
+    Metrics(Metrics),
+    /// Event reported by the extractor, parser or decoder
+    Stream(Event),
+}
+
+/// Parse and decode errors counted by a [`Pipeline`].
+///
+/// Hard errors of the parser and decoder count against the maximum set with
+/// [`PipelineBuilder::max_errors`], while the extractor recovers from invalid
+/// data by resynchronizing and its resyncs are only counted.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorBudget {
+    max_errors: Option<u64>,
+    /// Access units that failed to parse or decode
+    pub errors: u64,
+    /// Extraction errors the extractor recovered from
+    pub resyncs: u64,
+    /// Failed access units replaced by silence
+    pub concealed: u64,
+    /// Silent samples sent in place of failed access units
+    pub concealed_samples: u64,
+    first_error: Option<FailedAccessUnit>,
+}
+
+/// Position of an access unit that failed to parse or decode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FailedAccessUnit {
+    pub frame_count: u64,
+    pub byte_offset: u64,
+    /// Stream time of the access unit in seconds
+    pub time: f64,
+}
+
+impl ErrorBudget {
+    pub fn new(max_errors: Option<u64>) -> Self {
+        Self {
+            max_errors,
+            ..Default::default()
+        }
+    }
+
+    /// Counts a hard error, returning `true` when the budget is exceeded
+    pub fn record_error(&mut self, frame_count: u64, byte_offset: u64, time: f64) -> bool {
+        self.errors += 1;
+        self.first_error.get_or_insert(FailedAccessUnit {
+            frame_count,
+            byte_offset,
+            time,
+        });
+        self.max_errors
+            .is_some_and(|max_errors| self.errors > max_errors)
+    }
+
+    /// Returns the maximum number of errors tolerated, if limited.
+    pub fn max_errors(&self) -> Option<u64> {
+        self.max_errors
+    }
+
+    /// Returns the first access unit that failed to parse or decode.
+    pub fn first_error(&self) -> Option<&FailedAccessUnit> {
+        self.first_error.as_ref()
+    }
+}
+
+/// Stream position the decoder skips to without reconstructing PCM.
+///
+/// Decoding resumes at the last major sync within 128 access units of the
+/// target so that the output is complete from there on. The decoder passed to
+/// [`PipelineBuilder::decoder`] enters skip mode with
+/// [`Decoder::set_skip_decode`].
+#[derive(Debug, Clone, Copy)]
+pub enum SkipTarget {
+    /// Samples already present in a resumed output, which fails once
+    /// substream_info changes before them
+    Resume(u64),
+    /// Start time in seconds, following `trim_samples`
+    Start { seconds: f64, trim_samples: u64 },
+}
+
+impl SkipTarget {
+    /// Stream position of the target in samples, unknown before the sample
+    /// rate of the first major sync
+    pub fn samples(self, sample_rate: u32) -> Option<u64> {
+        match self {
+            SkipTarget::Resume(samples) => Some(samples),
+            SkipTarget::Start {
+                seconds,
+                trim_samples,
+            } => (sample_rate > 0)
+                .then(|| trim_samples + (seconds * sample_rate as f64).round() as u64),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SkipTarget::Resume(_) => "resume",
+            SkipTarget::Start { .. } => "start",
+        }
+    }
+}
+
+/// Builder of a [`Pipeline`].
+///
+/// The settings are applied to the extractor, parser and decoder when
+/// building, overriding those of components passed in.
+pub struct PipelineBuilder {
+    extractor: Extractor,
+    parser: Parser,
+    decoder: Decoder,
+    presentations: Vec<usize>,
+    substream_mask: Option<u8>,
+    fail_level: log::Level,
+    category_policies: Vec<(Category, Policy)>,
+    duplicate_handling: DuplicateHandling,
+    keep_evo_payloads: bool,
+    metrics: bool,
+    strict: bool,
+    max_errors: Option<u64>,
+    threads: usize,
+    metadata_only: bool,
+    skip_target: Option<SkipTarget>,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        Self {
+            extractor: Extractor::default(),
+            parser: Parser::default(),
+            decoder: Decoder::default(),
+            presentations: vec![0],
+            substream_mask: None,
+            fail_level: log::Level::Error,
+            category_policies: Vec::new(),
+            duplicate_handling: DuplicateHandling::default(),
+            keep_evo_payloads: false,
+            metrics: false,
+            strict: false,
+            max_errors: None,
+            threads: 1,
+            metadata_only: false,
+            skip_target: None,
+        }
+    }
+}
+
+impl PipelineBuilder {
+    /// Selects the presentation to decode (0-3), 0 by default.
+    pub fn presentation(mut self, presentation: usize) -> Self {
+        self.presentations = vec![presentation];
+        self
+    }
+
+    /// Selects several presentations to decode in one pass.
+    ///
+    /// The access units of the highest presentation are delivered with those
+    /// of the others in [`DecodedAccessUnit::other_presentations`], see
+    /// [`Decoder::decode_presentations`].
+    pub fn presentations(mut self, presentations: &[usize]) -> Self {
+        self.presentations = presentations.to_vec();
+        self
+    }
+
+    /// Decodes the substreams of `mask` instead of those required by the
+    /// presentation.
+    ///
+    /// See [`Decoder::decode_with_substream_mask`].
+    pub fn substream_mask(mut self, mask: Option<u8>) -> Self {
+        self.substream_mask = mask;
+        self
+    }
+
+    /// Sets the failure level for validation errors, [`log::Level::Error`]
+    /// by default.
+    ///
+    /// See [`Decoder::set_fail_level`].
+    pub fn fail_level(mut self, level: log::Level) -> Self {
+        self.fail_level = level;
+        self
+    }
+
+    /// Sets the handling of the validation errors of `category`.
+    ///
+    /// See [`Parser::set_category_policy`].
+    pub fn category_policy(mut self, category: Category, policy: Policy) -> Self {
+        self.category_policies.push((category, policy));
+        self
+    }
+
+    /// Sets how access units detected as duplicates are returned.
+    ///
+    /// See [`Decoder::set_duplicate_handling`].
+    pub fn duplicate_handling(mut self, handling: DuplicateHandling) -> Self {
+        self.duplicate_handling = handling;
+        self
+    }
+
+    /// Passes the EVO frame payloads through with the access units.
+    pub fn keep_evo_payloads(mut self, keep: bool) -> Self {
+        self.keep_evo_payloads = keep;
+        self
+    }
+
+    /// Collects parse and decode [`Metrics`], delivered as
+    /// [`PipelineEvent::Metrics`].
+    pub fn metrics(mut self, enable: bool) -> Self {
+        self.metrics = enable;
+        self
+    }
+
+    /// Stops at the first parse or decode error instead of replacing the
+    /// access unit with silence.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Stops once more than `max_errors` access units failed to parse or
+    /// decode. Unlimited by default.
+    pub fn max_errors(mut self, max_errors: Option<u64>) -> Self {
+        self.max_errors = max_errors;
+        self
+    }
+
+    /// Decodes batches of access units on up to `threads` threads, 1 by
+    /// default.
+    ///
+    /// Ignored in metadata-only mode, with a skip target and when decoding
+    /// several presentations, which drive the decoder access unit by access
+    /// unit.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Extracts the object audio metadata without decoding audio.
+    ///
+    /// The access units carry no PCM and no channels.
+    pub fn metadata_only(mut self, metadata_only: bool) -> Self {
+        self.metadata_only = metadata_only;
+        self
+    }
+
+    /// Skips decoding up to `target`.
+    pub fn skip_target(mut self, target: Option<SkipTarget>) -> Self {
+        self.skip_target = target;
+        self
+    }
+
+    /// Replaces the default extractor.
+    pub fn extractor(mut self, extractor: Extractor) -> Self {
+        self.extractor = extractor;
+        self
+    }
+
+    /// Replaces the default parser, e.g. to repair branches.
+    pub fn parser(mut self, parser: Parser) -> Self {
+        self.parser = parser;
+        self
+    }
+
+    /// Replaces the default decoder, e.g. to share a PCM pool or to start
+    /// in skip mode.
+    pub fn decoder(mut self, decoder: Decoder) -> Self {
+        self.decoder = decoder;
+        self
+    }
+
+    /// Builds the pipeline.
+    ///
+    /// Fails for a presentation index of [`MAX_PRESENTATIONS`] or more, for
+    /// zero threads and for a substream mask with several presentations.
+    pub fn build(self) -> Result<Pipeline> {
+        let PipelineBuilder {
+            mut extractor,
+            mut parser,
+            mut decoder,
+            presentations,
+            substream_mask,
+            fail_level,
+            category_policies,
+            duplicate_handling,
+            keep_evo_payloads,
+            metrics,
+            strict,
+            max_errors,
+            threads,
+            metadata_only,
+            skip_target,
+        } = self;
+
+        let mut requested = [false; MAX_PRESENTATIONS];
+        for &presentation in &presentations {
+            if presentation >= MAX_PRESENTATIONS {
+                bail!("Presentation index must be 0-3, got {presentation}");
+            }
+            requested[presentation] = true;
+        }
+        let Some(presentation) = requested.iter().rposition(|&requested| requested) else {
+            bail!("No presentation selected");
+        };
+        let presentations =
+            (requested.iter().filter(|&&requested| requested).count() > 1).then_some(requested);
+        if presentations.is_some() && substream_mask.is_some() {
+            bail!("A substream mask cannot be used with several presentations");
+        }
+        if threads == 0 {
+            bail!("The pipeline needs at least one thread");
+        }
+
+        // Metadata-only mode parses no substreams
+        let mut required_presentations = [false; MAX_PRESENTATIONS];
+        if !metadata_only {
+            required_presentations[..=presentation].fill(true);
+        }
+        parser.set_required_presentations(&required_presentations);
+        parser.set_fail_level(fail_level);
+        parser.enable_metrics(metrics);
+        decoder.set_fail_level(fail_level);
+        decoder.set_duplicate_handling(duplicate_handling);
+        decoder.set_keep_evo_payloads(keep_evo_payloads);
+        decoder.enable_metrics(metrics);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let collect = |events: &Arc<Mutex<Vec<Event>>>| {
+            let events = events.clone();
+            move |event| {
+                if let Ok(mut events) = events.lock() {
+                    events.push(event);
+                }
+            }
+        };
+        extractor.set_event_sink(collect(&events));
+        parser.set_event_sink(collect(&events));
+        decoder.set_event_sink(collect(&events));
+
+        let parallel =
+            (threads > 1 && skip_target.is_none() && !metadata_only && presentations.is_none())
+                .then(|| {
+                    let mut decoder = ParallelDecoder::new(presentation, threads);
+                    decoder.set_fail_level(fail_level);
+                    decoder.set_duplicate_handling(duplicate_handling);
+                    decoder.set_keep_evo_payloads(keep_evo_payloads);
+                    decoder.set_event_sink(collect(&events));
+                    decoder.enable_metrics(metrics);
+                    for &(category, policy) in &category_policies {
+                        decoder.set_category_policy(category, policy);
+                    }
+                    ParallelBatch::new(decoder)
+                });
+
+        for &(category, policy) in &category_policies {
+            extractor.set_category_policy(category, policy);
+            parser.set_category_policy(category, policy);
+            decoder.set_category_policy(category, policy);
+        }
+
+        Ok(Pipeline {
+            extractor,
+            parser,
+            decoder,
+            parallel,
+            oamd_extractor: metadata_only.then(OamdExtractor::default),
+            events,
+            presentation,
+            presentations,
+            substream_mask,
+            strict,
+            keep_evo_payloads,
+            metrics,
+            skip_target,
+            frame_count: 0,
+            total_samples: 0,
+            sample_position: 0,
+            output_timing_deviation: 0,
+            substream_info: None,
+            extended_substream_info: None,
+            errors: ErrorBudget::new(max_errors),
+            silence: None,
+            inputs: 0,
+            stopped: false,
+            read_buffer: vec![0; READ_CHUNK_SIZE].into_boxed_slice(),
+        })
+    }
+}
+
+/// Parsed access units waiting to be decoded as one parallel batch.
+struct ParallelBatch {
+    decoder: ParallelDecoder,
+    access_units: Vec<AccessUnit>,
+    frames: Vec<PendingFrame>,
+}
+
+/// Position and restart flag of a pending access unit
+struct PendingFrame {
+    frame_count: u64,
+    byte_offset: u64,
+    substream_info_changed: bool,
+    /// Output timing of the parser after the access unit
+    output_samples: i64,
+}
+
+impl ParallelBatch {
+    fn new(decoder: ParallelDecoder) -> Self {
+        let capacity = decoder.threads() * PARALLEL_ACCESS_UNITS_PER_THREAD;
+        Self {
+            decoder,
+            access_units: Vec::with_capacity(capacity),
+            frames: Vec::with_capacity(capacity),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.access_units.len() >= self.decoder.threads() * PARALLEL_ACCESS_UNITS_PER_THREAD
+    }
+}
+
+/// Extracts, parses and decodes a TrueHD bitstream into a [`Sink`].
+///
+/// Unlike [`StreamingDecoder`](crate::process::stream::StreamingDecoder),
+/// the pipeline keeps going after parse and decode errors: failed access
+/// units are replaced by silence shaped after the last access unit, so the
+/// output stays as long as the stream, until the error budget is exceeded.
+/// A `substream_info` change is flagged on the first access unit after it
+/// with [`DecodedAccessUnit::substream_info_changed`].
+///
+/// Errors stopping the pipeline are returned from [`decode`](Self::decode),
+/// [`push_bytes`](Self::push_bytes) and [`finish`](Self::finish): in strict
+/// mode the first parse or decode error, otherwise a
+/// [`PipelineError`] or a [`DecodeError::DuplicateAccessUnit`] with
+/// [`DuplicateHandling::Error`].
+///
+/// Not available on `wasm32-unknown-unknown`, which cannot spawn threads.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use truehd::process::decode::DecodedAccessUnit;
+/// use truehd::process::pipeline::Pipeline;
+///
+/// let file = std::fs::File::open("stream.thd")?;
+/// let mut pipeline = Pipeline::builder()
+///     .presentation(1)
+///     .fail_level(log::Level::Error)
+///     .threads(4)
+///     .build()?;
+///
+/// let mut decoded: Vec<DecodedAccessUnit> = Vec::new();
+/// pipeline.run(file, &mut decoded)?;
+/// println!("{} samples", pipeline.samples());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Pipeline {
+    extractor: Extractor,
+    parser: Parser,
+    decoder: Decoder,
+    /// Replaces the decoder when decoding on multiple threads
+    parallel: Option<ParallelBatch>,
+    /// Replaces the decoder in metadata-only mode
+    oamd_extractor: Option<OamdExtractor>,
+    /// Events of the extractor, parser and decoder not yet delivered
+    events: Arc<Mutex<Vec<Event>>>,
+    /// Highest presentation decoded
+    presentation: usize,
+    /// Presentations decoded in one pass, if more than one
+    presentations: Option<[bool; MAX_PRESENTATIONS]>,
+    substream_mask: Option<u8>,
+    strict: bool,
+    keep_evo_payloads: bool,
+    metrics: bool,
+    skip_target: Option<SkipTarget>,
+    frame_count: u64,
+    total_samples: u64,
+    /// Output position of the next access unit, skipped duplicates excluded
+    sample_position: u64,
+    /// Samples delivered minus the output timing at the last warning
+    output_timing_deviation: i64,
+    substream_info: Option<u8>,
+    extended_substream_info: Option<u8>,
+    errors: ErrorBudget,
+    /// Silent access unit shaped after the last one sent, standing in for failed ones
+    silence: Option<DecodedAccessUnit>,
+    /// Inputs passed to [`decode`](Self::decode) so far
+    inputs: usize,
+    /// Set once the sink stopped the pipeline
+    stopped: bool,
+    read_buffer: Box<[u8]>,
+}
+
+impl Pipeline {
+    /// Returns a builder with the default settings.
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    /// Decodes `reader` to its end and finishes the stream.
+    ///
+    /// Returns early without error when the sink stops the pipeline.
+    pub fn run(&mut self, reader: impl Read, sink: &mut dyn Sink) -> Result<()> {
+        // Finishing a stopped pipeline only delivers the metrics
+        let _ = self.decode(reader, sink)?;
+        let _ = self.finish(sink)?;
+        Ok(())
+    }
+
+    /// Decodes the input read from `reader` until its end or until `sink`
+    /// stops the pipeline.
+    ///
+    /// Each input after the first starts a new stream rather than continuing
+    /// the previous one as a branch. Call [`finish`](Self::finish) after the
+    /// last input.
+    pub fn decode(
+        &mut self,
+        mut reader: impl Read,
+        sink: &mut dyn Sink,
+    ) -> Result<ControlFlow<()>> {
+        if self.inputs > 0 {
+            let offset = self.extractor.byte_offset();
+            if let Err(e) = self.extractor.push_stream_boundary() {
+                log::warn!("{e} at byte offset {offset} ({offset:#X})");
+            }
+        }
+        self.inputs += 1;
+
+        loop {
+            let len = match reader.read(&mut self.read_buffer) {
+                Ok(0) => return Ok(ControlFlow::Continue(())),
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            self.extractor.push_bytes(&self.read_buffer[..len]);
+            if self.process_frames(sink)?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+    }
+
+    /// Decodes the access units completed by `data`.
+    ///
+    /// Every complete access unit is delivered before returning, so pushing
+    /// the data of a live source as it arrives adds no latency.
+    pub fn push_bytes(&mut self, data: &[u8], sink: &mut dyn Sink) -> Result<ControlFlow<()>> {
+        self.extractor.push_bytes(data);
+        self.process_frames(sink)
+    }
+
+    /// Ends the stream after the last input.
+    ///
+    /// Reports an access unit cut short by the end of the input instead of
+    /// keeping its bytes buffered, decodes the access units of a pending
+    /// parallel batch and delivers the remaining metrics. Only the metrics
+    /// are delivered once the sink stopped the pipeline.
+    pub fn finish(&mut self, sink: &mut dyn Sink) -> Result<ControlFlow<()>> {
+        if !self.stopped {
+            let offset = self.extractor.byte_offset();
+            if let Err(e) = self.extractor.flush_partial() {
+                log::warn!("{e} at byte offset {offset} ({offset:#X})");
+            }
+            if self.flush_parallel(sink)? {
+                self.stopped = true;
+            }
+        }
+        self.publish_metrics(sink);
+        self.drain_events(sink);
+
+        Ok(if self.stopped {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        })
+    }
+
+    /// Returns the validation statistics of the parser and decoder.
+    pub fn stats(&self) -> DecodeStats {
+        let mut stats = self.parser.stats();
+        match &self.parallel {
+            Some(parallel) => stats.merge(&parallel.decoder.stats()),
+            None => stats.merge(&self.decoder.stats()),
+        }
+        stats
+    }
+
+    /// Returns the parse and decode errors counted so far.
+    pub fn errors(&self) -> &ErrorBudget {
+        &self.errors
+    }
+
+    /// Returns the number of frames extracted so far.
+    pub fn frames(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Returns the number of samples delivered so far, including silence.
+    pub fn samples(&self) -> u64 {
+        self.total_samples
+    }
+
+    /// Returns the number of input bytes consumed by complete access units.
+    pub fn byte_offset(&self) -> u64 {
+        self.extractor.byte_offset()
+    }
+
+    /// Returns the MAT bursts unwrapped from the input, if it was MAT
+    /// encapsulated.
+    pub fn mat_encapsulation(&self) -> Option<MatEncapsulation> {
+        self.extractor.mat_encapsulation()
+    }
+
+    /// Processes the frames available in the extractor
+    fn process_frames(&mut self, sink: &mut dyn Sink) -> Result<ControlFlow<()>> {
+        if self.stopped {
+            return Ok(ControlFlow::Break(()));
+        }
+        if self.process_available_frames(sink)? {
+            self.stopped = true;
+            return Ok(ControlFlow::Break(()));
+        }
+        self.drain_events(sink);
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Returns `true` when the sink stopped the pipeline
+    fn process_available_frames(&mut self, sink: &mut dyn Sink) -> Result<bool> {
+        loop {
+            match self.extractor.next() {
+                Some(Ok(frame)) => {
+                    self.frame_count += 1;
+                    sink.event(PipelineEvent::Frame {
+                        frame_count: self.frame_count,
+                        byte_offset: frame.byte_offset,
+                    });
+
+                    if self.frame_count.is_multiple_of(METRICS_UPDATE_FRAMES) {
+                        self.publish_metrics(sink);
+                    }
+
+                    if let Some(timestamp) = &frame.timestamp {
+                        sink.event(PipelineEvent::Timestamp(timestamp.clone()));
+                    }
+
+                    if self.process_frame(&frame, sink)? {
+                        return Ok(true);
+                    }
+                }
+                Some(Err(ExtractError::Resynchronized {
+                    offset,
+                    skipped_bytes,
+                    check,
+                })) => {
+                    self.errors.resyncs += 1;
+                    log::debug!(
+                        "Corrupt region at byte offset {offset} ({offset:#X}), {skipped_bytes} bytes: {check} check failed"
+                    );
+                    log::warn!(
+                        "Skipped {skipped_bytes} bytes of invalid data before byte offset {}",
+                        self.extractor.byte_offset()
+                    );
+                    sink.event(PipelineEvent::Resync {
+                        byte_offset: self.extractor.byte_offset(),
+                    });
+                }
+                Some(Err(ExtractError::InsufficientData)) | None => return Ok(false),
+                Some(Err(_)) => {
+                    self.errors.resyncs += 1;
+                    sink.event(PipelineEvent::Resync {
+                        byte_offset: self.extractor.byte_offset(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Parses and decodes one frame, returning `true` when the sink stopped
+    /// the pipeline
+    fn process_frame(&mut self, frame: &Frame, sink: &mut dyn Sink) -> Result<bool> {
+        let access_unit = match self.parser.parse(frame) {
+            Ok(access_unit) => access_unit,
+            Err(e) => {
+                log::error!("Parse error at frame {}: {e:#}", self.frame_count);
+                self.record_error(e, self.frame_count, frame.byte_offset)?;
+                // Also with a stricter failure level, timing restarts at the next major sync
+                self.parser.resync();
+                // The silence follows the access units pending in a parallel batch
+                return Ok(
+                    self.flush_parallel(sink)? || self.conceal_access_unit(self.frame_count, sink)
+                );
+            }
+        };
+        self.log_timestamp_drift(frame);
+
+        let substream_info_changed =
+            access_unit
+                .major_sync_info
+                .as_ref()
+                .is_some_and(|major_sync| {
+                    if let (Ok(sampling_frequency), Ok(samples_per_au)) = (
+                        major_sync.format_info.sampling_frequency_1(),
+                        major_sync.format_info.samples_per_au(),
+                    ) {
+                        sink.event(PipelineEvent::MajorSync {
+                            frame_count: self.frame_count,
+                            sampling_frequency,
+                            samples_per_au,
+                            atmos: major_sync.is_atmos(),
+                        });
+                    }
+                    self.track_substream_info(
+                        major_sync.substream_info,
+                        major_sync.extended_substream_info,
+                    )
+                });
+
+        if let Some(oamd_extractor) = &mut self.oamd_extractor {
+            match oamd_extractor.extract(&access_unit) {
+                Ok(metadata) => {
+                    self.total_samples += metadata.sample_length as u64;
+
+                    let evo_payloads = if self.keep_evo_payloads {
+                        read_evo_payloads(&access_unit)
+                    } else {
+                        Vec::new()
+                    };
+                    let mut decoded =
+                        metadata_access_unit(metadata, evo_payloads, substream_info_changed);
+                    decoded.stream_start = access_unit.stream_start;
+                    update_silence(&mut self.silence, &decoded);
+                    return Ok(self.deliver(decoded, self.frame_count, sink));
+                }
+                Err(e) => {
+                    log::error!(
+                        "OAMD error at frame {} (byte offset {} / {:#X}): {e}",
+                        self.frame_count,
+                        frame.byte_offset,
+                        frame.byte_offset
+                    );
+                    self.record_error(e, self.frame_count, frame.byte_offset)?;
+                    return Ok(self.conceal_access_unit(self.frame_count, sink));
+                }
+            }
+        }
+
+        if let Some(target) = self.skip_target
+            && let Some(target_samples) = target.samples(self.parser.sampling_frequency())
+            && self.total_samples < target_samples
+        {
+            if substream_info_changed && matches!(target, SkipTarget::Resume(_)) {
+                bail!(PipelineError::SubstreamInfoChangedBeforeResume);
+            }
+
+            let preroll = access_unit.major_sync_info.as_ref().and_then(|major_sync| {
+                let samples_per_au = major_sync.format_info.samples_per_au().ok()?;
+                Some(MAX_MAJOR_SYNC_INTERVAL * samples_per_au as u64)
+            });
+            if self.decoder.is_skipping()
+                && preroll.is_some_and(|preroll| target_samples - self.total_samples <= preroll)
+            {
+                log::debug!(
+                    "Leaving skip mode at frame {} ({} samples before the {} point)",
+                    self.frame_count,
+                    target_samples - self.total_samples,
+                    target.name()
+                );
+                self.decoder.set_skip_decode(false);
+            }
+        }
+
+        if let Some(parallel) = &mut self.parallel {
+            parallel.access_units.push(access_unit);
+            parallel.frames.push(PendingFrame {
+                frame_count: self.frame_count,
+                byte_offset: frame.byte_offset,
+                substream_info_changed,
+                output_samples: self.parser.output_samples(),
+            });
+            return Ok(parallel.is_full() && self.flush_parallel(sink)?);
+        }
+
+        let result = match (self.substream_mask, &self.presentations) {
+            (Some(mask), _) => {
+                self.decoder
+                    .decode_with_substream_mask(&access_unit, mask, self.presentation)
+            }
+            (None, Some(presentations)) => self
+                .decoder
+                .decode_presentations(&access_unit, presentations)
+                .and_then(|mut decoded| {
+                    let (_, mut highest) = decoded
+                        .pop()
+                        .ok_or_else(|| anyhow!("No presentation decoded"))?;
+                    highest.other_presentations = decoded;
+                    Ok(highest)
+                }),
+            (None, None) => self
+                .decoder
+                .decode_presentation(&access_unit, self.presentation),
+        };
+        let stopped = self.send_decoded(
+            result,
+            self.frame_count,
+            frame.byte_offset,
+            substream_info_changed,
+            sink,
+        )?;
+        self.check_output_timing(self.parser.output_samples(), self.frame_count);
+        Ok(stopped)
+    }
+
+    /// Tracks the substream info of a major sync, returning `true` when it
+    /// changed
+    fn track_substream_info(&mut self, substream_info: u8, extended_substream_info: u8) -> bool {
+        let mut changed = false;
+        if let Some(current) = self.substream_info
+            && current != substream_info
+        {
+            log::info!("substream_info changed: {current:#02X} -> {substream_info:#02X}");
+            changed = true;
+        }
+        if let Some(current) = self.extended_substream_info
+            && current != extended_substream_info
+        {
+            log::info!(
+                "extended_substream_info changed: {current:#02X} -> {extended_substream_info:#02X}"
+            );
+            changed = true;
+        }
+        self.substream_info = Some(substream_info);
+        self.extended_substream_info = Some(extended_substream_info);
+        changed
+    }
+
+    /// Logs timestamp drift of more than one frame as a warning
+    fn log_timestamp_drift(&mut self, frame: &Frame) {
+        let frame_samples = frame
+            .timestamp
+            .as_ref()
+            .and_then(|timestamp| timestamp.frame_samples(self.parser.sampling_frequency()));
+
+        for warning in self.parser.take_warnings() {
+            match warning {
+                ParseWarning::TimestampDrift {
+                    expected, actual, ..
+                } if frame_samples.is_some_and(|samples| actual.abs_diff(expected) > samples) => {
+                    log::warn!("{warning}")
+                }
+                _ => log::debug!("{warning}"),
+            }
+        }
+    }
+
+    /// Decodes the pending parallel batch, returning `true` when the sink
+    /// stopped the pipeline
+    fn flush_parallel(&mut self, sink: &mut dyn Sink) -> Result<bool> {
+        let Some(parallel) = &mut self.parallel else {
+            return Ok(false);
+        };
+
+        let results = parallel.decoder.decode(&parallel.access_units);
+        parallel.access_units.clear();
+        let frames = std::mem::take(&mut parallel.frames);
+
+        for (frame, result) in frames.into_iter().zip(results) {
+            if self.send_decoded(
+                result,
+                frame.frame_count,
+                frame.byte_offset,
+                frame.substream_info_changed,
+                sink,
+            )? {
+                return Ok(true);
+            }
+            self.check_output_timing(frame.output_samples, frame.frame_count);
+        }
+        Ok(false)
+    }
+
+    /// Logs a warning when the samples delivered deviate from the output
+    /// timing by more than one access unit since the last warning
+    ///
+    /// `output_samples` is the output timing of the parser after the access
+    /// unit of `frame_count`, see [`Parser::output_samples`].
+    fn check_output_timing(&mut self, output_samples: i64, frame_count: u64) {
+        let samples_per_au = self.parser.samples_per_au() as u64;
+        let deviation = self.total_samples as i64 - output_samples;
+        if samples_per_au > 0 && deviation.abs_diff(self.output_timing_deviation) > samples_per_au {
+            log::warn!(
+                "Decoded samples deviate from the output timing by {deviation:+} samples at frame {frame_count}"
+            );
+            self.output_timing_deviation = deviation;
+        }
+    }
+
+    /// Delivers the metrics collected since the last update
+    fn publish_metrics(&mut self, sink: &mut dyn Sink) {
+        if !self.metrics {
+            return;
+        }
+
+        let mut metrics = self.parser.take_metrics().unwrap_or_default();
+        let decode_metrics = match &mut self.parallel {
+            Some(parallel) => parallel.decoder.take_metrics(),
+            None => self.decoder.take_metrics(),
+        };
+        if let Some(decode_metrics) = decode_metrics {
+            metrics.merge(&decode_metrics);
+        }
+        sink.event(PipelineEvent::Metrics(metrics));
+    }
+
+    /// Delivers the events reported by the extractor, parser and decoder
+    fn drain_events(&mut self, sink: &mut dyn Sink) {
+        let events = match self.events.lock() {
+            Ok(mut events) => std::mem::take(&mut *events),
+            Err(_) => return,
+        };
+        for event in events {
+            sink.event(PipelineEvent::Stream(event));
+        }
+    }
+
+    /// Delivers an access unit of frame `frame_count` after the events
+    /// reported before it, returning `true` when the sink stopped the pipeline
+    ///
+    /// The access unit is placed after those delivered before it, including
+    /// concealed and inserted silence, which the decoder does not see.
+    fn deliver(
+        &mut self,
+        mut decoded: DecodedAccessUnit,
+        frame_count: u64,
+        sink: &mut dyn Sink,
+    ) -> bool {
+        decoded.au_index = frame_count.saturating_sub(1);
+        decoded.sample_position = self.sample_position;
+        for (_, other) in &mut decoded.other_presentations {
+            other.au_index = decoded.au_index;
+            other.sample_position = decoded.sample_position;
+        }
+        if !decoded.is_duplicate {
+            self.sample_position += decoded.sample_length as u64;
+        }
+
+        self.drain_events(sink);
+        sink.access_unit(decoded).is_break()
+    }
+
+    /// Forwards a decode result, returning `true` when the sink stopped the
+    /// pipeline
+    fn send_decoded(
+        &mut self,
+        result: Result<DecodedAccessUnit>,
+        frame_count: u64,
+        byte_offset: u64,
+        substream_info_changed: bool,
+        sink: &mut dyn Sink,
+    ) -> Result<bool> {
+        match result {
+            Ok(mut decoded) => {
+                // Set the substream_info_changed flag if we detected a change
+                if substream_info_changed {
+                    decoded.substream_info_changed = true;
+                    for (_, other) in &mut decoded.other_presentations {
+                        other.substream_info_changed = true;
+                    }
+                }
+
+                for silent in silent_access_units(&mut decoded) {
+                    self.total_samples += silent.sample_length as u64;
+                    if self.deliver(silent, frame_count, sink) {
+                        return Ok(true);
+                    }
+                }
+
+                self.total_samples += decoded.sample_length as u64;
+                update_silence(&mut self.silence, &decoded);
+
+                if self.decoder.is_skipping()
+                    && let Some(target) = self.skip_target
+                    && target
+                        .samples(self.parser.sampling_frequency())
+                        .is_some_and(|samples| self.total_samples >= samples)
+                {
+                    bail!(PipelineError::SkipTargetMissed {
+                        target: target.name(),
+                        samples: self.total_samples,
+                    });
+                }
+
+                Ok(self.deliver(decoded, frame_count, sink))
+            }
+            Err(e) => {
+                // Only returned with DuplicateHandling::Error, which stops decoding
+                if matches!(e.downcast_ref(), Some(DecodeError::DuplicateAccessUnit(_))) {
+                    return Err(e);
+                }
+
+                log::error!(
+                    "Decode error at frame {frame_count} (byte offset {byte_offset} / {byte_offset:#X}): {e}"
+                );
+                self.record_error(e, frame_count, byte_offset)?;
+                Ok(self.conceal_access_unit(frame_count, sink))
+            }
+        }
+    }
+
+    /// Counts a parse or decode error, returning it when processing should stop
+    ///
+    /// Strict mode stops at the first error, otherwise the pipeline stops once
+    /// the error budget is exceeded.
+    fn record_error(
+        &mut self,
+        error: anyhow::Error,
+        frame_count: u64,
+        byte_offset: u64,
+    ) -> Result<()> {
+        // Parse errors precede the decoding of a parallel batch, so the time is
+        // derived from the frame index rather than the decoded samples
+        let sampling_frequency = self.parser.sampling_frequency();
+        let time = if sampling_frequency > 0 {
+            frame_count.saturating_sub(1) as f64 * self.parser.samples_per_au() as f64
+                / sampling_frequency as f64
+        } else {
+            0.0
+        };
+        let exceeded = self.errors.record_error(frame_count, byte_offset, time);
+
+        if self.strict {
+            return Err(error);
+        }
+        if exceeded {
+            bail!(PipelineError::MaxErrorsExceeded {
+                errors: self.errors.errors,
+                max_errors: self.errors.max_errors.unwrap_or_default(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Sends silence in place of the failed access unit of frame `frame_count`,
+    /// returning `true` when the sink stopped the pipeline
+    ///
+    /// The silence lasts the samples per access unit of the last major sync, or
+    /// of the last access unit sent before the first, keeping the output as long
+    /// as the stream. Nothing is sent before the first access unit.
+    fn conceal_access_unit(&mut self, frame_count: u64, sink: &mut dyn Sink) -> bool {
+        let Some(shape) = self.silence.as_ref() else {
+            return false;
+        };
+
+        let sample_length = match self.parser.samples_per_au() {
+            0 => shape.sample_length,
+            samples_per_au => samples_per_au.min(shape.pcm_data.len()),
+        };
+        let silence = silent_access_unit(shape, sample_length);
+
+        self.errors.concealed += 1;
+        self.errors.concealed_samples += sample_length as u64;
+        self.total_samples += sample_length as u64;
+        self.deliver(silence, frame_count, sink)
+    }
+}
+
+/// Keep the silent access unit in the shape of the last access unit sent
+fn update_silence(silence: &mut Option<DecodedAccessUnit>, decoded: &DecodedAccessUnit) {
+    if silence
+        .as_ref()
+        .is_none_or(|silence| !same_shape(silence, decoded))
+    {
+        *silence = Some(silent_access_unit(decoded, decoded.sample_length));
+    }
+}
+
+/// Whether `a` and `b` have the same sampling frequency, length and channels,
+/// also in the other presentations
+fn same_shape(a: &DecodedAccessUnit, b: &DecodedAccessUnit) -> bool {
+    a.sampling_frequency == b.sampling_frequency
+        && a.sample_length == b.sample_length
+        && a.channel_labels == b.channel_labels
+        && a.other_presentations.len() == b.other_presentations.len()
+        && a.other_presentations
+            .iter()
+            .zip(&b.other_presentations)
+            .all(|((a_index, a), (b_index, b))| a_index == b_index && same_shape(a, b))
+}
+
+/// Silence with the sampling frequency and channels of `decoded`, and of its
+/// other presentations
+fn silent_access_unit(decoded: &DecodedAccessUnit, sample_length: usize) -> DecodedAccessUnit {
+    DecodedAccessUnit {
+        sampling_frequency: decoded.sampling_frequency,
+        sample_length,
+        channel_count: decoded.channel_count,
+        pcm_data: PooledPcm::default(),
+        bit_depth: decoded.bit_depth,
+        channel_labels: decoded.channel_labels.clone(),
+        oamd: Vec::new(),
+        evo_payloads: Vec::new(),
+        is_duplicate: false,
+        substream_info_changed: false,
+        config_changed: false,
+        stream_start: false,
+        silence_samples: 0,
+        au_index: decoded.au_index,
+        sample_position: decoded.sample_position,
+        discontinuity: None,
+        heavy_drc_gain: None,
+        dialogue_level: decoded.dialogue_level,
+        other_presentations: decoded
+            .other_presentations
+            .iter()
+            .map(|(presentation, other)| (*presentation, silent_access_unit(other, sample_length)))
+            .collect(),
+    }
+}
+
+/// Wrap extracted metadata as a decoded access unit without audio
+fn metadata_access_unit(
+    metadata: AccessUnitMetadata,
+    evo_payloads: Vec<RawEvoPayload>,
+    substream_info_changed: bool,
+) -> DecodedAccessUnit {
+    DecodedAccessUnit {
+        sampling_frequency: metadata.sampling_frequency,
+        sample_length: metadata.sample_length,
+        channel_count: 0,
+        pcm_data: PooledPcm::default(),
+        bit_depth: 24,
+        channel_labels: Vec::new(),
+        oamd: metadata.oamd,
+        evo_payloads,
+        is_duplicate: false,
+        substream_info_changed,
+        config_changed: false,
+        stream_start: false,
+        silence_samples: 0,
+        au_index: 0,
+        sample_position: 0,
+        discontinuity: None,
+        heavy_drc_gain: None,
+        dialogue_level: None,
+        other_presentations: Vec::new(),
+    }
+}
+
+/// Split the silence inserted before a repaired branch into access units
+///
+/// The first silent access unit takes over the restart flag and the
+/// discontinuity of `decoded`.
+/// The other presentations are split alike.
+fn silent_access_units(decoded: &mut DecodedAccessUnit) -> Vec<DecodedAccessUnit> {
+    let mut others: Vec<_> = decoded
+        .other_presentations
+        .iter_mut()
+        .map(|(presentation, other)| (*presentation, silent_access_units(other).into_iter()))
+        .collect();
+    let mut remaining = std::mem::take(&mut decoded.silence_samples);
+    let mut silent = Vec::with_capacity(remaining.div_ceil(decoded.pcm_data.len()));
+
+    while remaining > 0 {
+        let sample_length = remaining.min(decoded.pcm_data.len());
+        remaining -= sample_length;
+
+        silent.push(DecodedAccessUnit {
+            sampling_frequency: decoded.sampling_frequency,
+            sample_length,
+            channel_count: decoded.channel_count,
+            pcm_data: PooledPcm::default(),
+            bit_depth: decoded.bit_depth,
+            channel_labels: decoded.channel_labels.clone(),
+            oamd: Vec::new(),
+            evo_payloads: Vec::new(),
+            is_duplicate: false,
+            substream_info_changed: std::mem::take(&mut decoded.substream_info_changed),
+            config_changed: std::mem::take(&mut decoded.config_changed),
+            stream_start: false,
+            silence_samples: 0,
+            au_index: decoded.au_index,
+            sample_position: decoded.sample_position,
+            discontinuity: std::mem::take(&mut decoded.discontinuity),
+            heavy_drc_gain: None,
+            dialogue_level: decoded.dialogue_level,
+            other_presentations: others
+                .iter_mut()
+                .filter_map(|(presentation, silent)| Some((*presentation, silent.next()?)))
+                .collect(),
+        });
+    }
+
+    silent
+}
+
+#[test]
+fn error_budget() {
+    let mut errors = ErrorBudget::new(Some(1));
+    assert!(!errors.record_error(3, 120, 0.0025));
+    errors.resyncs += 1;
+    assert!(errors.record_error(7, 300, 0.0058));
+    assert_eq!(
+        errors.first_error().map(|first| first.byte_offset),
+        Some(120)
+    );
+
+    let mut unlimited = ErrorBudget::default();
+    assert!((0..1000).all(|frame| !unlimited.record_error(frame, 0, 0.0)));
+}
+
+#[test]
+fn pipeline_matches_streaming_decoder() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::stream::StreamingDecoder;
+
+    /// Collects the access units and the frame and major sync events
+    #[derive(Default)]
+    struct Collect {
+        decoded: Vec<DecodedAccessUnit>,
+        frames: u64,
+        major_syncs: Vec<(u32, usize)>,
+    }
+
+    impl Sink for Collect {
+        fn access_unit(&mut self, decoded: DecodedAccessUnit) -> ControlFlow<()> {
+            self.decoded.push(decoded);
+            ControlFlow::Continue(())
+        }
+
+        fn event(&mut self, event: PipelineEvent) {
+            match event {
+                PipelineEvent::Frame { frame_count, .. } => self.frames = frame_count,
+                PipelineEvent::MajorSync {
+                    sampling_frequency,
+                    samples_per_au,
+                    ..
+                } => self.major_syncs.push((sampling_frequency, samples_per_au)),
+                _ => {}
+            }
+        }
+    }
+
+    let data = EXAMPLE_DATA.repeat(2);
+    let mut streaming = StreamingDecoder::new(data.as_slice());
+    streaming.set_presentation(1);
+    let expected = streaming.collect::<Result<Vec<_>>>()?;
+
+    let mut pipeline = Pipeline::builder().presentation(1).build()?;
+    let mut sink = Collect::default();
+    pipeline.run(std::io::Cursor::new(&data), &mut sink)?;
+
+    assert_eq!(sink.decoded.len(), 4);
+    for (decoded, expected) in sink.decoded.iter().zip(&expected) {
+        assert_eq!(decoded.sample_length, expected.sample_length);
+        assert_eq!(decoded.pcm_data, expected.pcm_data);
+    }
+    assert_eq!(sink.frames, 4);
+    assert_eq!(sink.major_syncs, [(48000, 40); 2]);
+    assert_eq!((pipeline.frames(), pipeline.samples()), (4, 160));
+    assert_eq!(pipeline.byte_offset(), data.len() as u64);
+    assert_eq!(pipeline.errors().errors, 0);
+    Ok(())
+}
+
+#[test]
+fn parallel_pipeline_matches_single_thread() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+
+    let data = EXAMPLE_DATA.repeat(8);
+    let mut single = Vec::new();
+    Pipeline::builder()
+        .presentation(1)
+        .build()?
+        .run(data.as_slice(), &mut single)?;
+
+    let mut parallel = Vec::new();
+    let mut pipeline = Pipeline::builder().presentation(1).threads(2).build()?;
+    pipeline.run(data.as_slice(), &mut parallel)?;
+
+    assert_eq!(parallel.len(), 16);
+    assert_eq!(parallel.len(), single.len());
+    for (parallel, single) in parallel.iter().zip(&single) {
+        assert_eq!(parallel.pcm_data, single.pcm_data);
+    }
+    assert_eq!(pipeline.stats().failures(), 0);
+    Ok(())
+}
+
+#[test]
+fn pipeline_decodes_several_presentations() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+
+    let data = EXAMPLE_DATA.repeat(2);
+    let mut single = Vec::new();
+    Pipeline::builder()
+        .presentation(0)
+        .build()?
+        .run(data.as_slice(), &mut single)?;
+
+    // Presentation 1 is a copy of presentation 0
+    let mut decoded = Vec::new();
+    let mut pipeline = Pipeline::builder().presentations(&[1, 0]).build()?;
+    pipeline.run(data.as_slice(), &mut decoded)?;
+
+    assert_eq!(decoded.len(), single.len());
+    for (decoded, single) in decoded.iter().zip(&single) {
+        assert_eq!(decoded.pcm_data, single.pcm_data);
+        let [(0, other)] = decoded.other_presentations.as_slice() else {
+            panic!("presentation 0 not delivered");
+        };
+        assert_eq!(other.sample_length, single.sample_length);
+        assert_eq!(other.pcm_data, single.pcm_data);
+    }
+    assert_eq!(pipeline.samples(), 160);
+
+    assert!(
+        Pipeline::builder()
+            .presentations(&[0, 1])
+            .substream_mask(Some(1))
+            .build()
+            .is_err()
+    );
+    assert!(Pipeline::builder().presentations(&[]).build().is_err());
+    Ok(())
+}
+
+#[test]
+fn sink_stops_pipeline() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+
+    /// Takes a single access unit
+    struct First(Option<DecodedAccessUnit>);
+
+    impl Sink for First {
+        fn access_unit(&mut self, decoded: DecodedAccessUnit) -> ControlFlow<()> {
+            self.0 = Some(decoded);
+            ControlFlow::Break(())
+        }
+    }
+
+    let mut pipeline = Pipeline::builder().build()?;
+    let mut sink = First(None);
+    assert!(
+        pipeline
+            .decode(EXAMPLE_DATA.repeat(4).as_slice(), &mut sink)?
+            .is_break()
+    );
+    assert!(pipeline.finish(&mut sink)?.is_break());
+    assert_eq!(pipeline.samples(), 40);
+    assert!(sink.0.is_some());
+
+    assert!(Pipeline::builder().presentation(4).build().is_err());
+    assert!(Pipeline::builder().threads(0).build().is_err());
+    Ok(())
+}
+
+#[test]
+fn failed_access_unit_is_concealed() -> Result<()> {
+    use crate::structs::channel::ChannelLabel;
+
+    let decoded = || {
+        let mut decoded = DecodedAccessUnit {
+            sampling_frequency: 48000,
+            sample_length: 40,
+            channel_count: 2,
+            pcm_data: PooledPcm::default(),
+            bit_depth: 24,
+            channel_labels: vec![ChannelLabel::L, ChannelLabel::R],
+            oamd: Vec::new(),
+            evo_payloads: Vec::new(),
+            is_duplicate: false,
+            substream_info_changed: false,
+            config_changed: false,
+            stream_start: false,
+            silence_samples: 0,
+            au_index: 0,
+            sample_position: 0,
+            discontinuity: None,
+            heavy_drc_gain: None,
+            dialogue_level: None,
+            other_presentations: Vec::new(),
+        };
+        decoded.pcm_data[0] = [1; 16];
+        decoded
+    };
+
+    // The second of three access units fails to decode
+    let mut pipeline = Pipeline::builder().presentation(1).build()?;
+    let mut sent = Vec::new();
+    assert!(!pipeline.send_decoded(Ok(decoded()), 1, 0, false, &mut sent)?);
+    assert!(!pipeline.send_decoded(Err(anyhow::anyhow!("corrupt")), 2, 64, false, &mut sent)?);
+    assert!(!pipeline.send_decoded(Ok(decoded()), 3, 128, false, &mut sent)?);
+
+    assert_eq!(sent.len(), 3);
+    assert_eq!(sent.iter().map(|au| au.sample_length).sum::<usize>(), 120);
+    assert_eq!(pipeline.samples(), 120);
+    assert_eq!(sent[1].channel_labels, [ChannelLabel::L, ChannelLabel::R]);
+    assert_eq!(
+        sent.iter()
+            .map(|au| (au.au_index, au.sample_position))
+            .collect::<Vec<_>>(),
+        [(0, 0), (1, 40), (2, 80)]
+    );
+    assert!(sent[1].pcm_data.iter().flatten().all(|&sample| sample == 0));
+    assert_eq!(
+        (pipeline.errors().errors, pipeline.errors().concealed),
+        (1, 1)
+    );
+
+    // Strict mode and an exceeded budget stop at the failed access unit
+    let mut strict = Pipeline::builder().strict(true).build()?;
+    assert!(
+        strict
+            .send_decoded(Err(anyhow::anyhow!("corrupt")), 1, 0, false, &mut sent)
+            .is_err()
+    );
+    let mut limited = Pipeline::builder().max_errors(Some(0)).build()?;
+    let error = limited
+        .send_decoded(Err(anyhow::anyhow!("corrupt")), 1, 0, false, &mut sent)
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(PipelineError::MaxErrorsExceeded {
+            errors: 1,
+            max_errors: 0
+        })
+    ));
+    Ok(())
+}
+
+#[test]
+fn low_latency_access_units_follow_input() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+
+    // Eight access units, the last one cut short by the end of the input
+    let mut data = EXAMPLE_DATA.repeat(4);
+    let mut extractor = Extractor::default();
+    extractor.push_bytes(&data);
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    for frame in extractor.filter_map(Result::ok) {
+        let end = frame.byte_offset as usize + frame.as_ref().len();
+        chunks.push(start..end);
+        start = end;
+    }
+    data.extend_from_slice(&EXAMPLE_DATA[16..26]);
+    chunks.push(start..data.len());
+
+    // Every access unit is decoded from the push completing it, so none
+    // waits for the following input
+    let mut pipeline = Pipeline::builder().presentation(1).build()?;
+    let mut decoded = Vec::new();
+    for (pushes, chunk) in chunks.into_iter().enumerate() {
+        assert!(
+            pipeline
+                .push_bytes(&data[chunk], &mut decoded)?
+                .is_continue()
+        );
+        assert_eq!(decoded.len(), (pushes + 1).min(8));
+    }
+    assert!(pipeline.finish(&mut decoded)?.is_continue());
+    assert_eq!(decoded.len(), 8);
+    Ok(())
+}
@@ -0,0 +1,232 @@
+use crate::process::MAX_PRESENTATIONS;
+use crate::process::decode::{DecodedAccessUnit, Decoder};
+use crate::process::extract::Extractor;
+use crate::process::parse::Parser;
+use crate::utils::errors::{Category, ExtractError, Policy};
+use crate::utils::events::{Event, EventSink};
+use anyhow::Result;
+use std::io::{ErrorKind, Read};
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Decodes a TrueHD bitstream from an [`io::Read`](std::io::Read) source.
+///
+/// Wires up [`Extractor`], [`Parser`] and [`Decoder`] and pulls data from the
+/// reader as needed, yielding one [`DecodedAccessUnit`] per access unit.
+/// Access units split across read boundaries are handled internally.
+///
+/// Iteration ends when the reader is exhausted and no complete access unit
+/// remains. Extraction, parse and decode errors are yielded as `Err` items;
+/// iteration may continue after them, mirroring [`Extractor`]. Skipped invalid
+/// data is logged rather than yielded.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use truehd::process::stream::StreamingDecoder;
+///
+/// let file = std::fs::File::open("stream.thd")?;
+/// let mut decoder = StreamingDecoder::new(file);
+/// decoder.set_presentation(1);
+///
+/// for decoded in decoder {
+///     let decoded = decoded?;
+///     println!("{} samples", decoded.sample_length);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct StreamingDecoder<R: Read> {
+    reader: R,
+    read_buffer: Box<[u8]>,
+    eof: bool,
+    extractor: Extractor,
+    parser: Parser,
+    decoder: Decoder,
+    presentation: usize,
+}
+
+impl<R: Read> StreamingDecoder<R> {
+    /// Creates a streaming decoder for presentation 0.
+    pub fn new(reader: R) -> Self {
+        let mut decoder = Self {
+            reader,
+            read_buffer: vec![0; READ_CHUNK_SIZE].into_boxed_slice(),
+            eof: false,
+            extractor: Extractor::default(),
+            parser: Parser::default(),
+            decoder: Decoder::default(),
+            presentation: 0,
+        };
+        decoder.set_presentation(0);
+        decoder
+    }
+
+    /// Selects the presentation to decode (0-3).
+    ///
+    /// Substreams not needed for presentations up to and including `presentation`
+    /// are not parsed. Should be called before iteration starts.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `presentation` is not less than [`MAX_PRESENTATIONS`].
+    pub fn set_presentation(&mut self, presentation: usize) {
+        assert!(
+            presentation < MAX_PRESENTATIONS,
+            "presentation index must be 0-3, got {presentation}"
+        );
+
+        let mut required_presentations = [false; MAX_PRESENTATIONS];
+        required_presentations[..=presentation].fill(true);
+        self.parser
+            .set_required_presentations(&required_presentations);
+        self.presentation = presentation;
+    }
+
+    /// Sets the failure level for validation errors in the parser and decoder.
+    ///
+    /// See [`Decoder::set_fail_level`].
+    pub fn set_fail_level(&mut self, level: log::Level) {
+        self.parser.set_fail_level(level);
+        self.decoder.set_fail_level(level);
+    }
+
+    /// Sets the handling of the validation errors of `category` in the
+    /// parser and decoder.
+    ///
+    /// See [`Parser::set_category_policy`].
+    pub fn set_category_policy(&mut self, category: Category, policy: Policy) {
+        self.parser.set_category_policy(category, policy);
+        self.decoder.set_category_policy(category, policy);
+    }
+
+    /// Sets a sink receiving the [`Event`]s of the parser and decoder.
+    ///
+    /// See [`Parser::set_event_sink`].
+    pub fn set_event_sink(&mut self, sink: impl Fn(Event) + Send + Sync + 'static) {
+        let sink = EventSink::new(sink);
+        let parser_sink = sink.clone();
+        self.parser
+            .set_event_sink(move |event| parser_sink.emit(event));
+        self.decoder.set_event_sink(move |event| sink.emit(event));
+    }
+
+    /// Returns the selected presentation index.
+    pub fn presentation(&self) -> usize {
+        self.presentation
+    }
+
+    /// Returns the number of input bytes consumed by complete access units.
+    pub fn byte_offset(&self) -> u64 {
+        self.extractor.byte_offset()
+    }
+
+    /// Returns the underlying decoder.
+    pub fn decoder_mut(&mut self) -> &mut Decoder {
+        &mut self.decoder
+    }
+
+    /// Consumes the streaming decoder, returning the reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads the next chunk into the extractor. Returns `false` at end of input.
+    fn fill(&mut self) -> std::io::Result<bool> {
+        loop {
+            match self.reader.read(&mut self.read_buffer) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    self.extractor.push_bytes(&self.read_buffer[..n]);
+                    return Ok(true);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for StreamingDecoder<R> {
+    type Item = Result<DecodedAccessUnit>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.extractor.next() {
+                Some(Ok(frame)) => {
+                    let decoded = self.parser.parse(&frame).and_then(|access_unit| {
+                        self.decoder
+                            .decode_presentation(&access_unit, self.presentation)
+                    });
+                    return Some(decoded);
+                }
+                Some(Err(ExtractError::InsufficientData)) | None => {
+                    if self.eof {
+                        return None;
+                    }
+                    match self.fill() {
+                        Ok(true) => {}
+                        Ok(false) => self.eof = true,
+                        Err(e) => {
+                            self.eof = true;
+                            return Some(Err(e.into()));
+                        }
+                    }
+                }
+                Some(Err(ExtractError::Resynchronized {
+                    offset,
+                    skipped_bytes,
+                    ..
+                })) => {
+                    log::warn!(
+                        "Skipped {skipped_bytes} bytes of invalid data at byte offset {offset}"
+                    );
+                }
+                Some(Err(e)) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+#[test]
+fn streaming_decode_matches_push() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+
+    /// Reader returning at most three bytes per call
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    let mut data = EXAMPLE_DATA.to_vec();
+    data.extend_from_slice(EXAMPLE_DATA);
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    let mut decoder = Decoder::default();
+    extractor.push_bytes(&data);
+    let mut expected = Vec::new();
+    for frame in extractor.filter_map(Result::ok) {
+        let access_unit = parser.parse(&frame)?;
+        expected.push(decoder.decode_presentation(&access_unit, 1)?);
+    }
+
+    let mut streaming = StreamingDecoder::new(Trickle(&data));
+    streaming.set_presentation(1);
+    let decoded = streaming.by_ref().collect::<Result<Vec<_>>>()?;
+
+    assert_eq!(decoded.len(), 4);
+    assert_eq!(decoded.len(), expected.len());
+    for (decoded, expected) in decoded.iter().zip(&expected) {
+        assert_eq!(decoded.sample_length, expected.sample_length);
+        assert_eq!(decoded.pcm_data, expected.pcm_data);
+    }
+    assert_eq!(streaming.byte_offset(), data.len() as u64);
+    assert!(streaming.next().is_none());
+    Ok(())
+}
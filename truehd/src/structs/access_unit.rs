@@ -9,9 +9,9 @@ use crate::process::parse::ParserState;
 use crate::structs::channel::ChannelLabel;
 use crate::structs::extra_data::ExtraData;
 use crate::structs::substream::{SubstreamDirectory, SubstreamSegment};
-use crate::structs::sync::{MAJOR_SYNC_FBA, MAJOR_SYNC_FBB, MajorSyncInfo, UNIMPLEMENTED_FBB_MSG};
-use crate::utils::bitstream_io::BsIoSliceReader;
-use crate::utils::errors::AccessUnitError;
+use crate::structs::sync::{MAJOR_SYNC_FBA, MAJOR_SYNC_FBB, MajorSyncInfo};
+use crate::utils::bitstream_io::{BitstreamWriter, BsIoSliceReader};
+use crate::utils::errors::{AccessUnitError, Category, ParseError, SubstreamError};
 
 /// A parsed access unit containing structured audio data and metadata.
 ///
@@ -20,6 +20,7 @@ use crate::utils::errors::AccessUnitError;
 /// and compressed audio segments.
 ///
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct AccessUnit {
     /// Check nibble for access unit validation.
     ///
@@ -41,6 +42,12 @@ pub struct AccessUnit {
     /// Contains stream configuration and decoder initialization parameters.
     pub major_sync_info: Option<MajorSyncInfo>,
 
+    /// Number of substreams in this access unit.
+    ///
+    /// Only the first `substreams` entries of `substream_directory` and
+    /// `substream_segment` are read.
+    pub substreams: usize,
+
     /// Substream directory for navigation and CRC control.
     ///
     /// Array of directory entries containing end pointers and control flags.
@@ -56,8 +63,30 @@ pub struct AccessUnit {
     /// Contains auxiliary information including object audio metadata.
     pub extra_data: Option<ExtraData>,
 
+    /// Channel count of each substream according to its last restart header,
+    /// which may precede this access unit.
+    ///
+    /// `None` until the parser has seen a restart header of the substream.
+    pub channel_counts: [Option<u8>; MAX_PRESENTATIONS],
+
     /// Indicates if this access unit is at a valid branch point.
     pub has_valid_branch: bool,
+
+    /// Indicates if this access unit starts a stream concatenated at a
+    /// boundary marked with
+    /// [`Extractor::push_stream_boundary`](crate::process::extract::Extractor::push_stream_boundary).
+    pub stream_start: bool,
+
+    /// Samples to insert (positive) or drop (negative) before this access unit
+    /// to repair an invalid branch.
+    ///
+    /// Only set with [`Parser::set_repair_branch`](crate::process::parse::Parser::set_repair_branch).
+    pub branch_repair: Option<isize>,
+
+    /// Indicates if this access unit starts a substream configuration change
+    /// accepted with [`Parser::set_allow_format_change`](crate::process::parse::Parser::set_allow_format_change),
+    /// restarting the decoder with the new presentation layout.
+    pub format_change: bool,
 }
 
 impl AccessUnit {
@@ -77,6 +106,8 @@ impl AccessUnit {
         state.output_timing_jump = false;
         state.peak_data_rate_jump = false;
         state.has_substream_info_changed = false;
+        state.format_change = None;
+        state.branch_repair = None;
 
         let mut au = Self {
             check_nibble: reader.get_n(4)?,
@@ -124,7 +155,7 @@ impl AccessUnit {
         let test_bytes: u32 = reader.get_n(32)?;
         reader.seek(-32)?;
 
-        if test_bytes == MAJOR_SYNC_FBA {
+        if test_bytes == MAJOR_SYNC_FBA || test_bytes == MAJOR_SYNC_FBB {
             au.major_sync_info = Some(MajorSyncInfo::read(state, reader)?);
 
             let suffix = if state.last_major_sync_index > 0 {
@@ -139,9 +170,6 @@ impl AccessUnit {
             trace!("AU {}: Major sync found {}", state.au_counter, suffix);
 
             state.last_major_sync_index = state.au_counter;
-        } else if test_bytes == MAJOR_SYNC_FBB {
-            // TODO: Implement FBB
-            unimplemented!("{}", UNIMPLEMENTED_FBB_MSG)
         } else {
             // no major sync, update gap check
 
@@ -154,7 +182,12 @@ impl AccessUnit {
 
         // TODO: 32 for FBB
         if state.format_sync == MAJOR_SYNC_FBA && major_sync_interval > 128 {
-            log_or_err!(state, Warn, anyhow!(AccessUnitError::FbaSyncTooFar));
+            log_or_err!(
+                state,
+                Category::Structure,
+                Warn,
+                anyhow!(AccessUnitError::FbaSyncTooFar)
+            );
         }
 
         // TODO: restart gap check
@@ -167,6 +200,8 @@ impl AccessUnit {
             bail!(AccessUnitError::NoSubstream)
         };
 
+        au.substreams = substreams;
+
         for i in 0..substreams {
             state.substream_index = i;
             au.substream_directory[i] = SubstreamDirectory::read(state, reader)?;
@@ -189,6 +224,8 @@ impl AccessUnit {
         state.substream_segment_start_pos = reader.position()?;
         state.has_parsed_substream = false;
 
+        au.check_substream_directory(state)?;
+
         for i in 0..substreams {
             state.substream_index = i;
 
@@ -211,12 +248,16 @@ impl AccessUnit {
         }
 
         state.has_parsed_au = true;
+        if state.is_major_sync {
+            state.resyncing = false;
+        }
 
         if reader.position()? <= state.expected_au_end_pos() as u64 {
             state.total_access_unit_length += au.access_unit_length as usize;
         } else {
             log_or_err!(
                 state,
+                Category::Structure,
                 Error,
                 anyhow!(AccessUnitError::AccessUnitTooLong(
                     reader.position()? as usize,
@@ -228,59 +269,263 @@ impl AccessUnit {
         state.au_counter += 1; // TODO: migrate to gap check, should reset on sync check
 
         au.has_valid_branch = state.has_valid_branch || state.has_substream_info_changed;
+        au.branch_repair = state.branch_repair.take();
+        au.channel_counts = state.restart_headers.each_ref().map(|restart_header| {
+            restart_header
+                .as_ref()
+                .map(|restart_header| restart_header.max_matrix_chan + 1)
+        });
+        if let Some(format_change) = state.format_change.take() {
+            format_change.check(state)?;
+            au.format_change = true;
+        }
 
         Ok(au)
     }
 
+    /// Checks the substream end pointers against the access unit length.
+    ///
+    /// End pointers must increase, each segment must hold at least one word
+    /// of blocks followed by its parity and CRC word when present, and the
+    /// last segment must end within the access unit.
+    fn check_substream_directory(&self, state: &ParserState) -> Result<()> {
+        let available = (state.expected_au_end_pos() as u64)
+            .saturating_sub(state.substream_segment_start_pos)
+            >> 4;
+        let mut segment_start = 0;
+
+        for (substream, entry) in self.substream_directory[..self.substreams]
+            .iter()
+            .enumerate()
+        {
+            let end_ptr = entry.substream_end_ptr as u64;
+            let min_length = 1 + entry.crc_present as u64;
+
+            if end_ptr < segment_start + min_length || end_ptr > available {
+                bail!(ParseError::SubstreamDirectoryInvalid {
+                    substream,
+                    end_ptr: entry.substream_end_ptr,
+                    au_length: self.access_unit_length,
+                });
+            }
+
+            segment_start = end_ptr;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the length in bytes of each substream segment according to
+    /// the substream directory.
+    pub fn substream_segment_sizes(&self) -> Vec<usize> {
+        let mut segment_start = 0;
+
+        self.substream_directory[..self.substreams]
+            .iter()
+            .map(|entry| {
+                let end_ptr = entry.substream_end_ptr as usize;
+                let size = end_ptr.saturating_sub(segment_start) * 2;
+                segment_start = end_ptr;
+                size
+            })
+            .collect()
+    }
+
+    /// Serializes the access unit back to its bitstream.
+    ///
+    /// Writing the access units of a stream in order with the same `writer`
+    /// reproduces the parsed bytes when they are unmodified. The lengths,
+    /// substream end pointers, check nibbles, parities and CRCs are
+    /// recomputed from what is written, and reserved bits are written as zero.
+    ///
+    /// All substreams must have been parsed, see
+    /// [`Parser::set_required_presentations`](crate::process::parse::Parser::set_required_presentations).
+    pub fn write(&self, writer: &mut BitstreamWriter) -> Result<Vec<u8>> {
+        let mut state = std::mem::take(&mut writer.state);
+        let result = self.write_with_state(&mut state, writer);
+        writer.state = state;
+
+        match result {
+            Ok(()) => Ok(writer.take_bytes()),
+            Err(e) => {
+                writer.take_bytes();
+                Err(e)
+            }
+        }
+    }
+
+    fn write_with_state(
+        &self,
+        state: &mut ParserState,
+        writer: &mut BitstreamWriter,
+    ) -> Result<()> {
+        writer.put_n(0u16, 16);
+        writer.put_n(self.input_timing, 16);
+
+        if let Some(major_sync_info) = &self.major_sync_info {
+            major_sync_info.write(state, writer)?;
+        } else if state.substreams.is_none() {
+            bail!(AccessUnitError::MissingInitialSync)
+        }
+
+        let Some(substreams) = state.substreams else {
+            bail!(AccessUnitError::NoSubstream)
+        };
+
+        let directory_start_pos = writer.position();
+        let mut end_ptr_pos = Vec::with_capacity(substreams);
+
+        for i in 0..substreams {
+            state.substream_index = i;
+            end_ptr_pos.push(writer.position() + 4);
+            self.substream_directory[i].write(state, writer)?;
+        }
+
+        let segment_start_pos = writer.position();
+
+        for (i, &end_ptr_pos) in end_ptr_pos.iter().enumerate() {
+            state.substream_index = i;
+            self.substream_segment[i].write(state, writer)?;
+
+            let end_ptr = (writer.position() - segment_start_pos) >> 4;
+            if end_ptr > 0xFFF {
+                bail!(SubstreamError::EndPtrOverflow {
+                    substream: i,
+                    end_ptr
+                });
+            }
+
+            writer.put_n_at(end_ptr_pos, end_ptr, 12);
+            state.substream_state_mut()?.substream_end_ptr = end_ptr as u16;
+        }
+
+        if let Some(extra_data) = &self.extra_data {
+            extra_data.write(state, writer)?;
+        }
+
+        let access_unit_length = writer.position().div_ceil(16);
+        if access_unit_length > 0xFFF {
+            bail!(AccessUnitError::AccessUnitLengthOverflow(
+                access_unit_length
+            ));
+        }
+
+        writer.put_n(0u8, ((access_unit_length << 4) - writer.position()) as u32);
+        writer.put_n_at(4, access_unit_length, 12);
+
+        let parity = writer.parity(0, 32)
+            ^ writer.parity(directory_start_pos, segment_start_pos - directory_start_pos);
+        writer.put_n_at(0, 0xF ^ (parity ^ (parity >> 4)) & 0xF, 4);
+
+        Ok(())
+    }
+
+    /// Returns the channel count of presentation `index`, the number of
+    /// elements including objects for presentation 3.
+    ///
+    /// Taken from the restart header of the substream in this access unit or
+    /// the last one seen before it, `None` when the substream has not
+    /// restarted yet.
+    pub fn presentation_channel_count(&self, index: usize) -> Option<u8> {
+        self.substream_segment
+            .get(index)?
+            .block
+            .first()
+            .and_then(|block| block.restart_header.as_ref())
+            .map(|restart_header| restart_header.max_matrix_chan + 1)
+            .or(*self.channel_counts.get(index)?)
+    }
+
     pub fn get_channel_labels(&self, presentation_index: usize) -> Option<Vec<ChannelLabel>> {
         let major_sync_info = self.major_sync_info.as_ref()?;
 
+        if major_sync_info.format_sync == MAJOR_SYNC_FBB {
+            return self.get_fbb_channel_labels(presentation_index);
+        }
+
         match presentation_index {
             0 => {
-                if self
-                    .substream_segment
-                    .as_ref()
-                    .first()?
-                    .block
-                    .first()?
-                    .restart_header
-                    .as_ref()?
-                    .max_matrix_chan
-                    == 0
-                {
+                if self.presentation_channel_count(0)? == 1 {
                     Some(vec![ChannelLabel::C])
                 } else {
                     Some(vec![ChannelLabel::L, ChannelLabel::R])
                 }
             }
-            1 => ChannelLabel::from_sixch_channel(
-                major_sync_info.format_info.sixch_decoder_channel_assignment,
-            )
-            .ok(),
-            2 => ChannelLabel::from_eightch_channel(
-                major_sync_info
-                    .format_info
-                    .eightch_decoder_channel_assignment,
-                major_sync_info.flags,
-            )
-            .ok(),
+            1 => self.labels_or_generic(
+                1,
+                ChannelLabel::from_sixch_channel(
+                    major_sync_info.format_info.sixch_decoder_channel_assignment,
+                ),
+            ),
+            2 => self.labels_or_generic(
+                2,
+                ChannelLabel::from_eightch_channel(
+                    major_sync_info
+                        .format_info
+                        .eightch_decoder_channel_assignment,
+                    major_sync_info.flags,
+                ),
+            ),
             3 => {
                 let ext_meaning = major_sync_info
                     .channel_meaning
                     .extra_channel_meaning
                     .as_ref()?;
 
-                if ext_meaning.dyn_object_only && ext_meaning.lfe_present || ext_meaning.lfe_only {
-                    Some(vec![ChannelLabel::LFE])
+                let mut labels = if ext_meaning.dyn_object_only && ext_meaning.lfe_present
+                    || ext_meaning.lfe_only
+                {
+                    vec![ChannelLabel::LFE]
                 } else {
                     ChannelLabel::from_sixteenth_channel(ext_meaning.sixteench_channel_assignment)
-                        .ok()
+                        .ok()?
+                };
+
+                // The channels following the bed carry the dynamic objects
+                if let Some(channels) = self.presentation_channel_count(3) {
+                    let channels = channels as usize;
+                    let objects = channels.saturating_sub(labels.len());
+                    labels.extend((0..objects).map(|object| ChannelLabel::Object(object as u8)));
                 }
+
+                Some(labels)
             }
             _ => None,
         }
     }
 
+    /// Falls back to generic labels for the channels of a presentation whose
+    /// channel assignment is reserved.
+    fn labels_or_generic(
+        &self,
+        presentation_index: usize,
+        labels: Result<Vec<ChannelLabel>>,
+    ) -> Option<Vec<ChannelLabel>> {
+        match labels {
+            Ok(labels) => Some(labels),
+            Err(e) => {
+                let channels = self.presentation_channel_count(presentation_index)?;
+                warn!(
+                    "{e}, labelling the {channels} channels of presentation {presentation_index} generically"
+                );
+                Some((0..channels).map(ChannelLabel::Generic).collect())
+            }
+        }
+    }
+
+    fn get_fbb_channel_labels(&self, presentation_index: usize) -> Option<Vec<ChannelLabel>> {
+        let major_sync_info = self.major_sync_info.as_ref()?;
+        let labels =
+            ChannelLabel::from_fbb_channel(major_sync_info.format_info.fbb_channel_assignment)
+                .ok()?;
+
+        match (presentation_index, major_sync_info.substreams) {
+            (0, 1) | (1, 2) => Some(labels),
+            (0, 2) => Some(vec![ChannelLabel::L, ChannelLabel::R]),
+            _ => None,
+        }
+    }
+
     fn check_fifo(state: &mut ParserState) -> Result<()> {
         if !state.check_fifo {
             return Ok(());
@@ -312,7 +557,7 @@ impl AccessUnit {
             warn!("Peak data rate exceeds maximum allowed");
         }
 
-        if !state.has_parsed_au {
+        if !state.has_parsed_au || state.resyncing {
             return Ok(());
         }
 
@@ -335,6 +580,7 @@ impl AccessUnit {
             if !state.allow_seamless_branch || !state.is_major_sync {
                 log_or_err!(
                     state,
+                    Category::Timing,
                     Warn,
                     anyhow!(AccessUnitError::TimingTooShort(
                         state.input_timing,
@@ -347,6 +593,7 @@ impl AccessUnit {
             if state.has_valid_branch {
                 log_or_err!(
                     state,
+                    Category::Timing,
                     Warn,
                     anyhow!(AccessUnitError::TimingTooShortAfterJump)
                 );
@@ -360,6 +607,7 @@ impl AccessUnit {
             if !state.allow_seamless_branch || !state.is_major_sync {
                 log_or_err!(
                     state,
+                    Category::Timing,
                     Warn,
                     anyhow!(AccessUnitError::TimingShorterThanPrevious)
                 );
@@ -368,6 +616,7 @@ impl AccessUnit {
             if state.has_valid_branch {
                 log_or_err!(
                     state,
+                    Category::Timing,
                     Warn,
                     anyhow!(AccessUnitError::TimingShorterThanPreviousAfterJump)
                 );
@@ -381,12 +630,18 @@ impl AccessUnit {
             && (state.prev_access_unit_length << 8 > input_timing_interval * state.peak_data_rate)
         {
             if !state.allow_seamless_branch || !state.is_major_sync {
-                log_or_err!(state, Warn, anyhow!(AccessUnitError::DataRateExceeded));
+                log_or_err!(
+                    state,
+                    Category::DataRate,
+                    Warn,
+                    anyhow!(AccessUnitError::DataRateExceeded)
+                );
             }
 
             if state.has_valid_branch {
                 log_or_err!(
                     state,
+                    Category::DataRate,
                     Warn,
                     anyhow!(AccessUnitError::DataRateExceededAfterJump)
                 );
@@ -398,12 +653,18 @@ impl AccessUnit {
 
         if state.has_parsed_au && input_timing_interval > samples_per_75ms as usize {
             if !state.allow_seamless_branch || !state.is_major_sync {
-                log_or_err!(state, Warn, anyhow!(AccessUnitError::TimingTooLong));
+                log_or_err!(
+                    state,
+                    Category::Timing,
+                    Warn,
+                    anyhow!(AccessUnitError::TimingTooLong)
+                );
             }
 
             if state.has_valid_branch {
                 log_or_err!(
                     state,
+                    Category::Timing,
                     Warn,
                     anyhow!(AccessUnitError::TimingTooLongAfterJump)
                 );
@@ -431,6 +692,7 @@ impl AccessUnit {
             if data_rate_16x.abs_diff(total_length_16x) >= 0x100 {
                 log_or_err!(
                     state,
+                    Category::DataRate,
                     Warn,
                     anyhow!(AccessUnitError::FixedRateMismatch(
                         data_rate_16x,
@@ -445,12 +707,230 @@ impl AccessUnit {
 
     pub fn update_decoder_state(&self, state: &mut DecoderState) -> Result<()> {
         state.has_valid_branch = self.has_valid_branch;
+        if let Some(gap) = self.branch_repair {
+            state.reset_for_branch_repair(gap);
+        }
+        if self.format_change && state.valid {
+            state.substream_info_changed = true;
+            state.reset_for_stream_restart();
+        }
         if let Some(major_sync_info) = &self.major_sync_info {
             major_sync_info.update_decoder_state(state)?;
         } else if !state.valid {
             return Ok(());
         }
 
+        // Nothing carries over from the stream before a boundary
+        if self.stream_start {
+            state.reset_for_stream_restart();
+            state.prev_input_timing = None;
+        }
+
         Ok(())
     }
 }
+
+#[test]
+fn write_round_trip() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    let round_trip = |data: &[u8]| -> Result<usize> {
+        let mut extractor = Extractor::default();
+        let mut parser = Parser::default();
+        let mut writer = BitstreamWriter::new();
+        extractor.push_bytes(data);
+
+        let mut access_units = 0;
+        for frame in extractor.filter_map(Result::ok) {
+            let access_unit = parser.parse(&frame)?;
+            assert_eq!(access_unit.write(&mut writer)?, frame.as_ref());
+            access_units += 1;
+        }
+        Ok(access_units)
+    };
+
+    assert_eq!(round_trip(EXAMPLE_DATA)?, 2);
+    assert_eq!(round_trip(&EXAMPLE_DATA.repeat(64))?, 128);
+    Ok(())
+}
+
+#[test]
+fn invalid_substream_directory() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::{Extractor, Frame};
+    use crate::process::parse::Parser;
+
+    let frames = {
+        let mut extractor = Extractor::default();
+        extractor.push_bytes(EXAMPLE_DATA);
+        extractor.filter_map(Result::ok).collect::<Vec<_>>()
+    };
+
+    // The second access unit is 10 words long with a single substream
+    // segment from word 3 to substream_end_ptr 0x007
+    let parse_with_end_ptr = |end_ptr: u8| -> Result<AccessUnit> {
+        let mut parser = Parser::default();
+        parser.parse(&frames[0])?;
+
+        let mut data = frames[1].as_ref().to_vec();
+        let delta = data[5] ^ end_ptr;
+        data[5] = end_ptr;
+        // Keep the nibble parity of the header and directory valid
+        data[0] ^= delta << 4;
+
+        parser.parse(&Frame {
+            data: data.into(),
+            ..frames[1].clone()
+        })
+    };
+
+    let access_unit = parse_with_end_ptr(0x07)?;
+    assert_eq!(access_unit.substreams, 1);
+    assert_eq!(access_unit.substream_segment_sizes(), [14]);
+
+    // Past the end of the access unit, and too short for the parity and CRC
+    for end_ptr in [0x08, 0x01] {
+        let err = parse_with_end_ptr(end_ptr).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ParseError::SubstreamDirectoryInvalid {
+                substream: 0,
+                end_ptr: e,
+                au_length: 0x00A
+            }) if *e == end_ptr as u16
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn sixteench_channel_labels_include_objects() {
+    use crate::structs::block::Block;
+    use crate::structs::channel::ExtraChannelMeaning;
+    use crate::structs::restart_header::RestartHeader;
+    use ChannelLabel::*;
+
+    let access_unit = |extra_channel_meaning, max_matrix_chan| {
+        let mut access_unit = AccessUnit::default();
+        let mut major_sync_info = MajorSyncInfo::default();
+        major_sync_info.channel_meaning.extra_channel_meaning = Some(extra_channel_meaning);
+        access_unit.major_sync_info = Some(major_sync_info);
+        access_unit.substream_segment[3].block.push(Block {
+            restart_header: Some(RestartHeader {
+                max_matrix_chan,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        access_unit
+    };
+
+    // 5.1 bed followed by 10 objects
+    let bed_and_objects = access_unit(
+        ExtraChannelMeaning {
+            sixteench_content_description: 1,
+            sixteench_channel_assignment: 0b1111,
+            ..Default::default()
+        },
+        15,
+    );
+    let labels = bed_and_objects.get_channel_labels(3).unwrap();
+    assert_eq!(labels.len(), 16);
+    assert_eq!(labels[..7], [L, R, C, LFE, Ls, Rs, Object(0)]);
+    assert_eq!(labels[15], Object(9));
+    assert_eq!(labels[15].to_string(), "Obj9");
+    assert_eq!(labels[3].to_string(), "LFE");
+
+    let lfe_and_objects = access_unit(
+        ExtraChannelMeaning {
+            dyn_object_only: true,
+            lfe_present: true,
+            ..Default::default()
+        },
+        2,
+    );
+    assert_eq!(
+        lfe_and_objects.get_channel_labels(3).unwrap(),
+        [LFE, Object(0), Object(1)]
+    );
+}
+
+#[test]
+fn channel_count_retained_between_restarts() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    assert_eq!(AccessUnit::default().presentation_channel_count(0), None);
+
+    let mut extractor = Extractor::default();
+    let mut parser = Parser::default();
+    extractor.push_bytes(EXAMPLE_DATA);
+
+    let mut without_restart = 0;
+    for frame in extractor.filter_map(Result::ok) {
+        let access_unit = parser.parse(&frame)?;
+        if access_unit.substream_segment[0].block[0]
+            .restart_header
+            .is_none()
+        {
+            without_restart += 1;
+        }
+        assert_eq!(access_unit.presentation_channel_count(0), Some(2));
+        assert_eq!(access_unit.presentation_channel_count(1), None);
+    }
+    assert!(without_restart > 0);
+
+    Ok(())
+}
+
+#[test]
+fn reserved_channel_assignment_labelled_generically() {
+    use crate::structs::block::Block;
+    use crate::structs::restart_header::RestartHeader;
+    use crate::utils::errors::SyncError;
+    use ChannelLabel::*;
+
+    // Eight channels do not fit a 6ch presentation
+    let err = ChannelLabel::from_sixch_channel(0x1F).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(SyncError::InvalidSixchChannelAssignment(0x1F))
+    ));
+    assert!(ChannelLabel::from_sixch_channel(0).is_err());
+    assert_eq!(
+        ChannelLabel::from_sixch_channel(0x0F).unwrap(),
+        [L, R, C, LFE, Ls, Rs]
+    );
+
+    assert!(matches!(
+        ChannelLabel::from_eightch_channel(0x1F3, 0)
+            .unwrap_err()
+            .downcast_ref(),
+        Some(SyncError::InvalidEightchChannelAssignment(0x1F3))
+    ));
+    // Only the lower five bits are defined in the alternative assignment
+    assert!(ChannelLabel::from_eightch_channel(0x21, 0x800).is_err());
+    assert_eq!(
+        ChannelLabel::from_eightch_channel(0x11, 0x800).unwrap(),
+        [L, R, Tsl, Tsr]
+    );
+
+    let mut access_unit = AccessUnit::default();
+    let mut major_sync_info = MajorSyncInfo::default();
+    major_sync_info.format_info.sixch_decoder_channel_assignment = 0x1F;
+    access_unit.major_sync_info = Some(major_sync_info);
+    access_unit.substream_segment[1].block.push(Block {
+        restart_header: Some(RestartHeader {
+            max_matrix_chan: 5,
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+
+    let labels = access_unit.get_channel_labels(1).unwrap();
+    assert_eq!(labels, (0..6).map(Generic).collect::<Vec<_>>());
+    assert_eq!(labels[5].to_string(), "Chan5");
+}
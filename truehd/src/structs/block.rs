@@ -12,7 +12,7 @@
 
 use anyhow::{Result, anyhow, bail};
 use log::Level::Warn;
-use log::{info, trace, warn};
+use log::{trace, warn};
 
 use crate::log_or_err;
 use crate::process::decode::DecoderState;
@@ -20,14 +20,15 @@ use crate::process::parse::{ParserState, ParserSubstreamState};
 use crate::structs::channel::ChannelParams;
 use crate::structs::matrix::Matrixing;
 use crate::structs::restart_header::{Guards, GuardsField, RestartHeader};
-use crate::utils::bitstream_io::BsIoSliceReader;
-use crate::utils::errors::BlockError;
+use crate::utils::bitstream_io::{BitstreamWriter, BsIoSliceReader};
+use crate::utils::errors::{BlockError, Category};
 
 /// Block header containing selective parameter updates.
 ///
 /// Block headers provide incremental updates to decoding parameters controlled
 /// by guard flags. Only parameters with enabled guards can be updated.
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct BlockHeader {
     pub guards: Option<Guards>,
     pub block_size: Option<usize>,
@@ -42,11 +43,14 @@ pub struct BlockHeader {
 /// Contains 8-160 samples per channel with optional restart header,
 /// block header, and compressed audio data.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Block {
     pub restart_header: Option<RestartHeader>,
     pub block_header: Option<BlockHeader>,
     pub block_data_bits: Option<u16>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub bypassed_lsb: [[i32; 16]; 160],
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub block_data: [[i32; 16]; 160],
     pub block_header_crc: u8,
 }
@@ -75,7 +79,10 @@ impl BlockHeader {
         if ss_state.guards.need_change(GuardsField::Guards) {
             // new_guards
             if reader.get()? {
-                ss_state.guards = Guards::read(reader)?;
+                let guards = Guards::read(reader)?;
+
+                bh.guards = Some(guards);
+                ss_state.guards = guards;
             }
         }
 
@@ -152,6 +159,75 @@ impl BlockHeader {
         Ok(bh)
     }
 
+    fn write(&self, state: &mut ParserState, writer: &mut BitstreamWriter) -> Result<()> {
+        let ss_state = state.substream_state_mut()?;
+
+        if ss_state.guards.need_change(GuardsField::Guards) {
+            writer.put(self.guards.is_some());
+            if let Some(guards) = self.guards {
+                guards.write(writer);
+                ss_state.guards = guards;
+            }
+        }
+
+        let guards = ss_state.guards;
+
+        if guards.need_change(GuardsField::BlockSize) {
+            writer.put(self.block_size.is_some());
+            if let Some(block_size) = self.block_size {
+                writer.put_n(block_size as u16, 9);
+                ss_state.block_size = block_size;
+            }
+        }
+
+        if guards.need_change(GuardsField::Matrixing) {
+            writer.put(self.matrixing.is_some());
+            if let Some(matrixing) = &self.matrixing {
+                matrixing.write(state, writer)?;
+            }
+        }
+
+        let ss_state = state.substream_state_mut()?;
+
+        if guards.need_change(GuardsField::OutputShift) {
+            let new_output_shift = self.output_shift.iter().any(Option::is_some);
+
+            writer.put(new_output_shift);
+            if new_output_shift {
+                for i in 0..=ss_state.max_matrix_chan {
+                    let output_shift = self.output_shift[i].unwrap_or(ss_state.output_shift[i]);
+
+                    writer.put_s(output_shift, 4);
+                    ss_state.output_shift[i] = output_shift;
+                }
+            }
+        }
+
+        if guards.need_change(GuardsField::QuantiserStepSize) {
+            let new_quantiser_step_size = self.quantiser_step_size.iter().any(Option::is_some);
+
+            writer.put(new_quantiser_step_size);
+            if new_quantiser_step_size {
+                for i in 0..=ss_state.max_chan {
+                    let quantiser_step_size =
+                        self.quantiser_step_size[i].unwrap_or(ss_state.quantiser_step_size[i]);
+
+                    writer.put_n(quantiser_step_size, 4);
+                    ss_state.quantiser_step_size[i] = quantiser_step_size;
+                }
+            }
+        }
+
+        for chi in ss_state.min_chan..=ss_state.max_chan {
+            writer.put(self.channel_params[chi].is_some());
+            if let Some(channel_params) = &self.channel_params[chi] {
+                channel_params.write(state, writer, chi)?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn update_decoder_state(&self, state: &mut DecoderState) -> Result<()> {
         if let Some(block_size) = self.block_size {
             state.substream_state_mut()?.block_size = block_size;
@@ -188,6 +264,7 @@ impl BlockHeader {
 impl Block {
     pub fn read(state: &mut ParserState, reader: &mut BsIoSliceReader) -> Result<Self> {
         let mut b = Block::default();
+        let start_pos = reader.position()?;
 
         // block_header_exists
         if reader.get()? {
@@ -199,6 +276,9 @@ impl Block {
             b.block_header = Some(BlockHeader::read(state, reader)?);
         }
 
+        // The block header CRC covers the bits up to here
+        let header_len = reader.position()? - start_pos;
+
         b.block_data_bits = if state.substream_state()?.error_protect {
             let block_data_bits = reader.get_n(16)?;
             if block_data_bits > 16000 {
@@ -250,11 +330,15 @@ impl Block {
                 state.au_counter, latency, prev_latency, state.advance
             );
 
-            if state.flags & 0x8000 == 0 || (!state.has_parsed_au) {
+            // The latency is not known until timing restarts after a parse error
+            let check_latency = !state.resyncing;
+
+            if state.flags & 0x8000 == 0 || !state.has_parsed_au || !check_latency {
                 prev_latency = latency;
             } else if prev_latency != latency {
                 log_or_err!(
                     state,
+                    Category::Timing,
                     Warn,
                     anyhow!(BlockError::LatencyInconsistent {
                         substream: state.substream_index
@@ -262,9 +346,10 @@ impl Block {
                 );
             }
 
-            if state.fifo_duration > prev_latency {
+            if check_latency && state.fifo_duration > prev_latency {
                 log_or_err!(
                     state,
+                    Category::Timing,
                     Warn,
                     anyhow!(BlockError::DurationExceedsLatency {
                         duration: state.fifo_duration,
@@ -275,9 +360,10 @@ impl Block {
 
             let samples_per_75ms = (state.audio_sampling_frequency_1 * 3).div_ceil(40);
 
-            if prev_latency as u32 > samples_per_75ms {
+            if check_latency && prev_latency as u32 > samples_per_75ms {
                 log_or_err!(
                     state,
+                    Category::Timing,
                     Warn,
                     anyhow!(BlockError::LatencyTooHigh {
                         latency: prev_latency,
@@ -286,9 +372,10 @@ impl Block {
                 );
             }
 
-            if prev_latency < samples_per_au {
+            if check_latency && prev_latency < samples_per_au {
                 log_or_err!(
                     state,
+                    Category::Timing,
                     Warn,
                     anyhow!(BlockError::LatencyTooLow {
                         latency: prev_latency,
@@ -421,38 +508,42 @@ impl Block {
                     0
                 };
 
+                // The LSBs and Huffman code can together exceed 32 bits, so
+                // the sample is accumulated in 64 bits and range checked below
                 let mut audio_data = if huff_type != 0 {
-                    let huff_code = reader.get_huffman(huff_type)?;
+                    let huff_code = reader.get_huffman(huff_type)? as i64;
                     let lsbs = if lsbs_bits > 0 {
-                        reader.get_n::<u32>(lsbs_bits)? as i32
+                        reader.get_n::<u64>(lsbs_bits)? as i64
                     } else {
                         0
                     };
                     let shift = lsbs_bits as i32 + (2 - huff_type as i32);
 
-                    lsbs + (huff_code << lsbs_bits) - if shift < 0 { 0 } else { 1 << shift }
+                    lsbs + (huff_code << lsbs_bits) - if shift < 0 { 0 } else { 1i64 << shift }
                 } else {
                     let lsbs = if lsbs_bits > 0 {
-                        reader.get_n::<u32>(lsbs_bits)? as i32
+                        reader.get_n::<u64>(lsbs_bits)? as i64
                     } else {
                         0
                     };
                     lsbs - (if lsbs_bits > 0 {
-                        1 << (lsbs_bits - 1)
+                        1i64 << (lsbs_bits - 1)
                     } else {
                         0
                     })
                 };
 
-                audio_data += huff_offset;
+                audio_data += huff_offset as i64;
                 audio_data <<= quantiser_step_size;
 
                 if position_checks_needed {
                     let huff_size = reader.position()? - huff_start_pos;
 
                     if audio_data >= 1 << 23 {
+                        state.stats.record_huffman_saturation(state.substream_index);
                         bail!(BlockError::HuffmanPositiveSaturation);
                     } else if audio_data < -(1 << 23) {
+                        state.stats.record_huffman_saturation(state.substream_index);
                         bail!(BlockError::HuffmanNegativeSaturation);
                     }
 
@@ -468,7 +559,7 @@ impl Block {
                     }
                 }
 
-                channel_data[chi] = audio_data;
+                channel_data[chi] = audio_data as i32;
             }
 
             block_data[min_chan..(max_chan + 1)]
@@ -487,15 +578,139 @@ impl Block {
 
         if error_protect {
             b.block_header_crc = reader.get_n(8)?;
-            info!(
-                "Block header CRC found: {:#02X} (error protection enabled)",
-                b.block_header_crc
-            );
+
+            let crc = reader.crc8_check(&state.crc_restart_block_header, start_pos, header_len)?;
+            if crc != b.block_header_crc {
+                log_or_err!(
+                    state,
+                    Category::Crc,
+                    log::Level::Error,
+                    anyhow!(BlockError::BlockHeaderCrcMismatch {
+                        au: state.au_counter,
+                        substream: state.substream_index,
+                        calculated: crc,
+                        read: b.block_header_crc,
+                    })
+                );
+            }
         }
 
         Ok(b)
     }
 
+    /// Writes the block, coding `block_data` with the Huffman parameters in
+    /// effect for the substream.
+    pub fn write(&self, state: &mut ParserState, writer: &mut BitstreamWriter) -> Result<()> {
+        let start_pos = writer.position();
+        writer.put(self.block_header.is_some());
+
+        if let Some(block_header) = &self.block_header {
+            writer.put(self.restart_header.is_some());
+            if let Some(restart_header) = &self.restart_header {
+                restart_header.write(state, writer)?;
+            }
+
+            block_header.write(state, writer)?;
+        }
+
+        let header_len = writer.position() - start_pos;
+
+        let ParserSubstreamState {
+            restart_sync_word,
+            min_chan,
+            max_chan,
+            block_size,
+            error_protect,
+
+            primitive_matrices,
+
+            huff_offset,
+            huff_lsbs,
+            huff_type,
+            lsb_bypass_used,
+            lsb_bypass_bit_count,
+            quantiser_step_size,
+            ..
+        } = *state.substream_state()?;
+
+        let block_data_bits_pos = writer.position();
+        if error_protect {
+            writer.put_n(0u16, 16);
+        }
+
+        let block_data_start_pos = writer.position();
+
+        for blki in 0..block_size {
+            for pmi in 0..primitive_matrices {
+                let bypassed_lsb = self.bypassed_lsb[blki][pmi] as u8;
+
+                if restart_sync_word == 0x31EC {
+                    writer.put_n(bypassed_lsb, lsb_bypass_bit_count[pmi] as u32);
+                } else if lsb_bypass_used[pmi] {
+                    writer.put_n(bypassed_lsb, 1);
+                }
+            }
+
+            for chi in min_chan..=max_chan {
+                let huff_type = huff_type[chi];
+                let quantiser_step_size = quantiser_step_size[chi];
+                if quantiser_step_size > huff_lsbs[chi] {
+                    bail!(BlockError::QuantiserStepTooLarge);
+                }
+
+                let lsbs_bits = huff_lsbs[chi] - quantiser_step_size;
+                let value = self.block_data[blki][chi];
+                let residual = (value as i64 >> quantiser_step_size) - huff_offset[chi] as i64;
+
+                if huff_type != 0 {
+                    let shift = lsbs_bits as i32 + (2 - huff_type as i32);
+                    let residual = residual + if shift < 0 { 0 } else { 1 << shift };
+                    let huff_code = residual >> lsbs_bits;
+
+                    writer
+                        .put_huffman(huff_type, huff_code as i32)
+                        .map_err(|_| BlockError::UncodableSample {
+                            channel: chi,
+                            value,
+                            huff_type,
+                        })?;
+                    writer.put_n(residual as u64, lsbs_bits);
+                } else {
+                    let lsbs = residual
+                        + if lsbs_bits > 0 {
+                            1 << (lsbs_bits - 1)
+                        } else {
+                            0
+                        };
+
+                    if lsbs >> lsbs_bits != 0 {
+                        bail!(BlockError::UncodableSample {
+                            channel: chi,
+                            value,
+                            huff_type,
+                        });
+                    }
+
+                    writer.put_n(lsbs as u64, lsbs_bits);
+                }
+            }
+        }
+
+        if error_protect {
+            let block_data_bits = writer.position() - block_data_start_pos;
+            if block_data_bits > 16000 {
+                bail!(BlockError::BlockDataBitsTooLarge(block_data_bits as u16));
+            }
+
+            writer.put_n_at(block_data_bits_pos, block_data_bits, 16);
+
+            let crc = writer.crc8(&state.crc_restart_block_header, start_pos, header_len)?;
+            writer.put_n(crc, 8);
+        }
+
+        Ok(())
+    }
+
     pub fn update_decoder_state(&self, state: &mut DecoderState) -> Result<()> {
         if let Some(restart_header) = &self.restart_header {
             restart_header.update_decoder_state(state)?;
@@ -517,3 +732,118 @@ impl Block {
         Ok(())
     }
 }
+
+#[test]
+fn wide_lsbs_decode_without_truncation() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::{Extractor, Frame};
+    use crate::process::parse::Parser;
+
+    let frame = {
+        let mut extractor = Extractor::default();
+        extractor.push_bytes(EXAMPLE_DATA);
+        extractor.filter_map(Result::ok).next().unwrap()
+    };
+
+    // Channel 0 of the first block gets 24 LSBs after its Huffman code and
+    // eight bypassed LSBs per sample, at least 33 bits in total
+    let mut access_unit = Parser::default().parse(&frame)?;
+    let block = &mut access_unit.substream_segment[0].block[0];
+    block.restart_header.as_mut().unwrap().max_lsbs = 24;
+
+    let block_header = block.block_header.as_mut().unwrap();
+    let matrixing = block_header.matrixing.as_mut().unwrap();
+    matrixing.primitive_matrices = 8;
+    matrixing.matrices[0].lsb_bypass_used = true;
+    for pmi in 1..8 {
+        matrixing.matrices[pmi] = matrixing.matrices[0];
+    }
+    block_header.quantiser_step_size[0] = Some(0);
+    let channel_params = block_header.channel_params[0].as_mut().unwrap();
+    channel_params.huff_type = 1;
+    channel_params.huff_lsbs = 24;
+    channel_params.huff_offset = Some(0);
+
+    // Samples spanning the 24-bit range in both directions
+    let reference = |blki: usize| ((blki as i64 * 0x29_A1F3) % (1 << 24) - (1 << 23)) as i32;
+    let block_size = 8;
+    for blki in 0..block_size {
+        block.block_data[blki][0] = reference(blki);
+        for pmi in 0..8 {
+            block.bypassed_lsb[blki][pmi] = ((blki + pmi) & 1) as i32;
+        }
+    }
+
+    let mut writer = BitstreamWriter::new();
+    let data = access_unit.write(&mut writer)?.to_vec();
+    let access_unit = Parser::default().parse(&Frame {
+        data: data.into(),
+        ..frame
+    })?;
+
+    let block = &access_unit.substream_segment[0].block[0];
+    for blki in 0..block_size {
+        assert_eq!(block.block_data[blki][0], reference(blki));
+        for pmi in 0..8 {
+            assert_eq!(block.bypassed_lsb[blki][pmi], ((blki + pmi) & 1) as i32);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn block_header_crc_mismatch_detected() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::extract::{Extractor, Frame};
+    use crate::process::parse::Parser;
+
+    let frame = {
+        let mut extractor = Extractor::default();
+        extractor.push_bytes(EXAMPLE_DATA);
+        extractor.filter_map(Result::ok).next().unwrap()
+    };
+
+    // Write the first substream with error protection and the Huffman offset
+    // of channel 0 in its first block header set to `huff_offset`
+    let write = |huff_offset: i32| -> Result<Vec<u8>> {
+        let mut access_unit = Parser::default().parse(&frame)?;
+        let block = &mut access_unit.substream_segment[0].block[0];
+        block.restart_header.as_mut().unwrap().error_protect = true;
+        let block_header = block.block_header.as_mut().unwrap();
+        block_header.quantiser_step_size[0] = Some(0);
+        block_header.channel_params[0].as_mut().unwrap().huff_offset = Some(huff_offset);
+        Ok(access_unit.write(&mut BitstreamWriter::new())?.to_vec())
+    };
+    // The added bits delay the FIFO, which is only warned about
+    let parse = |data: &[u8]| {
+        let mut parser = Parser::default();
+        parser.set_fail_level(log::Level::Error);
+        parser.parse(&Frame {
+            data: data.to_vec().into(),
+            ..frame.clone()
+        })
+    };
+
+    let data = write(0)?;
+    let access_unit = parse(&data)?;
+    let block = &access_unit.substream_segment[0].block[0];
+    assert!(block.block_data_bits.is_some());
+
+    // The first bit differing from a stream with another offset lies in the
+    // protected block header, and flipping it keeps the block data length
+    let bit = data
+        .iter()
+        .zip(write(1)?)
+        .enumerate()
+        .find_map(|(i, (a, b))| (a != &b).then(|| i * 8 + (a ^ b).leading_zeros() as usize))
+        .unwrap();
+    let mut corrupt = data.clone();
+    corrupt[bit / 8] ^= 0x80 >> (bit % 8);
+
+    let err = parse(&corrupt).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(BlockError::BlockHeaderCrcMismatch { substream: 0, .. })
+    ));
+    Ok(())
+}
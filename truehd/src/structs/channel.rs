@@ -13,14 +13,15 @@ use crate::process::decode::DecoderState;
 use crate::process::parse::ParserState;
 use crate::structs::filter::{CoeffType, FilterCoeffs};
 use crate::structs::restart_header::GuardsField;
-use crate::utils::bitstream_io::BsIoSliceReader;
-use crate::utils::errors::ChannelError;
+use crate::utils::bitstream_io::{BitstreamWriter, BsIoSliceReader};
+use crate::utils::errors::{Category, ChannelError, SyncError};
 
 /// Extended channel meaning information for 16-channel presentations.
 ///
 /// Contains dialogue normalization, mix levels, channel counts, and object
 /// audio metadata for channel configurations.
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct ExtraChannelMeaning {
     pub extra_channel_meaning_length: u8,
     pub sixteench_dialogue_norm: u8,
@@ -83,16 +84,70 @@ impl ExtraChannelMeaning {
                 }
             }
 
-            let pos = reader.position()?;
+            let Some(padding) = end_pos.checked_sub(reader.position()?) else {
+                bail!(ChannelError::ExtraChannelMeaningTooShort(
+                    extra_channel_meaning_length
+                ));
+            };
 
-            reader.seek((end_pos - pos) as i64)?;
+            reader.seek(padding as i64)?;
         }
 
         Ok(ecm)
     }
+
+    fn write(&self, state: &mut ParserState, writer: &mut BitstreamWriter) -> Result<()> {
+        let end_pos = writer.position() + ((self.extra_channel_meaning_length as u64 + 1) << 4);
+
+        writer.put_n(self.extra_channel_meaning_length, 4);
+
+        if state.substream_info >> 7 != 0 {
+            writer.put_n(self.sixteench_dialogue_norm, 5);
+            writer.put_n(self.sixteench_mix_level, 6);
+            writer.put_n(self.sixteench_channel_count, 5);
+            writer.put(self.dyn_object_only);
+
+            if self.dyn_object_only {
+                writer.put(self.lfe_present);
+            } else {
+                writer.put_n(self.sixteench_content_description, 4);
+
+                if self.sixteench_content_description & 1 != 0 {
+                    writer.put(self.chan_distribute);
+                    writer.put(false);
+                    writer.put(self.lfe_only);
+
+                    if !self.lfe_only {
+                        writer.put(false);
+                        writer.put_n(self.sixteench_channel_assignment, 10);
+                    }
+                }
+
+                if self.sixteench_content_description & 2 != 0 {
+                    writer.put_n(self.sixteench_isf, 3);
+                }
+
+                if self.sixteench_content_description & 4 != 0 {
+                    writer.put_n(self.sixteench_dynamic_object_count, 5);
+                }
+            }
+
+            let Some(padding) = end_pos.checked_sub(writer.position()) else {
+                bail!(
+                    "extra_channel_meaning_length {} is too short",
+                    self.extra_channel_meaning_length
+                )
+            };
+
+            writer.put_n(0u8, padding as u32);
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct ChannelMeaning {
     pub heavy_drc_start_up_gain: i8,
     pub twoch_control_enabled: bool,
@@ -171,9 +226,40 @@ impl ChannelMeaning {
 
         Ok(cm)
     }
+
+    pub fn write(&self, state: &mut ParserState, writer: &mut BitstreamWriter) -> Result<()> {
+        writer.put_s(self.heavy_drc_start_up_gain, 6);
+        writer.put(self.twoch_control_enabled);
+        writer.put(self.sixch_control_enabled);
+        writer.put(self.eightch_control_enabled);
+        writer.put(self.reserved1);
+        writer.put_s(self.drc_start_up_gain, 7);
+        writer.put_n(self.twoch_dialogue_norm, 6);
+        writer.put_n(self.twoch_mix_level, 6);
+        writer.put_n(self.sixch_dialogue_norm, 5);
+        writer.put_n(self.sixch_mix_level, 6);
+        writer.put_n(self.sixch_source_format, 5);
+        writer.put_n(self.eightch_dialogue_norm, 5);
+        writer.put_n(self.eightch_mix_level, 6);
+        writer.put_n(self.eightch_source_format, 6);
+        writer.put(self.reserved2);
+        writer.put(self.extra_channel_meaning_present);
+
+        if self.extra_channel_meaning_present {
+            let Some(extra_channel_meaning) = &self.extra_channel_meaning else {
+                bail!("extra_channel_meaning_present is set without extra_channel_meaning")
+            };
+
+            extra_channel_meaning.write(state, writer)?;
+            writer.align_16bit();
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct ChannelParams {
     pub coeffs_a: Option<FilterCoeffs>,
     pub coeffs_b: Option<FilterCoeffs>,
@@ -217,6 +303,7 @@ impl ChannelParams {
             if coeffs_a.order + coeffs_b.order > 8 {
                 log_or_err!(
                     state,
+                    Category::Filter,
                     log::Level::Error,
                     anyhow!(ChannelError::FilterOrderTooHigh {
                         a: coeffs_a.order,
@@ -228,6 +315,7 @@ impl ChannelParams {
             if coeffs_b.order != 0 && coeffs_a.order != 0 && coeffs_b.coeff_q != coeffs_a.coeff_q {
                 log_or_err!(
                     state,
+                    Category::Filter,
                     Error,
                     anyhow!(ChannelError::CoeffQMismatch {
                         chan: chi,
@@ -265,6 +353,7 @@ impl ChannelParams {
         if cp.huff_lsbs > max_huff_lsbs {
             log_or_err!(
                 state,
+                Category::Huffman,
                 Error,
                 anyhow!(ChannelError::HuffLsbsTooLarge {
                     chan: chi,
@@ -277,6 +366,46 @@ impl ChannelParams {
         Ok(cp)
     }
 
+    pub fn write(
+        &self,
+        state: &mut ParserState,
+        writer: &mut BitstreamWriter,
+        chi: usize,
+    ) -> Result<()> {
+        let ss_state = state.substream_state_mut()?;
+        let guards = ss_state.guards;
+
+        if guards.need_change(GuardsField::CoeffsA) {
+            writer.put(self.coeffs_a.is_some());
+            if let Some(coeffs_a) = &self.coeffs_a {
+                coeffs_a.write(writer);
+            }
+        }
+
+        if guards.need_change(GuardsField::CoeffsB) {
+            writer.put(self.coeffs_b.is_some());
+            if let Some(coeffs_b) = &self.coeffs_b {
+                coeffs_b.write(writer);
+            }
+        }
+
+        if guards.need_change(GuardsField::HuffOffset) {
+            writer.put(self.huff_offset.is_some());
+            if let Some(huff_offset) = self.huff_offset {
+                writer.put_s(huff_offset, 15);
+                ss_state.huff_offset[chi] = huff_offset;
+            }
+        }
+
+        writer.put_n(self.huff_type as u8, 2);
+        writer.put_n(self.huff_lsbs, 5);
+
+        ss_state.huff_lsbs[chi] = self.huff_lsbs;
+        ss_state.huff_type[chi] = self.huff_type;
+
+        Ok(())
+    }
+
     pub fn update_decoder_state(&self, state: &mut DecoderState, chi: usize) -> Result<()> {
         if let Some(coeffs_a) = &self.coeffs_a {
             coeffs_a.update_decoder_state(state, CoeffType::A, chi)?;
@@ -297,6 +426,7 @@ impl ChannelParams {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChannelLabel {
     L,
     R,
@@ -322,9 +452,26 @@ pub enum ChannelLabel {
     Rw,
     Tfc,
     LFE2,
+    /// Dynamic object of presentation 3, numbered from 0 after the bed channels
+    Object(u8),
+    /// Channel of a reserved channel assignment, numbered from 0
+    Generic(u8),
+}
+
+impl Display for ChannelLabel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChannelLabel::Object(n) => write!(f, "Obj{n}"),
+            ChannelLabel::Generic(n) => write!(f, "Chan{n}"),
+            label => write!(f, "{label:?}"),
+        }
+    }
 }
 
 impl ChannelLabel {
+    /// Returns the channels of a 6ch presentation `channel_assignment`.
+    ///
+    /// Assignments without channels or with more than six are reserved.
     pub fn from_sixch_channel(sixch_channel_assignment: u8) -> Result<Vec<Self>> {
         let mut labels = Vec::new();
 
@@ -341,12 +488,29 @@ impl ChannelLabel {
             }
         }
 
+        if labels.is_empty() || labels.len() > 6 {
+            bail!(SyncError::InvalidSixchChannelAssignment(
+                sixch_channel_assignment
+            ));
+        }
+
         Ok(labels)
     }
 
+    /// Returns the channels of an 8ch presentation `channel_assignment`.
+    ///
+    /// Bit 11 of `flags` selects the alternative assignment, in which only
+    /// the lower five bits are defined. Assignments without channels, with
+    /// more than eight or with undefined bits set are reserved.
     pub fn from_eightch_channel(eightch_channel_assignment: u16, flags: u16) -> Result<Vec<Self>> {
         let mut labels = Vec::new();
 
+        if flags & 0x800 != 0 && eightch_channel_assignment >> 5 != 0 {
+            bail!(SyncError::InvalidEightchChannelAssignment(
+                eightch_channel_assignment
+            ));
+        }
+
         if flags & 0x800 != 0 {
             for i in 0..5 {
                 if eightch_channel_assignment >> i & 1 == 1 {
@@ -383,9 +547,46 @@ impl ChannelLabel {
             }
         }
 
+        if labels.is_empty() || labels.len() > 8 || eightch_channel_assignment >> 13 != 0 {
+            bail!(SyncError::InvalidEightchChannelAssignment(
+                eightch_channel_assignment
+            ));
+        }
+
         Ok(labels)
     }
 
+    /// Returns the channels of an FBB `channel_assignment` in coding order.
+    ///
+    /// `Cb` is used for the single surround channel.
+    pub fn from_fbb_channel(fbb_channel_assignment: u8) -> Result<Vec<Self>> {
+        use ChannelLabel::*;
+
+        let labels: &[Self] = match fbb_channel_assignment {
+            0 => &[C],
+            1 => &[L, R],
+            2 => &[L, R, Cb],
+            3 => &[L, R, Ls, Rs],
+            4 => &[L, R, LFE],
+            5 => &[L, R, LFE, Cb],
+            6 => &[L, R, LFE, Ls, Rs],
+            7 => &[L, R, C],
+            8 | 13 => &[L, R, C, Cb],
+            9 | 14 => &[L, R, C, Ls, Rs],
+            10 | 15 => &[L, R, C, LFE],
+            11 | 16 => &[L, R, C, LFE, Cb],
+            12 | 17 => &[L, R, C, LFE, Ls, Rs],
+            18 => &[L, R, Ls, Rs, LFE],
+            19 => &[L, R, Ls, Rs, C],
+            20 => &[L, R, Ls, Rs, C, LFE],
+            _ => bail!(SyncError::InvalidFbbChannelAssignment(
+                fbb_channel_assignment
+            )),
+        };
+
+        Ok(labels.to_vec())
+    }
+
     pub fn from_sixteenth_channel(sixteench_channel_assignment: u16) -> Result<Vec<Self>> {
         let mut labels = Vec::new();
 
@@ -412,6 +613,7 @@ impl ChannelLabel {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChannelGroup {
     Stereo,
     LtRt,
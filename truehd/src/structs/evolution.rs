@@ -5,16 +5,19 @@
 
 use anyhow::Result;
 
-use crate::utils::bitstream_io::BsIoSliceReader;
+use crate::utils::bitstream_io::{BitstreamWriter, BsIoSliceReader};
 
 /// Configuration for Evolution payload data
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct EvoPayloadConfig {
     /// actually variable_bits(11)
     pub smploffst: Option<u32>,
     pub duration: Option<u32>,
     pub groupid: Option<u32>,
     pub codedcdata: Option<u8>,
+    pub discard_unknown_payload: bool,
+    pub payload_frame_aligned: bool,
     pub create_duplicate: Option<bool>,
     pub remove_duplicate: Option<bool>,
     pub priority: Option<u8>,
@@ -41,20 +44,19 @@ impl EvoPayloadConfig {
             config.codedcdata = Some(reader.get_n(8)?);
         }
 
-        // discard_unknown_payload
-        if !reader.get()? {
-            let payload_frame_aligned = if config.smploffst.is_none() {
-                reader.get()?
-            } else {
-                false
-            };
+        config.discard_unknown_payload = reader.get()?;
 
-            if payload_frame_aligned {
+        if !config.discard_unknown_payload {
+            if config.smploffst.is_none() {
+                config.payload_frame_aligned = reader.get()?;
+            }
+
+            if config.payload_frame_aligned {
                 config.create_duplicate = Some(reader.get()?);
                 config.remove_duplicate = Some(reader.get()?);
             }
 
-            if config.smploffst.is_some() || payload_frame_aligned {
+            if config.smploffst.is_some() || config.payload_frame_aligned {
                 config.priority = Some(reader.get_n(5)?);
                 config.proc_allowed = Some(reader.get_n(2)?);
             }
@@ -62,23 +64,51 @@ impl EvoPayloadConfig {
 
         Ok(config)
     }
-    pub fn payload_frame_aligned(&self) -> Option<bool> {
-        if self.create_duplicate.is_none() && self.remove_duplicate.is_none() {
-            return None;
+
+    pub fn write(&self, writer: &mut BitstreamWriter) {
+        writer.put(self.smploffst.is_some());
+        if let Some(smploffst) = self.smploffst {
+            writer.put_variable_bits(smploffst, 11);
         }
 
-        Some(self.create_duplicate.is_some() || self.remove_duplicate.is_some())
-    }
+        writer.put(self.duration.is_some());
+        if let Some(duration) = self.duration {
+            writer.put_variable_bits(duration, 11);
+        }
+
+        writer.put(self.groupid.is_some());
+        if let Some(groupid) = self.groupid {
+            writer.put_variable_bits(groupid, 2);
+        }
+
+        writer.put(self.codedcdata.is_some());
+        if let Some(codedcdata) = self.codedcdata {
+            writer.put_n(codedcdata, 8);
+        }
+
+        writer.put(self.discard_unknown_payload);
 
-    pub fn discard_unknown_payload(&self) -> bool {
-        self.payload_frame_aligned().is_none()
-            && self.priority.is_none()
-            && self.proc_allowed.is_none()
+        if !self.discard_unknown_payload {
+            if self.smploffst.is_none() {
+                writer.put(self.payload_frame_aligned);
+            }
+
+            if self.payload_frame_aligned {
+                writer.put(self.create_duplicate.unwrap_or_default());
+                writer.put(self.remove_duplicate.unwrap_or_default());
+            }
+
+            if self.smploffst.is_some() || self.payload_frame_aligned {
+                writer.put_n(self.priority.unwrap_or_default(), 5);
+                writer.put_n(self.proc_allowed.unwrap_or_default(), 2);
+            }
+        }
     }
 }
 
 /// Evolution frame payload container
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct EvoPayload {
     pub evo_payload_id: u32,
     pub evo_payload_config: EvoPayloadConfig,
@@ -104,10 +134,26 @@ impl EvoPayload {
         }
         Ok(evo_payload)
     }
+
+    pub fn write(&self, writer: &mut BitstreamWriter) {
+        writer.put_n(self.evo_payload_id, 5);
+
+        if self.evo_payload_id == 0 {
+            return;
+        }
+
+        self.evo_payload_config.write(writer);
+
+        writer.put_variable_bits(self.evo_payload_byte.len() as u32, 8);
+        for &byte in &self.evo_payload_byte {
+            writer.put_n(byte, 8);
+        }
+    }
 }
 
 /// Evolution frame protection and integrity data
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct EvoProtection {
     pub protection_length_primary: u8,
     pub protection_length_secondary: u8,
@@ -135,10 +181,28 @@ impl EvoProtection {
 
         Ok(evo_protection)
     }
+
+    pub fn write(&self, writer: &mut BitstreamWriter) {
+        writer.put_n(self.protection_length_primary, 2);
+        writer.put_n(self.protection_length_secondary, 2);
+
+        for &byte in
+            &self.protection_bits_primary[..Self::SIZE[self.protection_length_primary as usize]]
+        {
+            writer.put_n(byte, 8);
+        }
+
+        for &byte in
+            &self.protection_bits_secondary[..Self::SIZE[self.protection_length_secondary as usize]]
+        {
+            writer.put_n(byte, 8);
+        }
+    }
 }
 
 /// Complete Evolution frame structure (EMDF without sync)
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct EvoFrame {
     pub evo_version: u32,
     pub key_id: u32,
@@ -174,4 +238,29 @@ impl EvoFrame {
 
         Ok(evo_frame)
     }
+
+    pub fn write(&self, writer: &mut BitstreamWriter) {
+        if self.evo_version < 3 {
+            writer.put_n(self.evo_version, 2);
+        } else {
+            writer.put_n(3u8, 2);
+            writer.put_variable_bits(self.evo_version - 3, 2);
+        }
+
+        if self.key_id < 7 {
+            writer.put_n(self.key_id, 3);
+        } else {
+            writer.put_n(7u8, 3);
+            writer.put_variable_bits(self.key_id - 7, 3);
+        }
+
+        for evo_payload in &self.evo_payloads {
+            evo_payload.write(writer);
+        }
+
+        // Payload ID 0 ends the payloads
+        writer.put_n(0u8, 5);
+
+        self.evo_protection.write(writer);
+    }
 }
@@ -3,17 +3,18 @@
 //! This module contains structures for handling extra data sections,
 //! which may contain Evolution frames and other auxiliary information.
 
-use anyhow::{Result, anyhow};
+use anyhow::{Result, anyhow, bail};
 use log::trace;
 
 use crate::log_or_err;
 use crate::process::parse::ParserState;
 use crate::structs::evolution::EvoFrame;
-use crate::utils::bitstream_io::BsIoSliceReader;
-use crate::utils::errors::ExtraDataError;
+use crate::utils::bitstream_io::{BitstreamWriter, BsIoSliceReader};
+use crate::utils::errors::{Category, ExtraDataError};
 
 /// Extra data container for auxiliary information
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct ExtraData {
     pub header_check_nibble: u8,
     pub extra_data_length: u16,
@@ -29,6 +30,7 @@ impl ExtraData {
         if reader.position()? & 0x7 != 0 {
             log_or_err!(
                 state,
+                Category::Metadata,
                 log::Level::Warn,
                 anyhow!(ExtraDataError::MisalignedExtraDataStart)
             );
@@ -46,6 +48,7 @@ impl ExtraData {
                 if reader.get_n::<u16>(16)? != 0 {
                     log_or_err!(
                         state,
+                        Category::Metadata,
                         log::Level::Warn,
                         anyhow!(ExtraDataError::PaddingNotZero)
                     );
@@ -67,6 +70,7 @@ impl ExtraData {
         if parity != 0xF {
             log_or_err!(
                 state,
+                Category::Metadata,
                 log::Level::Warn,
                 anyhow!(ExtraDataError::LengthParityFailed(parity))
             );
@@ -80,6 +84,7 @@ impl ExtraData {
         if extra_data_bits > expected_remaining_bits {
             log_or_err!(
                 state,
+                Category::Metadata,
                 log::Level::Warn,
                 anyhow!(ExtraDataError::ExtraDataTooLong {
                     length: extra_data.extra_data_length,
@@ -95,6 +100,7 @@ impl ExtraData {
             if ((extra_data.evo_frame_byte_length as usize) << 3) + 24 > extra_data_bits {
                 log_or_err!(
                     state,
+                    Category::Metadata,
                     log::Level::Warn,
                     anyhow!(ExtraDataError::EvoFrameTooLong {
                         evo_len: extra_data.evo_frame_byte_length,
@@ -106,6 +112,7 @@ impl ExtraData {
             if reader.position()? & 0x7 != 0 {
                 log_or_err!(
                     state,
+                    Category::Metadata,
                     log::Level::Warn,
                     anyhow!(ExtraDataError::EvoFrameMisaligned)
                 );
@@ -119,6 +126,7 @@ impl ExtraData {
                 if reader.get()? {
                     log_or_err!(
                         state,
+                        Category::Metadata,
                         log::Level::Warn,
                         anyhow!(ExtraDataError::EvoFramePaddingNotZero)
                     );
@@ -136,6 +144,7 @@ impl ExtraData {
         if parity != extra_data.extra_data_parity {
             log_or_err!(
                 state,
+                Category::Metadata,
                 log::Level::Warn,
                 anyhow!(ExtraDataError::ExtraDataParityMismatch {
                     expected: parity,
@@ -146,4 +155,64 @@ impl ExtraData {
 
         Ok(extra_data)
     }
+    pub fn write(&self, state: &mut ParserState, writer: &mut BitstreamWriter) -> Result<()> {
+        // Padding only
+        if self.header_check_nibble == 0 && self.extra_data_length == 0 {
+            writer.put_n(0u8, 16);
+            writer.put_n(0u8, self.ectra_data_padding as u32);
+            return Ok(());
+        }
+
+        let Some(evo_frame) = &self.evo_frame else {
+            bail!(ExtraDataError::MissingEvoFrame)
+        };
+
+        if state.flags & 0x1000 == 0 {
+            bail!(ExtraDataError::MissingEvoFrame)
+        }
+
+        let header_pos = writer.position();
+        writer.put_n(0u16, 16);
+
+        let start_pos = writer.position();
+        writer.put_n(self.evo_frame_reserved, 4);
+        writer.put_n(0u16, 12);
+
+        let evo_frame_pos = writer.position();
+        evo_frame.write(writer);
+        let evo_frame_bits = writer.position() - evo_frame_pos;
+
+        // Keep the lengths read unless the frame no longer fits
+        let evo_frame_byte_length = if (self.evo_frame_byte_length as u64) << 3 >= evo_frame_bits {
+            self.evo_frame_byte_length
+        } else {
+            evo_frame_bits.div_ceil(8) as u16
+        };
+        let extra_data_length = self
+            .extra_data_length
+            .max((24 + evo_frame_bits).div_ceil(16) as u16);
+
+        if extra_data_length > 0xFFF {
+            bail!(ExtraDataError::EvoFrameTooLong {
+                evo_len: evo_frame_byte_length,
+                extra_len: extra_data_length
+            });
+        }
+
+        let parity_pos = start_pos + ((extra_data_length as u64) << 4) - 8;
+        writer.put_n(0u8, (parity_pos - writer.position()) as u32);
+
+        let parity = writer.parity(start_pos, parity_pos - start_pos) ^ 0xA9;
+        writer.put_n(parity, 8);
+
+        writer.put_n_at(evo_frame_pos - 12, evo_frame_byte_length, 12);
+
+        let check_nibble = (0..3).fold(0xF, |nibble, i| {
+            nibble ^ (extra_data_length >> (i << 2)) & 0xF
+        });
+        writer.put_n_at(header_pos, check_nibble, 4);
+        writer.put_n_at(header_pos + 4, extra_data_length, 12);
+
+        Ok(())
+    }
 }
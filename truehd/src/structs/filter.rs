@@ -16,7 +16,7 @@
 use anyhow::{Result, bail};
 
 use crate::process::decode::DecoderState;
-use crate::utils::bitstream_io::BsIoSliceReader;
+use crate::utils::bitstream_io::{BitstreamWriter, BsIoSliceReader};
 use crate::utils::errors::FilterError;
 
 /// FIR filter coefficients for one channel.
@@ -24,6 +24,7 @@ use crate::utils::errors::FilterError;
 /// Contains parameters for finite impulse response filter used for temporal prediction.
 /// Includes filter coefficients, quantization parameters, and filter state.
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct FilterCoeffs {
     pub order: u8,
 
@@ -137,6 +138,37 @@ impl FilterCoeffs {
         Ok(fc)
     }
 
+    pub fn write(&self, writer: &mut BitstreamWriter) {
+        writer.put_n(self.order, 4);
+
+        if self.order == 0 {
+            return;
+        }
+
+        writer.put_n(self.coeff_q, 4);
+        writer.put_n(self.coeff_bits, 5);
+        writer.put_n(self.coeff_shift, 3);
+
+        if self.coeff_bits != 0 {
+            for &coeff in &self.coeff[..self.order as usize] {
+                writer.put_s(coeff >> self.coeff_shift, self.coeff_bits as u32);
+            }
+        }
+
+        writer.put(self.new_states);
+
+        if self.new_states {
+            writer.put_n(self.state_bits, 4);
+            writer.put_n(self.state_shift, 4);
+
+            if self.state_bits != 0 {
+                for &state in &self.state[..self.order as usize] {
+                    writer.put_s(state >> self.state_shift, self.state_bits as u32);
+                }
+            }
+        }
+    }
+
     pub fn update_decoder_state(
         &self,
         state: &mut DecoderState,
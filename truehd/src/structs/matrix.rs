@@ -19,14 +19,15 @@ use crate::log_or_err;
 use crate::process::decode::DecoderState;
 use crate::process::parse::ParserState;
 use crate::structs::sync::BASE_SAMPLING_RATE_CD;
-use crate::utils::bitstream_io::BsIoSliceReader;
-use crate::utils::errors::MatrixError;
+use crate::utils::bitstream_io::{BitstreamWriter, BsIoSliceReader};
+use crate::utils::errors::{Category, MatrixError};
 
 /// Matrix primitive for single-channel decorrelation.
 ///
 /// Applies linear combinations of other channels to one target channel
 /// with configurable precision and coefficient updates.
 #[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Matrices {
     pub matrix_ch: u8,
     pub frac_bits: u8,
@@ -49,6 +50,7 @@ pub struct Matrices {
 ///
 /// Contains matrix primitives and control parameters for multi-channel decorrelation.
 #[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Matrixing {
     pub primitive_matrices: usize,
 
@@ -212,6 +214,7 @@ impl Matrixing {
             if matrices.matrix_ch as usize > max_matrix_chan {
                 log_or_err!(
                     state,
+                    Category::Matrixing,
                     Warn,
                     anyhow!(MatrixError::MatrixChannelTooHigh {
                         index: pmi,
@@ -222,6 +225,7 @@ impl Matrixing {
             } else if matrices.frac_bits > 14 {
                 log_or_err!(
                     state,
+                    Category::Matrixing,
                     Warn,
                     anyhow!(MatrixError::FracBitsTooHigh(matrices.frac_bits))
                 );
@@ -232,6 +236,7 @@ impl Matrixing {
             {
                 log_or_err!(
                     state,
+                    Category::Matrixing,
                     Warn,
                     anyhow!(MatrixError::InvalidLsbBypass {
                         info: this_substream_info
@@ -243,6 +248,121 @@ impl Matrixing {
         Ok(matrixing)
     }
 
+    pub fn write(&self, state: &mut ParserState, writer: &mut BitstreamWriter) -> Result<()> {
+        let ss_state = state.substream_state_mut()?;
+
+        let restart_sync_word = ss_state.restart_sync_word;
+        let max_matrix_chan = ss_state.max_matrix_chan;
+
+        if restart_sync_word == 0x31EC {
+            writer.put(self.new_matrix);
+
+            if self.new_matrix {
+                writer.put(self.new_matrix_config);
+
+                if self.new_matrix_config {
+                    writer.put_n(self.primitive_matrices.saturating_sub(1) as u8, 4);
+                    ss_state.primitive_matrices = self.primitive_matrices;
+
+                    for (pmi, matrices) in
+                        self.matrices[0..self.primitive_matrices].iter().enumerate()
+                    {
+                        writer.put_n(matrices.matrix_ch, 4);
+                        writer.put_n(matrices.frac_bits, 4);
+                        writer.put_n((matrices.cf_shift_code + 1) as u8, 3);
+                        writer.put_n(matrices.lsb_bypass_bit_count, 2);
+                        writer.put_n(matrices.dither_scale, 4);
+                        writer.put_n(matrices.cf_mask, max_matrix_chan as u32 + 1);
+
+                        ss_state.matrix_ch[pmi] = matrices.matrix_ch;
+                        ss_state.frac_bits[pmi] = matrices.frac_bits;
+                        ss_state.lsb_bypass_bit_count[pmi] = matrices.lsb_bypass_bit_count;
+                        ss_state.cf_mask[pmi] = matrices.cf_mask;
+                    }
+                }
+
+                let primitive_matrices = ss_state.primitive_matrices;
+
+                for (pmi, matrices) in self.matrices[0..primitive_matrices].iter().enumerate() {
+                    let frac_bits = ss_state.frac_bits[pmi] as u32;
+                    let cf_mask = ss_state.cf_mask[pmi];
+
+                    for chi in 0..=max_matrix_chan {
+                        if (cf_mask >> chi) & 1 != 0 {
+                            writer.put_s(matrices.m_coeff[chi], frac_bits + 2);
+                        }
+                    }
+                }
+            }
+
+            let primitive_matrices = ss_state.primitive_matrices;
+
+            writer.put(self.interpolation_used);
+
+            if self.interpolation_used {
+                writer.put(self.new_delta);
+
+                if self.new_delta {
+                    writer.put(self.new_delta_config);
+
+                    if self.new_delta_config {
+                        for (pmi, matrices) in
+                            self.matrices[0..primitive_matrices].iter().enumerate()
+                        {
+                            writer.put_n(matrices.delta_bits, 4);
+                            writer.put_n(matrices.delta_precision, 2);
+
+                            ss_state.delta_bits[pmi] = matrices.delta_bits;
+                        }
+                    }
+
+                    for (pmi, matrices) in self.matrices[0..primitive_matrices].iter().enumerate() {
+                        let cf_mask = ss_state.cf_mask[pmi];
+                        let delta_bits = ss_state.delta_bits[pmi] as u32;
+
+                        for chi in 0..=max_matrix_chan {
+                            if delta_bits != 0 && (cf_mask >> chi) & 1 != 0 {
+                                writer.put_s(matrices.delta_cf[chi], delta_bits + 1);
+                            }
+                        }
+                    }
+                }
+            }
+        } else {
+            writer.put_n(self.primitive_matrices as u8, 4);
+            ss_state.primitive_matrices = self.primitive_matrices;
+
+            for (pmi, matrices) in self.matrices[0..self.primitive_matrices].iter().enumerate() {
+                writer.put_n(matrices.matrix_ch, 4);
+                writer.put_n(matrices.frac_bits, 4);
+                writer.put(matrices.lsb_bypass_used);
+
+                ss_state.matrix_ch[pmi] = matrices.matrix_ch;
+                ss_state.frac_bits[pmi] = matrices.frac_bits;
+                ss_state.lsb_bypass_used[pmi] = matrices.lsb_bypass_used;
+
+                let coeff_bits = matrices.frac_bits as u32 + 2;
+
+                // A coefficient flagged but coded as zero is written as absent
+                for &m_coeff in &matrices.m_coeff
+                    [..=(max_matrix_chan + if restart_sync_word == 0x31EA { 2 } else { 0 })]
+                {
+                    writer.put(m_coeff != 0);
+
+                    if m_coeff != 0 {
+                        writer.put_s(m_coeff, coeff_bits);
+                    }
+                }
+
+                if restart_sync_word == 0x31EB {
+                    writer.put_n(matrices.dither_scale, 4);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn update_decoder_state(&self, state: &mut DecoderState) -> Result<()> {
         let ss_state = state.substream_state_mut()?;
 
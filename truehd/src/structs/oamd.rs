@@ -4,10 +4,10 @@
 //! which provides spatial audio information for immersive audio playback.
 
 use std::default::Default;
-use std::mem::transmute;
 
 use crate::utils::bitstream_io::BsIoSliceReader;
-use anyhow::Result;
+use crate::utils::errors::{OamdError, OamdWarning};
+use anyhow::{Result, bail};
 use log::{trace, warn};
 
 pub const MAX_OBJECT_COUNT: usize = 159;
@@ -26,7 +26,18 @@ struct OAMDParserState {
 
     object_element: Option<ObjectElement>,
     trim_element: Option<TrimElement>,
+    headphone_element: Option<HeadphoneElement>,
+    object_description_element: Option<ObjectDescriptionElement>,
     extended_object_element: Option<ExtendedObjectElement>,
+
+    warnings: Vec<OamdWarning>,
+}
+
+impl OAMDParserState {
+    fn push_warning(&mut self, warning: OamdWarning) {
+        warn!("{warning}");
+        self.warnings.push(warning);
+    }
 }
 
 impl Default for OAMDParserState {
@@ -40,7 +51,10 @@ impl Default for OAMDParserState {
             prev_object_render_info: ObjectRenderInfo::default(),
             object_element: None,
             trim_element: None,
+            headphone_element: None,
+            object_description_element: None,
             extended_object_element: None,
+            warnings: Vec::new(),
         }
     }
 }
@@ -182,7 +196,12 @@ impl ProgramAssignment {
             // intermediate spatial format (ISF)
             if content_description & 2 != 0 {
                 let intermediate_spatial_format_idx = reader.get_n::<u8>(3)?;
-                prog.num_isf_objects = ISF_COUNT_LIST[intermediate_spatial_format_idx as usize];
+                let Some(&num_isf_objects) =
+                    ISF_COUNT_LIST.get(intermediate_spatial_format_idx as usize)
+                else {
+                    bail!(OamdError::ReservedIsfIndex(intermediate_spatial_format_idx));
+                };
+                prog.num_isf_objects = num_isf_objects;
             }
 
             // object(s) with room-anchored or screen-anchored coordinates
@@ -221,8 +240,12 @@ pub struct ObjectAudioMetadataPayload {
 
     pub object_element: Option<ObjectElement>,
     pub trim_element: Option<TrimElement>,
+    pub headphone_element: Option<HeadphoneElement>,
+    pub object_description_element: Option<ObjectDescriptionElement>,
     pub extended_object_element: Option<ExtendedObjectElement>,
     pub oa_element_md: Vec<OAElementMD>,
+    /// Truncated or overrunning elements met while reading the payload
+    pub warnings: Vec<OamdWarning>,
 }
 
 impl ObjectAudioMetadataPayload {
@@ -236,7 +259,9 @@ impl ObjectAudioMetadataPayload {
             oamd_version += reader.get_n::<u8>(3)?;
         }
 
-        assert_eq!(oamd_version, 0, "Unsupported OAMD version {oamd_version}");
+        if oamd_version != 0 {
+            bail!(OamdError::UnsupportedVersion(oamd_version));
+        }
 
         let mut object_count_bits = reader.get_n::<u8>(5)?;
 
@@ -270,8 +295,11 @@ impl ObjectAudioMetadataPayload {
             b_alternate_object_data_present,
             object_element: state.object_element.clone(),
             trim_element: state.trim_element.clone(),
+            headphone_element: state.headphone_element.clone(),
+            object_description_element: state.object_description_element.clone(),
             extended_object_element: state.extended_object_element.clone(),
             oa_element_md,
+            warnings: std::mem::take(&mut state.warnings),
         };
 
         Ok(payload)
@@ -353,6 +381,11 @@ impl OAElementType {
 }
 
 impl OAElementMD {
+    /// Reads an element from its own reader bounded by `oa_element_size`.
+    ///
+    /// An element reader running past the end fails on the bounded reader
+    /// instead of consuming the following elements; the element is skipped
+    /// with an [`OamdWarning::ElementOverrun`].
     fn read(state: &mut OAMDParserState, reader: &mut BsIoSliceReader) -> Result<Self> {
         let mut md = OAElementMD {
             oa_element_id_idx: reader.get_n::<u8>(4)?,
@@ -362,22 +395,48 @@ impl OAElementMD {
         let oa_element_size_bits = reader.get_variable_bits_max(4, 4)? as u64;
         let oa_element_size = (oa_element_size_bits + 1) << 3;
 
-        let pos_start = reader.position()?;
-        let pos_end = if oa_element_size > reader.available()? {
-            // Does this happen?
-            warn!("Truncated oa_element_md with id {}", md.oa_element_id_idx);
-            reader.available()?
-        } else {
-            pos_start + oa_element_size
-        };
+        let available = reader.available()?;
+        if oa_element_size > available {
+            state.push_warning(OamdWarning::TruncatedElement {
+                id: md.oa_element_id_idx,
+                size: oa_element_size,
+                available,
+            });
+        }
+
+        let element_bytes = reader.get_bytes(oa_element_size.min(available))?;
+        let element_reader = &mut BsIoSliceReader::from_slice(&element_bytes);
+
+        if let Err(e) = md.read_element(state, element_reader) {
+            trace!("oa_element_md with id {}: {e}", md.oa_element_id_idx);
+            state.push_warning(OamdWarning::ElementOverrun {
+                id: md.oa_element_id_idx,
+                size: oa_element_size,
+            });
+        }
 
+        trace!(
+            "OAMD Element ID: {}, size: {}, actual_size: {}",
+            md.oa_element_id_idx,
+            oa_element_size,
+            element_reader.position()?
+        );
+
+        Ok(md)
+    }
+
+    fn read_element(
+        &mut self,
+        state: &mut OAMDParserState,
+        reader: &mut BsIoSliceReader,
+    ) -> Result<()> {
         if state.b_alternate_object_data_present {
-            md.alternate_object_data_id_idx = Some(reader.get_n::<u8>(4)?);
+            self.alternate_object_data_id_idx = Some(reader.get_n::<u8>(4)?);
         }
 
-        md.b_discard_unknown_element = reader.get()?;
+        self.b_discard_unknown_element = reader.get()?;
 
-        match OAElementType::from_u8(md.oa_element_id_idx) {
+        match OAElementType::from_u8(self.oa_element_id_idx) {
             OAElementType::Object => {
                 let object_element = ObjectElement::read(state, reader)?;
                 state.object_element = Some(object_element);
@@ -386,30 +445,28 @@ impl OAElementMD {
                 let trim_element = TrimElement::read(state, reader)?;
                 state.trim_element = Some(trim_element);
             }
+            OAElementType::Headphone => {
+                let headphone_element = HeadphoneElement::read(state, reader)?;
+                state.headphone_element = Some(headphone_element);
+            }
+            OAElementType::ObjectDescription => {
+                let object_description_element = ObjectDescriptionElement::read(state, reader)?;
+                state.object_description_element = Some(object_description_element);
+            }
             OAElementType::ExtendObject => {
                 let extended_object_element = ExtendedObjectElement::read(state, reader)?;
                 state.extended_object_element = Some(extended_object_element);
             }
             _ => {
                 warn!(
-                    "Unimplemented oa_element_md type {} with size={oa_element_size}, pos_end={pos_end}, please submit a sample",
-                    md.oa_element_id_idx
+                    "Unimplemented oa_element_md type {} with size={}, please submit a sample",
+                    self.oa_element_id_idx,
+                    reader.available()? + reader.position()?
                 )
             }
         }
 
-        // Padding
-        let pos_current = reader.position()?;
-        if pos_end > pos_current {
-            reader.skip_n((pos_end - pos_current) as u32)?;
-        }
-
-        trace!(
-            "OAMD Element ID: {}, size: {}, start: {}, expected_end: {}, actual_end: {}",
-            md.oa_element_id_idx, oa_element_size, pos_start, pos_end, pos_current
-        );
-
-        Ok(md)
+        Ok(())
     }
 }
 
@@ -491,8 +548,8 @@ impl MDUpdateInfo {
             },
             // sample_offset_bits
             2 => reader.get_n::<u8>(5)? as usize,
-            // 3 => // reserved
-            _ => unreachable!(),
+            // reserved
+            _ => bail!(OamdError::ReservedSampleOffsetCode),
         };
 
         let num_obj_info_blocks = (reader.get_n::<u8>(3)? + 1) as usize;
@@ -535,16 +592,16 @@ impl BlockUpdateInfo {
             0 => 0,
             1 => 512,
             2 => 1536,
-            3 => unsafe {
+            3 => {
                 // b_use_ramp_duration_idx
                 if reader.get()? {
                     // ramp_duration_idx
-                    *Self::RAMP_DURATION_LIST.get_unchecked(reader.get_n::<u64>(4)? as usize)
+                    Self::RAMP_DURATION_LIST[reader.get_n::<u8>(4)? as usize]
                 } else {
                     // ramp_duration_bits
                     reader.get_n(11)?
                 }
-            },
+            }
             _ => unreachable!(),
         };
 
@@ -1093,6 +1150,109 @@ impl ExtendedObjectElement {
     }
 }
 
+/// Headphone rendering of an object
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BinauralRenderMode {
+    Off = 0,
+    Near = 1,
+    Far = 2,
+    Middle = 3,
+}
+
+impl BinauralRenderMode {
+    pub fn from_u8(n: u8) -> Self {
+        match n & 3 {
+            0 => Self::Off,
+            1 => Self::Near,
+            2 => Self::Far,
+            _ => Self::Middle,
+        }
+    }
+
+    /// Name of the mode in the Dolby Atmos master metadata
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Near => "near",
+            Self::Far => "far",
+            Self::Middle => "middle",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+#[repr(C)]
+pub struct HeadphoneElement {
+    /// Binaural render mode per object, `None` if not signalled or the
+    /// object is a bed or ISF object
+    pub binaural_render_mode: Vec<Option<BinauralRenderMode>>,
+}
+
+impl HeadphoneElement {
+    fn read(state: &OAMDParserState, reader: &mut BsIoSliceReader) -> Result<Self> {
+        let beds_or_isf_count = state.program_assignment.beds_or_isf_count();
+        let mut element = Self {
+            binaural_render_mode: Vec::with_capacity(state.object_count),
+        };
+
+        for object_index in 0..state.object_count {
+            let mut render_mode = None;
+
+            // Bed and ISF objects are speaker-anchored and never rendered binaurally
+            if object_index >= beds_or_isf_count {
+                // b_headphone_render_mode_present
+                if reader.get()? {
+                    render_mode = Some(BinauralRenderMode::from_u8(reader.get_n::<u8>(2)?));
+                }
+            }
+
+            element.binaural_render_mode.push(render_mode);
+        }
+
+        Ok(element)
+    }
+}
+
+/// Content classification of an object
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct ObjectContentDescription {
+    pub b_dialog: bool,
+    pub b_music: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+#[repr(C)]
+pub struct ObjectDescriptionElement {
+    /// Content classification per object, `None` if not signalled
+    pub object_description: Vec<Option<ObjectContentDescription>>,
+}
+
+impl ObjectDescriptionElement {
+    fn read(state: &OAMDParserState, reader: &mut BsIoSliceReader) -> Result<Self> {
+        let mut element = Self {
+            object_description: Vec::with_capacity(state.object_count),
+        };
+
+        for _ in 0..state.object_count {
+            // b_content_description_present
+            let description = if reader.get()? {
+                Some(ObjectContentDescription {
+                    b_dialog: reader.get()?,
+                    b_music: reader.get()?,
+                })
+            } else {
+                None
+            };
+
+            element.object_description.push(description);
+        }
+
+        Ok(element)
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 #[repr(C)]
 pub struct ObjectDivergenceBlock {
@@ -1233,12 +1393,28 @@ impl SpeakerLabels {
         [ 1.0,      1.0,     -1.0],
     ];
 
+    const ALL: [Self; 17] = [
+        Self::L,
+        Self::R,
+        Self::C,
+        Self::LFE,
+        Self::Lss,
+        Self::Rss,
+        Self::Lrs,
+        Self::Rrs,
+        Self::Lfh,
+        Self::Rfh,
+        Self::Lts,
+        Self::Rts,
+        Self::Lrh,
+        Self::Rrh,
+        Self::Lw,
+        Self::Rw,
+        Self::LFE2,
+    ];
+
     pub fn from_u8(n: u8) -> Option<Self> {
-        if n <= SpeakerLabels::LFE2 as u8 {
-            Some(unsafe { transmute::<u8, Self>(n) })
-        } else {
-            None
-        }
+        Self::ALL.get(n as usize).copied()
     }
 
     pub fn pos(&self) -> &[f32; 3] {
@@ -1273,9 +1449,49 @@ pub const TEST_DATA_BROKEN: &[u8] = &[
 #[cfg(test)]
 mod tests {
     use crate::structs::oamd::{
-        ObjectAudioMetadataPayload, TEST_DATA, TEST_DATA_BROKEN, TEST_DATA_TRIM,
+        BinauralRenderMode, ObjectAudioMetadataPayload, ObjectContentDescription, TEST_DATA,
+        TEST_DATA_BROKEN, TEST_DATA_TRIM,
     };
+    use crate::utils::errors::OamdWarning;
     use anyhow::Result;
+    use bitstream_io::{BigEndian, BitWrite, BitWriter};
+
+    /// Payload of three objects, an LFE bed and two dynamic objects, with
+    /// the given elements as id and body bits
+    fn synthesize_payload(elements: &[(u8, &[u8])]) -> Result<Vec<u8>> {
+        let mut writer = BitWriter::endian(Vec::new(), BigEndian);
+
+        // oamd_version, object_count_bits
+        writer.write_var(2, 0u8)?;
+        writer.write_var(5, 2u8)?;
+        // b_dyn_object_only_program, b_lfe_present, b_alternate_object_data_present
+        writer.write_bit(true)?;
+        writer.write_bit(true)?;
+        writer.write_bit(false)?;
+        writer.write_var(4, elements.len() as u8)?;
+
+        for &(id, body) in elements {
+            // b_discard_unknown_element precedes the element bits
+            let bits = 1 + body.len();
+            let size = bits.div_ceil(8);
+
+            writer.write_var(4, id)?;
+            // oa_element_size_bits without further groups
+            writer.write_var(4, size as u8 - 1)?;
+            writer.write_bit(false)?;
+
+            writer.write_bit(false)?;
+            for &bit in body {
+                writer.write_bit(bit != 0)?;
+            }
+            for _ in bits..size * 8 {
+                writer.write_bit(false)?;
+            }
+        }
+        writer.byte_align()?;
+
+        Ok(writer.into_writer())
+    }
 
     #[test]
     fn test1() -> Result<()> {
@@ -1296,4 +1512,114 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn unsupported_version() {
+        use crate::utils::errors::OamdError;
+
+        // oamd_version 1, and 3 extended by 1
+        for (data, version) in [([0x40, 0x00], 1), ([0xC8, 0x00], 4)] {
+            let err = ObjectAudioMetadataPayload::read(&data).unwrap_err();
+            assert_eq!(
+                err.downcast_ref(),
+                Some(&OamdError::UnsupportedVersion(version))
+            );
+        }
+    }
+
+    #[test]
+    fn reserved_isf_index() {
+        use crate::utils::errors::OamdError;
+
+        // one object, content_description 2 with intermediate_spatial_format_idx 7
+        let err = ObjectAudioMetadataPayload::read(&[0x00, 0x2E]).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&OamdError::ReservedIsfIndex(7)));
+    }
+
+    #[test]
+    fn overrunning_element() -> Result<()> {
+        let expected = ObjectAudioMetadataPayload::read(TEST_DATA_TRIM)?;
+
+        // num_obj_info_blocks of the object element raised from 1 to 8
+        let mut data = TEST_DATA_TRIM.to_vec();
+        data[3] |= 0x01;
+        data[4] |= 0xC0;
+
+        let oamd = ObjectAudioMetadataPayload::read(&data)?;
+        assert_eq!(
+            oamd.warnings,
+            [OamdWarning::ElementOverrun { id: 1, size: 488 }]
+        );
+        assert!(oamd.object_element.is_none());
+        assert_eq!(oamd.oa_element_md.len(), 2);
+
+        let trim = oamd.trim_element.expect("trim element after the overrun");
+        let expected_trim = expected.trim_element.unwrap();
+        assert_eq!(trim.warp_mode, expected_trim.warp_mode);
+        assert_eq!(trim.global_trim_mode, expected_trim.global_trim_mode);
+        assert_eq!(
+            trim.b_disable_trim_per_obj,
+            expected_trim.b_disable_trim_per_obj
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_element() -> Result<()> {
+        let oamd = ObjectAudioMetadataPayload::read(&TEST_DATA_TRIM[..74])?;
+
+        assert_eq!(
+            oamd.warnings.first(),
+            Some(&OamdWarning::TruncatedElement {
+                id: 2,
+                size: 96,
+                available: 67
+            })
+        );
+        assert!(oamd.object_element.is_some());
+        assert_eq!(oamd.oa_element_md.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn headphone_and_object_description() -> Result<()> {
+        let payload = synthesize_payload(&[
+            // Object 1 near, object 2 not signalled
+            (3, &[1, 0, 1, 0]),
+            // Object 0 dialog, object 1 not signalled, object 2 music
+            (4, &[1, 1, 0, 0, 1, 0, 1]),
+        ])?;
+
+        let oamd = ObjectAudioMetadataPayload::read(&payload)?;
+        assert!(oamd.warnings.is_empty());
+        assert_eq!(oamd.object_count, 3);
+
+        let headphone = oamd.headphone_element.expect("headphone element");
+        assert_eq!(
+            headphone.binaural_render_mode,
+            [None, Some(BinauralRenderMode::Near), None]
+        );
+
+        let description = oamd
+            .object_description_element
+            .expect("object description element");
+        assert_eq!(
+            description.object_description,
+            [
+                Some(ObjectContentDescription {
+                    b_dialog: true,
+                    b_music: false
+                }),
+                None,
+                Some(ObjectContentDescription {
+                    b_dialog: false,
+                    b_music: true
+                }),
+            ]
+        );
+
+        Ok(())
+    }
 }
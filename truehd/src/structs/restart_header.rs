@@ -15,12 +15,11 @@
 
 use crate::log_or_err;
 use crate::process::decode::DecoderState;
-use crate::process::parse::ParserState;
-use crate::structs::sync::{
-    BASE_SAMPLING_RATE_CD, MAJOR_SYNC_FBA, MAJOR_SYNC_FBB, UNIMPLEMENTED_FBB_MSG,
-};
-use crate::utils::bitstream_io::BsIoSliceReader;
-use crate::utils::errors::RestartHeaderError;
+use crate::process::parse::{ParserState, TimingBranch, TimingBranchKind};
+use crate::structs::sync::{BASE_SAMPLING_RATE_CD, MAJOR_SYNC_FBA, MAJOR_SYNC_FBB};
+use crate::utils::bitstream_io::{BitstreamWriter, BsIoSliceReader};
+use crate::utils::errors::{Category, RestartHeaderError};
+use crate::utils::events::Event;
 use anyhow::{Result, anyhow, bail};
 use log::Level::Warn;
 use log::{info, trace, warn};
@@ -30,6 +29,7 @@ use log::{info, trace, warn};
 /// 16-bit restart sync word at the beginning of restart headers
 /// determining substream type and block organization.
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[repr(u16)]
 pub enum RestartSyncWord {
     #[default]
@@ -42,17 +42,19 @@ pub enum RestartSyncWord {
 impl RestartSyncWord {
     pub fn read(reader: &mut BsIoSliceReader) -> Result<Self> {
         let value = reader.get_n::<u16>(14)?;
-        Ok(RestartSyncWord::from(value))
+        Ok(RestartSyncWord::try_from(value)?)
     }
 }
 
-impl From<u16> for RestartSyncWord {
-    fn from(value: u16) -> Self {
+impl TryFrom<u16> for RestartSyncWord {
+    type Error = RestartHeaderError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
         match value {
-            0x31EA => RestartSyncWord::A,
-            0x31EB => RestartSyncWord::B,
-            0x31EC => RestartSyncWord::C,
-            _ => panic!("restart_sync_word must be 0x31EA, 0x31EB or 0x31EC. Read {value:#04X}"),
+            0x31EA => Ok(RestartSyncWord::A),
+            0x31EB => Ok(RestartSyncWord::B),
+            0x31EC => Ok(RestartSyncWord::C),
+            _ => Err(RestartHeaderError::InvalidRestartSyncWord(value)),
         }
     }
 }
@@ -72,6 +74,7 @@ impl From<RestartSyncWord> for u16 {
 /// Provides decoder state initialization at sync points.
 /// Protected by 8-bit CRC.
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct RestartHeader {
     pub restart_sync_word: RestartSyncWord,
     pub output_timing: u16,
@@ -124,6 +127,7 @@ impl RestartHeader {
             {
                 log_or_err!(
                     state,
+                    Category::Timing,
                     Warn,
                     anyhow!(RestartHeaderError::OutputTimingMismatch {
                         read: rh.output_timing,
@@ -162,6 +166,23 @@ impl RestartHeader {
                     .wrapping_sub(state.input_timing)
                     & 0xFFFF;
 
+                // The output timing history is lost after a parse error
+                if state.resyncing {
+                    if state.is_major_sync {
+                        state.reset_for_stream_restart();
+                        // Continue the history from the output timing read
+                        state.substream_state_mut()?.output_timing_history[history_index] = state
+                            .output_timing
+                            .wrapping_sub(state.output_timing_deviation)
+                            .wrapping_sub(samples_per_au);
+                        info!(
+                            "AU {}: Restarting timing after an access unit failed to parse",
+                            state.au_counter
+                        );
+                    }
+                    break 'check_output_timing;
+                }
+
                 let expected_output_timing = state
                     .output_timing_deviation
                     .wrapping_add(samples_per_au)
@@ -176,6 +197,7 @@ impl RestartHeader {
                     if state.has_valid_branch {
                         log_or_err!(
                             state,
+                            Category::Timing,
                             Warn,
                             anyhow!(RestartHeaderError::OutputTimingAfterJump {
                                 read: state.output_timing,
@@ -191,6 +213,7 @@ impl RestartHeader {
                 } else {
                     log_or_err!(
                         state,
+                        Category::Timing,
                         Warn,
                         anyhow!(RestartHeaderError::InvalidOutputTiming {
                             read: state.output_timing,
@@ -200,112 +223,24 @@ impl RestartHeader {
                 }
 
                 if state.has_jump() {
-                    let samples_per_au = state.samples_per_au;
-                    let prev_advance = state.prev_advance;
-                    let advance = state.advance;
-                    let prev_access_unit_length = state.prev_access_unit_length;
-                    let prev_fifo_duration = state.prev_fifo_duration;
-
-                    let input_timing_interval = samples_per_au
-                        .wrapping_add(prev_advance)
-                        .wrapping_sub(advance)
-                        & 0xFFFF;
-
-                    let data_rate = (state.audio_sampling_frequency_1 as usize
-                        * (prev_access_unit_length << 4))
-                        .div_ceil(input_timing_interval);
-
-                    if data_rate > state.max_data_rate {
-                        state.max_data_rate = data_rate;
-                        state.max_data_rate_au_index = state.au_counter - 1;
-                    }
-
-                    let samples_per_au_3q4 = 3 * (samples_per_au >> 2);
-                    let samples_per_75ms =
-                        (state.audio_sampling_frequency_1 as usize * 3).div_ceil(40);
-
-                    let c1 = advance <= prev_advance + samples_per_au_3q4;
-                    let c2 = advance <= prev_advance + samples_per_au - prev_fifo_duration;
-                    let c3 = advance <= samples_per_75ms - samples_per_au;
-                    let c4 = prev_access_unit_length << 8
-                        <= state.prev_peak_data_rate * input_timing_interval;
-
-                    if c1 && c2 && c3 && c4 {
-                        state.has_valid_branch = true;
-                        state.reset_for_branch();
-
-                        state.output_timing_deviation = state
-                            .output_timing
-                            .wrapping_sub(state.first_output_timing)
-                            .wrapping_sub(state.au_counter * samples_per_au)
-                            & 0xFFFF;
-
-                        info!(
-                            "AU {}: Valid seamless branch. Latency {} -> {}",
-                            state.au_counter,
-                            state.substream_state()?.prev_latency,
-                            state.output_timing.wrapping_sub(state.input_timing) & 0xFFFF,
-                        );
-
-                        break 'check_output_timing;
-                    }
-
-                    if !c1 {
-                        warn!(
-                            "AU {}: advance[n]>advance[n-1]+3*samples_per_au/4, \
-                            ({advance} > {prev_advance} + {})",
-                            state.au_counter,
-                            3 * (samples_per_au >> 2)
-                        );
-                    }
-
-                    if !c2 {
-                        warn!(
-                            "AU {}: advance[n]>advance[n-1]+samples_per_au-duration[n-1], \
-                            ({advance} > {prev_advance} + {samples_per_au} - {prev_fifo_duration})",
-                            state.au_counter
-                        );
-                    }
-
-                    if !c3 {
-                        warn!(
-                            "AU {}: advance[n]>samples_per_75ms-samples_per_au, \
-                            ({advance} > {samples_per_75ms} - {samples_per_au})",
-                            state.au_counter
-                        );
-                    }
-
-                    if !c4 {
-                        warn!(
-                            "AU {}: data_rate exceeds peak_data_rate after adjusting timing for jump",
-                            state.au_counter
-                        );
-                    }
-
-                    log_or_err!(
-                        state,
-                        Warn,
-                        anyhow!(RestartHeaderError::InvalidSeamlessBranch)
-                    );
+                    Self::check_seamless_branch(state, expected_output_timing)?;
                 }
             }
         }
 
         match rh.restart_sync_word {
-            RestartSyncWord::A => {
-                if state.substream_index == 1 && state.substream_info & 8 == 0 {
-                    bail!(RestartHeaderError::InvalidSyncBForSubstream1)
-                }
+            rsw if state.format_sync == MAJOR_SYNC_FBB && rsw != RestartSyncWord::A => {
+                bail!(RestartHeaderError::InvalidSyncFbb(rsw as u16))
             }
-            RestartSyncWord::B => {
-                if state.substream_index == 0 {
-                    bail!(RestartHeaderError::InvalidSyncBForSubstream0)
-                }
+            _ if state.format_sync == MAJOR_SYNC_FBB => {}
+            RestartSyncWord::A if state.substream_index == 1 && state.substream_info & 8 == 0 => {
+                bail!(RestartHeaderError::InvalidSyncBForSubstream1)
             }
-            rsw @ RestartSyncWord::C => {
-                if state.substream_index != 3 {
-                    bail!(RestartHeaderError::InvalidSyncC(rsw as u16))
-                }
+            RestartSyncWord::B if state.substream_index == 0 => {
+                bail!(RestartHeaderError::InvalidSyncBForSubstream0)
+            }
+            rsw @ RestartSyncWord::C if state.substream_index != 3 => {
+                bail!(RestartHeaderError::InvalidSyncC(rsw as u16))
             }
             _ => {}
         }
@@ -332,7 +267,6 @@ impl RestartHeader {
         reader.skip_n(2)?;
 
         if state.flags & 0x2000 != 0 {
-            //TODO: implement
             rh.heavy_drc_present = reader.get()?;
 
             if state.format_sync == MAJOR_SYNC_FBA {
@@ -352,17 +286,20 @@ impl RestartHeader {
             reader.skip_n(1)?;
         }
 
-        // prev
-        if state.substream_state_mut()?.heavy_drc_present {
+        if rh.heavy_drc_present {
             if state.format_sync == MAJOR_SYNC_FBB {
-                unimplemented!("{}", UNIMPLEMENTED_FBB_MSG)
+                // Heavy DRC is not defined for FBB streams
+                warn!("Ignoring heavy DRC parameters in FBB stream");
+                reader.skip_n(12)?;
             } else {
+                rh.heavy_drc_gain_update = reader.get_s(9)?;
+                rh.heavy_drc_time_update = reader.get_n(3)?;
+
                 let ss_state = state.substream_state_mut()?;
                 ss_state.heavy_drc_active = true;
                 ss_state.heavy_drc_count = 0;
-
-                rh.heavy_drc_gain_update = reader.get_s(9)?;
-                rh.heavy_drc_time_update = reader.get_n(3)?;
+                ss_state.heavy_drc_gain_update = rh.heavy_drc_gain_update;
+                ss_state.heavy_drc_time_update = rh.heavy_drc_time_update;
             }
         } else {
             reader.skip_n(12)?;
@@ -374,24 +311,21 @@ impl RestartHeader {
         for i in 0..=rh.max_matrix_chan as usize {
             let ch_assign = reader.get_n::<u8>(6)?;
 
-            if state.format_sync == MAJOR_SYNC_FBA {
-                if ch_assign > rh.max_matrix_chan {
-                    bail!(RestartHeaderError::ChannelAssignTooHigh {
-                        index: i,
-                        value: ch_assign,
-                        max: rh.max_matrix_chan
-                    })
-                } else if state.substream_index == 0
-                    && i != ch_assign as usize
-                    && state.audio_sampling_frequency_1 >= BASE_SAMPLING_RATE_CD << 2
-                {
-                    bail!(RestartHeaderError::ChannelAssignMisordered {
-                        index: i,
-                        value: ch_assign,
-                    })
-                }
-            } else {
-                unimplemented!("{}", UNIMPLEMENTED_FBB_MSG)
+            if ch_assign > rh.max_matrix_chan {
+                bail!(RestartHeaderError::ChannelAssignTooHigh {
+                    index: i,
+                    value: ch_assign,
+                    max: rh.max_matrix_chan
+                })
+            } else if state.format_sync == MAJOR_SYNC_FBA
+                && state.substream_index == 0
+                && i != ch_assign as usize
+                && state.audio_sampling_frequency_1 >= BASE_SAMPLING_RATE_CD << 2
+            {
+                bail!(RestartHeaderError::ChannelAssignMisordered {
+                    index: i,
+                    value: ch_assign,
+                })
             }
 
             let permutation_bit = 1 << ch_assign;
@@ -432,21 +366,255 @@ impl RestartHeader {
         ss_state.error_protect = rh.error_protect;
         ss_state.heavy_drc_present = rh.heavy_drc_present;
 
+        state.restart_headers[state.substream_index] = Some(rh.clone());
+
         Ok(rh)
     }
 
+    pub fn write(&self, state: &mut ParserState, writer: &mut BitstreamWriter) -> Result<()> {
+        let start_pos = writer.position();
+
+        writer.put_n(u16::from(self.restart_sync_word), 14);
+        writer.put_n(self.output_timing, 16);
+        writer.put_n(self.min_chan, 4);
+        writer.put_n(self.max_chan, 4);
+        writer.put_n(self.max_matrix_chan, 4);
+        writer.put_n(self.dither_shift, 4);
+        writer.put_n(self.dither_seed, 23);
+        writer.put_n(self.max_shift, 4);
+        writer.put_n(self.max_lsbs, 5);
+        writer.put_n(self.max_bits, 5);
+        writer.put_n(self.max_bits_repeat, 5);
+        writer.put(self.error_protect);
+        writer.put_n(self.lossless_check, 8);
+        writer.put(self.hires_output_timing);
+        writer.put_n(0u8, 2);
+
+        writer.put(state.flags & 0x2000 != 0 && self.heavy_drc_present);
+
+        if state.flags & 0x2000 != 0
+            && self.heavy_drc_present
+            && state.format_sync != MAJOR_SYNC_FBB
+        {
+            writer.put_s(self.heavy_drc_gain_update, 9);
+            writer.put_n(self.heavy_drc_time_update, 3);
+        } else {
+            writer.put_n(0u16, 12);
+        }
+
+        for &ch_assign in &self.ch_assign[..=self.max_matrix_chan as usize] {
+            writer.put_n(ch_assign as u8, 6);
+        }
+
+        let crc = writer.crc8(
+            &state.crc_restart_block_header,
+            start_pos,
+            writer.position() - start_pos,
+        )?;
+        writer.put_n(crc, 8);
+
+        state.reset_parser_substream_state();
+        let ss_state = state.substream_state_mut()?;
+
+        ss_state.restart_sync_word = self.restart_sync_word as u16;
+        ss_state.min_chan = self.min_chan as usize;
+        ss_state.max_chan = self.max_chan as usize;
+        ss_state.max_matrix_chan = self.max_matrix_chan as usize;
+        ss_state.max_shift = self.max_shift as i8;
+        ss_state.max_lsbs = self.max_lsbs as u32;
+        ss_state.error_protect = self.error_protect;
+        ss_state.heavy_drc_present = self.heavy_drc_present;
+
+        Ok(())
+    }
+
+    /// Validates a timing jump as a seamless branch (FBA 10.4).
+    ///
+    /// A zero input timing interval cannot come from a valid branch and is
+    /// typically the join of two concatenated streams. Timing and FIFO
+    /// tracking are restarted at this access unit instead.
+    ///
+    /// With branch repair enabled, an invalid branch is restarted the same
+    /// way and the distance between the read and the expected output timing
+    /// is recorded as the samples to insert or drop.
+    fn check_seamless_branch(state: &mut ParserState, expected_output_timing: usize) -> Result<()> {
+        let samples_per_au = state.samples_per_au;
+        let prev_advance = state.prev_advance;
+        let advance = state.advance;
+        let prev_access_unit_length = state.prev_access_unit_length;
+        let prev_fifo_duration = state.prev_fifo_duration;
+
+        let input_timing_interval = samples_per_au
+            .wrapping_add(prev_advance)
+            .wrapping_sub(advance)
+            & 0xFFFF;
+
+        if input_timing_interval == 0 {
+            log_or_err!(
+                state,
+                Category::Timing,
+                Warn,
+                anyhow!(RestartHeaderError::ZeroInputTimingInterval {
+                    advance,
+                    prev_advance
+                })
+            );
+
+            let (latency_before, latency_after) = Self::jump_latencies(state);
+            state.reset_for_stream_restart();
+
+            info!("AU {}: Restarting timing as a new stream", state.au_counter);
+            state.emit(Event::StreamRestart {
+                au: state.au_counter,
+            });
+            state.record_timing_branch(TimingBranch {
+                au: state.au_counter,
+                kind: TimingBranchKind::StreamRestart,
+                latency_before,
+                latency_after,
+            });
+
+            return Ok(());
+        }
+
+        let data_rate = (state.audio_sampling_frequency_1 as usize
+            * (prev_access_unit_length << 4))
+            .div_ceil(input_timing_interval);
+
+        if data_rate > state.max_data_rate {
+            state.max_data_rate = data_rate;
+            state.max_data_rate_au_index = state.au_counter - 1;
+        }
+
+        let samples_per_au_3q4 = 3 * (samples_per_au >> 2);
+        let samples_per_75ms = (state.audio_sampling_frequency_1 as usize * 3).div_ceil(40);
+
+        let c1 = advance <= prev_advance + samples_per_au_3q4;
+        let c2 = advance + prev_fifo_duration <= prev_advance + samples_per_au;
+        let c3 = advance + samples_per_au <= samples_per_75ms;
+        let c4 = prev_access_unit_length << 8 <= state.prev_peak_data_rate * input_timing_interval;
+
+        if c1 && c2 && c3 && c4 {
+            state.has_valid_branch = true;
+            state.reset_for_branch();
+
+            state.output_timing_deviation = state
+                .output_timing
+                .wrapping_sub(state.first_output_timing)
+                .wrapping_sub((state.au_counter - state.stream_start_au) * samples_per_au)
+                & 0xFFFF;
+
+            let (latency_before, latency_after) = Self::jump_latencies(state);
+            info!(
+                "AU {}: Valid seamless branch. Latency {latency_before} -> {latency_after}",
+                state.au_counter,
+            );
+            state.emit(Event::SeamlessBranch {
+                au: state.au_counter,
+                latency_before,
+                latency_after,
+            });
+            state.record_timing_branch(TimingBranch {
+                au: state.au_counter,
+                kind: TimingBranchKind::SeamlessBranch,
+                latency_before,
+                latency_after,
+            });
+
+            return Ok(());
+        }
+
+        if !c1 {
+            warn!(
+                "AU {}: advance[n]>advance[n-1]+3*samples_per_au/4, \
+                    ({advance} > {prev_advance} + {})",
+                state.au_counter,
+                3 * (samples_per_au >> 2)
+            );
+        }
+
+        if !c2 {
+            warn!(
+                "AU {}: advance[n]>advance[n-1]+samples_per_au-duration[n-1], \
+                    ({advance} > {prev_advance} + {samples_per_au} - {prev_fifo_duration})",
+                state.au_counter
+            );
+        }
+
+        if !c3 {
+            warn!(
+                "AU {}: advance[n]>samples_per_75ms-samples_per_au, \
+                    ({advance} > {samples_per_75ms} - {samples_per_au})",
+                state.au_counter
+            );
+        }
+
+        if !c4 {
+            warn!(
+                "AU {}: data_rate exceeds peak_data_rate after adjusting timing for jump",
+                state.au_counter
+            );
+        }
+
+        if state.repair_branch {
+            let gap = (state.output_timing.wrapping_sub(expected_output_timing) & 0xFFFF) as i16;
+            let (latency_before, latency_after) = Self::jump_latencies(state);
+            state.reset_for_stream_restart();
+            state.branch_repair = Some(gap as isize);
+
+            warn!(
+                "AU {}: Repairing invalid seamless branch as a new stream, {} {} samples",
+                state.au_counter,
+                if gap < 0 { "dropping" } else { "inserting" },
+                gap.unsigned_abs()
+            );
+            state.emit(Event::BranchRepaired {
+                au: state.au_counter,
+                samples: gap as isize,
+            });
+            state.record_timing_branch(TimingBranch {
+                au: state.au_counter,
+                kind: TimingBranchKind::BranchRepaired {
+                    samples: gap as isize,
+                },
+                latency_before,
+                latency_after,
+            });
+
+            return Ok(());
+        }
+
+        log_or_err!(
+            state,
+            Category::Timing,
+            Warn,
+            anyhow!(RestartHeaderError::InvalidSeamlessBranch)
+        );
+        Ok(())
+    }
+
+    /// Latency before the timing jump of the current access unit and the
+    /// latency the jump leads to
+    fn jump_latencies(state: &ParserState) -> (usize, usize) {
+        let latency_before = state.substream_state[state.substream_index].prev_latency;
+        let latency_after = state.output_timing.wrapping_sub(state.input_timing) & 0xFFFF;
+        (latency_before, latency_after)
+    }
+
     pub fn update_decoder_state(&self, state: &mut DecoderState) -> Result<()> {
         let valid = state.valid;
 
-        if valid && state.substream_index == state.presentation {
+        if valid && state.is_output_substream() {
             let substream_info = state.substream_info;
-            if match state.substream_index {
-                0 => true,
-                1 => substream_info & 8 != 0 || substream_info & 0x60 == 0x20,
-                2 => substream_info & 0x40 != 0,
-                3 => substream_info >> 7 != 0,
-                _ => bail!(RestartHeaderError::InvalidStream),
-            } {
+            if state.format_sync == MAJOR_SYNC_FBB
+                || match state.substream_index {
+                    0 => true,
+                    1 => substream_info & 8 != 0 || substream_info & 0x60 == 0x20,
+                    2 => substream_info & 0x40 != 0,
+                    3 => substream_info >> 7 != 0,
+                    _ => bail!(RestartHeaderError::InvalidStream),
+                }
+            {
                 let mut lossless_check_i32 = state.substream_state()?.lossless_check_i32_accum;
                 lossless_check_i32 ^= lossless_check_i32 >> 16;
                 lossless_check_i32 ^= lossless_check_i32 >> 8;
@@ -457,9 +625,17 @@ impl RestartHeader {
                         log::debug!(
                             "lossless_check failure is allowed on first access unit immediately after the jump"
                         )
+                    } else if state.lossless_check_unverified {
+                        log::debug!(
+                            "lossless_check is not verified for an interval decoded in skip mode"
+                        )
                     } else {
+                        state
+                            .stats
+                            .record_lossless_check_mismatch(state.substream_index);
                         log_or_err!(
                             state,
+                            Category::Lossless,
                             Warn,
                             anyhow!(RestartHeaderError::LosslessCheckMismatch {
                                 substream: state.substream_index,
@@ -470,13 +646,11 @@ impl RestartHeader {
                     }
                 }
             }
-        }
 
-        if valid
-            && !state.has_duplicate_timing
-            && state.substream_state()?.output_timing == self.output_timing
-        {
-            state.has_duplicate_timing = true;
+            // Lower presentations are checked before the decoded one
+            if !state.skip_decode && state.substream_index == state.presentation {
+                state.lossless_check_unverified = false;
+            }
         }
 
         state.reset_decoder_substream_state();
@@ -492,11 +666,22 @@ impl RestartHeader {
         ss_state.dither_seed = self.dither_seed;
         ss_state.ch_assign = self.ch_assign;
 
+        if self.heavy_drc_present && state.format_sync == MAJOR_SYNC_FBA {
+            let start_up_gain = state.heavy_drc_start_up_gain;
+            let ramp_length = state.samples_per_au << self.heavy_drc_time_update;
+            state.substream_state_mut()?.heavy_drc.update(
+                start_up_gain,
+                self.heavy_drc_gain_update as f32 / 32.0,
+                ramp_length,
+            );
+        }
+
         Ok(())
     }
 }
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Guards(u8);
 
 impl Default for Guards {
@@ -510,6 +695,10 @@ impl Guards {
         let guards = reader.get_n(8)?;
         Ok(Self(guards))
     }
+
+    pub fn write(&self, writer: &mut BitstreamWriter) {
+        writer.put_n(self.0, 8);
+    }
 }
 
 #[repr(u8)]
@@ -529,3 +718,171 @@ impl Guards {
         self.0 & (1 << field as u8) != 0
     }
 }
+
+#[test]
+fn zero_input_timing_interval_restarts_timing() -> Result<()> {
+    // Join of two streams: advance jumps by exactly samples_per_au
+    let mut state = ParserState {
+        samples_per_au: 40,
+        audio_sampling_frequency_1: 48000,
+        au_counter: 100,
+        first_output_timing: 1000,
+        output_timing: 5000,
+        input_timing: 4960,
+        prev_advance: 0,
+        advance: 40,
+        access_unit_length: 60,
+        prev_access_unit_length: 50,
+        fifo_duration: 12,
+        peak_data_rate: 1280,
+        output_timing_jump: true,
+        total_access_unit_length: 123_456,
+        ..Default::default()
+    };
+
+    RestartHeader::check_seamless_branch(&mut state, 5000)?;
+
+    assert!(state.has_valid_branch);
+    assert_eq!(state.output_timing_deviation, 0);
+    assert_eq!(state.unwrapped_input_timing, 4960);
+    assert_eq!(state.first_input_timing, 4960);
+    assert_eq!(state.prev_unwrapped_input_timing, 4960);
+    assert_eq!(state.prev_advance, 40);
+    assert_eq!(state.prev_access_unit_length, 60);
+    assert_eq!(state.prev_fifo_duration, 12);
+    assert_eq!(state.total_access_unit_length, 0);
+
+    state.fail_level = log::Level::Warn;
+    state.prev_advance = 0;
+    state.advance = 40;
+    let err = RestartHeader::check_seamless_branch(&mut state, 5000).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(RestartHeaderError::ZeroInputTimingInterval {
+            advance: 40,
+            prev_advance: 0
+        })
+    ));
+    Ok(())
+}
+
+#[test]
+fn category_policy_routes_timing_errors() {
+    use crate::utils::errors::Policy;
+
+    // Join with a zero input timing interval, reported as a timing warning
+    let check = |fail_level, policy: Option<Policy>| {
+        let mut state = ParserState {
+            fail_level,
+            samples_per_au: 40,
+            audio_sampling_frequency_1: 48000,
+            au_counter: 100,
+            output_timing: 5000,
+            input_timing: 4960,
+            prev_advance: 0,
+            advance: 40,
+            access_unit_length: 60,
+            fifo_duration: 12,
+            peak_data_rate: 1280,
+            output_timing_jump: true,
+            ..Default::default()
+        };
+        if let Some(policy) = policy {
+            state.category_policy.set(Category::Timing, policy);
+        }
+        RestartHeader::check_seamless_branch(&mut state, 5000).is_ok()
+    };
+
+    assert!(check(log::Level::Error, None));
+    assert!(!check(log::Level::Warn, None));
+    assert!(check(log::Level::Warn, Some(Policy::Ignore)));
+    assert!(check(log::Level::Warn, Some(Policy::Warn)));
+    assert!(!check(log::Level::Error, Some(Policy::Fail)));
+}
+
+#[test]
+fn invalid_branch_repair() -> Result<()> {
+    // advance[n] > advance[n-1] + 3 * samples_per_au / 4 fails c1
+    let branch_state = |expected_output_timing| -> Result<ParserState> {
+        let mut state = ParserState {
+            fail_level: log::Level::Warn,
+            repair_branch: true,
+            samples_per_au: 40,
+            audio_sampling_frequency_1: 48000,
+            au_counter: 100,
+            output_timing: 5000,
+            input_timing: 4860,
+            prev_advance: 0,
+            advance: 100,
+            prev_access_unit_length: 50,
+            peak_data_rate: 1280,
+            prev_peak_data_rate: 1280,
+            output_timing_jump: true,
+            ..Default::default()
+        };
+        RestartHeader::check_seamless_branch(&mut state, expected_output_timing)?;
+        Ok(state)
+    };
+
+    let state = branch_state(4900)?;
+    assert!(state.has_valid_branch);
+    assert_eq!(state.branch_repair, Some(100));
+    assert_eq!(state.total_access_unit_length, 0);
+
+    let state = branch_state(5100)?;
+    assert_eq!(state.branch_repair, Some(-100));
+    Ok(())
+}
+
+#[test]
+fn restart_sync_word_conversion() {
+    assert_eq!(
+        RestartSyncWord::try_from(0x31EB).ok(),
+        Some(RestartSyncWord::B)
+    );
+    assert_eq!(u16::from(RestartSyncWord::C), 0x31EC);
+    assert!(matches!(
+        RestartSyncWord::try_from(0x31ED),
+        Err(RestartHeaderError::InvalidRestartSyncWord(0x31ED))
+    ));
+}
+
+#[test]
+fn event_sink_receives_stream_restart() -> Result<()> {
+    use crate::utils::events::EventSink;
+    use std::sync::{Arc, Mutex};
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink_events = events.clone();
+    let mut state = ParserState {
+        event_sink: Some(EventSink::new(move |event| {
+            sink_events.lock().unwrap().push(event)
+        })),
+        samples_per_au: 40,
+        audio_sampling_frequency_1: 48000,
+        au_counter: 100,
+        output_timing: 5000,
+        input_timing: 4960,
+        prev_advance: 0,
+        advance: 40,
+        access_unit_length: 60,
+        fifo_duration: 12,
+        peak_data_rate: 1280,
+        output_timing_jump: true,
+        ..Default::default()
+    };
+    RestartHeader::check_seamless_branch(&mut state, 5000)?;
+
+    let events = events.lock().unwrap();
+    assert!(matches!(
+        events[0],
+        Event::Problem {
+            category: Category::Timing,
+            level: Warn,
+            ..
+        }
+    ));
+    assert_eq!(events[1], Event::StreamRestart { au: 100 });
+    assert_eq!(events.len(), 2);
+    Ok(())
+}
@@ -15,21 +15,22 @@
 //!
 //! Optional 8-bit parity check and CRC protection.
 
-use anyhow::{Result, anyhow};
+use anyhow::{Result, anyhow, bail};
 use log::{trace, warn};
 
 use crate::log_or_err;
 use crate::process::parse::ParserState;
 use crate::structs::block::Block;
 use crate::structs::sync::{MAJOR_SYNC_FBA, MAJOR_SYNC_FBB};
-use crate::utils::bitstream_io::BsIoSliceReader;
-use crate::utils::errors::SubstreamError;
+use crate::utils::bitstream_io::{BitstreamWriter, BsIoSliceReader};
+use crate::utils::errors::{AccessUnitError, Category, SubstreamError};
 
 /// Directory entry for substream navigation and control.
 ///
 /// Provides navigation information and control flags for one substream.
 /// Contains end pointers, restart flags, and optional dynamic range control data.
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct SubstreamDirectory {
     pub extra_substream_word: bool,
     pub restart_nonexistent: bool,
@@ -68,6 +69,7 @@ impl SubstreamDirectory {
             if state.format_sync == MAJOR_SYNC_FBB {
                 log_or_err!(
                     state,
+                    Category::Structure,
                     log::Level::Error,
                     anyhow!(SubstreamError::InvalidExtraSubstreamWordFbb)
                 );
@@ -89,6 +91,7 @@ impl SubstreamDirectory {
         if !(state.is_major_sync ^ sd.restart_nonexistent) {
             log_or_err!(
                 state,
+                Category::Structure,
                 log::Level::Warn,
                 anyhow!(SubstreamError::InvalidRestartNonexistent {
                     expected: !sd.restart_nonexistent,
@@ -108,12 +111,33 @@ impl SubstreamDirectory {
 
         Ok(sd)
     }
+
+    /// Writes the entry with the `substream_end_ptr` read, to be replaced
+    /// once the segment is written.
+    pub fn write(&self, state: &mut ParserState, writer: &mut BitstreamWriter) -> Result<()> {
+        writer.put(self.extra_substream_word);
+        writer.put(self.restart_nonexistent);
+        writer.put(self.crc_present);
+        writer.put(self.reserved);
+        writer.put_n(self.substream_end_ptr, 12);
+
+        if self.extra_substream_word {
+            writer.put_s(self.drc_gain_update, 9);
+            writer.put_n(self.drc_time_update, 3);
+            writer.put_n(0u8, 4);
+        }
+
+        state.substream_state_mut()?.crc_present = self.crc_present;
+
+        Ok(())
+    }
 }
 
 /// Stream termination information for final access unit.
 ///
 /// Contains termination markers indicating stream completion.
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Terminator {
     pub terminator_a: u32,
     pub zero_samples_indicated: bool,
@@ -125,6 +149,7 @@ pub struct Terminator {
 ///
 /// Contains compressed audio data for one substream with optional error protection.
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct SubstreamSegment {
     pub block: Vec<Block>,
     pub substream_parity: u8,
@@ -138,6 +163,7 @@ impl SubstreamSegment {
         if start_pos & 0xF != 0 {
             log_or_err!(
                 state,
+                Category::Structure,
                 log::Level::Error,
                 anyhow!(
                     "Substream {} segment not byte-aligned at start",
@@ -154,6 +180,7 @@ impl SubstreamSegment {
             if ss.block.len() > 4 || ss.block.len() >= 3 && state.format_sync == MAJOR_SYNC_FBA {
                 log_or_err!(
                     state,
+                    Category::Structure,
                     log::Level::Warn,
                     anyhow!(SubstreamError::TooManyBlocks(ss.block.len()))
                 );
@@ -176,7 +203,8 @@ impl SubstreamSegment {
             test_size += 16;
         }
 
-        if expected_end_pos - reader.position()? >= test_size {
+        // Blocks overrunning the end pointer leave no room for a terminator
+        if expected_end_pos.saturating_sub(reader.position()?) >= test_size {
             let terminator_a = reader.get_n(18)?;
 
             if terminator_a == 0x348D3 {
@@ -223,6 +251,8 @@ impl SubstreamSegment {
                         )
                     }
                 }
+
+                ss.terminator = Some(tm);
             } else {
                 warn!("Invalid termination word: expected 0x348D3, found {terminator_a:#X}",);
 
@@ -244,6 +274,7 @@ impl SubstreamSegment {
             if parity != ss.substream_parity {
                 log_or_err!(
                     state,
+                    Category::Crc,
                     log::Level::Error,
                     anyhow!(SubstreamError::ParityMismatch {
                         substream: state.substream_index,
@@ -258,6 +289,7 @@ impl SubstreamSegment {
             if crc != ss.substream_crc {
                 log_or_err!(
                     state,
+                    Category::Crc,
                     log::Level::Error,
                     anyhow!(SubstreamError::CrcMismatch {
                         substream: state.substream_index,
@@ -273,12 +305,14 @@ impl SubstreamSegment {
         if end_pos & 0xF != 0 {
             log_or_err!(
                 state,
+                Category::Structure,
                 log::Level::Error,
                 anyhow!(SubstreamError::UnalignedSegmentEnd(state.substream_index))
             );
         } else if expected_end_pos != end_pos {
             log_or_err!(
                 state,
+                Category::Structure,
                 log::Level::Error,
                 anyhow!(SubstreamError::SubstreamEndMismatch {
                     substream: state.substream_index,
@@ -290,4 +324,41 @@ impl SubstreamSegment {
 
         Ok(ss)
     }
+    pub fn write(&self, state: &mut ParserState, writer: &mut BitstreamWriter) -> Result<()> {
+        if self.block.is_empty() {
+            bail!(AccessUnitError::UnparsedSubstream(state.substream_index));
+        }
+
+        let start_pos = writer.position();
+
+        for (i, block) in self.block.iter().enumerate() {
+            block.write(state, writer)?;
+            writer.put(i + 1 == self.block.len());
+        }
+
+        writer.align_16bit();
+
+        if let Some(tm) = &self.terminator {
+            writer.put_n(tm.terminator_a, 18);
+            writer.put(tm.zero_samples_indicated);
+
+            if tm.zero_samples_indicated {
+                writer.put_n(tm.zero_samples, 13);
+            } else {
+                writer.put_n(tm.terminator_b, 13);
+            }
+        }
+
+        if state.substream_state()?.crc_present {
+            let len = writer.position() - start_pos;
+
+            let parity = writer.parity(start_pos, len) ^ 0xa9;
+            let crc = writer.crc8(&state.crc_substream, start_pos, len)?;
+
+            writer.put_n(parity, 8);
+            writer.put_n(crc, 8);
+        }
+
+        Ok(())
+    }
 }
@@ -8,7 +8,7 @@
 //! ## Format Types
 //!
 //! - **FBA Format** (0xF8726FBA): Dolby TrueHD format
-//! - **FBB Format** (0xF8726FBB): Meridian format (not implemented)
+//! - **FBB Format** (0xF8726FBB): Meridian Lossless Packing (MLP) format, up to 6 channels
 //!
 //! ## Sample Counts
 //!
@@ -16,15 +16,16 @@
 
 use anyhow::{Result, anyhow, bail};
 use log::Level::{Error, Warn};
-use log::{debug, warn};
+use log::{debug, info};
 
 use crate::log_or_err;
-use crate::process::PresentationMap;
 use crate::process::decode::DecoderState;
 use crate::process::parse::ParserState;
-use crate::structs::channel::ChannelMeaning;
-use crate::utils::bitstream_io::BsIoSliceReader;
-use crate::utils::errors::SyncError;
+use crate::process::{MAX_PRESENTATIONS, PresentationMap};
+use crate::structs::channel::{ChannelLabel, ChannelMeaning};
+use crate::utils::bitstream_io::{BitstreamWriter, BsIoSliceReader};
+use crate::utils::errors::{Category, SyncError};
+use crate::utils::events::Event;
 
 /// Major sync pattern for FBA (Dolby) format streams.
 ///
@@ -36,8 +37,6 @@ pub const MAJOR_SYNC_FBA: u32 = 0xF8_72_6F_BA;
 /// 32-bit sync word (0xF8726FBB) identifying Meridian MLP streams.
 pub const MAJOR_SYNC_FBB: u32 = 0xF8_72_6F_BB;
 
-pub const UNIMPLEMENTED_FBB_MSG: &str = "FBB format is not implemented yet";
-
 /// Base sampling rate for CD-family rates (44.1kHz, 88.2kHz, 176.4kHz).
 pub const BASE_SAMPLING_RATE_CD: u32 = 44100;
 
@@ -51,12 +50,13 @@ pub const BASE_SAMPLES_PER_AU: usize = 40;
 ///
 /// Stream configuration parsed from 32-bit format_info field containing
 /// sampling frequency and channel configuration parameters.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct FormatInfo {
-    pub _quantization_word_length_1: u8,
-    pub _quantization_word_length_2: u8,
+    pub quantization_word_length_1: u8,
+    pub quantization_word_length_2: u8,
     pub audio_sampling_frequency_1: u8,
-    pub _audio_sampling_frequency_2: u8,
+    pub audio_sampling_frequency_2: u8,
     pub multi_channel_type: u8,
     pub fbb_channel_assignment: u8,
 
@@ -79,20 +79,49 @@ impl FormatInfo {
 
         state.is_major_sync = true;
 
+        if state.format_sync == MAJOR_SYNC_FBB {
+            let channels = ChannelLabel::from_fbb_channel(fi.fbb_channel_assignment)?.len();
+            debug!(
+                "FBB format: {} bits, {channels} channels",
+                fi.quantization_word_length_1()?
+            );
+        }
+
         state.audio_sampling_frequency_1 = fi.sampling_frequency_1()?;
         state.samples_per_au = fi.samples_per_au()?;
 
         Ok(fi)
     }
 
-    // pub fn quantization_word_length_1(&self) -> Result<u8> {
-    //     Self::map_quantization(self.quantization_word_length_1, 1)
-    // }
+    /// Returns the word length in bits of the first channel group.
+    ///
+    /// Always 24 for FBA streams.
+    pub fn quantization_word_length_1(&self) -> Result<u8> {
+        Self::map_quantization(self.quantization_word_length_1, 1)
+    }
+
+    /// Returns the word length in bits of the second channel group of an FBB
+    /// stream, or `None` if there is no second group.
+    pub fn quantization_word_length_2(&self) -> Result<Option<u8>> {
+        if self.audio_sampling_frequency_2 == 0xF {
+            return Ok(None);
+        }
+        Self::map_quantization(self.quantization_word_length_2, 2).map(Some)
+    }
 
     pub fn sampling_frequency_1(&self) -> Result<u32> {
         Self::map_sampling_freq(self.audio_sampling_frequency_1, 1)
     }
 
+    /// Returns the sampling frequency of the second channel group of an FBB
+    /// stream, or `None` if there is no second group.
+    pub fn sampling_frequency_2(&self) -> Result<Option<u32>> {
+        if self.audio_sampling_frequency_2 == 0xF {
+            return Ok(None);
+        }
+        Self::map_sampling_freq(self.audio_sampling_frequency_2, 2).map(Some)
+    }
+
     pub fn samples_per_au(&self) -> Result<usize> {
         let freq = self.sampling_frequency_1()?;
         Ok((freq / BASE_SAMPLING_RATE_CD) as usize * BASE_SAMPLES_PER_AU)
@@ -107,8 +136,9 @@ impl FormatInfo {
 
     fn read_fba(reader: &mut BsIoSliceReader) -> Result<Self> {
         let mut fi = Self {
-            _quantization_word_length_1: 2,
+            quantization_word_length_1: 2,
             audio_sampling_frequency_1: reader.get_n(4)?,
+            audio_sampling_frequency_2: 0xF,
             sixch_multi_channel_type: reader.get()?,
             eightch_multi_channel_type: reader.get()?,
             ..Default::default()
@@ -126,10 +156,10 @@ impl FormatInfo {
 
     fn read_fbb(reader: &mut BsIoSliceReader) -> Result<Self> {
         let mut fi = Self {
-            _quantization_word_length_1: reader.get_n(4)?,
-            _quantization_word_length_2: reader.get_n(4)?,
+            quantization_word_length_1: reader.get_n(4)?,
+            quantization_word_length_2: reader.get_n(4)?,
             audio_sampling_frequency_1: reader.get_n(4)?,
-            _audio_sampling_frequency_2: reader.get_n(4)?,
+            audio_sampling_frequency_2: reader.get_n(4)?,
             ..Default::default()
         };
 
@@ -138,24 +168,50 @@ impl FormatInfo {
         reader.skip_n(3)?;
         fi.fbb_channel_assignment = reader.get_n(5)?;
 
-        // state.quantization_word_length_2 =
-        //     Self::map_quantization(fi.quantization_word_length_2, 2)?;
-        // state.audio_sampling_frequency_2 =
-        //     Self::map_sampling_freq(fi.audio_sampling_frequency_2, 2)?;
+        fi.quantization_word_length_1()?;
+        fi.quantization_word_length_2()?;
+        fi.sampling_frequency_2()?;
 
         Ok(fi)
     }
 
-    // fn map_quantization(value: u8, index: u8) -> Result<u8> {
-    //     match value {
-    //         0..=2 => Ok(16 + (value << 2)),
-    //         _ => bail!(
-    //             "Invalid format_info: quantization_word_length_{}. Read {:#01X}",
-    //             index,
-    //             value
-    //         ),
-    //     }
-    // }
+    fn write(&self, state: &mut ParserState, writer: &mut BitstreamWriter) -> Result<()> {
+        match state.format_sync {
+            MAJOR_SYNC_FBA => {
+                writer.put_n(self.audio_sampling_frequency_1, 4);
+                writer.put(self.sixch_multi_channel_type);
+                writer.put(self.eightch_multi_channel_type);
+                writer.put_n(0u8, 2);
+                writer.put_n(self.twoch_decoder_channel_modifier, 2);
+                writer.put_n(self.sixch_decoder_channel_modifier, 2);
+                writer.put_n(self.sixch_decoder_channel_assignment, 5);
+                writer.put_n(self.eightch_decoder_channel_modifier, 2);
+                writer.put_n(self.eightch_decoder_channel_assignment, 13);
+            }
+            MAJOR_SYNC_FBB => {
+                writer.put_n(self.quantization_word_length_1, 4);
+                writer.put_n(self.quantization_word_length_2, 4);
+                writer.put_n(self.audio_sampling_frequency_1, 4);
+                writer.put_n(self.audio_sampling_frequency_2, 4);
+                writer.put_n(0u8, 4);
+                writer.put_n(self.multi_channel_type, 4);
+                writer.put_n(0u8, 3);
+                writer.put_n(self.fbb_channel_assignment, 5);
+            }
+            sync => bail!(SyncError::InvalidFormatSync(sync)),
+        }
+
+        state.samples_per_au = self.samples_per_au()?;
+
+        Ok(())
+    }
+
+    fn map_quantization(value: u8, index: u8) -> Result<u8> {
+        match value {
+            0..=2 => Ok(16 + (value << 2)),
+            _ => bail!(SyncError::InvalidQuantizationWordLength { index, value }),
+        }
+    }
 
     fn map_sampling_freq(value: u8, index: u8) -> Result<u32> {
         match value {
@@ -171,6 +227,7 @@ impl FormatInfo {
 /// Contains stream configuration and decoder initialization parameters.
 /// Protected by 16-bit CRC.
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct MajorSyncInfo {
     pub format_sync: u32,
     pub format_info: FormatInfo,
@@ -183,6 +240,8 @@ pub struct MajorSyncInfo {
     pub extended_substream_info: u8,
     pub substream_info: u8,
     pub channel_meaning: ChannelMeaning,
+    /// Bits in place of the channel meaning in FBB streams.
+    pub fbb_reserved: u64,
     pub major_sync_info_crc: u16,
 }
 
@@ -207,6 +266,7 @@ impl MajorSyncInfo {
         if ms.signature != 0xB752 {
             log_or_err!(
                 state,
+                Category::Sync,
                 Warn,
                 anyhow!(SyncError::InvalidMajorSyncSignature(ms.signature))
             )
@@ -218,6 +278,7 @@ impl MajorSyncInfo {
         if ms.flags & 0x67FF != 0 {
             log_or_err!(
                 state,
+                Category::Sync,
                 Warn,
                 anyhow!(SyncError::ReservedFlagsNonZero(ms.flags))
             )
@@ -226,6 +287,7 @@ impl MajorSyncInfo {
         if state.has_parsed_au && state.flags != ms.flags {
             log_or_err!(
                 state,
+                Category::Sync,
                 Warn,
                 anyhow!(SyncError::FlagsMismatch {
                     read: ms.flags,
@@ -256,6 +318,7 @@ impl MajorSyncInfo {
             } else {
                 log_or_err!(
                     state,
+                    Category::DataRate,
                     Warn,
                     anyhow!(SyncError::PeakDataRateMismatch {
                         read: ms.peak_data_rate,
@@ -268,29 +331,49 @@ impl MajorSyncInfo {
         state.variable_rate = ms.variable_rate;
         state.peak_data_rate = ms.peak_data_rate as usize;
 
-        if let Some(substreams) = state.substreams {
-            if substreams != ms.substreams {
-                log_or_err!(
-                    state,
-                    Warn,
-                    anyhow!(SyncError::SubstreamCountMismatch {
-                        read: ms.substreams,
-                        expected: substreams,
-                    })
-                )
-            }
-        } else {
-            state.substreams = Some(ms.substreams);
+        if let Some(substreams) = state.substreams
+            && substreams != ms.substreams
+        {
+            log_or_err!(
+                state,
+                Category::Sync,
+                Warn,
+                anyhow!(SyncError::SubstreamCountMismatch {
+                    read: ms.substreams,
+                    expected: substreams,
+                })
+            );
+
+            // Treat the new configuration as the start of a new stream
+            state.has_parsed_au = false;
+            state.has_substream_info_changed = true;
+            state.reset_for_branch();
         }
 
+        state.substreams = Some(ms.substreams);
+
         // reserved(2) field is part of extended_substream_info
         ms.extended_substream_info = reader.get_n(4)?;
         ms.substream_info = reader.get_n(8)?;
 
         'check_substream_info: {
+            // Not signalled in FBB streams, where substream 0 is the 2ch downmix
+            if ms.format_sync == MAJOR_SYNC_FBB {
+                if ms.substreams > 2 {
+                    log_or_err!(
+                        state,
+                        Category::Sync,
+                        Error,
+                        anyhow!(SyncError::TooManyFbbSubstreams(ms.substreams))
+                    );
+                }
+                break 'check_substream_info;
+            }
+
             if ms.extended_substream_info >> 2 != 0 {
                 log_or_err!(
                     state,
+                    Category::Sync,
                     log::Level::Debug,
                     anyhow!(SyncError::ReservedExtendedSubstreamInfo(
                         ms.extended_substream_info >> 2
@@ -301,6 +384,7 @@ impl MajorSyncInfo {
             if ms.substream_info & 3 != 0 {
                 log_or_err!(
                     state,
+                    Category::Sync,
                     log::Level::Debug,
                     anyhow!(SyncError::ReservedSubstreamInfo(ms.substream_info))
                 );
@@ -318,40 +402,37 @@ impl MajorSyncInfo {
                 state.has_substream_info_changed = true;
                 state.reset_for_branch();
 
-                if !c1 {
-                    log_or_err!(
-                        state,
-                        Warn,
-                        anyhow!(SyncError::SubstreamInfoMismatch {
-                            read: ms.substream_info,
-                            expected: state.substream_info
-                        })
-                    )
-                }
-
-                if !c2 {
-                    log_or_err!(
-                        state,
-                        Warn,
-                        anyhow!(SyncError::ExtendedSubstreamInfoMismatch {
-                            read: ms.extended_substream_info,
-                            expected: state.extended_substream_info
-                        })
-                    )
+                let format_change = FormatChange {
+                    substream_info: ms.substream_info,
+                    extended_substream_info: ms.extended_substream_info,
+                    prev_substream_info: state.substream_info,
+                    prev_extended_substream_info: state.extended_substream_info,
+                    channel_counts: presentation_channel_counts(state),
+                };
+
+                if state.allow_format_change {
+                    // Nothing of the previous configuration applies to the
+                    // substreams, their restart headers follow in this access unit
+                    state.substream_state = Default::default();
+                    state.restart_headers = Default::default();
+                    state.format_change = Some(format_change);
+                } else {
+                    format_change.report(state)?;
                 }
             }
 
             let extended_substream_info = ms.extended_substream_info & 3;
             let substream_info = ms.substream_info & 0x7C;
 
-            if substream_info <= 76
-                && (76562297473007889u64 >> substream_info.wrapping_sub(20)) & 1 != 0
-                || (68987981841u64 >> substream_info.wrapping_sub(88)) & 1 != 0
-            {
+            if matches!(
+                substream_info,
+                20 | 24 | 28 | 36 | 40 | 52 | 60 | 72 | 76 | 88 | 92 | 100 | 104 | 116 | 124
+            ) {
                 debug!("substream_info={substream_info:#02X}")
             } else {
                 log_or_err!(
                     state,
+                    Category::Sync,
                     Error,
                     anyhow!(SyncError::InvalidSubstreamInfo(substream_info))
                 )
@@ -365,6 +446,7 @@ impl MajorSyncInfo {
             {
                 log_or_err!(
                     state,
+                    Category::Sync,
                     Warn,
                     anyhow!(SyncError::SubstreamInfoInCompatible {
                         substream_info,
@@ -378,6 +460,7 @@ impl MajorSyncInfo {
             if substream_info >> 7 == 0 && extended_substream_info != 0 {
                 log_or_err!(
                     state,
+                    Category::Sync,
                     log::Level::Debug,
                     anyhow!(SyncError::ReservedExtendedSubstreamInfo(
                         ms.extended_substream_info
@@ -392,6 +475,7 @@ impl MajorSyncInfo {
                 if sixch_assign as u16 != eightch_assign {
                     log_or_err!(
                         state,
+                        Category::Sync,
                         log::Level::Debug,
                         anyhow!(SyncError::SixchAndEightchChannelAssignmentMismatch {
                             sixch: sixch_assign,
@@ -407,6 +491,7 @@ impl MajorSyncInfo {
                     if sixch_modifier != eightch_modifier {
                         log_or_err!(
                             state,
+                            Category::Sync,
                             Warn,
                             anyhow!(SyncError::SixchAndEightchChannelModifierMismatch {
                                 sixch: sixch_modifier,
@@ -421,6 +506,7 @@ impl MajorSyncInfo {
                 if substream_info & (1 << bit) != 0 && ms.substreams < min {
                     log_or_err!(
                         state,
+                        Category::Sync,
                         Warn,
                         anyhow!(SyncError::SubstreamCountInsufficient { min, bit })
                     );
@@ -437,14 +523,14 @@ impl MajorSyncInfo {
             {
                 log_or_err!(
                     state,
+                    Category::Sync,
                     log::Level::Debug,
                     anyhow!(SyncError::SubstreamCountInfoInconsistent)
                 );
             };
         }
 
-        let presentation_map =
-            PresentationMap::with_substream_info(ms.substream_info, ms.extended_substream_info);
+        let presentation_map = ms.presentation_map();
 
         // TODO: check mismatch
         state.presentation_map = Some(presentation_map);
@@ -454,7 +540,12 @@ impl MajorSyncInfo {
         state.substream_info = ms.substream_info;
         state.extended_substream_info = ms.extended_substream_info;
 
-        ms.channel_meaning = ChannelMeaning::read(state, reader)?;
+        if ms.format_sync == MAJOR_SYNC_FBB {
+            // FBB streams carry no channel meaning
+            ms.fbb_reserved = reader.get_n(64)?;
+        } else {
+            ms.channel_meaning = ChannelMeaning::read(state, reader)?;
+        }
 
         let len = reader.position()? - start_pos;
 
@@ -465,6 +556,7 @@ impl MajorSyncInfo {
         if crc != ms.major_sync_info_crc {
             log_or_err!(
                 state,
+                Category::Crc,
                 Error,
                 anyhow!(SyncError::MajorSyncCrcMismatch {
                     calculated: crc,
@@ -478,40 +570,385 @@ impl MajorSyncInfo {
         Ok(ms)
     }
 
+    pub fn write(&self, state: &mut ParserState, writer: &mut BitstreamWriter) -> Result<()> {
+        let start_pos = writer.position();
+
+        writer.put_n(self.format_sync, 32);
+        state.format_sync = self.format_sync;
+
+        self.format_info.write(state, writer)?;
+
+        writer.put_n(self.signature, 16);
+        writer.put_n(self.flags, 16);
+        writer.put_n(self.reserved, 16);
+        writer.put(self.variable_rate);
+        writer.put_n(self.peak_data_rate, 15);
+        writer.put_n(self.substreams as u8, 4);
+        writer.put_n(self.extended_substream_info, 4);
+        writer.put_n(self.substream_info, 8);
+
+        state.flags = self.flags;
+        state.substreams = Some(self.substreams);
+        state.substream_info = self.substream_info;
+        state.extended_substream_info = self.extended_substream_info;
+
+        if self.format_sync == MAJOR_SYNC_FBB {
+            writer.put_n(self.fbb_reserved, 64);
+        } else {
+            self.channel_meaning.write(state, writer)?;
+        }
+
+        let crc = writer.crc16(
+            &state.crc_major_sync_info,
+            start_pos,
+            writer.position() - start_pos,
+        )?;
+        writer.put_n(crc, 16);
+
+        Ok(())
+    }
+
     pub fn update_decoder_state(&self, state: &mut DecoderState) -> Result<()> {
         self.format_info.update_decoder_state(state)?;
-        if state.valid && state.substreams != self.substreams {
-            state.valid = false;
-            warn!(
-                "Substream count must be constant: expected {}, found {}",
-                state.substreams, self.substreams
-            )
-        }
 
-        // Check for substream info changes that would require new output files
+        // A configuration change restarts decoding with a new presentation layout
         if state.valid
-            && (state.substream_info != self.substream_info
+            && (state.substreams != self.substreams
+                || state.substream_info != self.substream_info
                 || state.extended_substream_info != self.extended_substream_info)
         {
             log::debug!(
-                "substream_info changed in decoder: {} -> {}, extended_substream_info: {} -> {}",
+                "stream configuration changed in decoder: substreams {} -> {}, \
+                substream_info: {} -> {}, extended_substream_info: {} -> {}",
+                state.substreams,
+                self.substreams,
                 state.substream_info,
                 self.substream_info,
                 state.extended_substream_info,
                 self.extended_substream_info
             );
             state.substream_info_changed = true;
+            state.reset_for_stream_restart();
         }
 
+        state.format_sync = self.format_sync;
         state.substreams = self.substreams;
         state.substream_info = self.substream_info;
         state.extended_substream_info = self.extended_substream_info;
 
-        state.presentation_map = Some(PresentationMap::with_substream_info(
+        state.presentation_map = Some(self.presentation_map());
+
+        if self.format_sync == MAJOR_SYNC_FBA {
+            let channel_meaning = &self.channel_meaning;
+            state.heavy_drc_start_up_gain = channel_meaning.heavy_drc_start_up_gain as f32 / 4.0;
+            state.dialogue_level = [
+                Some(-(channel_meaning.twoch_dialogue_norm as i8)),
+                Some(-(channel_meaning.sixch_dialogue_norm as i8)),
+                Some(-(channel_meaning.eightch_dialogue_norm as i8)),
+                channel_meaning
+                    .extra_channel_meaning
+                    .as_ref()
+                    .map(|extra| -(extra.sixteench_dialogue_norm as i8)),
+            ];
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the stream carries Dolby Atmos in its 16-channel presentation.
+    ///
+    /// The object audio metadata itself only follows in the EVO frames of the
+    /// access units, so this is known from the first major sync.
+    pub fn is_atmos(&self) -> bool {
+        self.format_sync != MAJOR_SYNC_FBB && self.substream_info >> 7 != 0
+    }
+
+    /// Returns the substreams making up each presentation.
+    pub fn presentation_map(&self) -> PresentationMap {
+        if self.format_sync == MAJOR_SYNC_FBB {
+            PresentationMap::with_fbb_substreams(self.substreams)
+        } else {
+            PresentationMap::with_substream_info(self.substream_info, self.extended_substream_info)
+        }
+    }
+}
+
+/// Change of `substream_info` or `extended_substream_info` at a major sync
+#[derive(Debug, Clone, Copy)]
+pub struct FormatChange {
+    pub substream_info: u8,
+    pub extended_substream_info: u8,
+    pub prev_substream_info: u8,
+    pub prev_extended_substream_info: u8,
+    /// Channel counts of the presentations before the change
+    pub channel_counts: [Option<u8>; MAX_PRESENTATIONS],
+}
+
+impl FormatChange {
+    /// Accepts the change if the required presentations keep their channel
+    /// counts, which are known once the restart headers of the access unit
+    /// starting the new configuration are parsed, and reports it otherwise.
+    pub fn check(&self, state: &ParserState) -> Result<()> {
+        let compatible = presentation_channel_counts(state)
+            .iter()
+            .zip(&self.channel_counts)
+            .zip(&state.required_presentations)
+            .all(|((count, prev_count), &required)| !required || count == prev_count);
+        if !compatible {
+            return self.report(state);
+        }
+
+        let au = state.au_counter - 1;
+        info!(
+            "AU {au}: substream_info changed from {:#04X} to {:#04X} \
+            (extended_substream_info {:#X} to {:#X}), restarting with the new presentations",
+            self.prev_substream_info,
             self.substream_info,
-            self.extended_substream_info,
-        ));
+            self.prev_extended_substream_info,
+            self.extended_substream_info
+        );
+        state.emit(Event::FormatChange {
+            au,
+            substream_info: self.substream_info,
+            extended_substream_info: self.extended_substream_info,
+        });
+        Ok(())
+    }
+
+    /// Reports the changed fields as mismatches.
+    fn report(&self, state: &ParserState) -> Result<()> {
+        if self.substream_info != self.prev_substream_info {
+            log_or_err!(
+                state,
+                Category::Sync,
+                Warn,
+                anyhow!(SyncError::SubstreamInfoMismatch {
+                    read: self.substream_info,
+                    expected: self.prev_substream_info
+                })
+            )
+        }
+
+        if self.extended_substream_info != self.prev_extended_substream_info {
+            log_or_err!(
+                state,
+                Category::Sync,
+                Warn,
+                anyhow!(SyncError::ExtendedSubstreamInfoMismatch {
+                    read: self.extended_substream_info,
+                    expected: self.prev_extended_substream_info
+                })
+            )
+        }
 
         Ok(())
     }
 }
+
+/// Returns the channel count of each presentation, from the last restart
+/// header of the highest substream it is decoded from.
+fn presentation_channel_counts(state: &ParserState) -> [Option<u8>; MAX_PRESENTATIONS] {
+    let Some(presentation_map) = state.presentation_map else {
+        return [None; MAX_PRESENTATIONS];
+    };
+    presentation_map.masks.map(|mask| {
+        let substream = (u8::BITS - mask.leading_zeros()).checked_sub(1)? as usize;
+        state.restart_headers[substream]
+            .as_ref()
+            .map(|restart_header| restart_header.max_matrix_chan + 1)
+    })
+}
+
+#[test]
+fn configuration_change_restarts_decoder() -> Result<()> {
+    let mut state = DecoderState {
+        valid: true,
+        substreams: 4,
+        substream_info: 0xCC,
+        extended_substream_info: 1,
+        ..Default::default()
+    };
+    state.substream_state[3].max_matrix_chan = 15;
+
+    let mut ms = MajorSyncInfo {
+        substreams: 4,
+        substream_info: 0xCC,
+        extended_substream_info: 1,
+        ..Default::default()
+    };
+
+    ms.update_decoder_state(&mut state)?;
+    assert!(state.valid);
+    assert!(!state.substream_info_changed);
+
+    ms.substream_info = 0xFC;
+    ms.substreams = 3;
+    ms.update_decoder_state(&mut state)?;
+    assert!(!state.valid);
+    assert!(state.substream_info_changed);
+    assert_eq!(state.substreams, 3);
+    assert_eq!(state.substream_info, 0xFC);
+    assert_eq!(state.substream_state[3].max_matrix_chan, 0);
+    Ok(())
+}
+
+#[test]
+fn format_change_keeps_channel_counts() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::decode::Decoder;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+
+    // Append a copy of the stream with another substream_info and optionally
+    // a third matrix channel
+    let with_substream_info = |substream_info: u8, max_matrix_chan: u8| -> Result<Vec<u8>> {
+        let mut extractor = Extractor::default();
+        extractor.push_bytes(EXAMPLE_DATA);
+        let mut parser = Parser::default();
+        let mut writer = BitstreamWriter::new();
+        let mut stream = EXAMPLE_DATA.to_vec();
+        for frame in extractor.filter_map(Result::ok) {
+            let mut access_unit = parser.parse(&frame)?;
+            if let Some(major_sync_info) = &mut access_unit.major_sync_info {
+                assert_eq!(major_sync_info.substream_info, 0x14);
+                major_sync_info.substream_info = substream_info;
+            }
+            let blocks = access_unit.substream_segment[0].block.iter_mut();
+            for restart_header in blocks.flat_map(|block| &mut block.restart_header) {
+                restart_header.max_matrix_chan = max_matrix_chan;
+                restart_header.ch_assign[2] = 2;
+            }
+            stream.extend(access_unit.write(&mut writer)?);
+        }
+        Ok(stream)
+    };
+
+    let decode = |stream: &[u8], allow_format_change| -> Result<Vec<bool>> {
+        let mut extractor = Extractor::default();
+        extractor.push_bytes(stream);
+        let mut parser = Parser::default();
+        parser.set_fail_level(log::Level::Warn);
+        parser.set_allow_format_change(allow_format_change);
+        let mut decoder = Decoder::default();
+
+        let mut restarts = Vec::new();
+        for frame in extractor.filter_map(Result::ok) {
+            let access_unit = parser.parse(&frame)?;
+            let decoded = decoder.decode_presentation(&access_unit, 1)?;
+            assert_eq!(decoded.channel_labels, [ChannelLabel::L, ChannelLabel::R]);
+            restarts.push(access_unit.format_change);
+            assert_eq!(decoded.substream_info_changed, access_unit.format_change);
+        }
+        Ok(restarts)
+    };
+
+    // A reserved bit keeps the presentations as they are
+    let compatible = with_substream_info(0x15, 1)?;
+    let err = decode(&compatible, false).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(SyncError::SubstreamInfoMismatch {
+            read: 0x15,
+            expected: 0x14
+        })
+    ));
+    let restarts = decode(&compatible, true)?;
+    let frames = restarts.len() / 2;
+    assert_eq!(
+        restarts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &restart)| restart.then_some(i))
+            .collect::<Vec<_>>(),
+        [frames]
+    );
+
+    // Another channel count is still reported
+    let incompatible = with_substream_info(0x15, 2)?;
+    let err = decode(&incompatible, true).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(SyncError::SubstreamInfoMismatch {
+            read: 0x15,
+            expected: 0x14
+        })
+    ));
+    Ok(())
+}
+
+#[test]
+fn fbb_major_sync_decodes() -> Result<()> {
+    use crate::process::EXAMPLE_DATA;
+    use crate::process::decode::Decoder;
+    use crate::process::extract::Extractor;
+    use crate::process::parse::Parser;
+    use crate::utils::crc::{CRC_MAJOR_SYNC_INFO_ALG, Crc16};
+
+    // Rewrite the first access unit as FBB with 24-bit 48 kHz L/R in a single
+    // substream, dropping the extra substream word not allowed in FBB
+    let mut au = EXAMPLE_DATA[16..100].to_vec();
+    au.drain(34..36);
+    au[32] &= 0x7F;
+    au[1] -= 1; // access_unit_length in 16-bit words
+    au[7] = 0xBB;
+    au[8..12].copy_from_slice(&[0x2F, 0x0F, 0x00, 0x01]);
+
+    let crc = Crc16::new(&CRC_MAJOR_SYNC_INFO_ALG);
+    let crc = crc.update(crc.init, &au[4..30]);
+    au[30..32].copy_from_slice(&crc.to_be_bytes());
+
+    // Restore the nibble parity over the header and substream directory
+    let parity = au[..4]
+        .iter()
+        .chain(&au[32..34])
+        .fold(0, |p, b| p ^ (b >> 4) ^ (b & 0xF));
+    au[0] ^= (parity ^ 0xF) << 4;
+
+    let fbb_data = [&EXAMPLE_DATA[..16], &au, &EXAMPLE_DATA[100..]].concat();
+
+    let decode = |data: &[u8], presentation| -> Result<Vec<_>> {
+        let mut extractor = Extractor::default();
+        let mut parser = Parser::default();
+        let mut decoder = Decoder::default();
+        extractor.push_bytes(data);
+
+        let mut decoded = Vec::new();
+        for frame in extractor.filter_map(Result::ok) {
+            let access_unit = parser.parse(&frame)?;
+            if let Some(major_sync_info) = &access_unit.major_sync_info {
+                assert_eq!(
+                    major_sync_info.format_info.quantization_word_length_1()?,
+                    24
+                );
+                assert_eq!(major_sync_info.format_info.sampling_frequency_2()?, None);
+            }
+            decoded.push(decoder.decode_presentation(&access_unit, presentation)?);
+        }
+        Ok(decoded)
+    };
+
+    let fba = decode(EXAMPLE_DATA, 0)?;
+    let fbb = decode(&fbb_data, 1)?;
+
+    assert_eq!(fbb.len(), 2);
+    for (fba, fbb) in fba.iter().zip(&fbb) {
+        assert_eq!(fbb.channel_labels, [ChannelLabel::L, ChannelLabel::R]);
+        assert_eq!(fbb.channel_count, fba.channel_count);
+        assert_eq!(fbb.sample_length, fba.sample_length);
+        assert_eq!(fbb.pcm_data, fba.pcm_data);
+    }
+    Ok(())
+}
+
+#[test]
+fn atmos_signalled_by_substream_info() {
+    let major_sync = |format_sync, substream_info| MajorSyncInfo {
+        format_sync,
+        substream_info,
+        ..Default::default()
+    };
+
+    assert!(major_sync(MAJOR_SYNC_FBA, 0xCC).is_atmos());
+    assert!(!major_sync(MAJOR_SYNC_FBA, 0x4C).is_atmos());
+    assert!(!major_sync(MAJOR_SYNC_FBB, 0x80).is_atmos());
+}
@@ -9,6 +9,9 @@ use crate::utils::errors::TimestampError;
 use anyhow::{Result, bail, ensure};
 use log::trace;
 
+/// Length of a timestamp packet in bytes
+pub const TIMESTAMP_PACKET_LEN: usize = 16;
+
 /// SMPTE timestamp with frame and sample precision
 #[derive(Debug, Clone)]
 pub struct Timestamp {
@@ -45,13 +48,22 @@ impl Display for Timestamp {
 }
 
 impl Timestamp {
+    /// Checks whether `buffer` starts with the sync bytes of a timestamp packet.
+    pub fn is_packet(buffer: &[u8]) -> bool {
+        buffer.len() >= TIMESTAMP_PACKET_LEN
+            && buffer[0] == 0x01
+            && buffer[1] == 0x10
+            && buffer[14] == 0x80
+            && buffer[15] == 0
+    }
+
     pub fn from_bytes(buffer: &[u8]) -> Result<Self> {
         ensure!(
-            buffer.len() >= 16,
+            buffer.len() >= TIMESTAMP_PACKET_LEN,
             "Insufficient data for parsing Timestamp"
         );
 
-        if buffer[0] != 0x01 || buffer[1] != 0x10 || buffer[14] != 0x80 || buffer[15] != 0 {
+        if !Self::is_packet(buffer) {
             bail!(TimestampError::InvalidSyncBytes);
         }
 
@@ -90,6 +102,57 @@ impl Timestamp {
         Ok(timestamp)
     }
 
+    /// Returns the timecode in seconds, counting frames at the nominal rate.
+    ///
+    /// `samples` are counted at `sampling_frequency`. Returns `None` for an
+    /// invalid framerate.
+    pub fn seconds(&self, sampling_frequency: u32) -> Option<f64> {
+        let fps = self.framerate.nominal_fps()?;
+        let mut seconds =
+            self.hours as f64 * 3600.0 + self.minutes as f64 * 60.0 + self.seconds as f64;
+        seconds += self.frames as f64 / fps as f64;
+        if sampling_frequency > 0 {
+            seconds += self.samples as f64 / sampling_frequency as f64;
+        }
+        Some(seconds)
+    }
+
+    /// Returns the number of frames since 00:00:00:00.
+    ///
+    /// Drop frame timecode at 29.97 and 59.94 fps skips two or four frame
+    /// numbers at the start of every minute except each tenth. Returns `None`
+    /// for an invalid framerate.
+    pub fn frame_number(&self) -> Option<u64> {
+        let fps = self.framerate.nominal_fps()? as u64;
+        let minutes = self.hours as u64 * 60 + self.minutes as u64;
+        let mut frames = (minutes * 60 + self.seconds as u64) * fps + self.frames as u64;
+
+        if self.dropframe && matches!(self.framerate, Framerate::R29_97 | Framerate::R59_94) {
+            frames = frames.saturating_sub(fps / 15 * (minutes - minutes / 10));
+        }
+
+        Some(frames)
+    }
+
+    /// Returns the position in samples since 00:00:00:00.
+    ///
+    /// Unlike [`Timestamp::seconds`], frames are counted at the actual frame
+    /// rate, so positions of timestamps in one stream can be compared with
+    /// the number of samples between them. Returns `None` for an invalid
+    /// framerate.
+    pub fn sample_position(&self, sampling_frequency: u32) -> Option<u64> {
+        let (num, den) = self.framerate.frame_rate()?;
+        let frame_samples =
+            self.frame_number()? * sampling_frequency as u64 * den as u64 / num as u64;
+        Some(frame_samples + self.samples as u64)
+    }
+
+    /// Returns the duration of one frame in samples, rounded down.
+    pub fn frame_samples(&self, sampling_frequency: u32) -> Option<u64> {
+        let (num, den) = self.framerate.frame_rate()?;
+        Some(sampling_frequency as u64 * den as u64 / num as u64)
+    }
+
     pub fn parse_bcd16(value: u16) -> Result<u16> {
         let a = value >> 12;
         let b = (value >> 8) & 0xF;
@@ -135,6 +198,31 @@ impl From<u8> for Framerate {
     }
 }
 
+impl Framerate {
+    /// Returns the number of frames per timecode second.
+    pub fn nominal_fps(&self) -> Option<u32> {
+        match self {
+            Framerate::R23_976 | Framerate::R24 => Some(24),
+            Framerate::R25 => Some(25),
+            Framerate::R29_97 | Framerate::R30 => Some(30),
+            Framerate::R50 => Some(50),
+            Framerate::R59_94 | Framerate::R60 => Some(60),
+            Framerate::Invalid(_) => None,
+        }
+    }
+
+    /// Returns the actual frame rate as a numerator and denominator.
+    pub fn frame_rate(&self) -> Option<(u32, u32)> {
+        match self {
+            Framerate::R23_976 => Some((24000, 1001)),
+            Framerate::R29_97 => Some((30000, 1001)),
+            Framerate::R59_94 => Some((60000, 1001)),
+            Framerate::Invalid(_) => None,
+            _ => Some((self.nominal_fps()?, 1)),
+        }
+    }
+}
+
 impl Display for Framerate {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let fps = match &self {
@@ -168,3 +256,36 @@ fn print_timestamp() {
     };
     assert_eq!(format!("{timestamp}"), "02:34:56:23 @ 23.976 fps");
 }
+
+#[test]
+fn timestamp_seconds() -> Result<()> {
+    let timestamp = Timestamp::from_bytes(&crate::process::EXAMPLE_DATA[..16])?;
+    assert!(matches!(timestamp.framerate, Framerate::R29_97));
+    assert!(timestamp.dropframe);
+
+    let seconds = timestamp.seconds(48000).unwrap();
+    assert!((seconds - (3600.0 + 23.0 * 60.0 + 45.0 + 16.0 / 30.0 + 25.0 / 48000.0)).abs() < 1e-9);
+    Ok(())
+}
+
+#[test]
+fn timestamp_sample_position() -> Result<()> {
+    let mut timestamp = Timestamp::from_bytes(&crate::process::EXAMPLE_DATA[..16])?;
+
+    // 01:23:45:16 DF skips 2 frame numbers in 83 - 8 minutes
+    assert_eq!(timestamp.frame_number(), Some(5025 * 30 + 16 - 150));
+    assert_eq!(
+        timestamp.sample_position(48000),
+        Some((5025 * 30 + 16 - 150) * 48000 * 1001 / 30000 + 25)
+    );
+
+    // Ten minutes of drop frame timecode are 17982 frames, 0.6 ms short of ten minutes
+    timestamp.hours = 0;
+    timestamp.minutes = 10;
+    timestamp.seconds = 0;
+    timestamp.frames = 0;
+    timestamp.samples = 0;
+    assert_eq!(timestamp.sample_position(48000), Some(28_799_971));
+    assert_eq!(timestamp.frame_samples(48000), Some(1601));
+    Ok(())
+}
@@ -1,6 +1,6 @@
 //! Bitstream I/O utilities for audio parsing.
 //!
-//! Provides bitstream reading, Huffman decoding, CRC validation,
+//! Provides bitstream reading and writing, Huffman coding, CRC validation,
 //! and specialized bit manipulation functions for format parsing.
 
 use std::io;
@@ -10,6 +10,7 @@ use bitstream_io::{
     BigEndian, BitRead, BitReader, SignedInteger, UnsignedInteger, define_huffman_tree,
 };
 
+use crate::process::parse::ParserState;
 use crate::utils::crc::{Crc8, Crc16, crc8, crc16};
 
 const STACK_BUF_SIZE: usize = 256;
@@ -167,7 +168,13 @@ where
             value += read;
             b_read_more = self.get()?;
             if b_read_more {
-                value = (value + 1) << n;
+                let Some(next) = value.checked_add(1).filter(|v| v.leading_zeros() >= n) else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "get_variable_bits_max: value overflows 32 bits",
+                    ));
+                };
+                value = next << n;
                 num_group += 1;
             }
         }
@@ -198,7 +205,14 @@ where
     pub fn parity_check_for_last_n_bits(&mut self, len: u64) -> io::Result<u8> {
         let position = self.position()?;
 
-        self.seek(-(len as i64))?;
+        let Ok(offset) = i64::try_from(len) else {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "parity_check_for_last_n_bits: out of bounds bits",
+            ));
+        };
+
+        self.seek(-offset)?;
 
         let bytes_len = (len >> 3) as usize;
 
@@ -232,7 +246,7 @@ where
     pub fn crc8_check(&mut self, crc: &Crc8, start: u64, len: u64) -> io::Result<u8> {
         let position = self.position()?;
 
-        if start + len > self.len {
+        if start.checked_add(len).is_none_or(|end| end > self.len) || len < start & 7 {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
                 "crc8_check: out of bounds bits",
@@ -278,10 +292,10 @@ where
     pub fn crc16_check(&mut self, crc: &Crc16, start: u64, len: u64) -> io::Result<u16> {
         let position = self.position()?;
 
-        if start + len > self.len {
+        if start.checked_add(len).is_none_or(|end| end > self.len) || len < start & 7 {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
-                "crc8_check: out of bounds bits",
+                "crc16_check: out of bounds bits",
             ));
         }
 
@@ -334,7 +348,9 @@ where
 
     #[inline(always)]
     pub fn available(&mut self) -> io::Result<u64> {
-        self.bs.position_in_bits().map(|pos| self.len - pos)
+        self.bs
+            .position_in_bits()
+            .map(|pos| self.len.saturating_sub(pos))
     }
 
     #[inline(always)]
@@ -357,6 +373,28 @@ where
         }
     }
 
+    /// Reads the next `n` bits into bytes, padding the last byte with zeros.
+    pub fn get_bytes(&mut self, n: u64) -> io::Result<Vec<u8>> {
+        if n > self.available()? {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "get_bytes: out of bounds bits",
+            ));
+        }
+
+        let mut bytes = Vec::with_capacity(n.div_ceil(8) as usize);
+        for _ in 0..n >> 3 {
+            bytes.push(self.get_n::<u8>(8)?);
+        }
+
+        let remainder = (n & 7) as u32;
+        if remainder > 0 {
+            bytes.push(self.get_n::<u8>(remainder)? << (8 - remainder));
+        }
+
+        Ok(bytes)
+    }
+
     #[inline(always)]
     pub fn position(&mut self) -> io::Result<u64> {
         self.bs.position_in_bits()
@@ -377,3 +415,163 @@ impl Default for BsIoSliceReader<'_> {
         Self::from_slice(&[])
     }
 }
+
+/// Bit writer producing the bitstream read by [`BitstreamIoReader`].
+///
+/// Keeps the parser state of the access units written so far, which decides
+/// the fields present in the next one just like it does when reading.
+#[derive(Debug, Default)]
+pub struct BitstreamWriter {
+    buf: Vec<u8>,
+    len: u64,
+    pub(crate) state: ParserState,
+}
+
+impl BitstreamWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline(always)]
+    pub fn put(&mut self, bit: bool) {
+        if self.len & 7 == 0 {
+            self.buf.push(0);
+        }
+
+        if bit {
+            *self.buf.last_mut().unwrap() |= 0x80 >> (self.len & 7);
+        }
+
+        self.len += 1;
+    }
+
+    #[inline(always)]
+    pub fn put_n<I: Into<u64>>(&mut self, value: I, n: u32) {
+        let value = value.into();
+
+        // Bits above the width of the value are zero
+        for i in (0..n).rev() {
+            self.put(i < u64::BITS && (value >> i) & 1 != 0);
+        }
+    }
+
+    #[inline(always)]
+    pub fn put_s<S: Into<i64>>(&mut self, value: S, n: u32) {
+        self.put_n(value.into() as u64, n);
+    }
+
+    /// Writes the shortest code of `value` in the tree of `huff_type`.
+    pub fn put_huffman(&mut self, huff_type: usize, value: i32) -> io::Result<()> {
+        if !(1..=3).contains(&huff_type) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "put_huffman: unsupported huff_type",
+            ));
+        }
+
+        // Codes 1x.. hold the smallest values, 00.. the negative and 01.. the
+        // larger positive ones, one more leading zero per step
+        let lsb_bits = 3 - huff_type as u32;
+        let positive_base = 1 << lsb_bits;
+
+        let (prefix, steps) = match value {
+            0.. if value < positive_base => {
+                self.put(true);
+                self.put_n(value as u32, lsb_bits);
+                return Ok(());
+            }
+            -7..=-1 => (false, -value - 1),
+            _ if (positive_base..=positive_base + 6).contains(&value) => {
+                (true, value - positive_base)
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("put_huffman: {value} out of range for huff_type {huff_type}"),
+                ));
+            }
+        };
+
+        self.put(false);
+        self.put(prefix);
+        self.put_n(0u8, steps as u32);
+        if steps < 6 {
+            self.put(true);
+        } else {
+            self.put(false);
+        }
+
+        Ok(())
+    }
+
+    /// Writes `value` in groups of `n` bits read back by
+    /// [`BitstreamIoReader::get_variable_bits_max`].
+    pub fn put_variable_bits(&mut self, value: u32, n: u32) {
+        let mut groups = vec![value & ((1 << n) - 1)];
+        let mut value = value >> n;
+
+        while value != 0 {
+            value -= 1;
+            groups.push(value & ((1 << n) - 1));
+            value >>= n;
+        }
+
+        for (i, group) in groups.iter().enumerate().rev() {
+            self.put_n(*group, n);
+            self.put(i != 0);
+        }
+    }
+
+    /// Overwrites `n` bits at `position` with `value`.
+    pub fn put_n_at<I: Into<u64>>(&mut self, position: u64, value: I, n: u32) {
+        let value = value.into();
+
+        for i in 0..n as u64 {
+            let pos = position + i;
+            let mask = 0x80 >> (pos & 7);
+            let byte = &mut self.buf[(pos >> 3) as usize];
+
+            if (value >> (n as u64 - 1 - i)) & 1 != 0 {
+                *byte |= mask;
+            } else {
+                *byte &= !mask;
+            }
+        }
+    }
+
+    pub fn align_16bit(&mut self) {
+        while self.len & 7 != 0 {
+            self.put(false);
+        }
+
+        if self.len & 15 > 0 {
+            self.put_n(0u8, 8);
+        }
+    }
+
+    /// Byte parity of `len` bits written from the byte-aligned `start`.
+    pub fn parity(&self, start: u64, len: u64) -> u8 {
+        self.buf[(start >> 3) as usize..((start + len) >> 3) as usize]
+            .iter()
+            .fold(0, |acc, x| acc ^ x)
+    }
+
+    pub fn crc8(&self, crc: &Crc8, start: u64, len: u64) -> io::Result<u8> {
+        BsIoSliceReader::from_slice(&self.buf).crc8_check(crc, start, len)
+    }
+
+    pub fn crc16(&self, crc: &Crc16, start: u64, len: u64) -> io::Result<u16> {
+        BsIoSliceReader::from_slice(&self.buf).crc16_check(crc, start, len)
+    }
+
+    #[inline(always)]
+    pub fn position(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns the bytes written so far and starts over with an empty buffer.
+    pub fn take_bytes(&mut self) -> Vec<u8> {
+        self.len = 0;
+        std::mem::take(&mut self.buf)
+    }
+}
@@ -1,5 +1,7 @@
 use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, Weak};
 
 /// A thread-safe buffer pool for zero-copy frame processing.
 ///
@@ -50,3 +52,159 @@ impl Default for BufferPool {
         Self::new(16, 64 * 1024)
     }
 }
+
+/// PCM samples of one access unit organized as `[sample_index][channel_index]`.
+pub type PcmBlock = [[i32; 16]; 160];
+
+#[derive(Debug)]
+struct PcmPoolInner {
+    blocks: Mutex<Vec<Box<PcmBlock>>>,
+    max_size: usize,
+}
+
+/// A thread-safe pool of PCM blocks.
+///
+/// Blocks are handed out as [`PooledPcm`] handles that return them to the
+/// pool when dropped, so decoded access units can be queued without
+/// allocating a block per access unit.
+#[derive(Debug, Clone)]
+pub struct PcmPool {
+    inner: Arc<PcmPoolInner>,
+}
+
+impl PcmPool {
+    /// Creates a new PCM pool keeping up to `max_size` released blocks.
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            inner: Arc::new(PcmPoolInner {
+                blocks: Mutex::new(Vec::with_capacity(max_size)),
+                max_size,
+            }),
+        }
+    }
+
+    /// Acquires a block from the pool or allocates a new one if none available.
+    ///
+    /// The contents of a recycled block are unspecified.
+    pub fn acquire(&self) -> PooledPcm {
+        let block = self
+            .inner
+            .blocks
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| Box::new([[0; 16]; 160]));
+
+        PooledPcm {
+            block: Some(block),
+            pool: Some(Arc::downgrade(&self.inner)),
+        }
+    }
+
+    /// Returns the number of blocks available for reuse.
+    pub fn available(&self) -> usize {
+        self.inner.blocks.lock().unwrap().len()
+    }
+}
+
+impl Default for PcmPool {
+    fn default() -> Self {
+        Self::new(64)
+    }
+}
+
+/// A PCM block borrowed from a [`PcmPool`].
+///
+/// Dereferences to a [`PcmBlock`] and returns it to its pool when dropped.
+/// Blocks created with [`PooledPcm::default`] or from an array belong to no
+/// pool and are freed instead.
+pub struct PooledPcm {
+    /// Only taken when dropped
+    block: Option<Box<PcmBlock>>,
+    pool: Option<Weak<PcmPoolInner>>,
+}
+
+impl Default for PooledPcm {
+    fn default() -> Self {
+        Self::from([[0; 16]; 160])
+    }
+}
+
+impl From<PcmBlock> for PooledPcm {
+    fn from(block: PcmBlock) -> Self {
+        Self {
+            block: Some(Box::new(block)),
+            pool: None,
+        }
+    }
+}
+
+impl Deref for PooledPcm {
+    type Target = PcmBlock;
+
+    fn deref(&self) -> &PcmBlock {
+        self.block.as_deref().expect("PCM block taken before drop")
+    }
+}
+
+impl DerefMut for PooledPcm {
+    fn deref_mut(&mut self) -> &mut PcmBlock {
+        self.block
+            .as_deref_mut()
+            .expect("PCM block taken before drop")
+    }
+}
+
+impl PartialEq for PooledPcm {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl fmt::Debug for PooledPcm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledPcm")
+            .field("pooled", &self.pool.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for PooledPcm {
+    fn drop(&mut self) {
+        let (Some(block), Some(pool)) = (
+            self.block.take(),
+            self.pool.as_ref().and_then(Weak::upgrade),
+        ) else {
+            return;
+        };
+
+        let mut blocks = pool.blocks.lock().unwrap();
+        if blocks.len() < pool.max_size {
+            blocks.push(block);
+        }
+    }
+}
+
+#[test]
+fn pcm_blocks_are_recycled() {
+    let pool = PcmPool::new(1);
+
+    let mut first = pool.acquire();
+    first[0][0] = 1;
+    let second = pool.acquire();
+    assert_eq!(pool.available(), 0);
+
+    // Only one released block is kept
+    drop(first);
+    drop(second);
+    assert_eq!(pool.available(), 1);
+
+    let recycled = pool.acquire();
+    assert_eq!(recycled[0][0], 1);
+    assert_eq!(pool.available(), 0);
+
+    // Blocks outliving their pool are freed
+    drop(pool);
+    drop(recycled);
+    assert_eq!(PooledPcm::default(), PooledPcm::from([[0; 16]; 160]));
+}
@@ -1,7 +1,8 @@
 //! Dithering utilities for audio processing.
 //!
 //! Provides dither generation functions and lookup tables used in
-//! lossless matrix operations for noise shaping.
+//! lossless matrix operations for noise shaping, and a TPDF dither for
+//! requantizing decoded samples to a lower bit depth.
 
 /// Dither lookup table for format processing.
 #[rustfmt::skip]
@@ -41,3 +42,75 @@ pub fn dither_31eb(samples_per_au: usize, dither_seed: &mut u32) -> Vec<i32> {
 
     dither_table
 }
+
+/// Triangular probability density function (TPDF) dither.
+///
+/// Requantizes samples to fewer bits by adding the sum of two independent
+/// uniform random values of one target LSB each before rounding, which
+/// decorrelates the quantization error from the signal.
+#[derive(Debug, Clone)]
+pub struct TpdfDither {
+    state: u32,
+}
+
+impl Default for TpdfDither {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl TpdfDither {
+    /// Creates a dither generator from `seed`; a zero seed is replaced by one.
+    pub fn new(seed: u32) -> Self {
+        Self { state: seed.max(1) }
+    }
+
+    fn next_random(&mut self) -> u32 {
+        // xorshift32
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+
+    /// Requantizes a `bits` bit sample by dropping its `shift` least
+    /// significant bits.
+    ///
+    /// The result is clamped to the range of the remaining `bits - shift` bits.
+    pub fn requantize(&mut self, sample: i32, bits: u32, shift: u32) -> i32 {
+        if shift == 0 {
+            return sample;
+        }
+
+        let mask = (1i64 << shift) - 1;
+        let noise = (self.next_random() as i64 & mask) + (self.next_random() as i64 & mask) - mask;
+        let value = (sample as i64 + noise + (1 << (shift - 1))) >> shift;
+
+        let limit = 1i64 << (bits - shift - 1);
+        value.clamp(-limit, limit - 1) as i32
+    }
+}
+
+#[test]
+fn tpdf_requantize() {
+    let mut dither = TpdfDither::default();
+
+    // Exact multiples of the target LSB stay within one LSB of their value
+    let mut sum = 0i64;
+    for _ in 0..4096 {
+        let value = dither.requantize(0x1200, 24, 8);
+        assert!((0x11..=0x13).contains(&value));
+        sum += value as i64;
+    }
+    assert_eq!((sum as f64 / 4096.0).round(), 18.0);
+
+    // A sample between two steps averages to its exact value
+    let sum: i64 = (0..65536)
+        .map(|_| dither.requantize(0x1240, 24, 8) as i64)
+        .sum();
+    assert!((sum as f64 / 65536.0 - 18.25).abs() < 0.01);
+
+    assert_eq!(dither.requantize(0x7FFFFF, 24, 8), 0x7FFF);
+    assert_eq!(dither.requantize(-0x800000, 24, 8), -0x8000);
+    assert_eq!(dither.requantize(-5, 24, 0), -5);
+}
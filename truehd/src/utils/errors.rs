@@ -1,20 +1,120 @@
+/// Logs `$err` at `$level`, or returns it as an error.
+///
+/// The [`Policy`] set for `$category` in `$state.category_policy` decides;
+/// without one, errors at or above `$state.fail_level` are returned.
+/// The problem is also passed to `$state.event_sink` as an
+/// [`Event`](crate::utils::events::Event) when one is installed.
 #[macro_export]
 macro_rules! log_or_err {
-    ($state:expr, $level:expr, $err:expr $(,)?) => {{
-        if $level <= $state.fail_level {
-            return Err($err);
-        } else {
-            match $level {
-                ::log::Level::Error => ::log::error!("{}", $err),
-                ::log::Level::Warn => ::log::warn!("{}", $err),
-                ::log::Level::Info => ::log::info!("{}", $err),
-                ::log::Level::Debug => ::log::debug!("{}", $err),
-                ::log::Level::Trace => ::log::trace!("{}", $err),
-            }
+    ($state:expr, $category:expr, $level:expr, $err:expr $(,)?) => {{
+        let err = $err;
+        if let Some(sink) = &$state.event_sink {
+            sink.emit($crate::utils::events::Event::from_problem(
+                $category, $level, &err,
+            ));
+        }
+        match $state
+            .category_policy
+            .resolve($category, $level, $state.fail_level)
+        {
+            $crate::utils::errors::Policy::Fail => return Err(err),
+            $crate::utils::errors::Policy::Warn => match $level {
+                ::log::Level::Error => ::log::error!("{}", err),
+                ::log::Level::Warn => ::log::warn!("{}", err),
+                ::log::Level::Info => ::log::info!("{}", err),
+                ::log::Level::Debug => ::log::debug!("{}", err),
+                ::log::Level::Trace => ::log::trace!("{}", err),
+            },
+            $crate::utils::errors::Policy::Ignore => ::log::debug!("{}", err),
         }
     }};
 }
 
+/// Category of the problems reported with [`log_or_err!`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Category {
+    /// Input and output timing, latency and seamless branches
+    Timing,
+    /// Peak and fixed data rates
+    DataRate,
+    /// Parity and CRC checks
+    Crc,
+    /// Major sync fields and their consistency between major syncs
+    Sync,
+    /// Substream directory, segment and block structure
+    Structure,
+    /// Huffman coded residuals
+    Huffman,
+    /// Filter parameters
+    Filter,
+    /// Matrix parameters
+    Matrixing,
+    /// Lossless checks of the decoded output
+    Lossless,
+    /// EVO frames, extra data and Atmos metadata
+    Metadata,
+    /// Output files written by an application
+    Output,
+}
+
+impl Category {
+    pub const ALL: [Category; 11] = [
+        Category::Timing,
+        Category::DataRate,
+        Category::Crc,
+        Category::Sync,
+        Category::Structure,
+        Category::Huffman,
+        Category::Filter,
+        Category::Matrixing,
+        Category::Lossless,
+        Category::Metadata,
+        Category::Output,
+    ];
+}
+
+/// Handling of the problems of a [`Category`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Log at debug level only
+    Ignore,
+    /// Log at the level of the problem and continue
+    Warn,
+    /// Return the problem as an error
+    Fail,
+}
+
+/// Per-category [`Policy`] overriding the fail level.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CategoryPolicy {
+    policies: [Option<Policy>; Category::ALL.len()],
+}
+
+impl CategoryPolicy {
+    /// Sets the policy of `category`.
+    pub fn set(&mut self, category: Category, policy: Policy) {
+        self.policies[category as usize] = Some(policy);
+    }
+
+    /// Returns the policy set for `category`, if any.
+    pub fn get(&self, category: Category) -> Option<Policy> {
+        self.policies[category as usize]
+    }
+
+    /// Returns the handling of a problem of `category` logged at `level`.
+    ///
+    /// Without a policy for the category, problems at or above `fail_level`
+    /// fail and the others are logged.
+    pub fn resolve(&self, category: Category, level: log::Level, fail_level: log::Level) -> Policy {
+        self.get(category).unwrap_or(if level <= fail_level {
+            Policy::Fail
+        } else {
+            Policy::Warn
+        })
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum DecodeError {
     #[error("Positive saturation from the recorrelator: value = {0}")]
@@ -31,6 +131,52 @@ pub enum DecodeError {
 
     #[error("Invalid presentation index: {0}")]
     InvalidPresentation(usize),
+
+    #[error("Access unit precedes the first major sync")]
+    MissingMajorSync,
+
+    #[error(
+        "Substream mask {mask:#06b} must cover the substreams {required:#06b} of presentation {presentation} within {available:#06b}"
+    )]
+    InvalidSubstreamMask {
+        mask: u8,
+        presentation: usize,
+        required: u8,
+        available: u8,
+    },
+
+    #[error("Access unit {0} duplicates the previous access unit")]
+    DuplicateAccessUnit(u64),
+
+    #[error("AU {au}: skipped OAMD payload of {length} bytes: {reason}")]
+    OamdPayloadSkipped {
+        au: usize,
+        length: usize,
+        reason: String,
+    },
+}
+
+/// Check of an access unit that failed during extraction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum FrameCheck {
+    /// Check nibble over the access unit header and substream directory
+    Parity,
+    /// Access unit length too short for the substream directory
+    Length,
+    /// Major sync info CRC
+    Crc,
+}
+
+impl std::fmt::Display for FrameCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FrameCheck::Parity => "parity",
+            FrameCheck::Length => "length",
+            FrameCheck::Crc => "CRC",
+        })
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -46,6 +192,18 @@ pub enum ExtractError {
 
     #[error("Invalid sync pattern detected")]
     InvalidSyncPattern,
+
+    #[error(
+        "Resynchronized after skipping {skipped_bytes} bytes from byte offset {offset} ({offset:#X}), where the {check} check failed"
+    )]
+    Resynchronized {
+        offset: u64,
+        skipped_bytes: u64,
+        check: FrameCheck,
+    },
+
+    #[error("Input ended with {bytes} bytes of an incomplete access unit")]
+    PartialAccessUnit { bytes: usize },
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -55,6 +213,57 @@ pub enum ParseError {
 
     #[error("Invalid substream context index ({0} > {1})")]
     InvalidSubstreamIndex(usize, usize),
+
+    #[error(
+        "Invalid substream directory: substream_end_ptr {end_ptr:#03X} of substream {substream} does not fit access_unit_length {au_length:#03X}"
+    )]
+    SubstreamDirectoryInvalid {
+        substream: usize,
+        end_ptr: u16,
+        au_length: u16,
+    },
+
+    #[error(
+        "Presentations require {advertised} substreams but the access unit has {present}, using the presentations of the present substreams"
+    )]
+    MissingSubstream { advertised: usize, present: usize },
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ParseWarning {
+    #[error(
+        "Timestamp drift at access unit {au}: expected sample position {expected}, timestamp at {actual}"
+    )]
+    TimestampDrift {
+        au: usize,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum OamdWarning {
+    #[error(
+        "Truncated oa_element_md with id {id}: oa_element_size = {size} bits, only {available} bits remain"
+    )]
+    TruncatedElement { id: u8, size: u64, available: u64 },
+
+    #[error(
+        "oa_element_md with id {id} overruns its oa_element_size of {size} bits, element skipped"
+    )]
+    ElementOverrun { id: u8, size: u64 },
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum OamdError {
+    #[error("Unsupported OAMD version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Reserved sample_offset_code 3 in md_update_info")]
+    ReservedSampleOffsetCode,
+
+    #[error("Reserved intermediate_spatial_format_idx {0}")]
+    ReservedIsfIndex(u8),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -103,6 +312,12 @@ pub enum AccessUnitError {
 
     #[error("Stream is flagged as fixed rate, but variable rate detected: {0} != {1}")]
     FixedRateMismatch(usize, usize),
+
+    #[error("Substream {0} segment was not parsed and cannot be written")]
+    UnparsedSubstream(usize),
+
+    #[error("access_unit_length of {0} words exceeds 12 bits")]
+    AccessUnitLengthOverflow(u64),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -160,6 +375,23 @@ pub enum BlockError {
 
     #[error("block_data bit count mismatch: expected {expected}, got {actual}")]
     BlockDataBitCountMismatch { expected: u16, actual: u64 },
+
+    #[error(
+        "Block header CRC mismatch in AU {au}, substream {substream}: calculated {calculated:#04X}, read {read:#04X}"
+    )]
+    BlockHeaderCrcMismatch {
+        au: usize,
+        substream: usize,
+        calculated: u8,
+        read: u8,
+    },
+
+    #[error("Sample {value} of channel {channel} cannot be coded with huff_type {huff_type}")]
+    UncodableSample {
+        channel: usize,
+        value: i32,
+        huff_type: usize,
+    },
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -172,6 +404,9 @@ pub enum ChannelError {
 
     #[error("huff_lsbs[{chan}] must be ≤ {max}, got {actual}")]
     HuffLsbsTooLarge { chan: usize, max: u32, actual: u32 },
+
+    #[error("extra_channel_meaning_length {0} is too short for the 16-channel meaning")]
+    ExtraChannelMeaningTooShort(u8),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -207,6 +442,9 @@ pub enum ExtraDataError {
         "extra_data_parity check failed on evolution payload. Expected {expected:#X}, Read {actual:#X}"
     )]
     ExtraDataParityMismatch { expected: u8, actual: u8 },
+
+    #[error("EXTRA_DATA without an evo_frame() cannot be written")]
+    MissingEvoFrame,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -277,6 +515,11 @@ pub enum RestartHeaderError {
     #[error("Invalid seamless branch")]
     InvalidSeamlessBranch,
 
+    #[error(
+        "Zero input timing interval at timing jump (advance {advance}, previous advance {prev_advance})"
+    )]
+    ZeroInputTimingInterval { advance: usize, prev_advance: usize },
+
     #[error("Substream 1 must use sync word 0x31EB unless it is last in 6ch presentation")]
     InvalidSyncBForSubstream1,
 
@@ -286,6 +529,12 @@ pub enum RestartHeaderError {
     #[error("Sync word 0x31EC only allowed for substream 3. Got {0}")]
     InvalidSyncC(u16),
 
+    #[error("FBB streams must use sync word 0x31EA. Got {0:#04X}")]
+    InvalidSyncFbb(u16),
+
+    #[error("restart_sync_word must be 0x31EA, 0x31EB or 0x31EC. Read {0:#04X}")]
+    InvalidRestartSyncWord(u16),
+
     #[error(
         "Second occurrence of max_bits does not match first. First: {first:#02X}, Second: {second:#02X}"
     )]
@@ -361,6 +610,9 @@ pub enum SubstreamError {
         calculated: u8,
         read: u8,
     },
+
+    #[error("substream_end_ptr for substream {substream} exceeds 12 bits: {end_ptr:#X}")]
+    EndPtrOverflow { substream: usize, end_ptr: u64 },
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -371,6 +623,21 @@ pub enum SyncError {
     #[error("Invalid format_info: audio_sampling_frequency_{index}. Read {value:#01X}")]
     InvalidAudioSamplingFreq { index: u8, value: u8 },
 
+    #[error("Invalid format_info: quantization_word_length_{index}. Read {value:#01X}")]
+    InvalidQuantizationWordLength { index: u8, value: u8 },
+
+    #[error("FBB streams have at most 2 substreams. Read {0}")]
+    TooManyFbbSubstreams(usize),
+
+    #[error("Invalid format_info: FBB channel_assignment {0:#02X} is reserved")]
+    InvalidFbbChannelAssignment(u8),
+
+    #[error("Invalid format_info: 6ch_decoder_channel_assignment {0:#02X} is reserved")]
+    InvalidSixchChannelAssignment(u8),
+
+    #[error("Invalid format_info: 8ch_decoder_channel_assignment {0:#03X} is reserved")]
+    InvalidEightchChannelAssignment(u16),
+
     #[error("Invalid signature in major_sync_info. Read {0:#04X}, expected 0xB752")]
     InvalidMajorSyncSignature(u16),
 
@@ -441,6 +708,18 @@ pub enum SyncError {
     SubstreamCountInfoInconsistent,
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum PipelineError {
+    #[error("Cannot {target}: no major sync found before sample {samples}")]
+    SkipTargetMissed { target: &'static str, samples: u64 },
+
+    #[error("Cannot resume: substream_info changed before the resume point")]
+    SubstreamInfoChangedBeforeResume,
+
+    #[error("Stopped after {errors} parse or decode errors, exceeding the maximum of {max_errors}")]
+    MaxErrorsExceeded { errors: u64, max_errors: u64 },
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum TimestampError {
     #[error("Invalid Timestamp sync bytes")]
@@ -449,3 +728,29 @@ pub enum TimestampError {
     #[error("parse_bcd16: Invalid BCD digit")]
     InvalidBcdDigit,
 }
+
+#[test]
+fn category_policy_resolve() {
+    use log::Level::{Error, Warn};
+
+    // Without policies, the fail level decides as in strict and non-strict mode
+    let mut policy = CategoryPolicy::default();
+    for category in Category::ALL {
+        assert_eq!(policy.resolve(category, Error, Error), Policy::Fail);
+        assert_eq!(policy.resolve(category, Warn, Error), Policy::Warn);
+        assert_eq!(policy.resolve(category, Warn, Warn), Policy::Fail);
+        assert_eq!(
+            policy.resolve(category, log::Level::Debug, Warn),
+            Policy::Warn
+        );
+    }
+
+    policy.set(Category::Timing, Policy::Fail);
+    policy.set(Category::Crc, Policy::Ignore);
+    policy.set(Category::Metadata, Policy::Warn);
+    assert_eq!(policy.resolve(Category::Timing, Warn, Error), Policy::Fail);
+    assert_eq!(policy.resolve(Category::Crc, Error, Warn), Policy::Ignore);
+    assert_eq!(policy.resolve(Category::Metadata, Warn, Warn), Policy::Warn);
+    assert_eq!(policy.resolve(Category::Sync, Warn, Warn), Policy::Fail);
+    assert_eq!(policy.get(Category::Huffman), None);
+}
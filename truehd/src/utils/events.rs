@@ -0,0 +1,130 @@
+//! Typed events reported alongside log messages.
+//!
+//! Provides the [`Event`] enum delivered to the sink installed with
+//! [`Parser::set_event_sink`](crate::process::parse::Parser::set_event_sink)
+//! or [`Decoder::set_event_sink`](crate::process::decode::Decoder::set_event_sink).
+
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::utils::errors::{BlockError, Category, DecodeError, RestartHeaderError};
+
+/// Structured form of a notable condition reported while processing a stream.
+///
+/// Every problem reported with [`log_or_err!`](crate::log_or_err) produces an
+/// event whether it is logged, ignored or returned as an error. Problems
+/// without a dedicated variant are reported as [`Event::Problem`].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Event {
+    /// A timing jump passed the seamless branch checks
+    SeamlessBranch {
+        au: usize,
+        latency_before: usize,
+        latency_after: usize,
+    },
+    /// Timing and FIFO tracking restarted as a new stream
+    StreamRestart { au: usize },
+    /// The substream configuration changed without changing the channel
+    /// counts of the required presentations
+    FormatChange {
+        au: usize,
+        substream_info: u8,
+        extended_substream_info: u8,
+    },
+    /// An invalid seamless branch was restarted as a new stream, inserting
+    /// (positive) or dropping (negative) `samples`
+    BranchRepaired { au: usize, samples: isize },
+    /// The access unit decodes to the same samples as the previous one
+    DuplicateAccessUnit { au: usize },
+    /// FIFO latency changed although bit 15 of flags is set
+    LatencyInconsistent { substream: usize },
+    /// The lossless check of a restart header did not match the decoded output
+    LosslessCheckMismatch {
+        substream: usize,
+        calculated: i32,
+        read: u8,
+    },
+    /// An OAMD payload of `length` bytes failed to parse and was skipped,
+    /// decoding the audio of the access unit
+    OamdPayloadSkipped { au: usize, length: usize },
+    /// Any other problem reported with [`log_or_err!`](crate::log_or_err)
+    Problem {
+        category: Category,
+        level: log::Level,
+        message: String,
+    },
+}
+
+impl Event {
+    /// Returns the event of a problem reported with
+    /// [`log_or_err!`](crate::log_or_err).
+    pub fn from_problem<E: fmt::Display + 'static>(
+        category: Category,
+        level: log::Level,
+        err: &E,
+    ) -> Self {
+        if let Some(err) = (err as &dyn Any).downcast_ref::<anyhow::Error>() {
+            if let Some(&BlockError::LatencyInconsistent { substream }) = err.downcast_ref() {
+                return Event::LatencyInconsistent { substream };
+            }
+            if let Some(&RestartHeaderError::LosslessCheckMismatch {
+                substream,
+                calculated,
+                read,
+            }) = err.downcast_ref()
+            {
+                return Event::LosslessCheckMismatch {
+                    substream,
+                    calculated,
+                    read,
+                };
+            }
+            if let Some(&DecodeError::OamdPayloadSkipped { au, length, .. }) = err.downcast_ref() {
+                return Event::OamdPayloadSkipped { au, length };
+            }
+        }
+
+        Event::Problem {
+            category,
+            level,
+            message: err.to_string(),
+        }
+    }
+
+    /// Returns the level the event is logged at.
+    pub fn level(&self) -> log::Level {
+        match self {
+            Event::SeamlessBranch { .. }
+            | Event::StreamRestart { .. }
+            | Event::FormatChange { .. }
+            | Event::DuplicateAccessUnit { .. } => log::Level::Info,
+            Event::BranchRepaired { .. }
+            | Event::LatencyInconsistent { .. }
+            | Event::LosslessCheckMismatch { .. }
+            | Event::OamdPayloadSkipped { .. } => log::Level::Warn,
+            Event::Problem { level, .. } => *level,
+        }
+    }
+}
+
+/// Receiver of [`Event`]s shared by a state and its forks.
+#[derive(Clone)]
+pub struct EventSink(Arc<dyn Fn(Event) + Send + Sync>);
+
+impl EventSink {
+    pub fn new(sink: impl Fn(Event) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(sink))
+    }
+
+    pub fn emit(&self, event: Event) {
+        (self.0)(event)
+    }
+}
+
+impl fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventSink")
+    }
+}
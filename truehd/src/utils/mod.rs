@@ -1,11 +1,14 @@
 //! Utility functions and supporting infrastructure.
 //!
 //! Provides bitstream I/O, CRC validation, Huffman decoding, error handling,
-//! buffer management, and other supporting functionality for processing.
+//! typed events, buffer management, and other supporting functionality for
+//! processing.
 
 pub mod bitstream_io;
 pub mod buffer_pool;
 pub mod crc;
 pub mod dither;
 pub mod errors;
+pub mod events;
+pub mod resample;
 pub mod timing;
@@ -0,0 +1,285 @@
+//! Sample rate conversion.
+//!
+//! Provides a polyphase windowed-sinc resampler for rates with an exact
+//! rational ratio, such as the 44.1 kHz family to 48 kHz, and the conversion
+//! of sample positions between the two rates.
+
+/// Stopband attenuation the filter is designed for, in dB.
+pub const STOPBAND_ATTENUATION: f64 = 130.0;
+
+/// Passband edge relative to the lower of the two Nyquist frequencies; the
+/// stopband starts at that Nyquist frequency.
+pub const PASSBAND_EDGE: f64 = 0.9;
+
+/// Polyphase windowed-sinc resampler of interleaved integer samples.
+///
+/// Output sample `m` is the input interpolated at `m * input_rate /
+/// output_rate`, so the output is aligned with the input without delay. The
+/// lowpass filter is a Kaiser windowed sinc with a passband up to
+/// [`PASSBAND_EDGE`] of the lower Nyquist frequency and
+/// [`STOPBAND_ATTENUATION`] from that Nyquist frequency on, with one set of
+/// coefficients per output phase.
+///
+/// Output is produced as soon as the input it depends on has been pushed.
+/// [`flush`](Self::flush) completes the output at the end of the input.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    input_rate: u32,
+    output_rate: u32,
+    channels: usize,
+    bit_depth: u32,
+    /// Input samples advanced per output sample, in units of 1 / `phases`
+    step: u64,
+    phases: u64,
+    /// Input samples on each side of the interpolated position
+    half_taps: usize,
+    /// `2 * half_taps` coefficients of each phase
+    coefficients: Vec<f64>,
+    /// Interleaved input, starting at input sample `history_start`
+    history: Vec<f64>,
+    history_start: i64,
+    input_samples: u64,
+    output_samples: u64,
+}
+
+impl Resampler {
+    /// Creates a resampler of `channels` channels of `bit_depth` bit samples
+    /// from `input_rate` to `output_rate` Hz.
+    pub fn new(input_rate: u32, output_rate: u32, channels: usize, bit_depth: u32) -> Self {
+        let divisor = gcd(input_rate as u64, output_rate as u64);
+        let step = input_rate as u64 / divisor;
+        let phases = output_rate as u64 / divisor;
+
+        // Kaiser's estimate of the filter length at the lower rate
+        let min_rate = input_rate.min(output_rate) as f64;
+        let transition = (1.0 - PASSBAND_EDGE) / 2.0;
+        let length = (STOPBAND_ATTENUATION - 7.95)
+            / (2.285 * 2.0 * std::f64::consts::PI * transition)
+            * input_rate as f64
+            / min_rate;
+        let half_taps = (length / 2.0).ceil() as usize;
+
+        let beta = 0.1102 * (STOPBAND_ATTENUATION - 8.7);
+        // Cutoff centred in the transition band, in cycles per input sample
+        let cutoff = (1.0 + PASSBAND_EDGE) / 4.0 * min_rate / input_rate as f64;
+
+        let mut coefficients = Vec::with_capacity(phases as usize * 2 * half_taps);
+        for phase in 0..phases {
+            let fraction = phase as f64 / phases as f64;
+            let start = coefficients.len();
+            for tap in 0..2 * half_taps {
+                let x = tap as f64 + 1.0 - half_taps as f64 - fraction;
+                coefficients.push(
+                    2.0 * cutoff * sinc(2.0 * cutoff * x) * kaiser(x / half_taps as f64, beta),
+                );
+            }
+            // Unity gain at DC in every phase
+            let sum: f64 = coefficients[start..].iter().sum();
+            for coefficient in &mut coefficients[start..] {
+                *coefficient /= sum;
+            }
+        }
+
+        let mut resampler = Self {
+            input_rate,
+            output_rate,
+            channels,
+            bit_depth,
+            step,
+            phases,
+            half_taps,
+            coefficients,
+            history: Vec::new(),
+            history_start: 0,
+            input_samples: 0,
+            output_samples: 0,
+        };
+        resampler.reset();
+        resampler
+    }
+
+    pub fn input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn bit_depth(&self) -> u32 {
+        self.bit_depth
+    }
+
+    /// Starts a new input, discarding the buffered samples.
+    pub fn reset(&mut self) {
+        // The input is preceded by silence
+        self.history = vec![0.0; (self.half_taps - 1) * self.channels];
+        self.history_start = 1 - self.half_taps as i64;
+        self.input_samples = 0;
+        self.output_samples = 0;
+    }
+
+    /// Pushes interleaved input samples and appends the output samples that
+    /// can be computed to `output`.
+    pub fn process(&mut self, input: &[i32], output: &mut Vec<i32>) {
+        self.history
+            .extend(input.iter().map(|&sample| sample as f64));
+        self.input_samples += (input.len() / self.channels) as u64;
+        self.resample(self.input_samples, u64::MAX, output);
+    }
+
+    /// Completes the output of the input pushed so far, followed by silence,
+    /// and resets the resampler.
+    ///
+    /// The input of `n` samples then results in `ceil(n * output_rate /
+    /// input_rate)` output samples in total.
+    pub fn flush(&mut self, output: &mut Vec<i32>) {
+        let end = (self.input_samples * self.phases).div_ceil(self.step);
+        self.history
+            .resize(self.history.len() + self.half_taps * self.channels, 0.0);
+        self.resample(self.input_samples + self.half_taps as u64, end, output);
+        self.reset();
+    }
+
+    /// Computes the output samples up to `end` depending on the first
+    /// `available` input samples.
+    fn resample(&mut self, available: u64, end: u64, output: &mut Vec<i32>) {
+        let limit = 1i64 << (self.bit_depth - 1);
+        let taps = 2 * self.half_taps;
+        let mut acc = vec![0.0; self.channels];
+
+        while self.output_samples < end {
+            let position = self.output_samples * self.step;
+            let index = position / self.phases;
+            if index + self.half_taps as u64 >= available {
+                break;
+            }
+
+            let phase = (position % self.phases) as usize;
+            let coefficients = &self.coefficients[phase * taps..][..taps];
+            let first = (index as i64 + 1 - self.half_taps as i64 - self.history_start) as usize;
+            let frames = self.history[first * self.channels..].chunks_exact(self.channels);
+
+            acc.fill(0.0);
+            for (&coefficient, frame) in coefficients.iter().zip(frames) {
+                for (acc, &sample) in acc.iter_mut().zip(frame) {
+                    *acc += coefficient * sample;
+                }
+            }
+            output.extend(
+                acc.iter()
+                    .map(|&value| (value.round() as i64).clamp(-limit, limit - 1) as i32),
+            );
+            self.output_samples += 1;
+        }
+
+        // Drop the input before the first sample of the next output
+        let next =
+            (self.output_samples * self.step / self.phases) as i64 + 1 - self.half_taps as i64;
+        if next > self.history_start {
+            let frames =
+                ((next - self.history_start) as usize).min(self.history.len() / self.channels);
+            self.history.drain(..frames * self.channels);
+            self.history_start += frames as i64;
+        }
+    }
+}
+
+/// Converts a sample position at `input_rate` to the nearest position at
+/// `output_rate`.
+pub fn convert_position(samples: u64, input_rate: u32, output_rate: u32) -> u64 {
+    let input_rate = input_rate as u128;
+    ((samples as u128 * output_rate as u128 + input_rate / 2) / input_rate) as u64
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = std::f64::consts::PI * x;
+        x.sin() / x
+    }
+}
+
+/// Kaiser window at `x` in -1..=1
+fn kaiser(x: f64, beta: f64) -> f64 {
+    if x.abs() > 1.0 {
+        return 0.0;
+    }
+    bessel_i0(beta * (1.0 - x * x).sqrt()) / bessel_i0(beta)
+}
+
+/// Modified Bessel function of the first kind of order zero
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    for k in 1.. {
+        term *= (x / (2.0 * k as f64)).powi(2);
+        sum += term;
+        if term < sum * 1e-17 {
+            break;
+        }
+    }
+    sum
+}
+
+#[test]
+fn resample_sine() {
+    let amplitude = (1 << 22) as f64;
+    let sine = |rate: u32, index: usize| {
+        let t = index as f64 / rate as f64;
+        amplitude * (2.0 * std::f64::consts::PI * 1000.0 * t).sin()
+    };
+
+    for (input_rate, channels) in [(44100, 2), (88200, 1), (176400, 1)] {
+        let input = (0..input_rate as usize / 10)
+            .flat_map(|index| vec![sine(input_rate, index).round() as i32; channels])
+            .collect::<Vec<_>>();
+
+        // Output is the same in whichever chunks the input is pushed
+        let mut resampler = Resampler::new(input_rate, 48000, channels, 24);
+        let mut output = Vec::new();
+        for chunk in input.chunks(40 * channels) {
+            resampler.process(chunk, &mut output);
+        }
+        resampler.flush(&mut output);
+        assert_eq!(output.len(), 4800 * channels);
+
+        let mut whole = Vec::new();
+        resampler.process(&input, &mut whole);
+        resampler.flush(&mut whole);
+        assert_eq!(whole, output);
+
+        // Away from the edges the output is the sine sampled at 48 kHz
+        for (index, frame) in output
+            .chunks_exact(channels)
+            .enumerate()
+            .skip(200)
+            .take(4400)
+        {
+            let expected = sine(48000, index);
+            for &sample in frame {
+                assert!(
+                    (sample as f64 - expected).abs() < 2.0,
+                    "{input_rate} Hz, sample {index}: {sample} != {expected}"
+                );
+            }
+        }
+    }
+
+    assert_eq!(convert_position(44100, 44100, 48000), 48000);
+    assert_eq!(convert_position(147, 44100, 48000), 160);
+    assert_eq!(convert_position(1, 176400, 48000), 0);
+    assert_eq!(convert_position(2, 176400, 48000), 1);
+}
@@ -1,10 +1,11 @@
 //! Timing utilities for high-resolution output timing.
 //!
-//! Provides timing trait implementations and high-resolution timing
-//! state management for stream synchronization.
+//! Provides timing trait implementations, high-resolution timing
+//! state management and serialization for stream synchronization.
 
 use anyhow::Result;
 use log::{debug, trace, warn};
+use std::collections::VecDeque;
 
 use crate::process::parse::ParserState;
 
@@ -193,3 +194,381 @@ impl HiresOutputTimingState {
         self.counter = 0;
     }
 }
+
+/// Zero bits separating the fields of the high-resolution output timing.
+const HIRES_FIELD_SEPARATOR: usize = 5;
+
+/// Serializes the high-resolution output timing, one bit per restart header.
+///
+/// The inverse of [`HiresOutputTimingState`]: each field carries the upper
+/// bits of the output timing of the access unit it starts in, chosen so that
+/// the stream start timing read back equals `start_timing`.
+#[derive(Debug, Clone)]
+pub struct HiresOutputTimingEncoder {
+    start_timing: usize,
+    samples_per_au: usize,
+    bits: VecDeque<bool>,
+}
+
+impl HiresOutputTimingEncoder {
+    pub fn new(start_timing: usize, samples_per_au: usize) -> Self {
+        Self {
+            start_timing,
+            samples_per_au,
+            bits: VecDeque::from(vec![false; HIRES_FIELD_SEPARATOR]),
+        }
+    }
+
+    /// Returns the bit for the next restart header, read in access unit
+    /// `au_index` with the given output timing.
+    pub fn next_bit(&mut self, au_index: usize, output_timing: u16) -> bool {
+        if let Some(bit) = self.bits.pop_front() {
+            return bit;
+        }
+
+        // A field starts here, its value is fixed by this access unit
+        let timing = (self.start_timing + au_index * self.samples_per_au)
+            .saturating_sub(output_timing as usize)
+            >> 16;
+        self.bits.extend(Self::encode_field(timing));
+        self.bits
+            .extend(std::iter::repeat_n(false, HIRES_FIELD_SEPARATOR));
+
+        true
+    }
+
+    /// Encodes the data of a field, following its start bit.
+    ///
+    /// Up to four zeros followed by a one append those zeros to the value, or
+    /// a one and those zeros after a one. Four zeros switch back, five zeros
+    /// after a one end the field.
+    fn encode_field(timing: usize) -> Vec<bool> {
+        let digits: Vec<bool> = (0..usize::BITS - timing.leading_zeros())
+            .rev()
+            .map(|i| timing >> i & 1 != 0)
+            .collect();
+
+        let mut bits = Vec::new();
+        let mut pos = 0;
+        let mut after_one = false;
+        while pos < digits.len() {
+            if after_one {
+                pos += 1;
+            }
+            let zeros = digits[pos..].iter().take(4).take_while(|&&d| !d).count();
+            bits.extend(std::iter::repeat_n(false, zeros));
+            bits.push(true);
+            pos += zeros;
+            after_one = zeros < 4;
+        }
+
+        if !after_one {
+            bits.push(true);
+        }
+        bits
+    }
+}
+
+/// Timing of an access unit, passed to the callback set with
+/// [`Parser::set_timing_callback`](crate::process::parse::Parser::set_timing_callback).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessUnitTiming {
+    /// Index of the access unit in the stream.
+    pub au_index: usize,
+    /// Time the access unit starts to enter the decoder FIFO, in samples modulo 2^16.
+    pub input_timing: u16,
+    /// Time the first sample of the access unit is output, in samples modulo 2^16.
+    pub output_timing: u16,
+    /// Length of the access unit in bytes.
+    pub length: usize,
+    /// Sampling frequency in Hz from the most recent major sync.
+    pub sampling_frequency: u32,
+    /// Samples per access unit from the most recent major sync.
+    pub samples_per_au: usize,
+}
+
+/// Decoder FIFO contents right after an access unit entered it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FifoOccupancy {
+    /// Index of the access unit that entered the FIFO.
+    pub au_index: usize,
+    /// Bytes of the access units waiting for output.
+    pub bytes: usize,
+    /// Samples of the access units waiting for output.
+    pub samples: usize,
+}
+
+/// Access unit arriving too late or overfilling the decoder FIFO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FifoViolation {
+    /// Index of the access unit.
+    pub au_index: usize,
+    /// Samples the access unit arrives after its output time for an
+    /// underflow, samples in the FIFO for an overflow.
+    pub samples: usize,
+}
+
+/// Decoder FIFO occupancy over the access units modeled so far.
+///
+/// Returned by [`FifoModel::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FifoStats {
+    /// Number of access units modeled.
+    pub access_units: u64,
+    /// Occupancy with the fewest bytes.
+    pub min: Option<FifoOccupancy>,
+    /// Occupancy with the most bytes.
+    pub max: Option<FifoOccupancy>,
+    /// First access unit entering the FIFO after its output time.
+    pub first_underflow: Option<FifoViolation>,
+    /// First access unit leaving more than 75 ms of samples in the FIFO.
+    pub first_overflow: Option<FifoViolation>,
+}
+
+/// Models the decoder FIFO from the input and output timing of each access unit.
+///
+/// An access unit enters the FIFO at its input timing and leaves it at its
+/// output timing, when its first sample is output. The occupancy is taken
+/// right after each access unit entered. The encoder keeps the latency
+/// between input and output within 75 ms, so that much audio is the most the
+/// FIFO has to hold.
+///
+/// Timings wrap at 2^16 samples; they are unwrapped against the previous
+/// access unit, which requires the latency to stay below 2^15 samples.
+///
+/// ```rust,no_run
+/// use std::sync::{Arc, Mutex};
+/// use truehd::process::{extract::Extractor, parse::Parser};
+/// use truehd::utils::timing::FifoModel;
+///
+/// let model = Arc::new(Mutex::new(FifoModel::default()));
+/// let mut extractor = Extractor::default();
+/// let mut parser = Parser::default();
+///
+/// let callback_model = model.clone();
+/// parser.set_timing_callback(move |timing| {
+///     callback_model.lock().unwrap().push(timing);
+/// });
+///
+/// extractor.push_bytes(&std::fs::read("stream.thd")?);
+/// for frame in extractor.filter_map(Result::ok) {
+///     parser.parse(&frame)?;
+/// }
+/// println!("{:?}", model.lock().unwrap().stats().max);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct FifoModel {
+    /// Input timing of the previous access unit, wrapped and unwrapped
+    input_time: Option<(u16, i64)>,
+    /// Unwrapped output time, bytes and samples of the access units in the FIFO
+    queue: VecDeque<(i64, usize, usize)>,
+    bytes: usize,
+    samples: usize,
+    stats: FifoStats,
+}
+
+impl FifoModel {
+    /// Adds an access unit to the FIFO and returns the occupancy after it entered.
+    pub fn push(&mut self, timing: &AccessUnitTiming) -> FifoOccupancy {
+        let input_time = match self.input_time {
+            Some((prev_input_timing, prev_input_time)) => {
+                prev_input_time + timing.input_timing.wrapping_sub(prev_input_timing) as i64
+            }
+            None => timing.input_timing as i64,
+        };
+        self.input_time = Some((timing.input_timing, input_time));
+        let output_time =
+            input_time + timing.output_timing.wrapping_sub(timing.input_timing) as i16 as i64;
+
+        while let Some(&(time, bytes, samples)) = self.queue.front() {
+            if time > input_time {
+                break;
+            }
+            self.queue.pop_front();
+            self.bytes -= bytes;
+            self.samples -= samples;
+        }
+
+        if output_time < input_time {
+            self.stats.first_underflow.get_or_insert(FifoViolation {
+                au_index: timing.au_index,
+                samples: (input_time - output_time) as usize,
+            });
+        } else {
+            self.queue
+                .push_back((output_time, timing.length, timing.samples_per_au));
+            self.bytes += timing.length;
+            self.samples += timing.samples_per_au;
+        }
+
+        let occupancy = FifoOccupancy {
+            au_index: timing.au_index,
+            bytes: self.bytes,
+            samples: self.samples,
+        };
+
+        let samples_per_75ms = (timing.sampling_frequency as usize * 3).div_ceil(40);
+        if occupancy.samples > samples_per_75ms {
+            self.stats.first_overflow.get_or_insert(FifoViolation {
+                au_index: timing.au_index,
+                samples: occupancy.samples,
+            });
+        }
+
+        self.stats.access_units += 1;
+        if self.stats.min.is_none_or(|min| occupancy.bytes < min.bytes) {
+            self.stats.min = Some(occupancy);
+        }
+        if self.stats.max.is_none_or(|max| occupancy.bytes > max.bytes) {
+            self.stats.max = Some(occupancy);
+        }
+
+        occupancy
+    }
+
+    /// Returns the occupancy statistics of the access units modeled so far.
+    pub fn stats(&self) -> &FifoStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct TestTiming {
+    au_index: usize,
+    output_timing: usize,
+    hires_output_timing: Option<usize>,
+}
+
+#[cfg(test)]
+impl Timing for TestTiming {
+    fn au_index(&self) -> Result<usize> {
+        Ok(self.au_index)
+    }
+
+    fn samples_per_au(&self) -> Result<usize> {
+        Ok(40)
+    }
+
+    fn substream_index(&self) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn output_timing(&self) -> Result<usize> {
+        Ok(self.output_timing)
+    }
+
+    fn update_hires_output_timing(&mut self, hires_output_timing: usize) -> Result<()> {
+        assert!(
+            self.hires_output_timing
+                .is_none_or(|t| t == hires_output_timing)
+        );
+        self.hires_output_timing = Some(hires_output_timing);
+        Ok(())
+    }
+}
+
+#[test]
+fn hires_output_timing_round_trip() -> Result<()> {
+    for start_timing in [0, 1, 1234, 0x10000, 0x2_3456, 0x1F_0000] {
+        let mut encoder = HiresOutputTimingEncoder::new(start_timing, 40);
+        let mut state = HiresOutputTimingState::default();
+        let mut timing = TestTiming::default();
+
+        for au_index in 0..20000 {
+            let output_timing = ((start_timing + au_index * 40) & 0xFFFF) as u16;
+            timing.au_index = au_index;
+            timing.output_timing = output_timing as usize;
+            let bit = encoder.next_bit(au_index, output_timing);
+            state.update(&mut timing, bit)?;
+            // Every field after the first one is checked against it
+            assert_ne!(state.state_index, 0, "AU {au_index}");
+        }
+        assert_eq!(timing.hires_output_timing, Some(start_timing));
+        assert!(state.counter >= 2);
+    }
+
+    Ok(())
+}
+
+/// Timing of an access unit of 100 bytes at 48 kHz.
+#[cfg(test)]
+fn au_timing(au_index: usize, input_timing: u16, output_timing: u16) -> AccessUnitTiming {
+    AccessUnitTiming {
+        au_index,
+        input_timing,
+        output_timing,
+        length: 100,
+        sampling_frequency: 48000,
+        samples_per_au: 40,
+    }
+}
+
+#[test]
+fn fifo_model_tracks_constant_latency() {
+    let mut model = FifoModel::default();
+
+    // Each access unit waits 100 samples, across the wrap of the timing
+    let occupancy: Vec<_> = (0..10)
+        .map(|i| {
+            let input_timing = 0xFFB0u16.wrapping_add(i as u16 * 40);
+            model.push(&au_timing(i, input_timing, input_timing.wrapping_add(100)))
+        })
+        .map(|occupancy| (occupancy.bytes, occupancy.samples))
+        .collect();
+    assert_eq!(
+        occupancy[..4],
+        [(100, 40), (200, 80), (300, 120), (300, 120)]
+    );
+    assert!(occupancy[3..].iter().all(|&o| o == (300, 120)));
+
+    let stats = model.stats();
+    assert_eq!(stats.access_units, 10);
+    assert_eq!(stats.min.map(|min| min.au_index), Some(0));
+    assert_eq!(
+        stats.max.map(|max| (max.au_index, max.bytes)),
+        Some((2, 300))
+    );
+    assert!(stats.first_underflow.is_none());
+    assert!(stats.first_overflow.is_none());
+}
+
+#[test]
+fn fifo_model_reports_underflow_and_overflow() {
+    let mut model = FifoModel::default();
+
+    // AU 3 arrives 10 samples after its output time
+    for (i, (input_timing, output_timing)) in [(0, 80), (40, 120), (80, 160), (170, 160)]
+        .into_iter()
+        .enumerate()
+    {
+        model.push(&au_timing(i, input_timing, output_timing));
+    }
+    assert_eq!(
+        model.stats().first_underflow,
+        Some(FifoViolation {
+            au_index: 3,
+            samples: 10
+        })
+    );
+    assert!(model.stats().first_overflow.is_none());
+
+    // Access units arriving 20 samples apart pile up until more than
+    // 3600 samples, 75 ms, are waiting
+    let mut model = FifoModel::default();
+    for i in 0..200 {
+        let input_timing = i as u16 * 20;
+        model.push(&au_timing(i, input_timing, 3000 + i as u16 * 40));
+    }
+    assert_eq!(
+        model.stats().first_overflow,
+        Some(FifoViolation {
+            au_index: 90,
+            samples: 3640
+        })
+    );
+}
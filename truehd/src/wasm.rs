@@ -0,0 +1,32 @@
+//! JavaScript bindings for analyzing streams in the browser or Node.js.
+//!
+//! The extractor, parser and the structures they produce perform no IO and
+//! build for `wasm32-unknown-unknown`. Decoding is not exposed yet.
+//!
+//! The module and its JavaScript bindings are built with wasm-bindgen:
+//!
+//! ```sh
+//! cargo rustc -p truehd --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//! wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/truehd.wasm
+//! ```
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::process::analyze::StreamAnalyzer;
+
+/// Summarizes a complete TrueHD stream.
+///
+/// Returns the report of `truehdd info --json` as a plain object. The fields
+/// of the analysis options of `info` are not included. Fails if the stream
+/// has no major sync.
+#[wasm_bindgen]
+pub fn analyze(bytes: &[u8]) -> Result<JsValue, JsError> {
+    let mut analyzer = StreamAnalyzer::default();
+    analyzer.push_bytes(bytes);
+    let report = analyzer
+        .finish()
+        .ok_or_else(|| JsError::new("No TrueHD major sync found in the data"))?;
+
+    Ok(report.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+}
@@ -0,0 +1,33 @@
+// Smoke test of the WebAssembly bindings built with `wasm-bindgen --target nodejs`.
+//
+// Usage: node wasm/smoke.mjs <bindings directory>
+
+import assert from "node:assert/strict";
+import { createRequire } from "node:module";
+import path from "node:path";
+
+const require = createRequire(import.meta.url);
+const { analyze } = require(path.resolve(process.argv[2] ?? "pkg", "truehd.js"));
+
+// process::EXAMPLE_DATA
+const example = Buffer.from(
+  "01100001002300450016001900118000f02affacf8726fba00008001b7520000" +
+  "00008080101403803f1fe307e3005298b01803f0f1ea00000110000002095280" +
+  "00000002b44401e8c44088d1fe91006303e91833862068ffcb6edb6db6db6db7" +
+  "800064f9500a0000700791404800113ddbeff3ded000d504",
+  "hex",
+);
+
+const report = analyze(example);
+assert.equal(report.stream.sampling_frequency, 48000);
+assert.equal(report.stream.is_atmos, false);
+assert.equal(report.presentations.length, 3);
+assert.equal(report.bytes, example.length);
+assert.equal(report.timestamp, "01:23:45:16 +25 @ 29.97 fps DF");
+assert.equal(report.trim_samples, null);
+assert.equal(report.timestamp_drift, 0);
+assert.equal(report.frames, 2);
+
+assert.throws(() => analyze(new Uint8Array(64)), /No TrueHD major sync/);
+
+console.log("wasm smoke test passed");